@@ -3,12 +3,12 @@
 
 use xous_kernel::{PID, TID};
 
-pub fn enable_irq(_irq_no: usize) {
-    unimplemented!()
-}
+// Nothing ever raises an interrupt in hosted mode, so claiming one succeeds
+// but its handler is never called.
+pub fn enable_irq(_irq_no: usize) {}
 
 pub fn disable_irq(_irq_no: usize) -> Result<(), xous_kernel::Error> {
-    unimplemented!()
+    Ok(())
 }
 
 pub unsafe fn set_isr_return_pair(_pid: PID, _ctx: TID) {
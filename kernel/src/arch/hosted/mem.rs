@@ -63,7 +63,9 @@ pub fn map_page_inner(
     _req_flags: MemoryFlags,
     _map_user: bool,
 ) -> Result<(), xous_kernel::Error> {
-    unimplemented!()
+    // Processes are programs on the host, so there's no physical memory that
+    // could be mapped into them.
+    Err(xous_kernel::Error::BadAddress)
 }
 
 pub fn move_page_inner(
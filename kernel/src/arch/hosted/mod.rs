@@ -24,6 +24,12 @@ use xous_kernel::{ProcessInit, ProcessKey, Result, SysCall, ThreadInit, PID, TID
 enum ThreadMessage {
     SysCall(PID, TID, SysCall),
     NewConnection(TcpStream, ProcessKey),
+    #[cfg(feature = "gdbserver")]
+    GdbConnected(TcpStream),
+    #[cfg(feature = "gdbserver")]
+    GdbData(Vec<u8>),
+    #[cfg(feature = "gdbserver")]
+    GdbDisconnected,
 }
 
 #[derive(Debug)]
@@ -126,20 +132,33 @@ fn handle_connection(
                 packet_data[8],
             ) {
                 Ok(call) => call,
-                Err(e) => {
-                    eprintln!("KERNEL({}): Received invalid syscall: {:?}", pid, e);
-                    eprintln!(
-                        "Raw packet: {:08x} {} {} {} {} {} {} {}",
-                        packet_data[0],
-                        packet_data[1],
+                Err(_e) => {
+                    #[cfg(not(test))]
+                    {
+                        eprintln!("KERNEL({}): Received invalid syscall: {:?}", pid, _e);
+                        eprintln!(
+                            "Raw packet: {:08x} {} {} {} {} {} {} {}",
+                            packet_data[0],
+                            packet_data[1],
+                            packet_data[2],
+                            packet_data[3],
+                            packet_data[4],
+                            packet_data[5],
+                            packet_data[6],
+                            packet_data[7]
+                        );
+                    }
+                    // The thread is still waiting for an answer, so let the
+                    // kernel reject the call as it would on hardware.
+                    crate::SysCall::Invalid(
                         packet_data[2],
                         packet_data[3],
                         packet_data[4],
                         packet_data[5],
                         packet_data[6],
-                        packet_data[7]
-                    );
-                    continue;
+                        packet_data[7],
+                        packet_data[8],
+                    )
                 }
             };
 
@@ -464,6 +483,179 @@ fn listen_thread(
     exit_server(should_exit, clients);
 }
 
+/// Accept gdb connections one at a time, and pass whatever gdb sends on to
+/// the main thread, which is where the gdb server runs.
+#[cfg(feature = "gdbserver")]
+fn gdb_listen_thread(listen_addr: SocketAddr, chn: Sender<ThreadMessage>) {
+    let listener = match TcpListener::bind(listen_addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!(
+                "KERNEL: unable to start gdb server on {}: {}",
+                listen_addr, e
+            );
+            return;
+        }
+    };
+    println!(
+        "KERNEL: gdb server listening on {}",
+        listener.local_addr().unwrap()
+    );
+
+    for conn in listener.incoming() {
+        let mut conn = match conn {
+            Ok(conn) => conn,
+            Err(_) => continue,
+        };
+        conn.set_nodelay(true).ok();
+        let reply_conn = conn
+            .try_clone()
+            .expect("couldn't make a copy of the gdb connection for the kernel");
+        if chn.send(ThreadMessage::GdbConnected(reply_conn)).is_err() {
+            return;
+        }
+
+        let mut buffer = [0u8; 1024];
+        loop {
+            match conn.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => {
+                    if chn
+                        .send(ThreadMessage::GdbData(buffer[..len].to_vec()))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        }
+        if chn.send(ThreadMessage::GdbDisconnected).is_err() {
+            return;
+        }
+    }
+}
+
+/// Handle a syscall that `thread_id` of `pid` made, and send the response
+/// back to it. Returns `true` if the system is shutting down.
+fn handle_syscall(pid: PID, thread_id: TID, call: SysCall) -> bool {
+    // let measurement_start = std::time::Instant::now();
+    // println!("KERNEL({}): Received syscall {:?}", pid, call);
+    crate::arch::process::set_current_pid(pid);
+    // println!("KERNEL({}): Now running as the new process", pid);
+
+    // If the call being made is to terminate the current process, we need to know
+    // because we won't be able to send a response.
+    let is_terminate = call == SysCall::TerminateProcess(0);
+    let is_shutdown = call == SysCall::Shutdown;
+
+    // For a "Shutdown" command, send the response before we issue the shutdown.
+    // This is because the "process" will be "terminated" (the network socket will be closed),
+    // and we won't be able to send the response after we're done.
+    if is_shutdown {
+        // println!("KERNEL: Detected shutdown -- sending final \"Ok\" to the client");
+        let mut process = Process::current();
+        let mut response_vec = Vec::new();
+        response_vec.extend_from_slice(&thread_id.to_le_bytes());
+        for word in Result::Ok.to_args().iter_mut() {
+            response_vec.extend_from_slice(&word.to_le_bytes());
+        }
+        process.send(&response_vec).unwrap_or_else(|_e| {
+            // If we're unable to send data to the process, assume it's dead and terminate it.
+            println!(
+                "Unable to send response to process: {:?} -- terminating",
+                _e
+            );
+            crate::syscall::handle(pid, thread_id, false, SysCall::TerminateProcess(0)).ok();
+        });
+        // println!("KERNEL: Done sending");
+    }
+
+    // A process that lends memory reads it back once the call is over, even
+    // if the call fails.
+    let lent_memory = if call.is_borrow() || call.is_mutableborrow() {
+        call.memory()
+    } else {
+        None
+    };
+
+    // Handle the syscall within the Xous kernel
+    let response =
+        crate::syscall::handle(pid, thread_id, false, call).unwrap_or_else(Result::Error);
+
+    // println!("KERNEL({}): Syscall response {:?}", pid, response);
+    // There's a response if it wasn't a blocked process and we're not terminating.
+    // Send the response back to the target.
+    if response != Result::BlockedProcess && !is_terminate && !is_shutdown {
+        // The syscall may change what the current process is, but we always
+        // want to send a response to the process where the request came from.
+        // For this block, switch to the original PID, send the message, then
+        // switch back.
+        let existing_pid = crate::arch::process::current_pid();
+        crate::arch::process::set_current_pid(pid);
+
+        let mut process = Process::current();
+        let mut capacity = 9 * core::mem::size_of::<usize>();
+        if let Some(mem) = response.memory() {
+            capacity += mem.len();
+        }
+        let mut response_vec = Vec::with_capacity(capacity);
+
+        response_vec.extend_from_slice(&thread_id.to_le_bytes());
+        for word in response.to_args().iter_mut() {
+            response_vec.extend_from_slice(&word.to_le_bytes());
+        }
+        if let Some(mem) = response.memory() {
+            let s = unsafe { core::slice::from_raw_parts(mem.as_ptr(), mem.len()) };
+            response_vec.extend_from_slice(s);
+        }
+        if let (Result::Error(_), Some(mem)) = (&response, lent_memory) {
+            response_vec.extend_from_slice(mem.as_slice::<u8>());
+        }
+        process.send(&response_vec).unwrap_or_else(|_e| {
+            // If we're unable to send data to the process, assume it's dead and terminate it.
+            eprintln!(
+                "KERNEL({}): Unable to send response to process: {:?} -- terminating",
+                pid, _e
+            );
+            crate::syscall::handle(pid, thread_id, false, SysCall::TerminateProcess(0)).ok();
+        });
+        crate::arch::process::set_current_pid(existing_pid);
+        // println!(
+        //     "KERNEL [{:2}:{:2}] Syscall took {:7} usec",
+        //     pid,
+        //     thread_id,
+        //     measurement_start.elapsed().as_micros()
+        // );
+    }
+
+    #[cfg(feature = "gdbserver")]
+    crate::debug::gdb_hosted::syscall_handled();
+
+    #[cfg(test)]
+    SystemServices::with(|system_services| system_services.check_invariants());
+
+    is_shutdown
+}
+
+/// Handle syscalls that were held back while gdb had the kernel stopped, for
+/// as long as gdb lets the kernel run. Returns `true` if the system is
+/// shutting down.
+#[cfg(feature = "gdbserver")]
+fn handle_deferred_syscalls(
+    deferred: &mut std::collections::VecDeque<(PID, TID, SysCall)>,
+) -> bool {
+    while !crate::debug::gdb_hosted::stopped() {
+        let (pid, thread_id, call) = match deferred.pop_front() {
+            Some(syscall) => syscall,
+            None => break,
+        };
+        if handle_syscall(pid, thread_id, call) {
+            return true;
+        }
+    }
+    false
+}
+
 /// The idle function is run when there are no directly-runnable processes
 /// that kmain can activate. In a hosted environment,this is the primary
 /// thread that handles network communications, and this function never returns.
@@ -477,10 +669,12 @@ pub fn idle() -> bool {
     let pid1_key = PID1_KEY.with(|p1k| *p1k.borrow());
     let pid1_init = ProcessInit {
         key: ProcessKey::new(pid1_key),
+        priority: xous_kernel::THREAD_PRIORITY_DEFAULT,
+        page_limit: 0,
     };
     let pid1 = SystemServices::with_mut(|ss| ss.create_process(pid1_init)).unwrap();
     assert_eq!(pid1.get(), 1);
-    let _tid1 = SystemServices::with_mut(|ss| ss.create_thread(pid1, ThreadInit {})).unwrap();
+    let _tid1 = SystemServices::with_mut(|ss| ss.create_thread(pid1, ThreadInit::default())).unwrap();
 
     let listen_addr = env::var("XOUS_LISTEN_ADDR")
         .map(|s| {
@@ -491,6 +685,24 @@ pub fn idle() -> bool {
         })
         .unwrap_or_else(|_| NETWORK_LISTEN_ADDRESS.with(|nla| *nla.borrow()));
 
+    // Syscalls that arrived while gdb had the kernel stopped
+    #[cfg(feature = "gdbserver")]
+    let mut deferred_syscalls = std::collections::VecDeque::new();
+
+    #[cfg(feature = "gdbserver")]
+    if let Ok(gdb_addr) = env::var("XOUS_GDB_ADDR") {
+        let gdb_addr = gdb_addr
+            .to_socket_addrs()
+            .expect("invalid gdb server address")
+            .next()
+            .expect("unable to resolve gdb server address");
+        let gdb_sender = sender.clone();
+        std::thread::Builder::new()
+            .name("kernel gdb listener".to_owned())
+            .spawn(move || gdb_listen_thread(gdb_addr, gdb_sender))
+            .expect("couldn't spawn gdb listen thread");
+    }
+
     #[cfg(not(test))]
     let address_receiver = {
         let (sender, receiver) = unbounded();
@@ -527,6 +739,8 @@ pub fn idle() -> bool {
             let process_key = generate_pid_key();
             let init = xous_kernel::ProcessInit {
                 key: ProcessKey::new(process_key),
+                priority: xous_kernel::THREAD_PRIORITY_DEFAULT,
+                page_limit: 0,
             };
             let new_pid = SystemServices::with_mut(|ss| ss.create_process(init)).unwrap();
             println!(" {:^5} |  {}", new_pid, arg);
@@ -561,101 +775,40 @@ pub fn idle() -> bool {
                 // similar to having one core for each process
                 if new_pid != PID::new(1).unwrap() {
                     SystemServices::with_mut(|ss| {
-                        ss.create_thread(new_pid, ThreadInit {})?;
+                        ss.create_thread(new_pid, ThreadInit::default())?;
                         ss.switch_to_thread(new_pid, None)
                     })
                     .unwrap();
                 }
             }
+            #[cfg(feature = "gdbserver")]
+            ThreadMessage::SysCall(pid, thread_id, call) if crate::debug::gdb_hosted::stopped() => {
+                deferred_syscalls.push_back((pid, thread_id, call));
+            }
             ThreadMessage::SysCall(pid, thread_id, call) => {
-                // let measurement_start = std::time::Instant::now();
-                // println!("KERNEL({}): Received syscall {:?}", pid, call);
-                crate::arch::process::set_current_pid(pid);
-                // println!("KERNEL({}): Now running as the new process", pid);
-
-                // If the call being made is to terminate the current process, we need to know
-                // because we won't be able to send a response.
-                let is_terminate = call == SysCall::TerminateProcess(0);
-                let is_shutdown = call == SysCall::Shutdown;
-
-                // For a "Shutdown" command, send the response before we issue the shutdown.
-                // This is because the "process" will be "terminated" (the network socket will be closed),
-                // and we won't be able to send the response after we're done.
-                if is_shutdown {
-                    // println!("KERNEL: Detected shutdown -- sending final \"Ok\" to the client");
-                    let mut process = Process::current();
-                    let mut response_vec = Vec::new();
-                    response_vec.extend_from_slice(&thread_id.to_le_bytes());
-                    for word in Result::Ok.to_args().iter_mut() {
-                        response_vec.extend_from_slice(&word.to_le_bytes());
-                    }
-                    process.send(&response_vec).unwrap_or_else(|_e| {
-                        // If we're unable to send data to the process, assume it's dead and terminate it.
-                        println!(
-                            "Unable to send response to process: {:?} -- terminating",
-                            _e
-                        );
-                        crate::syscall::handle(pid, thread_id, false, SysCall::TerminateProcess(0))
-                            .ok();
-                    });
-                    // println!("KERNEL: Done sending");
-                }
-
-                // Handle the syscall within the Xous kernel
-                let response = crate::syscall::handle(pid, thread_id, false, call)
-                    .unwrap_or_else(Result::Error);
-
-                // println!("KERNEL({}): Syscall response {:?}", pid, response);
-                // There's a response if it wasn't a blocked process and we're not terminating.
-                // Send the response back to the target.
-                if response != Result::BlockedProcess && !is_terminate && !is_shutdown {
-                    // The syscall may change what the current process is, but we always
-                    // want to send a response to the process where the request came from.
-                    // For this block, switch to the original PID, send the message, then
-                    // switch back.
-                    let existing_pid = crate::arch::process::current_pid();
-                    crate::arch::process::set_current_pid(pid);
-
-                    let mut process = Process::current();
-                    let mut capacity = 9 * core::mem::size_of::<usize>();
-                    if let Some(mem) = response.memory() {
-                        capacity += mem.len();
-                    }
-                    let mut response_vec = Vec::with_capacity(capacity);
-
-                    response_vec.extend_from_slice(&thread_id.to_le_bytes());
-                    for word in response.to_args().iter_mut() {
-                        response_vec.extend_from_slice(&word.to_le_bytes());
-                    }
-                    if let Some(mem) = response.memory() {
-                        let s = unsafe { core::slice::from_raw_parts(mem.as_ptr(), mem.len()) };
-                        response_vec.extend_from_slice(s);
-                    }
-                    process.send(&response_vec).unwrap_or_else(|_e| {
-                        // If we're unable to send data to the process, assume it's dead and terminate it.
-                        eprintln!(
-                            "KERNEL({}): Unable to send response to process: {:?} -- terminating",
-                            pid, _e
-                        );
-                        crate::syscall::handle(pid, thread_id, false, SysCall::TerminateProcess(0))
-                            .ok();
-                    });
-                    crate::arch::process::set_current_pid(existing_pid);
-                    // println!(
-                    //     "KERNEL [{:2}:{:2}] Syscall took {:7} usec",
-                    //     pid,
-                    //     thread_id,
-                    //     measurement_start.elapsed().as_micros()
-                    // );
-                }
-
-                if is_shutdown {
+                if handle_syscall(pid, thread_id, call) {
                     exit_sender
                         .send(ExitMessage::Exit)
                         .expect("couldn't send shutdown signal");
                     break;
                 }
             }
+            #[cfg(feature = "gdbserver")]
+            ThreadMessage::GdbConnected(conn) => crate::debug::gdb_hosted::connect(conn),
+            #[cfg(feature = "gdbserver")]
+            ThreadMessage::GdbData(data) => crate::debug::gdb_hosted::receive(&data),
+            #[cfg(feature = "gdbserver")]
+            ThreadMessage::GdbDisconnected => crate::debug::gdb_hosted::disconnect(),
+        }
+
+        // gdb may have just continued, so let through as many of the
+        // syscalls that were held back as it now allows.
+        #[cfg(feature = "gdbserver")]
+        if handle_deferred_syscalls(&mut deferred_syscalls) {
+            exit_sender
+                .send(ExitMessage::Exit)
+                .expect("couldn't send shutdown signal");
+            break;
         }
     }
 
@@ -2,6 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub const MAX_THREAD: TID = 31;
+/// Thread IDs are handed out from 1, so the last of the `MAX_THREAD + 1`
+/// slots is `MAX_THREAD + 1`.
+pub const THREAD_IDS: core::ops::RangeInclusive<TID> = 1..=MAX_THREAD + 1;
+use crate::arch::mem::MemoryMapping;
 use crate::services::ProcessInner;
 use core::cell::RefCell;
 use std::io::Write;
@@ -273,6 +277,24 @@ impl Process {
         })
     }
 
+    /// Call `op` with each allocated thread of the current process.
+    #[cfg(feature = "gdbserver")]
+    pub fn for_each_thread_mut<F>(&self, mut op: F)
+    where
+        F: FnMut(TID, &Thread),
+    {
+        PROCESS_TABLE.with(|pt| {
+            let process_table = pt.borrow();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            let process = process_table.table[current_pid_idx].as_ref().unwrap();
+            for (index, thread) in process.threads.iter().enumerate() {
+                if thread.allocated {
+                    op(index as TID + 1, thread);
+                }
+            }
+        })
+    }
+
     pub fn thread_exists(&self, _tid: TID) -> bool {
         false
     }
@@ -337,7 +359,12 @@ impl Process {
     /// Initialize this process with the given memory space. THIS DOES NOT
     /// INITIALIZE A MAIN THREAD. You must call `setup_thread()` in order to
     /// select a main thread.
-    pub fn create(pid: PID, init_data: ProcessInit) -> PID {
+    /// Register a new process. The program itself is started separately, so
+    /// there is never an address space or initial thread to hand back.
+    pub fn create(
+        pid: PID,
+        init_data: ProcessInit,
+    ) -> Result<Option<(MemoryMapping, ThreadInit)>, xous_kernel::Error> {
         PROCESS_TABLE.with(|process_table| {
             let mut process_table = process_table.borrow_mut();
             let pid_idx = (pid.get() - 1) as usize;
@@ -359,7 +386,7 @@ impl Process {
             } else {
                 panic!("pid already allocated!");
             }
-            pid
+            Ok(None)
         })
     }
 
@@ -176,6 +176,17 @@ pub extern "C" fn trap_handler(
     let ex = RiscvException::from_regs(sc.bits(), sepc::read(), stval::read());
     // println!("ex: {:?}", ex);
     if sc.is_exception() {
+        // Breakpoints and watchpoints belong to the debugger, which may have
+        // stopped this process in favour of another one.
+        #[cfg(feature = "gdbserver")]
+        if sstatus::read().spp() == sstatus::SPP::User
+            && crate::debug::gdb_server::handle_exception(pid, &ex)
+        {
+            ArchProcess::with_current_mut(|process| {
+                crate::arch::syscall::resume(current_pid().get() == 1, process.current_thread())
+            });
+        }
+
         // See if it's a known exception, such as writing to a demand-paged area
         // or returning from a handler or thread. If so, handle the exception
         // and return right away.
@@ -17,6 +17,10 @@ pub const PAGE_SIZE: usize = 4096;
 const PAGE_TABLE_OFFSET: usize = 0xff40_0000;
 const PAGE_TABLE_ROOT_OFFSET: usize = 0xff80_0000;
 
+/// Kernel-only page used to fill in the page tables of a new process
+/// before its address space can be activated.
+const PROCESS_SETUP_SCRATCH: usize = 0xff00_0000;
+
 extern "C" {
     fn flush_mmu();
 }
@@ -217,6 +221,77 @@ impl MemoryMapping {
         l0_pt.entries[vpn0] = translate_flags(flags).bits();
         Ok(())
     }
+
+    /// Allocate an empty address space for `pid`. It contains the kernel,
+    /// plus the root page table mapped at `PAGE_TABLE_ROOT_OFFSET` and the
+    /// leaf tables needed to reach it, laid out the same way the loader does
+    /// for the initial processes. All pages are owned by `pid`, so they are
+    /// freed along with the rest of the process.
+    ///
+    /// The current address space stays active.
+    pub fn allocate(mm: &mut MemoryManager, pid: PID) -> Result<MemoryMapping, xous_kernel::Error> {
+        let root = mm.alloc_page(pid)?;
+        // Covers `PAGE_TABLE_ROOT_OFFSET`, which is also where the process context lives
+        let root_l0 = mm.alloc_page(pid)?;
+        // Covers `PAGE_TABLE_OFFSET`, which is where the leaf tables get mapped
+        let tables_l0 = mm.alloc_page(pid)?;
+
+        let root_vpn1 = PAGE_TABLE_ROOT_OFFSET >> 22;
+        let tables_vpn1 = PAGE_TABLE_OFFSET >> 22;
+        let table_entry = |phys: usize| ((phys >> 12) << 10) | MMUFlags::VALID.bits();
+        let page_entry = |phys: usize| {
+            ((phys >> 12) << 10)
+                | (MMUFlags::VALID | MMUFlags::R | MMUFlags::W | MMUFlags::D | MMUFlags::A).bits()
+        };
+
+        // The kernel's megapage is shared by every process
+        let kernel_entry = unsafe {
+            (PAGE_TABLE_ROOT_OFFSET as *const usize)
+                .add(1023)
+                .read_volatile()
+        };
+
+        with_scratch_page(mm, root, |entries| {
+            entries[1023] = kernel_entry;
+            entries[root_vpn1] = table_entry(root_l0);
+            entries[tables_vpn1] = table_entry(tables_l0);
+        })?;
+        with_scratch_page(mm, root_l0, |entries| {
+            entries[(PAGE_TABLE_ROOT_OFFSET >> 12) & ((1 << 10) - 1)] = page_entry(root);
+        })?;
+        with_scratch_page(mm, tables_l0, |entries| {
+            entries[root_vpn1] = page_entry(root_l0);
+            entries[tables_vpn1] = page_entry(tables_l0);
+        })?;
+
+        Ok(MemoryMapping {
+            satp: 0x8000_0000 | ((pid.get() as usize) << 22) | (root >> 12),
+        })
+    }
+}
+
+/// Temporarily map the physical page `phys` into the current address space,
+/// zero it, and let `f` fill it in as a page table.
+fn with_scratch_page<F>(mm: &mut MemoryManager, phys: usize, f: F) -> Result<(), xous_kernel::Error>
+where
+    F: FnOnce(&mut [usize; 1024]),
+{
+    let pid = crate::arch::process::current_pid();
+    map_page_inner(
+        mm,
+        pid,
+        phys,
+        PROCESS_SETUP_SCRATCH,
+        MemoryFlags::R | MemoryFlags::W,
+        false,
+    )?;
+    let entries = unsafe { &mut (*(PROCESS_SETUP_SCRATCH as *mut LeafPageTable)).entries };
+    for entry in entries.iter_mut() {
+        *entry = 0;
+    }
+    f(entries);
+    unmap_page_inner(mm, PROCESS_SETUP_SCRATCH)?;
+    Ok(())
 }
 
 pub const DEFAULT_MEMORY_MAPPING: MemoryMapping = MemoryMapping { satp: 0 };
@@ -568,6 +643,37 @@ pub fn move_page_inner(
     result
 }
 
+/// Allow code in the given page of the current address space to be executed,
+/// and stop the page from being written.
+pub fn make_executable(virt: usize) -> Result<(), xous_kernel::Error> {
+    let entry = pagetable_entry(virt)?;
+    if *entry & MMUFlags::VALID.bits() == 0 {
+        return Err(xous_kernel::Error::BadAddress);
+    }
+    *entry = (*entry & !MMUFlags::W.bits()) | MMUFlags::X.bits();
+    unsafe { flush_mmu() };
+    Ok(())
+}
+
+/// Allow or forbid writes to the given page of the current address space,
+/// returning whether it was writable before. The debugger uses this to catch
+/// writes to memory that is being watched.
+#[cfg(feature = "gdbserver")]
+pub fn set_page_writable(virt: usize, writable: bool) -> Result<bool, xous_kernel::Error> {
+    let entry = pagetable_entry(virt & !(PAGE_SIZE - 1))?;
+    if *entry & MMUFlags::VALID.bits() == 0 {
+        return Err(xous_kernel::Error::BadAddress);
+    }
+    let was_writable = *entry & MMUFlags::W.bits() != 0;
+    if writable {
+        *entry |= MMUFlags::W.bits();
+    } else {
+        *entry &= !MMUFlags::W.bits();
+    }
+    unsafe { flush_mmu() };
+    Ok(was_writable)
+}
+
 /// Determine if a page has been lent.
 pub fn page_is_lent(src_addr: *mut u8) -> bool {
     let entry = if let Ok(val) = pagetable_entry(src_addr as usize) {
@@ -752,10 +858,10 @@ pub fn ensure_page_exists_inner(address: usize) -> Result<usize, xous_kernel::Er
         return Err(xous_kernel::Error::BadAddress);
     }
 
-    let new_page = MemoryManager::with_mut(|mm| {
-        mm.alloc_page(crate::arch::process::current_pid())
-            .expect("Couldn't allocate new page")
-    });
+    // This fails if the process has run out of RAM, in which case it gets
+    // the fault rather than the page.
+    let new_page =
+        MemoryManager::with_mut(|mm| mm.alloc_page(crate::arch::process::current_pid()))?;
     let ppn1 = (new_page >> 22) & ((1 << 12) - 1);
     let ppn0 = (new_page >> 12) & ((1 << 10) - 1);
     unsafe {
@@ -4,18 +4,27 @@
 use core::mem;
 static mut PROCESS: *mut ProcessImpl = 0xff80_1000 as *mut ProcessImpl;
 pub const MAX_THREAD: TID = 31;
+/// Every thread ID that indexes a slot in `ProcessImpl::threads`
+pub const THREAD_IDS: core::ops::Range<TID> = 0..MAX_THREAD;
 pub const EXCEPTION_TID: TID = 1;
 pub const INITIAL_TID: TID = 2;
 pub const IRQ_TID: TID = 0;
-use crate::arch::mem::PAGE_SIZE;
+use crate::arch::mem::{
+    make_executable, map_page_inner, memset, page_is_lent, virt_to_phys, MemoryMapping, PAGE_SIZE,
+    USER_AREA_END,
+};
+use crate::mem::MemoryManager;
 use crate::services::ProcessInner;
-use xous_kernel::{ProcessInit, ThreadInit, PID, TID};
+use xous_kernel::{MemoryRange, ProcessInit, ThreadInit, PID, TID};
 
 // use crate::args::KernelArguments;
 pub const DEFAULT_STACK_SIZE: usize = 131072;
 pub const MAX_PROCESS_COUNT: usize = 64;
 // pub use crate::arch::mem::DEFAULT_STACK_TOP;
 
+/// The stack of the initial thread grows down from here.
+pub const USER_STACK_TOP: usize = 0x8000_0000;
+
 /// This is the address a program will jump to in order to return from an ISR.
 pub const RETURN_FROM_ISR: usize = 0xff80_2000;
 
@@ -497,8 +506,154 @@ impl Process {
         );
     }
 
-    pub fn create(_pid: PID, _init_data: ProcessInit) -> PID {
-        todo!();
+    /// Build the address space for a new process and move its program
+    /// image out of the calling process. The result is laid out just like
+    /// the processes the loader creates at boot, so it starts running from
+    /// the returned `ThreadInit` the first time it is switched to.
+    ///
+    /// The image is spent whether or not this succeeds: if the process can't
+    /// be created, `discard_image()` frees whatever the caller still holds.
+    pub fn create(
+        pid: PID,
+        init_data: ProcessInit,
+    ) -> Result<Option<(MemoryMapping, ThreadInit)>, xous_kernel::Error> {
+        let image_src = init_data.image.as_ptr() as usize;
+        let image_len = init_data.image.len();
+        let image_base = init_data.image_base;
+        let text_base = init_data.text_base;
+        let text_size = init_data.text_size;
+        if image_src & (PAGE_SIZE - 1) != 0
+            || image_len & (PAGE_SIZE - 1) != 0
+            || image_base & (PAGE_SIZE - 1) != 0
+            || text_base & (PAGE_SIZE - 1) != 0
+            || text_size & (PAGE_SIZE - 1) != 0
+        {
+            return Err(xous_kernel::Error::BadAlignment);
+        }
+        let image_end = image_base
+            .checked_add(image_len)
+            .ok_or(xous_kernel::Error::BadAddress)?;
+        // `setup_process()` reserves one page more than the stack itself
+        let stack_bottom = USER_STACK_TOP - DEFAULT_STACK_SIZE - PAGE_SIZE;
+        if image_base == 0
+            || image_end > USER_AREA_END
+            || (image_base < USER_STACK_TOP && image_end > stack_bottom)
+            || text_base < image_base
+            || text_base > image_end
+            || text_size > image_end - text_base
+            || init_data.entrypoint < text_base
+            || init_data.entrypoint - text_base >= text_size
+        {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+        for offset in (0..image_len).step_by(PAGE_SIZE) {
+            virt_to_phys(image_src + offset)?;
+        }
+
+        let caller_pid = current_pid();
+        let caller_mapping = MemoryMapping::current();
+        MemoryManager::with_mut(|mm| {
+            let result = Self::populate(
+                mm,
+                pid,
+                caller_pid,
+                &caller_mapping,
+                image_src,
+                image_base,
+                image_len,
+                text_base..text_base + text_size,
+            );
+            caller_mapping.activate().unwrap();
+            if result.is_err() {
+                unsafe { mm.release_all_memory_for_process(pid) };
+            }
+            result
+        })
+        .map(|mapping| {
+            let sp = USER_STACK_TOP - 16;
+            let mut thread_init = ThreadInit::new(
+                init_data.entrypoint,
+                unsafe { MemoryRange::new(sp - DEFAULT_STACK_SIZE, DEFAULT_STACK_SIZE).unwrap() },
+                pid.get() as usize,
+                0,
+                0,
+                0,
+            );
+            thread_init.priority = init_data.priority;
+            Some((mapping, thread_init))
+        })
+    }
+
+    /// Create the address space and context page for `pid`, then hand it the
+    /// pages of the program image. The pages in `text` can be run but not
+    /// written, and the rest of the image written but not run.
+    #[allow(clippy::too_many_arguments)]
+    fn populate(
+        mm: &mut MemoryManager,
+        pid: PID,
+        caller_pid: PID,
+        caller_mapping: &MemoryMapping,
+        image_src: usize,
+        image_base: usize,
+        image_len: usize,
+        text: core::ops::Range<usize>,
+    ) -> Result<MemoryMapping, xous_kernel::Error> {
+        let mapping = MemoryMapping::allocate(mm, pid)?;
+
+        mapping.activate()?;
+        let context_virt = unsafe { PROCESS } as usize;
+        let context = mm.alloc_page(pid)?;
+        map_page_inner(
+            mm,
+            pid,
+            context,
+            context_virt,
+            xous_kernel::MemoryFlags::R | xous_kernel::MemoryFlags::W,
+            false,
+        )?;
+        unsafe { memset(context_virt as *mut u8, 0, PAGE_SIZE) };
+        caller_mapping.activate()?;
+
+        for offset in (0..image_len).step_by(PAGE_SIZE) {
+            mm.move_page(
+                caller_pid,
+                caller_mapping,
+                (image_src + offset) as *mut u8,
+                pid,
+                &mapping,
+                (image_base + offset) as *mut u8,
+            )?;
+        }
+
+        // The caller could only map the image read-write, so allow the new
+        // process to run its code.
+        mapping.activate()?;
+        for virt in text.step_by(PAGE_SIZE) {
+            make_executable(virt)?;
+        }
+        Ok(mapping)
+    }
+
+    /// Free what's left of the image in the calling process after
+    /// `CreateProcess` failed, so the caller isn't left holding pages it has
+    /// no use for. Pages that are lent out or part of a shared region are
+    /// left alone, as they aren't the caller's to give up.
+    pub fn discard_image(init_data: &ProcessInit) {
+        let image_src = init_data.image.as_ptr() as usize;
+        let image_len = init_data.image.len();
+        if image_src & (PAGE_SIZE - 1) != 0 || image_len > USER_AREA_END {
+            return;
+        }
+        MemoryManager::with_mut(|mm| {
+            if mm.shared_region_in(image_src, image_len) != Ok(None) {
+                return;
+            }
+            for virt in (image_src..image_src + image_len).step_by(PAGE_SIZE) {
+                if virt_to_phys(virt).is_ok() && !page_is_lent(virt as *mut u8) {
+                    mm.unmap_page(virt as *mut usize).ok();
+                }
+            }
+        })
     }
 
     pub fn destroy(pid: PID) -> Result<(), xous_kernel::Error> {
@@ -0,0 +1,344 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A gdb server for kernels running in hosted mode. Processes are programs
+//! on the host, so their registers and memory are out of reach. Instead, gdb
+//! stops the whole kernel, and `monitor` commands show what every server,
+//! queue and connection looked like at that moment.
+
+use std::cell::RefCell;
+use std::io::{BufWriter, Write};
+use std::net::TcpStream;
+
+use gdbstub::common::Tid;
+use gdbstub::state_machine::GdbStubStateMachine;
+use gdbstub::target::ext::base::multithread::{
+    GdbInterrupt, MultiThreadOps, ResumeAction, ThreadStopReason,
+};
+use gdbstub::target::ext::base::BaseOps;
+use gdbstub::target::ext::monitor_cmd::{ConsoleOutput, MonitorCmdOps};
+use gdbstub::target::{Target, TargetError, TargetResult};
+use gdbstub::{outputln, DisconnectReason};
+
+use super::gdb_monitor::{gdb_tid, has_started, xous_tid};
+use crate::arch::process::Process as ArchProcess;
+use crate::services::SystemServices;
+
+#[derive(Copy, Clone, PartialEq)]
+enum RunState {
+    /// Syscalls are held back until gdb continues
+    Stopped,
+
+    /// Syscalls are handled as they arrive
+    Running,
+
+    /// The next syscall is handled, then the kernel stops again
+    Stepping,
+}
+
+pub struct XousTarget {
+    state: RunState,
+
+    /// `true` if gdb asked for a thread to be stepped the next time it
+    /// resumes
+    step_requested: bool,
+}
+
+/// The socket gdb is connected to. Replies are buffered until gdbstub
+/// flushes them, rather than sending a packet for each byte.
+pub struct GdbConnection(BufWriter<TcpStream>);
+
+impl gdbstub::Connection for GdbConnection {
+    type Error = std::io::Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.0.write_all(&[byte])
+    }
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(None)
+    }
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.flush()
+    }
+}
+
+struct XousDebugState {
+    target: XousTarget,
+    server: GdbStubStateMachine<'static, XousTarget, GdbConnection>,
+}
+
+// Like the rest of the kernel state, the debugger belongs to the thread that
+// runs `idle()`.
+thread_local!(static GDB_STATE: RefCell<Option<XousDebugState>> = RefCell::new(None));
+
+impl Target for XousTarget {
+    type Arch = gdbstub_arch::riscv::Riscv32;
+    type Error = &'static str;
+    fn base_ops(&mut self) -> BaseOps<Self::Arch, Self::Error> {
+        BaseOps::MultiThread(self)
+    }
+    fn monitor_cmd(&mut self) -> Option<MonitorCmdOps<Self>> {
+        Some(self)
+    }
+}
+
+impl MultiThreadOps for XousTarget {
+    fn resume(
+        &mut self,
+        default_resume_action: ResumeAction,
+        _gdb_interrupt: GdbInterrupt<'_>,
+    ) -> Result<Option<ThreadStopReason<u32>>, Self::Error> {
+        let step = self.step_requested
+            || matches!(
+                default_resume_action,
+                ResumeAction::Step | ResumeAction::StepWithSignal(_)
+            );
+        self.step_requested = false;
+        self.state = if step {
+            RunState::Stepping
+        } else {
+            RunState::Running
+        };
+        Ok(None)
+    }
+
+    fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
+        self.step_requested = false;
+        Ok(())
+    }
+
+    fn set_resume_action(&mut self, _tid: Tid, action: ResumeAction) -> Result<(), Self::Error> {
+        if let ResumeAction::Step | ResumeAction::StepWithSignal(_) = action {
+            self.step_requested = true;
+        }
+        Ok(())
+    }
+
+    fn read_registers(
+        &mut self,
+        _regs: &mut gdbstub_arch::riscv::reg::RiscvCoreRegs<u32>,
+        tid: Tid,
+    ) -> TargetResult<(), Self> {
+        // The registers live in a host process, so they all read as zero
+        xous_tid(tid).ok_or(TargetError::NonFatal)?;
+        Ok(())
+    }
+
+    fn write_registers(
+        &mut self,
+        _regs: &gdbstub_arch::riscv::reg::RiscvCoreRegs<u32>,
+        _tid: Tid,
+    ) -> TargetResult<(), Self> {
+        Err(TargetError::NonFatal)
+    }
+
+    fn read_addrs(
+        &mut self,
+        _start_addr: u32,
+        _data: &mut [u8],
+        _tid: Tid,
+    ) -> TargetResult<(), Self> {
+        Err(TargetError::NonFatal)
+    }
+
+    fn write_addrs(&mut self, _start_addr: u32, _data: &[u8], _tid: Tid) -> TargetResult<(), Self> {
+        Err(TargetError::NonFatal)
+    }
+
+    fn list_active_threads(
+        &mut self,
+        register_thread: &mut dyn FnMut(Tid),
+    ) -> Result<(), Self::Error> {
+        SystemServices::with(|system_services| {
+            let current_pid = system_services.current_pid();
+            let pids = system_services
+                .processes
+                .iter()
+                .filter(|process| !process.free())
+                .map(|process| process.pid);
+            for pid in pids {
+                if !has_started(system_services, pid) {
+                    continue;
+                }
+                system_services
+                    .get_process(pid)
+                    .unwrap()
+                    .activate()
+                    .unwrap();
+                ArchProcess::current().for_each_thread_mut(|tid, _thr| {
+                    register_thread(gdb_tid(pid, tid));
+                });
+            }
+            system_services
+                .get_process(current_pid)
+                .unwrap()
+                .activate()
+                .unwrap();
+        });
+        Ok(())
+    }
+}
+
+impl gdbstub::target::ext::monitor_cmd::MonitorCmd for XousTarget {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        let cmd = core::str::from_utf8(cmd).unwrap_or("");
+        if super::gdb_monitor::system_command(cmd, None, &mut out) {
+            return Ok(());
+        }
+        outputln!(out, "Xous kernel debugger commands:");
+        super::gdb_monitor::write_help(&mut out);
+        outputln!(out);
+        outputln!(out, "Threads of process P are numbered P * 256 + TID.");
+        outputln!(out, "No syscalls are handled while the kernel is stopped.");
+        outputln!(out, "\"stepi\" lets one syscall through.");
+        Ok(())
+    }
+}
+
+/// Take the debugger state, if gdb is connected, and put back whatever `f`
+/// returns. The state is taken out so that `f` is free to call back in.
+fn with_state<F>(f: F)
+where
+    F: FnOnce(XousDebugState) -> Option<XousDebugState>,
+{
+    if let Some(state) = GDB_STATE.with(|gdb_state| gdb_state.borrow_mut().take()) {
+        let state = f(state);
+        GDB_STATE.with(|gdb_state| *gdb_state.borrow_mut() = state);
+    }
+}
+
+/// Tell gdb that the kernel has stopped, if it is waiting to hear
+fn report_stop(state: XousDebugState) -> Option<XousDebugState> {
+    let XousDebugState {
+        mut target,
+        server: gdb,
+    } = state;
+    target.state = RunState::Stopped;
+    let gdb = match gdb {
+        GdbStubStateMachine::DeferredStopReason(gdb_state) => {
+            match gdb_state.deferred_stop_reason(&mut target, ThreadStopReason::DoneStep) {
+                Ok((gdb, None)) => gdb,
+                Ok((_, Some(disconnect_reason))) => {
+                    println!("KERNEL: gdb disconnected: {:?}", disconnect_reason);
+                    return None;
+                }
+                Err(e) => {
+                    println!("KERNEL: gdb deferred_stop_reason error: {:?}", e);
+                    return None;
+                }
+            }
+        }
+        gdb => gdb,
+    };
+    Some(XousDebugState {
+        target,
+        server: gdb,
+    })
+}
+
+/// Start a debugging session on `conn`, with the kernel stopped.
+pub fn connect(conn: TcpStream) {
+    if GDB_STATE.with(|gdb_state| gdb_state.borrow().is_some()) {
+        println!("KERNEL: gdb is already connected");
+        conn.shutdown(std::net::Shutdown::Both).ok();
+        return;
+    }
+    // The state machine borrows its buffer for as long as it exists, and
+    // is kept in a thread local. Sessions are rare enough that leaking one
+    // buffer each is simpler than working out when it's safe to reuse.
+    let buffer = Box::leak(vec![0u8; 4096].into_boxed_slice());
+    match gdbstub::GdbStubBuilder::new(GdbConnection(BufWriter::new(conn)))
+        .with_packet_buffer(buffer)
+        .build()
+    {
+        Ok(gdb) => match gdb.run_state_machine() {
+            Ok(state) => GDB_STATE.with(|gdb_state| {
+                *gdb_state.borrow_mut() = Some(XousDebugState {
+                    target: XousTarget {
+                        state: RunState::Stopped,
+                        step_requested: false,
+                    },
+                    server: state,
+                })
+            }),
+            Err(e) => println!("KERNEL: unable to start gdb state machine: {}", e),
+        },
+        Err(e) => println!("KERNEL: unable to start gdb server: {}", e),
+    }
+}
+
+/// Feed bytes that gdb sent to the gdb server
+pub fn receive(bytes: &[u8]) {
+    for b in bytes {
+        with_state(|state| {
+            let XousDebugState {
+                mut target,
+                server: gdb,
+            } = state;
+            let gdb = match gdb {
+                GdbStubStateMachine::Pump(gdb_state) => match gdb_state.pump(&mut target, *b) {
+                    Ok((gdb, None)) => gdb,
+                    Ok((_, Some(disconnect_reason))) => {
+                        match disconnect_reason {
+                            DisconnectReason::Disconnect => println!("KERNEL: gdb disconnected"),
+                            DisconnectReason::TargetExited(_) => println!("KERNEL: target exited"),
+                            DisconnectReason::TargetTerminated(_) => {
+                                println!("KERNEL: target terminated")
+                            }
+                            DisconnectReason::Kill => println!("KERNEL: gdb sent a kill command"),
+                        }
+                        return None;
+                    }
+                    Err(e) => {
+                        println!("KERNEL: gdbstub error: {}", e);
+                        return None;
+                    }
+                },
+
+                // gdb interrupted the running kernel
+                gdb @ GdbStubStateMachine::DeferredStopReason(_) => {
+                    return report_stop(XousDebugState {
+                        target,
+                        server: gdb,
+                    });
+                }
+            };
+            Some(XousDebugState {
+                target,
+                server: gdb,
+            })
+        });
+    }
+}
+
+/// End the debugging session after gdb's connection closes
+pub fn disconnect() {
+    if GDB_STATE
+        .with(|gdb_state| gdb_state.borrow_mut().take())
+        .is_some()
+    {
+        println!("KERNEL: gdb disconnected");
+    }
+}
+
+/// `true` if gdb has the kernel stopped, in which case syscalls must wait
+/// until it continues.
+pub fn stopped() -> bool {
+    GDB_STATE.with(|gdb_state| {
+        matches!(&*gdb_state.borrow(), Some(state) if state.target.state == RunState::Stopped)
+    })
+}
+
+/// Note that a syscall was handled, which finishes a step.
+pub fn syscall_handled() {
+    with_state(|state| {
+        if state.target.state == RunState::Stepping {
+            report_stop(state)
+        } else {
+            Some(state)
+        }
+    });
+}
@@ -0,0 +1,346 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The parts of the gdb server that don't depend on how processes are run:
+//! naming threads, and the `monitor` commands that show the state of
+//! `SystemServices`.
+
+use core::fmt::{Result, Write};
+use core::num::NonZeroU8;
+
+use gdbstub::common::Tid;
+
+use crate::arch::process::Process as ArchProcess;
+use crate::services::SystemServices;
+use xous_kernel::{ProcessStatus, PID, SID, TID};
+
+/// gdb is shown the threads of every process, so each thread ID carries the
+/// PID in its upper bits.
+pub fn gdb_tid(pid: PID, tid: TID) -> Tid {
+    Tid::new((pid.get() as usize) << 8 | tid).unwrap()
+}
+
+pub fn xous_tid(tid: Tid) -> Option<(PID, TID)> {
+    let pid = PID::new((tid.get() >> 8) as u8)?;
+    let tid = tid.get() & 0xff;
+    if !crate::arch::process::THREAD_IDS.contains(&tid) {
+        return None;
+    }
+    Some((pid, tid))
+}
+
+/// Processes that haven't started yet have no threads to show or stop
+pub fn has_started(system_services: &SystemServices, pid: PID) -> bool {
+    match system_services.process_info(pid, pid) {
+        Ok(info) => !matches!(info.status, ProcessStatus::Allocated | ProcessStatus::Setup),
+        Err(_) => false,
+    }
+}
+
+/// Servers are usually named with a 16-character string, which is easier to
+/// recognise than the numbers it turns into.
+fn write_sid(out: &mut dyn Write, sid: SID) -> Result {
+    let words = sid.to_array();
+    let mut name = [0u8; 16];
+    for (bytes, word) in name.chunks_exact_mut(4).zip(words.iter()) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    match core::str::from_utf8(&name) {
+        Ok(name) if name.bytes().all(|b| b.is_ascii_graphic() || b == b' ') => {
+            write!(out, "\"{}\"", name)
+        }
+        _ => write!(
+            out,
+            "{:08x}-{:08x}-{:08x}-{:08x}",
+            words[0], words[1], words[2], words[3]
+        ),
+    }
+}
+
+fn write_processes(
+    system_services: &SystemServices,
+    attached: Option<PID>,
+    out: &mut dyn Write,
+) -> Result {
+    writeln!(out, "PID  NAME              STATUS")?;
+    for process in system_services.processes.iter() {
+        if process.free() {
+            continue;
+        }
+        let status = match system_services.process_info(process.pid, process.pid) {
+            Ok(info) => info.status,
+            Err(_) => continue,
+        };
+        writeln!(
+            out,
+            "{:>3}  {:<16}  {}{}",
+            process.pid,
+            system_services.process_name(process.pid).unwrap_or(""),
+            status,
+            if Some(process.pid) == attached {
+                " (attached)"
+            } else {
+                ""
+            }
+        )?;
+    }
+    Ok(())
+}
+
+fn write_servers(system_services: &SystemServices, out: &mut dyn Write) -> Result {
+    writeln!(out, "SIDX  PID  QUEUED  SID")?;
+    for (sidx, server) in system_services.servers() {
+        write!(
+            out,
+            "{:>4}  {:>3}  {:>6}  ",
+            sidx,
+            server.pid,
+            server.queue_len()
+        )?;
+        write_sid(out, server.sid)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn write_queue(system_services: &SystemServices, sidx: usize, out: &mut dyn Write) -> Result {
+    let server = match system_services.server_from_sidx(sidx) {
+        Some(server) => server,
+        None => return writeln!(out, "There is no server {}", sidx),
+    };
+    write!(out, "Server {} owned by PID {}: ", sidx, server.pid)?;
+    write_sid(out, server.sid)?;
+    writeln!(out)?;
+    server.write_queue(out)
+}
+
+/// The connection map of `pid`, which is only visible while the process is
+/// active.
+fn connection_map(system_services: &SystemServices, pid: PID) -> Option<[Option<NonZeroU8>; 32]> {
+    if !has_started(system_services, pid) {
+        return None;
+    }
+    let current_pid = system_services.current_pid();
+    system_services.get_process(pid).ok()?.activate().ok()?;
+    let connection_map = ArchProcess::with_inner(|process_inner| process_inner.connection_map);
+    system_services
+        .get_process(current_pid)
+        .unwrap()
+        .activate()
+        .unwrap();
+    Some(connection_map)
+}
+
+fn write_connections(
+    system_services: &SystemServices,
+    only: Option<PID>,
+    out: &mut dyn Write,
+) -> Result {
+    writeln!(out, "PID  CID  SIDX  OWNER  SID")?;
+    let pids = system_services
+        .processes
+        .iter()
+        .filter(|process| !process.free())
+        .map(|process| process.pid)
+        .filter(|pid| only.map(|only| only == *pid).unwrap_or(true));
+    for pid in pids {
+        let connection_map = match connection_map(system_services, pid) {
+            Some(connection_map) => connection_map,
+            None => continue,
+        };
+        for (idx, connection) in connection_map.iter().enumerate() {
+            // Values of 0 and 1 mark a connection that has been closed
+            let sidx = match connection.map(|value| value.get() as usize) {
+                Some(value) if value >= 2 => value - 2,
+                _ => continue,
+            };
+            write!(out, "{:>3}  {:>3}  {:>4}  ", pid, idx + 2, sidx)?;
+            match system_services.server_from_sidx(sidx) {
+                Some(server) => {
+                    write!(out, "{:>5}  ", server.pid)?;
+                    write_sid(out, server.sid)?;
+                    writeln!(out)?;
+                }
+                None => writeln!(out, "    -  (destroyed)")?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run `cmd` if it is one of the commands that every gdb server supports.
+/// `attached` is the process gdb is debugging, if there is one. Returns
+/// `false` if the command wasn't recognised.
+pub fn system_command(cmd: &str, attached: Option<PID>, out: &mut dyn Write) -> bool {
+    let mut words = cmd.split_whitespace();
+    SystemServices::with(|system_services| {
+        let result = match (words.next(), words.next(), words.next()) {
+            (Some("ps"), None, _) => write_processes(system_services, attached, out),
+            (Some("servers"), None, _) => write_servers(system_services, out),
+            (Some("queue"), Some(sidx), None) => match sidx.parse() {
+                Ok(sidx) => write_queue(system_services, sidx, out),
+                Err(_) => writeln!(out, "\"{}\" is not a server index", sidx),
+            },
+            (Some("connections"), None, _) => write_connections(system_services, None, out),
+            (Some("connections"), Some(pid), None) => {
+                match pid.parse::<u8>().ok().and_then(PID::new) {
+                    Some(pid) => write_connections(system_services, Some(pid), out),
+                    None => writeln!(out, "\"{}\" is not a PID", pid),
+                }
+            }
+            _ => return false,
+        };
+        result.ok();
+        true
+    })
+}
+
+/// List the commands that `system_command()` handles
+pub fn write_help(out: &mut dyn Write) {
+    writeln!(out, "  monitor ps                 list processes").ok();
+    writeln!(
+        out,
+        "  monitor servers            list servers and how many messages they have"
+    )
+    .ok();
+    writeln!(
+        out,
+        "  monitor queue <sidx>       show the messages queued for a server"
+    )
+    .ok();
+    writeln!(
+        out,
+        "  monitor connections [pid]  show which servers each process is connected to"
+    )
+    .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xous_kernel::{ProcessInit, ProcessKey, ThreadInit};
+
+    /// Start PID 1 the same way the hosted kernel does, then allocate a
+    /// second process that hasn't connected yet.
+    fn start_processes() -> (PID, PID) {
+        SystemServices::with_mut(|system_services| {
+            let init = ProcessInit {
+                key: ProcessKey::new([1; 16]),
+                priority: xous_kernel::THREAD_PRIORITY_DEFAULT,
+                page_limit: 0,
+            };
+            let pid1 = system_services.create_process(init).unwrap();
+            system_services
+                .create_thread(pid1, ThreadInit::default())
+                .unwrap();
+            let init = ProcessInit {
+                key: ProcessKey::new([2; 16]),
+                ..init
+            };
+            let pid2 = system_services.create_process(init).unwrap();
+            (pid1, pid2)
+        })
+    }
+
+    fn run(cmd: &str, attached: Option<PID>) -> Option<String> {
+        let mut out = String::new();
+        if system_command(cmd, attached, &mut out) {
+            Some(out)
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn thread_ids_round_trip() {
+        let pid = PID::new(3).unwrap();
+        for tid in crate::arch::process::THREAD_IDS {
+            assert_eq!(xous_tid(gdb_tid(pid, tid)), Some((pid, tid)));
+        }
+        // Hosted threads are numbered from 1, so the last one is past
+        // `MAX_THREAD`.
+        let last = crate::arch::process::MAX_THREAD + 1;
+        assert_eq!(xous_tid(gdb_tid(pid, last)), Some((pid, last)));
+        assert_eq!(xous_tid(gdb_tid(pid, last + 1)), None);
+        assert_eq!(xous_tid(gdb_tid(pid, 0)), None);
+        assert_eq!(xous_tid(Tid::new(5).unwrap()), None);
+    }
+
+    #[test]
+    fn sids_print_as_names_when_they_can() {
+        let mut out = String::new();
+        write_sid(&mut out, SID::from_bytes(b"ticktimer-server").unwrap()).unwrap();
+        assert_eq!(out, "\"ticktimer-server\"");
+
+        let mut out = String::new();
+        write_sid(&mut out, SID::from_u32(1, 2, 0xdead_beef, 0)).unwrap();
+        assert_eq!(out, "00000001-00000002-deadbeef-00000000");
+    }
+
+    #[test]
+    fn process_list() {
+        let (pid1, pid2) = start_processes();
+        let out = run("ps", Some(pid1)).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some("PID  NAME              STATUS"));
+        assert_eq!(
+            lines.next().map(str::trim_end),
+            Some("  1                    Running (attached)")
+        );
+        assert_eq!(
+            lines.next().map(str::trim_end),
+            Some("  2                    Allocated")
+        );
+        assert_eq!(lines.next(), None);
+        assert!(!run("ps", Some(pid2))
+            .unwrap()
+            .contains("Running (attached)"));
+    }
+
+    #[test]
+    fn servers_queues_and_connections() {
+        let (pid1, pid2) = start_processes();
+        let sid = SID::from_bytes(b"monitor-test-srv").unwrap();
+        SystemServices::with_mut(|system_services| {
+            system_services
+                .create_server_with_address(pid1, sid)
+                .unwrap()
+        });
+
+        let out = run("servers", None).unwrap();
+        assert_eq!(
+            out,
+            "SIDX  PID  QUEUED  SID\n   0    1       0  \"monitor-test-srv\"\n"
+        );
+
+        let out = run("queue 0", None).unwrap();
+        assert!(out.starts_with("Server 0 owned by PID 1: \"monitor-test-srv\"\n"));
+        assert_eq!(run("queue 1", None).unwrap(), "There is no server 1\n");
+        assert_eq!(
+            run("queue x", None).unwrap(),
+            "\"x\" is not a server index\n"
+        );
+
+        // Creating the server connected PID 1 to it. PID 2 hasn't started,
+        // so it has no connection map to show.
+        let expected = "PID  CID  SIDX  OWNER  SID\n  1    2     0      1  \"monitor-test-srv\"\n";
+        assert_eq!(run("connections", None).unwrap(), expected);
+        assert_eq!(
+            run(&format!("connections {}", pid1), None).unwrap(),
+            expected
+        );
+        assert_eq!(
+            run(&format!("connections {}", pid2), None).unwrap(),
+            "PID  CID  SIDX  OWNER  SID\n"
+        );
+        assert_eq!(run("connections 0", None).unwrap(), "\"0\" is not a PID\n");
+    }
+
+    #[test]
+    fn unknown_commands_are_left_to_the_caller() {
+        assert_eq!(run("", None), None);
+        assert_eq!(run("help", None), None);
+        assert_eq!(run("ps extra", None), None);
+        assert_eq!(run("queue", None), None);
+        assert_eq!(run("queue 0 1", None), None);
+    }
+}
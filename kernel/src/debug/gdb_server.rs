@@ -2,14 +2,130 @@ use gdbstub::common::Tid;
 use gdbstub::target::ext::base::multithread::{
     GdbInterrupt, MultiThreadOps, ResumeAction, ThreadStopReason,
 };
-use gdbstub::{DisconnectReason, GdbStubError};
+use gdbstub::target::ext::breakpoints::WatchKind;
+use gdbstub::target::ext::monitor_cmd::{ConsoleOutput, MonitorCmdOps};
+use gdbstub::{outputln, DisconnectReason, GdbStubError};
 
 use gdbstub::state_machine::GdbStubStateMachine;
 use gdbstub::target::ext::base::BaseOps;
-use gdbstub::target::{Target, TargetResult};
+use gdbstub::target::{Target, TargetError, TargetResult};
+
+use crate::arch::exception::RiscvException;
+use crate::arch::mem::{peek_memory, poke_memory, set_page_writable, PAGE_SIZE};
+use crate::arch::process::Process as ArchProcess;
+use crate::debug::gdb_monitor::{gdb_tid, has_started, xous_tid};
+use crate::services::SystemServices;
+use xous_kernel::{ProcessStatus, PID, TID};
+
+/// `ebreak`, which replaces 32-bit instructions
+const EBREAK: u32 = 0x0010_0073;
+
+/// `c.ebreak`, which replaces 16-bit instructions
+const C_EBREAK: u16 = 0x9002;
+
+/// The number of memory watchpoints that may be set at once
+const MAX_WATCHPOINTS: usize = 4;
+
+/// This version of gdbstub doesn't pass on the length of a watched region,
+/// so each watchpoint covers one word. gdb splits larger regions into words.
+const WATCH_LEN: usize = 4;
+
+/// An instruction that was replaced with an `ebreak` so that the process
+/// stops when it gets there.
+#[derive(Copy, Clone)]
+struct TempBreakpoint {
+    addr: usize,
+    original: [u16; 2],
+}
+
+impl TempBreakpoint {
+    /// Put an `ebreak` at `addr` in the current address space, using the
+    /// same size as the instruction that's already there.
+    fn insert(addr: usize) -> Result<TempBreakpoint, xous_kernel::Error> {
+        let first = peek_memory(addr as *mut u16)?;
+        if first & 3 != 3 {
+            poke_memory(addr as *mut u16, C_EBREAK)?;
+            return Ok(TempBreakpoint {
+                addr,
+                original: [first, 0],
+            });
+        }
+        let second = peek_memory((addr + 2) as *mut u16)?;
+        poke_memory(addr as *mut u16, EBREAK as u16)?;
+        poke_memory((addr + 2) as *mut u16, (EBREAK >> 16) as u16)?;
+        Ok(TempBreakpoint {
+            addr,
+            original: [first, second],
+        })
+    }
+
+    /// Put the original instruction back. The address space of the process
+    /// it was inserted into must be active.
+    fn remove(&self) {
+        poke_memory(self.addr as *mut u16, self.original[0]).ok();
+        if self.original[0] & 3 == 3 {
+            poke_memory((self.addr + 2) as *mut u16, self.original[1]).ok();
+        }
+    }
+}
+
+/// A thread that is running until it reaches one of the places its current
+/// instruction could go next, since RISC-V has no way of stepping a single
+/// instruction from Supervisor mode.
+#[derive(Copy, Clone)]
+struct Step {
+    pid: PID,
+    tid: TID,
+    breakpoints: [Option<TempBreakpoint>; 2],
+
+    /// `true` if gdb asked for this step, as opposed to the debugger
+    /// stepping over a write to a page with a watchpoint in it
+    requested: bool,
+
+    /// The watchpoint that the stepped instruction wrote to, if any
+    watch_hit: Option<usize>,
+}
+
+impl Step {
+    fn hits(&self, pc: usize) -> bool {
+        self.breakpoints.iter().flatten().any(|bp| bp.addr == pc)
+    }
+
+    fn remove_breakpoints(&self) {
+        for bp in self.breakpoints.iter().rev().flatten() {
+            bp.remove();
+        }
+    }
+}
+
+/// A word of memory that stops the process when it is written. Writes are
+/// caught by taking away write access to the page it's in.
+#[derive(Copy, Clone)]
+struct Watchpoint {
+    pid: PID,
+    addr: usize,
+}
+
+impl Watchpoint {
+    fn page(&self) -> usize {
+        self.addr & !(PAGE_SIZE - 1)
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        addr >= self.addr && addr < self.addr + WATCH_LEN
+    }
+}
 
 pub struct XousTarget {
+    /// The process that is stopped while gdb has control, and that is
+    /// resumed when gdb continues
     pid: Option<xous_kernel::PID>,
+
+    /// The thread gdb asked to step the next time it resumes
+    step_request: Option<Tid>,
+
+    step: Option<Step>,
+    watchpoints: [Option<Watchpoint>; MAX_WATCHPOINTS],
 }
 pub struct XousDebugState<'a> {
     pub target: XousTarget,
@@ -19,17 +135,222 @@ pub struct XousDebugState<'a> {
 pub static mut GDB_STATE: Option<XousDebugState> = None;
 pub static mut GDB_BUFFER: [u8; 4096] = [0u8; 4096];
 
+/// Run `f` with the address space and threads of `pid` active, then switch
+/// back to the current process.
+fn with_process<F, R>(pid: PID, f: F) -> Result<R, xous_kernel::Error>
+where
+    F: FnOnce(&mut ArchProcess) -> R,
+{
+    SystemServices::with(|system_services| {
+        let current_pid = system_services.current_pid();
+        system_services.get_process(pid)?.activate()?;
+        let result = f(&mut ArchProcess::current());
+        system_services
+            .get_process(current_pid)
+            .unwrap()
+            .activate()
+            .unwrap();
+        Ok(result)
+    })
+}
+
+/// Sign-extend the lowest `bits` bits of `value`
+fn sign_extend(value: u32, bits: u32) -> usize {
+    (((value << (32 - bits)) as i32) >> (32 - bits)) as usize
+}
+
+/// Work out where execution may go after the instruction `insn` at `pc`.
+/// Both entries are the same unless the instruction is a branch. `reg`
+/// reads an integer register of the thread being stepped.
+fn next_pcs(pc: usize, insn: u32, reg: &dyn Fn(u32) -> usize) -> [usize; 2] {
+    let bit = |n: u32| (insn >> n) & 1;
+    let bits = |hi: u32, lo: u32| (insn >> lo) & ((1 << (hi - lo + 1)) - 1);
+
+    // Compressed instructions
+    if insn & 3 != 3 {
+        let next = pc.wrapping_add(2);
+        return match (insn & 3, bits(15, 13)) {
+            // c.jal and c.j
+            (1, 0b001) | (1, 0b101) => {
+                let offset = bit(12) << 11
+                    | bit(11) << 4
+                    | bits(10, 9) << 8
+                    | bit(8) << 10
+                    | bit(7) << 6
+                    | bit(6) << 7
+                    | bits(5, 3) << 1
+                    | bit(2) << 5;
+                let target = pc.wrapping_add(sign_extend(offset, 12));
+                [target, target]
+            }
+            // c.beqz and c.bnez
+            (1, 0b110) | (1, 0b111) => {
+                let offset = bit(12) << 8
+                    | bits(11, 10) << 3
+                    | bits(6, 5) << 6
+                    | bits(4, 3) << 1
+                    | bit(2) << 5;
+                [next, pc.wrapping_add(sign_extend(offset, 9))]
+            }
+            // c.jr and c.jalr, but not c.ebreak
+            (2, 0b100) if bits(6, 2) == 0 && bits(11, 7) != 0 => {
+                let target = reg(bits(11, 7)) & !1;
+                [target, target]
+            }
+            _ => [next, next],
+        };
+    }
+
+    let next = pc.wrapping_add(4);
+    match insn & 0x7f {
+        // jal
+        0x6f => {
+            let offset = bit(31) << 20 | bits(30, 21) << 1 | bit(20) << 11 | bits(19, 12) << 12;
+            let target = pc.wrapping_add(sign_extend(offset, 21));
+            [target, target]
+        }
+        // jalr
+        0x67 => {
+            let target = reg(bits(19, 15)).wrapping_add(sign_extend(bits(31, 20), 12)) & !1;
+            [target, target]
+        }
+        // beq, bne, blt, bge, bltu and bgeu
+        0x63 => {
+            let offset = bit(31) << 12 | bits(30, 25) << 5 | bits(11, 8) << 1 | bit(7) << 11;
+            [next, pc.wrapping_add(sign_extend(offset, 13))]
+        }
+        _ => [next, next],
+    }
+}
+
 impl XousTarget {
     pub fn new() -> XousTarget {
+        // Prefer a process that has already been stopped, such as by a crash
+        let halted = SystemServices::with(|system_services| {
+            system_services
+                .processes
+                .iter()
+                .filter(|process| !process.free())
+                .map(|process| process.pid)
+                .find(|pid| {
+                    matches!(
+                        system_services
+                            .process_info(*pid, *pid)
+                            .map(|info| info.status),
+                        Ok(ProcessStatus::Debug)
+                    )
+                })
+        });
         XousTarget {
-            // pid: Some(crate::services::SystemServices::with_mut(
-            //     |system_services| system_services.current_pid(),
-            pid: xous_kernel::PID::new(2),
+            pid: halted.or_else(|| xous_kernel::PID::new(2)),
+            step_request: None,
+            step: None,
+            watchpoints: [None; MAX_WATCHPOINTS],
         }
     }
     pub fn pid(&self) -> &Option<xous_kernel::PID> {
         &self.pid
     }
+
+    /// Stop `pid` and make it the process that gdb resumes, letting the
+    /// previously attached process run again.
+    fn attach(&mut self, pid: PID) -> Result<(), xous_kernel::Error> {
+        SystemServices::with(|system_services| {
+            if pid.get() == 1 || !has_started(system_services, pid) {
+                Err(xous_kernel::Error::ProcessNotFound)
+            } else {
+                Ok(())
+            }
+        })?;
+        if self.step.map(|step| step.pid) == self.pid {
+            self.cancel_step();
+        }
+        SystemServices::with_mut(|system_services| {
+            system_services.suspend_process(pid)?;
+            if let Some(previous) = self.pid.replace(pid) {
+                if previous != pid {
+                    system_services.continue_process(previous)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Put breakpoints everywhere the current instruction of `tid` could go
+    /// next. `process` must be the active process.
+    fn begin_step(
+        &mut self,
+        process: &ArchProcess,
+        pid: PID,
+        tid: TID,
+        requested: bool,
+    ) -> Result<(), xous_kernel::Error> {
+        let thread = process.thread(tid);
+        let pc = thread.sepc;
+        let mut insn = peek_memory(pc as *mut u16)? as u32;
+        if insn & 3 == 3 {
+            insn |= (peek_memory((pc + 2) as *mut u16)? as u32) << 16;
+        }
+        let reg = |r: u32| match r {
+            0 => 0,
+            r => thread.registers[r as usize - 1],
+        };
+        let [next, target] = next_pcs(pc, insn, &reg);
+
+        let first = TempBreakpoint::insert(next)?;
+        let second = if target != next {
+            match TempBreakpoint::insert(target) {
+                Ok(bp) => Some(bp),
+                Err(e) => {
+                    first.remove();
+                    return Err(e);
+                }
+            }
+        } else {
+            None
+        };
+        self.step = Some(Step {
+            pid,
+            tid,
+            breakpoints: [Some(first), second],
+            requested,
+            watch_hit: None,
+        });
+        Ok(())
+    }
+
+    /// Abandon a step that is under way, such as when gdb interrupts the
+    /// process before it gets there.
+    fn cancel_step(&mut self) {
+        if let Some(step) = self.step.take() {
+            with_process(step.pid, |_| {
+                step.remove_breakpoints();
+                self.protect_watched_pages(step.pid);
+            })
+            .ok();
+        }
+    }
+
+    /// Take away write access to every page with a watchpoint in it, which
+    /// is needed after a write to one of those pages is allowed through.
+    /// `pid` must be the active process.
+    fn protect_watched_pages(&self, pid: PID) {
+        for watchpoint in self.watchpoints.iter().flatten() {
+            if watchpoint.pid == pid {
+                set_page_writable(watchpoint.addr, false).ok();
+            }
+        }
+    }
+
+    /// Undo everything the debugger did to processes, for when gdb goes away.
+    fn release(&mut self) {
+        self.cancel_step();
+        for watchpoint in self.watchpoints.iter_mut() {
+            if let Some(w) = watchpoint.take() {
+                with_process(w.pid, |_| set_page_writable(w.addr, true)).ok();
+            }
+        }
+    }
 }
 
 impl Target for XousTarget {
@@ -41,6 +362,9 @@ impl Target for XousTarget {
     fn breakpoints(&mut self) -> Option<gdbstub::target::ext::breakpoints::BreakpointsOps<Self>> {
         Some(self)
     }
+    fn monitor_cmd(&mut self) -> Option<MonitorCmdOps<Self>> {
+        Some(self)
+    }
 }
 
 impl MultiThreadOps for XousTarget {
@@ -50,27 +374,54 @@ impl MultiThreadOps for XousTarget {
         _gdb_interrupt: GdbInterrupt<'_>,
     ) -> Result<Option<ThreadStopReason<u32>>, Self::Error> {
         unsafe { HALTED = false };
-        match default_resume_action {
-            ResumeAction::Step | ResumeAction::StepWithSignal(_) => {
-                return Err("single-stepping not supported")
+        let attached = self.pid.ok_or("no process is attached")?;
+
+        // A step without a thread is for the thread gdb last looked at
+        let step = match (self.step_request.take(), default_resume_action) {
+            (Some(tid), _) => Some(tid),
+            (None, ResumeAction::Step) | (None, ResumeAction::StepWithSignal(_)) => {
+                let tid = SystemServices::with(|system_services| {
+                    system_services
+                        .get_process(attached)
+                        .unwrap()
+                        .current_thread
+                });
+                Some(gdb_tid(attached, tid))
+            }
+            (None, _) => None,
+        };
+
+        if let Some(tid) = step {
+            let (pid, tid) = xous_tid(tid).ok_or("can't step a thread that doesn't exist")?;
+            self.cancel_step();
+            with_process(pid, |process| self.begin_step(process, pid, tid, true))
+                .and_then(|result| result)
+                .or(Err(
+                    "couldn't put a breakpoint after the current instruction",
+                ))?;
+            if pid != attached {
+                crate::services::SystemServices::with_mut(|system_services| {
+                    system_services.continue_process(pid).unwrap()
+                });
             }
-            _ => (),
         }
 
         crate::services::SystemServices::with_mut(|system_services| {
-            system_services.continue_process(self.pid.unwrap()).unwrap()
+            system_services.continue_process(attached).unwrap()
         });
         Ok(None)
     }
 
     fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
+        self.step_request = None;
         Ok(())
     }
 
-    fn set_resume_action(&mut self, _tid: Tid, action: ResumeAction) -> Result<(), Self::Error> {
+    fn set_resume_action(&mut self, tid: Tid, action: ResumeAction) -> Result<(), Self::Error> {
         match action {
             ResumeAction::Step | ResumeAction::StepWithSignal(_) => {
-                Err("single-stepping resume action not supported")
+                self.step_request = Some(tid);
+                Ok(())
             }
             ResumeAction::Continue | ResumeAction::ContinueWithSignal(_) => Ok(()),
         }
@@ -81,32 +432,16 @@ impl MultiThreadOps for XousTarget {
         regs: &mut gdbstub_arch::riscv::reg::RiscvCoreRegs<u32>,
         tid: Tid,
     ) -> TargetResult<(), Self> {
-        crate::services::SystemServices::with(|system_services| {
-            let current_pid = system_services.current_pid();
-            // Actiavte the debugging process and iterate through it,
-            // noting down each active thread.
-            let debugging_pid = self.pid.unwrap();
-            system_services
-                .get_process(debugging_pid)
-                .unwrap()
-                .activate()
-                .unwrap();
-            let process = crate::arch::process::Process::current();
-            let thread = process.thread(tid.get());
+        let (pid, tid) = xous_tid(tid).ok_or(TargetError::NonFatal)?;
+        with_process(pid, |process| {
+            let thread = process.thread(tid);
             regs.x[0] = 0;
             for (dbg_reg, thr_reg) in regs.x[1..].iter_mut().zip(thread.registers.iter()) {
                 *dbg_reg = (*thr_reg) as u32;
             }
             regs.pc = (thread.sepc) as u32;
-
-            // Restore the previous PID
-            system_services
-                .get_process(current_pid)
-                .unwrap()
-                .activate()
-                .unwrap();
-        });
-        Ok(())
+        })
+        .or(Err(TargetError::NonFatal))
     }
 
     fn write_registers(
@@ -114,102 +449,53 @@ impl MultiThreadOps for XousTarget {
         regs: &gdbstub_arch::riscv::reg::RiscvCoreRegs<u32>,
         tid: Tid,
     ) -> TargetResult<(), Self> {
-        crate::services::SystemServices::with(|system_services| {
-            let current_pid = system_services.current_pid();
-
-            // Actiavte the debugging process and iterate through it,
-            // noting down each active thread.
-            let debugging_pid = self.pid.unwrap();
-            system_services
-                .get_process(debugging_pid)
-                .unwrap()
-                .activate()
-                .unwrap();
-            let mut process = crate::arch::process::Process::current();
-            let thread = process.thread_mut(tid.get());
+        let (pid, tid) = xous_tid(tid).ok_or(TargetError::NonFatal)?;
+        with_process(pid, |process| {
+            let thread = process.thread_mut(tid);
             for (thr_reg, dbg_reg) in thread.registers.iter_mut().zip(regs.x[1..].iter()) {
                 *thr_reg = (*dbg_reg) as usize;
             }
             thread.sepc = (regs.pc) as usize;
-
-            // Restore the previous PID
-            system_services
-                .get_process(current_pid)
-                .unwrap()
-                .activate()
-                .unwrap();
-        });
-        Ok(())
+        })
+        .or(Err(TargetError::NonFatal))
     }
 
     fn read_addrs(
         &mut self,
         start_addr: u32,
         data: &mut [u8],
-        _tid: Tid, // same address space for each core
+        tid: Tid, // every thread of a process shares its address space
     ) -> TargetResult<(), Self> {
+        let (pid, _) = xous_tid(tid).ok_or(TargetError::NonFatal)?;
         let mut current_addr = start_addr;
-        crate::services::SystemServices::with(|system_services| {
-            let current_pid = system_services.current_pid();
-
-            // Actiavte the debugging process and iterate through it,
-            // noting down each active thread.
-            let debugging_pid = self.pid.unwrap();
-            system_services
-                .get_process(debugging_pid)
-                .unwrap()
-                .activate()
-                .unwrap();
+        with_process(pid, |_| {
             data.iter_mut().for_each(|b| {
-                *b = crate::arch::mem::peek_memory(current_addr as *mut u8).unwrap_or(0xff);
+                *b = peek_memory(current_addr as *mut u8).unwrap_or(0xff);
                 current_addr += 1;
             });
-
-            // Restore the previous PID
-            system_services
-                .get_process(current_pid)
-                .unwrap()
-                .activate()
-                .unwrap();
-        });
-        Ok(())
+        })
+        .or(Err(TargetError::NonFatal))
     }
 
     fn write_addrs(
         &mut self,
         start_addr: u32,
         data: &[u8],
-        _tid: Tid, // same address space for each core
+        tid: Tid, // every thread of a process shares its address space
     ) -> TargetResult<(), Self> {
+        let (pid, _) = xous_tid(tid).ok_or(TargetError::NonFatal)?;
         let mut current_addr = start_addr;
         // gprintln!("Writing data to {:08x}", start_addr);
-        crate::services::SystemServices::with(|system_services| {
-            let current_pid = system_services.current_pid();
-
-            // Actiavte the debugging process and iterate through it,
-            // noting down each active thread.
-            let debugging_pid = self.pid.unwrap();
-            system_services
-                .get_process(debugging_pid)
-                .unwrap()
-                .activate()
-                .unwrap();
+        with_process(pid, |_| {
             data.iter().for_each(|b| {
-                if let Err(_e) = crate::arch::mem::poke_memory(current_addr as *mut u8, *b) {
+                if let Err(_e) = poke_memory(current_addr as *mut u8, *b) {
                     panic!("couldn't poke memory: {:?}", _e);
                     // gprintln!("Error writing to {:08x}: {:?}", current_addr, e);
                 }
                 current_addr += 1;
             });
-
-            // Restore the previous PID
-            system_services
-                .get_process(current_pid)
-                .unwrap()
-                .activate()
-                .unwrap();
-        });
-        Ok(())
+        })
+        .or(Err(TargetError::NonFatal))
     }
 
     fn list_active_threads(
@@ -219,19 +505,29 @@ impl MultiThreadOps for XousTarget {
         crate::services::SystemServices::with(|system_services| {
             let current_pid = system_services.current_pid();
 
-            let debugging_pid = self.pid.unwrap();
+            // The attached process goes first, so that it is the one gdb
+            // picks when it connects.
+            let others = system_services
+                .processes
+                .iter()
+                .filter(|process| !process.free() && Some(process.pid) != self.pid)
+                .map(|process| process.pid);
+            for pid in self.pid.into_iter().chain(others) {
+                if pid.get() == 1 || !has_started(system_services, pid) {
+                    continue;
+                }
 
-            // Actiavte the debugging process and iterate through it,
-            // noting down each active thread.
-            system_services
-                .get_process(debugging_pid)
-                .unwrap()
-                .activate()
-                .unwrap();
-            crate::arch::process::Process::current().for_each_thread_mut(|tid, _thr| {
-                println!("Registering thread {}", tid);
-                register_thread(Tid::new(tid).unwrap());
-            });
+                // Actiavte each process and iterate through it, noting down
+                // each active thread.
+                system_services
+                    .get_process(pid)
+                    .unwrap()
+                    .activate()
+                    .unwrap();
+                ArchProcess::current().for_each_thread_mut(|tid, _thr| {
+                    register_thread(gdb_tid(pid, tid));
+                });
+            }
 
             // Restore the previous PID
             system_services
@@ -250,6 +546,11 @@ impl gdbstub::target::ext::breakpoints::Breakpoints for XousTarget {
     ) -> Option<gdbstub::target::ext::breakpoints::HwBreakpointOps<Self>> {
         Some(self)
     }
+    fn hw_watchpoint(
+        &mut self,
+    ) -> Option<gdbstub::target::ext::breakpoints::HwWatchpointOps<Self>> {
+        Some(self)
+    }
 }
 
 impl gdbstub::target::ext::breakpoints::HwBreakpoint for XousTarget {
@@ -262,6 +563,261 @@ impl gdbstub::target::ext::breakpoints::HwBreakpoint for XousTarget {
     }
 }
 
+impl gdbstub::target::ext::breakpoints::HwWatchpoint for XousTarget {
+    fn add_hw_watchpoint(&mut self, addr: u32, kind: WatchKind) -> TargetResult<bool, Self> {
+        // Only writes can be caught, since a page can't be made unreadable
+        // without also making it look unmapped.
+        let pid = match (kind, self.pid) {
+            (WatchKind::Write, Some(pid)) => pid,
+            _ => return Ok(false),
+        };
+        let watchpoint = Watchpoint {
+            pid,
+            addr: addr as usize,
+        };
+        let slot = match self.watchpoints.iter().position(|w| w.is_none()) {
+            Some(slot) => slot,
+            None => return Ok(false),
+        };
+
+        // The page may already be protected for another watchpoint.
+        // Otherwise, it must be writable in the first place.
+        let shares_page = self
+            .watchpoints
+            .iter()
+            .flatten()
+            .any(|w| w.pid == pid && w.page() == watchpoint.page());
+        if !shares_page {
+            let protected = with_process(pid, |_| {
+                crate::arch::mem::ensure_page_exists_inner(watchpoint.addr)?;
+                set_page_writable(watchpoint.addr, false)
+            });
+            if !matches!(protected, Ok(Ok(true))) {
+                return Ok(false);
+            }
+        }
+        self.watchpoints[slot] = Some(watchpoint);
+        Ok(true)
+    }
+
+    fn remove_hw_watchpoint(&mut self, addr: u32, kind: WatchKind) -> TargetResult<bool, Self> {
+        let pid = match (kind, self.pid) {
+            (WatchKind::Write, Some(pid)) => pid,
+            _ => return Ok(false),
+        };
+        let slot = match self
+            .watchpoints
+            .iter()
+            .position(|w| matches!(w, Some(w) if w.pid == pid && w.addr == addr as usize))
+        {
+            Some(slot) => slot,
+            None => return Ok(false),
+        };
+        let watchpoint = self.watchpoints[slot].take().unwrap();
+        let shares_page = self
+            .watchpoints
+            .iter()
+            .flatten()
+            .any(|w| w.pid == pid && w.page() == watchpoint.page());
+        if !shares_page {
+            with_process(pid, |_| set_page_writable(watchpoint.addr, true))
+                .or(Err(TargetError::NonFatal))?
+                .or(Err(TargetError::NonFatal))?;
+        }
+        Ok(true)
+    }
+}
+
+impl gdbstub::target::ext::monitor_cmd::MonitorCmd for XousTarget {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        let cmd = core::str::from_utf8(cmd).unwrap_or("");
+        if super::gdb_monitor::system_command(cmd, self.pid, &mut out) {
+            return Ok(());
+        }
+        let mut words = cmd.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("attach"), Some(which)) => {
+                // Processes may be given by PID or by name
+                let pid = SystemServices::with(|system_services| {
+                    which.parse::<u8>().ok().and_then(PID::new).or_else(|| {
+                        system_services
+                            .processes
+                            .iter()
+                            .filter(|process| !process.free())
+                            .map(|process| process.pid)
+                            .find(|pid| system_services.process_name(*pid) == Some(which))
+                    })
+                });
+                match pid.map(|pid| (pid, self.attach(pid))) {
+                    Some((pid, Ok(()))) => {
+                        outputln!(out, "Attached to PID {}", pid);
+                        outputln!(out, "Run \"info threads\" and \"thread\" to switch to it");
+                    }
+                    Some((pid, Err(e))) => {
+                        outputln!(out, "Couldn't attach to PID {}: {:?}", pid, e)
+                    }
+                    None => outputln!(out, "No process is called \"{}\"", which),
+                }
+            }
+            _ => {
+                outputln!(out, "Xous kernel debugger commands:");
+                super::gdb_monitor::write_help(&mut out);
+                outputln!(
+                    out,
+                    "  monitor attach <pid>       stop a process and debug it"
+                );
+                outputln!(
+                    out,
+                    "  monitor attach <name>      stop a process and debug it"
+                );
+                outputln!(out);
+                outputln!(out, "Threads of process P are numbered P * 256 + TID.");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stop `pid` and tell gdb why, if gdb is waiting to hear. `pid` must be the
+/// current process.
+fn report_stop(pid: PID, reason: ThreadStopReason<u32>) {
+    SystemServices::with_mut(|system_services| {
+        system_services
+            .suspend_process(pid)
+            .expect("couldn't debug current process");
+    });
+    crate::syscall::reset_switchto_caller();
+
+    let XousDebugState {
+        mut target,
+        server: gdb,
+    } = match unsafe { GDB_STATE.take() } {
+        Some(state) => state,
+        None => return,
+    };
+    let new_gdb = match gdb {
+        GdbStubStateMachine::DeferredStopReason(gdb_state) => {
+            match gdb_state.deferred_stop_reason(&mut target, reason) {
+                Ok((gdb, None)) => gdb,
+                Ok((_, Some(disconnect_reason))) => {
+                    cleanup(&mut target);
+                    println!("client disconnected: {:?}", disconnect_reason);
+                    return;
+                }
+                Err(e) => {
+                    cleanup(&mut target);
+                    println!("deferred_stop_reason_error: {:?}", e);
+                    return;
+                }
+            }
+        }
+        // gdb thinks everything is stopped already, so the process will
+        // show up as stopped the next time gdb looks.
+        gdb => gdb,
+    };
+    unsafe {
+        HALTED = true;
+        GDB_STATE = Some(XousDebugState {
+            target,
+            server: new_gdb,
+        })
+    };
+}
+
+/// Give the debugger the first look at exception `ex`, which the current
+/// process `pid` just took. Returns `true` if it was caused by the debugger,
+/// in which case the caller should resume whichever thread is now current.
+pub fn handle_exception(pid: PID, ex: &RiscvException) -> bool {
+    let target = match unsafe { GDB_STATE.as_mut() } {
+        Some(state) => &mut state.target,
+        None => return false,
+    };
+    let tid = crate::arch::process::current_tid();
+
+    match *ex {
+        RiscvException::Breakpoint(epc) => {
+            let step = match target.step {
+                Some(step) if step.pid == pid && step.hits(epc) => step,
+                // Any other `ebreak` is one of gdb's breakpoints
+                _ => {
+                    report_stop(pid, ThreadStopReason::SwBreak(gdb_tid(pid, tid)));
+                    return true;
+                }
+            };
+            target.step = None;
+            step.remove_breakpoints();
+            target.protect_watched_pages(pid);
+
+            let reason = if let Some(addr) = step.watch_hit {
+                Some(ThreadStopReason::Watch {
+                    tid: gdb_tid(pid, tid),
+                    kind: WatchKind::Write,
+                    addr: addr as u32,
+                })
+            } else if !step.requested {
+                None
+            } else if step.tid == tid {
+                Some(ThreadStopReason::DoneStep)
+            } else {
+                // Other threads keep running while one is stepped, and may
+                // get to the breakpoints first.
+                Some(ThreadStopReason::SwBreak(gdb_tid(pid, tid)))
+            };
+            if let Some(reason) = reason {
+                report_stop(pid, reason);
+            }
+            true
+        }
+
+        RiscvException::StorePageFault(_pc, addr) => {
+            let page = addr & !(PAGE_SIZE - 1);
+            let mut watched = target
+                .watchpoints
+                .iter()
+                .flatten()
+                .filter(|w| w.pid == pid && w.page() == page)
+                .peekable();
+            if watched.peek().is_none() {
+                return false;
+            }
+            let watch_hit = watched.find(|w| w.contains(addr)).map(|w| w.addr);
+
+            // Let the write through, then step over it so the page can be
+            // protected again. gdb expects to hear about a watchpoint after
+            // the memory has changed.
+            set_page_writable(page, true).ok();
+            match target.step {
+                Some(ref mut step) if step.pid == pid => {
+                    step.watch_hit = step.watch_hit.or(watch_hit);
+                }
+                // Only one step can be under way at a time. The page will be
+                // protected again when that one finishes.
+                Some(_) => (),
+                None => {
+                    let stepped = ArchProcess::with_current(|process| {
+                        target.begin_step(process, pid, tid, false)
+                    });
+                    match (stepped, target.step.as_mut()) {
+                        (Ok(()), Some(step)) => step.watch_hit = watch_hit,
+                        _ => {
+                            target.protect_watched_pages(pid);
+                            return false;
+                        }
+                    }
+                }
+            }
+            // The faulting instruction runs again, and this time succeeds
+            true
+        }
+
+        _ => false,
+    }
+}
+
 pub fn handle(b: u8) -> bool {
     if let Some(XousDebugState {
         mut target,
@@ -282,7 +838,7 @@ pub fn handle(b: u8) -> bool {
                     gdb
                 }
                 Ok((_, Some(_disconnect_reason))) => {
-                    cleanup();
+                    cleanup(&mut target);
                     match _disconnect_reason {
                         DisconnectReason::Disconnect => println!("GDB Disconnected"),
                         DisconnectReason::TargetExited(_) => println!("Target exited"),
@@ -292,19 +848,21 @@ pub fn handle(b: u8) -> bool {
                     return true;
                 }
                 Err(GdbStubError::TargetError(e)) => {
-                    cleanup();
+                    cleanup(&mut target);
                     println!("Target raised a fatal error: {}", e);
                     return true;
                 }
                 Err(e) => {
-                    cleanup();
+                    cleanup(&mut target);
                     println!("gdbstub internal error: {}", e);
                     return true;
                 }
                 Ok((gdb, None)) => gdb,
             },
 
+            // gdb interrupted the running process
             GdbStubStateMachine::DeferredStopReason(gdb_state) => {
+                target.cancel_step();
                 match gdb_state.deferred_stop_reason(&mut target, ThreadStopReason::DoneStep) {
                     Ok((gdb, None)) => {
                         crate::services::SystemServices::with_mut(|system_services| {
@@ -312,15 +870,16 @@ pub fn handle(b: u8) -> bool {
                                 .suspend_process(target.pid().unwrap())
                                 .unwrap()
                         });
+                        unsafe { HALTED = true };
                         gdb
                     }
                     Ok((_, Some(disconnect_reason))) => {
-                        cleanup();
+                        cleanup(&mut target);
                         println!("client disconnected: {:?}", disconnect_reason);
                         return true;
                     }
                     Err(e) => {
-                        cleanup();
+                        cleanup(&mut target);
                         println!("deferred_stop_reason_error: {:?}", e);
                         return true;
                     }
@@ -346,8 +905,14 @@ pub fn setup() {
     {
         Ok(gdb) => match gdb.run_state_machine() {
             Ok(state) => unsafe {
+                let mut target = XousTarget::new();
+                if let Some(pid) = target.pid.take() {
+                    if let Err(e) = target.attach(pid) {
+                        println!("Unable to stop PID {}: {:?}", pid, e);
+                    }
+                }
                 GDB_STATE = Some(XousDebugState {
-                    target: XousTarget::new(),
+                    target,
                     server: state,
                 });
                 super::DEBUG_OUTPUT = Some(&mut GUART);
@@ -359,7 +924,8 @@ pub fn setup() {
     }
 }
 
-fn cleanup() {
+fn cleanup(target: &mut XousTarget) {
+    target.release();
     unsafe { super::DEBUG_OUTPUT = Some(&mut super::UART) };
 }
 
@@ -117,8 +117,14 @@ impl Uart {
     }
 }
 
+#[cfg(feature = "gdbserver")]
+pub mod gdb_monitor;
+
 #[cfg(all(feature = "gdbserver", baremetal))]
-mod gdb_server;
+pub mod gdb_server;
+
+#[cfg(all(feature = "gdbserver", not(baremetal)))]
+pub mod gdb_hosted;
 
 #[cfg(all(feature = "gdbserver", baremetal))]
 impl gdbstub::Connection for Uart {
@@ -272,6 +278,28 @@ fn process_irq_character(b: u8) {
             });
             println!("{} k total", total_bytes / 1024);
         }
+        #[cfg(feature = "ipc-trace")]
+        b't' => {
+            // Process names come first so that `decode-ipc-trace` can
+            // name the servers in each event.
+            println!("IPC trace:");
+            crate::services::SystemServices::with(|system_services| {
+                for process in &system_services.processes {
+                    if !process.free() {
+                        println!(
+                            "process {} {}",
+                            process.pid,
+                            system_services.process_name(process.pid).unwrap_or("")
+                        );
+                    }
+                }
+            });
+            crate::trace::IpcTrace::with(|trace| {
+                for event in trace.iter() {
+                    println!("event {}", event);
+                }
+            });
+        }
         #[cfg(all(feature = "gdbserver", baremetal))]
         b'g' => {
             println!("Starting GDB server -- attach your debugger now");
@@ -288,6 +316,8 @@ fn process_irq_character(b: u8) {
             println!(" p  | print all processes");
             println!(" P  | print all processes and threads");
             println!(" r  | report RAM usage of all processes");
+            #[cfg(feature = "ipc-trace")]
+            println!(" t  | dump the IPC trace");
         }
         _ => {}
     }
@@ -58,6 +58,19 @@ where
     }
 }
 
+/// Return a bitmask of the interrupts claimed by `pid`.
+pub fn interrupts_claimed_by(pid: PID) -> usize {
+    let mut mask = 0;
+    unsafe {
+        for (idx, handler) in IRQ_HANDLERS.iter().enumerate() {
+            if matches!(handler, Some((owner, _, _)) if *owner == pid) {
+                mask |= 1 << idx;
+            }
+        }
+    }
+    mask
+}
+
 pub fn interrupt_claim(
     irq: usize,
     pid: PID,
@@ -67,7 +80,7 @@ pub fn interrupt_claim(
     // Unsafe is required since we're accessing a static mut array.
     // However, we disable interrupts to prevent contention on this array.
     unsafe {
-        if irq > IRQ_HANDLERS.len() {
+        if irq >= IRQ_HANDLERS.len() {
             Err(xous_kernel::Error::InterruptNotFound)
         } else if IRQ_HANDLERS[irq].is_some() {
             Err(xous_kernel::Error::InterruptInUse)
@@ -21,9 +21,12 @@ mod args;
 mod irq;
 mod macros;
 mod mem;
+mod scheduler;
 mod server;
 mod services;
 mod syscall;
+#[cfg(feature = "ipc-trace")]
+mod trace;
 
 use services::SystemServices;
 use xous_kernel::*;
@@ -105,41 +108,23 @@ pub unsafe extern "C" fn init(arg_offset: *const u32, init_offset: *const u32, r
 }
 
 /// Loop through the SystemServices list to determine the next PID to be run.
-/// If no process is ready, return `None`.
+/// The process with the highest-priority runnable thread wins, and processes
+/// of equal priority take turns. If no process is ready, return `None`.
 fn next_pid_to_run(last_pid: Option<PID>) -> Option<PID> {
-    // PIDs are 1-indexed but arrays are 0-indexed.  By not subtracting
-    // 1 from the PID when we use it as an array index, we automatically
-    // pick the next process in the list.
-    let current_pid = last_pid.unwrap_or(unsafe { PID::new_unchecked(1) }).get() as usize;
+    // PIDs are 1-indexed but arrays are 0-indexed, so the index of the last
+    // PID is one less than its value.
+    let last_idx = last_pid.unwrap_or(unsafe { PID::new_unchecked(1) }).get() as usize - 1;
 
     SystemServices::with(|system_services| {
-        for test_idx in current_pid..system_services.processes.len() {
-            if system_services.processes[test_idx].ppid.get() == 1 {
-                // print!("PID {} is owned by PID1... ", test_idx + 1);
-                if system_services.processes[test_idx].runnable() {
-                    // println!(" and is runnable");
-                    return match pid_from_usize(test_idx + 1) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    };
-                }
-                // println!(" and is NOT RUNNABLE");
+        let processes = &system_services.processes;
+        scheduler::pick_next(processes.len(), last_idx, |idx| {
+            if processes[idx].ppid.get() == 1 {
+                processes[idx].runnable_priority()
+            } else {
+                None
             }
-        }
-        for test_idx in 0..current_pid {
-            if system_services.processes[test_idx].ppid.get() == 1 {
-                // print!("PID {} is owned by PID1... ", test_idx + 1);
-                if system_services.processes[test_idx].runnable() {
-                    // println!(" and is runnable");
-                    return match pid_from_usize(test_idx + 1) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    };
-                }
-                // println!(" and is NOT RUNNABLE");
-            }
-        }
-        None
+        })
+        .and_then(|idx| pid_from_usize(idx + 1).ok())
     })
 }
 
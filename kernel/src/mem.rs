@@ -4,10 +4,13 @@
 use core::fmt;
 
 pub use crate::arch::mem::{MemoryMapping, PAGE_SIZE};
-use crate::arch::process::Process;
+use crate::arch::process::{Process, MAX_PROCESS_COUNT};
 
 use xous_kernel::{MemoryFlags, MemoryRange, PID};
 
+/// The most shared regions that may exist at once
+pub const MAX_SHARED_REGIONS: usize = 16;
+
 #[derive(Debug)]
 enum ClaimReleaseMove {
     Claim,
@@ -40,6 +43,178 @@ impl fmt::Display for MemoryRangeExtra {
     }
 }
 
+/// How many pages of RAM a process owns, and how many it is allowed to own.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PageQuota {
+    pub used: usize,
+
+    /// The most pages the process may own, or 0 if there is no limit
+    pub limit: usize,
+}
+
+impl PageQuota {
+    pub const fn unlimited() -> Self {
+        PageQuota { used: 0, limit: 0 }
+    }
+
+    /// Whether the process may take on `pages` more pages.
+    pub fn allows(&self, pages: usize) -> bool {
+        self.limit == 0 || self.used.saturating_add(pages) <= self.limit
+    }
+}
+
+/// Physically-contiguous RAM that several processes have mapped. Its pages
+/// are owned by one of those processes, and so count against that process's
+/// page limit. When the owner lets go, the pages pass to another holder.
+/// A process only comes to hold a region by mapping it itself, once a holder
+/// has offered it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SharedRegion {
+    /// The name the region was created with
+    pub name: usize,
+
+    /// Physical address of the first page
+    pub phys: usize,
+
+    /// Length of the region, in bytes
+    pub size: usize,
+
+    /// What each process may do with the region, indexed by PID - 1.
+    /// Processes that don't hold the region have no flags at all.
+    access: [MemoryFlags; MAX_PROCESS_COUNT],
+
+    /// The most each process has been offered, indexed by PID - 1
+    offers: [MemoryFlags; MAX_PROCESS_COUNT],
+}
+
+impl SharedRegion {
+    #[allow(dead_code)]
+    pub fn new(name: usize, phys: usize, size: usize, creator: PID, flags: MemoryFlags) -> Self {
+        let mut access = [MemoryFlags::empty(); MAX_PROCESS_COUNT];
+        if let Some(creator_access) = access.get_mut(creator.get() as usize - 1) {
+            *creator_access = flags;
+        }
+        SharedRegion {
+            name,
+            phys,
+            size,
+            access,
+            offers: [MemoryFlags::empty(); MAX_PROCESS_COUNT],
+        }
+    }
+
+    /// The flags the specified process has the region mapped with, which are
+    /// empty if it doesn't hold the region.
+    pub fn access(&self, pid: PID) -> MemoryFlags {
+        self.access
+            .get(pid.get() as usize - 1)
+            .copied()
+            .unwrap_or_else(MemoryFlags::empty)
+    }
+
+    /// The most the specified process may map the region with, which is
+    /// empty if it hasn't been offered the region.
+    pub fn offered(&self, pid: PID) -> MemoryFlags {
+        self.offers
+            .get(pid.get() as usize - 1)
+            .copied()
+            .unwrap_or_else(MemoryFlags::empty)
+    }
+
+    /// Every process that holds the region.
+    pub fn holders(&self) -> impl Iterator<Item = PID> + '_ {
+        self.access
+            .iter()
+            .zip(1..)
+            .filter(|(flags, _)| !flags.is_empty())
+            .filter_map(|(_, pid)| PID::new(pid))
+    }
+
+    pub fn contains(&self, phys: usize) -> bool {
+        phys >= self.phys && phys < self.phys + self.size
+    }
+
+    /// Let `to` map the region with at most `flags`, on behalf of `from`.
+    /// This replaces any earlier offer to `to`.
+    ///
+    /// # Errors
+    ///
+    /// * BadAddress - `from` doesn't hold the region
+    /// * InvalidSyscall - `flags` don't allow reading
+    /// * AccessDenied - `flags` allow more than `from` has
+    /// * MemoryInUse - `to` already holds the region
+    #[allow(dead_code)]
+    pub fn offer(
+        &mut self,
+        from: PID,
+        to: PID,
+        flags: MemoryFlags,
+    ) -> Result<(), xous_kernel::Error> {
+        let allowed = self.access(from);
+        if allowed.is_empty() {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+        if !flags.contains(MemoryFlags::R) {
+            return Err(xous_kernel::Error::InvalidSyscall);
+        }
+        if !allowed.contains(flags) {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        if !self.access(to).is_empty() {
+            return Err(xous_kernel::Error::MemoryInUse);
+        }
+        let offer = self
+            .offers
+            .get_mut(to.get() as usize - 1)
+            .ok_or(xous_kernel::Error::ProcessNotFound)?;
+        *offer = flags;
+        Ok(())
+    }
+
+    /// Take up the offer made to `pid`, so that it holds the region with
+    /// `flags`.
+    ///
+    /// # Errors
+    ///
+    /// * BadAddress - `pid` hasn't been offered the region
+    /// * InvalidSyscall - `flags` don't allow reading
+    /// * AccessDenied - `flags` allow more than the offer
+    /// * MemoryInUse - `pid` already holds the region
+    #[allow(dead_code)]
+    pub fn accept(&mut self, pid: PID, flags: MemoryFlags) -> Result<(), xous_kernel::Error> {
+        let offered = self.offered(pid);
+        if offered.is_empty() {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+        if !flags.contains(MemoryFlags::R) {
+            return Err(xous_kernel::Error::InvalidSyscall);
+        }
+        if !offered.contains(flags) {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        if !self.access(pid).is_empty() {
+            return Err(xous_kernel::Error::MemoryInUse);
+        }
+        let idx = pid.get() as usize - 1;
+        self.offers[idx] = MemoryFlags::empty();
+        self.access[idx] = flags;
+        Ok(())
+    }
+
+    /// Drop the specified process's hold on the region, along with anything
+    /// it has been offered. Returns `true` if no process holds it any more.
+    pub fn release(&mut self, pid: PID) -> bool {
+        let idx = pid.get() as usize - 1;
+        if let Some(access) = self.access.get_mut(idx) {
+            *access = MemoryFlags::empty();
+        }
+        if let Some(offer) = self.offers.get_mut(idx) {
+            *offer = MemoryFlags::empty();
+        }
+        self.holders().next().is_none()
+    }
+}
+
 pub struct MemoryManager {
     ram_start: usize,
     ram_size: usize,
@@ -47,6 +222,12 @@ pub struct MemoryManager {
     ram_name: u32,
     #[allow(dead_code)]
     last_ram_page: usize,
+
+    /// RAM quota of each process, indexed by PID - 1
+    quotas: [PageQuota; MAX_PROCESS_COUNT],
+
+    /// Regions of RAM that are mapped into more than one process
+    shared_regions: [Option<SharedRegion>; MAX_SHARED_REGIONS],
 }
 
 impl Default for MemoryManager {
@@ -77,6 +258,8 @@ impl MemoryManager {
             ram_size: 0,
             ram_name: 0,
             last_ram_page: 0,
+            quotas: [PageQuota::unlimited(); MAX_PROCESS_COUNT],
+            shared_regions: [None; MAX_SHARED_REGIONS],
         }
     }
 
@@ -148,7 +331,13 @@ impl MemoryManager {
 
         let mut mem_size = self.ram_size / PAGE_SIZE;
         for tag in args_iter {
-            if tag.name == u32::from_le_bytes(*b"MREx") {
+            if tag.name == u32::from_le_bytes(*b"PLim") {
+                for entry in tag.data.chunks_exact(2) {
+                    if let Some(pid) = PID::new(entry[0] as u8) {
+                        self.set_page_limit(pid, entry[1] as usize);
+                    }
+                }
+            } else if tag.name == u32::from_le_bytes(*b"MREx") {
                 unsafe {
                     assert!(
                         EXTRA_REGIONS.is_empty(),
@@ -173,6 +362,12 @@ impl MemoryManager {
         unsafe {
             MEMORY_ALLOCATIONS = slice::from_raw_parts_mut(base as *mut Option<PID>, mem_size)
         };
+
+        // The loader has already handed out pages to the initial processes,
+        // so start counting from there.
+        for owner in unsafe { &MEMORY_ALLOCATIONS[0..self.ram_size / PAGE_SIZE] } {
+            self.account_page(None, *owner);
+        }
         Ok(())
     }
 
@@ -192,6 +387,58 @@ impl MemoryManager {
         owned_bytes
     }
 
+    /// Return how many pages of RAM the specified process owns and may own.
+    pub fn page_quota(&self, pid: PID) -> PageQuota {
+        self.quotas
+            .get(pid.get() as usize - 1)
+            .copied()
+            .unwrap_or_else(PageQuota::unlimited)
+    }
+
+    /// Limit the specified process to owning `limit` pages of RAM. A limit
+    /// of 0 removes the limit.
+    pub fn set_page_limit(&mut self, pid: PID, limit: usize) {
+        if let Some(quota) = self.quotas.get_mut(pid.get() as usize - 1) {
+            quota.limit = limit;
+        }
+    }
+
+    /// Ensure the specified process may take on another `size` bytes of RAM.
+    ///
+    /// # Errors
+    ///
+    /// * OutOfMemory - The process would exceed its page limit
+    pub fn check_page_quota(&self, pid: PID, size: usize) -> Result<(), xous_kernel::Error> {
+        if self
+            .page_quota(pid)
+            .allows((size + PAGE_SIZE - 1) / PAGE_SIZE)
+        {
+            Ok(())
+        } else {
+            Err(xous_kernel::Error::OutOfMemory)
+        }
+    }
+
+    /// Note that a page of RAM changed hands from `from` to `to`.
+    #[cfg(baremetal)]
+    fn account_page(&mut self, from: Option<PID>, to: Option<PID>) {
+        if let Some(quota) = from.and_then(|pid| self.quotas.get_mut(pid.get() as usize - 1)) {
+            quota.used = quota.used.saturating_sub(1);
+        }
+        if let Some(quota) = to.and_then(|pid| self.quotas.get_mut(pid.get() as usize - 1)) {
+            quota.used += 1;
+        }
+    }
+
+    /// Return the number of RAM bytes that are owned by any process,
+    /// along with the total number of RAM bytes.
+    #[cfg(baremetal)]
+    pub fn ram_usage(&self) -> (usize, usize) {
+        let ram_pages = unsafe { &MEMORY_ALLOCATIONS[0..self.ram_size / PAGE_SIZE] };
+        let used_pages = ram_pages.iter().filter(|owner| owner.is_some()).count();
+        (used_pages * PAGE_SIZE, self.ram_size)
+    }
+
     #[cfg(all(baremetal, feature = "print-debug"))]
     pub fn print_ownership(&self) {
         println!("Ownership ({} bytes in all):", unsafe {
@@ -250,6 +497,10 @@ impl MemoryManager {
     /// This function CANNOT zero the page, as it hasn't been mapped yet.
     #[cfg(baremetal)]
     pub fn alloc_page(&mut self, pid: PID) -> Result<usize, xous_kernel::Error> {
+        if !self.page_quota(pid).allows(1) {
+            return Err(xous_kernel::Error::OutOfMemory);
+        }
+
         // Go through all RAM pages looking for a free page.
         // println!("Allocating page for PID {}", pid);
         unsafe {
@@ -266,6 +517,7 @@ impl MemoryManager {
                 // );
                 if allocation.is_none() {
                     *allocation = Some(pid);
+                    self.account_page(None, Some(pid));
                     self.last_ram_page = index + 1;
                     // if self.last_ram_page >= end_point {
                     //     self.last_ram_page = 0;
@@ -303,6 +555,38 @@ impl MemoryManager {
         Err(xous_kernel::Error::OutOfMemory)
     }
 
+    /// Allocate `count` physically-contiguous pages to the given process,
+    /// returning the address of the first one. Like `alloc_page()`, this
+    /// does not zero the pages.
+    #[cfg(baremetal)]
+    fn alloc_contiguous_pages(
+        &mut self,
+        pid: PID,
+        count: usize,
+    ) -> Result<usize, xous_kernel::Error> {
+        if !self.page_quota(pid).allows(count) {
+            return Err(xous_kernel::Error::OutOfMemory);
+        }
+
+        let end_point = self.ram_size / PAGE_SIZE;
+        let mut start = 0;
+        while start + count <= end_point {
+            let run = unsafe { &mut MEMORY_ALLOCATIONS[start..start + count] };
+            // Skip past the last page in the way, since no run that includes
+            // it can be free.
+            if let Some(used) = run.iter().rposition(|owner| owner.is_some()) {
+                start += used + 1;
+                continue;
+            }
+            for owner in run.iter_mut() {
+                *owner = Some(pid);
+                self.account_page(None, Some(pid));
+            }
+            return Ok(start * PAGE_SIZE + self.ram_start);
+        }
+        Err(xous_kernel::Error::OutOfMemory)
+    }
+
     /// Find a virtual address in the current process that is big enough
     /// to fit `size` bytes.
     pub fn find_virtual_address(
@@ -462,6 +746,292 @@ impl MemoryManager {
         Ok(virt)
     }
 
+    /// Create a shared region of `size` bytes called `name`, and map it into
+    /// the current process, `pid`, with `flags`. The region is zeroed.
+    ///
+    /// # Errors
+    ///
+    /// * BadAlignment - `size` isn't a multiple of the page size
+    /// * InvalidSyscall - `flags` don't allow reading
+    /// * MemoryInUse - A shared region called `name` already exists
+    /// * OutOfMemory - There is no room for the region, or no free slot
+    #[cfg(baremetal)]
+    pub fn create_shared_region(
+        &mut self,
+        pid: PID,
+        name: usize,
+        size: usize,
+        flags: MemoryFlags,
+    ) -> Result<MemoryRange, xous_kernel::Error> {
+        if size & (PAGE_SIZE - 1) != 0 {
+            return Err(xous_kernel::Error::BadAlignment);
+        }
+        if !flags.contains(MemoryFlags::R) {
+            return Err(xous_kernel::Error::InvalidSyscall);
+        }
+        let slot = self.shared_region_slot(name)?;
+
+        let phys = self.alloc_contiguous_pages(pid, size / PAGE_SIZE)?;
+        let range = match self.map_shared_pages(pid, phys, size, flags, true) {
+            Ok(range) => range,
+            Err(e) => {
+                for page in (phys..phys + size).step_by(PAGE_SIZE) {
+                    self.release_page(page as *mut usize, pid).ok();
+                }
+                return Err(e);
+            }
+        };
+
+        self.shared_regions[slot] = Some(SharedRegion::new(name, phys, size, pid, flags));
+        Ok(range)
+    }
+
+    /// Record a region that `pid` has mapped with `flags`, the way
+    /// `create_shared_region()` does once the pages are in place.
+    #[cfg(test)]
+    pub fn add_shared_region(
+        &mut self,
+        pid: PID,
+        name: usize,
+        phys: usize,
+        size: usize,
+        flags: MemoryFlags,
+    ) -> Result<(), xous_kernel::Error> {
+        let slot = self.shared_region_slot(name)?;
+        self.shared_regions[slot] = Some(SharedRegion::new(name, phys, size, pid, flags));
+        Ok(())
+    }
+
+    /// The shared region called `name`, if there is one
+    #[cfg(test)]
+    pub fn shared_region(&self, name: usize) -> Option<&SharedRegion> {
+        self.shared_region_index(name)
+            .and_then(|slot| self.shared_regions[slot].as_ref())
+    }
+
+    /// A free slot for a new shared region called `name`.
+    ///
+    /// # Errors
+    ///
+    /// * MemoryInUse - A shared region called `name` already exists
+    /// * OutOfMemory - There are already `MAX_SHARED_REGIONS` regions
+    #[cfg(any(baremetal, test))]
+    fn shared_region_slot(&self, name: usize) -> Result<usize, xous_kernel::Error> {
+        if self.shared_region_index(name).is_some() {
+            return Err(xous_kernel::Error::MemoryInUse);
+        }
+        self.shared_regions
+            .iter()
+            .position(|region| region.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)
+    }
+
+    /// Let `to` map the shared region called `name` with at most `flags`,
+    /// on behalf of `from`.
+    ///
+    /// # Errors
+    ///
+    /// * BadAddress - `from` doesn't hold a shared region called `name`
+    /// * InvalidSyscall - `flags` don't allow reading
+    /// * AccessDenied - `flags` allow more than `from` has
+    /// * MemoryInUse - `to` already holds the region
+    #[cfg(any(baremetal, test))]
+    pub fn offer_shared_region(
+        &mut self,
+        from: PID,
+        to: PID,
+        name: usize,
+        flags: MemoryFlags,
+    ) -> Result<(), xous_kernel::Error> {
+        let slot = self
+            .shared_region_index(name)
+            .ok_or(xous_kernel::Error::BadAddress)?;
+        self.shared_regions[slot]
+            .as_mut()
+            .unwrap()
+            .offer(from, to, flags)
+    }
+
+    /// Map the shared region called `name`, which has been offered to the
+    /// current process, `pid`, with `flags`.
+    ///
+    /// # Errors
+    ///
+    /// * BadAddress - `pid` hasn't been offered a shared region called `name`
+    /// * InvalidSyscall - `flags` don't allow reading
+    /// * AccessDenied - `flags` allow more than the offer
+    /// * MemoryInUse - `pid` already holds the region
+    /// * OutOfMemory - There is no room for the region in `pid`
+    #[cfg(baremetal)]
+    pub fn map_shared_region(
+        &mut self,
+        pid: PID,
+        name: usize,
+        flags: MemoryFlags,
+    ) -> Result<MemoryRange, xous_kernel::Error> {
+        let slot = self
+            .shared_region_index(name)
+            .ok_or(xous_kernel::Error::BadAddress)?;
+        let mut region = self.shared_regions[slot].unwrap();
+        region.accept(pid, flags)?;
+
+        let range = self.map_shared_pages(pid, region.phys, region.size, flags, false)?;
+        self.shared_regions[slot] = Some(region);
+        Ok(range)
+    }
+
+    /// Map the pages of a shared region into the current process, `pid`,
+    /// wherever there is room for them, zeroing them first if `zero` is set.
+    /// Either every page ends up mapped and handed to the process, or none
+    /// of them are.
+    #[cfg(baremetal)]
+    fn map_shared_pages(
+        &mut self,
+        pid: PID,
+        phys: usize,
+        size: usize,
+        flags: MemoryFlags,
+        zero: bool,
+    ) -> Result<MemoryRange, xous_kernel::Error> {
+        let virt = self.find_virtual_address(
+            core::ptr::null_mut(),
+            size,
+            xous_kernel::MemoryType::Default,
+        )? as usize;
+        if let Err(e) = self.map_shared_pages_at(pid, phys, virt, size, flags, zero) {
+            for offset in (0..size).step_by(PAGE_SIZE) {
+                crate::arch::mem::unmap_page_inner(self, virt + offset).ok();
+            }
+            return Err(e);
+        }
+        unsafe { MemoryRange::new(virt, size) }
+    }
+
+    #[cfg(baremetal)]
+    fn map_shared_pages_at(
+        &mut self,
+        pid: PID,
+        phys: usize,
+        virt: usize,
+        size: usize,
+        flags: MemoryFlags,
+        zero: bool,
+    ) -> Result<(), xous_kernel::Error> {
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            crate::arch::mem::map_page_inner(
+                self,
+                pid,
+                phys + offset,
+                virt + offset,
+                flags,
+                false,
+            )?;
+        }
+        if zero {
+            unsafe { (virt as *mut u8).write_bytes(0, size) };
+        }
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            crate::arch::mem::hand_page_to_user((virt + offset) as *mut u8)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the specified process's hold on the shared region called `name`,
+    /// without unmapping it. If the process owned the pages, they pass to
+    /// another holder, or are freed once there are no holders left.
+    ///
+    /// # Errors
+    ///
+    /// * BadAddress - The process doesn't hold a shared region called `name`
+    pub fn release_shared_region(
+        &mut self,
+        pid: PID,
+        name: usize,
+    ) -> Result<(), xous_kernel::Error> {
+        let slot = self
+            .shared_region_index(name)
+            .ok_or(xous_kernel::Error::BadAddress)?;
+        let mut region = self.shared_regions[slot].unwrap();
+        if region.access(pid).is_empty() {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+        let unused = region.release(pid);
+        self.shared_regions[slot] = if unused { None } else { Some(region) };
+
+        #[cfg(baremetal)]
+        {
+            let heir = region.holders().next();
+            for page in (region.phys..region.phys + region.size).step_by(PAGE_SIZE) {
+                let owner = unsafe { &mut MEMORY_ALLOCATIONS[(page - self.ram_start) / PAGE_SIZE] };
+                if *owner == Some(pid) {
+                    *owner = heir;
+                    self.account_page(Some(pid), heir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// If `virt..virt + size` in the current process covers any of a shared
+    /// region, return the name of the region, which it must cover exactly.
+    ///
+    /// # Errors
+    ///
+    /// * BadAddress - The range runs past the end of the address space
+    /// * ShareViolation - The range covers only part of a shared region
+    pub fn shared_region_in(
+        &self,
+        virt: usize,
+        size: usize,
+    ) -> Result<Option<usize>, xous_kernel::Error> {
+        let end = virt
+            .checked_add(size)
+            .ok_or(xous_kernel::Error::BadAddress)?;
+        for addr in (virt..end).step_by(PAGE_SIZE) {
+            let phys = match crate::arch::mem::virt_to_phys(addr) {
+                Ok(phys) => phys,
+                Err(_) => continue,
+            };
+            if let Some(region) = self.shared_region_at(phys) {
+                return if addr == virt && phys == region.phys && size == region.size {
+                    Ok(Some(region.name))
+                } else {
+                    Err(xous_kernel::Error::ShareViolation)
+                };
+            }
+        }
+        Ok(None)
+    }
+
+    /// Ensure `virt..virt + size` in the current process may be moved to
+    /// another process. Shared regions are never moved, as their other
+    /// holders would be left mapping pages the receiver then owns.
+    ///
+    /// # Errors
+    ///
+    /// * BadAddress - The range runs past the end of the address space
+    /// * ShareViolation - The range covers any of a shared region
+    pub fn check_movable(&self, virt: usize, size: usize) -> Result<(), xous_kernel::Error> {
+        match self.shared_region_in(virt, size)? {
+            None => Ok(()),
+            Some(_) => Err(xous_kernel::Error::ShareViolation),
+        }
+    }
+
+    /// The shared region that the given physical address is part of
+    fn shared_region_at(&self, phys: usize) -> Option<&SharedRegion> {
+        self.shared_regions
+            .iter()
+            .flatten()
+            .find(|region| region.contains(phys))
+    }
+
+    fn shared_region_index(&self, name: usize) -> Option<usize> {
+        self.shared_regions
+            .iter()
+            .position(|region| matches!(region, Some(region) if region.name == name))
+    }
+
     pub fn is_main_memory(&self, phys: *mut u8) -> bool {
         (phys as usize) >= self.ram_start && (phys as usize) < self.ram_start + self.ram_size
     }
@@ -472,6 +1042,7 @@ impl MemoryManager {
     /// # Errors
     ///
     /// * MemoryInUse - The specified page is already mapped
+    /// * BadAddress - The physical range runs past the end of the address space
     pub fn map_range(
         &mut self,
         phys_ptr: *mut u8,
@@ -490,7 +1061,10 @@ impl MemoryManager {
         }
 
         // 1. Attempt to claim all physical pages in the range
-        for claim_phys in (phys..(phys + size)).step_by(PAGE_SIZE) {
+        let phys_end = phys
+            .checked_add(size)
+            .ok_or(xous_kernel::Error::BadAddress)?;
+        for claim_phys in (phys..phys_end).step_by(PAGE_SIZE) {
             if let Err(err) = self.claim_page(claim_phys as *mut usize, pid) {
                 // If we were unable to claim one or more pages, release everything and return
                 for rel_phys in (phys..claim_phys).step_by(PAGE_SIZE) {
@@ -532,9 +1106,12 @@ impl MemoryManager {
         let pid = crate::arch::process::current_pid();
 
         // If the virtual address has an assigned physical address, release that
-        // address from this process.
+        // address from this process. Pages of a shared region are released
+        // along with the region instead.
         if let Ok(phys) = crate::arch::mem::virt_to_phys(virt as usize) {
-            self.release_page(phys as *mut usize, pid).ok();
+            if self.shared_region_at(phys).is_none() {
+                self.release_page(phys as *mut usize, pid).ok();
+            }
         };
 
         // Free the virtual address.
@@ -553,6 +1130,9 @@ impl MemoryManager {
         dest_addr: *mut u8,
     ) -> Result<(), xous_kernel::Error> {
         let phys_addr = crate::arch::mem::virt_to_phys(src_addr as usize)?;
+        if self.shared_region_at(phys_addr).is_some() {
+            return Err(xous_kernel::Error::ShareViolation);
+        }
         crate::arch::mem::move_page_inner(
             self,
             src_mapping,
@@ -690,7 +1270,10 @@ impl MemoryManager {
         // Happy path: The address is in main RAM
         if addr >= self.ram_start && addr < self.ram_start + self.ram_size {
             offset += (addr - self.ram_start) / PAGE_SIZE;
-            return unsafe { action_inner(&mut MEMORY_ALLOCATIONS[offset], pid, action) };
+            let previous_owner = unsafe { MEMORY_ALLOCATIONS[offset] };
+            unsafe { action_inner(&mut MEMORY_ALLOCATIONS[offset], pid, action)? };
+            self.account_page(previous_owner, unsafe { MEMORY_ALLOCATIONS[offset] });
+            return Ok(());
         }
 
         offset += self.ram_size / PAGE_SIZE;
@@ -758,12 +1341,27 @@ impl MemoryManager {
     /// memory from the process, it only marks it as free.
     /// This is very unsafe because the memory can immediately be re-allocated
     /// to another process, so only call this as part of destroying a process.
-    pub unsafe fn release_all_memory_for_process(&mut self, _pid: PID) {
+    pub unsafe fn release_all_memory_for_process(&mut self, pid: PID) {
+        // Shared regions go first, so that pages other processes still have
+        // mapped are handed to one of them rather than freed. Offers made to
+        // the process go too, or whatever is given its PID next could take
+        // them up.
+        for slot in 0..MAX_SHARED_REGIONS {
+            if let Some(region) = self.shared_regions[slot].as_mut() {
+                if !region.access(pid).is_empty() {
+                    let name = region.name;
+                    self.release_shared_region(pid, name).ok();
+                } else {
+                    region.release(pid);
+                }
+            }
+        }
+
         #[cfg(baremetal)]
         for (idx, owner) in MEMORY_ALLOCATIONS.iter_mut().enumerate() {
             // If this address has been allocated to this process, consider
             // freeing it or reparenting it.
-            if owner == &mut Some(_pid) {
+            if owner == &mut Some(pid) {
                 let phys_addr = self.allocation_offset_to_address(idx).unwrap();
                 if crate::arch::mem::page_is_lent(phys_addr as *mut u8) {
                     // If the page is lent, reparent it to PID 1 so it will
@@ -773,6 +1371,9 @@ impl MemoryManager {
                     // Mark this page as free, which allows it to be re-allocated.
                     *owner = None;
                 }
+                if idx < self.ram_size / PAGE_SIZE {
+                    self.account_page(Some(pid), *owner);
+                }
             }
         }
     }
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Scheduling policy. These functions only decide *what* should run next,
+//! so that the same choice is made on hardware and in hosted mode. Actually
+//! switching to the chosen thread is left to `SystemServices`.
+
+use xous_kernel::{ThreadPriority, TID};
+
+/// Given `count` slots and a function that returns the priority of each
+/// slot, or `None` if that slot cannot run, return the slot that should run
+/// next. The highest priority always wins, and slots of equal priority are
+/// taken in round-robin order starting with the one after `previous`.
+pub fn pick_next<F>(count: usize, previous: usize, priority_of: F) -> Option<usize>
+where
+    F: Fn(usize) -> Option<ThreadPriority>,
+{
+    let mut best: Option<(ThreadPriority, usize)> = None;
+    for offset in 1..=count {
+        let idx = (previous + offset) % count;
+        if let Some(priority) = priority_of(idx) {
+            if best.map_or(true, |(best_priority, _)| priority > best_priority) {
+                best = Some((priority, idx));
+            }
+        }
+    }
+    best.map(|(_, idx)| idx)
+}
+
+fn is_ready(ready: usize, tid: TID) -> bool {
+    tid < usize::BITS as usize && ready & (1 << tid) != 0
+}
+
+/// The highest priority of all threads whose bit is set in `ready`.
+pub fn highest_priority(ready: usize, priorities: &[ThreadPriority]) -> Option<ThreadPriority> {
+    priorities
+        .iter()
+        .enumerate()
+        .filter(|(tid, _)| is_ready(ready, *tid))
+        .map(|(_, priority)| *priority)
+        .max()
+}
+
+/// Pick the thread to run out of the threads whose bit is set in `ready`,
+/// where `current` is the thread that ran last.
+pub fn next_thread(ready: usize, current: TID, priorities: &[ThreadPriority]) -> Option<TID> {
+    pick_next(priorities.len(), current, |tid| {
+        if is_ready(ready, tid) {
+            Some(priorities[tid])
+        } else {
+            None
+        }
+    })
+}
@@ -102,6 +102,24 @@ pub enum WaitingMessage {
     ForgetMemory(MemoryRange),
 }
 
+/// What a queued message is holding on to, as seen by the kernel's invariant
+/// checks.
+#[cfg(test)]
+pub struct MessageHold {
+    /// The client thread that is blocked until the message is answered
+    pub blocked: Option<(PID, TID)>,
+
+    /// `true` if the server hasn't received the message yet
+    pub queued: bool,
+
+    /// Where the server's copy of memory sent with the message lives
+    pub memory: Option<MemoryRange>,
+
+    /// The process that lent `memory` and expects it back when the message
+    /// is answered
+    pub lender: Option<PID>,
+}
+
 /// Internal representation of a queued message for a server. This should be
 /// exactly 8 words / 32 bytes, yielding 128 queued messages per server
 #[repr(usize)]
@@ -489,6 +507,153 @@ impl Server {
     //     }
     // }
 
+    /// The number of messages waiting in the queue, including ones that a
+    /// server thread has received but not yet responded to.
+    #[cfg(feature = "gdbserver")]
+    pub fn queue_len(&self) -> usize {
+        self.queue
+            .iter()
+            .filter(|entry| **entry != QueuedMessage::Empty)
+            .count()
+    }
+
+    /// Describe the queue to a debugger, one entry per line.
+    #[cfg(feature = "gdbserver")]
+    pub fn write_queue(&self, out: &mut dyn core::fmt::Write) -> core::fmt::Result {
+        writeln!(
+            out,
+            "head {} (gen {}), tail {} (gen {}), ready threads {:#x}",
+            self.queue_head,
+            self.head_generation,
+            self.queue_tail,
+            self.tail_generation,
+            self.ready_threads
+        )?;
+        for (idx, entry) in self.queue.iter().enumerate() {
+            if entry != &QueuedMessage::Empty {
+                writeln!(out, "  [{:3}] {:?}", idx, entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Describe what each message in the queue is holding on to.
+    #[cfg(test)]
+    pub fn holds(&self) -> impl Iterator<Item = MessageHold> + '_ {
+        fn blocked(pid: u16, tid: u8) -> Option<(PID, TID)> {
+            Some((PID::new(pid as u8).unwrap(), tid as TID))
+        }
+        self.queue.iter().filter_map(|entry| {
+            let (blocked, queued, memory, lent) = match *entry {
+                QueuedMessage::Empty => return None,
+                QueuedMessage::ScalarMessage(..) => (None, true, (0, 0), false),
+                QueuedMessage::BlockingScalarMessage(pid, tid, ..) => {
+                    (blocked(pid, tid), true, (0, 0), false)
+                }
+                QueuedMessage::MemoryMessageSend(_, _, _, _, _, server_addr, len, ..) => {
+                    (None, true, (server_addr, len), false)
+                }
+                QueuedMessage::MemoryMessageROLend(pid, tid, _, _, _, server_addr, len, ..)
+                | QueuedMessage::MemoryMessageRWLend(pid, tid, _, _, _, server_addr, len, ..) => {
+                    (blocked(pid, tid), true, (server_addr, len), true)
+                }
+                QueuedMessage::MemoryMessageROLendTerminated(
+                    _,
+                    _,
+                    _,
+                    _,
+                    _,
+                    server_addr,
+                    len,
+                    ..,
+                )
+                | QueuedMessage::MemoryMessageRWLendTerminated(
+                    _,
+                    _,
+                    _,
+                    _,
+                    _,
+                    server_addr,
+                    len,
+                    ..,
+                ) => (None, true, (server_addr, len), false),
+                QueuedMessage::BlockingScalarTerminated(..) => (None, true, (0, 0), false),
+                QueuedMessage::WaitingReturnMemory(pid, tid, _, server_addr, _, len) => {
+                    (blocked(pid, tid), false, (server_addr, len), true)
+                }
+                QueuedMessage::WaitingForget(_, _, _, server_addr, _, len) => {
+                    (None, false, (server_addr, len), false)
+                }
+                QueuedMessage::WaitingReturnScalar(pid, tid, ..) => {
+                    (blocked(pid, tid), false, (0, 0), false)
+                }
+            };
+            Some(MessageHold {
+                blocked,
+                queued,
+                memory: unsafe { MemoryRange::new(memory.0, memory.1) }.ok(),
+                lender: blocked.filter(|_| lent).map(|(pid, _)| pid),
+            })
+        })
+    }
+
+    /// The server is going away, so take every message whose sender is still
+    /// waiting for an answer and pass it to `f`, which must wake the sender.
+    pub fn take_blocked_senders<F>(&mut self, mut f: F)
+    where
+        F: FnMut(WaitingMessage),
+    {
+        for entry in self.queue.iter_mut() {
+            let waiting = match *entry {
+                QueuedMessage::BlockingScalarMessage(pid, tid, ..)
+                | QueuedMessage::WaitingReturnScalar(pid, tid, ..) => {
+                    WaitingMessage::ScalarMessage(PID::new(pid as _).unwrap(), tid as _)
+                }
+                QueuedMessage::MemoryMessageROLend(
+                    pid,
+                    tid,
+                    _,
+                    client_addr,
+                    _,
+                    server_addr,
+                    len,
+                    ..,
+                )
+                | QueuedMessage::MemoryMessageRWLend(
+                    pid,
+                    tid,
+                    _,
+                    client_addr,
+                    _,
+                    server_addr,
+                    len,
+                    ..,
+                )
+                | QueuedMessage::WaitingReturnMemory(pid, tid, _, server_addr, client_addr, len) => {
+                    match (
+                        MemoryAddress::new(server_addr),
+                        MemoryAddress::new(client_addr),
+                        MemorySize::new(len),
+                    ) {
+                        (Some(server_addr), Some(client_addr), Some(len)) => {
+                            WaitingMessage::BorrowedMemory(
+                                PID::new(pid as _).unwrap(),
+                                tid as _,
+                                server_addr,
+                                client_addr,
+                                len,
+                            )
+                        }
+                        _ => WaitingMessage::None,
+                    }
+                }
+                _ => continue,
+            };
+            *entry = QueuedMessage::Empty;
+            f(waiting);
+        }
+    }
+
     /// When a process terminates, there may be memory that is lent to us.
     /// Mark all of that memory to be discarded when it is returned, rather than
     /// giving it back to the previous process space.
@@ -546,6 +711,27 @@ impl Server {
                         );
                     }
                 }
+                // The server already has this memory, and there is no
+                // longer anyone to give it back to.
+                QueuedMessage::WaitingReturnMemory(
+                    msg_pid,
+                    tid,
+                    idx,
+                    server_addr,
+                    client_addr,
+                    len,
+                ) => {
+                    if msg_pid == pid.get() as _ {
+                        *entry = QueuedMessage::WaitingForget(
+                            msg_pid,
+                            tid,
+                            idx,
+                            server_addr,
+                            client_addr,
+                            len,
+                        );
+                    }
+                }
                 // For "Scalar" and "Move" messages, this memory has already
                 // been moved into this process, so memory will be reclaimed
                 // when the process terminates.
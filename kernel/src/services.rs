@@ -10,15 +10,20 @@ use xous_kernel::MemoryRange;
 use core::num::NonZeroU8;
 
 use crate::filled_array;
-use crate::server::Server;
+use crate::server::{Server, WaitingMessage};
 // use core::mem;
 use xous_kernel::{
-    pid_from_usize, Error, MemoryAddress, Message, ProcessInit, ThreadInit, CID, PID, SID, TID,
+    pid_from_usize, Error, MemoryAddress, Message, ProcessInfo, ProcessInit, ProcessStatus,
+    ThreadInit, ThreadPriority, CID, PID, SID, THREAD_PRIORITY_DEFAULT, THREAD_PRIORITY_MAX, TID,
 };
 
 const MAX_SERVER_COUNT: usize = 128;
 
-pub use crate::arch::process::{INITIAL_TID, MAX_PROCESS_COUNT};
+pub use crate::arch::process::{INITIAL_TID, MAX_PROCESS_COUNT, MAX_THREAD};
+
+/// Hosted mode numbers its threads from 1 rather than 0, so its highest thread
+/// ID is one past `MAX_THREAD`. Per-thread tables are sized to fit either.
+const THREAD_SLOTS: usize = MAX_THREAD + 2;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct ExceptionHandler {
@@ -134,6 +139,17 @@ pub struct Process {
 
     /// When an exception is hit, the kernel will switch to this Thread.
     exception_handler: Option<ExceptionHandler>,
+
+    /// Scheduling priority of each thread, indexed by TID
+    thread_priorities: [ThreadPriority; THREAD_SLOTS],
+
+    /// Return values of threads that exited before anyone joined them,
+    /// indexed by TID
+    exit_values: [Option<usize>; THREAD_SLOTS],
+
+    /// Processes from the boot image may inspect other processes. Processes
+    /// that are created later may only inspect themselves.
+    privileged: bool,
 }
 
 impl Default for Process {
@@ -201,12 +217,19 @@ impl Default for ProcessInner {
 }
 
 impl Process {
-    /// This process has at least one context that may be run
-    pub fn runnable(&self) -> bool {
-        matches!(
-            self.state,
-            ProcessState::Setup(_) | ProcessState::Ready(_) | ProcessState::Exception(_)
-        )
+    /// The priority of the most important thread in this process that could
+    /// run right now, or `None` if nothing in this process can run.
+    pub fn runnable_priority(&self) -> Option<ThreadPriority> {
+        match self.state {
+            ProcessState::Setup(_) => Some(self.thread_priorities[INITIAL_TID]),
+            ProcessState::Ready(x) => {
+                crate::scheduler::highest_priority(x, &self.thread_priorities)
+            }
+            // Exception handlers run ahead of everything else so that a
+            // faulting process can't be starved while it recovers.
+            ProcessState::Exception(_) => Some(THREAD_PRIORITY_MAX),
+            _ => None,
+        }
     }
 
     /// This process slot is unallocated and may be turn into a process
@@ -251,6 +274,9 @@ std::thread_local!(static SYSTEM_SERVICES: core::cell::RefCell<SystemServices> =
         current_thread: 0_usize,
         previous_thread: INITIAL_TID as TID,
         exception_handler: None,
+        thread_priorities: [THREAD_PRIORITY_DEFAULT; THREAD_SLOTS],
+        exit_values: [None; THREAD_SLOTS],
+        privileged: false,
     }; MAX_PROCESS_COUNT],
     // Note we can't use MAX_SERVER_COUNT here because of how Rust's
     // macro tokenization works
@@ -267,6 +293,9 @@ static mut SYSTEM_SERVICES: SystemServices = SystemServices {
         current_thread: 0_usize,
         previous_thread: INITIAL_TID as TID,
         exception_handler: None,
+        thread_priorities: [THREAD_PRIORITY_DEFAULT; THREAD_SLOTS],
+        exit_values: [None; THREAD_SLOTS],
+        privileged: false,
     }; MAX_PROCESS_COUNT],
     // Note we can't use MAX_SERVER_COUNT here because of how Rust's
     // macro tokenization works
@@ -353,6 +382,7 @@ impl SystemServices {
                 process.ppid = PID::new_unchecked(1);
                 process.pid = PID::new(pid as _).unwrap();
             };
+            process.privileged = true;
             // let old_state = process.state;
             if pid == 1 {
                 process.state = ProcessState::Running(0);
@@ -385,17 +415,34 @@ impl SystemServices {
     /// Add a new entry to the process table. This results in a new address space
     /// and a new PID, though the process is in the state `Setup()`.
     pub fn create_process(&mut self, init_process: ProcessInit) -> Result<PID, xous_kernel::Error> {
+        self.check_priority(crate::arch::process::current_pid(), init_process.priority)?;
         for (idx, mut entry) in self.processes.iter_mut().enumerate() {
             if entry.state != ProcessState::Free {
                 continue;
             }
             let new_pid = pid_from_usize(idx + 1)?;
-            arch::process::Process::create(new_pid, init_process);
+            let startup = arch::process::Process::create(new_pid, init_process)?;
             let ppid = crate::arch::process::current_pid();
             // println!("Creating new process for PID {} with PPID {}", new_pid, ppid);
             entry.state = ProcessState::Allocated;
             entry.ppid = ppid;
             entry.pid = new_pid;
+            // In hosted mode the boot processes are created by PID 1, which
+            // is the closest thing there is to a boot image.
+            entry.privileged = ppid.get() == 1 && startup.is_none();
+            if let Some((mapping, thread_init)) = startup {
+                // The process already has a program to run, so just like the
+                // processes from the boot image it's up to the kernel to
+                // schedule it.
+                entry.mapping = mapping;
+                entry.ppid = PID::new(1).unwrap();
+                entry.state = ProcessState::Setup(thread_init);
+            }
+            entry.thread_priorities = [init_process.priority; THREAD_SLOTS];
+            entry.exit_values = [None; THREAD_SLOTS];
+            crate::mem::MemoryManager::with_mut(|mm| {
+                mm.set_page_limit(new_pid, init_process.page_limit)
+            });
             return Ok(new_pid);
         }
         Err(xous_kernel::Error::ProcessNotFound)
@@ -404,6 +451,9 @@ impl SystemServices {
     pub fn get_process(&self, pid: PID) -> Result<&Process, xous_kernel::Error> {
         // PID0 doesn't exist -- process IDs are offset by 1.
         let pid_idx = pid.get() as usize - 1;
+        if pid_idx >= self.processes.len() {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
         if cfg!(baremetal) && self.processes[pid_idx].mapping.get_pid() != pid {
             println!(
                 "Process doesn't match ({} vs {})",
@@ -418,6 +468,9 @@ impl SystemServices {
     pub fn get_process_mut(&mut self, pid: PID) -> Result<&mut Process, xous_kernel::Error> {
         // PID0 doesn't exist -- process IDs are offset by 1.
         let pid_idx = pid.get() as usize - 1;
+        if pid_idx >= self.processes.len() {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
 
         // if self.processes[pid_idx].mapping.get_pid() != pid {
         //     println!(
@@ -719,17 +772,12 @@ impl SystemServices {
             }
             ProcessState::Ready(x) => {
                 let new_thread = match tid {
-                    None => {
-                        let mut new_context = 0;
-
-                        while x & (1 << new_context) == 0 {
-                            new_context += 1;
-                            if new_context > arch::process::MAX_THREAD {
-                                new_context = 0;
-                            }
-                        }
-                        new_context
-                    }
+                    None => crate::scheduler::next_thread(
+                        x,
+                        process.current_thread,
+                        &process.thread_priorities,
+                    )
+                    .expect("Ready process had no ready threads"),
                     Some(ctx) => {
                         // Ensure the specified context is ready to run
                         if x & (1 << ctx) == 0 {
@@ -760,17 +808,12 @@ impl SystemServices {
                 let mut p = ArchProcess::current();
                 // let current_thread = p.current_thread();
                 let new_thread = match tid {
-                    None => {
-                        let mut new_thread = 0;
-
-                        while ready_threads & (1 << new_thread) == 0 {
-                            new_thread += 1;
-                            if new_thread > arch::process::MAX_THREAD {
-                                new_thread = 0;
-                            }
-                        }
-                        new_thread
-                    }
+                    None => crate::scheduler::next_thread(
+                        ready_threads,
+                        process.current_thread,
+                        &process.thread_priorities,
+                    )
+                    .expect("Running process had no ready threads"),
                     Some(tid) => {
                         // Ensure the specified context is ready to run, or is
                         // currently running.
@@ -933,26 +976,21 @@ impl SystemServices {
                     // new.current_thread = new_tid;
                 }
                 ProcessState::Running(x) | ProcessState::Ready(x) => {
-                    // If no new context is specified, take the previous
-                    // context.  If that is not runnable, do a round-robin
-                    // search for the next available context.
+                    // If no new context is specified, pick the highest-priority
+                    // context that is ready, going round-robin among contexts
+                    // of the same priority.
                     assert!(
                         x != 0,
                         "process was {:?} but had no free contexts",
                         new.state
                     );
                     if new_tid == 0 {
-                        new_tid = (new.current_thread + 1) as usize;
-                        while x & (1 << new_tid) == 0 {
-                            new_tid += 1;
-                            if new_tid > arch::process::MAX_THREAD {
-                                new_tid = 0;
-                            } else if new_tid == (new.current_thread + 1) as usize {
-                                // If we've looped around, return an error.
-                                // println!("Looked through all contexts and couldn't find one that was ready");
-                                return Err(xous_kernel::Error::ProcessNotFound);
-                            }
-                        }
+                        new_tid = crate::scheduler::next_thread(
+                            x,
+                            new.current_thread,
+                            &new.thread_priorities,
+                        )
+                        .ok_or(xous_kernel::Error::ProcessNotFound)?;
                         new.current_thread = new_tid as _;
                         klog!("picked thread ID {}", new_tid);
                     } else if x & (1 << new_tid) == 0 {
@@ -1083,20 +1121,16 @@ impl SystemServices {
             // let old_state = new.state;
             new.state = if let ProcessState::Running(x) = new.state {
                 let previous_tid = new.current_thread;
-                // If no new thread is specified, take the previous
-                // thread.  If that is not runnable, do a round-robin
-                // search for the next available thread.
+                // If no new thread is specified, pick the highest-priority
+                // thread that is ready, going round-robin among threads of
+                // the same priority.
                 if new_tid == 0 {
-                    new_tid = (new.current_thread + 1) as usize;
-                    while x & (1 << new_tid) == 0 {
-                        new_tid += 1;
-                        if new_tid > arch::process::MAX_THREAD {
-                            new_tid = 0;
-                        } else if new_tid == (new.current_thread + 1) as usize {
-                            // If we've looped around, return an error.
-                            return Err(xous_kernel::Error::ProcessNotFound);
-                        }
-                    }
+                    new_tid = crate::scheduler::next_thread(
+                        x,
+                        new.current_thread,
+                        &new.thread_priorities,
+                    )
+                    .ok_or(xous_kernel::Error::ProcessNotFound)?;
                     new.current_thread = new_tid as _;
                 } else if x & (1 << new_tid) == 0 {
                     return Err(xous_kernel::Error::ProcessNotFound);
@@ -1150,15 +1184,10 @@ impl SystemServices {
     ///
     /// # Errors
     ///
-    /// * **ShareViolation**: Tried to mutably share a region that was already
-    ///   shared
+    /// * **ShareViolation**: Tried to move memory that is part of a shared
+    ///   region
     /// * **BadAddress**: The provided address was not valid
     /// * **BadAlignment**: The provided address or length was not page-aligned
-    ///
-    /// # Panics
-    ///
-    /// If the memory should have been able to go into the destination process
-    /// but failed, then the system panics.
     #[cfg(baremetal)]
     pub fn send_memory(
         &mut self,
@@ -1201,6 +1230,9 @@ impl SystemServices {
         let src_mapping = self.get_process(current_pid)?.mapping;
         let dest_mapping = self.get_process(dest_pid)?.mapping;
         crate::mem::MemoryManager::with_mut(|mm| {
+            // Refuse the whole range before any of it has moved.
+            mm.check_movable(src_virt as usize, len)?;
+
             // Locate an address to fit the new memory.
             dest_mapping.activate()?;
             let dest_virt = mm
@@ -1213,8 +1245,6 @@ impl SystemServices {
                 .activate()
                 .expect("Couldn't switch back to source mapping");
 
-            let mut error = None;
-
             // Move each subsequent page.
             for offset in (0..usize_len).step_by(usize_page) {
                 assert!(((src_virt.wrapping_add(offset) as usize) & 0xfff) == 0);
@@ -1227,10 +1257,9 @@ impl SystemServices {
                     dest_pid,
                     &dest_mapping,
                     dest_virt.wrapping_add(offset) as *mut u8,
-                )
-                .unwrap_or_else(|e| error = Some(e));
+                )?;
             }
-            error.map_or_else(|| Ok(dest_virt), |e| panic!("unable to send: {:?}", e))
+            Ok(dest_virt)
         })
         .map(|val| val as *mut usize)
     }
@@ -1502,11 +1531,17 @@ impl SystemServices {
     ///
     /// * **ThreadNotAvailable**: The process has used all of its context
     ///   slots.
+    /// * **AccessDenied**: Only privileged processes may use
+    ///   `THREAD_PRIORITY_MAX`
     pub fn create_thread(
         &mut self,
         pid: PID,
         thread_init: ThreadInit,
     ) -> Result<TID, xous_kernel::Error> {
+        // The initial thread was vetted along with the process.
+        if self.get_process(pid)?.state != ProcessState::Allocated {
+            self.check_priority(pid, thread_init.priority)?;
+        }
         let mut process = self.get_process_mut(pid)?;
         process.activate()?;
 
@@ -1517,6 +1552,12 @@ impl SystemServices {
 
         arch_process.setup_thread(new_tid, thread_init)?;
 
+        // The initial thread keeps the priority the process was created with.
+        if process.state != ProcessState::Allocated {
+            process.thread_priorities[new_tid] = thread_init.priority;
+        }
+        process.exit_values[new_tid] = None;
+
         // println!("KERNEL({}): Created new thread {}", pid, new_tid);
 
         // let old_state = process.state;
@@ -1540,6 +1581,30 @@ impl SystemServices {
         Ok(new_tid)
     }
 
+    /// Set the scheduling priority of thread `tid` in process `pid`, returning
+    /// its previous priority.
+    ///
+    /// # Errors
+    ///
+    /// * **ThreadNotAvailable**: The thread ID is out of range
+    /// * **AccessDenied**: Only privileged processes may use `THREAD_PRIORITY_MAX`
+    pub fn set_thread_priority(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        priority: ThreadPriority,
+    ) -> Result<ThreadPriority, xous_kernel::Error> {
+        self.check_priority(pid, priority)?;
+        let process = self.get_process_mut(pid)?;
+        let slot = process
+            .thread_priorities
+            .get_mut(tid)
+            .ok_or(xous_kernel::Error::ThreadNotAvailable)?;
+        let previous = *slot;
+        *slot = priority;
+        Ok(previous)
+    }
+
     /// Destroy the given thread. Returns `true` if the PID has been updated.
     /// # Errors
     ///
@@ -1568,6 +1633,10 @@ impl SystemServices {
             // Wake up the thread
             self.set_thread_result(pid, waiting_tid, xous_kernel::Result::Scalar1(return_value))?;
             waiting_threads |= 1 << waiting_tid;
+        } else {
+            // Nobody is waiting yet, so hold on to the value until the thread
+            // is joined.
+            self.get_process_mut(pid)?.exit_values[tid] = Some(return_value);
         }
 
         // Mark this process as `Ready` if there are waiting threads, or `Sleeping` if
@@ -1616,9 +1685,15 @@ impl SystemServices {
                 .map(|_| Ok(xous_kernel::Result::ResumeProcess))
                 .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
         } else {
-            // The thread does not exist -- continue execution
+            // The thread does not exist -- continue execution, handing back
+            // its return value if it already exited.
             // Err(xous_kernel::Error::ThreadNotAvailable)
-            Ok(xous_kernel::Result::Scalar1(0))
+            let exit_value = self
+                .get_process_mut(pid)?
+                .exit_values
+                .get_mut(join_tid)
+                .and_then(Option::take);
+            Ok(xous_kernel::Result::Scalar1(exit_value.unwrap_or(0)))
         }
     }
 
@@ -1804,8 +1879,14 @@ impl SystemServices {
                     continue;
                 }
 
+                // Skip tombstones left behind by servers that have gone away
+                let server_idx = server_idx.unwrap().get() as usize;
+                if server_idx < 2 {
+                    continue;
+                }
+
                 // If a connection to this server ID exists already, return it.
-                let server_idx = (server_idx.unwrap().get() as usize) - 2;
+                let server_idx = server_idx - 2;
                 if let Some(allocated_server) = &self.servers[server_idx] {
                     if allocated_server.sid == sid {
                         // println!("KERNEL({}): Existing connection to SID {:?} found in this process @ {}, process connection map is: {:?}",
@@ -1894,7 +1975,7 @@ impl SystemServices {
 
     /// Return a server based on the connection id and the current process
     pub fn server_from_sidx(&self, sidx: usize) -> Option<&Server> {
-        if sidx > self.servers.len() {
+        if sidx >= self.servers.len() {
             None
         } else {
             self.servers[sidx].as_ref()
@@ -1903,13 +1984,101 @@ impl SystemServices {
 
     /// Return a server based on the connection id and the current process
     pub fn server_from_sidx_mut(&mut self, sidx: usize) -> Option<&mut Server> {
-        if sidx > self.servers.len() {
+        if sidx >= self.servers.len() {
             None
         } else {
             self.servers[sidx].as_mut()
         }
     }
 
+    /// Iterate over every server that exists, along with its index
+    #[cfg(any(feature = "gdbserver", test))]
+    pub fn servers(&self) -> impl Iterator<Item = (usize, &Server)> {
+        self.servers
+            .iter()
+            .enumerate()
+            .filter_map(|(sidx, server)| server.as_ref().map(|server| (sidx, server)))
+    }
+
+    /// Panic if the records of servers and the messages queued for them have
+    /// come apart: a server or an unreceived message that belongs to a process
+    /// that has gone, a thread that is blocked on two messages at once, the
+    /// same memory attached to two messages, memory that overlaps another
+    /// loan, or a loan that will be given back to a process that has
+    /// terminated rather than being reclaimed.
+    #[cfg(test)]
+    pub fn check_invariants(&self) {
+        let mut blocked = std::collections::HashSet::new();
+        let mut memory = std::collections::HashSet::new();
+        let mut lent = std::vec::Vec::new();
+        for (sidx, server) in self.servers() {
+            assert!(
+                !self.processes[server.pid.get() as usize - 1].free(),
+                "server {} belongs to PID {}, which has terminated",
+                sidx,
+                server.pid
+            );
+            for hold in server.holds() {
+                if let Some((pid, tid)) = hold.blocked {
+                    assert!(
+                        !hold.queued || !self.processes[pid.get() as usize - 1].free(),
+                        "server {} has a message from PID {}, which has terminated",
+                        sidx,
+                        pid
+                    );
+                    assert!(
+                        blocked.insert((pid, tid)),
+                        "PID {} TID {} is blocked on more than one message",
+                        pid,
+                        tid
+                    );
+                }
+                if let Some(range) = hold.memory {
+                    assert!(
+                        memory.insert(range.as_ptr() as usize),
+                        "memory at {:08x} is attached to more than one message",
+                        range.as_ptr() as usize
+                    );
+                }
+                if let Some(lender) = hold.lender {
+                    assert!(
+                        !self.processes[lender.get() as usize - 1].free(),
+                        "server {} holds memory lent by PID {}, which has terminated",
+                        sidx,
+                        lender
+                    );
+                    if let Some(range) = hold.memory {
+                        lent.push((range.as_ptr() as usize, range.len(), sidx));
+                    }
+                }
+            }
+        }
+
+        // A lender keeps its memory while it is lent, so no two loans can
+        // cover the same page, or in hosted mode the same byte.
+        lent.sort_unstable();
+        for pair in lent.windows(2) {
+            let (addr, len, sidx) = pair[0];
+            let (next_addr, _, next_sidx) = pair[1];
+            assert!(
+                addr + len <= next_addr,
+                "memory at {:08x} is lent to server {} and to server {}",
+                next_addr,
+                sidx,
+                next_sidx
+            );
+        }
+    }
+
+    /// Return the server with the given SID, whichever process owns it
+    #[cfg(feature = "ipc-trace")]
+    pub fn server_from_sid(&self, sid: SID) -> Option<&Server> {
+        self.servers
+            .iter()
+            .flatten()
+            .find(|server| server.sid == sid)
+    }
+
     /// Retrieve a Server ID (Extended) value from the given Connection ID
     /// within the current process.
     pub fn sidx_from_cid(&self, cid: CID) -> Option<usize> {
@@ -2023,6 +2192,42 @@ impl SystemServices {
     //     None
     // }
 
+    /// Answer a message that was sent to a server of `server_pid`, which is
+    /// terminating, with an error. Lent memory goes back to the sender, which
+    /// requires `server_pid` to be the current process.
+    fn wake_abandoned_sender(&mut self, server_pid: PID, waiting: WaitingMessage) {
+        let (pid, tid, lent) = match waiting {
+            WaitingMessage::ScalarMessage(pid, tid) => (pid, tid, None),
+            WaitingMessage::BorrowedMemory(pid, tid, server_addr, client_addr, len) => {
+                (pid, tid, Some((server_addr, client_addr, len)))
+            }
+            _ => return,
+        };
+        // Senders that are terminating too have nobody left to wake
+        if pid == server_pid || self.get_process(pid).map_or(true, |p| p.free()) {
+            return;
+        }
+        if let Some((server_addr, client_addr, len)) = lent {
+            self.return_memory(
+                server_addr.get() as _,
+                pid,
+                tid,
+                client_addr.get() as _,
+                len.get(),
+            )
+            .ok();
+        }
+        self.set_thread_result(
+            pid,
+            tid,
+            xous_kernel::Result::Error(xous_kernel::Error::ServerNotFound),
+        )
+        .ok();
+        if cfg!(baremetal) {
+            self.ready_thread(pid, tid).ok();
+        }
+    }
+
     /// Terminate the given process. Returns the process' parent PID.
     pub fn terminate_process(&mut self, target_pid: PID) -> Result<PID, xous_kernel::Error> {
         // To terminate a process, we must perform the following:
@@ -2033,6 +2238,21 @@ impl SystemServices {
         // 4. Mark all "Borrowed" memory as "Free-when-returned". That way, if we've shared
         //    memory to a Server, it will be reclaimed by the system when it comes back
 
+        // Start with step 3, while the servers still exist: wake every client
+        // that is waiting on one of them, giving back any memory it lent.
+        for sidx in 0..self.servers.len() {
+            let mut server = match self.servers[sidx].take() {
+                Some(server) if server.pid == target_pid => server,
+                other => {
+                    self.servers[sidx] = other;
+                    continue;
+                }
+            };
+            self.get_process(target_pid)?.activate()?;
+            server.take_blocked_senders(|waiting| self.wake_abandoned_sender(target_pid, waiting));
+            self.servers[sidx] = Some(server);
+        }
+
         // 1. Find all servers associated with this PID and remove them.
         for (idx, server) in self.servers.iter_mut().enumerate() {
             if let Some(server) = server {
@@ -2238,6 +2458,12 @@ impl SystemServices {
         Ok(())
     }
 
+    /// Returns the process name, if any, of a given PID. Hosted processes have no names.
+    #[cfg(not(baremetal))]
+    pub fn process_name(&self, _pid: PID) -> Option<&str> {
+        None
+    }
+
     /// Returns the process name, if any, of a given PID
     #[cfg(baremetal)]
     pub fn process_name(&self, pid: PID) -> Option<&str> {
@@ -2278,4 +2504,108 @@ impl SystemServices {
         }
         None
     }
+
+    /// Ensure `caller` is privileged, which is true of processes that
+    /// were part of the boot image.
+    pub fn check_privileged(&self, caller: PID) -> Result<(), xous_kernel::Error> {
+        if self.get_process(caller)?.privileged {
+            Ok(())
+        } else {
+            Err(xous_kernel::Error::AccessDenied)
+        }
+    }
+
+    /// Ensure `caller` may run a thread at `priority`. A thread at
+    /// `THREAD_PRIORITY_MAX` can starve everything else, including whatever
+    /// would step in to stop it, so only privileged processes may put one
+    /// there. In hosted mode PID 1 stands in for the boot image, so it counts
+    /// as privileged too.
+    pub fn check_priority(
+        &self,
+        caller: PID,
+        priority: ThreadPriority,
+    ) -> Result<(), xous_kernel::Error> {
+        if priority < THREAD_PRIORITY_MAX || caller.get() == 1 {
+            return Ok(());
+        }
+        self.check_privileged(caller)
+    }
+
+    /// Ensure `caller` may inspect process `target`. Only privileged
+    /// processes may look at processes other than themselves.
+    pub fn check_inspect(&self, caller: PID, target: PID) -> Result<(), xous_kernel::Error> {
+        if target.get() as usize > MAX_PROCESS_COUNT {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
+        if caller != target {
+            self.check_privileged(caller)?;
+        }
+        if self.get_process(target)?.free() {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
+        Ok(())
+    }
+
+    /// Describe process `target` on behalf of `caller`.
+    pub fn process_info(
+        &self,
+        caller: PID,
+        target: PID,
+    ) -> Result<ProcessInfo, xous_kernel::Error> {
+        self.check_inspect(caller, target)?;
+        let process = self.get_process(target)?;
+        let (status, ready_threads) = match process.state {
+            ProcessState::Free => return Err(xous_kernel::Error::ProcessNotFound),
+            ProcessState::Allocated => (ProcessStatus::Allocated, 0),
+            ProcessState::Setup(_) => (ProcessStatus::Setup, 0),
+            ProcessState::Ready(x) => (ProcessStatus::Ready, x),
+            // The running thread isn't part of the bitmask, but it can
+            // certainly run.
+            ProcessState::Running(x) => (ProcessStatus::Running, x | 1 << process.current_thread),
+            ProcessState::Sleeping => (ProcessStatus::Sleeping, 0),
+            ProcessState::Debug(x) => (ProcessStatus::Debug, x),
+            ProcessState::Exception(x) | ProcessState::BlockedException(x) => {
+                (ProcessStatus::Exception, x)
+            }
+        };
+
+        #[cfg(baremetal)]
+        let ram_used = crate::mem::MemoryManager::with(|mm| mm.ram_used_by(target));
+        #[cfg(not(baremetal))]
+        let ram_used = 0;
+
+        let servers = self
+            .servers
+            .iter()
+            .flatten()
+            .filter(|server| server.pid == target)
+            .count();
+
+        // The connection table lives in the target's own memory, so switch
+        // to it long enough to count its connections. Processes that haven't
+        // started yet have neither the table nor any connections.
+        let connections = match status {
+            ProcessStatus::Allocated | ProcessStatus::Setup => 0,
+            _ => {
+                let current_pid = crate::arch::process::current_pid();
+                process.activate()?;
+                let connections = ArchProcess::with_inner(|process_inner| {
+                    process_inner.connection_map.iter().flatten().count()
+                });
+                self.get_process(current_pid)?.activate()?;
+                connections
+            }
+        };
+
+        Ok(ProcessInfo {
+            pid: target,
+            ppid: process.ppid,
+            status,
+            ready_threads,
+            ram_used,
+            servers,
+            connections,
+            irqs: crate::irq::interrupts_claimed_by(target),
+        })
+    }
 }
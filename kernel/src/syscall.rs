@@ -602,6 +602,8 @@ pub fn handle(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallResult
     print!("KERNEL({}:{}): Syscall {:x?}", pid, tid, call);
     // let call_string = format!("{:x?}", call);
     // let start_time = std::time::Instant::now();
+    #[cfg(feature = "ipc-trace")]
+    crate::trace::IpcTrace::with_mut(|trace| trace.record(pid, tid, &call));
     #[allow(clippy::let_and_return)]
     let result = if in_irq && !call.can_call_from_interrupt() {
         Err(xous_kernel::Error::InvalidSyscall)
@@ -646,6 +648,11 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
                     // println!("map: bad alignment of size {:08x}", size);
                     return Err(xous_kernel::Error::BadAlignment);
                 }
+
+                // Mapping fresh RAM counts against the process' page limit.
+                if phys.is_none() || mm.is_main_memory(phys_ptr) {
+                    mm.check_page_quota(pid, size.get())?;
+                }
                 // println!(
                 //     "Mapping {:08x} -> {:08x} ({} bytes, flags: {:?})",
                 //     phys_ptr as u32, virt_ptr as u32, size, req_flags
@@ -695,6 +702,13 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             if cfg!(baremetal) && virt & 0xfff != 0 {
                 return Err(xous_kernel::Error::BadAlignment);
             }
+            // No process can have more than the user area mapped, and the
+            // range is walked a page at a time below.
+            if size > arch::mem::USER_AREA_END {
+                return Err(xous_kernel::Error::BadAddress);
+            }
+            // Shared regions can only be let go of as a whole
+            let shared_region = mm.shared_region_in(virt, size)?;
             for addr in (virt..(virt + size)).step_by(PAGE_SIZE) {
                 if let Err(e) = mm.unmap_page(addr as *mut usize) {
                     if result.is_ok() {
@@ -702,12 +716,16 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
                     }
                 }
             }
+            if let Some(name) = shared_region {
+                mm.release_shared_region(pid, name)?;
+            }
             result
         }),
         SysCall::IncreaseHeap(delta, flags) => {
             if delta & 0xfff != 0 {
                 return Err(xous_kernel::Error::BadAlignment);
             }
+            MemoryManager::with_mut(|mm| mm.check_page_quota(pid, delta))?;
             let start = {
                 ArchProcess::with_inner_mut(|process_inner| {
                     if process_inner.mem_heap_size + delta > process_inner.mem_heap_max {
@@ -804,6 +822,11 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
         SysCall::CreateProcess(process_init) => SystemServices::with_mut(|ss| {
             ss.create_process(process_init)
                 .map(xous_kernel::Result::ProcessID)
+        })
+        .map_err(|e| {
+            #[cfg(baremetal)]
+            crate::arch::process::Process::discard_image(&process_init);
+            e
         }),
         SysCall::CreateServerWithAddress(name) => SystemServices::with_mut(|ss| {
             ss.create_server_with_address(pid, name)
@@ -852,9 +875,9 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
                 Err(e) => Err(e),
             }
         }
-        SysCall::ConnectForProcess(pid, sid) => {
+        SysCall::ConnectForProcess(target_pid, sid) => {
             let result = SystemServices::with_mut(|ss| {
-                ss.connect_process_to_server(pid, sid)
+                ss.connect_process_to_server(target_pid, sid)
                     .map(xous_kernel::Result::ConnectionID)
             });
             match result {
@@ -888,6 +911,94 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             ss.set_exception_handler(pid, pc, sp)
                 .and(Ok(xous_kernel::Result::Ok))
         }),
+        SysCall::GetProcessName(target, offset) => SystemServices::with(|ss| {
+            if target.get() as usize > arch::process::MAX_PROCESS_COUNT || ss.get_process(target)?.free() {
+                return Err(xous_kernel::Error::ProcessNotFound);
+            }
+            let name = ss.process_name(target).unwrap_or("").as_bytes();
+            let mut words = [0usize; 4];
+            for (word, chunk) in words
+                .iter_mut()
+                .zip(name.get(offset..).unwrap_or(&[]).chunks(mem::size_of::<usize>()))
+            {
+                let mut bytes = [0u8; mem::size_of::<usize>()];
+                bytes[..chunk.len()].copy_from_slice(chunk);
+                *word = usize::from_le_bytes(bytes);
+            }
+            Ok(xous_kernel::Result::Scalar5(
+                name.len(),
+                words[0],
+                words[1],
+                words[2],
+                words[3],
+            ))
+        }),
+        SysCall::SetThreadPriority(target_tid, priority) => SystemServices::with_mut(|ss| {
+            ss.set_thread_priority(pid, target_tid, priority)
+                .map(|previous| xous_kernel::Result::Scalar1(previous as usize))
+        }),
+        SysCall::GetProcessInfo(target) => SystemServices::with(|ss| {
+            let args = ss.process_info(pid, target)?.to_args();
+            Ok(xous_kernel::Result::Scalar5(
+                args[0], args[1], args[2], args[3], args[4],
+            ))
+        }),
+        #[cfg(baremetal)]
+        SysCall::GetRamUsage => MemoryManager::with(|mm| {
+            let (used, total) = mm.ram_usage();
+            Ok(xous_kernel::Result::Scalar2(used, total))
+        }),
+        #[cfg(not(baremetal))]
+        SysCall::GetRamUsage => Ok(xous_kernel::Result::Scalar2(0, 0)),
+        SysCall::GetPageQuota(target) => {
+            SystemServices::with(|ss| ss.check_inspect(pid, target))?;
+            let quota = MemoryManager::with_mut(|mm| mm.page_quota(target));
+            Ok(xous_kernel::Result::Scalar2(quota.used, quota.limit))
+        }
+        #[cfg(feature = "ipc-trace")]
+        SysCall::GetIpcTrace(seq, half) => {
+            if half > 1 {
+                return Err(xous_kernel::Error::InvalidSyscall);
+            }
+            SystemServices::with(|ss| ss.check_privileged(pid))?;
+            crate::trace::IpcTrace::with(|trace| {
+                // Let the caller catch up if it has fallen behind the trace
+                let seq = if half == 0 {
+                    seq.max(trace.oldest())
+                } else {
+                    seq
+                };
+                match trace.get(seq) {
+                    Some(event) => {
+                        let args = event.to_args()[half];
+                        Ok(xous_kernel::Result::Scalar5(
+                            args[0], args[1], args[2], args[3], args[4],
+                        ))
+                    }
+                    None => Ok(xous_kernel::Result::None),
+                }
+            })
+        }
+        #[cfg(baremetal)]
+        SysCall::CreateSharedRegion(name, size, flags) => MemoryManager::with_mut(|mm| {
+            mm.create_shared_region(pid, name, size.get(), flags)
+                .map(xous_kernel::Result::MemoryRange)
+        }),
+        #[cfg(baremetal)]
+        SysCall::MapSharedRegion(name, flags) => MemoryManager::with_mut(|mm| {
+            mm.map_shared_region(pid, name, flags)
+                .map(xous_kernel::Result::MemoryRange)
+        }),
+        #[cfg(baremetal)]
+        SysCall::OfferSharedRegion(name, target, flags) => SystemServices::with(|ss| {
+            if target.get() as usize > arch::process::MAX_PROCESS_COUNT
+                || ss.get_process(target)?.free()
+            {
+                return Err(xous_kernel::Error::ProcessNotFound);
+            }
+            MemoryManager::with_mut(|mm| mm.offer_shared_region(pid, target, name, flags))
+                .map(|_| xous_kernel::Result::Ok)
+        }),
         _ => Err(xous_kernel::Error::UnhandledSyscall),
     }
 }
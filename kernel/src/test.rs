@@ -8,6 +8,8 @@ use crossbeam_channel::unbounded;
 use std::net::ToSocketAddrs;
 use xous_kernel::{rsyscall, SysCall};
 
+mod fuzz;
+
 #[cfg(feature = "report-memory")]
 use stats_alloc::{Region, Stats, StatsAlloc, INSTRUMENTED_SYSTEM};
 #[cfg(feature = "report-memory")]
@@ -1170,3 +1172,514 @@ fn process_restart_server() {
 
     main_thread.join().expect("couldn't join kernel process");
 }
+
+#[test]
+fn process_name() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let xous_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("process_name", || {
+            let pid = xous_kernel::current_pid().expect("couldn't get our pid");
+            let mut name = [0u8; 32];
+            // hosted processes are not given names by a loader
+            assert_eq!(xous_kernel::process_name(pid, &mut name), Ok(0));
+
+            let missing = xous_kernel::PID::new(200).unwrap();
+            assert_eq!(
+                xous_kernel::process_name(missing, &mut name),
+                Err(xous_kernel::Error::ProcessNotFound)
+            );
+        }),
+    )
+    .expect("couldn't spawn process");
+    xous_kernel::wait_process_as_thread(xous_process).expect("couldn't join process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn process_info() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let xous_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("process_info", || {
+            let pid = xous_kernel::current_pid().expect("couldn't get our pid");
+            let sid = xous_kernel::create_server().expect("couldn't create server");
+            let _cid = xous_kernel::connect(sid).expect("couldn't connect to server");
+
+            let info = xous_kernel::process_info(pid).expect("couldn't get process info");
+            assert_eq!(info.pid, pid);
+            assert_eq!(info.status, xous_kernel::ProcessStatus::Running);
+            assert_ne!(info.ready_threads, 0);
+            assert_eq!(info.servers, 1);
+            assert_eq!(info.connections, 1);
+            assert_eq!(info.irqs, 0);
+            // hosted processes don't own any RAM
+            assert_eq!(info.ram_used, 0);
+            assert_eq!(xous_kernel::ram_usage(), Ok((0, 0)));
+            assert_eq!(xous_kernel::page_quota(pid), Ok((0, None)));
+
+            let missing = xous_kernel::PID::new(200).unwrap();
+            assert_eq!(
+                xous_kernel::process_info(missing),
+                Err(xous_kernel::Error::ProcessNotFound)
+            );
+
+            // Processes started by PID 1 may look at other processes, too.
+            let pid1 = xous_kernel::PID::new(1).unwrap();
+            let info = xous_kernel::process_info(pid1).expect("couldn't get PID 1 info");
+            assert_eq!(info.pid, pid1);
+            assert_eq!(info.servers, 0);
+        }),
+    )
+    .expect("couldn't spawn process");
+    xous_kernel::wait_process_as_thread(xous_process).expect("couldn't join process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn ipc_trace() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let xous_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("ipc_trace", || {
+            #[cfg(feature = "ipc-trace")]
+            let pid = xous_kernel::current_pid().expect("couldn't get our pid");
+            let sid = xous_kernel::create_server().expect("couldn't create server");
+            let cid = xous_kernel::connect(sid).expect("couldn't connect to server");
+            xous_kernel::send_message(cid, xous_kernel::Message::new_scalar(7, 1, 2, 3, 4))
+                .expect("couldn't send message");
+
+            #[cfg(not(feature = "ipc-trace"))]
+            assert_eq!(
+                xous_kernel::ipc_trace_event(0),
+                Err(xous_kernel::Error::UnhandledSyscall)
+            );
+
+            #[cfg(feature = "ipc-trace")]
+            {
+                let mut events = vec![];
+                while let Some(event) =
+                    xous_kernel::ipc_trace_event(events.len()).expect("couldn't read the trace")
+                {
+                    assert_eq!(event.seq, events.len());
+                    events.push(event);
+                }
+                let connect = events
+                    .iter()
+                    .find(|e| e.pid == pid && e.kind == xous_kernel::IpcTraceKind::Connect)
+                    .expect("connect wasn't traced");
+                assert_eq!(connect.sid, Some(sid));
+                assert_eq!(connect.peer, Some(pid));
+
+                let send = events
+                    .iter()
+                    .find(|e| e.pid == pid && e.kind == xous_kernel::IpcTraceKind::SendMessage)
+                    .expect("send wasn't traced");
+                assert_eq!(send.cid, cid);
+                assert_eq!(send.sid, Some(sid));
+                assert_eq!(send.peer, Some(pid));
+                assert_eq!(send.opcode, 7);
+                assert_eq!(send.len, 0);
+                assert!(send.timestamp >= connect.timestamp);
+                assert!(xous_kernel::ipc_trace_event(events.len())
+                    .expect("couldn't read past the end of the trace")
+                    .is_none());
+            }
+        }),
+    )
+    .expect("couldn't spawn process");
+    xous_kernel::wait_process_as_thread(xous_process).expect("couldn't join process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn page_quota() {
+    use crate::mem::{MemoryManager, PageQuota, PAGE_SIZE};
+
+    let quota = PageQuota { used: 3, limit: 4 };
+    assert!(quota.allows(1));
+    assert!(!quota.allows(2));
+    assert!(PageQuota { used: 3, limit: 0 }.allows(usize::MAX));
+
+    let pid = xous_kernel::PID::new(2).unwrap();
+    let mut mm = MemoryManager::default();
+    mm.check_page_quota(pid, 100 * PAGE_SIZE)
+        .expect("unlimited process was refused");
+    mm.set_page_limit(pid, 2);
+    assert_eq!(mm.page_quota(pid), PageQuota { used: 0, limit: 2 });
+    mm.check_page_quota(pid, 2 * PAGE_SIZE)
+        .expect("allocation within the limit was refused");
+    // Partial pages still take up a whole page.
+    assert_eq!(
+        mm.check_page_quota(pid, 2 * PAGE_SIZE + 1),
+        Err(xous_kernel::Error::OutOfMemory)
+    );
+
+    // PIDs past the end of the table have no quota to enforce.
+    let big_pid = xous_kernel::PID::new(u8::MAX).unwrap();
+    mm.set_page_limit(big_pid, 1);
+    assert_eq!(mm.page_quota(big_pid), PageQuota::unlimited());
+}
+
+#[test]
+fn shared_region_holders() {
+    use crate::mem::SharedRegion;
+    use xous_kernel::{Error, MemoryFlags, PID};
+
+    let creator = PID::new(2).unwrap();
+    let reader = PID::new(3).unwrap();
+    let stranger = PID::new(4).unwrap();
+    let mut region = SharedRegion::new(
+        0x5348_4152,
+        0x4000_0000,
+        2 * crate::mem::PAGE_SIZE,
+        creator,
+        MemoryFlags::R | MemoryFlags::W,
+    );
+    assert!(region.contains(0x4000_1000));
+    assert!(!region.contains(0x4000_2000));
+
+    // Peers can't be offered more than the process handing out the region has.
+    assert_eq!(
+        region.offer(creator, reader, MemoryFlags::R | MemoryFlags::X),
+        Err(Error::AccessDenied)
+    );
+    assert_eq!(
+        region.offer(creator, reader, MemoryFlags::W),
+        Err(Error::InvalidSyscall)
+    );
+    assert_eq!(
+        region.accept(reader, MemoryFlags::R),
+        Err(Error::BadAddress)
+    );
+    region
+        .offer(creator, reader, MemoryFlags::R | MemoryFlags::W)
+        .expect("couldn't offer the region");
+    // Nothing is held until the offer is taken up, and then only with what
+    // was offered.
+    assert!(region.access(reader).is_empty());
+    assert_eq!(
+        region.accept(reader, MemoryFlags::R | MemoryFlags::X),
+        Err(Error::AccessDenied)
+    );
+    region
+        .accept(reader, MemoryFlags::R)
+        .expect("couldn't take up the offer read-only");
+    assert_eq!(region.access(reader), MemoryFlags::R);
+    assert!(region.offered(reader).is_empty());
+    assert_eq!(
+        region.accept(reader, MemoryFlags::R),
+        Err(Error::BadAddress)
+    );
+    assert_eq!(
+        region.offer(creator, reader, MemoryFlags::R),
+        Err(Error::MemoryInUse)
+    );
+    assert_eq!(
+        region.offer(reader, stranger, MemoryFlags::R | MemoryFlags::W),
+        Err(Error::AccessDenied)
+    );
+    assert_eq!(
+        region.offer(stranger, reader, MemoryFlags::R),
+        Err(Error::BadAddress)
+    );
+
+    // The region lives on until its last holder lets go.
+    assert!(!region.release(creator));
+    assert_eq!(region.holders().collect::<Vec<_>>(), [reader]);
+    assert!(region.release(reader));
+    assert!(region.access(creator).is_empty());
+}
+
+#[test]
+fn shared_region_offers() {
+    use crate::mem::{MemoryManager, MAX_SHARED_REGIONS, PAGE_SIZE};
+    use xous_kernel::{Error, MemoryFlags, PID};
+
+    const NAME: usize = 0x5348_4152;
+    let creator = PID::new(2).unwrap();
+    let reader = PID::new(3).unwrap();
+    let stranger = PID::new(4).unwrap();
+    let rw = MemoryFlags::R | MemoryFlags::W;
+
+    let mut mm = MemoryManager::default();
+    mm.add_shared_region(creator, NAME, 0x4000_0000, 2 * PAGE_SIZE, rw)
+        .expect("couldn't add the region");
+    assert_eq!(
+        mm.add_shared_region(reader, NAME, 0x4001_0000, PAGE_SIZE, rw),
+        Err(Error::MemoryInUse)
+    );
+
+    // Offering the region maps nothing into the other process.
+    mm.offer_shared_region(creator, reader, NAME, MemoryFlags::R)
+        .expect("couldn't offer the region");
+    let region = mm.shared_region(NAME).unwrap();
+    assert_eq!(region.offered(reader), MemoryFlags::R);
+    assert_eq!(region.holders().collect::<Vec<_>>(), [creator]);
+    assert_eq!(
+        mm.offer_shared_region(stranger, reader, NAME, MemoryFlags::R),
+        Err(Error::BadAddress)
+    );
+    assert_eq!(
+        mm.offer_shared_region(creator, reader, NAME + 1, MemoryFlags::R),
+        Err(Error::BadAddress)
+    );
+
+    // An offer doesn't outlive the process it was made to, or the next
+    // process to get its PID could take it up.
+    mm.offer_shared_region(creator, stranger, NAME, rw)
+        .expect("couldn't offer the region");
+    unsafe { mm.release_all_memory_for_process(stranger) };
+    let region = mm.shared_region(NAME).unwrap();
+    assert!(region.offered(stranger).is_empty());
+    assert_eq!(region.offered(reader), MemoryFlags::R);
+
+    // Nor does an offer keep the region alive once its last holder is gone.
+    unsafe { mm.release_all_memory_for_process(creator) };
+    assert!(mm.shared_region(NAME).is_none());
+    assert_eq!(
+        mm.offer_shared_region(creator, reader, NAME, MemoryFlags::R),
+        Err(Error::BadAddress)
+    );
+
+    // The region's slot was freed along with it.
+    for name in 0..MAX_SHARED_REGIONS {
+        mm.add_shared_region(creator, name, 0x4000_0000, PAGE_SIZE, rw)
+            .expect("couldn't fill the shared region table");
+    }
+    assert_eq!(
+        mm.add_shared_region(creator, NAME, 0x4000_0000, PAGE_SIZE, rw),
+        Err(Error::OutOfMemory)
+    );
+}
+
+#[test]
+fn shared_regions_cannot_be_moved() {
+    use crate::mem::{MemoryManager, PAGE_SIZE};
+    use xous_kernel::{Error, MemoryFlags, PID};
+
+    // Hosted addresses translate to themselves, so the region's physical
+    // address is also where the sender has it mapped.
+    const REGION: usize = 0x4000_0000;
+    let creator = PID::new(2).unwrap();
+    let mut mm = MemoryManager::default();
+    mm.add_shared_region(
+        creator,
+        0x4d4f_5645,
+        REGION,
+        2 * PAGE_SIZE,
+        MemoryFlags::R | MemoryFlags::W,
+    )
+    .expect("couldn't add the region");
+
+    // Sending the region as a `Move`, or any part of it, is refused before
+    // a page changes hands.
+    assert_eq!(
+        mm.check_movable(REGION, 2 * PAGE_SIZE),
+        Err(Error::ShareViolation)
+    );
+    assert_eq!(
+        mm.check_movable(REGION + PAGE_SIZE, PAGE_SIZE),
+        Err(Error::ShareViolation)
+    );
+    assert_eq!(
+        mm.check_movable(REGION - PAGE_SIZE, 2 * PAGE_SIZE),
+        Err(Error::ShareViolation)
+    );
+    assert_eq!(
+        mm.check_movable(usize::MAX - PAGE_SIZE + 1, 2 * PAGE_SIZE),
+        Err(Error::BadAddress)
+    );
+
+    // Memory around the region moves as before.
+    mm.check_movable(REGION - PAGE_SIZE, PAGE_SIZE)
+        .expect("memory before the region couldn't be moved");
+    mm.check_movable(REGION + 2 * PAGE_SIZE, PAGE_SIZE)
+        .expect("memory after the region couldn't be moved");
+}
+
+#[test]
+fn lent_memory_is_forgotten_when_the_lender_terminates() {
+    use crate::server::{Server, WaitingMessage};
+    use xous_kernel::{MemoryAddress, MemoryMessage, MemoryRange, Message, PID, SID};
+
+    let client = PID::new(3).unwrap();
+    let buf = unsafe { MemoryRange::new(0x1000_0000, 0x2000) }.unwrap();
+    let mut server = None;
+    Server::init(
+        &mut server,
+        PID::new(2).unwrap(),
+        SID::from_u32(1, 2, 3, 4),
+        unsafe { MemoryRange::new(4096, 4096) }.unwrap(),
+    )
+    .expect("couldn't create server");
+    let server = server.as_mut().unwrap();
+
+    let message = Message::MutableBorrow(MemoryMessage {
+        id: 1,
+        buf,
+        offset: None,
+        valid: None,
+    });
+    let idx = server
+        .queue_message(client, 2, message, MemoryAddress::new(0x2000_0000))
+        .expect("couldn't queue message");
+
+    // The server has the memory, and the client is waiting to get it back.
+    assert!(server.take_next_message(0).is_some());
+    let hold = server.holds().next().unwrap();
+    assert!(!hold.queued);
+    assert_eq!(hold.lender, Some(client));
+    assert_eq!(hold.memory, Some(buf));
+
+    // Once the client has gone, the memory is reclaimed when the server is
+    // done with it rather than being handed to whatever gets the PID next.
+    server.discard_messages_for_pid(client);
+    let hold = server.holds().next().unwrap();
+    assert_eq!(hold.lender, None);
+    assert_eq!(hold.memory, Some(buf));
+    match server.take_waiting_message(idx, Some(&buf)) {
+        Ok(WaitingMessage::ForgetMemory(range)) => assert_eq!(range, buf),
+        other => panic!(
+            "lent memory would go back to a terminated process: {:?}",
+            other
+        ),
+    }
+    assert!(server.holds().next().is_none());
+}
+
+#[test]
+fn scheduler_picks_highest_priority() {
+    use crate::scheduler::{highest_priority, next_thread, pick_next};
+
+    let mut priorities = [xous_kernel::THREAD_PRIORITY_DEFAULT; 8];
+    priorities[3] = 6;
+    priorities[5] = 6;
+    priorities[6] = xous_kernel::THREAD_PRIORITY_MAX;
+    let ready = (1 << 2) | (1 << 3) | (1 << 5);
+
+    assert_eq!(highest_priority(ready, &priorities), Some(6));
+    assert_eq!(highest_priority(0, &priorities), None);
+
+    // Threads 3 and 5 share the highest ready priority, so they alternate and
+    // thread 2 never gets a turn.
+    assert_eq!(next_thread(ready, 2, &priorities), Some(3));
+    assert_eq!(next_thread(ready, 3, &priorities), Some(5));
+    assert_eq!(next_thread(ready, 5, &priorities), Some(3));
+
+    // Once it's the only one left, the low-priority thread runs.
+    assert_eq!(next_thread(1 << 2, 2, &priorities), Some(2));
+    assert_eq!(next_thread(0, 2, &priorities), None);
+
+    // Equal priorities are taken in round-robin order, wrapping at the end.
+    assert_eq!(pick_next(4, 2, |_| Some(1)), Some(3));
+    assert_eq!(pick_next(4, 3, |_| Some(1)), Some(0));
+    assert_eq!(pick_next(4, 0, |_| None), None);
+}
+
+#[test]
+fn thread_priority() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let xous_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("thread_priority", || {
+            let worker = xous_kernel::create_thread_with_priority(6, || {
+                let tid = xous_kernel::current_tid().expect("couldn't get our tid");
+                assert_eq!(xous_kernel::set_thread_priority(tid, 2), Ok(6));
+            })
+            .expect("couldn't spawn thread");
+            xous_kernel::wait_thread(worker).expect("thread did not start with its priority");
+
+            let tid = xous_kernel::current_tid().expect("couldn't get our tid");
+            assert_eq!(
+                xous_kernel::set_thread_priority(tid, xous_kernel::THREAD_PRIORITY_MAX),
+                Ok(xous_kernel::THREAD_PRIORITY_DEFAULT)
+            );
+            assert_eq!(
+                xous_kernel::set_thread_priority(tid, xous_kernel::THREAD_PRIORITY_MAX + 1),
+                Err(xous_kernel::Error::InvalidSyscall)
+            );
+            assert_eq!(
+                xous_kernel::set_thread_priority(200, xous_kernel::THREAD_PRIORITY_IDLE),
+                Err(xous_kernel::Error::ThreadNotAvailable)
+            );
+        }),
+    )
+    .expect("couldn't spawn process");
+    xous_kernel::wait_process_as_thread(xous_process).expect("couldn't join process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn max_thread_priority_needs_privilege() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    // Processes started by PID 1 stand in for the boot image, so the ones
+    // they start in turn are the first that aren't privileged.
+    let xous_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("privileged", || {
+            let child = xous_kernel::create_process_as_thread(
+                xous_kernel::ProcessArgsAsThread::new("unprivileged", || {
+                    let tid = xous_kernel::current_tid().expect("couldn't get our tid");
+                    assert_eq!(
+                        xous_kernel::set_thread_priority(tid, xous_kernel::THREAD_PRIORITY_MAX),
+                        Err(xous_kernel::Error::AccessDenied)
+                    );
+                    assert!(matches!(
+                        xous_kernel::create_thread_with_priority(
+                            xous_kernel::THREAD_PRIORITY_MAX,
+                            || {}
+                        ),
+                        Err(xous_kernel::Error::AccessDenied)
+                    ));
+                    // Anything short of the top is still fine.
+                    assert_eq!(
+                        xous_kernel::set_thread_priority(tid, xous_kernel::THREAD_PRIORITY_MAX - 1),
+                        Ok(xous_kernel::THREAD_PRIORITY_DEFAULT)
+                    );
+                }),
+            )
+            .expect("couldn't spawn unprivileged process");
+            xous_kernel::wait_process_as_thread(child).expect("couldn't join child process");
+
+            let worker = xous_kernel::create_thread_with_priority(
+                xous_kernel::THREAD_PRIORITY_MAX,
+                || {},
+            )
+            .expect("privileged process couldn't use the top priority");
+            xous_kernel::wait_thread(worker).expect("couldn't join thread");
+        }),
+    )
+    .expect("couldn't spawn process");
+    xous_kernel::wait_process_as_thread(xous_process).expect("couldn't join process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn thread_join_value() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let xous_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("thread_join_value", || {
+            let greeting = String::from("hello");
+            let worker = xous_kernel::create_thread(move || format!("{}, world", greeting))
+                .expect("couldn't spawn thread");
+            assert_eq!(worker.join(), Ok(String::from("hello, world")));
+        }),
+    )
+    .expect("couldn't spawn process");
+    xous_kernel::wait_process_as_thread(xous_process).expect("couldn't join process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
@@ -0,0 +1,296 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Randomised tests that throw the sort of register values a buggy or
+//! hostile process could pass at the syscall decoder and the hosted kernel.
+//!
+//! Every run prints the seed it used. Set `XOUS_FUZZ_SEED` to replay a
+//! failing run, and `XOUS_FUZZ_ROUNDS` to run for longer than the default.
+//! While these tests run, the kernel checks its own bookkeeping after every
+//! syscall (see `SystemServices::check_invariants()`), so a failure may show
+//! up as a panic in the kernel thread rather than in the test itself.
+
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use crossbeam_channel::unbounded;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use xous_kernel::{MemoryFlags, Message, ScalarMessage, SysCall, CID, SID};
+
+use super::{shutdown_kernel, start_kernel, SERVER_SPEC};
+
+const DEFAULT_SEED: u64 = 0x786f_7573_6675_7a7a;
+
+/// Opcode that a fuzz client sends to each server once it's finished
+const DONE: usize = 0xd0e;
+
+const CLIENT_COUNT: usize = 4;
+const SERVER_COUNT: usize = 2;
+
+fn seed() -> u64 {
+    let seed = std::env::var("XOUS_FUZZ_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(DEFAULT_SEED);
+    println!("fuzzing with XOUS_FUZZ_SEED={}", seed);
+    seed
+}
+
+fn rounds(default: usize) -> usize {
+    std::env::var("XOUS_FUZZ_ROUNDS")
+        .ok()
+        .and_then(|rounds| rounds.parse().ok())
+        .unwrap_or(default)
+}
+
+/// A register value, biased towards the ones that tend to find bugs
+fn word(rng: &mut StdRng) -> usize {
+    match rng.gen_range(0, 8) {
+        0 => 0,
+        1 => rng.gen_range(1, 64),
+        2 => rng.gen_range(0, 0x1_0000) * 4096,
+        3 => u32::MAX as usize,
+        4 => usize::MAX,
+        5 => (u32::MAX as usize).wrapping_add(rng.gen_range(1, 64)),
+        _ => rng.gen(),
+    }
+}
+
+/// The registers of a syscall, which is usually one that exists
+fn syscall_args(rng: &mut StdRng) -> [usize; 8] {
+    let mut args = [0; 8];
+    for arg in args.iter_mut() {
+        *arg = word(rng);
+    }
+    if rng.gen_range(0, 10) != 0 {
+        args[0] = rng.gen_range(0, 48);
+    }
+    args
+}
+
+/// Calls that would stop the fuzz client, block it forever or take down
+/// more than the client itself, none of which are bugs. Servers are left out
+/// too, as each one made here would never be destroyed and would keep one of
+/// the client's connections.
+fn disruptive(call: &SysCall) -> bool {
+    match call {
+        SysCall::CreateServer | SysCall::CreateServerWithAddress(_) => true,
+        SysCall::Shutdown
+        | SysCall::TerminateProcess(_)
+        | SysCall::ReturnToParent(..)
+        | SysCall::SwitchTo(..)
+        | SysCall::WaitEvent
+        | SysCall::ReceiveMessage(_)
+        | SysCall::Connect(_)
+        | SysCall::ConnectForProcess(..)
+        | SysCall::JoinThread(_)
+        | SysCall::CreateThread(_)
+        | SysCall::CreateProcess(_) => true,
+        SysCall::SendMessage(_, message) | SysCall::TrySendMessage(_, message) => {
+            message.is_blocking()
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn decode_random_syscalls() {
+    let mut rng = StdRng::seed_from_u64(seed());
+    for _ in 0..rounds(100_000) {
+        let [nr, a1, a2, a3, a4, a5, a6, a7] = syscall_args(&mut rng);
+        // Whatever a call decodes to must survive being encoded again, which
+        // is how it travels from the kernel to a server.
+        if let Ok(call) = SysCall::from_args(nr, a1, a2, a3, a4, a5, a6, a7) {
+            let [nr, a1, a2, a3, a4, a5, a6, a7] = call.as_args();
+            assert_eq!(
+                SysCall::from_args(nr, a1, a2, a3, a4, a5, a6, a7),
+                Ok(call),
+                "syscall {:x?} changed after encoding",
+                [nr, a1, a2, a3, a4, a5, a6, a7]
+            );
+        }
+    }
+}
+
+/// A server that answers everything it's sent until each client has said
+/// it's done. If `quit_after` is set, it instead exits after that many
+/// messages, still holding whatever memory it was last lent.
+fn fuzz_server(sid: SID, quit_after: Option<usize>) {
+    let mut done = 0;
+    let mut received = 0;
+    while done < CLIENT_COUNT {
+        let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+        received += 1;
+        if Some(received) == quit_after {
+            return;
+        }
+        match envelope.body {
+            Message::Scalar(ScalarMessage { id: DONE, .. }) => done += 1,
+            Message::BlockingScalar(ScalarMessage { id, .. }) => {
+                xous_kernel::return_scalar(envelope.sender, id).ok();
+            }
+            Message::Borrow(m) | Message::MutableBorrow(m) => {
+                xous_kernel::return_memory(envelope.sender, m.buf).ok();
+            }
+            Message::Move(m) => {
+                xous_kernel::unmap_memory(m.buf).ok();
+            }
+            Message::Scalar(_) => (),
+        }
+    }
+}
+
+/// Make random calls, some raw and some through the usual wrappers, with
+/// `servers` as the servers that are answering messages. Errors are expected
+/// and ignored, since most of the calls are nonsense.
+fn fuzz_client(mut rng: StdRng, servers: Vec<SID>, rounds: usize) {
+    let mut connections: Vec<CID> = servers
+        .iter()
+        .filter_map(|sid| xous_kernel::try_connect(*sid).ok())
+        .collect();
+    for _ in 0..rounds {
+        let connection = if connections.is_empty() {
+            None
+        } else {
+            Some(connections[rng.gen_range(0, connections.len())])
+        };
+        match (rng.gen_range(0, 8), connection) {
+            (0, _) | (1, _) => {
+                let args = syscall_args(&mut rng);
+                let [nr, a1, a2, a3, a4, a5, a6, a7] = args;
+                match SysCall::from_args(nr, a1, a2, a3, a4, a5, a6, a7) {
+                    Ok(call) if disruptive(&call) => (),
+                    _ => {
+                        xous_kernel::arch::raw_syscall(args).ok();
+                    }
+                }
+            }
+            (2, Some(cid)) => {
+                let message = ScalarMessage::from_usize(word(&mut rng), 1, 2, 3, 4);
+                if message.id != DONE {
+                    xous_kernel::send_message(cid, Message::Scalar(message)).ok();
+                }
+            }
+            (3, Some(cid)) => {
+                let message = ScalarMessage::from_usize(word(&mut rng), 1, 2, 3, 4);
+                xous_kernel::send_message(cid, Message::BlockingScalar(message)).ok();
+            }
+            (4, Some(cid)) => {
+                let mut bytes = vec![0u8; rng.gen_range(1, 3 * 4096)];
+                rng.fill(&mut bytes[..]);
+                let mut carton = xous_kernel::carton::Carton::from_bytes(&bytes);
+                if rng.gen() {
+                    carton.lend(cid, word(&mut rng)).ok();
+                } else {
+                    carton.lend_mut(cid, word(&mut rng)).ok();
+                }
+            }
+            (5, _) => {
+                let size = rng.gen_range(1, 4) * 4096;
+                if let Ok(range) =
+                    xous_kernel::map_memory(None, None, size, MemoryFlags::R | MemoryFlags::W)
+                {
+                    xous_kernel::unmap_memory(range).expect("couldn't unmap memory");
+                }
+            }
+            (6, _) => {
+                if let Ok(sid) = xous_kernel::create_server() {
+                    if let Ok(cid) = xous_kernel::try_connect(sid) {
+                        unsafe { xous_kernel::disconnect(cid) }.ok();
+                    }
+                    xous_kernel::destroy_server(sid).ok();
+                }
+            }
+            (7, Some(cid)) => {
+                // Servers may have gone away, so connections are thrown out
+                // now and then and made again.
+                connections.retain(|c| *c != cid);
+                unsafe { xous_kernel::disconnect(cid) }.ok();
+                let sid = servers[rng.gen_range(0, servers.len())];
+                if let Ok(cid) = xous_kernel::try_connect(sid) {
+                    connections.push(cid);
+                }
+            }
+            _ => xous_kernel::yield_slice(),
+        }
+    }
+
+    for sid in servers {
+        if let Ok(cid) = xous_kernel::try_connect(sid) {
+            xous_kernel::send_message(
+                cid,
+                Message::Scalar(ScalarMessage::from_usize(DONE, 0, 0, 0, 0)),
+            )
+            .ok();
+        }
+    }
+}
+
+#[test]
+fn random_syscalls_from_many_processes() {
+    let main_thread = start_kernel(SERVER_SPEC);
+    let mut rng = StdRng::seed_from_u64(seed());
+    let rounds = rounds(500);
+
+    let (sid_send, sid_recv) = unbounded();
+    let mut processes = vec![];
+    for idx in 0..SERVER_COUNT {
+        let sid_send = sid_send.clone();
+        // One server gives up part way through, leaving its clients to be
+        // woken by the kernel.
+        let quit_after = if idx == 0 {
+            Some(rng.gen_range(1, CLIENT_COUNT * rounds / 4))
+        } else {
+            None
+        };
+        processes.push(
+            xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+                "fuzz server",
+                move || {
+                    let sid = xous_kernel::create_server().expect("couldn't create server");
+                    sid_send.send(sid).unwrap();
+                    fuzz_server(sid, quit_after);
+                },
+            ))
+            .expect("couldn't start server"),
+        );
+    }
+    let servers: Vec<SID> = (0..SERVER_COUNT)
+        .map(|_| sid_recv.recv().unwrap())
+        .collect();
+
+    for _ in 0..CLIENT_COUNT {
+        let client_rng = StdRng::seed_from_u64(rng.gen());
+        let servers = servers.clone();
+        processes.push(
+            xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+                "fuzz client",
+                move || fuzz_client(client_rng, servers, rounds),
+            ))
+            .expect("couldn't start client"),
+        );
+    }
+
+    // A client stuck on a message that will never be answered is a bug too,
+    // so don't wait on them forever.
+    let (finished_send, finished_recv) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for process in processes {
+            finished_send
+                .send(xous_kernel::wait_process_as_thread(process))
+                .ok();
+        }
+    });
+    for _ in 0..SERVER_COUNT + CLIENT_COUNT {
+        match finished_recv.recv_timeout(Duration::from_secs(120)) {
+            Ok(result) => {
+                result.expect("fuzz process panicked");
+            }
+            Err(RecvTimeoutError::Timeout) => panic!("fuzz processes stopped making progress"),
+            Err(RecvTimeoutError::Disconnected) => unreachable!(),
+        }
+    }
+
+    shutdown_kernel();
+    main_thread.join().expect("kernel panicked");
+}
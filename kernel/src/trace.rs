@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A record of the most recent IPC calls, for tracking down services that
+//! deadlock on each other. Only built with the `ipc-trace` feature.
+
+use crate::server::SenderID;
+use crate::services::SystemServices;
+use xous_kernel::{IpcTraceEvent, IpcTraceKind, SysCall, PID, TID};
+
+/// The number of events that are kept before the oldest is overwritten
+pub const IPC_TRACE_DEPTH: usize = 128;
+
+pub struct IpcTrace {
+    events: [Option<IpcTraceEvent>; IPC_TRACE_DEPTH],
+
+    /// The sequence number that the next event will get
+    next_seq: usize,
+
+    /// The number of syscalls seen so far, which is the only clock the
+    /// kernel has on hardware
+    syscalls: usize,
+
+    #[cfg(not(baremetal))]
+    start: std::time::Instant,
+}
+
+#[cfg(not(baremetal))]
+std::thread_local!(static IPC_TRACE: core::cell::RefCell<IpcTrace> = core::cell::RefCell::new(IpcTrace {
+    events: [None; IPC_TRACE_DEPTH],
+    next_seq: 0,
+    syscalls: 0,
+    start: std::time::Instant::now(),
+}));
+
+#[cfg(baremetal)]
+static mut IPC_TRACE: IpcTrace = IpcTrace {
+    events: [None; IPC_TRACE_DEPTH],
+    next_seq: 0,
+    syscalls: 0,
+};
+
+impl IpcTrace {
+    pub fn with<F, R>(f: F) -> R
+    where
+        F: FnOnce(&IpcTrace) -> R,
+    {
+        #[cfg(baremetal)]
+        unsafe {
+            f(&IPC_TRACE)
+        }
+
+        #[cfg(not(baremetal))]
+        IPC_TRACE.with(|trace| f(&trace.borrow()))
+    }
+
+    pub fn with_mut<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut IpcTrace) -> R,
+    {
+        #[cfg(baremetal)]
+        unsafe {
+            f(&mut IPC_TRACE)
+        }
+
+        #[cfg(not(baremetal))]
+        IPC_TRACE.with(|trace| f(&mut trace.borrow_mut()))
+    }
+
+    fn timestamp(&self) -> usize {
+        #[cfg(baremetal)]
+        return self.syscalls;
+
+        #[cfg(not(baremetal))]
+        return self.start.elapsed().as_micros() as usize;
+    }
+
+    /// Note that `pid`:`tid` is about to make `call`, and record it if it
+    /// is one of the IPC calls that are traced. Must be called before the
+    /// syscall runs, since a `Disconnect` removes the connection that
+    /// identifies the server.
+    pub fn record(&mut self, pid: PID, tid: TID, call: &SysCall) {
+        let timestamp = self.timestamp();
+        self.syscalls = self.syscalls.wrapping_add(1);
+        let mut event = match describe(pid, tid, call) {
+            Some(event) => event,
+            None => return,
+        };
+        event.seq = self.next_seq;
+        event.timestamp = timestamp;
+        self.events[self.next_seq % IPC_TRACE_DEPTH] = Some(event);
+        self.next_seq += 1;
+    }
+
+    /// The sequence number of the oldest event still in the trace
+    pub fn oldest(&self) -> usize {
+        self.next_seq.saturating_sub(IPC_TRACE_DEPTH)
+    }
+
+    /// Return event `seq`, or `None` if it was overwritten or hasn't
+    /// happened yet.
+    pub fn get(&self, seq: usize) -> Option<&IpcTraceEvent> {
+        if seq < self.oldest() || seq >= self.next_seq {
+            return None;
+        }
+        self.events[seq % IPC_TRACE_DEPTH].as_ref()
+    }
+
+    /// Iterate over the recorded events, oldest first.
+    #[cfg(baremetal)]
+    pub fn iter(&self) -> impl Iterator<Item = &IpcTraceEvent> {
+        (self.oldest()..self.next_seq).filter_map(move |seq| self.get(seq))
+    }
+}
+
+/// Work out what an IPC call refers to while the caller is still the
+/// current process, so that connection IDs can be resolved.
+fn describe(pid: PID, tid: TID, call: &SysCall) -> Option<IpcTraceEvent> {
+    let mut event = IpcTraceEvent {
+        seq: 0,
+        timestamp: 0,
+        kind: IpcTraceKind::SendMessage,
+        pid,
+        tid,
+        cid: 0,
+        sid: None,
+        peer: None,
+        opcode: 0,
+        len: 0,
+    };
+    SystemServices::with(|ss| {
+        match call {
+            SysCall::SendMessage(cid, message) | SysCall::TrySendMessage(cid, message) => {
+                let server = ss
+                    .sidx_from_cid(*cid)
+                    .and_then(|sidx| ss.server_from_sidx(sidx));
+                event.cid = *cid;
+                event.sid = server.map(|server| server.sid);
+                event.peer = server.map(|server| server.pid);
+                event.opcode = message.id();
+                event.len = message.memory().map(|buf| buf.len()).unwrap_or(0);
+            }
+            SysCall::ReceiveMessage(sid) | SysCall::TryReceiveMessage(sid) => {
+                event.kind = IpcTraceKind::ReceiveMessage;
+                event.sid = Some(*sid);
+            }
+            SysCall::ReturnMemory(sender, buf, _, _) => {
+                event.kind = IpcTraceKind::ReturnMemory;
+                event.sid = ss
+                    .server_from_sidx(SenderID::from(*sender).sidx)
+                    .map(|server| server.sid);
+                event.peer = sender.pid();
+                event.len = buf.len();
+            }
+            SysCall::ReturnScalar1(sender, _) | SysCall::ReturnScalar2(sender, _, _) => {
+                event.kind = IpcTraceKind::ReturnScalar;
+                event.sid = ss
+                    .server_from_sidx(SenderID::from(*sender).sidx)
+                    .map(|server| server.sid);
+                event.peer = sender.pid();
+            }
+            SysCall::Connect(sid)
+            | SysCall::TryConnect(sid)
+            | SysCall::ConnectForProcess(_, sid) => {
+                event.kind = IpcTraceKind::Connect;
+                event.sid = Some(*sid);
+                event.peer = ss.server_from_sid(*sid).map(|server| server.pid);
+            }
+            SysCall::Disconnect(cid) => {
+                let server = ss
+                    .sidx_from_cid(*cid)
+                    .and_then(|sidx| ss.server_from_sidx(sidx));
+                event.kind = IpcTraceKind::Disconnect;
+                event.cid = *cid;
+                event.sid = server.map(|server| server.sid);
+                event.peer = server.map(|server| server.pid);
+            }
+            _ => return None,
+        }
+        Some(event)
+    })
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::api::DnsResponseCode;
+
+/// The addresses each name resolved to, with the seconds left on each one's TTL.
+///
+/// Whether IPv6 hosts can be reached decides between looking up AAAA and A records,
+/// so the cache remembers which it was when its names were resolved, and starts over
+/// when that changes. Otherwise it would hand out IPv6-only answers that can no longer
+/// be connected to, or keep clients on IPv4 after IPv6 came up.
+pub(crate) struct DnsCache {
+    names: HashMap<String, HashMap<IpAddr, u32>>,
+    ipv6_connected: bool,
+}
+
+impl DnsCache {
+    pub(crate) fn new() -> DnsCache {
+        DnsCache {
+            names: HashMap::new(),
+            ipv6_connected: false,
+        }
+    }
+    /// The addresses `name` resolves to: from the cache if they're there, and otherwise
+    /// from `resolve`, which is told whether IPv6 hosts can be reached.
+    pub(crate) fn lookup<F>(
+        &mut self,
+        name: &str,
+        ipv6_connected: bool,
+        resolve: F,
+    ) -> Result<&HashMap<IpAddr, u32>, DnsResponseCode>
+    where
+        F: FnOnce(&str, bool) -> Result<HashMap<IpAddr, u32>, DnsResponseCode>,
+    {
+        if ipv6_connected != self.ipv6_connected {
+            if !self.names.is_empty() {
+                log::info!("IPv6 connectivity changed, flushing the DNS cache");
+            }
+            self.names.clear();
+            self.ipv6_connected = ipv6_connected;
+        }
+        if !self.names.contains_key(name) {
+            let addrs = resolve(name, ipv6_connected)?;
+            if addrs.is_empty() {
+                return Err(DnsResponseCode::NameError);
+            }
+            self.names.insert(String::from(name), addrs);
+        }
+        Ok(&self.names[name])
+    }
+    pub(crate) fn flush(&mut self) {
+        self.names.clear();
+    }
+    /// Take `secs` off every TTL, forgetting the addresses whose TTL runs out, and the
+    /// names that have no addresses left
+    pub(crate) fn age(&mut self, secs: u32) {
+        for (name, addrs) in self.names.iter_mut() {
+            addrs.retain(|addr, ttl| {
+                log::debug!("entry: {:?}, ttl: {}, incr: {}", addr, ttl, secs);
+                if *ttl < secs {
+                    log::debug!("DNS cache expiring {:?} for {}", addr, name);
+                    false
+                } else {
+                    *ttl -= secs;
+                    true
+                }
+            });
+        }
+        self.names.retain(|name, addrs| {
+            if addrs.is_empty() {
+                log::debug!("DNS cache removing {}", name);
+            }
+            !addrs.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const V4: IpAddr = IpAddr::V4(Ipv4Addr::new(185, 199, 108, 153));
+    const V6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2606, 0x50c0, 0x8000, 0, 0, 0, 0, 0x153));
+
+    /// Answers with the AAAA record when IPv6 hosts can be reached, and the A record
+    /// when they can't, counting the queries
+    fn resolver(
+        queries: &mut usize,
+    ) -> impl FnOnce(&str, bool) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> + '_ {
+        move |_, ipv6_connected| {
+            *queries += 1;
+            let addr = if ipv6_connected { V6 } else { V4 };
+            Ok([(addr, 300)].iter().cloned().collect())
+        }
+    }
+
+    fn addrs(answer: Result<&HashMap<IpAddr, u32>, DnsResponseCode>) -> Vec<IpAddr> {
+        answer.unwrap().keys().cloned().collect()
+    }
+
+    #[test]
+    fn answers_are_cached() {
+        let mut cache = DnsCache::new();
+        let mut queries = 0;
+        assert_eq!(
+            addrs(cache.lookup("betrusted.io", false, resolver(&mut queries))),
+            [V4]
+        );
+        assert_eq!(
+            addrs(cache.lookup("betrusted.io", false, resolver(&mut queries))),
+            [V4]
+        );
+        assert_eq!(queries, 1);
+
+        cache.flush();
+        assert_eq!(
+            addrs(cache.lookup("betrusted.io", false, resolver(&mut queries))),
+            [V4]
+        );
+        assert_eq!(queries, 2);
+    }
+
+    #[test]
+    fn connectivity_changes_flush_the_cache() {
+        let mut cache = DnsCache::new();
+        let mut queries = 0;
+        assert_eq!(
+            addrs(cache.lookup("betrusted.io", true, resolver(&mut queries))),
+            [V6]
+        );
+        assert_eq!(queries, 1);
+
+        // IPv6 went away: the AAAA answer can't be used any more
+        assert_eq!(
+            addrs(cache.lookup("betrusted.io", false, resolver(&mut queries))),
+            [V4]
+        );
+        assert_eq!(queries, 2);
+        assert_eq!(
+            addrs(cache.lookup("betrusted.io", false, resolver(&mut queries))),
+            [V4]
+        );
+        assert_eq!(queries, 2);
+
+        // and when it comes back, clients move off IPv4 again
+        assert_eq!(
+            addrs(cache.lookup("betrusted.io", true, resolver(&mut queries))),
+            [V6]
+        );
+        assert_eq!(queries, 3);
+    }
+
+    #[test]
+    fn failures_are_not_cached() {
+        let mut cache = DnsCache::new();
+        let mut queries = 0;
+        let answer = cache.lookup("nonexistent.invalid", false, |_, _| {
+            queries += 1;
+            Ok(HashMap::new())
+        });
+        assert!(matches!(answer, Err(DnsResponseCode::NameError)));
+        let answer = cache.lookup("nonexistent.invalid", false, |_, _| {
+            queries += 1;
+            Err(DnsResponseCode::NetworkError)
+        });
+        assert!(matches!(answer, Err(DnsResponseCode::NetworkError)));
+        assert_eq!(queries, 2);
+    }
+
+    #[test]
+    fn entries_expire_with_their_ttl() {
+        let mut cache = DnsCache::new();
+        let mut queries = 0;
+        cache
+            .lookup("betrusted.io", false, |_, _| {
+                Ok([(V4, 100), (V6, 400)].iter().cloned().collect())
+            })
+            .unwrap();
+
+        cache.age(300);
+        let answer = cache
+            .lookup("betrusted.io", false, resolver(&mut queries))
+            .unwrap();
+        assert_eq!(answer.get(&V6), Some(&100));
+        assert_eq!(answer.len(), 1);
+        assert_eq!(queries, 0);
+
+        cache.age(200);
+        assert_eq!(
+            addrs(cache.lookup("betrusted.io", false, resolver(&mut queries))),
+            [V4]
+        );
+        assert_eq!(queries, 1);
+    }
+}
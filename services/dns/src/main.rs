@@ -3,6 +3,7 @@
 
 mod api;
 use api::*;
+mod cache;
 
 use net::{NetIpAddr, Duration};
 use num_traits::*;
@@ -31,6 +32,7 @@ enum QueryType {
     // SOA = 6,
     // MX = 15,
     // TXT = 16,
+    AAAA = 28,
 }
 
 #[repr(u16)]
@@ -341,13 +343,28 @@ impl Resolver {
     pub fn trng_u32(&self) -> u32 {
         self.trng.get_u32().unwrap()
     }
-    pub fn resolve(&mut self, name: &str) -> Result<HashMap::<IpAddr, u32>, DnsResponseCode> {
+    /// True if the Net server says we can reach IPv6 hosts
+    pub fn ipv6_connected(&self) -> bool {
+        self.mgr.ipv6_connected()
+    }
+    /// Looks up the AAAA records of a name when we can reach IPv6 hosts, and falls back to
+    /// its A records if there are none, or if we can't.
+    pub fn resolve(&mut self, name: &str, ipv6_connected: bool) -> Result<HashMap::<IpAddr, u32>, DnsResponseCode> {
+        if ipv6_connected {
+            match self.query(name, QueryType::AAAA) {
+                Ok(map) if map.len() > 0 => return Ok(map),
+                Ok(_) => log::debug!("no AAAA records for {}, trying A", name),
+                Err(e) => log::debug!("AAAA query for {} failed ({:?}), trying A", name, e),
+            }
+        }
+        self.query(name, QueryType::A)
+    }
+    fn query(&mut self, name: &str, qtype: QueryType) -> Result<HashMap::<IpAddr, u32>, DnsResponseCode> {
         if let Some(dns_address) = self.mgr.get_random() {
             let dns_port = 53;
             let server = SocketAddr::new(dns_address, dns_port);
 
             let qname = name;
-            let qtype = QueryType::A;
             let qclass = QueryClass::IN;
             let query = Message::query(qname, qtype, qclass, self.trng.get_u32().unwrap() as u16);
 
@@ -403,7 +420,7 @@ fn xmain() -> ! {
     // if you wanted to force a server into the initial config, you can do it here, for example:
     // resolver.add_server(IpAddr::V4(Ipv4Addr::new(1,1,1,1)));
 
-    let mut dns_cache = HashMap::<std::string::String, HashMap::<IpAddr, u32>>::new();
+    let mut dns_cache = cache::DnsCache::new();
 
     // build a thread that pings the UpdateTtl function once every few minutes to expire the DNS cache
     thread::spawn({
@@ -428,62 +445,27 @@ fn xmain() -> ! {
             Some(Opcode::Lookup) => {
                 let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
                 let name = buf.to_original::<String::<DNS_NAME_LENGTH_LIMIT>, _>().unwrap();
-                let name_std = std::string::String::from(name.as_str().unwrap());
-                if let Some(cache_entry) = dns_cache.get(&name_std) {
-                    // pick a random entry
-                    let rand = resolver.trng_u32() as usize % cache_entry.len();
-                    for (index, (ip_addr, _)) in cache_entry.iter().enumerate() {
-                        if rand == index {
-                            log::debug!("DNS cached: {}->{:?}", name, ip_addr);
-                            let response = DnsResponse {
-                                addr: Some(NetIpAddr::from(*ip_addr)),
-                                code: DnsResponseCode::NoError,
-                            };
-                            buf.replace(response).unwrap();
-                            break;
+                let ipv6_connected = resolver.ipv6_connected();
+                let response = match dns_cache.lookup(name.as_str().unwrap(), ipv6_connected, |name, ipv6_connected| resolver.resolve(name, ipv6_connected)) {
+                    Ok(cache_entry) => {
+                        // pick a random entry
+                        let rand = resolver.trng_u32() as usize % cache_entry.len();
+                        let ip_addr = cache_entry.keys().nth(rand).unwrap();
+                        log::debug!("DNS: {}->{:?}", name, ip_addr);
+                        DnsResponse {
+                            addr: Some(NetIpAddr::from(*ip_addr)),
+                            code: DnsResponseCode::NoError,
                         }
-                    }
-                } else {
-                    match resolver.resolve(name.as_str().unwrap()) {
-                        Ok(cache_entry) => {
-                            if cache_entry.len() > 0 {
-                                dns_cache.insert(name_std, cache_entry);
-
-                                // now pick the entry back out again, as it was consumed...
-                                let name_std = std::string::String::from(name.as_str().unwrap());
-                                let cache_entry = dns_cache.get(&name_std).unwrap();
-
-                                // pick a random entry from the query response
-                                let rand = resolver.trng_u32() as usize % cache_entry.len();
-                                for (index, (ip_addr, _)) in cache_entry.iter().enumerate() {
-                                    if rand == index {
-                                        let response = DnsResponse {
-                                            addr: Some(NetIpAddr::from(*ip_addr)),
-                                            code: DnsResponseCode::NoError,
-                                        };
-                                        buf.replace(response).unwrap();
-                                        break;
-                                    }
-                                }
-                            } else {
-                                // no names found
-                                let response = DnsResponse {
-                                    addr: None,
-                                    code: DnsResponseCode::NameError,
-                                };
-                                buf.replace(response).unwrap();
-                            }
-                        },
-                        Err(e) => {
-                            log::debug!("DNS query failed: {}->{:?}", name, e);
-                            let response = DnsResponse {
-                                addr: None,
-                                code: e,
-                            };
-                            buf.replace(response).unwrap();
-                        },
-                    }
-                }
+                    },
+                    Err(e) => {
+                        log::debug!("DNS query failed: {}->{:?}", name, e);
+                        DnsResponse {
+                            addr: None,
+                            code: e,
+                        }
+                    },
+                };
+                buf.replace(response).unwrap();
             },
             Some(Opcode::UpdateTtl) => msg_scalar_unpack!(msg, incr_secs, _, _, _, {
                 let increment = if incr_secs < u32::MAX as usize {
@@ -492,40 +474,11 @@ fn xmain() -> ! {
                     u32::MAX
                 };
                 if !resolver.get_freeze() {
-                    let mut expired_names = Vec::<std::string::String>::new();
-                    for (name, cache_map) in dns_cache.iter_mut() {
-                        // each entry can have multiple names with a different TTL
-                        // decrement the TTL, and note which go to zero
-                        let mut expired_entries = Vec::<IpAddr>::new();
-                        for (entry, ttl) in cache_map.iter_mut() {
-                            log::debug!("entry: {:?}, ttl: {}, incr: {}", entry, ttl, increment);
-                            if *ttl < increment {
-                                *ttl = 0;
-                                expired_entries.push(*entry);
-                            } else {
-                                *ttl = *ttl - increment as u32;
-                            }
-                        }
-                        // remove the entries that are 0
-                        for entry in expired_entries {
-                            log::debug!("DNS cache expiring {:?}", entry);
-                            cache_map.remove(&entry);
-                        }
-                        // if all the entries are removed, mark for removal from the cache entirely
-                        if cache_map.len() == 0 {
-                            // have to copy the name to a new object to track it
-                            let name = std::string::String::from(name.as_str());
-                            expired_names.push(name);
-                        }
-                    }
-                    for name in expired_names {
-                        log::debug!("DNS cache removing {}", &name);
-                        dns_cache.remove(&name);
-                    }
+                    dns_cache.age(increment);
                 }
             }),
             Some(Opcode::Flush) => {
-                dns_cache.clear();
+                dns_cache.flush();
             },
             Some(Opcode::FreezeConfig) => {
                 resolver.set_freeze_config(true);
@@ -0,0 +1,105 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+//! Pings, echoes UDP and resolves names through the Net and DNS servers, with `net-peer`
+//! standing in for the rest of the network. `tools/net_hosted_test.sh` runs it in a hosted
+//! Xous, and looks for the verdict it logs.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use dns::api::DnsResponseCode;
+use net::Duration;
+
+/// `net-peer` is the gateway, and echoes UDP sent to this port of any address
+const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+const ECHO_PORT: u16 = 7;
+const LOCAL_PORT: u16 = 7007;
+/// The name that `tools/net_hosted_test.sh` tells `net-peer` about
+const TEST_HOST: &str = "betrusted.io";
+const TEST_HOST_ADDR: Ipv4Addr = Ipv4Addr::new(185, 199, 108, 153);
+/// The servers that are needed take a moment to come up, so early failures are tried again
+const ATTEMPTS: usize = 10;
+const RETRY_MS: usize = 1000;
+const UDP_TIMEOUT_MS: u64 = 5000;
+
+fn ping(tt: &ticktimer_server::Ticktimer) -> Result<(), String> {
+    for _ in 0..ATTEMPTS {
+        let (reachable, ms) = net::Ping::blocking(IpAddr::V4(GATEWAY));
+        if reachable {
+            log::info!("net-test: ping to {} answered in {} ms", GATEWAY, ms);
+            return Ok(());
+        }
+        tt.sleep_ms(RETRY_MS).unwrap();
+    }
+    Err(format!("no answer to pings to {}", GATEWAY))
+}
+
+fn udp_echo() -> Result<(), String> {
+    let mut socket =
+        net::UdpSocket::bind_xous(SocketAddr::from((Ipv4Addr::UNSPECIFIED, LOCAL_PORT)), None)
+            .map_err(|e| format!("couldn't bind UDP port {}: {:?}", LOCAL_PORT, e))?;
+    socket
+        .set_read_timeout(Some(Duration::from_millis(UDP_TIMEOUT_MS)))
+        .unwrap();
+    let echo = SocketAddr::from((GATEWAY, ECHO_PORT));
+    let sent = b"net-test echo";
+    socket
+        .send_to(sent, &echo)
+        .map_err(|e| format!("couldn't send UDP to {}: {:?}", echo, e))?;
+    let mut received = [0u8; 64];
+    let (len, from) = socket
+        .recv_from(&mut received)
+        .map_err(|e| format!("no UDP echo from {}: {:?}", echo, e))?;
+    if from != echo || &received[..len] != sent {
+        return Err(format!("UDP echo from {} was {:?}", from, &received[..len]));
+    }
+    log::info!("net-test: UDP echoed by {}", echo);
+    Ok(())
+}
+
+fn resolve(xns: &xous_names::XousNames, tt: &ticktimer_server::Ticktimer) -> Result<(), String> {
+    let dns = dns::Dns::new(xns).unwrap();
+    let mut attempts = 0;
+    let addr = loop {
+        match dns.lookup(TEST_HOST) {
+            // the DNS server may not have heard which server to ask yet
+            Err(DnsResponseCode::NoServerSpecified) if attempts < ATTEMPTS => {
+                attempts += 1;
+                tt.sleep_ms(RETRY_MS).unwrap();
+            }
+            Ok(addr) => break IpAddr::from(addr),
+            Err(code) => return Err(format!("couldn't resolve {}: {:?}", TEST_HOST, code)),
+        }
+    };
+    if addr != IpAddr::V4(TEST_HOST_ADDR) {
+        return Err(format!(
+            "{} resolved to {}, not {}",
+            TEST_HOST, addr, TEST_HOST_ADDR
+        ));
+    }
+    match dns.lookup("nonexistent.invalid") {
+        Err(DnsResponseCode::NameError) => (),
+        Err(code) => return Err(format!("unknown name wasn't reported as such: {:?}", code)),
+        Ok(addr) => return Err(format!("unknown name resolved to {}", IpAddr::from(addr))),
+    }
+    log::info!("net-test: {} resolved to {}", TEST_HOST, addr);
+    Ok(())
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    log::info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    match ping(&tt)
+        .and_then(|_| udp_echo())
+        .and_then(|_| resolve(&xns, &tt))
+    {
+        Ok(()) => log::info!("net-test: PASS"),
+        Err(e) => log::error!("net-test: FAIL: {}", e),
+    }
+    xous::terminate_process(0)
+}
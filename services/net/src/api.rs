@@ -2,18 +2,19 @@ pub(crate) mod udp;
 pub(crate) use udp::*;
 pub(crate) mod ping;
 pub(crate) use ping::*;
+pub(crate) mod tcp;
+pub(crate) use tcp::*;
 pub use ping::NetPingCallback;
 
 use rkyv::{Archive, Deserialize, Serialize};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use smoltcp::wire::IpAddress;
-use std::convert::TryInto;
 use std::fmt;
 use std::fmt::Debug;
 use std::io::Write;
 
-// republish this so we can decode the icmpv4 error codes
-pub use smoltcp::wire::Icmpv4DstUnreachable;
+// republish these so we can decode the icmpv4 and icmpv6 error codes
+pub use smoltcp::wire::{Icmpv4DstUnreachable, Icmpv6DstUnreachable};
 
 pub(crate) const SERVER_NAME_NET: &str     = "_Middleware Network Server_";
 
@@ -26,6 +27,23 @@ pub(crate) enum Opcode {
     UdpTx,
     UdpSetTtl,
     UdpGetTtl,
+    /// Set or get one of the `NetUdpOption`s
+    UdpSetOption,
+    UdpGetOption,
+    UdpJoinMulticastV4,
+    UdpLeaveMulticastV4,
+    UdpJoinMulticastV6,
+    UdpLeaveMulticastV6,
+
+    /// Calls for TCP implementation
+    TcpConnect,
+    TcpTx,
+    TcpRx,
+    TcpShutdown,
+    TcpClose,
+    TcpListen,
+    TcpAccept,
+    TcpListenClose,
 
     // The DNS server can hook the Net crate for notifications on config updates
     /// Adds an Ipv4 as a DNS server
@@ -34,6 +52,8 @@ pub(crate) enum Opcode {
     DnsHookAddIpv6,
     /// Called on IP config update -- clears all DNS servers.
     DnsHookAllClear,
+    /// Hooks notifications of whether IPv6 hosts beyond the local link can be reached (arg1 = 1) or not (arg1 = 0)
+    DnsHookIpv6Connectivity,
     DnsUnhookAll,
 
     /// Ping stack
@@ -43,6 +63,16 @@ pub(crate) enum Opcode {
     PingSetTimeout,
     PingGetTimeout,
 
+    /// Packet capture: claim the token the other Pcap calls need, passed in arg1-4. Only the first
+    /// claim after boot succeeds; returns 1 if it did.
+    PcapClaim,
+    /// Start teeing frames into a ring of `NetPcap.limit` bytes (0 for the default); the ring size is returned there
+    PcapStart,
+    /// Stop capturing, keeping what was captured
+    PcapStop,
+    /// Write the capture to the log as pcap
+    PcapDump,
+
     /// [Internal] com llio interrupt callback
     ComInterrupt,
     /// [Internal] run the network stack code
@@ -71,6 +101,7 @@ pub(crate) enum PrivateDnsOp {
     RemoveIpv4DnsServer,
     RemoveIpv6DnsServer,
     RemoveAllServers,
+    Ipv6Connectivity,
     Quit,
 }
 
@@ -80,6 +111,20 @@ pub(crate) enum NetCallback {
     Drop,
 }
 
+/// Lent with every packet capture call but the claim. Frames can hold anything the device
+/// sends or receives, so only the holder of the claimed token gets at them.
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct NetPcap {
+    pub token: [u32; 4],
+    pub limit: u32,
+    /// filled in by the Net server: frames in the capture ring
+    pub frames: u32,
+    /// filled in by the Net server: older frames that were pushed out of the ring to make room
+    pub overwritten: u32,
+    /// filled in by the Net server: false if `token` wasn't the claimed one
+    pub granted: bool,
+}
+
 #[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
 pub(crate) enum NetMemResponse {
     Ok,
@@ -170,17 +215,7 @@ impl From<NetIpAddr> for IpAddress {
                 IpAddress::Ipv4(smoltcp::wire::Ipv4Address::new(a, b, c, d))
             }
             NetIpAddr::Ipv6(ipv6) => {
-                    IpAddress::Ipv6(smoltcp::wire::Ipv6Address::new(
-                        u16::from_be_bytes(ipv6[0..1].try_into().unwrap()),
-                        u16::from_be_bytes(ipv6[2..3].try_into().unwrap()),
-                        u16::from_be_bytes(ipv6[4..5].try_into().unwrap()),
-                        u16::from_be_bytes(ipv6[6..7].try_into().unwrap()),
-                        u16::from_be_bytes(ipv6[8..9].try_into().unwrap()),
-                        u16::from_be_bytes(ipv6[10..11].try_into().unwrap()),
-                        u16::from_be_bytes(ipv6[12..13].try_into().unwrap()),
-                        u16::from_be_bytes(ipv6[14..15].try_into().unwrap()),
-                    )
-                )
+                IpAddress::Ipv6(smoltcp::wire::Ipv6Address::from_bytes(&ipv6))
             }
         }
     }
@@ -221,7 +256,7 @@ impl fmt::Display for NetIpAddr {
                     fmt.pad(buf)
                 }
             },
-            NetIpAddr::Ipv6(ip) => ip.fmt(fmt),
+            NetIpAddr::Ipv6(octets) => fmt::Display::fmt(&Ipv6Addr::from(*octets), fmt),
         }
     }
 }
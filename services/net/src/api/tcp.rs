@@ -0,0 +1,94 @@
+use rkyv::{Archive, Deserialize, Serialize};
+use crate::api::*;
+
+//////// Intra-crate TCP structures
+/// Unlike UDP, a TCP stream has no packet boundaries to preserve, so each
+/// read or write moves as much as fits in the page of memory that is lent
+/// to the Net server anyways, with a bit of room left for the other fields.
+pub(crate) const TCP_CHUNK_LEN: usize = 4000;
+
+/// Outcome of a TCP request, filled in by the Net server
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, PartialEq)]
+pub(crate) enum NetTcpResult {
+    Ok,
+    /// Nothing could be moved right now. The Net server will send a callback
+    /// once the socket is ready.
+    WouldBlock,
+    /// No more data can move in this direction
+    Closed,
+    /// The network isn't up yet
+    NotConnected,
+    AddrInUse,
+    Invalid,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct NetTcpConnect {
+    pub cb_sid: [u32; 4],
+    pub remote: NetSocketAddr,
+    /// filled in by the Net server: the handle of the new stream
+    pub id: Option<u32>,
+    /// filled in by the Net server: the address the stream is sent from
+    pub local: Option<NetSocketAddr>,
+    pub result: Option<NetTcpResult>,
+}
+
+/// Used for both reads and writes. `id` names the stream, as several streams
+/// may share a local port.
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct NetTcpTransfer {
+    /// the SID of the stream's callback server, which proves the caller owns the stream
+    pub cb_sid: [u32; 4],
+    pub id: u32,
+    /// the most that may be moved; replaced by the amount that was
+    pub len: u16,
+    /// leave the data in the socket when reading it
+    pub peek: bool,
+    pub result: Option<NetTcpResult>,
+    pub data: [u8; TCP_CHUNK_LEN],
+}
+
+/// Names a stream for the requests that don't move any data: shutting down
+/// writes, and closing the stream.
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct NetTcpStream {
+    /// the SID of the stream's callback server, which proves the caller owns the stream
+    pub cb_sid: [u32; 4],
+    pub id: u32,
+    pub result: Option<NetTcpResult>,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct NetTcpListen {
+    pub cb_sid: [u32; 4],
+    /// a port of 0 is replaced with the port the Net server picked
+    pub local: NetSocketAddr,
+    pub result: Option<NetTcpResult>,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct NetTcpAccept {
+    /// the SID of the listener, which proves the caller owns the port
+    pub listener_sid: [u32; 4],
+    pub port: u16,
+    /// the callback server for the accepted stream
+    pub cb_sid: [u32; 4],
+    /// filled in by the Net server
+    pub id: Option<u32>,
+    /// filled in by the Net server
+    pub remote: Option<NetSocketAddr>,
+    pub result: Option<NetTcpResult>,
+}
+
+/// Scalars the Net server sends to a TCP socket's callback server. They are
+/// only sent to wake up a caller that was told `NetTcpResult::WouldBlock`.
+#[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub(crate) enum NetTcpCallback {
+    /// arg1 is 1 if the connection was made, and 0 if it was refused
+    Connected,
+    RxReady,
+    TxReady,
+    /// a listener has a connection waiting to be accepted
+    Incoming,
+    Drop,
+}
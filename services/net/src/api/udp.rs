@@ -45,6 +45,25 @@ pub(crate) struct NetUdpBind {
     pub(crate) max_payload: Option<u16>, // defaults to MTU if not specified
 }
 
+/// Options that are stored per port by the Net server, and shared by all clones
+/// of a socket. Values are passed as a scalar argument, with booleans as 0 or 1.
+#[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub(crate) enum NetUdpOption {
+    Broadcast,
+    MulticastTtlV4,
+    MulticastLoopV4,
+    MulticastLoopV6,
+}
+
+/// Joins or leaves an IPv6 multicast group, which is too big to go in a scalar with the port
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct NetUdpMulticastV6 {
+    pub(crate) port: u16,
+    pub(crate) group: [u8; 16],
+    /// filled in by the Net server
+    pub(crate) result: Option<bool>,
+}
+
 #[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub(crate) enum NetUdpCallback {
     RxData,
@@ -1,98 +1,334 @@
-use com::Com;
-use com::api::NET_MTU;
+#[cfg(any(target_os = "none", target_os = "xous"))]
+mod implementation {
+    use com::Com;
+    use com::api::{Ipv4Conf, NET_MTU};
 
-use smoltcp::Result;
-use smoltcp::phy::{self, DeviceCapabilities, Medium};
+    use smoltcp::Result;
+    use smoltcp::phy::{self, DeviceCapabilities, Medium};
 
-use smoltcp::{
-    time::Instant,
-};
+    use smoltcp::{
+        time::Instant,
+    };
 
-pub struct NetPhy {
-    rx_buffer: [u8; NET_MTU],
-    tx_buffer: [u8; NET_MTU],
-    com: Com,
-    rx_avail: Option<u16>,
-}
+    use std::cell::RefCell;
 
-impl<'a> NetPhy {
-    pub fn new(xns: &xous_names::XousNames) -> NetPhy {
-        NetPhy {
-            rx_buffer: [0; NET_MTU],
-            tx_buffer: [0; NET_MTU],
-            com: Com::new(&xns).unwrap(),
-            rx_avail: None,
-        }
-    }
-    // returns None if there was a slot to put the availability into
-    // returns Some(len) if not
-    pub fn push_rx_avail(&mut self, len: u16) -> Option<u16> {
-        if self.rx_avail.is_none() {
-            self.rx_avail = Some(len);
-            None
-        } else {
-            Some(len)
+    use crate::pcap::{Capture, SharedCapture};
+
+    pub struct NetPhy {
+        rx_buffer: [u8; NET_MTU],
+        tx_buffer: [u8; NET_MTU],
+        com: Com,
+        rx_avail: Option<u16>,
+        capture: SharedCapture,
+    }
+
+    impl<'a> NetPhy {
+        pub fn new(xns: &xous_names::XousNames, capture: SharedCapture) -> NetPhy {
+            NetPhy {
+                rx_buffer: [0; NET_MTU],
+                tx_buffer: [0; NET_MTU],
+                com: Com::new(&xns).unwrap(),
+                rx_avail: None,
+                capture,
+            }
+        }
+        // returns None if there was a slot to put the availability into
+        // returns Some(len) if not
+        pub fn push_rx_avail(&mut self, len: u16) -> Option<u16> {
+            if self.rx_avail.is_none() {
+                self.rx_avail = Some(len);
+                None
+            } else {
+                Some(len)
+            }
         }
     }
-}
 
-impl<'a> phy::Device<'a> for NetPhy {
-    type RxToken = NetPhyRxToken<'a>;
-    type TxToken = NetPhyTxToken<'a>;
+    impl<'a> phy::Device<'a> for NetPhy {
+        type RxToken = NetPhyRxToken<'a>;
+        type TxToken = NetPhyTxToken<'a>;
 
-    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        if let Some(rx_len) = self.rx_avail.take() {
-            self.com.wlan_fetch_packet(&mut self.rx_buffer[..rx_len as usize]).expect("Couldn't call wlan_fetch_packet in device adapter");
+        fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+            if let Some(rx_len) = self.rx_avail.take() {
+                self.com.wlan_fetch_packet(&mut self.rx_buffer[..rx_len as usize]).expect("Couldn't call wlan_fetch_packet in device adapter");
+
+                Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize], capture: &self.capture},
+                NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com, capture: &self.capture}))
+            } else {
+                None
+            }
+        }
 
-            Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize]},
-            NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com}))
-        } else {
-            None
+        fn transmit(&'a mut self) -> Option<Self::TxToken> {
+            Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com, capture: &self.capture})
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            let mut caps = DeviceCapabilities::default();
+            caps.max_transmission_unit = NET_MTU;
+            caps.max_burst_size = Some(1);
+            caps.medium = Medium::Ethernet;
+            caps
         }
     }
 
-    fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com})
+    pub struct NetPhyRxToken<'a> {
+        buf: &'a mut [u8],
+        capture: &'a RefCell<Capture>,
     }
 
-    fn capabilities(&self) -> DeviceCapabilities {
-        let mut caps = DeviceCapabilities::default();
-        caps.max_transmission_unit = NET_MTU;
-        caps.max_burst_size = Some(1);
-        caps.medium = Medium::Ethernet;
-        caps
+    impl<'a, 'c> phy::RxToken for NetPhyRxToken<'a> {
+        fn consume<R, F>(mut self, timestamp: Instant, f: F) -> Result<R>
+            where F: FnOnce(&mut [u8]) -> Result<R>
+        {
+            self.capture.borrow_mut().record(timestamp, self.buf);
+            let result = f(&mut self.buf);
+            //log::info!("rx: {:x?}", self.buf);
+            result
+        }
     }
-}
 
-pub struct NetPhyRxToken<'a> {
-    buf: &'a mut [u8],
-}
+    pub struct NetPhyTxToken<'a> {
+        buf: &'a mut [u8],
+        com: &'a Com,
+        capture: &'a RefCell<Capture>,
+    }
 
-impl<'a, 'c> phy::RxToken for NetPhyRxToken<'a> {
-    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> Result<R>
-        where F: FnOnce(&mut [u8]) -> Result<R>
-    {
-        let result = f(&mut self.buf);
-        //log::info!("rx: {:x?}", self.buf);
-        result
+    impl<'a> phy::TxToken for NetPhyTxToken<'a> {
+        fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
+            where F: FnOnce(&mut [u8]) -> Result<R>
+        {
+            let result = f(&mut self.buf[..len]);
+            //log::info!("txlen: {}", len);
+
+            if result.is_ok() {
+                self.capture.borrow_mut().record(timestamp, &self.buf[..len]);
+                self.com.wlan_send_packet(&self.buf[..len]).map_err(|_| smoltcp::Error::Dropped)?;
+            }
+            result
+        }
     }
-}
 
-pub struct NetPhyTxToken<'a> {
-    buf: &'a mut [u8],
-    com: &'a Com,
+    /// Only hosted mode makes up its own IP config; on hardware it comes from the EC.
+    pub fn hosted_config() -> Option<Ipv4Conf> {
+        None
+    }
 }
 
-impl<'a> phy::TxToken for NetPhyTxToken<'a> {
-    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> Result<R>
-        where F: FnOnce(&mut [u8]) -> Result<R>
-    {
-        let result = f(&mut self.buf[..len]);
-        //log::info!("txlen: {}", len);
+/// Hosted mode has no WF200, so frames are exchanged with a stand-in for the rest of the
+/// network instead, such as the `net-peer` tool. Each Ethernet frame travels as one UDP
+/// datagram between `XOUS_NET_BIND` (any local port by default) and `XOUS_NET_PEER`. If
+/// no peer is given, the link stays down and every frame is dropped.
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+mod implementation {
+    use com::api::{Ipv4Conf, NET_MTU};
+    use num_traits::ToPrimitive;
+
+    use smoltcp::Result;
+    use smoltcp::phy::{self, DeviceCapabilities, Medium};
+
+    use smoltcp::{
+        time::Instant,
+    };
+
+    use std::cell::RefCell;
+
+    use crate::pcap::{Capture, SharedCapture};
+
+    use std::collections::VecDeque;
+    use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use crate::api::{Opcode, SERVER_NAME_NET};
+
+    /// Frames that can wait for the interface before more are dropped
+    const RX_QUEUE_LEN: usize = 16;
+    /// a locally administered address, the same one QEMU gives its first NIC
+    const HOSTED_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+    struct Link {
+        socket: UdpSocket,
+        peer: SocketAddr,
+        rx_queue: Mutex<VecDeque<Vec<u8>>>,
+    }
+
+    thread_local! {
+        // the interface is rebuilt with a new NetPhy whenever the IP config changes, but
+        // they all share the one link, which lasts as long as the Net server
+        static LINK: RefCell<Option<Arc<Link>>> = RefCell::new(None);
+    }
+
+    fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+        let value = std::env::var(name).ok()?;
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                log::error!("ignoring {}, as {} isn't valid", name, value);
+                None
+            }
+        }
+    }
+
+    fn open_link() -> Option<Arc<Link>> {
+        let peer: SocketAddr = env_parse("XOUS_NET_PEER")?;
+        let bind: SocketAddr = env_parse("XOUS_NET_BIND").unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+        let socket = match UdpSocket::bind(bind) {
+            Ok(socket) => socket,
+            Err(e) => {
+                log::error!("couldn't bind the hosted network link to {}: {:?}", bind, e);
+                return None;
+            }
+        };
+        log::info!("hosted network link from {:?} to {}", socket.local_addr(), peer);
+        let link = Arc::new(Link {
+            socket,
+            peer,
+            rx_queue: Mutex::new(VecDeque::new()),
+        });
+        thread::spawn({
+            let link = Arc::clone(&link);
+            move || receive_frames(link)
+        });
+        Some(link)
+    }
+
+    /// Queue up the frames that the peer sends, and wake the Net server to take them,
+    /// which is what the WlanRxReady interrupt does on hardware.
+    fn receive_frames(link: Arc<Link>) {
+        let xns = xous_names::XousNames::new().unwrap();
+        let net_conn = xns.request_connection_blocking(SERVER_NAME_NET).expect("can't connect to Net server");
+        let mut frame = [0u8; NET_MTU];
+        loop {
+            match link.socket.recv_from(&mut frame) {
+                Ok((len, from)) if from == link.peer => {
+                    {
+                        let mut rx_queue = link.rx_queue.lock().unwrap();
+                        if rx_queue.len() >= RX_QUEUE_LEN {
+                            log::warn!("hosted network link is backed up, dropping a frame");
+                            continue;
+                        }
+                        rx_queue.push_back(frame[..len].to_vec());
+                    }
+                    xous::try_send_message(net_conn,
+                        xous::Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0)
+                    ).ok();
+                }
+                Ok((_, from)) => log::warn!("ignoring a frame from {}, which isn't our peer", from),
+                Err(e) => {
+                    log::error!("hosted network link failed: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
 
-        if result.is_ok() {
-            self.com.wlan_send_packet(&self.buf[..len]).map_err(|_| smoltcp::Error::Dropped)?;
+    /// A stand-in for the config that DHCP on the EC would hand us, for when there is a
+    /// link to a peer. It defaults to the addresses of QEMU's user networking, which are
+    /// also what `net-peer` answers to, and can be changed with `XOUS_NET_ADDR`,
+    /// `XOUS_NET_GATEWAY` and `XOUS_NET_DNS`.
+    pub fn hosted_config() -> Option<Ipv4Conf> {
+        if !LINK.with(|link| link.borrow().is_some()) {
+            return None;
         }
-        result
+        let mut config = Ipv4Conf::default();
+        config.mac = HOSTED_MAC;
+        config.addr = env_parse("XOUS_NET_ADDR").unwrap_or(Ipv4Addr::new(10, 0, 2, 15)).octets();
+        config.gtwy = env_parse("XOUS_NET_GATEWAY").unwrap_or(Ipv4Addr::new(10, 0, 2, 2)).octets();
+        config.dns1 = env_parse("XOUS_NET_DNS").unwrap_or(Ipv4Addr::new(10, 0, 2, 3)).octets();
+        config.dns2 = [0; 4];
+        Some(config)
     }
-}
\ No newline at end of file
+
+    pub struct NetPhy {
+        rx_buffer: [u8; NET_MTU],
+        tx_buffer: [u8; NET_MTU],
+        link: Option<Arc<Link>>,
+        capture: SharedCapture,
+    }
+
+    impl<'a> NetPhy {
+        pub fn new(_xns: &xous_names::XousNames, capture: SharedCapture) -> NetPhy {
+            let link = LINK.with(|link| {
+                let mut link = link.borrow_mut();
+                if link.is_none() {
+                    *link = open_link();
+                }
+                link.clone()
+            });
+            NetPhy {
+                rx_buffer: [0; NET_MTU],
+                tx_buffer: [0; NET_MTU],
+                link,
+                capture,
+            }
+        }
+        // frames are queued by the link's own thread, so there's never any availability to push
+        pub fn push_rx_avail(&mut self, len: u16) -> Option<u16> {
+            Some(len)
+        }
+    }
+
+    impl<'a> phy::Device<'a> for NetPhy {
+        type RxToken = NetPhyRxToken<'a>;
+        type TxToken = NetPhyTxToken<'a>;
+
+        fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+            let frame = self.link.as_ref()?.rx_queue.lock().unwrap().pop_front()?;
+            let rx_len = frame.len();
+            self.rx_buffer[..rx_len].copy_from_slice(&frame);
+
+            Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len], capture: &self.capture},
+            NetPhyTxToken{buf: &mut self.tx_buffer[..], link: self.link.as_deref(), capture: &self.capture}))
+        }
+
+        fn transmit(&'a mut self) -> Option<Self::TxToken> {
+            Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], link: self.link.as_deref(), capture: &self.capture})
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            let mut caps = DeviceCapabilities::default();
+            caps.max_transmission_unit = NET_MTU;
+            caps.max_burst_size = Some(1);
+            caps.medium = Medium::Ethernet;
+            caps
+        }
+    }
+
+    pub struct NetPhyRxToken<'a> {
+        buf: &'a mut [u8],
+        capture: &'a RefCell<Capture>,
+    }
+
+    impl<'a> phy::RxToken for NetPhyRxToken<'a> {
+        fn consume<R, F>(mut self, timestamp: Instant, f: F) -> Result<R>
+            where F: FnOnce(&mut [u8]) -> Result<R>
+        {
+            self.capture.borrow_mut().record(timestamp, self.buf);
+            f(&mut self.buf)
+        }
+    }
+
+    pub struct NetPhyTxToken<'a> {
+        buf: &'a mut [u8],
+        link: Option<&'a Link>,
+        capture: &'a RefCell<Capture>,
+    }
+
+    impl<'a> phy::TxToken for NetPhyTxToken<'a> {
+        fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
+            where F: FnOnce(&mut [u8]) -> Result<R>
+        {
+            let result = f(&mut self.buf[..len]);
+            if result.is_ok() {
+                self.capture.borrow_mut().record(timestamp, &self.buf[..len]);
+            }
+            if let (Ok(_), Some(link)) = (&result, self.link) {
+                link.socket.send_to(&self.buf[..len], link.peer).map_err(|_| smoltcp::Error::Dropped)?;
+            }
+            result
+        }
+    }
+}
+
+pub use implementation::*;
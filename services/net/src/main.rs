@@ -7,31 +7,46 @@ use num_traits::*;
 use com::api::{ComIntSources, Ipv4Conf, NET_MTU};
 
 mod device;
+mod pcap;
 
 use xous::{Message, CID, SID, msg_scalar_unpack, msg_blocking_scalar_unpack};
 use xous_ipc::Buffer;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::convert::TryInto;
 
-use smoltcp::phy::{Medium, Device};
+use smoltcp::phy::{ChecksumCapabilities, Medium, Device};
 use smoltcp::iface::{InterfaceBuilder, NeighborCache, Routes, Interface};
 use smoltcp::socket::{IcmpEndpoint, IcmpPacketMetadata, IcmpSocket, IcmpSocketBuffer, SocketSet};
 use smoltcp::wire::{
-    EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr, IpEndpoint
+    EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr, IpEndpoint
 };
 use smoltcp::wire::{
     Icmpv4Packet, Icmpv4Repr, Icmpv6Packet, Icmpv6Repr,
+    IpProtocol, IpVersion, Ipv6Packet, Ipv6Repr, NdiscPrefixInfoFlags, NdiscRepr,
 };
 use byteorder::{ByteOrder, NetworkEndian};
 
 use smoltcp::socket::{UdpPacketMetadata, UdpSocket, UdpSocketBuffer, SocketHandle};
-use smoltcp::time::Instant;
+use smoltcp::socket::{TcpSocket, TcpSocketBuffer, TcpState};
+use smoltcp::socket::{RawPacketMetadata, RawSocket, RawSocketBuffer};
+use smoltcp::time::{Duration, Instant};
 use std::thread;
 use std::sync::Arc;
 use core::sync::atomic::{AtomicU32, Ordering};
 
 const PING_DEFAULT_TIMEOUT_MS: u32 = 10_000;
+/// Size of each of the Rx and Tx buffers of a TCP socket
+const TCP_BUFFER_SIZE: usize = 8192;
+/// Connections a TCP listener can have waiting to be accepted, and also the number of
+/// sockets kept listening for them
+const TCP_LISTEN_BACKLOG: usize = 2;
+/// A closed TCP socket is freed if the remote end goes quiet for this long
+const TCP_CLOSE_TIMEOUT_MS: u64 = 30_000;
+const TCP_EPHEMERAL_PORT_START: u16 = 49152;
+/// How soon to try again to wake a TCP client whose callback queue was full
+const TCP_NOTIFY_RETRY_MS: u64 = 10;
 
 fn set_ipv4_addr<DeviceT>(iface: &mut Interface<'_, DeviceT>, cidr: Ipv4Cidr)
 where
@@ -43,6 +58,135 @@ where
     });
 }
 
+/// Replace the interface's IPv6 addresses with `cidrs`, leaving the IPv4 address alone
+fn set_ipv6_addrs<DeviceT>(iface: &mut Interface<'_, DeviceT>, cidrs: &[Ipv6Cidr])
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    iface.update_ip_addrs(|addrs| {
+        let mut updated: Vec<IpCidr> = addrs.iter().filter(|cidr| match cidr {
+            IpCidr::Ipv6(_) => false,
+            _ => true,
+        }).cloned().collect();
+        updated.extend(cidrs.iter().map(|&cidr| IpCidr::Ipv6(cidr)));
+        *addrs = updated.into();
+    });
+}
+
+/// Make an address out of the top 64 bits of `prefix`, and the modified EUI-64 interface
+/// identifier of `mac` (RFC 4291 appendix A), as SLAAC does.
+fn ipv6_from_mac(prefix: Ipv6Address, mac: &[u8; 6]) -> Ipv6Address {
+    let mut octets = prefix.0;
+    octets[8..].copy_from_slice(&[mac[0] ^ 0x02, mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5]]);
+    Ipv6Address(octets)
+}
+
+/// The IPv6 side of the network config. The WLAN only hands us an IPv4 config, so the
+/// link-local address is derived from the MAC address, and a global address and default
+/// route are configured from router advertisements (SLAAC). Prefix lifetimes aren't
+/// tracked: an address stays until a router withdraws it, or the WLAN config changes.
+#[derive(Default)]
+struct Ipv6Config {
+    link_local: Option<Ipv6Cidr>,
+    global: Option<Ipv6Cidr>,
+    router: Option<Ipv6Address>,
+}
+impl Ipv6Config {
+    /// Start over with just a link-local address, as after joining a network
+    fn reset(&mut self, mac: &[u8; 6]) {
+        self.link_local = Some(Ipv6Cidr::new(ipv6_from_mac(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac), 64));
+        self.global = None;
+        self.router = None;
+    }
+    /// Can we reach IPv6 hosts beyond the local link?
+    fn connected(&self) -> bool {
+        self.global.is_some() && self.router.is_some()
+    }
+    /// The address that smoltcp sends from when a socket doesn't name one, which is the
+    /// first IPv6 address of the interface. The ICMPv6 checksum covers it, so pings have
+    /// to know it in advance.
+    fn source(&self) -> Option<Ipv6Address> {
+        self.global.or(self.link_local).map(|cidr| cidr.address())
+    }
+    fn apply<DeviceT>(&self, iface: &mut Interface<'_, DeviceT>)
+    where
+        DeviceT: for<'d> Device<'d>,
+    {
+        // the global address goes first, so it is the one we send from
+        let cidrs: Vec<Ipv6Cidr> = self.global.iter().chain(self.link_local.iter()).cloned().collect();
+        set_ipv6_addrs(iface, &cidrs);
+        iface.routes_mut().remove_default_ipv6_route();
+        if let Some(router) = self.router {
+            if let Err(e) = iface.routes_mut().add_default_ipv6_route(router) {
+                log::error!("couldn't add IPv6 default route via {}: {:?}", router, e);
+            }
+        }
+    }
+    /// Take up the address and route in a router advertisement. Returns true if the config changed.
+    fn router_advert(&mut self, mac: &[u8; 6], router: Ipv6Address, advert: &NdiscRepr) -> bool {
+        let before = (self.global, self.router);
+        if let NdiscRepr::RouterAdvert { router_lifetime, prefix_info, .. } = advert {
+            if router_lifetime.total_millis() > 0 {
+                self.router = Some(router);
+            } else if self.router == Some(router) {
+                self.router = None;
+            }
+            if let Some(info) = prefix_info {
+                // SLAAC only works on /64 prefixes, as that leaves room for the interface identifier
+                if info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF) && info.prefix_len == 64 {
+                    self.global = if info.valid_lifetime.total_millis() > 0 {
+                        Some(Ipv6Cidr::new(ipv6_from_mac(info.prefix, mac), 64))
+                    } else {
+                        None
+                    };
+                }
+            }
+        }
+        before != (self.global, self.router)
+    }
+}
+
+/// Ask the routers on the link to advertise themselves, rather than wait for their next
+/// unsolicited advertisement. smoltcp doesn't do router discovery, so this goes out through
+/// a raw socket, which needs the whole IPv6 packet to be built.
+fn send_router_solicit(socket: &mut RawSocket, src: Ipv6Address, checksum: &ChecksumCapabilities) {
+    let icmp_repr = Icmpv6Repr::Ndisc(NdiscRepr::RouterSolicit { lladdr: None });
+    let ip_repr = Ipv6Repr {
+        src_addr: src,
+        dst_addr: Ipv6Address::LINK_LOCAL_ALL_ROUTERS,
+        next_header: IpProtocol::Icmpv6,
+        payload_len: icmp_repr.buffer_len(),
+        hop_limit: 255,
+    };
+    match socket.send(ip_repr.buffer_len() + icmp_repr.buffer_len()) {
+        Ok(buf) => {
+            let mut packet = Ipv6Packet::new_unchecked(buf);
+            ip_repr.emit(&mut packet);
+            let mut icmp_packet = Icmpv6Packet::new_unchecked(packet.payload_mut());
+            icmp_repr.emit(&src.into(), &ip_repr.dst_addr.into(), &mut icmp_packet, checksum);
+        }
+        Err(e) => log::warn!("couldn't send router solicitation: {:?}", e),
+    }
+}
+
+/// Pick the neighbor discovery messages out of what the raw ICMPv6 socket received,
+/// which is everything ICMPv6 that reached the interface. Messages that may have been
+/// forwarded from off the link are dropped (RFC 4861 §6.1.2): they must arrive with a
+/// hop limit of 255, and router advertisements must come from a link-local address.
+fn parse_ndisc<'a>(packet: &'a [u8], checksum: &ChecksumCapabilities) -> Option<(Ipv6Address, NdiscRepr<'a>)> {
+    let ipv6_packet = Ipv6Packet::new_checked(packet).ok()?;
+    let ipv6_repr = Ipv6Repr::parse(&ipv6_packet).ok()?;
+    if ipv6_repr.hop_limit != 255 {
+        return None;
+    }
+    let icmp_packet = Icmpv6Packet::new_checked(ipv6_packet.payload()).ok()?;
+    match Icmpv6Repr::parse(&ipv6_repr.src_addr.into(), &ipv6_repr.dst_addr.into(), &icmp_packet, checksum).ok()? {
+        Icmpv6Repr::Ndisc(NdiscRepr::RouterAdvert { .. }) if !ipv6_repr.src_addr.is_link_local() => None,
+        Icmpv6Repr::Ndisc(repr) => Some((ipv6_repr.src_addr, repr)),
+        _ => None,
+    }
+}
+
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 enum WaitOp {
     WaitMs,
@@ -50,12 +194,209 @@ enum WaitOp {
     Quit,
 }
 
+/// Is `addr` a broadcast to the whole network, or to the whole of one of our subnets?
+fn is_broadcast<DeviceT>(iface: &Interface<'_, DeviceT>, addr: &IpAddress) -> bool
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    match addr {
+        IpAddress::Ipv4(ipv4) => {
+            ipv4.is_broadcast() || iface.ip_addrs().iter().any(|cidr| match cidr {
+                IpCidr::Ipv4(cidr) => cidr.broadcast() == Some(*ipv4),
+                _ => false,
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Join an IPv4 multicast group on behalf of one more UDP port. The interface only
+/// joins the group, and sends an IGMP report, for the first port to ask.
+fn join_multicast_v4<DeviceT>(
+    iface: &mut Interface<'_, DeviceT>,
+    groups: &mut HashMap<Ipv4Address, usize>,
+    group: Ipv4Address,
+    timestamp: Instant,
+) -> bool
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    if let Some(count) = groups.get_mut(&group) {
+        *count += 1;
+        return true;
+    }
+    match iface.join_multicast_group(group, timestamp) {
+        Ok(_) => {
+            groups.insert(group, 1);
+            true
+        }
+        Err(e) => {
+            log::error!("couldn't join multicast group {}: {:?}", group, e);
+            false
+        }
+    }
+}
+
+/// Undo one `join_multicast_v4()`, leaving the group once no port is in it
+fn leave_multicast_v4<DeviceT>(
+    iface: &mut Interface<'_, DeviceT>,
+    groups: &mut HashMap<Ipv4Address, usize>,
+    group: Ipv4Address,
+    timestamp: Instant,
+) where
+    DeviceT: for<'d> Device<'d>,
+{
+    match groups.get_mut(&group) {
+        Some(count) if *count > 1 => *count -= 1,
+        Some(_) => {
+            groups.remove(&group);
+            if let Err(e) = iface.leave_multicast_group(group, timestamp) {
+                log::error!("couldn't leave multicast group {}: {:?}", group, e);
+            }
+        }
+        None => log::error!("left multicast group {} more times than it was joined", group),
+    }
+}
+
+/// Options set on a UDP port. Like the port's smoltcp socket, they're shared by every
+/// clone bound to it.
+pub struct UdpOptions {
+    /// hop limit of unicast datagrams; smoltcp's default is used if unset
+    ttl: Option<u8>,
+    /// allow datagrams to be sent to broadcast addresses
+    broadcast: bool,
+    /// hop limit of multicasts. std only has a setter for IPv4's, but it serves for IPv6 too.
+    multicast_ttl: u8,
+    /// deliver our own multicasts to the sockets on this device that are listening for them
+    multicast_loop: bool,
+    multicast_loop_v6: bool,
+    groups: Vec<Ipv4Address>,
+    /// smoltcp neither filters IPv6 multicasts nor sends MLD reports, so these groups are
+    /// only noted for the sake of looping back our own multicasts
+    groups_v6: Vec<Ipv6Address>,
+}
+impl Default for UdpOptions {
+    fn default() -> Self {
+        // the same defaults as other IP stacks: multicasts stay on the local network, and are looped back
+        UdpOptions {
+            ttl: None,
+            broadcast: false,
+            multicast_ttl: 1,
+            multicast_loop: true,
+            multicast_loop_v6: true,
+            groups: Vec::new(),
+            groups_v6: Vec::new(),
+        }
+    }
+}
+
 /// UdpState will return a full custom datastructure, and is designed to work with
 /// a one-time use dedicated server created as part of the Net library code.
 pub struct UdpState {
     handle: SocketHandle,
     cid: CID,
     sid: SID,
+    options: UdpOptions,
+}
+
+/// Hand a received datagram to every socket bound to its port: the one that bound it
+/// first, and its clones.
+fn udp_deliver(udpstate: &UdpState, clones: Option<&HashMap<[u32; 4], CID>>, response: NetUdpResponse) {
+    let buf = Buffer::into_buf(response).expect("couldn't convert UDP response to memory message");
+    buf.send(udpstate.cid, NetUdpCallback::RxData.to_u32().unwrap()).expect("couldn't send UDP response");
+    // now send copies to the cloned receiver array, if they exist
+    if let Some(clone_map) = clones {
+        for &cids in clone_map.values() {
+            let buf = Buffer::into_buf(response).expect("couldn't convert UDP response to memory message");
+            buf.send(cids, NetUdpCallback::RxData.to_u32().unwrap()).expect("couldn't send UDP response");
+        }
+    }
+}
+
+/// TcpStreamState is one connection of a TcpStream. Streams are identified by an id
+/// handed out by the Net server, as accepted connections share the listener's port.
+/// The `_waiting` fields note that the client was told to wait, and needs to hear
+/// from its callback server once the socket is ready.
+pub struct TcpStreamState {
+    handle: SocketHandle,
+    cid: CID,
+    /// the stream's callback server, which the owner must name to use the stream
+    sid: SID,
+    local_port: u16,
+    connecting: bool,
+    rx_waiting: bool,
+    tx_waiting: bool,
+}
+
+/// TcpListenerState tracks the sockets listening on a port on behalf of a TcpListener.
+/// smoltcp sockets take on the connection they accept, so several are kept listening,
+/// and are replaced as they connect.
+pub struct TcpListenerState {
+    cid: CID,
+    sid: SID,
+    endpoint: IpEndpoint,
+    backlog: Vec<SocketHandle>,
+    /// connections that are established, but haven't been accepted yet
+    accepted: VecDeque<SocketHandle>,
+    accept_waiting: bool,
+}
+
+impl TcpListenerState {
+    fn fill_backlog(&mut self, sockets: &mut SocketSet) {
+        while self.backlog.len() < TCP_LISTEN_BACKLOG && self.accepted.len() < TCP_LISTEN_BACKLOG {
+            let mut socket = new_tcp_socket();
+            match socket.listen(self.endpoint) {
+                Ok(_) => self.backlog.push(sockets.add(socket)),
+                Err(e) => {
+                    log::error!("Tcp couldn't listen on {}: {:?}", self.endpoint, e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn new_tcp_socket<'a>() -> TcpSocket<'a> {
+    let tcp_rx_buffer = TcpSocketBuffer::new(vec![0; TCP_BUFFER_SIZE]);
+    let tcp_tx_buffer = TcpSocketBuffer::new(vec![0; TCP_BUFFER_SIZE]);
+    TcpSocket::new(tcp_rx_buffer, tcp_tx_buffer)
+}
+
+/// Wake a TCP client that is waiting on its callback server. This never blocks, so a
+/// client that stops answering can't stall the Net server. Returns `false` if the
+/// client's callback queue was full, in which case the caller keeps its `_waiting`
+/// flag set and tries again on a later pump.
+fn tcp_notify(cid: CID, event: NetTcpCallback, arg: usize) -> bool {
+    match xous::try_send_message(cid, Message::new_scalar(event.to_usize().unwrap(), arg, 0, 0, 0)) {
+        Ok(_) => true,
+        Err(xous::Error::ServerQueueFull) => {
+            log::debug!("Tcp callback queue is full, {:?} will be sent again later", event);
+            false
+        }
+        Err(xous::Error::ServerNotFound) => {
+            log::debug!("Tcp callback server disappeared before it could be notified of {:?}", event);
+            true
+        }
+        Err(e) => {
+            log::error!("Couldn't send Tcp notification {:?}: {:?}", event, e);
+            true
+        }
+    }
+}
+
+/// Pick a local port for a TCP socket that wasn't given one
+fn tcp_ephemeral_port(
+    next_port: &mut u16,
+    streams: &HashMap<u32, TcpStreamState>,
+    listeners: &HashMap<u16, TcpListenerState>,
+) -> u16 {
+    loop {
+        let port = *next_port;
+        *next_port = if port == u16::MAX { TCP_EPHEMERAL_PORT_START } else { port + 1 };
+        if !listeners.contains_key(&port) && !streams.values().any(|state| state.local_port == port) {
+            return port;
+        }
+    }
 }
 
 /// PingConnection can return a Scalar: because of the simplicity of the return data
@@ -141,12 +482,28 @@ fn xmain() -> ! {
     let mut ping_destinations = HashMap::<PingConnection, HashMap::<u16, u64>>::new();
     let mut ping_timeout_ms = PING_DEFAULT_TIMEOUT_MS;
 
+    // ipv6 storage: a raw socket sees all of the ICMPv6 traffic, so router advertisements can be picked out of it
+    let ndisc_rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 4], vec![0; 2048]);
+    let ndisc_tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 128]);
+    let ndisc_handle = sockets.add(RawSocket::new(IpVersion::Ipv6, IpProtocol::Icmpv6, ndisc_rx_buffer, ndisc_tx_buffer));
+    let mut ipv6_config = Ipv6Config::default();
+
     // udp storage
     let mut udp_handles = HashMap::<u16, UdpState>::new();
     // UDP requires multiple copies. The way it works is that Tx can come from anyone;
     // for Rx, copies of a CID,SID tuple are kept for every clone is kept in a HashMap. This
     // allows for the Rx data to be cc:'d to each clone, and identified by SID upon drop
     let mut udp_clones = HashMap::<u16, HashMap::<[u32; 4], CID>>::new(); // additional clones for UDP responders
+    // the number of UDP ports that are in each multicast group
+    let mut multicast_groups = HashMap::<Ipv4Address, usize>::new();
+
+    // tcp storage
+    let mut tcp_streams = HashMap::<u32, TcpStreamState>::new();
+    let mut tcp_listeners = HashMap::<u16, TcpListenerState>::new();
+    // sockets whose owners have dropped them, kept until they finish closing
+    let mut tcp_closing = Vec::<SocketHandle>::new();
+    let mut tcp_next_id: u32 = 0;
+    let mut tcp_next_port = TCP_EPHEMERAL_PORT_START;
 
     // other link storage
     let timer = ticktimer_server::Ticktimer::new().unwrap();
@@ -154,19 +511,27 @@ fn xmain() -> ! {
     let ip_addrs = [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)];
     let routes = Routes::new(BTreeMap::new());
 
-    let device = device::NetPhy::new(&xns);
+    // frames seen by the device; outlives the interface, which is rebuilt on config updates
+    let capture = pcap::Capture::new_shared();
+    // claimed by the first process that asks, which is the only one that can run captures after that
+    let mut pcap_token: Option<[u32; 4]> = None;
+    let device = device::NetPhy::new(&xns, capture.clone());
     // needed by ICMP to determine if we should compute checksums
     let device_caps = device.capabilities();
     let medium = device.capabilities().medium;
     let mut builder = InterfaceBuilder::new(device)
         .ip_addrs(ip_addrs)
-        .routes(routes);
+        .routes(routes)
+        .ipv4_multicast_groups(BTreeMap::new());
     if medium == Medium::Ethernet {
         builder = builder
             .ethernet_addr(EthernetAddress::from_bytes(&[0; 6]))
             .neighbor_cache(neighbor_cache);
     }
     let mut iface = builder.finalize();
+    // set when the network config changes, so the interface is rebuilt before the next message is handled.
+    // In hosted mode, there's no COM to tell us about the network, so the hosted link's config is taken up right away.
+    let mut ip_config_pending = device::hosted_config().is_some();
 
     // DNS hooks - the DNS server can ask the Net crate to tickle it when IP configs change using these hooks
     // Currently, we assume there is only one DNS server in Xous. I suppose you could
@@ -176,6 +541,7 @@ fn xmain() -> ! {
     let mut dns_ipv4_hook = XousScalarEndpoint::new();
     let mut dns_ipv6_hook = XousScalarEndpoint::new();
     let mut dns_allclear_hook = XousScalarEndpoint::new();
+    let mut dns_ipv6_connectivity_hook = XousScalarEndpoint::new();
 
     log::trace!("ready to accept requests");
     // register a suspend/resume listener
@@ -184,6 +550,94 @@ fn xmain() -> ! {
 
     let mut cid_to_disconnect: Option<CID> = None;
     loop {
+        // the interface is rebuilt here, rather than on a message to ourselves, so that no other
+        // process can ask for it, and sending it can't block on our own queue
+        if ip_config_pending {
+            ip_config_pending = false;
+            // right now the WLAN implementation only does IPV4, so IPV6 is configured locally: a link-local
+            // address comes from the MAC, and SLAAC picks up a global address once a router advertises.
+            let config = match device::hosted_config() {
+                Some(config) => config,
+                None => com.wlan_get_config().expect("couldn't retrieve updated ipv4 config"),
+            };
+            log::info!("Network config acquired: {:?}", config);
+            net_config = Some(config);
+            let mac = EthernetAddress::from_bytes(&config.mac);
+
+            // we need to clear the ARP cache in case we've migrated base stations (e.g. in a wireless network
+            // that is coverd by multiple AP), as the host AP's MAC address would have changed, and we wouldn't
+            // be able to route responses back. I can't seem to find a function in smoltcp 0.7.5 that allows us
+            // to neatly clear the ARP cache as the BTreeMap that underlies it is moved into the container and
+            // no "clear" API is exposed, so let's just rebuild the whole interface if we get a DHCP renewal.
+            let neighbor_cache = NeighborCache::new(BTreeMap::new());
+            let ip_addrs = [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)];
+            let routes = Routes::new(BTreeMap::new());
+            let device = device::NetPhy::new(&xns, capture.clone());
+            let medium = device.capabilities().medium;
+            let mut builder = InterfaceBuilder::new(device)
+                .ip_addrs(ip_addrs)
+                .routes(routes)
+                .ipv4_multicast_groups(BTreeMap::new());
+            if medium == Medium::Ethernet {
+                builder = builder
+                    .ethernet_addr(mac)
+                    .neighbor_cache(neighbor_cache);
+            }
+            iface = builder.finalize();
+
+            let ip_addr =
+                Ipv4Cidr::new(Ipv4Address::new(
+                    config.addr[0],
+                    config.addr[1],
+                    config.addr[2],
+                    config.addr[3],
+                ), 24);
+            set_ipv4_addr(&mut iface, ip_addr);
+            let default_v4_gw = Ipv4Address::new(
+                config.gtwy[0],
+                config.gtwy[1],
+                config.gtwy[2],
+                config.gtwy[3],
+            );
+
+            // reset the default route, in case it has changed
+            iface.routes_mut().remove_default_ipv4_route();
+            match iface.routes_mut().add_default_ipv4_route(default_v4_gw) {
+                Ok(route) => log::info!("routing table updated successfully [{:?}]", route),
+                Err(e) => log::error!("routing table update error: {}", e),
+            }
+            // start IPv6 over, as we may have joined a different network
+            ipv6_config.reset(&config.mac);
+            ipv6_config.apply(&mut iface);
+            if let Some(src) = ipv6_config.source() {
+                send_router_solicit(&mut sockets.get::<RawSocket>(ndisc_handle), src, &device_caps.checksum);
+                xous::try_send_message(net_conn,
+                    Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0)
+                ).ok();
+            }
+            dns_ipv6_connectivity_hook.notify_custom_args([Some(0), None, None, None]);
+
+            // the new interface has forgotten which multicast groups we're in
+            let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+            for &group in multicast_groups.keys() {
+                if let Err(e) = iface.join_multicast_group(group, timestamp) {
+                    log::error!("couldn't rejoin multicast group {}: {:?}", group, e);
+                }
+            }
+            dns_allclear_hook.notify();
+            dns_ipv4_hook.notify_custom_args([
+                Some(u32::from_be_bytes(config.dns1)),
+                None, None, None,
+            ]);
+            // the current implementation always returns 0.0.0.0 as the second dns,
+            // ignore this if that's what we've got; otherwise, pass it on.
+            if config.dns2 != [0, 0, 0, 0] {
+                dns_ipv4_hook.notify_custom_args([
+                    Some(u32::from_be_bytes(config.dns2)),
+                    None, None, None,
+                ]);
+            }
+        }
         let mut msg = xous::receive_message(net_sid).unwrap();
         if let Some(dc_cid) = cid_to_disconnect.take() { // disconnect previous loop iter's connection after d/c OK response was sent
             unsafe{
@@ -203,9 +657,14 @@ fn xmain() -> ! {
                 let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
                 let mut pkt = buf.to_original::<NetPingPacket, _>().unwrap();
                 let mut socket = sockets.get::<IcmpSocket>(icmp_handle);
-                if socket.can_send() {
+                let remote = IpAddress::from(pkt.endpoint);
+                // there's no IPv6 address to send from until the WLAN is up
+                let addressable = match remote {
+                    IpAddress::Ipv6(_) => ipv6_config.source().is_some(),
+                    _ => true,
+                };
+                if socket.can_send() && addressable {
                     log::trace!("sending ping to {:?}", pkt.endpoint);
-                    let remote = IpAddress::from(pkt.endpoint);
                     // we take advantage of the fact that the same CID is always returned for repeated connect requests to the same SID.
                     let cid = match pkt.server {
                         XousServerId::PrivateSid(sid) => match xous::connect(SID::from_array(sid)) {
@@ -256,8 +715,7 @@ fn xmain() -> ! {
                             icmp_repr.emit(&mut icmp_packet, &device_caps.checksum);
                         }
                         IpAddress::Ipv6(_) => {
-                            // not sure if this is a valid thing to do, to just assign the source some number like this??
-                            let src_ipv6 = IpAddress::v6(0xfdaa, 0, 0, 0, 0, 0, 0, 1);
+                            let src_ipv6 = IpAddress::Ipv6(ipv6_config.source().expect("checked before the ping was queued"));
                             let icmp_repr = Icmpv6Repr::EchoRequest {
                                 ident,
                                 seq_no: seq,
@@ -311,6 +769,36 @@ fn xmain() -> ! {
             Some(Opcode::PingGetTimeout) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 xous::return_scalar(msg.sender, ping_timeout_ms as usize).unwrap();
             }),
+            Some(Opcode::PcapClaim) => msg_blocking_scalar_unpack!(msg, t0, t1, t2, t3, {
+                if pcap_token.is_none() {
+                    pcap_token = Some([t0 as u32, t1 as u32, t2 as u32, t3 as u32]);
+                    xous::return_scalar(msg.sender, 1).unwrap();
+                } else {
+                    log::warn!("packet capture token already claimed");
+                    xous::return_scalar(msg.sender, 0).unwrap();
+                }
+            }),
+            Some(op @ Opcode::PcapStart) | Some(op @ Opcode::PcapStop) | Some(op @ Opcode::PcapDump) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut request = buf.to_original::<NetPcap, _>().unwrap();
+                request.granted = pcap_token.is_some() && pcap_token == Some(request.token);
+                if request.granted {
+                    let mut capture = capture.borrow_mut();
+                    match op {
+                        Opcode::PcapStart => {
+                            request.limit = capture.start(request.limit as usize) as u32;
+                            log::info!("packet capture started, keeping the last {} bytes", request.limit);
+                        }
+                        Opcode::PcapStop => capture.stop(),
+                        _ => capture.dump(),
+                    }
+                    request.frames = capture.frames() as u32;
+                    request.overwritten = capture.overwritten();
+                } else {
+                    log::warn!("packet capture request without the claimed token");
+                }
+                buf.replace(request).expect("couldn't respond to packet capture request");
+            }
             Some(Opcode::DnsHookAddIpv4) => {
                 let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
                 let hook = buf.to_original::<XousPrivateServerHook, _>().unwrap();
@@ -322,6 +810,13 @@ fn xmain() -> ! {
                         hook.op,
                         hook.args,
                     );
+                    // the network may have come up before the hook was set, as the hosted link does
+                    if let Some(config) = net_config {
+                        dns_ipv4_hook.notify_custom_args([Some(u32::from_be_bytes(config.dns1)), None, None, None]);
+                        if config.dns2 != [0, 0, 0, 0] {
+                            dns_ipv4_hook.notify_custom_args([Some(u32::from_be_bytes(config.dns2)), None, None, None]);
+                        }
+                    }
                     buf.replace(NetMemResponse::Ok).unwrap();
                 }
             }
@@ -354,10 +849,27 @@ fn xmain() -> ! {
                 }
 
             }
+            Some(Opcode::DnsHookIpv6Connectivity) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let hook = buf.to_original::<XousPrivateServerHook, _>().unwrap();
+                if dns_ipv6_connectivity_hook.is_set() {
+                    buf.replace(NetMemResponse::AlreadyUsed).unwrap();
+                } else {
+                    dns_ipv6_connectivity_hook.set(
+                        xous::connect(SID::from_array(hook.one_time_sid)).unwrap(),
+                        hook.op,
+                        hook.args,
+                    );
+                    // IPv6 may have come up before the hook was set
+                    dns_ipv6_connectivity_hook.notify_custom_args([Some(ipv6_config.connected() as u32), None, None, None]);
+                    buf.replace(NetMemResponse::Ok).unwrap();
+                }
+            }
             Some(Opcode::DnsUnhookAll) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 dns_ipv4_hook.clear();
                 dns_ipv6_hook.clear();
                 dns_allclear_hook.clear();
+                dns_ipv6_connectivity_hook.clear();
                 xous::return_scalar(msg.sender, 1).expect("couldn't ack unhook");
             }),
             Some(Opcode::UdpBind) => {
@@ -402,7 +914,8 @@ fn xmain() -> ! {
                             let udpstate = UdpState {
                                 handle: sockets.add(udp_socket),
                                 cid: xous::connect(sid).unwrap(),
-                                sid
+                                sid,
+                                options: UdpOptions::default(),
                             };
                             udp_handles.insert(udpspec.port, udpstate);
                             buf.replace(NetMemResponse::Ok).unwrap();
@@ -439,6 +952,10 @@ fn xmain() -> ! {
                                 // if the clone map is nil, close the socket, we're done
                                 None => {
                                     sockets.get::<UdpSocket>(udpstate.handle).close();
+                                    let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                                    for &group in udpstate.options.groups.iter() {
+                                        leave_multicast_v4(&mut iface, &mut multicast_groups, group, timestamp);
+                                    }
                                     buf.replace(NetMemResponse::Ok).unwrap();
                                 }
                                 // if the clone map has entries, promote an arbitrary map entry to the primary handle
@@ -447,6 +964,10 @@ fn xmain() -> ! {
                                         // removing SIDs doesn't remove the map, so it's possible to have an empty mapping. Get rid of it, and we're done.
                                         udp_clones.remove(&udpspec.port);
                                         sockets.get::<UdpSocket>(udpstate.handle).close();
+                                        let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                                        for &group in udpstate.options.groups.iter() {
+                                            leave_multicast_v4(&mut iface, &mut multicast_groups, group, timestamp);
+                                        }
                                         buf.replace(NetMemResponse::Ok).unwrap();
                                     } else {
                                         // take an arbitrary key, re-insert it into the handles map.
@@ -455,6 +976,7 @@ fn xmain() -> ! {
                                             handle: udpstate.handle,
                                             cid: *clone_map.get(&new_primary_sid).unwrap(),
                                             sid: SID::from_array(new_primary_sid),
+                                            options: udpstate.options,
                                         };
                                         udp_handles.insert(udpspec.port, udpstate);
                                         // now remove it from the clone map
@@ -478,41 +1000,91 @@ fn xmain() -> ! {
                 use std::convert::TryInto;
                 let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
                 let udp_tx = buf.to_original::<NetUdpTransmit, _>().unwrap();
+                let mut loopback: Option<IpEndpoint> = None;
                 match udp_handles.get_mut(&udp_tx.local_port) {
                     Some(udpstate) => {
                         if let Some(dest_socket) = udp_tx.dest_socket {
-                            let endpoint = IpEndpoint::new(
+                            let mut endpoint = IpEndpoint::new(
                                 dest_socket.addr.try_into().unwrap(),
                                 dest_socket.port
                             );
-                            let mut socket = sockets.get::<UdpSocket>(udpstate.handle);
-                            match socket.send_slice(&udp_tx.data[..udp_tx.len as usize], endpoint) {
-                                Ok(_) => buf.replace(NetMemResponse::Sent(udp_tx.len)).unwrap(),
-                                _ => buf.replace(NetMemResponse::LibraryError).unwrap(),
+                            if is_broadcast(&iface, &endpoint.addr) && !udpstate.options.broadcast {
+                                buf.replace(NetMemResponse::AccessDenied).unwrap();
+                            } else {
+                                if is_broadcast(&iface, &endpoint.addr) {
+                                    // smoltcp would try to ARP for a subnet broadcast address, so send it to
+                                    // the limited broadcast address instead, which reaches the same hosts
+                                    endpoint.addr = IpAddress::Ipv4(Ipv4Address::BROADCAST);
+                                }
+                                let multicast = endpoint.addr.is_multicast();
+                                let mut socket = sockets.get::<UdpSocket>(udpstate.handle);
+                                // the Tx buffer holds just one datagram, which has to go out before another
+                                // can be queued, so it goes out with the hop limit that is set here
+                                socket.set_hop_limit(if multicast {
+                                    Some(udpstate.options.multicast_ttl)
+                                } else {
+                                    udpstate.options.ttl
+                                });
+                                match socket.send_slice(&udp_tx.data[..udp_tx.len as usize], endpoint) {
+                                    Ok(_) => {
+                                        let multicast_loop = match endpoint.addr {
+                                            IpAddress::Ipv6(_) => udpstate.options.multicast_loop_v6,
+                                            _ => udpstate.options.multicast_loop,
+                                        };
+                                        if multicast && multicast_loop {
+                                            loopback = Some(endpoint);
+                                        }
+                                        buf.replace(NetMemResponse::Sent(udp_tx.len)).unwrap()
+                                    }
+                                    _ => buf.replace(NetMemResponse::LibraryError).unwrap(),
+                                }
+                                // fire off a Pump to get the stack to actually transmit the ping; the send call merely queues it for sending
+                                xous::try_send_message(net_conn,
+                                    Message::new_scalar(
+                                        Opcode::NetPump.to_usize().unwrap(),
+                                        0, 0, 0, 0)
+                                ).ok();
                             }
-                            // fire off a Pump to get the stack to actually transmit the ping; the send call merely queues it for sending
-                            xous::try_send_message(net_conn,
-                                Message::new_scalar(
-                                    Opcode::NetPump.to_usize().unwrap(),
-                                    0, 0, 0, 0)
-                            ).ok();
                         } else {
                             buf.replace(NetMemResponse::Invalid).unwrap()
                         }
                     }
                     _ => buf.replace(NetMemResponse::Invalid).unwrap()
                 }
+                // the interface never hears its own multicasts, so copies go straight to the socket
+                // on this device that is bound to the destination port, if it has joined the group
+                let receiver = loopback.and_then(|endpoint| Some((endpoint, udp_handles.get(&endpoint.port)?)));
+                if let Some((endpoint, udpstate)) = receiver {
+                    let source = match endpoint.addr {
+                        IpAddress::Ipv4(group) if udpstate.options.groups.contains(&group) => {
+                            net_config.map(|config| NetIpAddr::Ipv4(config.addr))
+                        }
+                        IpAddress::Ipv6(group) if udpstate.options.groups_v6.contains(&group) => {
+                            ipv6_config.source().map(|src| NetIpAddr::Ipv6(src.0))
+                        }
+                        _ => None,
+                    };
+                    if let Some(source) = source {
+                        let mut response = NetUdpResponse {
+                            endpoint_ip_addr: source,
+                            len: udp_tx.len,
+                            endpoint_port: udp_tx.local_port,
+                            data: [0; UDP_RESPONSE_MAX_LEN],
+                        };
+                        response.data[..udp_tx.len as usize].copy_from_slice(&udp_tx.data[..udp_tx.len as usize]);
+                        udp_deliver(udpstate, udp_clones.get(&endpoint.port), response);
+                    }
+                }
             },
             Some(Opcode::UdpSetTtl) => msg_scalar_unpack!(msg, ttl, port, _, _, {
                 match udp_handles.get_mut(&(port as u16)) {
                     Some(udpstate) => {
-                        let mut socket = sockets.get::<UdpSocket>(udpstate.handle);
                         let checked_ttl = if ttl > 255 || ttl == 0 {
                             64
                         } else {
                             ttl as u8
                         };
-                        socket.set_hop_limit(Some(checked_ttl));
+                        udpstate.options.ttl = Some(checked_ttl);
                     }
                     None => {
                         log::error!("Set TTL message received, but no port was bound! port {} ttl {}", port, ttl);
@@ -522,8 +1094,7 @@ fn xmain() -> ! {
             Some(Opcode::UdpGetTtl) => msg_blocking_scalar_unpack!(msg, port, _, _, _, {
                 match udp_handles.get_mut(&(port as u16)) {
                     Some(udpstate) => {
-                        let socket = sockets.get::<UdpSocket>(udpstate.handle);
-                        let ttl = socket.hop_limit().unwrap_or(64); // 64 is the value used by smoltcp if hop limit isn't set
+                        let ttl = udpstate.options.ttl.unwrap_or(64); // 64 is the value used by smoltcp if hop limit isn't set
                         xous::return_scalar(msg.sender, ttl as usize).expect("couldn't return TTL");
                     }
                     None => {
@@ -532,6 +1103,387 @@ fn xmain() -> ! {
                     }
                 }
             }),
+            Some(Opcode::UdpSetOption) => msg_blocking_scalar_unpack!(msg, port, option, value, _, {
+                let ok = match (udp_handles.get_mut(&(port as u16)), FromPrimitive::from_usize(option)) {
+                    (Some(udpstate), Some(NetUdpOption::Broadcast)) => {
+                        udpstate.options.broadcast = value != 0;
+                        true
+                    }
+                    (Some(udpstate), Some(NetUdpOption::MulticastTtlV4)) if value <= 255 => {
+                        udpstate.options.multicast_ttl = value as u8;
+                        true
+                    }
+                    (Some(udpstate), Some(NetUdpOption::MulticastLoopV4)) => {
+                        udpstate.options.multicast_loop = value != 0;
+                        true
+                    }
+                    (Some(udpstate), Some(NetUdpOption::MulticastLoopV6)) => {
+                        udpstate.options.multicast_loop_v6 = value != 0;
+                        true
+                    }
+                    _ => false,
+                };
+                xous::return_scalar(msg.sender, ok as usize).expect("couldn't ack UDP option");
+            }),
+            Some(Opcode::UdpGetOption) => msg_blocking_scalar_unpack!(msg, port, option, _, _, {
+                let value = match (udp_handles.get(&(port as u16)), FromPrimitive::from_usize(option)) {
+                    (Some(udpstate), Some(NetUdpOption::Broadcast)) => udpstate.options.broadcast as usize,
+                    (Some(udpstate), Some(NetUdpOption::MulticastTtlV4)) => udpstate.options.multicast_ttl as usize,
+                    (Some(udpstate), Some(NetUdpOption::MulticastLoopV4)) => udpstate.options.multicast_loop as usize,
+                    (Some(udpstate), Some(NetUdpOption::MulticastLoopV6)) => udpstate.options.multicast_loop_v6 as usize,
+                    _ => usize::MAX,
+                };
+                xous::return_scalar(msg.sender, value).expect("couldn't return UDP option");
+            }),
+            Some(Opcode::UdpJoinMulticastV4) => msg_blocking_scalar_unpack!(msg, port, group, _, _, {
+                let group = Ipv4Address::from_bytes(&(group as u32).to_be_bytes());
+                let joined = match udp_handles.get_mut(&(port as u16)) {
+                    Some(udpstate) if group.is_multicast() && !udpstate.options.groups.contains(&group) => {
+                        let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                        if join_multicast_v4(&mut iface, &mut multicast_groups, group, timestamp) {
+                            udpstate.options.groups.push(group);
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                    _ => false,
+                };
+                xous::return_scalar(msg.sender, joined as usize).expect("couldn't ack multicast join");
+            }),
+            Some(Opcode::UdpLeaveMulticastV4) => msg_blocking_scalar_unpack!(msg, port, group, _, _, {
+                let group = Ipv4Address::from_bytes(&(group as u32).to_be_bytes());
+                let left = match udp_handles.get_mut(&(port as u16)) {
+                    Some(udpstate) if udpstate.options.groups.contains(&group) => {
+                        udpstate.options.groups.retain(|&g| g != group);
+                        let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                        leave_multicast_v4(&mut iface, &mut multicast_groups, group, timestamp);
+                        true
+                    }
+                    _ => false,
+                };
+                xous::return_scalar(msg.sender, left as usize).expect("couldn't ack multicast leave");
+            }),
+            Some(Opcode::UdpJoinMulticastV6) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut request = buf.to_original::<NetUdpMulticastV6, _>().unwrap();
+                let group = Ipv6Address(request.group);
+                let joined = match udp_handles.get_mut(&request.port) {
+                    Some(udpstate) if group.is_multicast() && !udpstate.options.groups_v6.contains(&group) => {
+                        udpstate.options.groups_v6.push(group);
+                        true
+                    }
+                    _ => false,
+                };
+                request.result = Some(joined);
+                buf.replace(request).expect("couldn't ack multicast join");
+            }
+            Some(Opcode::UdpLeaveMulticastV6) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut request = buf.to_original::<NetUdpMulticastV6, _>().unwrap();
+                let group = Ipv6Address(request.group);
+                let left = match udp_handles.get_mut(&request.port) {
+                    Some(udpstate) if udpstate.options.groups_v6.contains(&group) => {
+                        udpstate.options.groups_v6.retain(|&g| g != group);
+                        true
+                    }
+                    _ => false,
+                };
+                request.result = Some(left);
+                buf.replace(request).expect("couldn't ack multicast leave");
+            }
+
+            Some(Opcode::TcpConnect) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut connect = buf.to_original::<NetTcpConnect, _>().unwrap();
+                let remote = IpEndpoint::new(IpAddress::from(connect.remote.addr), connect.remote.port);
+                // the stream is sent from our address in the remote end's family
+                let local_addr = match remote.addr {
+                    IpAddress::Ipv4(_) => net_config.map(|config|
+                        IpAddress::v4(config.addr[0], config.addr[1], config.addr[2], config.addr[3])
+                    ),
+                    IpAddress::Ipv6(_) => net_config.and(ipv6_config.source()).map(IpAddress::Ipv6),
+                    _ => None,
+                };
+                connect.result = Some(if let Some(local_addr) = local_addr {
+                    let local_port = tcp_ephemeral_port(&mut tcp_next_port, &tcp_streams, &tcp_listeners);
+                    let local = IpEndpoint::new(local_addr, local_port);
+                    let mut socket = new_tcp_socket();
+                    let sid = SID::from_array(connect.cb_sid);
+                    match socket.connect(remote, local) {
+                        Ok(_) => match xous::connect(sid) {
+                            Ok(cid) => {
+                                tcp_next_id = tcp_next_id.wrapping_add(1);
+                                tcp_streams.insert(tcp_next_id, TcpStreamState {
+                                    handle: sockets.add(socket),
+                                    cid,
+                                    sid,
+                                    local_port,
+                                    connecting: true,
+                                    rx_waiting: false,
+                                    tx_waiting: false,
+                                });
+                                connect.id = Some(tcp_next_id);
+                                connect.local = Some(NetSocketAddr {
+                                    addr: NetIpAddr::from(local_addr),
+                                    port: local_port,
+                                });
+                                // the SYN goes out on the next pump
+                                xous::try_send_message(net_conn,
+                                    Message::new_scalar(
+                                        Opcode::NetPump.to_usize().unwrap(),
+                                        0, 0, 0, 0)
+                                ).ok();
+                                NetTcpResult::Ok
+                            }
+                            Err(e) => {
+                                log::error!("Tcp connect request had an invalid callback SID: {:?}", e);
+                                NetTcpResult::Invalid
+                            }
+                        },
+                        Err(e) => {
+                            log::error!("Tcp couldn't connect to {}: {:?}", remote, e);
+                            NetTcpResult::Invalid
+                        }
+                    }
+                } else {
+                    NetTcpResult::NotConnected
+                });
+                buf.replace(connect).expect("couldn't respond to Tcp connect");
+            },
+            Some(Opcode::TcpTx) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut tx = buf.to_original::<NetTcpTransfer, _>().unwrap();
+                tx.result = Some(match tcp_streams.get_mut(&tx.id) {
+                    Some(state) if state.sid == SID::from_array(tx.cb_sid) => {
+                        let mut socket = sockets.get::<TcpSocket>(state.handle);
+                        if socket.can_send() {
+                            // this takes only what fits in the socket's buffer, and the client tries again with the rest
+                            match socket.send_slice(&tx.data[..(tx.len as usize).min(TCP_CHUNK_LEN)]) {
+                                Ok(len) => {
+                                    tx.len = len as u16;
+                                    xous::try_send_message(net_conn,
+                                        Message::new_scalar(
+                                            Opcode::NetPump.to_usize().unwrap(),
+                                            0, 0, 0, 0)
+                                    ).ok();
+                                    NetTcpResult::Ok
+                                }
+                                Err(e) => {
+                                    log::error!("Tcp send error: {:?}", e);
+                                    NetTcpResult::Invalid
+                                }
+                            }
+                        } else if socket.may_send() || state.connecting {
+                            state.tx_waiting = true;
+                            NetTcpResult::WouldBlock
+                        } else {
+                            NetTcpResult::Closed
+                        }
+                    }
+                    _ => NetTcpResult::Invalid,
+                });
+                buf.replace(tx).expect("couldn't respond to Tcp transmit");
+            },
+            Some(Opcode::TcpRx) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut rx = buf.to_original::<NetTcpTransfer, _>().unwrap();
+                rx.result = Some(match tcp_streams.get_mut(&rx.id) {
+                    Some(state) if state.sid == SID::from_array(rx.cb_sid) => {
+                        let mut socket = sockets.get::<TcpSocket>(state.handle);
+                        if socket.can_recv() {
+                            let maxlen = (rx.len as usize).min(TCP_CHUNK_LEN);
+                            let received = if rx.peek {
+                                socket.peek_slice(&mut rx.data[..maxlen])
+                            } else {
+                                socket.recv_slice(&mut rx.data[..maxlen])
+                            };
+                            match received {
+                                Ok(len) => {
+                                    rx.len = len as u16;
+                                    // draining the buffer opens up the receive window, which the remote end should hear about
+                                    xous::try_send_message(net_conn,
+                                        Message::new_scalar(
+                                            Opcode::NetPump.to_usize().unwrap(),
+                                            0, 0, 0, 0)
+                                    ).ok();
+                                    NetTcpResult::Ok
+                                }
+                                Err(e) => {
+                                    log::error!("Tcp receive error: {:?}", e);
+                                    NetTcpResult::Invalid
+                                }
+                            }
+                        } else if socket.may_recv() || state.connecting {
+                            state.rx_waiting = true;
+                            NetTcpResult::WouldBlock
+                        } else {
+                            rx.len = 0;
+                            NetTcpResult::Closed
+                        }
+                    }
+                    _ => NetTcpResult::Invalid,
+                });
+                buf.replace(rx).expect("couldn't respond to Tcp receive");
+            },
+            Some(Opcode::TcpShutdown) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut shutdown = buf.to_original::<NetTcpStream, _>().unwrap();
+                shutdown.result = Some(match tcp_streams.get(&shutdown.id) {
+                    Some(state) if state.sid == SID::from_array(shutdown.cb_sid) => {
+                        // smoltcp's close() only closes our half of the connection, which is exactly a shutdown
+                        sockets.get::<TcpSocket>(state.handle).close();
+                        xous::try_send_message(net_conn,
+                            Message::new_scalar(
+                                Opcode::NetPump.to_usize().unwrap(),
+                                0, 0, 0, 0)
+                        ).ok();
+                        NetTcpResult::Ok
+                    }
+                    _ => NetTcpResult::Invalid,
+                });
+                buf.replace(shutdown).expect("couldn't respond to Tcp shutdown");
+            },
+            Some(Opcode::TcpClose) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut close = buf.to_original::<NetTcpStream, _>().unwrap();
+                close.result = Some(match tcp_streams.remove(&close.id) {
+                    Some(state) if state.sid == SID::from_array(close.cb_sid) => {
+                        let mut socket = sockets.get::<TcpSocket>(state.handle);
+                        socket.close();
+                        socket.set_timeout(Some(Duration::from_millis(TCP_CLOSE_TIMEOUT_MS)));
+                        tcp_closing.push(state.handle);
+                        cid_to_disconnect = Some(state.cid);
+                        xous::try_send_message(net_conn,
+                            Message::new_scalar(
+                                Opcode::NetPump.to_usize().unwrap(),
+                                0, 0, 0, 0)
+                        ).ok();
+                        NetTcpResult::Ok
+                    }
+                    Some(state) => {
+                        // not the owner: put it back
+                        tcp_streams.insert(close.id, state);
+                        NetTcpResult::Invalid
+                    }
+                    None => NetTcpResult::Invalid,
+                });
+                buf.replace(close).expect("couldn't respond to Tcp close");
+            },
+            Some(Opcode::TcpListen) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut listen = buf.to_original::<NetTcpListen, _>().unwrap();
+                if listen.local.port == 0 {
+                    listen.local.port = tcp_ephemeral_port(&mut tcp_next_port, &tcp_streams, &tcp_listeners);
+                }
+                listen.result = Some(if tcp_listeners.contains_key(&listen.local.port) {
+                    NetTcpResult::AddrInUse
+                } else {
+                    let sid = SID::from_array(listen.cb_sid);
+                    match xous::connect(sid) {
+                        Ok(cid) => {
+                            let mut listener = TcpListenerState {
+                                cid,
+                                sid,
+                                endpoint: IpEndpoint::new(IpAddress::from(listen.local.addr), listen.local.port),
+                                backlog: Vec::new(),
+                                accepted: VecDeque::new(),
+                                accept_waiting: false,
+                            };
+                            listener.fill_backlog(&mut sockets);
+                            if listener.backlog.is_empty() {
+                                cid_to_disconnect = Some(cid);
+                                NetTcpResult::Invalid
+                            } else {
+                                tcp_listeners.insert(listen.local.port, listener);
+                                NetTcpResult::Ok
+                            }
+                        }
+                        Err(e) => {
+                            log::error!("Tcp listen request had an invalid callback SID: {:?}", e);
+                            NetTcpResult::Invalid
+                        }
+                    }
+                });
+                buf.replace(listen).expect("couldn't respond to Tcp listen");
+            },
+            Some(Opcode::TcpAccept) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut accept = buf.to_original::<NetTcpAccept, _>().unwrap();
+                accept.result = Some(match tcp_listeners.get_mut(&accept.port) {
+                    Some(listener) if listener.sid == SID::from_array(accept.listener_sid) => {
+                        // connections that were reset while they waited are thrown away
+                        while let Some(&handle) = listener.accepted.front() {
+                            if sockets.get::<TcpSocket>(handle).remote_endpoint().is_specified() {
+                                break;
+                            }
+                            listener.accepted.pop_front();
+                            tcp_closing.push(handle);
+                        }
+                        if listener.accepted.is_empty() {
+                            listener.accept_waiting = true;
+                            NetTcpResult::WouldBlock
+                        } else {
+                            let sid = SID::from_array(accept.cb_sid);
+                            match xous::connect(sid) {
+                                Ok(cid) => {
+                                    let handle = listener.accepted.pop_front().unwrap(); // checked above
+                                    let remote = sockets.get::<TcpSocket>(handle).remote_endpoint();
+                                    tcp_next_id = tcp_next_id.wrapping_add(1);
+                                    tcp_streams.insert(tcp_next_id, TcpStreamState {
+                                        handle,
+                                        cid,
+                                        sid,
+                                        local_port: accept.port,
+                                        connecting: false,
+                                        rx_waiting: false,
+                                        tx_waiting: false,
+                                    });
+                                    listener.fill_backlog(&mut sockets);
+                                    accept.id = Some(tcp_next_id);
+                                    accept.remote = Some(NetSocketAddr {
+                                        addr: NetIpAddr::from(remote.addr),
+                                        port: remote.port,
+                                    });
+                                    NetTcpResult::Ok
+                                }
+                                Err(e) => {
+                                    log::error!("Tcp accept request had an invalid callback SID: {:?}", e);
+                                    NetTcpResult::Invalid
+                                }
+                            }
+                        }
+                    }
+                    _ => NetTcpResult::Invalid,
+                });
+                buf.replace(accept).expect("couldn't respond to Tcp accept");
+            },
+            Some(Opcode::TcpListenClose) => {
+                let mut buf = unsafe{Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())};
+                let mut listen = buf.to_original::<NetTcpListen, _>().unwrap();
+                listen.result = Some(match tcp_listeners.remove(&listen.local.port) {
+                    Some(listener) if listener.sid == SID::from_array(listen.cb_sid) => {
+                        for &handle in listener.backlog.iter().chain(listener.accepted.iter()) {
+                            sockets.get::<TcpSocket>(handle).abort();
+                            tcp_closing.push(handle);
+                        }
+                        cid_to_disconnect = Some(listener.cid);
+                        xous::try_send_message(net_conn,
+                            Message::new_scalar(
+                                Opcode::NetPump.to_usize().unwrap(),
+                                0, 0, 0, 0)
+                        ).ok();
+                        NetTcpResult::Ok
+                    }
+                    Some(listener) => {
+                        // not the owner: put it back
+                        tcp_listeners.insert(listen.local.port, listener);
+                        NetTcpResult::Invalid
+                    }
+                    None => NetTcpResult::Invalid,
+                });
+                buf.replace(listen).expect("couldn't respond to Tcp listen close");
+            },
 
             Some(Opcode::ComInterrupt) => {
                 com_int_list.clear();
@@ -549,67 +1501,8 @@ fn xmain() -> ! {
                             log::warn!("Battery is critical! TODO: go into SHIP mode");
                         },
                         ComIntSources::WlanIpConfigUpdate => {
-                            // right now the WLAN implementation only does IPV4. So IPV6 compatibility ends here.
-                            // if IPV6 gets added to the EC/COM bus, ideally this is one of a couple spots in Xous that needs a tweak.
-                            let config = com.wlan_get_config().expect("couldn't retrieve updated ipv4 config");
-                            log::info!("Network config acquired: {:?}", config);
-                            net_config = Some(config);
-                            let mac = EthernetAddress::from_bytes(&config.mac);
-
-                            // we need to clear the ARP cache in case we've migrated base stations (e.g. in a wireless network
-                            // that is coverd by multiple AP), as the host AP's MAC address would have changed, and we wouldn't
-                            // be able to route responses back. I can't seem to find a function in smoltcp 0.7.5 that allows us
-                            // to neatly clear the ARP cache as the BTreeMap that underlies it is moved into the container and
-                            // no "clear" API is exposed, so let's just rebuild the whole interface if we get a DHCP renewal.
-                            let neighbor_cache = NeighborCache::new(BTreeMap::new());
-                            let ip_addrs = [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)];
-                            let routes = Routes::new(BTreeMap::new());
-                            let device = device::NetPhy::new(&xns);
-                            let medium = device.capabilities().medium;
-                            let mut builder = InterfaceBuilder::new(device)
-                                .ip_addrs(ip_addrs)
-                                .routes(routes);
-                            if medium == Medium::Ethernet {
-                                builder = builder
-                                    .ethernet_addr(mac)
-                                    .neighbor_cache(neighbor_cache);
-                            }
-                            iface = builder.finalize();
-
-                            let ip_addr =
-                                Ipv4Cidr::new(Ipv4Address::new(
-                                    config.addr[0],
-                                    config.addr[1],
-                                    config.addr[2],
-                                    config.addr[3],
-                                ), 24);
-                            set_ipv4_addr(&mut iface, ip_addr);
-                            let default_v4_gw = Ipv4Address::new(
-                                config.gtwy[0],
-                                config.gtwy[1],
-                                config.gtwy[2],
-                                config.gtwy[3],
-                            );
-
-                            // reset the default route, in case it has changed
-                            iface.routes_mut().remove_default_ipv4_route();
-                            match iface.routes_mut().add_default_ipv4_route(default_v4_gw) {
-                                Ok(route) => log::info!("routing table updated successfully [{:?}]", route),
-                                Err(e) => log::error!("routing table update error: {}", e),
-                            }
-                            dns_allclear_hook.notify();
-                            dns_ipv4_hook.notify_custom_args([
-                                Some(u32::from_be_bytes(config.dns1)),
-                                None, None, None,
-                            ]);
-                            // the current implementation always returns 0.0.0.0 as the second dns,
-                            // ignore this if that's what we've got; otherwise, pass it on.
-                            if config.dns2 != [0, 0, 0, 0] {
-                                dns_ipv4_hook.notify_custom_args([
-                                    Some(u32::from_be_bytes(config.dns2)),
-                                    None, None, None,
-                                ]);
-                            }
+                            // taken up at the top of the loop, as soon as this message is done
+                            ip_config_pending = true;
                         },
                         ComIntSources::WlanRxReady => {
                             if let Some(_config) = net_config {
@@ -677,15 +1570,7 @@ fn xmain() -> ! {
                                 for (&src, dst) in data.iter().zip(response.data.iter_mut()) {
                                     *dst = src;
                                 }
-                                let buf = Buffer::into_buf(response).expect("couldn't convert UDP response to memory message");
-                                buf.send(udpstate.cid, NetUdpCallback::RxData.to_u32().unwrap()).expect("couldn't send UDP response");
-                                // now send copies to the cloned receiver array, if they exist
-                                if let Some(clone_map) = udp_clones.get(port) {
-                                    for &cids in clone_map.values() {
-                                        let buf = Buffer::into_buf(response).expect("couldn't convert UDP response to memory message");
-                                        buf.send(cids, NetUdpCallback::RxData.to_u32().unwrap()).expect("couldn't send UDP response");
-                                    }
-                                }
+                                udp_deliver(udpstate, udp_clones.get(port), response);
                             }
                             Err(_) => {
                                 // do nothing
@@ -694,6 +1579,75 @@ fn xmain() -> ! {
                     }
                 }
 
+                // this block handles TCP. Data moves when clients ask for it, so all that happens here is
+                // waking up clients that were told to wait, queuing new connections for listeners, and
+                // freeing sockets that have finished closing. A client that can't be told yet is tried
+                // again on a later pump.
+                let mut tcp_notify_retry = false;
+                {
+                    for state in tcp_streams.values_mut() {
+                        let socket = sockets.get::<TcpSocket>(state.handle);
+                        if state.connecting {
+                            if socket.state() == TcpState::SynSent {
+                                continue;
+                            }
+                            state.connecting = !tcp_notify(state.cid, NetTcpCallback::Connected, if socket.may_send() { 1 } else { 0 });
+                            if state.connecting {
+                                tcp_notify_retry = true;
+                                continue;
+                            }
+                        }
+                        if state.rx_waiting && (socket.can_recv() || !socket.may_recv()) {
+                            state.rx_waiting = !tcp_notify(state.cid, NetTcpCallback::RxReady, 0);
+                            tcp_notify_retry |= state.rx_waiting;
+                        }
+                        if state.tx_waiting && (socket.can_send() || !socket.may_send()) {
+                            state.tx_waiting = !tcp_notify(state.cid, NetTcpCallback::TxReady, 0);
+                            tcp_notify_retry |= state.tx_waiting;
+                        }
+                    }
+                    for listener in tcp_listeners.values_mut() {
+                        let (listening, connected): (Vec<SocketHandle>, Vec<SocketHandle>) =
+                            listener.backlog.drain(..).partition(|&handle|
+                                match sockets.get::<TcpSocket>(handle).state() {
+                                    TcpState::Listen | TcpState::SynReceived => true,
+                                    _ => false,
+                                }
+                            );
+                        listener.backlog = listening;
+                        listener.accepted.extend(connected);
+                        listener.fill_backlog(&mut sockets);
+                        if listener.accept_waiting && !listener.accepted.is_empty() {
+                            listener.accept_waiting = !tcp_notify(listener.cid, NetTcpCallback::Incoming, 0);
+                            tcp_notify_retry |= listener.accept_waiting;
+                        }
+                    }
+                    tcp_closing.retain(|&handle|
+                        if sockets.get::<TcpSocket>(handle).state() == TcpState::Closed {
+                            sockets.remove(handle);
+                            false
+                        } else {
+                            true
+                        }
+                    );
+                }
+
+                // this block handles IPv6 router advertisements, which configure our global address (SLAAC)
+                {
+                    let mut socket = sockets.get::<RawSocket>(ndisc_handle);
+                    let mut changed = false;
+                    while let Ok(packet) = socket.recv() {
+                        if let (Some((router, advert)), Some(config)) = (parse_ndisc(packet, &device_caps.checksum), net_config) {
+                            changed |= ipv6_config.router_advert(&config.mac, router, &advert);
+                        }
+                    }
+                    if changed {
+                        log::info!("IPv6 config updated: address {:?} via {:?}", ipv6_config.global, ipv6_config.router);
+                        ipv6_config.apply(&mut iface);
+                        dns_ipv6_connectivity_hook.notify_custom_args([Some(ipv6_config.connected() as u32), None, None, None]);
+                    }
+                }
+
                 // this block contains the ICMP Rx handler. Tx is initiated by an incoming message to the Net crate.
                 {
                     let mut socket = sockets.get::<IcmpSocket>(icmp_handle);
@@ -702,7 +1656,7 @@ fn xmain() -> ! {
                     }
 
                     if socket.can_recv() {
-                        let (payload, _) = socket.recv().expect("couldn't receive on socket despite asserting availability");
+                        let (payload, from) = socket.recv().expect("couldn't receive on socket despite asserting availability");
                         log::trace!("icmp payload: {:x?}", payload);
                         let now = timer.elapsed_ms();
 
@@ -764,16 +1718,29 @@ fn xmain() -> ! {
                                 }
 
                                 IpAddress::Ipv6(_) => {
-                                    // NOTE: actually not sure what src_ipv6 should be. This is just from an example.
-                                    let src_ipv6 = IpAddress::v6(0xfdaa, 0, 0, 0, 0, 0, 0, 1);
-                                    let icmp_packet = Icmpv6Packet::new_checked(&payload).unwrap();
-                                    let icmp_repr = Icmpv6Repr::parse(
-                                        &remote_addr,
-                                        &src_ipv6,
-                                        &icmp_packet,
-                                        &device_caps.checksum,
-                                    )
-                                    .unwrap();
+                                    // the checksum covers both addresses, so the reply can only be checked
+                                    // against the ping that was sent to where it came from
+                                    if from != remote_addr {
+                                        continue;
+                                    }
+                                    let src_ipv6 = match ipv6_config.source() {
+                                        Some(src) => IpAddress::Ipv6(src),
+                                        None => continue,
+                                    };
+                                    let icmp_repr = match Icmpv6Packet::new_checked(&payload).and_then(|icmp_packet|
+                                        Icmpv6Repr::parse(
+                                            &remote_addr,
+                                            &src_ipv6,
+                                            &icmp_packet,
+                                            &device_caps.checksum,
+                                        )
+                                    ) {
+                                        Ok(icmp_repr) => icmp_repr,
+                                        Err(e) => {
+                                            log::warn!("couldn't parse ICMPv6 reply from {:?}: {:?}", remote_addr, e);
+                                            continue;
+                                        }
+                                    };
                                     let ra = remote_addr.as_bytes();
                                     if let Icmpv6Repr::EchoReply { seq_no, data, .. } = icmp_repr {
                                         if let Some(_) = waiting_queue.get(&seq_no) {
@@ -904,7 +1871,12 @@ fn xmain() -> ! {
 
                 // establish our next check-up interval
                 let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
-                if let Some(delay) = iface.poll_delay(&sockets, timestamp) {
+                let mut poll_delay = iface.poll_delay(&sockets, timestamp);
+                if tcp_notify_retry {
+                    let retry = Duration::from_millis(TCP_NOTIFY_RETRY_MS);
+                    poll_delay = Some(poll_delay.map_or(retry, |delay| delay.min(retry)));
+                }
+                if let Some(delay) = poll_delay {
                     let delay_ms = delay.total_millis();
                     if delay_ms < 2 {
                         xous::try_send_message(net_conn,
@@ -0,0 +1,267 @@
+use smoltcp::time::Instant;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use com::api::NET_MTU;
+
+/// Ring size used when the caller doesn't ask for one
+pub(crate) const PCAP_DEFAULT_BYTES: usize = 64 * 1024;
+/// Upper bound on the ring, as it comes out of the Net server's own heap
+pub(crate) const PCAP_MAX_BYTES: usize = 256 * 1024;
+/// pcap file header, then a per-frame record header
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_LEN: usize = 16;
+const LINKTYPE_ETHERNET: u32 = 1;
+/// bytes of the capture per line of log output
+const DUMP_LINE_BYTES: usize = 64;
+
+/// Frames seen by the `NetPhy`, kept until there's no room left, at which point the
+/// oldest ones go first. The interface is rebuilt on every IP config update, so the
+/// capture is shared with each new `NetPhy` rather than owned by one.
+pub(crate) struct Capture {
+    frames: VecDeque<(Instant, Vec<u8>)>,
+    /// bytes of frame data held in `frames`
+    bytes: usize,
+    limit: usize,
+    running: bool,
+    /// frames that were pushed out of the ring to make room for newer ones
+    overwritten: u32,
+}
+
+pub(crate) type SharedCapture = Rc<RefCell<Capture>>;
+
+impl Capture {
+    pub(crate) fn new_shared() -> SharedCapture {
+        Rc::new(RefCell::new(Capture {
+            frames: VecDeque::new(),
+            bytes: 0,
+            limit: PCAP_DEFAULT_BYTES,
+            running: false,
+            overwritten: 0,
+        }))
+    }
+    /// Throw away anything previously captured and start over with a ring of `limit` bytes
+    /// (0 picks the default). Returns the size of ring that was actually set up.
+    pub(crate) fn start(&mut self, limit: usize) -> usize {
+        self.limit = match limit {
+            0 => PCAP_DEFAULT_BYTES,
+            limit => limit.clamp(NET_MTU, PCAP_MAX_BYTES),
+        };
+        self.frames.clear();
+        self.frames.shrink_to_fit();
+        self.bytes = 0;
+        self.overwritten = 0;
+        self.running = true;
+        self.limit
+    }
+    /// Stop adding frames; what's been captured stays around to be dumped
+    pub(crate) fn stop(&mut self) {
+        self.running = false;
+    }
+    pub(crate) fn frames(&self) -> usize {
+        self.frames.len()
+    }
+    pub(crate) fn overwritten(&self) -> u32 {
+        self.overwritten
+    }
+    pub(crate) fn record(&mut self, timestamp: Instant, frame: &[u8]) {
+        if !self.running {
+            return;
+        }
+        while self.bytes + frame.len() > self.limit {
+            match self.frames.pop_front() {
+                Some((_, old)) => {
+                    self.bytes -= old.len();
+                    self.overwritten = self.overwritten.saturating_add(1);
+                }
+                None => break,
+            }
+        }
+        self.bytes += frame.len();
+        self.frames.push_back((timestamp, frame.to_vec()));
+    }
+    /// Write the capture to the log as a classic pcap file, hex-encoded into `PCAP`
+    /// lines. To get a file Wireshark can open out of a copy of the log:
+    ///
+    /// `sed -n 's/.*PCAP //p' uart.log | xxd -r -p > net.pcap`
+    pub(crate) fn dump(&self) {
+        log::info!(
+            "PCAP-BEGIN {} frames, {} overwritten",
+            self.frames.len(),
+            self.overwritten
+        );
+        let mut line = Vec::<u8>::with_capacity(DUMP_LINE_BYTES + NET_MTU + PCAP_RECORD_LEN);
+        self.write_pcap(|bytes| {
+            line.extend_from_slice(bytes);
+            while line.len() >= DUMP_LINE_BYTES {
+                log_hex(&line[..DUMP_LINE_BYTES]);
+                line.drain(..DUMP_LINE_BYTES);
+            }
+        });
+        if !line.is_empty() {
+            log_hex(&line);
+        }
+        log::info!("PCAP-END");
+    }
+    /// Hand the capture to `write` as a classic pcap file, a piece at a time, so that the
+    /// whole file never has to be held in memory
+    fn write_pcap<F: FnMut(&[u8])>(&self, mut write: F) {
+        let mut header = [0u8; PCAP_HEADER_LEN];
+        header[0..4].copy_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        header[4..6].copy_from_slice(&2u16.to_le_bytes());
+        header[6..8].copy_from_slice(&4u16.to_le_bytes());
+        header[8..12].copy_from_slice(&0i32.to_le_bytes()); // timestamps count from boot, not from the epoch
+        header[12..16].copy_from_slice(&0u32.to_le_bytes());
+        header[16..20].copy_from_slice(&(NET_MTU as u32).to_le_bytes());
+        header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        write(&header);
+
+        for (timestamp, frame) in self.frames.iter() {
+            let millis = timestamp.total_millis().max(0) as u64;
+            let mut record = [0u8; PCAP_RECORD_LEN];
+            record[0..4].copy_from_slice(&((millis / 1000) as u32).to_le_bytes());
+            record[4..8].copy_from_slice(&(((millis % 1000) * 1000) as u32).to_le_bytes());
+            record[8..12].copy_from_slice(&(frame.len() as u32).to_le_bytes());
+            record[12..16].copy_from_slice(&(frame.len() as u32).to_le_bytes());
+            write(&record);
+            write(frame);
+        }
+    }
+}
+
+fn log_hex(data: &[u8]) {
+    let mut hex = std::string::String::with_capacity(data.len() * 2);
+    for byte in data {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    log::info!("PCAP {}", hex);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcap_bytes(capture: &Capture) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        capture.write_pcap(|piece| bytes.extend_from_slice(piece));
+        bytes
+    }
+
+    fn le32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn nothing_is_kept_until_started() {
+        let shared = Capture::new_shared();
+        let mut capture = shared.borrow_mut();
+        capture.record(Instant::from_millis(0), &[0; 64]);
+        assert_eq!(capture.frames(), 0);
+
+        assert_eq!(capture.start(0), PCAP_DEFAULT_BYTES);
+        capture.record(Instant::from_millis(1), &[0; 64]);
+        assert_eq!(capture.frames(), 1);
+
+        capture.stop();
+        capture.record(Instant::from_millis(2), &[0; 64]);
+        assert_eq!(capture.frames(), 1);
+    }
+
+    #[test]
+    fn ring_size_is_clamped() {
+        let shared = Capture::new_shared();
+        let mut capture = shared.borrow_mut();
+        assert_eq!(capture.start(1), NET_MTU);
+        assert_eq!(capture.start(NET_MTU * 3), NET_MTU * 3);
+        assert_eq!(capture.start(usize::MAX), PCAP_MAX_BYTES);
+    }
+
+    #[test]
+    fn oldest_frames_are_evicted_to_stay_within_the_limit() {
+        let shared = Capture::new_shared();
+        let mut capture = shared.borrow_mut();
+        let limit = capture.start(NET_MTU * 2);
+        for i in 0..4u8 {
+            capture.record(Instant::from_millis(i as i64), &[i; NET_MTU]);
+            assert!(capture.bytes <= limit);
+        }
+        assert_eq!(capture.frames(), 2);
+        assert_eq!(capture.overwritten(), 2);
+        assert_eq!(capture.bytes, NET_MTU * 2);
+        assert_eq!(capture.frames[0].1[0], 2);
+        assert_eq!(capture.frames[1].1[0], 3);
+
+        // one small frame still fits next to a full-sized one, but not next to two of them
+        capture.record(Instant::from_millis(4), &[4; NET_MTU / 2]);
+        assert_eq!(capture.frames(), 2);
+        assert_eq!(capture.overwritten(), 3);
+        assert_eq!(capture.bytes, NET_MTU + NET_MTU / 2);
+        capture.record(Instant::from_millis(5), &[5; NET_MTU / 2]);
+        assert_eq!(capture.frames(), 3);
+        assert_eq!(capture.overwritten(), 3);
+        assert_eq!(capture.bytes, NET_MTU * 2);
+    }
+
+    #[test]
+    fn starting_again_forgets_the_last_capture() {
+        let shared = Capture::new_shared();
+        let mut capture = shared.borrow_mut();
+        capture.start(NET_MTU);
+        capture.record(Instant::from_millis(0), &[0; NET_MTU]);
+        capture.record(Instant::from_millis(1), &[1; NET_MTU]);
+        assert_eq!(capture.overwritten(), 1);
+
+        capture.start(0);
+        assert_eq!(capture.frames(), 0);
+        assert_eq!(capture.overwritten(), 0);
+        assert_eq!(capture.bytes, 0);
+    }
+
+    #[test]
+    fn pcap_header_layout() {
+        let shared = Capture::new_shared();
+        let bytes = pcap_bytes(&shared.borrow());
+        assert_eq!(bytes.len(), PCAP_HEADER_LEN);
+        assert_eq!(&bytes[0..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(&bytes[4..8], &[2, 0, 4, 0]);
+        assert_eq!(le32(&bytes, 8), 0);
+        assert_eq!(le32(&bytes, 12), 0);
+        assert_eq!(le32(&bytes, 16), NET_MTU as u32);
+        assert_eq!(le32(&bytes, 20), LINKTYPE_ETHERNET);
+    }
+
+    #[test]
+    fn pcap_records_follow_the_header() {
+        let shared = Capture::new_shared();
+        let mut capture = shared.borrow_mut();
+        capture.start(0);
+        capture.record(Instant::from_millis(1_234), &[0xaa, 0xbb, 0xcc]);
+        capture.record(Instant::from_millis(60_000), &[0xdd]);
+        let bytes = pcap_bytes(&capture);
+        assert_eq!(bytes.len(), PCAP_HEADER_LEN + 2 * PCAP_RECORD_LEN + 4);
+
+        let record = &bytes[PCAP_HEADER_LEN..];
+        assert_eq!(le32(record, 0), 1);
+        assert_eq!(le32(record, 4), 234_000);
+        assert_eq!(le32(record, 8), 3);
+        assert_eq!(le32(record, 12), 3);
+        assert_eq!(
+            &record[PCAP_RECORD_LEN..PCAP_RECORD_LEN + 3],
+            &[0xaa, 0xbb, 0xcc]
+        );
+
+        let record = &record[PCAP_RECORD_LEN + 3..];
+        assert_eq!(le32(record, 0), 60);
+        assert_eq!(le32(record, 4), 0);
+        assert_eq!(le32(record, 8), 1);
+        assert_eq!(le32(record, 12), 1);
+        assert_eq!(&record[PCAP_RECORD_LEN..], &[0xdd]);
+    }
+}
@@ -1,6 +1,10 @@
 pub mod udp;
 pub use udp::*;
+pub mod tcp;
+pub use tcp::*;
 pub mod dns;
 pub use dns::*;
 pub mod ping;
-pub use ping::*;
\ No newline at end of file
+pub use ping::*;
+pub mod pcap;
+pub use pcap::*;
@@ -12,10 +12,12 @@ use crate::api::*;
 use num_traits::*;
 
 use std::collections::HashSet;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 pub struct DnsServerManager {
     net: NetConn,
     servers: Arc<Mutex<HashSet::<IpAddr>>>,
+    ipv6_connected: Arc<AtomicBool>,
     cb_sid: SID,
     handle: Option<JoinHandle::<()>>,
     freeze: bool,
@@ -27,10 +29,12 @@ impl DnsServerManager {
         let cb_sid = xous::create_server().unwrap();
 
         let servers = Arc::new(Mutex::new(HashSet::<IpAddr>::new()));
+        let ipv6_connected = Arc::new(AtomicBool::new(false));
 
         let handle = thread::spawn({
             let cb_sid_clone = cb_sid.clone();
             let servers = Arc::clone(&servers);
+            let ipv6_connected = Arc::clone(&ipv6_connected);
             move || {
                 loop {
                     let msg = xous::receive_message(cb_sid_clone).unwrap();
@@ -80,6 +84,9 @@ impl DnsServerManager {
                         Some(PrivateDnsOp::RemoveAllServers) => msg_scalar_unpack!(msg, _, _, _, _, {
                             servers.lock().unwrap().clear();
                         }),
+                        Some(PrivateDnsOp::Ipv6Connectivity) => msg_scalar_unpack!(msg, connected, _, _, _, {
+                            ipv6_connected.store(connected != 0, Ordering::SeqCst);
+                        }),
                         Some(PrivateDnsOp::Quit) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                             log::debug!("Quit received, exiting DNS notifier");
                             xous::return_scalar(msg.sender, 1).unwrap(); // actual return value doesn't matter -- it's that there is a return value
@@ -132,11 +139,25 @@ impl DnsServerManager {
             NetMemResponse::Ok => {},
             _ => success = false,
         }
+        let hook = XousPrivateServerHook {
+            one_time_sid: cb_sid.to_array(),
+            op: PrivateDnsOp::Ipv6Connectivity.to_usize().unwrap(),
+            args: [None; 4]
+        };
+        let mut buf = Buffer::into_buf(hook)
+            .or(Err(Error::new(ErrorKind::Other, "can't hook with Net server")))?;
+        buf.lend_mut(net.conn(), Opcode::DnsHookIpv6Connectivity.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "can't hook with Net server")))?;
+        match buf.to_original().unwrap() {
+            NetMemResponse::Ok => {},
+            _ => success = false,
+        }
         if success {
             Ok(DnsServerManager {
                 net,
                 cb_sid,
                 servers,
+                ipv6_connected,
                 handle: Some(handle),
                 freeze: false,
             })
@@ -169,6 +190,10 @@ impl DnsServerManager {
     pub fn set_freeze(&mut self, freeze: bool) {
         self.freeze = freeze;
     }
+    /// True if the Net server has a global IPv6 address and a router to reach other IPv6 hosts through
+    pub fn ipv6_connected(&self) -> bool {
+        self.ipv6_connected.load(Ordering::SeqCst)
+    }
     /// Get one of the DNS servers. Which one we get, we don't know!
     pub fn get_random(&self) -> Option<IpAddr> {
         if let Some(&addr) = self.servers.lock().unwrap().iter().next() {
@@ -0,0 +1,86 @@
+use xous::{Message, send_message};
+use xous_ipc::Buffer;
+use crate::NetConn;
+use crate::api::*;
+use num_traits::*;
+
+/// What a packet capture in the Net server is holding
+#[derive(Debug, Copy, Clone)]
+pub struct PcapStats {
+    /// frames in the capture ring
+    pub frames: usize,
+    /// older frames that were pushed out of the ring to make room
+    pub overwritten: usize,
+}
+
+/// Controls the Net server's packet capture, which tees every Ethernet frame the device
+/// sends or receives into a bounded ring. There is one capture for the whole server, and
+/// only one process gets to control it: the first to make a `Pcap`.
+pub struct Pcap {
+    net: NetConn,
+    token: [u32; 4],
+}
+
+impl Pcap {
+    /// Claims the capture. Only the first claim made after boot succeeds, so this should be
+    /// called early by the trusted process that will run captures; anyone else gets `AccessDenied`.
+    pub fn new(xns: &xous_names::XousNames) -> Result<Pcap, xous::Error> {
+        let net = NetConn::new(xns)?;
+        let token = xous::create_server_id()?.to_array();
+        let response = send_message(
+            net.conn(),
+            Message::new_blocking_scalar(
+                Opcode::PcapClaim.to_usize().unwrap(),
+                token[0] as usize,
+                token[1] as usize,
+                token[2] as usize,
+                token[3] as usize,
+            )
+        )?;
+        match response {
+            xous::Result::Scalar1(1) => Ok(Pcap { net, token }),
+            xous::Result::Scalar1(_) => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Throw away any previous capture and start a new one that keeps the most recent
+    /// `limit` bytes of frames; `None` picks a default. The ring is capped by the Net server,
+    /// so the size it really set up is returned.
+    pub fn start(&self, limit: Option<usize>) -> Result<usize, xous::Error> {
+        let response = self.request(Opcode::PcapStart, limit.unwrap_or(0).min(u32::MAX as usize) as u32)?;
+        Ok(response.limit as usize)
+    }
+    /// Stop capturing. The frames already captured are kept until the next `start`.
+    pub fn stop(&self) -> Result<PcapStats, xous::Error> {
+        self.stats(Opcode::PcapStop)
+    }
+    /// Write the capture out over the log UART as a hex-encoded pcap file. It can be
+    /// dumped while the capture is still running.
+    pub fn dump(&self) -> Result<PcapStats, xous::Error> {
+        self.stats(Opcode::PcapDump)
+    }
+    fn stats(&self, op: Opcode) -> Result<PcapStats, xous::Error> {
+        let response = self.request(op, 0)?;
+        Ok(PcapStats {
+            frames: response.frames as usize,
+            overwritten: response.overwritten as usize,
+        })
+    }
+    fn request(&self, op: Opcode, limit: u32) -> Result<NetPcap, xous::Error> {
+        let request = NetPcap {
+            token: self.token,
+            limit,
+            frames: 0,
+            overwritten: 0,
+            granted: false,
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.net.conn(), op.to_u32().unwrap())?;
+        let response = buf.to_original::<NetPcap, _>().unwrap();
+        if response.granted {
+            Ok(response)
+        } else {
+            Err(xous::Error::AccessDenied)
+        }
+    }
+}
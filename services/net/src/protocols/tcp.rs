@@ -0,0 +1,587 @@
+use std::io;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+
+use smoltcp::time::Duration;
+
+use xous::{Message, SID, msg_blocking_scalar_unpack, msg_scalar_unpack};
+use xous_ipc::Buffer;
+use crate::NetConn;
+use crate::api::*;
+use num_traits::*;
+
+/// How long `TcpStream::connect()` waits for the remote end, if no timeout is given
+const TCP_CONNECT_TIMEOUT_MS: u64 = 30_000;
+
+/// Events reported by the Net server, recorded by the callback thread until
+/// the socket's owner looks at them
+#[derive(Default)]
+struct TcpEvents {
+    connected: Option<bool>,
+    readable: bool,
+    writable: bool,
+    incoming: bool,
+}
+
+impl TcpEvents {
+    /// Note an event from the Net server. `arg` is the first argument of its scalar.
+    fn record(&mut self, event: &NetTcpCallback, arg: usize) {
+        match event {
+            NetTcpCallback::Connected => self.connected = Some(arg != 0),
+            NetTcpCallback::RxReady => self.readable = true,
+            NetTcpCallback::TxReady => self.writable = true,
+            NetTcpCallback::Incoming => self.incoming = true,
+            NetTcpCallback::Drop => (),
+        }
+    }
+}
+
+/// The single-use server that the Net server sends a socket's events to, and
+/// the thread that answers it.
+///
+/// Data never comes through here. Reads and writes are lends to the Net server,
+/// which moves only what fits in its socket buffers, so a slow reader closes
+/// the TCP window instead of piling data up in this process. When nothing can
+/// be moved, the Net server remembers that someone is waiting, and sends an
+/// event here once the socket is ready again.
+struct TcpCallback {
+    sid: SID,
+    handle: Option<JoinHandle::<()>>,
+    events: Arc<Mutex<TcpEvents>>,
+}
+
+impl TcpCallback {
+    fn new() -> Result<TcpCallback> {
+        let sid = xous::create_server()
+            .or(Err(Error::new(ErrorKind::Other, "can't create TCP callback server")))?;
+        let events = Arc::new(Mutex::new(TcpEvents::default()));
+        let handle = thread::spawn({
+            let events = Arc::clone(&events);
+            move || {
+                loop {
+                    let msg = xous::receive_message(sid).unwrap();
+                    match FromPrimitive::from_usize(msg.body.id()) {
+                        Some(NetTcpCallback::Drop) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                            log::debug!("Drop received, exiting Tcp handler");
+                            xous::return_scalar(msg.sender, 1).unwrap(); // actual return value doesn't matter -- it's that there is a return value
+                            break;
+                        }),
+                        Some(event) => msg_scalar_unpack!(msg, arg, _, _, _, {
+                            events.lock().unwrap().record(&event, arg);
+                        }),
+                        None => {
+                            log::error!("got unknown message type on Tcp callback: {:?}", msg);
+                        }
+                    }
+                }
+            }
+        });
+        Ok(TcpCallback {
+            sid,
+            handle: Some(handle),
+            events,
+        })
+    }
+
+    /// Yield until `ready` sees the event it is waiting for, giving up once the
+    /// ticktimer passes `deadline`.
+    fn wait<F>(&self, deadline: u64, ticktimer: &ticktimer_server::Ticktimer, ready: F) -> Result<()>
+    where
+        F: Fn(&TcpEvents) -> bool,
+    {
+        loop {
+            if ready(&self.events.lock().unwrap()) {
+                return Ok(());
+            }
+            if deadline < ticktimer.elapsed_ms() {
+                return Err(Error::new(ErrorKind::WouldBlock, "TCP timeout reached"));
+            }
+            xous::yield_slice();
+        }
+    }
+}
+
+impl Drop for TcpCallback {
+    fn drop(&mut self) {
+        let drop_cid = xous::connect(self.sid).unwrap();
+        xous::send_message(
+            drop_cid,
+            Message::new_blocking_scalar(NetTcpCallback::Drop.to_usize().unwrap(), 0, 0, 0, 0)
+        ).expect("couldn't send Drop to our repsonding server");
+        unsafe{xous::disconnect(drop_cid).unwrap()}; // should be safe because we're the only connection and the previous was a blocking scalar
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+        xous::destroy_server(self.sid).unwrap();
+    }
+}
+
+fn deadline_ms(timeout: Option<Duration>, ticktimer: &ticktimer_server::Ticktimer) -> u64 {
+    if let Some(to) = timeout {
+        to.total_millis() + ticktimer.elapsed_ms()
+    } else {
+        u64::MAX
+    }
+}
+
+fn checked_timeout(timeout: Option<Duration>) -> Result<Option<Duration>> {
+    match timeout {
+        Some(duration) if duration.total_millis() == 0 => {
+            Err(Error::new(ErrorKind::InvalidInput, "zero duration is not valid"))
+        }
+        _ => Ok(timeout),
+    }
+}
+
+///////// TcpStream implementation
+pub struct TcpStream {
+    net: NetConn,
+    callback: TcpCallback,
+    /// the Net server's handle for this connection
+    id: u32,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    ticktimer: ticktimer_server::Ticktimer,
+    nonblocking: bool,
+    read_shutdown: AtomicBool,
+}
+
+impl TcpStream {
+    /// Connect to the first address in `addr` that accepts the connection
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<TcpStream> {
+        let mut last_err = Error::new(ErrorKind::InvalidInput, "IP address invalid");
+        for socket_addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_inner(&socket_addr, None) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    pub fn connect_timeout(addr: &SocketAddr, timeout: Duration) -> Result<TcpStream> {
+        TcpStream::connect_inner(addr, checked_timeout(Some(timeout))?)
+    }
+
+    fn connect_inner(addr: &SocketAddr, timeout: Option<Duration>) -> Result<TcpStream> {
+        let xns = xous_names::XousNames::new().unwrap();
+        let net = NetConn::new(&xns).unwrap();
+        let callback = TcpCallback::new()?;
+
+        let request = NetTcpConnect {
+            cb_sid: callback.sid.to_array(),
+            remote: NetSocketAddr::from(*addr),
+            id: None,
+            local: None,
+            result: None,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "can't register with Net server")))?;
+        buf.lend_mut(net.conn(), Opcode::TcpConnect.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "can't register with Net server")))?;
+        let response = buf.to_original::<NetTcpConnect, _>().unwrap();
+        let (id, local) = match (response.result, response.id, response.local) {
+            (Some(NetTcpResult::Ok), Some(id), Some(local)) => (id, local),
+            (Some(NetTcpResult::NotConnected), _, _) => {
+                return Err(Error::new(ErrorKind::NotConnected, "network is not up"))
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "can't connect to that address")),
+        };
+        // from here on, dropping the stream tells the Net server to let go of the connection
+        let stream = TcpStream {
+            net,
+            callback,
+            id,
+            local_addr: SocketAddr::new(IpAddr::from(local.addr), local.port),
+            peer_addr: *addr,
+            read_timeout: None,
+            write_timeout: None,
+            ticktimer: ticktimer_server::Ticktimer::new().unwrap(),
+            nonblocking: false,
+            read_shutdown: AtomicBool::new(false),
+        };
+        let deadline = deadline_ms(
+            timeout.or(Some(Duration::from_millis(TCP_CONNECT_TIMEOUT_MS))),
+            &stream.ticktimer
+        );
+        stream.callback.wait(deadline, &stream.ticktimer, |events| events.connected.is_some())
+            .or(Err(Error::new(ErrorKind::TimedOut, "TCP connect timed out")))?;
+        if stream.callback.events.lock().unwrap().connected == Some(true) {
+            Ok(stream)
+        } else {
+            Err(Error::new(ErrorKind::ConnectionRefused, "TCP connection refused"))
+        }
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.peer_addr)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.read_timeout = checked_timeout(timeout)?;
+        Ok(())
+    }
+
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.write_timeout = checked_timeout(timeout)?;
+        Ok(())
+    }
+
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.read_timeout)
+    }
+
+    pub fn write_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.write_timeout)
+    }
+
+    pub fn set_nonblocking(&mut self, nb: bool) -> Result<()> {
+        self.nonblocking = nb;
+        Ok(())
+    }
+
+    pub fn get_nonblocking(&self) -> bool { self.nonblocking }
+
+    /// Shutting down reads only affects this side: anything the remote end
+    /// sends afterwards is held by the Net server until the stream is dropped.
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            self.read_shutdown.store(true, Ordering::SeqCst);
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            match self.stream_request(Opcode::TcpShutdown)? {
+                Some(NetTcpResult::Ok) => (),
+                _ => return Err(Error::new(ErrorKind::NotConnected, "TCP stream is not connected")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a request that names only this stream, returning the Net server's verdict
+    fn stream_request(&self, opcode: Opcode) -> Result<Option<NetTcpResult>> {
+        let request = NetTcpStream {
+            cb_sid: self.callback.sid.to_array(),
+            id: self.id,
+            result: None,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "can't send to Net server")))?;
+        buf.lend_mut(self.net.conn(), opcode.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "can't send to Net server")))?;
+        Ok(buf.to_original::<NetTcpStream, _>().unwrap().result)
+    }
+
+    pub fn peek(&self, buf: &mut [u8]) -> Result<usize> {
+        self.recv_inner(buf, true)
+    }
+
+    fn recv_inner(&self, buf: &mut [u8], do_peek: bool) -> Result<usize> {
+        if buf.len() == 0 || self.read_shutdown.load(Ordering::SeqCst) {
+            return Ok(0);
+        }
+        let deadline = deadline_ms(self.read_timeout, &self.ticktimer);
+        loop {
+            // clear the flag before asking, so that a wakeup for this request isn't lost
+            self.callback.events.lock().unwrap().readable = false;
+            let request = NetTcpTransfer {
+                cb_sid: self.callback.sid.to_array(),
+                id: self.id,
+                len: buf.len().min(TCP_CHUNK_LEN) as u16,
+                peek: do_peek,
+                result: None,
+                data: [0; TCP_CHUNK_LEN],
+            };
+            let mut lend = Buffer::into_buf(request)
+                .or(Err(Error::new(ErrorKind::Other, "can't send to Net server")))?;
+            lend.lend_mut(self.net.conn(), Opcode::TcpRx.to_u32().unwrap())
+                .or(Err(Error::new(ErrorKind::Other, "can't send to Net server")))?;
+            let response = lend.to_original::<NetTcpTransfer, _>().unwrap();
+            match response.result {
+                Some(NetTcpResult::Ok) => {
+                    let len = response.len as usize;
+                    buf[..len].copy_from_slice(&response.data[..len]);
+                    return Ok(len);
+                }
+                Some(NetTcpResult::Closed) => return Ok(0),
+                Some(NetTcpResult::WouldBlock) => {
+                    if self.nonblocking {
+                        return Err(Error::new(ErrorKind::WouldBlock, "Nonblocking mode: Rx is empty"));
+                    }
+                    self.callback.wait(deadline, &self.ticktimer, |events| events.readable)?;
+                }
+                _ => return Err(Error::new(ErrorKind::NotConnected, "TCP stream is not connected")),
+            }
+        }
+    }
+
+    fn send_inner(&self, buf: &[u8]) -> Result<usize> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        let deadline = deadline_ms(self.write_timeout, &self.ticktimer);
+        let len = buf.len().min(TCP_CHUNK_LEN);
+        let mut request = NetTcpTransfer {
+            cb_sid: self.callback.sid.to_array(),
+            id: self.id,
+            len: len as u16,
+            peek: false,
+            result: None,
+            data: [0; TCP_CHUNK_LEN],
+        };
+        request.data[..len].copy_from_slice(&buf[..len]);
+        loop {
+            self.callback.events.lock().unwrap().writable = false;
+            let mut lend = Buffer::into_buf(request)
+                .or(Err(Error::new(ErrorKind::Other, "can't send to Net server")))?;
+            lend.lend_mut(self.net.conn(), Opcode::TcpTx.to_u32().unwrap())
+                .or(Err(Error::new(ErrorKind::Other, "can't send to Net server")))?;
+            let response = lend.to_original::<NetTcpTransfer, _>().unwrap();
+            match response.result {
+                // this may be less than was asked for, if the socket's buffer is nearly full
+                Some(NetTcpResult::Ok) => return Ok(response.len as usize),
+                Some(NetTcpResult::Closed) => {
+                    return Err(Error::new(ErrorKind::BrokenPipe, "TCP stream was shut down for writing"))
+                }
+                Some(NetTcpResult::WouldBlock) => {
+                    if self.nonblocking {
+                        return Err(Error::new(ErrorKind::WouldBlock, "Nonblocking mode: Tx is full"));
+                    }
+                    self.callback.wait(deadline, &self.ticktimer, |events| events.writable)?;
+                }
+                _ => return Err(Error::new(ErrorKind::NotConnected, "TCP stream is not connected")),
+            }
+        }
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.recv_inner(buf, false)
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.send_inner(buf)
+    }
+
+    /// Writes are handed straight to the Net server, so there's nothing to flush
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for TcpStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpStream")
+            .field("addr", &self.local_addr)
+            .field("peer", &self.peer_addr)
+            .finish()
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        // the Net server finishes closing the connection in the background, and won't send
+        // anything else to our callback server; the callback server is torn down after this
+        self.stream_request(Opcode::TcpClose).expect("can't unregister with Net server");
+    }
+}
+
+///////// TcpListener implementation
+pub struct TcpListener {
+    net: NetConn,
+    callback: TcpCallback,
+    local_addr: SocketAddr,
+    ticktimer: ticktimer_server::Ticktimer,
+    nonblocking: bool,
+}
+
+impl TcpListener {
+    /// Listen on the first address in `addr`. A port of 0 picks any free port,
+    /// which `local_addr()` will then report.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<TcpListener> {
+        let socket_addr = match addr.to_socket_addrs()?.next() {
+            Some(socket_addr) => socket_addr,
+            None => return Err(Error::new(ErrorKind::InvalidInput, "IP address invalid")),
+        };
+        let xns = xous_names::XousNames::new().unwrap();
+        let net = NetConn::new(&xns).unwrap();
+        let callback = TcpCallback::new()?;
+
+        let request = NetTcpListen {
+            cb_sid: callback.sid.to_array(),
+            local: NetSocketAddr::from(socket_addr),
+            result: None,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "can't register with Net server")))?;
+        buf.lend_mut(net.conn(), Opcode::TcpListen.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "can't register with Net server")))?;
+        let response = buf.to_original::<NetTcpListen, _>().unwrap();
+        match response.result {
+            Some(NetTcpResult::Ok) => {
+                Ok(TcpListener {
+                    net,
+                    callback,
+                    local_addr: SocketAddr::new(socket_addr.ip(), response.local.port),
+                    ticktimer: ticktimer_server::Ticktimer::new().unwrap(),
+                    nonblocking: false,
+                })
+            }
+            Some(NetTcpResult::AddrInUse) => Err(Error::new(ErrorKind::AddrInUse, "TCP port is already in use")),
+            _ => Err(Error::new(ErrorKind::Other, "can't register with Net server")),
+        }
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    pub fn set_nonblocking(&mut self, nb: bool) -> Result<()> {
+        self.nonblocking = nb;
+        Ok(())
+    }
+
+    pub fn get_nonblocking(&self) -> bool { self.nonblocking }
+
+    pub fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        let callback = TcpCallback::new()?;
+        loop {
+            self.callback.events.lock().unwrap().incoming = false;
+            let request = NetTcpAccept {
+                listener_sid: self.callback.sid.to_array(),
+                port: self.local_addr.port(),
+                cb_sid: callback.sid.to_array(),
+                id: None,
+                remote: None,
+                result: None,
+            };
+            let mut buf = Buffer::into_buf(request)
+                .or(Err(Error::new(ErrorKind::Other, "can't send to Net server")))?;
+            buf.lend_mut(self.net.conn(), Opcode::TcpAccept.to_u32().unwrap())
+                .or(Err(Error::new(ErrorKind::Other, "can't send to Net server")))?;
+            let response = buf.to_original::<NetTcpAccept, _>().unwrap();
+            match (response.result, response.id, response.remote) {
+                (Some(NetTcpResult::Ok), Some(id), Some(remote)) => {
+                    let xns = xous_names::XousNames::new().unwrap();
+                    let peer_addr = SocketAddr::new(IpAddr::from(remote.addr), remote.port);
+                    let stream = TcpStream {
+                        net: NetConn::new(&xns).unwrap(),
+                        callback,
+                        id,
+                        local_addr: self.local_addr,
+                        peer_addr,
+                        read_timeout: None,
+                        write_timeout: None,
+                        ticktimer: ticktimer_server::Ticktimer::new().unwrap(),
+                        nonblocking: false,
+                        read_shutdown: AtomicBool::new(false),
+                    };
+                    return Ok((stream, peer_addr));
+                }
+                (Some(NetTcpResult::WouldBlock), _, _) => {
+                    if self.nonblocking {
+                        return Err(Error::new(ErrorKind::WouldBlock, "Nonblocking mode: no connection waiting"));
+                    }
+                    self.callback.wait(u64::MAX, &self.ticktimer, |events| events.incoming)?;
+                }
+                _ => return Err(Error::new(ErrorKind::Other, "can't accept from Net server")),
+            }
+        }
+    }
+
+    /// Iterate over connections as they're accepted. The iterator never ends.
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+}
+
+impl std::fmt::Debug for TcpListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpListener")
+            .field("addr", &self.local_addr)
+            .finish()
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let request = NetTcpListen {
+            cb_sid: self.callback.sid.to_array(),
+            local: NetSocketAddr::from(self.local_addr),
+            result: None,
+        };
+        let mut buf = Buffer::into_buf(request).expect("can't unregister with Net server");
+        buf.lend_mut(self.net.conn(), Opcode::TcpListenClose.to_u32().unwrap()).expect("can't unregister with Net server");
+        match buf.to_original::<NetTcpListen, _>().unwrap().result {
+            Some(NetTcpResult::Ok) => (),
+            _ => panic!("Couldn't unregister with net server"),
+        }
+    }
+}
+
+pub struct Incoming<'a> {
+    listener: &'a TcpListener,
+}
+
+impl<'a> Iterator for Incoming<'a> {
+    type Item = io::Result<TcpStream>;
+    fn next(&mut self) -> Option<io::Result<TcpStream>> {
+        Some(self.listener.accept().map(|(stream, _)| stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_recorded_until_cleared() {
+        let mut events = TcpEvents::default();
+        events.record(&NetTcpCallback::RxReady, 0);
+        events.record(&NetTcpCallback::Incoming, 0);
+        assert!(events.readable && events.incoming);
+        assert!(!events.writable);
+        assert_eq!(events.connected, None);
+
+        events.record(&NetTcpCallback::TxReady, 0);
+        events.record(&NetTcpCallback::Drop, 0);
+        assert!(events.readable && events.writable && events.incoming);
+    }
+
+    #[test]
+    fn connected_reports_whether_the_connection_was_made() {
+        let mut events = TcpEvents::default();
+        events.record(&NetTcpCallback::Connected, 0);
+        assert_eq!(events.connected, Some(false));
+        events.record(&NetTcpCallback::Connected, 1);
+        assert_eq!(events.connected, Some(true));
+    }
+
+    #[test]
+    fn zero_timeouts_are_rejected() {
+        assert_eq!(checked_timeout(None).unwrap(), None);
+        let timeout = Some(Duration::from_millis(5));
+        assert_eq!(checked_timeout(timeout).unwrap(), timeout);
+        let err = checked_timeout(Some(Duration::from_millis(0))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn requests_fit_in_a_page() {
+        // each request is lent to the Net server in a single page of memory
+        assert!(core::mem::size_of::<<NetTcpTransfer as rkyv::Archive>::Archived>() <= 4096);
+        assert!(core::mem::size_of::<<NetTcpStream as rkyv::Archive>::Archived>() <= 4096);
+    }
+}
@@ -1,4 +1,3 @@
-use std::convert::TryInto;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 use std::unimplemented;
 use std::io;
@@ -250,16 +249,7 @@ impl UdpSocket {
                         IpAddr::V4(Ipv4Addr::new(ipv4.0[0], ipv4.0[1], ipv4.0[2], ipv4.0[3]))
                     },
                     IpAddress::Ipv6(ipv6) => {
-                        IpAddr::V6(Ipv6Addr::new(
-                            u16::from_be_bytes(ipv6.0[0..1].try_into().unwrap()),
-                            u16::from_be_bytes(ipv6.0[2..3].try_into().unwrap()),
-                            u16::from_be_bytes(ipv6.0[4..5].try_into().unwrap()),
-                            u16::from_be_bytes(ipv6.0[6..7].try_into().unwrap()),
-                            u16::from_be_bytes(ipv6.0[8..9].try_into().unwrap()),
-                            u16::from_be_bytes(ipv6.0[10..11].try_into().unwrap()),
-                            u16::from_be_bytes(ipv6.0[12..13].try_into().unwrap()),
-                            u16::from_be_bytes(ipv6.0[14..15].try_into().unwrap()),
-                        ))
+                        IpAddr::V6(Ipv6Addr::from(ipv6.0))
                     },
                     _ => {
                         panic!("malformed endpoint record");
@@ -358,6 +348,7 @@ impl UdpSocket {
             .or(Err(Error::new(ErrorKind::Other, "can't send to Net server")))?;
         match buf.to_original().unwrap() {
             NetMemResponse::Sent(len) => Ok(len as usize),
+            NetMemResponse::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "set_broadcast() is needed to send broadcasts")),
             _ => Err(Error::new(ErrorKind::Other, "send failed")),
         }
     }
@@ -398,7 +389,7 @@ impl UdpSocket {
         send_message(
             self.net.conn(),
             Message::new_scalar(Opcode::UdpSetTtl.to_usize().unwrap(), ttl as usize, self.socket_addr.port() as usize, 0, 0)
-        ).map_or_else(|_| Ok(()), |_| Err(Error::new(ErrorKind::ConnectionRefused, "can't send TTL set message")))
+        ).map(|_| ()).or(Err(Error::new(ErrorKind::ConnectionRefused, "can't send TTL set message")))
     }
 
     pub fn ttl(&self) -> io::Result<u32> {
@@ -423,53 +414,124 @@ impl UdpSocket {
         Ok(())
     }
 
-    //////// the remaining functions are currently unimplemented: we don't have multicast or broadcast support //////////
-    pub fn set_broadcast(&self, _: bool) -> io::Result<()> {
-        unimplemented!("work in progress")
+    fn set_option(&self, option: NetUdpOption, value: usize) -> io::Result<()> {
+        let result = send_message(
+            self.net.conn(),
+            Message::new_blocking_scalar(Opcode::UdpSetOption.to_usize().unwrap(), self.socket_addr.port() as usize, option.to_usize().unwrap(), value, 0)
+        ).or(Err(Error::new(ErrorKind::ConnectionRefused, "can't send option to Net server")))?;
+        match result {
+            xous::Result::Scalar1(1) => Ok(()),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "Net server rejected the option")),
+        }
+    }
+
+    fn option(&self, option: NetUdpOption) -> io::Result<usize> {
+        let result = send_message(
+            self.net.conn(),
+            Message::new_blocking_scalar(Opcode::UdpGetOption.to_usize().unwrap(), self.socket_addr.port() as usize, option.to_usize().unwrap(), 0, 0)
+        ).or(Err(Error::new(ErrorKind::ConnectionRefused, "can't get option from Net server")))?;
+        match result {
+            xous::Result::Scalar1(value) if value != usize::MAX => Ok(value),
+            _ => Err(Error::new(ErrorKind::NotConnected, "socket is not bound")),
+        }
+    }
+
+    /// Broadcasts can always be received, but can only be sent once this is set.
+    /// Like the TTL, this belongs to the port, so it affects clones of this socket too.
+    pub fn set_broadcast(&self, broadcast: bool) -> io::Result<()> {
+        self.set_option(NetUdpOption::Broadcast, broadcast as usize)
     }
 
     pub fn broadcast(&self) -> io::Result<bool> {
-        unimplemented!("work in progress")
+        Ok(self.option(NetUdpOption::Broadcast)? != 0)
     }
 
-    pub fn set_multicast_loop_v4(&self, _: bool) -> io::Result<()> {
-        unimplemented!("work in progress")
+    pub fn set_multicast_loop_v4(&self, multicast_loop_v4: bool) -> io::Result<()> {
+        self.set_option(NetUdpOption::MulticastLoopV4, multicast_loop_v4 as usize)
     }
 
     pub fn multicast_loop_v4(&self) -> io::Result<bool> {
-        unimplemented!("work in progress")
+        Ok(self.option(NetUdpOption::MulticastLoopV4)? != 0)
     }
 
-    pub fn set_multicast_ttl_v4(&self, _: u32) -> io::Result<()> {
-        unimplemented!("work in progress")
+    pub fn set_multicast_ttl_v4(&self, multicast_ttl_v4: u32) -> io::Result<()> {
+        if multicast_ttl_v4 > 255 {
+            return Err(Error::new(ErrorKind::InvalidInput, "TTL must be less than 256"))
+        }
+        self.set_option(NetUdpOption::MulticastTtlV4, multicast_ttl_v4 as usize)
     }
 
     pub fn multicast_ttl_v4(&self) -> io::Result<u32> {
-        unimplemented!("work in progress")
+        Ok(self.option(NetUdpOption::MulticastTtlV4)? as u32)
     }
 
-    pub fn join_multicast_v4(&self, _: &Ipv4Addr, _: &Ipv4Addr) -> io::Result<()> {
-        unimplemented!("work in progress")
+    /// There is only one network interface, so `interface` is ignored.
+    pub fn join_multicast_v4(&self, multiaddr: &Ipv4Addr, _interface: &Ipv4Addr) -> io::Result<()> {
+        if !multiaddr.is_multicast() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not a multicast address"));
+        }
+        let result = send_message(
+            self.net.conn(),
+            Message::new_blocking_scalar(Opcode::UdpJoinMulticastV4.to_usize().unwrap(), self.socket_addr.port() as usize, u32::from(*multiaddr) as usize, 0, 0)
+        ).or(Err(Error::new(ErrorKind::ConnectionRefused, "can't send multicast join to Net server")))?;
+        match result {
+            xous::Result::Scalar1(1) => Ok(()),
+            _ => Err(Error::new(ErrorKind::AddrInUse, "couldn't join multicast group, or already joined")),
+        }
     }
 
-    pub fn leave_multicast_v4(&self, _: &Ipv4Addr, _: &Ipv4Addr) -> io::Result<()> {
-        unimplemented!("work in progress")
+    pub fn leave_multicast_v4(&self, multiaddr: &Ipv4Addr, _interface: &Ipv4Addr) -> io::Result<()> {
+        let result = send_message(
+            self.net.conn(),
+            Message::new_blocking_scalar(Opcode::UdpLeaveMulticastV4.to_usize().unwrap(), self.socket_addr.port() as usize, u32::from(*multiaddr) as usize, 0, 0)
+        ).or(Err(Error::new(ErrorKind::ConnectionRefused, "can't send multicast leave to Net server")))?;
+        match result {
+            xous::Result::Scalar1(1) => Ok(()),
+            _ => Err(Error::new(ErrorKind::AddrNotAvailable, "not a member of that multicast group")),
+        }
     }
 
-    pub fn set_multicast_loop_v6(&self, _: bool) -> io::Result<()> {
-        unimplemented!("ipv6 not implemented")
+    pub fn set_multicast_loop_v6(&self, multicast_loop_v6: bool) -> io::Result<()> {
+        self.set_option(NetUdpOption::MulticastLoopV6, multicast_loop_v6 as usize)
     }
 
     pub fn multicast_loop_v6(&self) -> io::Result<bool> {
-        unimplemented!("ipv6 not implemented")
+        Ok(self.option(NetUdpOption::MulticastLoopV6)? != 0)
     }
 
-    pub fn join_multicast_v6(&self, _: &Ipv6Addr, _: u32) -> io::Result<()> {
-        unimplemented!("ipv6 not implemented")
+    fn multicast_v6(&self, opcode: Opcode, multiaddr: &Ipv6Addr) -> io::Result<bool> {
+        let request = NetUdpMulticastV6 {
+            port: self.socket_addr.port(),
+            group: multiaddr.octets(),
+            result: None,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "can't allocate multicast request")))?;
+        buf.lend_mut(self.net.conn(), opcode.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::ConnectionRefused, "can't send multicast request to Net server")))?;
+        let response = buf.to_original::<NetUdpMulticastV6, _>().unwrap();
+        Ok(response.result == Some(true))
+    }
+
+    /// The Net server doesn't send MLD reports, so this relies on the network flooding
+    /// multicasts to the device. `interface` is ignored, as there is only one.
+    pub fn join_multicast_v6(&self, multiaddr: &Ipv6Addr, _interface: u32) -> io::Result<()> {
+        if !multiaddr.is_multicast() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not a multicast address"));
+        }
+        if self.multicast_v6(Opcode::UdpJoinMulticastV6, multiaddr)? {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::AddrInUse, "couldn't join multicast group, or already joined"))
+        }
     }
 
-    pub fn leave_multicast_v6(&self, _: &Ipv6Addr, _: u32) -> io::Result<()> {
-        unimplemented!("ipv6 not implemented")
+    pub fn leave_multicast_v6(&self, multiaddr: &Ipv6Addr, _interface: u32) -> io::Result<()> {
+        if self.multicast_v6(Opcode::UdpLeaveMulticastV6, multiaddr)? {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::AddrNotAvailable, "not a member of that multicast group"))
+        }
     }
 
 }
@@ -40,40 +40,199 @@ pub(crate) const PDDB_FAST_SPACE_SYSTEM_BASIS: &'static str = ".FastSpace";
 
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum Opcode {
+    /// Open or create a dictionary, optionally within a specific basis
+    DictRequest,
+    /// Delete a dictionary and all of its keys
+    DictRemove,
+    /// Delete a dictionary and all of its keys, overwriting their data on disk with noise
+    DictErase,
+    /// List the names of the dictionaries in the open basis, one page at a time
+    DictList,
+    /// Request a token to access a particular key, optionally creating it
     KeyRequest,
+    /// Release a token previously issued by a KeyRequest
+    KeyDrop,
+    /// Delete a key
+    KeyRemove,
+    /// Delete a key, overwriting its data on disk
+    KeyErase,
+    /// Returns the current length of the key referred to by a token
+    KeyLen,
+    /// List the names of the keys in a dictionary, one page at a time
+    KeyList,
+    /// Returns the metadata of a key, by name
+    KeyStat,
 
-    /// read implemented using only scalars -- fast, but limited size
-    ReadKeyScalar,
-    /// read implemented using a memory buffer -- slower, but just shy of a page in size
+    /// Read and write are implemented using a page-sized memory buffer. There is no scalar
+    /// variant of the read/write calls because the scalar arguments have no room to carry
+    /// the read/write position.
     ReadKeyMem,
-
-    WriteKeyScalar1,
-    WriteKeyScalar2,
-    WriteKeyScalar3,
-    WriteKeyScalar4,
     WriteKeyMem,
     WriteKeyFlush,
 
+    /// Create a new secret basis. The basis is created locked.
+    BasisCreate,
+    /// Unlock a secret basis with its password
+    BasisUnlock,
+    /// Lock a secret basis, purging its contents from RAM
+    BasisLock,
+    /// List the names of the open basis
+    BasisList,
+    /// Set the limit on how much large key data a basis caches in RAM, and report how much it holds
+    BasisCacheLimit,
+
     /// Suspend/resume callback
     SuspendResume,
 }
 
-/// A structure for requesting a token to access a particular key/value pair
+/// Callback opcodes for the per-key server that is created in the application's
+/// process space when it asks to be notified of changes to a key.
+#[allow(dead_code)]
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum KeyCallback {
+    /// The key was modified through a token other than the one that registered the callback
+    Changed,
+    /// Shuts down the callback server
+    Drop,
+}
+
+/// Result codes for dictionary and key requests
+#[allow(dead_code)]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum PddbRequestCode {
+    Uninit,
+    NoErr,
+    NotFound,
+    AccessDenied,
+    NoFreeSpace,
+    DuplicateEntry,
+    InvalidName,
+    InternalError,
+}
+
+/// A structure for opening, creating, or removing a dictionary
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbDictRequest {
+    pub(crate) basis: Option<xous_ipc::String::</*BASIS_NAME_LEN*/ 64>>, // pending https://github.com/rust-lang/rust/issues/90195
+    pub(crate) dict: xous_ipc::String::</*DICT_NAME_LEN*/ 111>, // pending https://github.com/rust-lang/rust/issues/90195
+    pub(crate) create: bool,
+    pub(crate) result: PddbRequestCode,
+}
+
+/// A structure for requesting a token to access a particular key/value pair. Also used to specify
+/// the key for a KeyRemove request.
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub(crate) struct PddbKeyRequest {
+    pub(crate) basis: Option<xous_ipc::String::</*BASIS_NAME_LEN*/ 64>>, // pending https://github.com/rust-lang/rust/issues/90195
     pub(crate) dict: xous_ipc::String::</*DICT_NAME_LEN*/ 111>, // pending https://github.com/rust-lang/rust/issues/90195
     pub(crate) key: xous_ipc::String::</*KEY_NAME_LEN*/ 95>, // pending https://github.com/rust-lang/rust/issues/90195
+    /// create the dictionary if it doesn't already exist
+    pub(crate) create_dict: bool,
+    /// create the key if it doesn't already exist
+    pub(crate) create_key: bool,
+    /// hint for how much space to reserve for the key's data when creating it
+    pub(crate) alloc_hint: Option<u64>,
+    /// if present, the server to notify when the key is modified through another token
+    pub(crate) cb_sid: Option<[u32; 4]>,
+    pub(crate) result: PddbRequestCode,
     pub(crate) token: Option<[u32; 3]>,
 }
 
+/// A structure for creating, unlocking, or locking a basis
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbBasisRequest {
+    pub(crate) name: xous_ipc::String::</*BASIS_NAME_LEN*/ 64>, // pending https://github.com/rust-lang/rust/issues/90195
+    /// required to create or unlock a basis; not used to lock one. bcrypt() only considers the first 72 bytes.
+    pub(crate) password: Option<xous_ipc::String::<72>>,
+    pub(crate) result: PddbRequestCode,
+}
+
+/// The maximum number of basis names returned by a BasisList request
+#[allow(dead_code)]
+pub(crate) const BASIS_LIST_MAX: usize = 16;
+/// The names of the open basis, in order of increasing priority: the last one is the default basis
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbBasisList {
+    pub(crate) list: [xous_ipc::String::</*BASIS_NAME_LEN*/ 64>; /*BASIS_LIST_MAX*/ 16], // pending https://github.com/rust-lang/rust/issues/90195
+    pub(crate) num: u32,
+}
+
+/// A request to set the limit on a basis' cache of large key data, or to read back how much it holds
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbCacheRequest {
+    /// the basis to apply the request to; if None, the default basis
+    pub(crate) basis: Option<xous_ipc::String::</*BASIS_NAME_LEN*/ 64>>, // pending https://github.com/rust-lang/rust/issues/90195
+    /// the new limit, in bytes; if None, the limit is left as it is
+    pub(crate) limit: Option<u64>,
+    /// on return, the number of bytes of large key data the basis holds in RAM
+    pub(crate) usage: u64,
+    pub(crate) result: PddbRequestCode,
+}
+
+/// The maximum number of names returned by a single DictList or KeyList request
+#[allow(dead_code)]
+pub(crate) const LIST_PAGE_MAX: usize = 32;
+/// One page of a sorted listing of dictionary or key names. The first request of a listing has no `token`;
+/// the server snapshots the listing, and returns a token that fetches the page after the one returned.
+/// The token comes back as None with the last page.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbListRequest {
+    /// if present, only the named basis is listed; otherwise the union across all the open basis is listed
+    pub(crate) basis: Option<xous_ipc::String::</*BASIS_NAME_LEN*/ 64>>, // pending https://github.com/rust-lang/rust/issues/90195
+    /// the dictionary to list the keys of; ignored when listing dictionaries
+    pub(crate) dict: xous_ipc::String::</*DICT_NAME_LEN*/ 111>, // pending https://github.com/rust-lang/rust/issues/90195
+    /// the listing to continue; only valid for the process that started it
+    pub(crate) token: Option<[u32; 3]>,
+    /// key names are shorter than dictionary names, so the same storage serves both listings
+    pub(crate) list: [xous_ipc::String::</*DICT_NAME_LEN*/ 111>; /*LIST_PAGE_MAX*/ 32], // pending https://github.com/rust-lang/rust/issues/90195
+    /// number of valid entries in `list`
+    pub(crate) num: u32,
+    /// total number of names in the listing
+    pub(crate) total: u32,
+    pub(crate) result: PddbRequestCode,
+}
+
+/// The metadata of a key, as reported by `PddbDict::stat()`. The server fills in the same structure.
+#[derive(Debug, Clone)]
+pub struct KeyStat {
+    /// the basis that holds the copy of the key that was reported
+    pub basis: String,
+    /// length of the key's data
+    pub len: u64,
+    /// space reserved for the key's data; always at least `len`
+    pub reserved: u64,
+    /// the number of times the key has been modified
+    pub age: u32,
+    /// the raw `KeyFlags` bits: bit 0 is set if the key is valid, bit 1 if its location is not yet resolved
+    pub flags: u32,
+}
+
+/// A request for the metadata of a key
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbKeyStat {
+    /// the basis to search; if None, the open basis are searched in order of priority. On return,
+    /// this is set to the basis the key was found in.
+    pub(crate) basis: Option<xous_ipc::String::</*BASIS_NAME_LEN*/ 64>>, // pending https://github.com/rust-lang/rust/issues/90195
+    pub(crate) dict: xous_ipc::String::</*DICT_NAME_LEN*/ 111>, // pending https://github.com/rust-lang/rust/issues/90195
+    pub(crate) key: xous_ipc::String::</*KEY_NAME_LEN*/ 95>, // pending https://github.com/rust-lang/rust/issues/90195
+    pub(crate) len: u64,
+    pub(crate) reserved: u64,
+    pub(crate) age: u32,
+    pub(crate) flags: u32,
+    pub(crate) result: PddbRequestCode,
+}
+
 /// Return codes for Read/Write API calls to the main server
 #[repr(u8)]
+#[allow(dead_code)]
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum PddbRetcode {
     Uninit = 0,
     Ok = 1,
     BasisLost = 2,
     AccessDenied = 3,
+    DiskFull = 4,
+    InternalError = 5,
 }
 /// PddbBuf is a C-representation of a page of memory that's used
 /// to shuttle data for streaming channels. It must be exactly one
@@ -90,9 +249,16 @@ pub(crate) struct PddbBuf {
     pub(crate) len: u16,
     /// a field reserved for the return code
     pub(crate) retcode: PddbRetcode,
-    pub(crate) reserved: u8,
-    pub(crate) data: [u8; 4080],
+    /// `PDDB_BUF_TRUNCATE` is the only flag defined
+    pub(crate) flags: u8,
+    /// position in the key to start the read or write from. The position is tracked by
+    /// the caller, so the server doesn't have to keep a cursor for every token.
+    pub(crate) position: u64,
+    pub(crate) data: [u8; PDDB_BUF_DATA_LEN],
 }
+pub(crate) const PDDB_BUF_DATA_LEN: usize = 4072;
+/// Set on a write to discard whatever data the key holds past the end of the write
+pub(crate) const PDDB_BUF_TRUNCATE: u8 = 0b1;
 impl PddbBuf {
     pub(crate) fn from_slice_mut(slice: &mut [u8]) -> &mut PddbBuf {
         // this transforms the slice [u8] into a PddbBuf ref.
@@ -138,9 +138,12 @@ use core::mem::size_of;
 use std::convert::TryInto;
 use aes_gcm_siv::{Aes256GcmSiv, Key};
 use aes_gcm_siv::aead::NewAead;
+use aes::Aes256;
+use aes::cipher::{NewBlockCipher, generic_array::GenericArray};
 use std::iter::IntoIterator;
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::io::{Result, Error, ErrorKind};
+use std::num::NonZeroU32;
 
 pub(crate) const SMALL_POOL_START: u64 = 0x0000_003F_8000_0000;
 pub(crate) const SMALL_POOL_END: u64 = 0x0000_007E_FF02_0000;
@@ -152,6 +155,9 @@ pub(crate) const SMALL_POOL_STRIDE: u64 = 0xFE_0000;
 pub(crate) const SMALL_CAPACITY: usize = VPAGE_SIZE;
 pub(crate) const LARGE_POOL_START: u64 = 0x0000_FE00_0000_0000;
 pub(crate) const KEY_MAXCOUNT: usize = 131_071; // 2^17 - 1
+/// Default upper bound, in bytes, on the decrypted large-pool data that a basis keeps in its key caches.
+/// Large pool pages are evicted least-recently-used first once this is exceeded.
+pub(crate) const LARGE_CACHE_DEFAULT_LIMIT: usize = 8 * VPAGE_SIZE;
 
 /// The chosen "stride" of a dict/key entry. Drives a lot of key parameters in the database's characteristics.
 /// This is chosen such that 32 of these entries fit evenly into a VPAGE.
@@ -223,6 +229,89 @@ impl BasisCache {
     // placeholder reminder: deleting a basis is a bit more complicated, as it requires
     // syncing its contents.
 
+    /// Creates a new secret basis with the given name and password. The basis is left locked; it
+    /// has to be opened with `basis_unlock()` before it can be used.
+    pub(crate) fn basis_create(&mut self, hw: &mut PddbOs, name: &str, password: &str) -> Result<()> {
+        basis_name_check(name)?;
+        let mut key = hw.basis_derive_key(name, password);
+        // the (name, password) combination must be unique, otherwise the two basis would share a page table
+        let result = if hw.pt_scan_key(&key, name).is_some() {
+            Err(Error::new(ErrorKind::AlreadyExists, "Basis already exists"))
+        } else if !hw.ensure_fast_space_alloc(1, &self.cache) {
+            Err(Error::new(ErrorKind::OutOfMemory, "No free space to create basis"))
+        } else {
+            hw.basis_root_create(name, &key)
+        };
+        volatile_wipe(&mut key);
+        result
+    }
+    /// Unlocks a secret basis and places it on top of the stack of open basis, so it takes priority for
+    /// any operation that doesn't name a basis. A wrong password is indistinguishable from a basis that
+    /// doesn't exist; both return `PermissionDenied`.
+    pub(crate) fn basis_unlock(&mut self, hw: &mut PddbOs, name: &str, password: &str) -> Result<()> {
+        basis_name_check(name)?;
+        if self.cache.iter().any(|bc| bc.name == name) {
+            return Err(Error::new(ErrorKind::AlreadyExists, "A basis with this name is already unlocked"));
+        }
+        let mut key = hw.basis_derive_key(name, password);
+        let basis = BasisCacheEntry::mount(hw, name, &key, false);
+        volatile_wipe(&mut key);
+        if let Some(basis) = basis {
+            self.basis_add(basis);
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::PermissionDenied, "Basis could not be unlocked"))
+        }
+    }
+    /// Syncs a secret basis to disk, then closes it and wipes all of its cached data from RAM.
+    /// The system basis can't be locked.
+    pub(crate) fn basis_lock(&mut self, hw: &mut PddbOs, name: &str) -> Result<()> {
+        if name == PDDB_DEFAULT_SYSTEM_BASIS {
+            return Err(Error::new(ErrorKind::PermissionDenied, "The system basis can't be locked"));
+        }
+        let basis_index = if let Some(index) = self.cache.iter().position(|bc| bc.name == name) {
+            index
+        } else {
+            return Err(Error::new(ErrorKind::NotFound, "Basis is not unlocked"));
+        };
+        let mut estimate = 1;
+        for dict_entry in self.cache[basis_index].dicts.values() {
+            estimate += dict_entry.alloc_estimate_small();
+        }
+        if !hw.ensure_fast_space_alloc(estimate, &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "No free space to sync basis before locking it"));
+        }
+        self.cache[basis_index].sync(hw)?;
+        let mut basis = self.cache.remove(basis_index);
+        basis.purge();
+        log::info!("Basis {} locked", name);
+        Ok(())
+    }
+    /// Locks every open basis except for the system basis, returning the names of the ones that were locked.
+    /// A basis that fails to sync is still locked, because this is used on suspend, where leaving a secret
+    /// basis open is worse than losing the data that hasn't been synced.
+    pub(crate) fn basis_lock_secrets(&mut self, hw: &mut PddbOs) -> Vec::<String> {
+        let secrets: Vec::<String> = self.cache.iter()
+            .filter(|bc| bc.name != PDDB_DEFAULT_SYSTEM_BASIS)
+            .map(|bc| bc.name.to_string())
+            .collect();
+        for name in secrets.iter() {
+            if let Err(e) = self.basis_lock(hw, name) {
+                log::error!("Couldn't sync basis {} before locking it, changes are lost: {:?}", name, e);
+                if let Some(index) = self.cache.iter().position(|bc| bc.name == *name) {
+                    let mut basis = self.cache.remove(index);
+                    basis.purge();
+                }
+            }
+        }
+        secrets
+    }
+    /// Returns the names of the open basis, in order of increasing priority: the last one is the
+    /// default target for operations that don't name a basis.
+    pub(crate) fn basis_list(&self) -> Vec::<String> {
+        self.cache.iter().map(|bc| bc.name.to_string()).collect()
+    }
+
     fn select_basis(&mut self, basis_name: Option<&str>) -> Option<usize> {
         if self.cache.len() == 0 {
             log::error!("Can't select basis: PDDB is not mounted");
@@ -299,23 +388,41 @@ impl BasisCache {
         }
     }
 
-    /// Returns a list of all the known dictionaries, across all the basis. A HashSet is returned
-    /// because you can have the same-named dictionary in multiple basis, and what we're asking for
-    /// is the union of all the dictionary names, without duplicates.
-    pub(crate) fn dict_list(&mut self, hw: &mut PddbOs) -> HashSet::<String> {
+    /// Checks if a dictionary exists in the specified basis. If `basis_name` is None, the most
+    /// recently opened basis is checked.
+    pub(crate) fn dict_exists(&mut self, hw: &mut PddbOs, name: &str, basis_name: Option<&str>) -> bool {
+        if let Some(basis_index) = self.select_basis(basis_name) {
+            self.cache[basis_index].ensure_dict_in_cache(hw, name)
+        } else {
+            false
+        }
+    }
+
+    /// Returns a list of all the known dictionaries. If `basis_name` is None, the list is drawn from
+    /// all the open basis. A HashSet is returned because you can have the same-named dictionary in multiple
+    /// basis, and what we're asking for is the union of all the dictionary names, without duplicates.
+    /// If `basis_name` is Some, only the dictionaries of that basis are listed.
+    pub(crate) fn dict_list(&mut self, hw: &mut PddbOs, basis_name: Option<&str>) -> Result<HashSet::<String>> {
+        if let Some(name) = basis_name {
+            if !self.cache.iter().any(|bc| bc.name == name) {
+                return Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."));
+            }
+        }
         let mut dict_set = HashSet::<String>::new();
-        for basis in self.cache.iter_mut() {
+        for basis in self.cache.iter_mut().filter(|bc| basis_name.map_or(true, |n| bc.name == n)) {
             basis.populate_caches(hw);
             for key in basis.dicts.keys() {
                 dict_set.insert(String::from(key));
             }
         }
-        dict_set
+        Ok(dict_set)
     }
-    pub(crate) fn key_list(&mut self, hw: &mut PddbOs, dict: &str) -> Result<HashSet::<String>> {
+    /// Returns the names of the keys in a dictionary. Like `dict_list()`, this is the union across all
+    /// the open basis if `basis_name` is None.
+    pub(crate) fn key_list(&mut self, hw: &mut PddbOs, dict: &str, basis_name: Option<&str>) -> Result<HashSet::<String>> {
         let mut merge_list = HashSet::<String>::new();
         let mut found_dict = false;
-        for basis in self.cache.iter_mut() {
+        for basis in self.cache.iter_mut().filter(|bc| basis_name.map_or(true, |n| bc.name == n)) {
             basis.populate_caches(hw);
             if let Some(dcache) = basis.dicts.get_mut(dict) {
                 dcache.key_list(hw, &basis.v2p_map, &basis.cipher, &mut merge_list);
@@ -328,6 +435,25 @@ impl BasisCache {
             return Err(Error::new(ErrorKind::NotFound, "dictionary not found"))
         }
     }
+    /// Returns the metadata of a key. If `basis_name` is None, the open basis are searched in order of
+    /// decreasing priority, and the first copy of the key that is found is reported; this is the same
+    /// copy that a read would return.
+    pub(crate) fn key_stat(&mut self, hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>) -> Result<KeyStat> {
+        for basis in self.cache.iter_mut().rev().filter(|bc| basis_name.map_or(true, |n| bc.name == n)) {
+            if !basis.ensure_dict_in_cache(hw, dict) {
+                continue;
+            }
+            if let Some(dict_entry) = basis.dicts.get_mut(dict) {
+                if dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
+                    let kcache = dict_entry.keys.get(key).expect("Entry was assured, but then not there!");
+                    if kcache.flags.valid() {
+                        return Ok(kcache.stat(&basis.name));
+                    }
+                }
+            }
+        }
+        Err(Error::new(ErrorKind::NotFound, "key not found"))
+    }
 
     /// This version of the call only removes one instance of a dictionary from the specified basis.
     /// Perhaps there also needs to be a `dict_remove_all` call which iterates through every basis
@@ -351,6 +477,25 @@ impl BasisCache {
         }
     }
 
+    /// Compacts a dictionary within the specified basis, reclaiming the space of removed keys and
+    /// repacking the small pool. Enough FastSpace is reserved up front to rewrite every page that
+    /// belongs to the dictionary, so the operation doesn't fail midway for lack of space.
+    pub(crate) fn dict_compact(&mut self,
+        hw: &mut PddbOs, dict: &str, basis_name: Option<&str>
+    ) -> Result<()> {
+        if let Some(basis_index) = self.select_basis(basis_name) {
+            log::info!("compacting dict {}", dict);
+            // one more page for the new copy of the basis root that commits the compaction
+            let pages_needed = self.cache[basis_index].dict_mapped_pages(hw, dict)? + 1;
+            if !hw.ensure_fast_space_alloc(pages_needed, &self.cache) {
+                return Err(Error::new(ErrorKind::OutOfMemory, "Insufficient free space to compact dictionary"));
+            }
+            self.cache[basis_index].dict_compact(hw, dict)
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))
+        }
+    }
+
     pub(crate) fn key_read(&mut self, hw: &mut PddbOs, dict: &str, key: &str, data: &mut [u8],
         offset: Option<usize>, basis_name:Option<&str>
     ) -> Result<usize> {
@@ -363,49 +508,54 @@ impl BasisCache {
                 if dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
                     let kcache = dict_entry.keys.get_mut(key).expect("Entry was assured, but then not there!");
                     // the key exists, *and* there's sufficient space for the data
+                    let offset = offset.unwrap_or(0) as u64;
+                    if offset >= kcache.len {
+                        return Ok(0)
+                    }
+                    // don't read past the end of the key's data
+                    let readlen = ((kcache.len - offset) as usize).min(data.len());
                     if kcache.start < SMALL_POOL_END {
                         // small pool fetch
                         // for now, the rule is, if we have a small key, we also have its data in cache.
                         if let KeyCacheData::Small(cache_data) = kcache.data.as_mut().expect("small pool should all have their data 'hot' if the index entry is also in cache") {
                             let mut bytes_read = 0;
-                            for (&src, dst) in cache_data.data[offset.unwrap_or(0)..].iter().zip(data.iter_mut()) {
+                            for (&src, dst) in cache_data.data[offset as usize..].iter().zip(data[..readlen].iter_mut()) {
                                 *dst = src;
                                 bytes_read += 1;
                             }
                             if bytes_read != data.len() {
-                                log::warn!("Not enough bytes available to read for key {}:{} ({}/{})", dict, key, bytes_read, data.len());
+                                log::debug!("Not enough bytes available to read for key {}:{} ({}/{})", dict, key, bytes_read, data.len());
                             }
                             return Ok(bytes_read)
                         } else {
                             panic!("Key allocated to small area but its cache data was not of the small type");
                         }
                     } else {
-                        // large pool fetch
-                        let mut cur_offset = offset.unwrap_or(0) as u64;
-                        let mut first_iter = true;
-                        while cur_offset < data.len() as u64 {
-                            let start_vpage_addr = ((kcache.start + cur_offset) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
-                            if let Some(pp) = basis.v2p_map.get(&VirtAddr::new(start_vpage_addr).unwrap()) {
-                                let pt_data = hw.data_decrypt_page(&basis.cipher, &basis.aad, pp).expect("Decryption auth error");
-                                if !first_iter {
-                                    assert!((cur_offset as usize % VPAGE_SIZE) == 0, "algorithm error in handling offset data");
-                                }
-                                // note: cur_offset % VPAGE_SIZE handles mis-aligned starts; cur_offset will increment until start + cur_offset is
-                                // a multiple of a VPAGE_SIZE, so on later iterations, cur_offset % VPAGE_SIZE should be equal to 0.
+                        // large pool fetch, through the key's page cache. The cache is trimmed back to its limit
+                        // as each page comes in, so that a long read can't run it past `large_cache_limit`.
+                        let start = kcache.start;
+                        let mut bytes_read = 0;
+                        while bytes_read < readlen {
+                            let cur_addr = start + offset + bytes_read as u64;
+                            let vpage_addr = VirtAddr::new((cur_addr / VPAGE_SIZE as u64) * VPAGE_SIZE as u64).unwrap();
+                            let kcache = basis.dicts.get_mut(dict).and_then(|d| d.keys.get_mut(key))
+                                .expect("Entry was assured, but then not there!");
+                            if let Some(pt_data) = kcache.large_page_fetch(hw, &basis.v2p_map, &basis.cipher, &basis.aad, vpage_addr) {
+                                // only the first page can have a mis-aligned start; every page after that starts at 0.
+                                let page_offset = (cur_addr % VPAGE_SIZE as u64) as usize;
                                 for (&src, dst) in
-                                pt_data[size_of::<JournalType>() + cur_offset as usize % VPAGE_SIZE..].iter().zip(data.iter_mut()) {
+                                pt_data[size_of::<JournalType>() + page_offset..].iter().zip(data[bytes_read..readlen].iter_mut()) {
                                     *dst = src;
-                                    cur_offset += 1;
+                                    bytes_read += 1;
                                 }
-                                first_iter = false;
                             } else {
-                                log::warn!("Not enough bytes available to read for key {}:{} ({}/{})", dict, key, cur_offset, data.len());
-                                return Ok(cur_offset as usize)
+                                log::warn!("Not enough bytes available to read for key {}:{} ({}/{})", dict, key, bytes_read, data.len());
+                                break;
                             }
+                            basis.large_cache_prune();
                         }
-                        return Ok(cur_offset as usize)
+                        return Ok(bytes_read)
                     }
-
                 } else {
                     return Err(Error::new(ErrorKind::NotFound, "key not found"));
                 }
@@ -417,14 +567,64 @@ impl BasisCache {
         }
     }
 
+    /// Sets the upper bound, in bytes, on the large key data that a basis keeps cached in RAM. Caches
+    /// that are over the new limit are trimmed right away. A limit smaller than one vpage disables caching.
+    pub(crate) fn large_cache_set_limit(&mut self, limit: usize, basis_name: Option<&str>) -> Result<()> {
+        if let Some(basis_index) = self.select_basis(basis_name) {
+            let basis = &mut self.cache[basis_index];
+            basis.large_cache_limit = limit;
+            basis.large_cache_prune();
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))
+        }
+    }
+    /// Reports how many bytes of large key data a basis is currently holding in RAM.
+    pub(crate) fn large_cache_usage(&mut self, basis_name: Option<&str>) -> Result<usize> {
+        if let Some(basis_index) = self.select_basis(basis_name) {
+            Ok(self.cache[basis_index].large_cache_usage())
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))
+        }
+    }
+
+    /// Returns the length of the data stored in a key.
+    pub(crate) fn key_len(&mut self, hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>) -> Result<u64> {
+        if let Some(basis_index) = self.select_basis(basis_name) {
+            let basis = &mut self.cache[basis_index];
+            if !basis.ensure_dict_in_cache(hw, dict) {
+                return Err(Error::new(ErrorKind::NotFound, "dictionary not found"));
+            }
+            if let Some(dict_entry) = basis.dicts.get_mut(dict) {
+                if dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
+                    Ok(dict_entry.keys.get(key).expect("Entry was assured, but then not there!").len)
+                } else {
+                    Err(Error::new(ErrorKind::NotFound, "key not found"))
+                }
+            } else {
+                Err(Error::new(ErrorKind::NotFound, "dictionary not found"))
+            }
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))
+        }
+    }
+
     pub(crate) fn key_remove(&mut self,
         hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>, paranoid: bool
     ) -> Result<()> {
         if let Some(basis_index) = self.select_basis(basis_name) {
-            let basis = &mut self.cache[basis_index];
-            if !basis.ensure_dict_in_cache(hw, dict) {
+            if !self.cache[basis_index].ensure_dict_in_cache(hw, dict) {
                 return Err(Error::new(ErrorKind::NotFound, "dictionary not found"));
             }
+            if paranoid {
+                // an erase re-writes the small pool and the key's descriptor page immediately, so reserve
+                // space for the pending small pool data plus one page each for the pool and the descriptor.
+                let estimate = self.cache[basis_index].dicts.get(dict).map_or(0, |d| d.alloc_estimate_small()) + 2;
+                if !hw.ensure_fast_space_alloc(estimate, &self.cache) {
+                    return Err(Error::new(ErrorKind::OutOfMemory, "No free space to erase key"));
+                }
+            }
+            let basis = &mut self.cache[basis_index];
             if let Some(dict_entry) = basis.dicts.get_mut(dict) {
                 basis.age = basis.age.saturating_add(1);
                 basis.clean = false;
@@ -432,15 +632,17 @@ impl BasisCache {
                     if !paranoid {
                         dict_entry.key_remove(hw, &mut basis.v2p_map, &basis.cipher, key, false);
                     } else {
-                        // this implementation is still in progress
-                        dict_entry.key_erase(key);
+                        let displaced = dict_entry.key_erase(hw, &mut basis.v2p_map, &basis.cipher, key)?;
 
-                        // encrypt and write the dict entry to disk
+                        // encrypt and write the dict entry to disk, scrubbing the key's descriptor
                         basis.dict_sync(hw, dict)?;
                         // sync the root basis structure as well, while we're at it...
                         basis.basis_sync(hw);
                         // finally, sync the page tables.
                         basis.pt_sync(hw);
+                        // the page table now points at the re-written small pool, so the page that held
+                        // the erased key can be overwritten with noise and given back.
+                        hw.pages_release(displaced);
                     }
                     return Ok(())
                 } else {
@@ -454,6 +656,58 @@ impl BasisCache {
         }
     }
 
+    /// Returns the physical pages that currently hold a key's data. Used by tests to inspect the
+    /// ciphertext on the disk.
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    pub(crate) fn key_phys_pages(&mut self, hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>) -> Result<Vec::<PhysPage>> {
+        if let Some(basis_index) = self.select_basis(basis_name) {
+            let basis = &mut self.cache[basis_index];
+            if !basis.ensure_dict_in_cache(hw, dict) {
+                return Err(Error::new(ErrorKind::NotFound, "dictionary not found"));
+            }
+            let dict_entry = basis.dicts.get_mut(dict).expect("entry was ensured, but somehow missing");
+            if !dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
+                return Err(Error::new(ErrorKind::NotFound, "key not found"));
+            }
+            let kcache = dict_entry.keys.get(key).expect("entry was ensured, but somehow missing");
+            let mut pages = Vec::<PhysPage>::new();
+            // small pool slots never straddle a vpage, and large pool keys always start on one
+            for vbase in (kcache.start..kcache.start + kcache.reserved).step_by(VPAGE_SIZE) {
+                let vpage = VirtAddr::new((vbase / VPAGE_SIZE as u64) * VPAGE_SIZE as u64).unwrap();
+                if let Some(pp) = basis.v2p_map.get(&vpage) {
+                    pages.push(pp.clone());
+                }
+            }
+            Ok(pages)
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))
+        }
+    }
+    /// Replaces the FastSpace record with a freshly generated one, which normally only happens once the
+    /// FastSpace runs low. Used by tests to exercise the FSCB rollover.
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    pub(crate) fn fast_space_regenerate(&self, hw: &mut PddbOs) -> bool {
+        hw.fast_space_regenerate(&self.cache)
+    }
+
+    /// Commits any pending changes to a dictionary to disk, e.g. after a lazy `key_remove()`.
+    pub(crate) fn dict_sync(&mut self, hw: &mut PddbOs, dict: &str, basis_name: Option<&str>) -> Result<()> {
+        if let Some(basis_index) = self.select_basis(basis_name) {
+            let basis = &mut self.cache[basis_index];
+            if let Some(dict_entry) = basis.dicts.get_mut(dict) {
+                dict_entry.sync_small_pool(hw, &mut basis.v2p_map, &basis.cipher);
+                basis.dict_sync(hw, dict)?;
+                basis.basis_sync(hw);
+                basis.pt_sync(hw);
+                Ok(())
+            } else {
+                Err(Error::new(ErrorKind::NotFound, "dictionary not found"))
+            }
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))
+        }
+    }
+
     /// Updates a key in a dictionary; if it doesn't exist, creates it. User can specify a basis,
     /// or rely upon the auto-basis select algorithm.
     pub(crate) fn key_update(&mut self,
@@ -464,7 +718,7 @@ impl BasisCache {
         // mutate the page table to allocate data while we're accessing the page table. This huge gob of code
         // computes the pages needed. :-/
         let mut pages_needed = 0;
-        let reserved = if data.len() + offset.unwrap_or(0) < alloc_hint.unwrap_or(0) {
+        let reserved = if data.len() + offset.unwrap_or(0) > alloc_hint.unwrap_or(0) {
             data.len() + offset.unwrap_or(0)
         } else {
             alloc_hint.unwrap_or(0)
@@ -484,7 +738,10 @@ impl BasisCache {
                 // see if we need to make a kcache entry
                 if let Some(kcache) = dict_entry.keys.get(key) {
                     // now check for data reservations
-                    if reserved < SMALL_CAPACITY {
+                    if reserved as u64 > kcache.reserved {
+                        // the key will be re-allocated with the larger reservation
+                        pages_needed += reserved_pages;
+                    } else if reserved < SMALL_CAPACITY {
                         // it's probably going in the small pool.
                         // index exists, see if the page exists
                         let key_index = (((kcache.start - SMALL_POOL_START as u64) - (dict_entry.index as u64 * SMALL_POOL_STRIDE as u64)) / SMALL_CAPACITY as u64) as usize;
@@ -544,7 +801,7 @@ impl BasisCache {
             // pre-flight & allocatefree space requirements
             if let Some(dict_entry) = self.cache[basis_index].dicts.get(dict) {
                 hw.ensure_fast_space_alloc(dict_entry.alloc_estimate_small(), &self.cache);
-                // large pools don't have caching implemented, so we don't have to check for free space for them
+                // large pool caches are write-through, and don't need any free space to be reserved for them
             }
             // refetch the basis here to avoid the re-borrow problem, now that all the potential dict cache mutations are done
             let basis = &mut self.cache[basis_index];
@@ -562,8 +819,6 @@ impl BasisCache {
                 basis.large_alloc_ptr = Some(updated_ptr);
 
                 dict_entry.sync_small_pool(hw, &mut basis.v2p_map, &basis.cipher);
-                // we don't have large pool caches yet, but this is a placeholder to remember to do "something" at this point,
-                // once we do have them.
                 dict_entry.sync_large_pool();
 
                 // encrypt and write the dict entry to disk
@@ -572,6 +827,7 @@ impl BasisCache {
                 basis.basis_sync(hw);
                 // finally, sync the page tables.
                 basis.pt_sync(hw);
+                basis.large_cache_prune();
             } else {
                 return Err(Error::new(ErrorKind::NotFound, "Requested dictionary not found, or could not be allocated."));
             }
@@ -601,6 +857,8 @@ pub(crate) struct BasisCacheEntry {
     pub free_dict_offset: Option<u32>,
     /// the cipher for the basis
     pub cipher: Aes256GcmSiv,
+    /// the cipher for the basis' page table entries -- cached, so we can save the time cost of constructing the key schedule
+    pub cipher_ecb: Aes256,
     /// the AAD associated with this Basis
     pub aad: Vec::<u8>,
     /// modification count
@@ -611,6 +869,8 @@ pub(crate) struct BasisCacheEntry {
     pub journal: u32,
     /// current allocation pointer for the "large" pool. This just keeps incrementing "forever".
     pub large_alloc_ptr: Option<PageAlignedVa>,
+    /// upper bound, in bytes, on the large pool data cached across all the keys in this basis. Rounded down to whole vpages.
+    pub large_cache_limit: usize,
 }
 impl BasisCacheEntry {
     /// given a pointer to the hardware, name of the basis, and its cryptographic key, try to derive
@@ -618,6 +878,7 @@ impl BasisCacheEntry {
     /// If it `lazy` is false, it will populate the dictionary cache and key cache entries, as well as
     /// discover the location of the `large_alloc_ptr`.
     pub(crate) fn mount(hw: &mut PddbOs, name: &str, key: &[u8; AES_KEYSIZE], lazy: bool) -> Option<BasisCacheEntry> {
+        hw.pt_settle_pending(key, name);
         if let Some(basis_map) = hw.pt_scan_key(key, name) {
             let cipher = Aes256GcmSiv::new(Key::from_slice(key));
             let aad = hw.data_aad(name);
@@ -653,12 +914,14 @@ impl BasisCacheEntry {
                     num_dicts: basis_root.num_dictionaries,
                     dicts: HashMap::<String, DictCacheEntry>::new(),
                     cipher,
+                    cipher_ecb: Aes256::new(GenericArray::from_slice(key)),
                     aad,
                     age: basis_root.age,
                     free_dict_offset: None,
                     v2p_map: basis_map,
                     journal: u32::from_le_bytes(vpage[..size_of::<JournalType>()].try_into().unwrap()),
                     large_alloc_ptr: None,
+                    large_cache_limit: LARGE_CACHE_DEFAULT_LIMIT,
                 };
                 if !lazy {
                     bcache.populate_caches(hw);
@@ -715,12 +978,13 @@ impl BasisCacheEntry {
         }
     }
 
-    /// If `paranoid` is true, it recurses through each key and replaces its data with random junk.
-    /// Otherwise, it does a "shallow" delete and just removes the directory entry, which is much
-    /// more performant. Note that the intended "fast" way to secure-erase data is to store sensitive
-    /// data in its own Basis, and then remove the Basis itself. This is much faster than picking
-    /// through compounded data and re-writing partias sectors, and because of this, initially,
-    /// the `paranoid` erase is `unimplemented`.
+    /// If `paranoid` is true, it recurses through each key and replaces its data with random junk:
+    /// large pool pages and small pool pages are overwritten with TRNG noise before they are freed,
+    /// and the cached copies of the key data are wiped from RAM. Otherwise, it does a "shallow" delete
+    /// and just removes the directory entry, which is much more performant. Either way, the dictionary
+    /// and key descriptor pages are overwritten with noise. Note that the intended "fast" way to
+    /// secure-erase data is to store sensitive data in its own Basis, and then remove the Basis itself.
+    /// This is much faster than picking through compounded data and re-writing partial sectors.
     pub(crate) fn dict_delete(&mut self, hw: &mut PddbOs, name: &str, paranoid: bool) -> Result<()> {
         if self.ensure_dict_in_cache(hw, name) {
             let dcache = self.dicts.get_mut(name).expect("entry was ensured, but somehow missing");
@@ -766,6 +1030,9 @@ impl BasisCacheEntry {
                     log::warn!("Inconsistent internal state: requested dictionary didn't have a mapping in the page table.");
                 }
             }
+            if paranoid {
+                dcache.purge();
+            }
 
             // mark data for re-use
             self.free_dict_offset = Some(dcache.index);
@@ -855,7 +1122,7 @@ impl BasisCacheEntry {
         for (&virt, phys) in self.v2p_map.iter_mut() {
             if !phys.clean() {
                 log::info!("syncing dirty pte va: {:x?} pa: {:x?}", virt, phys);
-                hw.pt_patch_mapping(virt, phys.page_number());
+                hw.pt_patch_mapping(virt, phys.page_number(), &self.cipher_ecb);
                 phys.set_clean(true);
             }
         }
@@ -924,20 +1191,24 @@ impl BasisCacheEntry {
                             key.descriptor_vaddr(dict_offset) < next_vpage {
                                 // key is within the current page, add it to the target list
                                 let mut dk_entry = DictKeyEntry::default();
-                                let mut kn = [0u8; KEY_NAME_LEN];
-                                for (&src, dst) in key_name.as_bytes().iter().zip(kn.iter_mut()) {
-                                    *dst = src;
-                                }
-                                let key_desc = KeyDescriptor {
-                                    start: key.start,
-                                    len: key.len,
-                                    reserved: key.reserved,
-                                    flags: key.flags,
-                                    age: key.age,
-                                    name: kn,
-                                };
-                                for (&src, dst) in key_desc.deref().iter().zip(dk_entry.data.iter_mut()) {
-                                    *dst = src;
+                                // removed keys are written out as a blank descriptor, so their name and extents
+                                // don't linger on disk. A blank descriptor reads back as an invalid entry.
+                                if key.flags.valid() {
+                                    let mut kn = [0u8; KEY_NAME_LEN];
+                                    for (&src, dst) in key_name.as_bytes().iter().zip(kn.iter_mut()) {
+                                        *dst = src;
+                                    }
+                                    let key_desc = KeyDescriptor {
+                                        start: key.start,
+                                        len: key.len,
+                                        reserved: key.reserved,
+                                        flags: key.flags,
+                                        age: key.age,
+                                        name: kn,
+                                    };
+                                    for (&src, dst) in key_desc.deref().iter().zip(dk_entry.data.iter_mut()) {
+                                        *dst = src;
+                                    }
                                 }
                                 dk_vpage.elements[key.descriptor_index.get() as usize % DK_PER_VPAGE] = Some(dk_entry);
                                 key.clean = true;
@@ -987,34 +1258,285 @@ impl BasisCacheEntry {
         }
     }
 
-    /// Runs through the dictionary listing in a basis and compacts them. Call when the
-    /// the dictionary space becomes sufficiently fragmented that accesses are becoming
-    /// inefficient.
-    pub(crate) fn dict_compact(&self, _basis_name: Option<&str>) -> Result<()> {
-        unimplemented!();
+    /// Counts the number of pages currently mapped to a dictionary: its descriptor pages, its small pool, and the
+    /// data of its large keys. This is an upper bound on the number of fresh pages a `dict_compact()` will need.
+    pub(crate) fn dict_mapped_pages(&mut self, hw: &mut PddbOs, name: &str) -> Result<usize> {
+        if self.ensure_dict_in_cache(hw, name) {
+            let dcache = self.dicts.get_mut(name).expect("entry was ensured, but somehow missing");
+            dcache.fill(hw, &self.v2p_map, &self.cipher);
+            let dict_base = dcache.index as u64 * DICT_VSIZE;
+            let pool_base = dcache.index as u64 * SMALL_POOL_STRIDE + SMALL_POOL_START;
+            let mut count = 0;
+            for vaddr in self.v2p_map.keys() {
+                if (vaddr.get() >= dict_base && vaddr.get() < dict_base + DICT_VSIZE)
+                || (vaddr.get() >= pool_base && vaddr.get() < pool_base + SMALL_POOL_STRIDE) {
+                    count += 1;
+                }
+            }
+            for kcache in dcache.keys.values() {
+                if kcache.flags.valid() {
+                    count += kcache.large_pool_vpages().iter().filter(|&vpage| self.v2p_map.contains_key(vpage)).count();
+                }
+            }
+            Ok(count)
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "Dictionary not found"))
+        }
+    }
+
+    /// Compacts a dictionary. Call when the dictionary space becomes sufficiently fragmented
+    /// (e.g. after a lot of keys have been removed) that accesses are becoming inefficient
+    /// and pages are being wasted on mostly-empty small pool slots.
+    ///
+    /// The compaction repacks the small pool, moves large key data onto fresh pages (trimming any
+    /// excess reservation), drops the descriptors of removed keys and renumbers the remaining ones
+    /// densely, and then writes out entirely fresh descriptor pages. It's a make-before-break operation:
+    ///   1. All the new data is written into freshly allocated pages; the old pages are left untouched.
+    ///      The journal revs of the new pages are bumped past the old ones. A virtual page that is no
+    ///      longer needed gets a blank page, so that it can't resurrect the old data on a later mount.
+    ///      A fresh copy of the basis root, with its age bumped, is written last.
+    ///   2. The new pages get page table entries marked as pending, tagged with the new root's age. A mount
+    ///      ignores pending entries unless the basis root it finds carries the same age.
+    ///   3. The PTE of the new basis root is written. This single write, which goes through the MBBB like any
+    ///      other page table patch, is the commit: from here on a mount sees every new page, and before it,
+    ///      none of them.
+    ///   4. The pending entries are re-written as ordinary ones, and then the old pages, followed by the blank
+    ///      ones, are scrubbed with noise, have their PTEs destroyed, and are returned to FastSpace in a random order.
+    /// If power is lost before step 3, the next mount destroys the pending entries and the dictionary is as it
+    /// was; after it, the mount finishes promoting them. Step 4 is otherwise safe to interrupt; the worst case is a
+    /// leak of a few pages until the next FastSpace regeneration.
+    ///
+    /// Every page touched is rewritten regardless of whether its contents changed, so the set of modified
+    /// pages on disk reveals only which dictionary was compacted, not what was in it.
+    ///
+    /// Assumes the caller has ensured there is at least `dict_mapped_pages()` + 1 worth of FastSpace available.
+    /// If it runs out anyways, nothing is committed, and the dictionary is dropped from the cache so that it's
+    /// re-read from the disk on its next use.
+    pub(crate) fn dict_compact(&mut self, hw: &mut PddbOs, name: &str) -> Result<()> {
+        if !self.ensure_dict_in_cache(hw, name) {
+            return Err(Error::new(ErrorKind::NotFound, "Dictionary not found"));
+        }
+        if self.age == u32::MAX {
+            // the commit tag is derived from the age, which has stopped counting
+            return Err(Error::new(ErrorKind::Other, "Basis age is saturated, can't commit a compaction"));
+        }
+        self.last_sync = Some(hw.timestamp_now());
+        {
+            let dcache = self.dicts.get_mut(name).expect("entry was ensured, but somehow missing");
+            dcache.fill(hw, &self.v2p_map, &self.cipher);
+            // flush any small pool updates that haven't hit the disk, so our view of the cache is authoritative
+            dcache.sync_small_pool(hw, &mut self.v2p_map, &self.cipher);
+        }
+        // ...and the descriptors, so that if the compaction has to be abandoned, the disk still has everything
+        self.dict_sync(hw, name)?;
+        self.pt_sync(hw);
+
+        let before = self.v2p_map.clone();
+        let (displaced, vacated) = match self.dict_compact_stage(hw, name, &before) {
+            Ok(staged) => staged,
+            Err(e) => {
+                // none of the staged pages have a PTE yet, so putting the old map back undoes the whole thing
+                let staged: Vec::<PhysPage> = self.v2p_map.iter()
+                    .filter(|(va, pp)| before.get(*va).map(|old| old.page_number()) != Some(pp.page_number()))
+                    .map(|(_, &pp)| pp)
+                    .collect();
+                self.v2p_map = before;
+                self.dicts.remove(name);
+                hw.pages_release(staged);
+                return Err(e);
+            }
+        };
+        let root_va = VirtAddr::new(VPAGE_SIZE as u64).unwrap();
+
+        // 2. stage the new mappings; at this point they're in the page table, but don't count
+        let tag = self.age.saturating_add(1) as u8;
+        for (&virt, phys) in self.v2p_map.iter() {
+            if !phys.clean() && virt != root_va {
+                hw.pt_patch_pending_mapping(virt, phys.page_number(), tag, &self.cipher_ecb);
+            }
+        }
+        // 3. commit
+        let root_pp = self.v2p_map.get_mut(&root_va).expect("basis root went missing during compaction");
+        hw.pt_patch_mapping(root_va, root_pp.page_number(), &self.cipher_ecb);
+        root_pp.set_clean(true);
+        self.age = self.age.saturating_add(1);
+        // the new root was written one journal rev past the old one, so bump ours to keep in-place updates ahead of it
+        self.journal = self.journal.saturating_add(1);
+        self.clean = true;
+
+        // 4. promote the pending entries, then scrub and release the old pages ("break")
+        self.pt_sync(hw);
+        let blanks: Vec::<PhysPage> = vacated.iter().filter_map(|va| self.v2p_map.remove(va)).collect();
+        log::info!("dict {} compacted, releasing {} displaced and {} blank pages", name, displaced.len(), blanks.len());
+        hw.pages_release(displaced);
+        hw.pages_release(blanks);
+        Ok(())
+    }
+
+    /// Step 1 of `dict_compact()`: writes the compacted dictionary and the new basis root into fresh pages, and
+    /// updates the cache to match. Returns the physical pages that the new ones replace, and the virtual pages
+    /// that were only given a blank page.
+    fn dict_compact_stage(&mut self, hw: &mut PddbOs, name: &str, before: &HashMap::<VirtAddr, PhysPage>)
+    -> Result<(Vec::<PhysPage>, Vec::<VirtAddr>)> {
+        let dcache = self.dicts.get_mut(name).expect("entry was ensured, but somehow missing");
+        // removed keys only exist as tombstones waiting for a sync; the descriptor rewrite below will erase them from disk.
+        dcache.keys.retain(|_, kcache| kcache.flags.valid());
+
+        // 1a. move the data
+        let mut displaced = dcache.compact_small_pool(hw, &mut self.v2p_map, &self.cipher)?;
+        displaced.append(&mut dcache.compact_large_pool(hw, &mut self.v2p_map, &self.cipher)?);
+
+        // 1b. renumber the key descriptors densely, preserving their relative order
+        let mut order = Vec::<(u32, String)>::new();
+        for (key_name, kcache) in dcache.keys.iter() {
+            order.push((kcache.descriptor_index.get(), key_name.to_string()));
+        }
+        order.sort();
+        for (new_index, (_, key_name)) in order.iter().enumerate() {
+            let kcache = dcache.keys.get_mut(key_name).expect("key vanished during compaction");
+            kcache.descriptor_index = NonZeroU32::new(new_index as u32 + 1).unwrap();
+            kcache.clean = false;
+        }
+        let key_count = order.len() as u32;
+        dcache.key_count = key_count;
+        dcache.last_disk_key_index = key_count + 1;
+        dcache.free_keys.clear();
+        dcache.free_keys.push(FreeKeyRange{start: key_count + 1, run: KEY_MAXCOUNT as u32 - 2 - key_count});
+        dcache.age = dcache.age.saturating_add(1);
+
+        // 1c. write out entirely fresh descriptor pages. We can't use dict_sync() here, because it merges into
+        // the existing page contents, which would preserve the descriptors of keys we just removed or renumbered.
+        let mut dict_name = [0u8; DICT_NAME_LEN];
+        for (src, dst) in name.bytes().into_iter().zip(dict_name.iter_mut()) {
+            *dst = src;
+        }
+        let dict_disk = Dictionary {
+            flags: dcache.flags,
+            age: dcache.age,
+            num_keys: dcache.key_count,
+            free_key_index: dcache.last_disk_key_index,
+            name: dict_name,
+        };
+        let dict_offset = dcache.index as u64 * DICT_VSIZE;
+        let dk_pages = key_count as usize / DK_PER_VPAGE + 1;
+        for vpage_num in 0..dk_pages {
+            let cur_vpage = VirtAddr::new(dict_offset + (vpage_num * VPAGE_SIZE) as u64).unwrap();
+            let mut page = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+            if let Some(old_pp) = self.v2p_map.remove(&cur_vpage) {
+                if let Some(old_page) = hw.data_decrypt_page(&self.cipher, &self.aad, &old_pp) {
+                    for (&src, dst) in old_page[..size_of::<JournalType>()].iter().zip(page[..size_of::<JournalType>()].iter_mut()) {
+                        *dst = src;
+                    }
+                }
+                displaced.push(old_pp);
+            }
+            if vpage_num == 0 {
+                for (&src, dst) in dict_disk.deref().iter().zip(page[size_of::<JournalType>()..size_of::<JournalType>() + DK_STRIDE].iter_mut()) {
+                    *dst = src;
+                }
+            }
+            for (key_name, key) in dcache.keys.iter_mut() {
+                if key.descriptor_vpage_num() == vpage_num {
+                    let mut kn = [0u8; KEY_NAME_LEN];
+                    for (&src, dst) in key_name.as_bytes().iter().zip(kn.iter_mut()) {
+                        *dst = src;
+                    }
+                    let key_desc = KeyDescriptor {
+                        start: key.start,
+                        len: key.len,
+                        reserved: key.reserved,
+                        flags: key.flags,
+                        age: key.age,
+                        name: kn,
+                    };
+                    let slot = size_of::<JournalType>() + (key.descriptor_index.get() as usize % DK_PER_VPAGE) * DK_STRIDE;
+                    for (&src, dst) in key_desc.deref().iter().zip(page[slot..slot + DK_STRIDE].iter_mut()) {
+                        *dst = src;
+                    }
+                    key.clean = true;
+                }
+            }
+            let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "No free space to compact dictionary"))?;
+            pp.set_valid(true);
+            hw.data_encrypt_and_patch_page(&self.cipher, &self.aad, &mut page, &pp);
+            self.v2p_map.insert(cur_vpage, pp);
+        }
+        // descriptor pages past the new end of the key list are no longer needed
+        let stale_dk: Vec::<VirtAddr> = self.v2p_map.keys()
+            .filter(|&va| va.get() >= dict_offset + (dk_pages * VPAGE_SIZE) as u64 && va.get() < dict_offset + DICT_VSIZE)
+            .map(|&va| va)
+            .collect();
+        for va in stale_dk {
+            if let Some(old_pp) = self.v2p_map.remove(&va) {
+                displaced.push(old_pp);
+            }
+        }
+        dcache.clean = true;
+
+        // 1d. every virtual page that lost its mapping gets a blank page with a newer journal rev, which outranks
+        // the old page in case it outlives the commit. The blanks are released again once the old pages are gone.
+        let by_page: HashMap::<u32, VirtAddr> = before.iter().map(|(&va, pp)| (pp.page_number(), va)).collect();
+        let mut vacated = Vec::<VirtAddr>::new();
+        for old_pp in displaced.iter() {
+            let va = match by_page.get(&old_pp.page_number()) {
+                Some(&va) => va,
+                None => continue,
+            };
+            if self.v2p_map.contains_key(&va) {
+                continue;
+            }
+            let mut page = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+            if let Some(old_page) = hw.data_decrypt_page(&self.cipher, &self.aad, old_pp) {
+                for (&src, dst) in old_page[..size_of::<JournalType>()].iter().zip(page[..size_of::<JournalType>()].iter_mut()) {
+                    *dst = src;
+                }
+            }
+            let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "No free space to compact dictionary"))?;
+            pp.set_valid(true);
+            hw.data_encrypt_and_patch_page(&self.cipher, &self.aad, &mut page, &pp);
+            self.v2p_map.insert(va, pp);
+            vacated.push(va);
+        }
+
+        // 1e. a fresh copy of the basis root, one age and one journal rev ahead of the current one
+        let root_va = VirtAddr::new(VPAGE_SIZE as u64).unwrap();
+        let mut root_pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "No free space to compact dictionary"))?;
+        root_pp.set_valid(true);
+        let mut block = self.root_block(self.age.saturating_add(1), self.journal.saturating_add(1));
+        hw.data_encrypt_and_patch_page(&self.cipher, &self.aad, &mut block, &root_pp);
+        if let Some(old_root) = self.v2p_map.insert(root_va, root_pp) {
+            displaced.push(old_root);
+        }
+        Ok((displaced, vacated))
+    }
+
+    /// Serializes the basis root, with the journal rev that precedes the one it will be written with
+    fn root_block(&self, age: u32, journal: JournalType) -> [u8; VPAGE_SIZE + size_of::<JournalType>()] {
+        let basis_root = BasisRoot {
+            magic: PDDB_MAGIC,
+            version: PDDB_VERSION,
+            name: BasisRootName::try_from_str(&self.name).unwrap(),
+            age,
+            num_dictionaries: self.num_dicts,
+        };
+        let journal_bytes = journal.to_le_bytes(); // journal gets bumped by the patching function
+        let slice_iter =
+            journal_bytes.iter() // journal rev
+            .chain(basis_root.as_ref().iter());
+        let mut block = [0 as u8; VPAGE_SIZE + size_of::<JournalType>()];
+        for (&src, dst) in slice_iter.zip(block.iter_mut()) {
+            *dst = src;
+        }
+        block
     }
 
     /// Syncs *only* the basis header to disk.
     pub(crate) fn basis_sync(&mut self, hw: &mut PddbOs) {
         self.last_sync = Some(hw.timestamp_now());
         if !self.clean {
-            let basis_root = BasisRoot {
-                magic: PDDB_MAGIC,
-                version: PDDB_VERSION,
-                name: BasisRootName::try_from_str(&self.name).unwrap(),
-                age: self.age,
-                num_dictionaries: self.num_dicts,
-            };
             let pp = self.v2p_map.get(&VirtAddr::new(1 * VPAGE_SIZE as u64).unwrap())
                 .expect("Internal consistency error: Basis exists, but its root map was not allocated!");
-            let journal_bytes = self.journal.to_le_bytes(); // journal gets bumped by the patching function now
-            let slice_iter =
-                journal_bytes.iter() // journal rev
-                .chain(basis_root.as_ref().iter());
-            let mut block = [0 as u8; VPAGE_SIZE + size_of::<JournalType>()];
-            for (&src, dst) in slice_iter.zip(block.iter_mut()) {
-                *dst = src;
-            }
+            let mut block = self.root_block(self.age, self.journal);
             hw.data_encrypt_and_patch_page(&self.cipher, &self.aad, &mut block, &pp);
             self.clean = true;
         }
@@ -1039,6 +1561,76 @@ impl BasisCacheEntry {
         dict_found
     }
 
+    /// Writes every dirty structure in the basis to disk: the small pools and descriptors of each cached
+    /// dictionary, the basis root, and finally the page table.
+    pub(crate) fn sync(&mut self, hw: &mut PddbOs) -> Result<()> {
+        let dict_names: Vec::<String> = self.dicts.keys().cloned().collect();
+        for name in dict_names.iter() {
+            if let Some(dict_entry) = self.dicts.get_mut(name) {
+                dict_entry.sync_small_pool(hw, &mut self.v2p_map, &self.cipher);
+                dict_entry.sync_large_pool();
+            }
+            self.dict_sync(hw, name)?;
+        }
+        self.basis_sync(hw);
+        self.pt_sync(hw);
+        Ok(())
+    }
+    /// Wipes all the cached key data of the basis from RAM, along with its key schedules and the names of
+    /// the basis, its dictionaries and keys, and drops its dictionary and page table caches.
+    /// The entry can't be used after this; it's meant to be called just before a locked basis is dropped.
+    pub(crate) fn purge(&mut self) {
+        for (mut name, mut dict_entry) in self.dicts.drain() {
+            dict_entry.purge();
+            volatile_wipe_string(&mut name);
+        }
+        self.v2p_map.clear();
+        // the ciphers don't zeroize themselves on drop. Both hold nothing but arrays of round keys, so
+        // they can be wiped in place.
+        unsafe {
+            volatile_wipe_value(&mut self.cipher);
+            volatile_wipe_value(&mut self.cipher_ecb);
+        }
+        volatile_wipe(&mut self.aad);
+        volatile_wipe_string(&mut self.name);
+    }
+
+    /// Number of bytes of large pool data currently held in the key caches of this basis.
+    pub(crate) fn large_cache_usage(&self) -> usize {
+        let mut pages = 0;
+        for dict_entry in self.dicts.values() {
+            for kcache in dict_entry.keys.values() {
+                pages += kcache.large_pages_cached();
+            }
+        }
+        pages * VPAGE_SIZE
+    }
+    /// Evicts the least recently used large pool pages from the key caches, until the cache fits within
+    /// `large_cache_limit`. The caches are write-through, so eviction never has to touch the disk.
+    pub(crate) fn large_cache_prune(&mut self) {
+        let max_pages = self.large_cache_limit / VPAGE_SIZE;
+        if self.large_cache_usage() / VPAGE_SIZE <= max_pages {
+            return;
+        }
+        let mut candidates = Vec::<(u64, String, String, VirtAddr)>::new();
+        for (dict_name, dict_entry) in self.dicts.iter() {
+            for (key_name, kcache) in dict_entry.keys.iter() {
+                if let Some(KeyCacheData::Large(cache)) = &kcache.data {
+                    for (&vpage, page) in cache.pages.iter() {
+                        candidates.push((page.last_used, dict_name.to_string(), key_name.to_string(), vpage));
+                    }
+                }
+            }
+        }
+        candidates.sort_by_key(|c| c.0);
+        let excess = candidates.len() - max_pages;
+        for (_, dict_name, key_name, vpage) in candidates.into_iter().take(excess) {
+            if let Some(kcache) = self.dicts.get_mut(&dict_name).and_then(|d| d.keys.get_mut(&key_name)) {
+                kcache.large_page_evict(vpage);
+            }
+        }
+    }
+
     // allocate a pointer data in the large pool, of length `amount`. "always" succeeds because...
     // there's 16 million terabytes of large pool to allocate before you run out?
     /*
@@ -1058,6 +1650,18 @@ impl BasisCacheEntry {
 // Beginning of serializers for the data structures in this file.
 // ****
 
+/// Checks that a name can be used for a secret basis.
+fn basis_name_check(name: &str) -> Result<()> {
+    // names are stored null-terminated in the basis root, so a name can't use up the whole field
+    if name.len() == 0 || name.len() >= BASIS_NAME_LEN {
+        Err(Error::new(ErrorKind::InvalidInput, "Basis names must be between 1 and 63 bytes long"))
+    } else if name == PDDB_DEFAULT_SYSTEM_BASIS || name == PDDB_FAST_SPACE_SYSTEM_BASIS {
+        Err(Error::new(ErrorKind::PermissionDenied, "Basis name is reserved for the system"))
+    } else {
+        Ok(())
+    }
+}
+
 /// Newtype for BasisRootName so we can give it a default initializer.
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub struct BasisRootName(pub [u8; BASIS_NAME_LEN]);
@@ -159,8 +159,11 @@ impl DictCacheEntry {
         if self.keys.len() < self.key_count as usize {
             self.fill(hw, v2p_map, cipher);
         }
-        for key in self.keys.keys() {
-            merge_list.insert(key.to_string());
+        // removed keys linger in the cache as invalid entries until the dictionary is synced
+        for (key, kcache) in self.keys.iter() {
+            if kcache.flags.valid() {
+                merge_list.insert(key.to_string());
+            }
         }
     }
     /// Simply ensures we have the description of a key in cache. Only tries to load small key data.
@@ -302,10 +305,22 @@ impl DictCacheEntry {
         self.clean = false;
         if self.ensure_key_entry(hw, v2p_map, cipher, name) {
             let kcache = self.keys.get_mut(name).expect("Entry was assured, but then not there!");
-            // the update isn't going to fit in the reserved space, remove it, and re-insert it with an entirely new entry.
+            // the update isn't going to fit in the reserved space. Read out the existing data, merge in the update,
+            // remove the key, and re-insert it as an entirely new entry. This costs a copy of the whole key in RAM,
+            // but the large pool can't be grown in place, and without the merge any data around the update is lost.
             if kcache.reserved < (data.len() + offset) as u64 {
+                let mut merged = self.key_data(hw, v2p_map, cipher, name);
+                if truncate {
+                    merged.truncate(offset);
+                }
+                while merged.len() < data.len() + offset {
+                    merged.push(0);
+                }
+                for (&src, dst) in data.iter().zip(merged[offset..].iter_mut()) {
+                    *dst = src;
+                }
                 self.key_remove(hw, v2p_map, cipher, name, false);
-                return self.key_update(hw, v2p_map, cipher, name, data, offset, alloc_hint, truncate, large_alloc_ptr);
+                return self.key_update(hw, v2p_map, cipher, name, &merged, 0, alloc_hint, truncate, large_alloc_ptr);
             }
             // the key exists, *and* there's sufficient space for the data
             if kcache.start < SMALL_POOL_END {
@@ -321,7 +336,13 @@ impl DictCacheEntry {
                     for (&src, dst) in data.iter().zip(cache_data.data[offset..].iter_mut()) {
                         *dst = src;
                     }
-                    // for now, we ignore "truncate" on a small key
+                    if truncate {
+                        cache_data.data.truncate(data.len() + offset);
+                    }
+                    if kcache.len != cache_data.data.len() as u64 {
+                        kcache.len = cache_data.data.len() as u64;
+                        kcache.clean = false;
+                    }
                 } else {
                     panic!("Key allocated to small area but its cache data was not of the small type");
                 }
@@ -330,74 +351,52 @@ impl DictCacheEntry {
                 self.small_pool[pool_index].clean = false;
                 // note: there is no need to update small_pool_free because the reserved size did not change.
             } else {
-                // it's a large key
-                if let Some(_kcd) = &kcache.data {
-                    unimplemented!("caching is not yet implemented for large data sets");
-                } else {
-                    kcache.age = kcache.age.saturating_add(1);
-                    kcache.clean = false;
-                    // 1. handle unaligned start offsets
-                    let mut written: usize = 0;
-                    if ((kcache.start + offset as u64) % VPAGE_SIZE as u64) != 0 {
-                        let start_vpage_addr = ((kcache.start + offset as u64) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
-                        let pp = v2p_map.get(&VirtAddr::new(start_vpage_addr).unwrap()).expect("large key data allocation missing");
-                        let mut pt_data = hw.data_decrypt_page(&cipher, &self.aad, pp).expect("Decryption auth error");
-                        for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>() + offset..].iter_mut()) {
-                            *dst = src;
-                            written += 1;
-                        }
-                        if written < data.len() {
-                            assert!((kcache.start + offset as u64 + written as u64) % VPAGE_SIZE as u64 == 0, "alignment algorithm failed");
-                        }
-                        hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut pt_data, &pp);
-                    }
-                    // 2. do the rest
-                    while written < data.len() {
-                        let vpage_addr = ((kcache.start + written as u64 + offset as u64) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
-                        let pp = v2p_map.get(&VirtAddr::new(vpage_addr).unwrap()).expect("large key data allocation missing");
-                        if data.len() - written >= VPAGE_SIZE {
-                            // overwrite whole pages without decryption
-                            let mut block = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
-                            for (&src, dst) in data[written..].iter().zip(block[size_of::<JournalType>()..].iter_mut()) {
-                                *dst = src;
-                                written += 1;
-                            }
-                            hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut block, pp);
-                        } else {
-                            // handle partial trailing pages
-                            if let Some(pt_data) = hw.data_decrypt_page(&cipher, &self.aad, pp).as_mut() {
-                                for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>()..].iter_mut()) {
-                                    *dst = src;
-                                    written += 1;
-                                }
-                                hw.data_encrypt_and_patch_page(cipher, &self.aad, pt_data, pp);
-                            } else {
-                                // page didn't exist, initialize it with 0's and merge the tail end.
-                                let mut pt_data = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
-                                for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>()..].iter_mut()) {
-                                    *dst = src;
-                                    written += 1;
-                                }
-                                hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut pt_data, pp);
-                            }
+                // it's a large key. Pages that are only partially overwritten have to be merged with their existing
+                // contents, which come out of the key's page cache if possible. Every page is written straight
+                // through to disk, and any cached copy is updated to match.
+                kcache.age = kcache.age.saturating_add(1);
+                kcache.clean = false;
+                let mut written: usize = 0;
+                while written < data.len() {
+                    let cur_addr = kcache.start + (offset + written) as u64;
+                    let vpage = VirtAddr::new((cur_addr / VPAGE_SIZE as u64) * VPAGE_SIZE as u64).unwrap();
+                    // only the first page can have a mis-aligned start; every page after that starts at 0.
+                    let page_offset = (cur_addr % VPAGE_SIZE as u64) as usize;
+                    let chunk = (data.len() - written).min(VPAGE_SIZE - page_offset);
+                    let pp = v2p_map.get(&vpage).expect("large key data allocation missing");
+                    let mut block = if chunk == VPAGE_SIZE {
+                        // overwrite whole pages without decryption
+                        vec![0u8; VPAGE_SIZE + size_of::<JournalType>()]
+                    } else {
+                        match kcache.large_page_fetch(hw, v2p_map, cipher, &self.aad, vpage) {
+                            Some(pt_data) => pt_data.to_vec(),
+                            // page didn't exist, initialize it with 0's and merge the update in.
+                            None => vec![0u8; VPAGE_SIZE + size_of::<JournalType>()],
                         }
-                    }
-                    log::info!("data written: {}, data requested to write: {}", written, data.len());
-                    assert!(written == data.len(), "algorithm problem -- didn't write all the data we thought we would");
-                    // 3. truncate.
-                    if truncate {
-                        // discard all whole pages after written+offset, and reset the reserved field to the smaller size.
-                        let vpage_end_offset = PageAlignedVa::from((written + offset) as u64);
-                        if (vpage_end_offset.as_u64() - kcache.start) > kcache.reserved {
-                            for vpage in (vpage_end_offset.as_u64()..kcache.start + kcache.reserved).step_by(VPAGE_SIZE) {
-                                if let Some(pp) = v2p_map.remove(&VirtAddr::new(vpage).unwrap()) {
-                                    hw.fast_space_free(pp);
-                                }
+                    };
+                    block[size_of::<JournalType>() + page_offset..size_of::<JournalType>() + page_offset + chunk]
+                        .copy_from_slice(&data[written..written + chunk]);
+                    hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut block, pp);
+                    kcache.large_page_update(vpage, &block);
+                    written += chunk;
+                }
+                // truncate.
+                if truncate {
+                    // discard all whole pages after written+offset, and reset the reserved field to the smaller size.
+                    // at least one page is always kept, so the key's start address stays mapped.
+                    let vpage_end_offset = PageAlignedVa::from(((written + offset) as u64).max(VPAGE_SIZE as u64));
+                    if vpage_end_offset.as_u64() < kcache.reserved {
+                        for vpage in (kcache.start + vpage_end_offset.as_u64()..kcache.start + kcache.reserved).step_by(VPAGE_SIZE) {
+                            if let Some(pp) = v2p_map.remove(&VirtAddr::new(vpage).unwrap()) {
+                                hw.fast_space_free(pp);
                             }
-                            kcache.reserved = vpage_end_offset.as_u64() - kcache.start;
-                            kcache.clean = false;
+                            kcache.large_page_evict(VirtAddr::new(vpage).unwrap());
                         }
+                        kcache.reserved = vpage_end_offset.as_u64();
                     }
+                    kcache.len = (written + offset) as u64;
+                } else if (written + offset) as u64 > kcache.len {
+                    kcache.len = (written + offset) as u64;
                 }
             }
         } else {
@@ -492,7 +491,7 @@ impl DictCacheEntry {
                     age: 0,
                     descriptor_index,
                     clean: false,
-                    data: None, // no caching implemented yet for large keys
+                    data: None, // large keys start out uncached; their pages are cached as they are accessed
                 };
                 self.keys.insert(name.to_string(), kcache);
                 self.key_count += 1;
@@ -507,6 +506,35 @@ impl DictCacheEntry {
         }
         Ok(large_alloc_ptr)
     }
+    /// Returns a copy of all the data stored in a key, up to its length. Assumes the caller
+    /// has already done an `ensure_key_entry()` for the key.
+    fn key_data(&self, hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &Aes256GcmSiv, name: &str) -> Vec::<u8> {
+        let kcache = self.keys.get(name).expect("key_data called on a key that isn't in the cache");
+        let mut data = Vec::<u8>::new();
+        if let Some(KeyCacheData::Small(cache_data)) = &kcache.data {
+            for &b in cache_data.data.iter().take(kcache.len as usize) {
+                data.push(b);
+            }
+        } else {
+            for vpage in kcache.large_pool_vpages() {
+                if data.len() as u64 >= kcache.len {
+                    break;
+                }
+                let chunk = ((kcache.len - data.len() as u64) as usize).min(VPAGE_SIZE);
+                if let Some(page) = v2p_map.get(&vpage).and_then(|pp| hw.data_decrypt_page(cipher, &self.aad, pp)) {
+                    for &b in page[size_of::<JournalType>()..size_of::<JournalType>() + chunk].iter() {
+                        data.push(b);
+                    }
+                } else {
+                    // reserved, but never written
+                    for _ in 0..chunk {
+                        data.push(0);
+                    }
+                }
+            }
+        }
+        data
+    }
     #[allow(dead_code)]
     pub fn key_contains(&mut self, name: &str) -> bool {
         self.keys.contains_key(&String::from(name))
@@ -520,7 +548,7 @@ impl DictCacheEntry {
     }
     /// Used to remove a key from the dictionary. If you call it with a non-existent key,
     /// the routine has no effect, and does not report an error. Small keys are not immediately
-    /// overwritten in paranoid mode, but large keys are.
+    /// overwritten in paranoid mode, but large keys are; use `key_erase()` to destroy a small key's data immediately.
     pub fn key_remove(&mut self, hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, cipher: &Aes256GcmSiv,
         name_str: &str, paranoid: bool) {
         // this call makes sure we have a cache entry to operate on.
@@ -578,12 +606,47 @@ impl DictCacheEntry {
         //   - disk still contains a key entry that claims we have a valid key
         // a call to sync is necessary to completely flush things, but, we don't sync every time we remove because it's inefficient.
     }
-    /// used to remove a key from the dictionary, syncing 0's to the disk in the key's place
-    /// sort of less relevant now that the large keys have a paranoid mode; probably this routine should actually
-    /// be a higher-level function that catches the paranoid request and does an "update" of 0's to the key
-    /// then does a disk sync and then calls remove
-    pub fn key_erase(&mut self, _name: &str) {
-        unimplemented!();
+    /// Removes a key and destroys its data on disk, rather than just dropping its bookkeeping. Large pool
+    /// pages are overwritten with TRNG noise before they are released. The small pool slot that held a
+    /// small key is re-written without it right away, into a fresh page, and any copy of the data in the
+    /// key cache is wiped as well. The key's descriptor is scrubbed by the next `dict_sync`.
+    ///
+    /// Returns the page that held the small pool slot before the re-write. It still holds the erased key's
+    /// ciphertext, and its PTE is still on disk: once `dict_sync` and `pt_sync` have committed the new copy,
+    /// hand it to `PddbOs::pages_release()`, which overwrites it with noise.
+    ///
+    /// The small pool re-write needs a page of FastSpace, so call `ensure_fast_space_alloc()` with
+    /// `alloc_estimate_small()` beforehand.
+    pub(crate) fn key_erase(&mut self, hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, cipher: &Aes256GcmSiv,
+        name_str: &str) -> Result<Vec::<PhysPage>> {
+        self.ensure_key_entry(hw, v2p_map, cipher, name_str);
+        let small_index = self.keys.get(name_str).and_then(|kcache| small_storage_index_from_key(kcache, self.index));
+        self.key_remove(hw, v2p_map, cipher, name_str, true);
+        if let Some(kcache) = self.keys.get_mut(name_str) {
+            kcache.purge();
+        }
+        let mut displaced = Vec::<PhysPage>::new();
+        if let Some(index) = small_index {
+            let pool_vaddr = VirtAddr::new(self.index as u64 * SMALL_POOL_STRIDE + SMALL_POOL_START + index as u64 * SMALL_CAPACITY as u64).unwrap();
+            let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "No free space to erase small key"))?;
+            pp.set_valid(true);
+            let mut page = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+            if let Some(old_pp) = v2p_map.remove(&pool_vaddr) {
+                // carry the journal rev forward, so that until the old page is released, the page table
+                // scan resolves the duplicate mapping in favor of the new copy.
+                if let Some(old_page) = hw.data_decrypt_page(cipher, &self.aad, &old_pp) {
+                    page[..size_of::<JournalType>()].copy_from_slice(&old_page[..size_of::<JournalType>()]);
+                }
+                displaced.push(old_pp);
+            }
+            small_pool_pack(&mut self.keys, &self.small_pool[index].contents, pool_vaddr, &mut page);
+            hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut page, &pp);
+            v2p_map.insert(pool_vaddr, pp);
+            self.small_pool[index].clean = true;
+        }
+        // flush any other pool that was left dirty, so the caller's sync sees a consistent set of descriptors
+        self.sync_small_pool(hw, v2p_map, cipher);
+        Ok(displaced)
     }
     /// estimates the amount of space needed to sync the dict cache. Pass this to ensure_fast_space_alloc() before calling a sync.
     /// estimate can be inaccurate under pathological allocation conditions.
@@ -637,26 +700,7 @@ impl DictCacheEntry {
                 //   5. sync the page tables
                 // This implementation just skips to step 3.
                 let mut page = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
-                let mut pool_offset = 0;
-                // visit the entries in arbitrary order, but back them in optimally
-                for key_name in &entry.contents {
-                    let kcache = self.keys.get_mut(key_name).expect("data record without index");
-                    kcache.start = pool_vaddr.get() + pool_offset as u64;
-                    kcache.age = kcache.age.saturating_add(1);
-                    kcache.clean = false;
-                    kcache.flags.set_unresolved(false);
-                    kcache.flags.set_valid(true);
-                    if let Some(KeyCacheData::Small(data)) = kcache.data.as_mut() {
-                        data.clean = true;
-                        for (&src, dst) in data.data.iter()
-                        .zip(page[size_of::<JournalType>() + pool_offset..size_of::<JournalType>() + pool_offset + kcache.reserved as usize].iter_mut())
-                        {*dst = src;}
-                    } else {
-                        // we have a rule that all small keys, when cached, also carry their data: there should not be an index without data.
-                        panic!("Incorrect data cache type for small key entry.");
-                    }
-                    pool_offset += kcache.reserved as usize;
-                }
+                small_pool_pack(&mut self.keys, &entry.contents, pool_vaddr, &mut page);
                 // now commit the sector to disk
                 hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut page, &pp);
                 entry.clean = true;
@@ -665,10 +709,152 @@ impl DictCacheEntry {
         // we now have a bunch of dirty kcache entries. You should call `dict_sync` shortly after this to synchronize those entries to disk.
     }
 
-    /// No data cache to flush yet...large pool caches not implemented!
+    /// Large pool caches are write-through, so there is never any dirty data to flush. This exists
+    /// to mirror `sync_small_pool()`, in case the caching policy changes.
     pub(crate) fn sync_large_pool(&self) {
     }
 
+    /// Wipes the cached data of every key in the dictionary and drops the key cache entries. Any
+    /// unsynced changes are lost, so sync the dictionary first.
+    pub(crate) fn purge(&mut self) {
+        for (mut name, mut kcache) in self.keys.drain() {
+            kcache.purge();
+            volatile_wipe_string(&mut name);
+        }
+        for mut ksp in self.small_pool.drain(..) {
+            for name in ksp.contents.iter_mut() {
+                volatile_wipe_string(name);
+            }
+        }
+        self.small_pool_free.clear();
+        volatile_wipe(&mut self.aad);
+    }
+
+    /// Repacks all the live small keys into the minimum number of small pool slots, and writes the result
+    /// into freshly allocated pages. Keys are packed first-fit, largest reservation first.
+    ///
+    /// Returns the physical pages that were displaced by the repack. These still hold the old (valid!)
+    /// ciphertext and their PTEs are still on disk, so they must not be released until the page table has
+    /// been synced with the new mappings. The key descriptors are left dirty; the caller is responsible
+    /// for rewriting them.
+    ///
+    /// Assumes the caller has done a `fill()`, and made sure there is enough FastSpace for one fresh page per
+    /// currently allocated small pool slot. If it runs out anyways, the cache is left half-way through the
+    /// repack, and the caller has to throw it away.
+    pub(crate) fn compact_small_pool(&mut self, hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, cipher: &Aes256GcmSiv) -> Result<Vec::<PhysPage>> {
+        let mut displaced = Vec::<PhysPage>::new();
+        // 1. figure out the new packing
+        let mut live = Vec::<(String, u64)>::new();
+        for ksp in self.small_pool.iter() {
+            for key_name in ksp.contents.iter() {
+                let kcache = self.keys.get(key_name).expect("data record without index");
+                live.push((key_name.to_string(), kcache.reserved));
+            }
+        }
+        live.sort_by(|a, b| b.1.cmp(&a.1));
+        let mut packed = Vec::<KeySmallPool>::new();
+        for (key_name, reserved) in live {
+            if let Some(ksp) = packed.iter_mut().find(|ksp| ksp.avail as u64 >= reserved) {
+                ksp.contents.push(key_name);
+                ksp.avail -= reserved as u16;
+            } else {
+                let mut ksp = KeySmallPool::new();
+                ksp.contents.push(key_name);
+                ksp.avail -= reserved as u16;
+                packed.push(ksp);
+            }
+        }
+        log::info!("compacting small pool of dict {}: {} slots -> {} slots", self.index, self.small_pool.len(), packed.len());
+
+        // 2. serialize the new pools into fresh pages
+        for (index, ksp) in packed.iter_mut().enumerate() {
+            let pool_vaddr = VirtAddr::new(self.index as u64 * SMALL_POOL_STRIDE + SMALL_POOL_START + index as u64 * SMALL_CAPACITY as u64).unwrap();
+            let mut page = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+            if let Some(old_pp) = v2p_map.remove(&pool_vaddr) {
+                // carry the journal rev forward, so that if we lose power before the old page is erased,
+                // the page table scan resolves the duplicate mapping in favor of the new copy.
+                if let Some(old_page) = hw.data_decrypt_page(cipher, &self.aad, &old_pp) {
+                    for (&src, dst) in old_page[..size_of::<JournalType>()].iter().zip(page[..size_of::<JournalType>()].iter_mut()) {
+                        *dst = src;
+                    }
+                }
+                displaced.push(old_pp);
+            }
+            small_pool_pack(&mut self.keys, &ksp.contents, pool_vaddr, &mut page);
+            let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "No free space to compact small key storage"))?;
+            pp.set_valid(true);
+            hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut page, &pp);
+            v2p_map.insert(pool_vaddr, pp);
+            ksp.clean = true;
+        }
+        // 3. any slots past the end of the new packing are no longer needed
+        for index in packed.len()..self.small_pool.len() {
+            let pool_vaddr = VirtAddr::new(self.index as u64 * SMALL_POOL_STRIDE + SMALL_POOL_START + index as u64 * SMALL_CAPACITY as u64).unwrap();
+            if let Some(old_pp) = v2p_map.remove(&pool_vaddr) {
+                displaced.push(old_pp);
+            }
+        }
+        self.small_pool = packed;
+        self.rebuild_free_pool();
+        Ok(displaced)
+    }
+
+    /// Moves the data of every live large key onto freshly allocated pages, and trims each key's reservation
+    /// down to the pages actually needed to hold its data. The virtual addresses of the keys do not change;
+    /// de-allocated large pool address space is still never reclaimed.
+    ///
+    /// Returns the displaced physical pages, with the same caveats as `compact_small_pool()`.
+    pub(crate) fn compact_large_pool(&mut self, hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, cipher: &Aes256GcmSiv) -> Result<Vec::<PhysPage>> {
+        let mut displaced = Vec::<PhysPage>::new();
+        for (key_name, kcache) in self.keys.iter_mut() {
+            if kcache.start < LARGE_POOL_START || !kcache.flags.valid() {
+                continue;
+            }
+            // always keep at least one page, so that the key's start address stays mapped
+            let trimmed = if kcache.len == 0 {
+                VPAGE_SIZE as u64
+            } else {
+                PageAlignedVa::from(kcache.len).as_u64()
+            };
+            log::info!("compacting large key {}: reserved {} -> {}", key_name, kcache.reserved, trimmed.min(kcache.reserved));
+            for vpage in kcache.large_pool_vpages() {
+                if let Some(old_pp) = v2p_map.remove(&vpage) {
+                    let key_offset = vpage.get() - kcache.start;
+                    if key_offset < trimmed {
+                        let mut page = match hw.data_decrypt_page(cipher, &self.aad, &old_pp) {
+                            Some(data) => data,
+                            // reserved but never written
+                            None => vec![0u8; VPAGE_SIZE + size_of::<JournalType>()],
+                        };
+                        // don't carry anything past the end of the key's data into the new page
+                        let valid_len = if key_offset < kcache.len {
+                            ((kcache.len - key_offset) as usize).min(VPAGE_SIZE)
+                        } else {
+                            0
+                        };
+                        for b in page[size_of::<JournalType>() + valid_len..].iter_mut() {
+                            *b = 0;
+                        }
+                        let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "No free space to compact large key storage"))?;
+                        pp.set_valid(true);
+                        hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut page, &pp);
+                        kcache.large_page_update(vpage, &page);
+                        v2p_map.insert(vpage, pp);
+                    } else {
+                        kcache.large_page_evict(vpage);
+                    }
+                    displaced.push(old_pp);
+                }
+            }
+            if trimmed < kcache.reserved {
+                kcache.reserved = trimmed;
+                kcache.age = kcache.age.saturating_add(1);
+                kcache.clean = false;
+            }
+        }
+        Ok(displaced)
+    }
+
     /// Finds the next available slot to store the key metadata (not the data itself). It also
     /// does bookkeeping to bound brute-force searches for keys within the dictionary's index space.
     pub(crate) fn get_free_key_index(&mut self) -> Option<NonZeroU32> {
@@ -741,6 +927,32 @@ impl DictCacheEntry {
     }
 }
 
+/// Packs the cached data of the small keys listed in `contents` into a small pool page that will live at
+/// `pool_vaddr`, and points their cache entries at their new locations. `page` is a plaintext page buffer,
+/// journal header included; the data area must be zeroed.
+fn small_pool_pack(keys: &mut HashMap::<String, KeyCacheEntry>, contents: &[String], pool_vaddr: VirtAddr, page: &mut [u8]) {
+    let mut pool_offset = 0;
+    // visit the entries in arbitrary order, but back them in optimally
+    for key_name in contents {
+        let kcache = keys.get_mut(key_name).expect("data record without index");
+        kcache.start = pool_vaddr.get() + pool_offset as u64;
+        kcache.age = kcache.age.saturating_add(1);
+        kcache.clean = false;
+        kcache.flags.set_unresolved(false);
+        kcache.flags.set_valid(true);
+        if let Some(KeyCacheData::Small(data)) = kcache.data.as_mut() {
+            data.clean = true;
+            for (&src, dst) in data.data.iter()
+            .zip(page[size_of::<JournalType>() + pool_offset..size_of::<JournalType>() + pool_offset + kcache.reserved as usize].iter_mut())
+            {*dst = src;}
+        } else {
+            // we have a rule that all small keys, when cached, also carry their data: there should not be an index without data.
+            panic!("Incorrect data cache type for small key entry.");
+        }
+        pool_offset += kcache.reserved as usize;
+    }
+}
+
 /// Derives the index of a Small Pool storage block given the key cache entry and the dictionary index.
 /// The index maps into the small_pool array, which itself maps 1:1 onto blocks inside the small pool
 /// memory space.
@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 use crate::api::*;
+use super::PAGE_SIZE;
 
 use std::sync::Once;
 use std::mem::MaybeUninit;
@@ -16,6 +17,20 @@ use std::io::prelude::*;
 // Note that this is a concurrently accessed, unsafe, unchecked vector.
 struct FlashSingleton {
     memory: Vec::<u8>,
+    /// a pending power failure, and the number of sector writes left before it strikes
+    fault: Option<(PowerFault, usize)>,
+    /// cleared when a simulated power failure strikes; every write is dropped until power is restored
+    powered: bool,
+}
+
+/// How a simulated power failure treats the sector write that it interrupts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PowerFault {
+    /// the interrupted sector write never reaches the FLASH
+    Abort,
+    /// the interrupted sector write is torn: only the first half of it is programmed, and the rest
+    /// of the written range is left erased, as if power went out between the erase and the program.
+    Tear,
 }
 
 fn flashmem() -> &'static mut FlashSingleton {
@@ -30,6 +45,8 @@ fn flashmem() -> &'static mut FlashSingleton {
             }
             let flashmem = FlashSingleton {
                 memory,
+                fault: None,
+                powered: true,
             };
             SINGLETON.write(flashmem);
         });
@@ -60,6 +77,24 @@ impl EmuStorage {
             *b = 0xFF;
         }
     }
+    /// replaces the entire contents of the storage, e.g. with an image saved with `as_slice()`
+    pub fn restore(&mut self, image: &[u8]) {
+        flashmem().memory.copy_from_slice(image);
+    }
+    /// Arms a simulated power failure: the next `sector_writes` sector writes go through, and the
+    /// one after that is interrupted as described by `fault`. Nothing is written after that, until
+    /// `power_restore()` is called.
+    pub fn power_fault_arm(&mut self, fault: PowerFault, sector_writes: usize) {
+        flashmem().fault = Some((fault, sector_writes));
+        flashmem().powered = true;
+    }
+    /// Disarms any pending power failure and restores power. Returns `true` if the power had failed.
+    pub fn power_restore(&mut self) -> bool {
+        let failed = !flashmem().powered;
+        flashmem().fault = None;
+        flashmem().powered = true;
+        failed
+    }
     pub fn dump_fs(&self, name: &Option<String>) {
         let defaultname = String::from("pddb");
         let rootname = name.as_ref().unwrap_or(&defaultname);
@@ -87,11 +122,37 @@ impl HostedSpinor {
         HostedSpinor {
         }
     }
+    /// Writes are counted one erase sector at a time, so that a simulated power failure (see
+    /// `EmuStorage::power_fault_arm()`) can strike between any two sector writes. Once the power has
+    /// failed, writes are silently dropped: on real hardware, the CPU would be dead too.
     pub fn patch(&self, _region: &[u8], _region_base: u32, data: &[u8], offset: u32) -> Result<(), xous::Error> {
-        for (&src, dst) in data.iter().zip(
-            flashmem().memory.as_mut_slice()[offset as usize..offset as usize + data.len()].iter_mut()
-        ) {
-            *dst = src;
+        let flash = flashmem();
+        let mut start = offset as usize;
+        let mut remaining = data;
+        while remaining.len() > 0 {
+            let sector_len = (PAGE_SIZE - start % PAGE_SIZE).min(remaining.len());
+            let (chunk, rest) = remaining.split_at(sector_len);
+            let dst = &mut flash.memory[start..start + sector_len];
+            match flash.fault {
+                _ if !flash.powered => {},
+                Some((fault, 0)) => {
+                    if fault == PowerFault::Tear {
+                        let torn = sector_len / 2;
+                        dst[..torn].copy_from_slice(&chunk[..torn]);
+                        for b in dst[torn..].iter_mut() {
+                            *b = 0xFF;
+                        }
+                    }
+                    flash.powered = false;
+                }
+                Some((fault, count)) => {
+                    dst.copy_from_slice(chunk);
+                    flash.fault = Some((fault, count - 1));
+                }
+                None => dst.copy_from_slice(chunk),
+            }
+            start += sector_len;
+            remaining = rest;
         }
         Ok(())
     }
@@ -14,11 +14,15 @@ use std::collections::HashSet;
 use std::collections::BinaryHeap;
 use std::cmp::Reverse;
 use std::io::{Result, Error, ErrorKind};
+use sha2::{FallbackStrategy, Sha512Trunc256};
+use digest::Digest;
 
 /// Implementation-specific PDDB structures: for Precursor/Xous OS pair
 
 pub(crate) const MBBB_PAGES: usize = 10;
 pub(crate) const FSCB_PAGES: usize = 16;
+/// bcrypt cost used to derive secret basis keys from passwords; matches the cost the root keys use for passwords.
+pub(crate) const BASIS_BCRYPT_COST: u32 = 7;
 
 /// size of a physical page
 pub const PAGE_SIZE: usize = spinor::SPINOR_ERASE_SIZE as usize;
@@ -189,16 +193,27 @@ impl PddbOs {
     pub fn dbg_dump(&self, _name: Option<String>) {
         // placeholder
     }
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    /// raw contents of the data region, for tests that need to inspect what's actually on the disk
+    pub(crate) fn dbg_data_region(&self) -> &[u8] {
+        &self.pddb_mr.as_slice()[self.data_phys_base.as_usize()..]
+    }
     #[allow(dead_code)]
     #[cfg(not(any(target_os = "none", target_os = "xous")))]
     /// used to reset the hardware structure for repeated runs of testing within a single invocation
     pub fn test_reset(&mut self) {
+        self.test_reboot();
+        self.pddb_mr.reset();
+    }
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    /// forgets everything held in RAM, leaving the storage as-is, as if the system had just booted.
+    /// Used to simulate a power cycle.
+    pub fn test_reboot(&mut self) {
         self.fspace_cache = HashSet::<PhysPage>::new();
         self.fspace_log_addrs = Vec::<PageAlignedPa>::new();
         self.system_basis_key = None;
         self.cipher_ecb = None;
         self.fspace_log_next_addr = None;
-        self.pddb_mr.reset();
     }
 
     pub(crate) fn nonce_gen(&mut self) -> Nonce {
@@ -316,9 +331,8 @@ impl PddbOs {
     /// physical mapping. Note that the `va` is an *address* (in units of bytes), and the `phy_page_num` is
     /// a *page number* (so a physical address divided by the page size). It's a slightly awkward units, but
     /// it saves a bit of math going back and forth between the native storage formats of the records.
-    pub(crate) fn pt_patch_mapping(&mut self, va: VirtAddr, phys_page_num: u32) {
-        self.syskey_ensure();
-        let cipher = self.cipher_ecb.as_ref().expect("Inconsistent internal state - syskey_ensure() failed");
+    /// Each basis encrypts its page table entries with its own key, so the caller provides the basis' cipher.
+    pub(crate) fn pt_patch_mapping(&mut self, va: VirtAddr, phys_page_num: u32, cipher: &Aes256) {
         let mut pte = Pte::new(va, PtFlags::CLEAN, Rc::clone(&self.entropy));
         let mut block = Block::from_mut_slice(pte.deref_mut());
         //log::info!("pte pt: {:x?}", block);
@@ -326,6 +340,13 @@ impl PddbOs {
         //log::info!("pte ct: {:x?}", block);
         self.patch_pagetable(&block, phys_page_num * aes::BLOCK_SIZE as u32);
     }
+    /// Overwrites the page table entry for `phys_page_num` with random data, which is cryptographically
+    /// indistinguishable from an entry that was never allocated. Goes through the MBBB like any other PT patch.
+    pub(crate) fn pt_erase_mapping(&mut self, phys_page_num: u32) {
+        let mut junk = [0u8; aes::BLOCK_SIZE];
+        self.entropy.borrow_mut().get_slice(&mut junk);
+        self.patch_pagetable(&junk, phys_page_num * aes::BLOCK_SIZE as u32);
+    }
     /// Searches the page table for an MBBB slot. This is currently an O(N) search but
     /// in practice for Precursor there are only 8 pages, so it's quite fast on average.
     /// This would want to be optimized or cached for a much larger filesystem.
@@ -344,12 +365,48 @@ impl PddbOs {
         &self.pddb_mr.as_slice()[self.pt_phys_base.as_usize()..self.pt_phys_base.as_usize() + size_of::<PageTableInFlash>()]
     }
 
+    /// Like `pt_patch_mapping()`, but the entry is ignored by a mount until the basis root is re-written with an
+    /// age whose low byte is `tag`. Used to stage a compaction so that it takes effect all at once.
+    pub(crate) fn pt_patch_pending_mapping(&mut self, va: VirtAddr, phys_page_num: u32, tag: u8, cipher: &Aes256) {
+        let mut pte = Pte::new_pending(va, tag, Rc::clone(&self.entropy));
+        let mut block = Block::from_mut_slice(pte.deref_mut());
+        cipher.encrypt_block(&mut block);
+        self.patch_pagetable(&block, phys_page_num * aes::BLOCK_SIZE as u32);
+    }
+
     /// scans the page tables and returns all entries for a given basis
     /// basis_name is needed to decrypt pages in case of a journal conflict
     pub(crate) fn pt_scan_key(&self, key: &[u8; AES_KEYSIZE], basis_name: &str) -> Option<HashMap::<VirtAddr, PhysPage>> {
+        let (map, _) = self.pt_scan(key, basis_name);
+        if map.len() > 0 {
+            Some(map)
+        } else {
+            None
+        }
+    }
+    /// Finishes off a compaction that was interrupted: pending entries that were committed are re-written as
+    /// ordinary entries, and the ones that never were are destroyed. Call before mounting a basis, so that
+    /// a later compaction can't accidentally commit stale entries that happen to carry the same tag.
+    pub(crate) fn pt_settle_pending(&mut self, key: &[u8; AES_KEYSIZE], basis_name: &str) {
+        let (_, pending) = self.pt_scan(key, basis_name);
+        if pending.len() == 0 {
+            return;
+        }
+        let cipher = Aes256::new(&GenericArray::from_slice(key));
+        for (va, pp, committed) in pending {
+            if committed {
+                self.pt_patch_mapping(va, pp.page_number(), &cipher);
+            } else {
+                self.pt_erase_mapping(pp.page_number());
+            }
+        }
+    }
+    /// Returns the resolved map of a basis, and every pending entry found along with whether it was committed.
+    fn pt_scan(&self, key: &[u8; AES_KEYSIZE], basis_name: &str) -> (HashMap::<VirtAddr, PhysPage>, Vec::<(VirtAddr, PhysPage, bool)>) {
         let cipher = Aes256::new(&GenericArray::from_slice(key));
         let pt = self.pt_as_slice();
         let mut map = HashMap::<VirtAddr, PhysPage>::new();
+        let mut pending = Vec::<(VirtAddr, PhysPage, u8)>::new();
         let blank = [0xffu8; aes::BLOCK_SIZE];
         for (page_index, pt_page) in pt.chunks(PAGE_SIZE).enumerate() {
             let clean_page = if pt_page[..aes::BLOCK_SIZE] == blank {
@@ -371,38 +428,60 @@ impl PddbOs {
                     pp.set_page_number(((page_index * PAGE_SIZE / aes::BLOCK_SIZE) + index) as PhysAddr);
                     // the state is clean because this entry is, by definition, synchronized with the disk
                     pp.set_clean(true);
-                    // handle conflicting journal versions here
-                    if let Some(prev_page) = map.get(&pte.vaddr()) {
-                        let cipher = Aes256GcmSiv::new(Key::from_slice(key));
-                        let aad = self.data_aad(basis_name);
-                        let prev_data = self.data_decrypt_page(&cipher, &aad, prev_page);
-                        let new_data = self.data_decrypt_page(&cipher, &aad, &pp);
-                        if let Some(new_d) = new_data {
-                            if let Some(prev_d) = prev_data {
-                                let prev_j = JournalType::from_le_bytes(prev_d[..size_of::<JournalType>()].try_into().unwrap());
-                                let new_j = JournalType::from_le_bytes(new_d[..size_of::<JournalType>()].try_into().unwrap());
-                                if new_j > prev_j {
-                                    map.insert(pte.vaddr(), pp);
-                                } else if new_j == prev_j {
-                                    log::error!("Found duplicate blocks with same journal age, picking arbitrary block and moving on...");
-                                }
-                            } else {
-                                // prev data was bogus anyways, replace with the new entry
-                                map.insert(pte.vaddr(), pp);
-                            }
-                        } else {
-                            // new data is bogus, ignore it
-                        }
+                    if let Some(tag) = pte.pending_tag() {
+                        pending.push((pte.vaddr(), pp, tag));
                     } else {
-                        map.insert(pte.vaddr(), pp);
+                        self.pt_resolve(&mut map, pte.vaddr(), pp, key, basis_name);
                     }
                 }
             }
         }
-        if map.len() > 0 {
-            Some(map)
+        // pending entries count only if the basis root that wins the scan above commits them
+        let committed_tag = map.get(&VirtAddr::new(VPAGE_SIZE as u64).unwrap()).and_then(|root_page| {
+            let cipher = Aes256GcmSiv::new(Key::from_slice(key));
+            let vpage = self.data_decrypt_page(&cipher, &self.data_aad(basis_name), root_page)?;
+            let mut basis_root = BasisRoot::default();
+            for (&src, dst) in vpage[size_of::<JournalType>()..].iter().zip(basis_root.deref_mut().iter_mut()) {
+                *dst = src;
+            }
+            Some(basis_root.age as u8)
+        });
+        let mut settled = Vec::<(VirtAddr, PhysPage, bool)>::new();
+        for (va, pp, tag) in pending {
+            let committed = committed_tag == Some(tag);
+            if committed {
+                self.pt_resolve(&mut map, va, pp, key, basis_name);
+            }
+            settled.push((va, pp, committed));
+        }
+        (map, settled)
+    }
+    /// Adds a mapping found in the page table, settling a conflict with an earlier mapping of the same vaddr
+    /// in favor of the page with the newer journal revision.
+    fn pt_resolve(&self, map: &mut HashMap::<VirtAddr, PhysPage>, va: VirtAddr, pp: PhysPage, key: &[u8; AES_KEYSIZE], basis_name: &str) {
+        if let Some(prev_page) = map.get(&va) {
+            let cipher = Aes256GcmSiv::new(Key::from_slice(key));
+            let aad = self.data_aad(basis_name);
+            let prev_data = self.data_decrypt_page(&cipher, &aad, prev_page);
+            let new_data = self.data_decrypt_page(&cipher, &aad, &pp);
+            if let Some(new_d) = new_data {
+                if let Some(prev_d) = prev_data {
+                    let prev_j = JournalType::from_le_bytes(prev_d[..size_of::<JournalType>()].try_into().unwrap());
+                    let new_j = JournalType::from_le_bytes(new_d[..size_of::<JournalType>()].try_into().unwrap());
+                    if new_j > prev_j {
+                        map.insert(va, pp);
+                    } else if new_j == prev_j {
+                        log::error!("Found duplicate blocks with same journal age, picking arbitrary block and moving on...");
+                    }
+                } else {
+                    // prev data was bogus anyways, replace with the new entry
+                    map.insert(va, pp);
+                }
+            } else {
+                // new data is bogus, ignore it
+            }
         } else {
-            None
+            map.insert(va, pp);
         }
     }
 
@@ -908,39 +987,60 @@ impl PddbOs {
             // next journal entry the next time around.
         }
     }
+    /// Releases a set of pages that have been displaced by a copy-on-write operation (e.g. a dictionary
+    /// compaction). This is the "break" half of make-before-break: call it only after the page table entries
+    /// that supersede these pages have been synced.
+    ///
+    /// The pages are released in a random order, so that the sequence of SpaceUpdate records in the FSCB doesn't
+    /// mirror the layout of the structure that was rewritten. Each page has its stale PTE destroyed and its
+    /// contents overwritten with noise before it goes back to FastSpace, so that no old ciphertext is left
+    /// behind for a differential image analysis to line up against the freshly written copies.
+    pub(crate) fn pages_release(&mut self, mut pages: Vec::<PhysPage>) {
+        // same shuffle as in fast_space_generate()
+        for i in (1..pages.len()).rev() {
+            pages.swap(i, self.entropy.borrow_mut().get_u32() as usize % (i+1));
+        }
+        for pp in pages {
+            self.pt_erase_mapping(pp.page_number());
+            let mut noise = [0u8; PAGE_SIZE];
+            self.trng_slice(&mut noise);
+            self.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+            self.fast_space_free(pp);
+        }
+    }
     /// This is a "look before you leap" function that will potentially pause all system operations
     /// and do a deep scan for space if the required amount is not available.
     pub fn ensure_fast_space_alloc(&mut self, pages: usize, cache: &Vec::<BasisCacheEntry>) -> bool {
         if self.fast_space_len() > pages {
             true
         } else {
-            if let Some(used_pages) = self.pddb_generate_used_map(cache) {
-                let free_pool = self.fast_space_generate(used_pages);
-                if free_pool.len() == 0 {
-                    // we're out of free space
-                    false
-                } else {
-                    let mut fast_space = FastSpace {
-                        free_pool: [PhysPage(0); FASTSPACE_FREE_POOL_LEN],
-                    };
-                    for (&src, dst) in free_pool.iter().zip(fast_space.free_pool.iter_mut()) {
-                        *dst = src;
-                    }
-                    // write just commits a new record to disk, but doesn't update our internal data cache
-                    self.fast_space_write(&fast_space);
-                    // this will ensure the data cache is fully in sync
-                    self.fast_space_read();
-
-                    // check that we have enough space now -- if not, we're just out of disk space!
-                    if self.fast_space_len() > pages {
-                        true
-                    } else {
-                        false
-                    }
-                }
-            } else {
+            // check that we have enough space after the regeneration -- if not, we're just out of disk space!
+            self.fast_space_regenerate(cache) && self.fast_space_len() > pages
+        }
+    }
+    /// Scans the open bases for free space, and replaces the FastSpace record (along with every update
+    /// record) with a new one. Returns false if there is no free space left to put in it.
+    pub(crate) fn fast_space_regenerate(&mut self, cache: &Vec::<BasisCacheEntry>) -> bool {
+        if let Some(used_pages) = self.pddb_generate_used_map(cache) {
+            let free_pool = self.fast_space_generate(used_pages);
+            if free_pool.len() == 0 {
+                // we're out of free space
                 false
+            } else {
+                let mut fast_space = FastSpace {
+                    free_pool: [PhysPage(0); FASTSPACE_FREE_POOL_LEN],
+                };
+                for (&src, dst) in free_pool.iter().zip(fast_space.free_pool.iter_mut()) {
+                    *dst = src;
+                }
+                // write just commits a new record to disk, but doesn't update our internal data cache
+                self.fast_space_write(&fast_space);
+                // this will ensure the data cache is fully in sync
+                self.fast_space_read();
+                true
             }
+        } else {
+            false
         }
     }
 
@@ -1004,6 +1104,7 @@ impl PddbOs {
         self.fast_space_read();
         self.syskey_ensure();
         if let Some(syskey) = self.system_basis_key {
+            self.pt_settle_pending(&syskey, PDDB_DEFAULT_SYSTEM_BASIS);
             if let Some(sysbasis_map) = self.pt_scan_key(&syskey, PDDB_DEFAULT_SYSTEM_BASIS) {
                 let cipher = Aes256GcmSiv::new(Key::from_slice(&syskey));
                 let aad = self.data_aad(PDDB_DEFAULT_SYSTEM_BASIS);
@@ -1113,6 +1214,7 @@ impl PddbOs {
         self.entropy.borrow_mut().get_slice(&mut system_basis_key);
         let mut basis_key_aes: [u8; AES_KEYSIZE] = system_basis_key.clone();
         self.system_basis_key = Some(system_basis_key); // causes system_basis_key to be owned by self
+        self.cipher_ecb = None; // any cached PTE/FSCB cipher was derived from the previous system key
         for block in basis_key_aes.chunks_mut(aes::BLOCK_SIZE) {
             self.rootkeys.encrypt_block(block.try_into().unwrap());
         }
@@ -1181,35 +1283,32 @@ impl PddbOs {
             ).expect("couldn't fill in disk with random datax");
         }
 
-        // step 6. create the system basis root structure
+        // step 6. create the system basis root structure, and write it to Flash along with its page table entry.
+        self.fast_space_read(); // we reconstitute our fspace map even though it was just generated, partially as a sanity check that everything is ok
+        self.basis_root_create(PDDB_DEFAULT_SYSTEM_BASIS, &system_basis_key)?;
+
+        Ok(())
+    }
+
+    /// Writes out the root record of a new, empty basis, encrypted with `key`, and commits its page table entry.
+    /// The caller is responsible for ensuring there is at least one page available in the FastSpace pool, and
+    /// for checking that the `(name, key)` pair is not already in use.
+    pub(crate) fn basis_root_create(&mut self, name: &str, key: &[u8; AES_KEYSIZE]) -> Result<()> {
         let basis_root = BasisRoot {
             magic: api::PDDB_MAGIC,
             version: api::PDDB_VERSION,
-            name: BasisRootName::try_from_str(PDDB_DEFAULT_SYSTEM_BASIS).unwrap(),
+            name: BasisRootName::try_from_str(name)?,
             age: 0,
             num_dictionaries: 0,
         };
+        let mut pp = self.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "No free space to create basis"))?;
+        pp.set_valid(true);
+        let va = VirtAddr::new((1 * VPAGE_SIZE) as u64).unwrap(); // page 1 is where the root goes, by definition
+        log::info!("adding basis va {:x?} with pte {:?}", va, pp);
 
-        // step 7. Create a hashmap for our reverse PTE, allocate sectors, and add it to the Pddb's cache
-        self.fast_space_read(); // we reconstitute our fspace map even though it was just generated, partially as a sanity check that everything is ok
-
-        let mut basis_v2p_map = HashMap::<VirtAddr, PhysPage>::new();
-        // allocate one page for the basis root
-        if let Some(alloc) = self.try_fast_space_alloc() {
-            let mut rpte = alloc.clone();
-            rpte.set_clean(true); // it's not clean _right now_ but it will be by the time this routine is done...
-            rpte.set_valid(true);
-            let va = VirtAddr::new((1 * VPAGE_SIZE) as u64).unwrap(); // page 1 is where the root goes, by definition
-            log::info!("adding basis va {:x?} with pte {:?}", va, rpte);
-            basis_v2p_map.insert(va, rpte);
-        }
-
-        // step 8. write the System basis to Flash, at the physical locations noted above. This is an extract
-        // from the basis_sync() method on a BasisCache entry, but because we haven't created a cache entry,
-        // we're copypasta'ing the code here
+        // This is an extract from the basis_sync() method on a BasisCache entry, but because we haven't created
+        // a cache entry, we're copypasta'ing the code here
         let aad = basis_root.aad(self.dna);
-        let pp = basis_v2p_map.get(&VirtAddr::new(1 * VPAGE_SIZE as u64).unwrap())
-            .expect("Internal consistency error: Basis exists, but its root map was not allocated!");
         let journal_bytes = (0 as u32).to_le_bytes();
         let slice_iter =
             journal_bytes.iter() // journal rev
@@ -1218,17 +1317,46 @@ impl PddbOs {
         for (&src, dst) in slice_iter.zip(block.iter_mut()) {
             *dst = src;
         }
-        let key = Key::clone_from_slice(self.system_basis_key.as_ref().unwrap());
-        self.data_encrypt_and_patch_page(&Aes256GcmSiv::new(&key), &aad, &mut block, &pp);
+        self.data_encrypt_and_patch_page(&Aes256GcmSiv::new(Key::from_slice(key)), &aad, &mut block, &pp);
+        self.pt_patch_mapping(va, pp.page_number(), &Aes256::new(GenericArray::from_slice(key)));
+        Ok(())
+    }
 
-        // step 9. generate & write initial page table entries
-        for (&virt, phys) in basis_v2p_map.iter_mut() {
-            self.pt_patch_mapping(virt, phys.page_number());
-            // mark the entry as clean, as it has been sync'd to disk
-            phys.set_clean(true);
+    /// Derives the key of a secret basis from its name and password, following the "Basis Unlock Procedure"
+    /// documented at the top of the crate: the common salt stored on disk is XOR'd with the basis name, and
+    /// the bcrypt() result is expanded to 256 bits using SHA-512/256.
+    pub(crate) fn basis_derive_key(&self, name: &str, password: &str) -> [u8; AES_KEYSIZE] {
+        let scd = self.static_crypto_data_get();
+        let mut salt = [0u8; 16];
+        for (&src, dst) in scd.salt_base.iter().zip(salt.iter_mut()) {
+            *dst = src;
+        }
+        for (i, &b) in name.as_bytes().iter().enumerate() {
+            salt[i % 16] ^= b;
         }
+        let mut hashed_password: [u8; 24] = [0; 24];
+        let start_time = self.timestamp_now();
+        // note: this internally makes a copy of the password, and destroys it
+        root_keys::bcrypt::bcrypt(BASIS_BCRYPT_COST, &salt, password, &mut hashed_password);
+        log::info!("basis bcrypt cost: {} time: {}ms", BASIS_BCRYPT_COST, self.timestamp_now() - start_time);
 
-        Ok(())
+        // for such a small hash, software is the most performant choice
+        let mut hasher = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+        hasher.update(hashed_password);
+        let digest = hasher.finalize();
+        let mut key = [0u8; AES_KEYSIZE];
+        for (&src, dst) in digest.iter().zip(key.iter_mut()) {
+            *dst = src;
+        }
+        // the intermediate hash is as good as the key, so wipe it
+        let hp = hashed_password.as_mut_ptr();
+        for i in 0..hashed_password.len() {
+            unsafe {
+                hp.add(i).write_volatile(core::mem::zeroed());
+            }
+        }
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+        key
     }
 
     /// This function will prompt the user to unlock all the Basis. If the user asserts all
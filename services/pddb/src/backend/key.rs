@@ -5,6 +5,8 @@ use std::num::NonZeroU32;
 use core::ops::{Deref, DerefMut};
 use std::cmp::Ordering;
 use bitfield::bitfield;
+use std::collections::HashMap;
+use aes_gcm_siv::Aes256GcmSiv;
 
 bitfield! {
     #[derive(Copy, Clone, PartialEq, Eq)]
@@ -87,6 +89,16 @@ pub(crate) struct KeyCacheEntry {
     pub(crate) data: Option<KeyCacheData>,
 }
 impl KeyCacheEntry {
+    /// Reports the key's metadata, as found in `basis`
+    pub(crate) fn stat(&self, basis: &str) -> KeyStat {
+        KeyStat {
+            basis: basis.to_string(),
+            len: self.len,
+            reserved: self.reserved,
+            age: self.age,
+            flags: self.flags.0,
+        }
+    }
     /// Given a base offset of the dictionary containing the key, compute the starting VirtAddr of the key itself.
     pub(crate) fn descriptor_vaddr(&self, dict_offset: VirtAddr) -> VirtAddr {
         VirtAddr::new(dict_offset.get() + ((self.descriptor_index.get() as u64) * DK_STRIDE as u64)).unwrap()
@@ -110,12 +122,107 @@ impl KeyCacheEntry {
         }
         vpages
     }
+    /// Returns the plaintext of one of this key's large-pool vpages, including the journal header. The page is
+    /// served out of the key's page cache if it's there; otherwise, it's decrypted from disk and added to the cache.
+    /// Returns None if the page isn't mapped, or if it was reserved but never written.
+    pub(crate) fn large_page_fetch(&mut self, hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>,
+        cipher: &Aes256GcmSiv, aad: &[u8], vpage: VirtAddr) -> Option<&[u8]> {
+        let now = hw.timestamp_now();
+        let cached = match &self.data {
+            Some(KeyCacheData::Large(cache)) => cache.pages.contains_key(&vpage),
+            Some(_) => return None, // small keys are always fully cached, and have no large pages
+            None => false,
+        };
+        if !cached {
+            let data = v2p_map.get(&vpage).and_then(|pp| hw.data_decrypt_page(cipher, aad, pp))?;
+            if self.data.is_none() {
+                self.data = Some(KeyCacheData::Large(KeyLargeData { pages: HashMap::new() }));
+            }
+            if let Some(KeyCacheData::Large(cache)) = self.data.as_mut() {
+                cache.pages.insert(vpage, LargeCachePage { data, last_used: now });
+            }
+        }
+        if let Some(KeyCacheData::Large(cache)) = self.data.as_mut() {
+            let page = cache.pages.get_mut(&vpage).expect("page was assured, but then not there!");
+            page.last_used = now;
+            Some(&page.data)
+        } else {
+            None
+        }
+    }
+    /// Call after patching a large-pool vpage on disk, so that a cached copy of it stays coherent. `data` is the
+    /// plaintext that was written, including the journal header. Pages that aren't cached are not added, so
+    /// that bulk writes don't flush out the pages that are being actively read.
+    pub(crate) fn large_page_update(&mut self, vpage: VirtAddr, data: &[u8]) {
+        if let Some(KeyCacheData::Large(cache)) = self.data.as_mut() {
+            if let Some(page) = cache.pages.get_mut(&vpage) {
+                page.data.copy_from_slice(data);
+            }
+        }
+    }
+    /// Drops a vpage from the key's page cache, if it's there.
+    pub(crate) fn large_page_evict(&mut self, vpage: VirtAddr) {
+        let now_empty = if let Some(KeyCacheData::Large(cache)) = self.data.as_mut() {
+            cache.pages.remove(&vpage);
+            cache.pages.len() == 0
+        } else {
+            false
+        };
+        if now_empty {
+            self.data = None;
+        }
+    }
+    /// Wipes any plaintext data held in the cache entry, leaving the entry uncached. Used when a basis is
+    /// locked, so that its contents don't linger in RAM after it's closed.
+    pub(crate) fn purge(&mut self) {
+        match self.data.take() {
+            Some(KeyCacheData::Small(mut cache)) => volatile_wipe(&mut cache.data),
+            Some(KeyCacheData::Large(mut cache)) => {
+                for page in cache.pages.values_mut() {
+                    volatile_wipe(&mut page.data);
+                }
+            }
+            None => (),
+        }
+    }
+    /// Number of vpages held in the key's page cache.
+    pub(crate) fn large_pages_cached(&self) -> usize {
+        if let Some(KeyCacheData::Large(cache)) = &self.data {
+            cache.pages.len()
+        } else {
+            0
+        }
+    }
+}
+
+/// Overwrites `data` with zeroes, using volatile writes and a compiler fence to ensure the wipe isn't optimized out.
+pub(crate) fn volatile_wipe(data: &mut [u8]) {
+    let b = data.as_mut_ptr();
+    for i in 0..data.len() {
+        unsafe {
+            b.add(i).write_volatile(core::mem::zeroed());
+        }
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+/// Overwrites a string's bytes with zeroes before it's dropped, so names don't linger in the freed heap.
+pub(crate) fn volatile_wipe_string(s: &mut String) {
+    // zeroes are valid UTF-8, so the string stays well-formed
+    volatile_wipe(unsafe { s.as_bytes_mut() });
+    s.clear();
+}
+/// Overwrites the memory of `value` with zeroes, in place.
+///
+/// Safety: only for plain data such as cipher key schedules, where all zeroes is a valid value of `T` and
+/// the value doesn't own any other allocation.
+pub(crate) unsafe fn volatile_wipe_value<T>(value: &mut T) {
+    volatile_wipe(core::slice::from_raw_parts_mut(value as *mut T as *mut u8, core::mem::size_of::<T>()));
 }
 
 pub (crate) enum KeyCacheData {
     Small(KeySmallData),
     // the "Medium" type has a region reserved for it, but we haven't coded a handler for it.
-    #[allow(dead_code)] // Large data caching isn't implemented, so of course, we don't ever create this type
     Large(KeyLargeData),
 }
 /// Small data is optimized for low overhead, and always represent a complete copy of the data.
@@ -123,14 +230,18 @@ pub(crate) struct KeySmallData {
     pub clean: bool,
     pub(crate) data: Vec::<u8>,
 }
-/// This can hold just a portion of a large key's data. For now, we now essentially manually
-/// encode a sub-slice in parts, but, later on we could get more clever and start to cache
-/// multiple disjoint portions of a large key's data...
-#[allow(dead_code)] // large key caching is not yet implemented
+/// This holds just a portion of a large key's data, as a set of decrypted vpages. Updates to large keys
+/// are written through to disk immediately, so the cached pages are always clean and can be evicted at
+/// any time; the containing basis evicts the least recently used pages to stay within its `large_cache_limit`.
 pub(crate) struct KeyLargeData {
-    pub clean: bool,
-    pub(crate) start: u64,
+    /// plaintext of the cached vpages, including the journal header, keyed by the vpage's virtual address.
+    pub(crate) pages: HashMap::<VirtAddr, LargeCachePage>,
+}
+pub(crate) struct LargeCachePage {
+    /// a decrypted vpage, exactly VPAGE_SIZE + size_of::<JournalType>() long
     pub(crate) data: Vec::<u8>,
+    /// timestamp of the last access, in ms; used to pick pages for eviction
+    pub(crate) last_used: u64,
 }
 
 /// A storage pool for data that is strictly smaller than one VPAGE. These element are serialized
@@ -175,3 +286,29 @@ impl PartialEq for KeySmallPoolOrd {
         self.avail == other.avail
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::{NewBlockCipher, generic_array::GenericArray};
+    #[test]
+    fn test_volatile_wipe_value() {
+        let mut cipher = aes::Aes256::new(GenericArray::from_slice(&[0xa5u8; 32]));
+        let size = core::mem::size_of::<aes::Aes256>();
+        let raw = unsafe { core::slice::from_raw_parts(&cipher as *const aes::Aes256 as *const u8, size) };
+        assert!(raw.iter().any(|&b| b != 0));
+        unsafe { volatile_wipe_value(&mut cipher); }
+        let raw = unsafe { core::slice::from_raw_parts(&cipher as *const aes::Aes256 as *const u8, size) };
+        assert!(raw.iter().all(|&b| b == 0));
+    }
+    #[test]
+    fn test_volatile_wipe_string() {
+        let mut name = String::from("secret dictionary");
+        let len = name.len();
+        volatile_wipe_string(&mut name);
+        assert!(name.is_empty());
+        // the bytes are still allocated, so they can be checked
+        let raw = unsafe { core::slice::from_raw_parts(name.as_ptr(), len) };
+        assert!(raw.iter().all(|&b| b == 0));
+    }
+}
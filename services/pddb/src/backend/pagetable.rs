@@ -20,6 +20,9 @@ bitflags! {
         /// This flag exists because there is a chance that the 32-bit checksum used to protect
         /// a page table entry experiences a collision.
         const CHECKED             = 0b0000_0010;
+        /// set for entries written by a compaction that hasn't committed yet. They only take effect
+        /// once the basis root's age matches the tag in the entry's `reserved` field.
+        const PENDING             = 0b0000_0100;
     }
 }
 impl Default for PtFlags {
@@ -47,7 +50,7 @@ pub(crate) struct Pte {
     pddb_addr: [u8; 6],
     /// this maps to a u8
     flags: PtFlags,
-    #[allow(dead_code)]
+    /// commit tag of a `PENDING` entry; 0 otherwise
     reserved: u8,
     /// 32-bit strength of a nonce, but can be varied
     nonce: [u8; 4],
@@ -71,6 +74,30 @@ impl Pte {
 
         pte
     }
+    /// An entry that is ignored until the basis root is re-written with an age whose low byte is `tag`
+    pub fn new_pending(va: VirtAddr, tag: u8, entropy: Rc<RefCell<TrngPool>>) -> Self {
+        let nonce_u32 = entropy.borrow_mut().get_u32();
+        let mut pte = Pte {
+            pddb_addr: va.get().to_le_bytes()[..6].try_into().unwrap(),
+            flags: PtFlags::CLEAN | PtFlags::PENDING,
+            reserved: tag,
+            nonce: nonce_u32.to_le_bytes(),
+            checksum: [0; 4],
+        };
+        let pte_data = pte.deref();
+        let checksum = murmur3_32(&pte_data[..12], nonce_u32);
+        pte.checksum = checksum.to_le_bytes();
+
+        pte
+    }
+    /// The commit tag, if this entry belongs to a compaction that may not have committed
+    pub fn pending_tag(&self) -> Option<u8> {
+        if self.flags.contains(PtFlags::PENDING) {
+            Some(self.reserved)
+        } else {
+            None
+        }
+    }
     pub fn vaddr(&self) -> VirtAddr {
         let mut full_addr = [0u8; 8];
         // LSB encoded, so this loop deposits the partial pddb_addr in the LSBs, and the MSBs are correctly 0 from above initializer
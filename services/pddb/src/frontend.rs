@@ -1,2 +1,52 @@
 pub mod pddbkey;
 pub use pddbkey::*;
+pub mod pddbdict;
+pub use pddbdict::*;
+pub mod pddbbasis;
+pub use pddbbasis::*;
+
+use core::sync::atomic::AtomicU32;
+/// Count of the frontend objects in this process that share the connection to the PDDB server.
+pub(crate) static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+
+use crate::*;
+use xous::CID;
+use xous_ipc::Buffer;
+use num_traits::*;
+use std::io::{Result, Error, ErrorKind};
+
+/// Fetches a complete listing of dictionary or key names, one page at a time.
+pub(crate) fn list_request(conn: CID, op: Opcode, basis: Option<&str>, dict: &str) -> Result<Vec::<String>> {
+    let mut names = Vec::<String>::new();
+    let mut token = None;
+    loop {
+        let request = PddbListRequest {
+            basis: basis.map(|b| xous_ipc::String::<BASIS_NAME_LEN>::from_str(b)),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict),
+            token,
+            list: [xous_ipc::String::<DICT_NAME_LEN>::new(); LIST_PAGE_MAX],
+            num: 0,
+            total: 0,
+            result: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(conn, op.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbListRequest, _>().unwrap();
+        match response.result {
+            PddbRequestCode::NoErr => {},
+            PddbRequestCode::NotFound => return Err(Error::new(ErrorKind::NotFound, "Basis/Dict not found")),
+            PddbRequestCode::AccessDenied => return Err(Error::new(ErrorKind::PermissionDenied, "Listing was abandoned")),
+            _ => return Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+        names.reserve((response.total as usize).saturating_sub(names.len()));
+        for name in response.list[..(response.num as usize).min(LIST_PAGE_MAX)].iter() {
+            names.push(String::from(name.to_str()));
+        }
+        match response.token {
+            Some(next) if response.num != 0 => token = Some(next),
+            _ => return Ok(names),
+        }
+    }
+}
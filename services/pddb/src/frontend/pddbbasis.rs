@@ -0,0 +1,138 @@
+use crate::*;
+use xous::CID;
+use xous_ipc::Buffer;
+
+use num_traits::*;
+use std::io::{Result, Error, ErrorKind};
+use std::format;
+use std::string::String;
+use core::sync::atomic::Ordering;
+use super::REFCOUNT;
+
+/// Manages the set of open basis. Basis are stacked in the order they are unlocked: operations that don't
+/// name a basis go to the most recently unlocked one. The system basis is always open, and can't be locked.
+///
+/// Only open basis can be enumerated. There is deliberately no way to find out if a locked basis exists,
+/// other than to unlock it with the correct name and password.
+pub struct PddbBasisManager {
+    conn: CID,
+}
+impl PddbBasisManager {
+    pub fn new() -> Self {
+        let xns = xous_names::XousNames::new().unwrap();
+        REFCOUNT.store(REFCOUNT.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_PDDB).expect("Can't connect to Pddb server");
+        PddbBasisManager { conn }
+    }
+
+    /// Creates a new secret basis. The basis is created locked, and has to be unlocked before it can be used.
+    /// It is an error to create a basis with the same name and password as an existing one.
+    pub fn create(&self, basis_name: &str, password: &str) -> Result<()> {
+        self.request(Opcode::BasisCreate, basis_name, Some(password))
+    }
+    /// Unlocks a secret basis. The basis becomes the default basis, until it is locked or another basis
+    /// is unlocked. A wrong password can't be told apart from a basis that doesn't exist: both are
+    /// reported as `PermissionDenied`.
+    pub fn unlock(&self, basis_name: &str, password: &str) -> Result<()> {
+        self.request(Opcode::BasisUnlock, basis_name, Some(password))
+    }
+    /// Locks a secret basis. Its contents are committed to disk and purged from RAM, and any keys
+    /// that were opened explicitly within the basis become unusable.
+    pub fn lock(&self, basis_name: &str) -> Result<()> {
+        self.request(Opcode::BasisLock, basis_name, None)
+    }
+    /// Returns the names of the open basis, in order of increasing priority: the last entry is the
+    /// default basis.
+    pub fn list(&self) -> Result<Vec::<String>> {
+        let list = PddbBasisList {
+            list: [xous_ipc::String::<BASIS_NAME_LEN>::new(); BASIS_LIST_MAX],
+            num: 0,
+        };
+        let mut buf = Buffer::into_buf(list)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::BasisList.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbBasisList, _>().unwrap();
+        let mut names = Vec::<String>::new();
+        for name in response.list[..(response.num as usize).min(BASIS_LIST_MAX)].iter() {
+            names.push(String::from(name.to_str()));
+        }
+        Ok(names)
+    }
+    /// Returns the names of the dictionaries in the open basis, sorted. If `basis_name` is None, this is
+    /// the union across all the open basis; otherwise only the named basis is listed.
+    pub fn dict_list(&self, basis_name: Option<&str>) -> Result<Vec::<String>> {
+        if basis_name.map_or(false, |b| b.len() >= BASIS_NAME_LEN) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB basis names must be shorter than {} bytes", BASIS_NAME_LEN)));
+        }
+        super::list_request(self.conn, Opcode::DictList, basis_name, "")
+    }
+
+    /// Sets the most RAM, in bytes, that a basis may use to cache the data of large keys. If `basis_name` is
+    /// None, the default basis is changed. A limit smaller than one page turns the cache off. Returns the
+    /// number of bytes left in the cache once it has been trimmed to the new limit.
+    pub fn large_cache_set_limit(&self, limit: usize, basis_name: Option<&str>) -> Result<usize> {
+        self.cache_request(Some(limit), basis_name)
+    }
+    /// Returns the number of bytes of large key data that a basis is holding in RAM.
+    pub fn large_cache_usage(&self, basis_name: Option<&str>) -> Result<usize> {
+        self.cache_request(None, basis_name)
+    }
+    fn cache_request(&self, limit: Option<usize>, basis_name: Option<&str>) -> Result<usize> {
+        if basis_name.map_or(false, |b| b.len() >= BASIS_NAME_LEN) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB basis names must be shorter than {} bytes", BASIS_NAME_LEN)));
+        }
+        let request = PddbCacheRequest {
+            basis: basis_name.map(|b| xous_ipc::String::<BASIS_NAME_LEN>::from_str(b)),
+            limit: limit.map(|l| l as u64),
+            usage: 0,
+            result: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::BasisCacheLimit.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbCacheRequest, _>().unwrap();
+        match response.result {
+            PddbRequestCode::NoErr => Ok(response.usage as usize),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis is not open")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+
+    fn request(&self, op: Opcode, basis_name: &str, password: Option<&str>) -> Result<()> {
+        if basis_name.len() >= BASIS_NAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB basis names must be shorter than {} bytes", BASIS_NAME_LEN)));
+        }
+        let request = PddbBasisRequest {
+            name: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
+            password: password.map(|p| xous_ipc::String::<72>::from_str(p)),
+            result: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let sent = buf.lend_mut(self.conn, op.to_u32().unwrap());
+        let result = buf.to_original::<PddbBasisRequest, _>().map(|r| r.result);
+        // don't leave a copy of the password lying around in our memory space
+        buf.volatile_clear();
+        sent.or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        match result {
+            Ok(PddbRequestCode::NoErr) => Ok(()),
+            Ok(PddbRequestCode::NotFound) => Err(Error::new(ErrorKind::NotFound, "Basis is not open")),
+            Ok(PddbRequestCode::AccessDenied) => Err(Error::new(ErrorKind::PermissionDenied, "Basis access denied")),
+            Ok(PddbRequestCode::NoFreeSpace) => Err(Error::new(ErrorKind::OutOfMemory, "No free space")),
+            Ok(PddbRequestCode::DuplicateEntry) => Err(Error::new(ErrorKind::AlreadyExists, "Basis already exists")),
+            Ok(PddbRequestCode::InvalidName) => Err(Error::new(ErrorKind::InvalidInput, "Invalid basis name")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+}
+
+impl Drop for PddbBasisManager {
+    fn drop(&mut self) {
+        REFCOUNT.store(REFCOUNT.load(Ordering::Relaxed) - 1, Ordering::Relaxed);
+        if REFCOUNT.load(Ordering::Relaxed) == 0 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
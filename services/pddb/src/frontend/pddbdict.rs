@@ -0,0 +1,214 @@
+use crate::*;
+use xous::CID;
+use xous_ipc::Buffer;
+
+use num_traits::*;
+use std::io::{Result, Error, ErrorKind};
+use std::io::Write;
+use std::format;
+use std::string::String;
+use core::sync::atomic::Ordering;
+use super::REFCOUNT;
+
+/// A handle to a dictionary in the PDDB. If the dictionary was opened without specifying a basis,
+/// all of its operations go to the most recently opened basis.
+pub struct PddbDict {
+    conn: CID,
+    name: String,
+    basis: Option<String>,
+}
+impl PddbDict {
+    /// Opens a dictionary only if it exists
+    pub fn open(dict_name: &str, basis_name: Option<&str>) -> Result<PddbDict> {
+        PddbDict::request(dict_name, basis_name, false)
+    }
+    /// Opens a dictionary, creating it if it does not already exist
+    pub fn create(dict_name: &str, basis_name: Option<&str>) -> Result<PddbDict> {
+        PddbDict::request(dict_name, basis_name, true)
+    }
+    fn request(dict_name: &str, basis_name: Option<&str>, create: bool) -> Result<PddbDict> {
+        if dict_name.len() > DICT_NAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB dictionary names must be shorter than {} bytes", DICT_NAME_LEN)));
+        }
+        if basis_name.map_or(false, |b| b.len() > BASIS_NAME_LEN) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB basis names must be shorter than {} bytes", BASIS_NAME_LEN)));
+        }
+        let xns = xous_names::XousNames::new().unwrap();
+        REFCOUNT.store(REFCOUNT.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_PDDB).expect("Can't connect to Pddb server");
+        let dict = PddbDict {
+            conn,
+            name: String::from(dict_name),
+            basis: basis_name.map(|b| String::from(b)),
+        };
+        // if this fails, `dict` is dropped and the connection refcount is released
+        dict.dict_op(Opcode::DictRequest, create)?;
+        Ok(dict)
+    }
+    fn dict_op(&self, op: Opcode, create: bool) -> Result<()> {
+        let request = PddbDictRequest {
+            basis: self.basis.as_ref().map(|b| xous_ipc::String::<BASIS_NAME_LEN>::from_str(b)),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(&self.name),
+            create,
+            result: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, op.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbDictRequest, _>().unwrap();
+        request_code_to_result(response.result)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns a key only if it exists. `key_changed_cb` is called from a separate thread whenever
+    /// another process modifies or removes the key while the returned `PddbKey` is alive.
+    pub fn get<'a>(&mut self, key_name: &str, key_changed_cb: Option<impl FnMut() + Send + 'static>) -> Result<Option<PddbKey<'a>>> {
+        match PddbKey::open(&self.name, key_name, self.basis.as_deref(), false, false, None,
+            key_changed_cb.map(|cb| Box::new(cb) as Box<dyn FnMut() + Send>)
+        ) {
+            Ok(key) => Ok(Some(key)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// Returns a key, creating an empty one if it does not exist. `alloc_hint` is the amount of
+    /// space to reserve for the key if it's created; reserving space up front avoids the cost of
+    /// re-allocating the key as it grows.
+    pub fn get_or_create<'a>(&mut self, key_name: &str, alloc_hint: Option<usize>,
+        key_changed_cb: Option<impl FnMut() + Send + 'static>
+    ) -> Result<PddbKey<'a>> {
+        PddbKey::open(&self.name, key_name, self.basis.as_deref(), false, true, alloc_hint,
+            key_changed_cb.map(|cb| Box::new(cb) as Box<dyn FnMut() + Send>)
+        )
+    }
+    /// Replaces an existing key's value. Returns a `NotFound` error if the key does not exist.
+    pub fn update(&mut self, key_name: &str, data: &[u8]) -> Result<()> {
+        // the value is overwritten in place and the old tail is cut off by the last write, so the
+        // key never goes missing from the disk part way through an update.
+        let mut key = PddbKey::open(&self.name, key_name, self.basis.as_deref(), false, false, None, None)?;
+        key.write_all_truncate(data)?;
+        key.flush()
+    }
+    /// Creates a key or overwrites it. Returns `true` if an existing key was overwritten.
+    pub fn insert(&mut self, key_name: &str, data: &[u8]) -> Result<bool> {
+        let (mut key, existed) = match self.get(key_name, None::<fn()>)? {
+            Some(key) => (key, true),
+            None => (PddbKey::open(&self.name, key_name, self.basis.as_deref(), false, true, Some(data.len()), None)?, false),
+        };
+        key.write_all_truncate(data)?;
+        key.flush()?;
+        Ok(existed)
+    }
+    /// Deletes a key within the dictionary
+    pub fn remove(&mut self, key_name: &str) -> Result<()> {
+        self.key_remove(key_name, Opcode::KeyRemove)
+    }
+    /// Deletes a key within the dictionary, and overwrites its data on disk so that it can't be
+    /// recovered from an image of the disk. This is much slower than `remove()`.
+    pub fn remove_paranoid(&mut self, key_name: &str) -> Result<()> {
+        self.key_remove(key_name, Opcode::KeyErase)
+    }
+    fn key_remove(&mut self, key_name: &str, op: Opcode) -> Result<()> {
+        if key_name.len() > KEY_NAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB key names must be shorter than {} bytes", KEY_NAME_LEN)));
+        }
+        let request = PddbKeyRequest {
+            basis: self.basis.as_ref().map(|b| xous_ipc::String::<BASIS_NAME_LEN>::from_str(b)),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(&self.name),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name),
+            create_dict: false,
+            create_key: false,
+            alloc_hint: None,
+            cb_sid: None,
+            result: PddbRequestCode::Uninit,
+            token: None,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, op.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbKeyRequest, _>().unwrap();
+        request_code_to_result(response.result)
+    }
+    /// Returns the names of the keys in the dictionary, sorted. If the dictionary was opened without
+    /// specifying a basis, this is the union of the keys across all the open basis.
+    pub fn list(&self) -> Result<Vec::<String>> {
+        super::list_request(self.conn, Opcode::KeyList, self.basis.as_deref(), &self.name)
+    }
+    /// Returns the metadata of a key. If the dictionary was opened without specifying a basis, the
+    /// copy of the key in the highest priority basis is reported, which is the one a read would return.
+    pub fn stat(&self, key_name: &str) -> Result<KeyStat> {
+        if key_name.len() > KEY_NAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB key names must be shorter than {} bytes", KEY_NAME_LEN)));
+        }
+        let request = PddbKeyStat {
+            basis: self.basis.as_ref().map(|b| xous_ipc::String::<BASIS_NAME_LEN>::from_str(b)),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(&self.name),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name),
+            len: 0,
+            reserved: 0,
+            age: 0,
+            flags: 0,
+            result: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::KeyStat.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbKeyStat, _>().unwrap();
+        request_code_to_result(response.result)?;
+        Ok(KeyStat {
+            basis: response.basis.map(|b| String::from(b.to_str())).unwrap_or(String::new()),
+            len: response.len,
+            reserved: response.reserved,
+            age: response.age,
+            flags: response.flags,
+        })
+    }
+    /// Deletes the entire dictionary, and all the keys within it
+    pub fn delete(self) -> Result<()> {
+        self.dict_op(Opcode::DictRemove, false)
+    }
+    /// Deletes the entire dictionary, and overwrites the data of all the keys within it with noise
+    pub fn delete_paranoid(self) -> Result<()> {
+        self.dict_op(Opcode::DictErase, false)
+    }
+}
+
+fn request_code_to_result(code: PddbRequestCode) -> Result<()> {
+    match code {
+        PddbRequestCode::NoErr => Ok(()),
+        PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Dict/Key not found")),
+        PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Dict/Key access denied")),
+        PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "No free space")),
+        _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+    }
+}
+
+impl Drop for PddbDict {
+    fn drop(&mut self) {
+        REFCOUNT.store(REFCOUNT.load(Ordering::Relaxed) - 1, Ordering::Relaxed);
+        if REFCOUNT.load(Ordering::Relaxed) == 0 {
+            if let Err(e) = unsafe{xous::disconnect(self.conn)} {
+                log::warn!("couldn't disconnect from the PDDB server: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_request_code_to_result() {
+        assert!(request_code_to_result(PddbRequestCode::NoErr).is_ok());
+        assert_eq!(request_code_to_result(PddbRequestCode::NotFound).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(request_code_to_result(PddbRequestCode::AccessDenied).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(request_code_to_result(PddbRequestCode::NoFreeSpace).unwrap_err().kind(), ErrorKind::OutOfMemory);
+        assert_eq!(request_code_to_result(PddbRequestCode::Uninit).unwrap_err().kind(), ErrorKind::Other);
+    }
+}
@@ -1,14 +1,18 @@
 use crate::*;
-use xous::{CID, send_message, Message};
+use xous::{CID, SID, send_message, Message, msg_blocking_scalar_unpack};
 use xous_ipc::Buffer;
 
 use num_traits::*;
 use std::io::{Result, Error, ErrorKind};
 use std::path::{Path, Component};
 use std::format;
-use std::io::{Read, Write};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::thread;
+use std::thread::JoinHandle;
 
 use std::string::String;
+use core::sync::atomic::Ordering;
+use super::REFCOUNT;
 
 pub struct PddbKey<'a> {
     conn: CID,
@@ -16,13 +20,16 @@ pub struct PddbKey<'a> {
     key: String,
     token: [u32; 3],
     buf: Buffer<'a>,
+    /// position of the read/write cursor. It's tracked on our side and sent along with every
+    /// request, so the server doesn't have to keep a cursor for every token.
+    pos: u64,
+    /// the server that receives key-changed notifications, if a callback was registered
+    cb_sid: Option<SID>,
+    cb_handle: Option<JoinHandle::<()>>,
 }
 impl<'a> PddbKey<'a> {
+    /// Opens an existing key, specified as a `dict:key` path, in the most recently opened basis.
     pub fn get<P: AsRef<Path>>(path: P) -> Result<PddbKey<'a>> {
-        let xns = xous_names::XousNames::new().unwrap();
-        REFCOUNT.store(REFCOUNT.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
-        let conn = xns.request_connection_blocking(api::SERVER_NAME_PDDB).expect("Can't connect to Pddb server");
-
         if !path.as_ref().is_absolute() {
             return Err(Error::new(ErrorKind::InvalidInput, "All PDDB keys must be fully specified relative to a dictionary"));
         }
@@ -32,11 +39,7 @@ impl<'a> PddbKey<'a> {
         match components.next().unwrap() {
             Component::Prefix(prefix_component) => {
                 if let Some(dictstr) = prefix_component.as_os_str().to_str() {
-                    if dictstr.len() <= DICT_NAME_LEN {
-                        dict.push_str(dictstr);
-                    } else {
-                        return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB dictionary names must be shorter than {} bytes", DICT_NAME_LEN)));
-                    }
+                    dict.push_str(dictstr);
                 } else {
                     return Err(Error::new(ErrorKind::InvalidInput, "PDDB dictionary names must valid UTF-8"));
                 }
@@ -53,14 +56,65 @@ impl<'a> PddbKey<'a> {
                 return Err(Error::new(ErrorKind::InvalidInput, "PDDB dictionary names must valid UTF-8"));
             }
         }
+        PddbKey::open(&dict, &key, None, false, false, None, None)
+    }
 
+    /// Requests a token for a key from the server. Name lengths are checked here, so the server can
+    /// trust the lengths of the strings it gets.
+    pub(crate) fn open(dict: &str, key: &str, basis: Option<&str>, create_dict: bool, create_key: bool,
+        alloc_hint: Option<usize>, key_changed_cb: Option<Box<dyn FnMut() + Send>>
+    ) -> Result<PddbKey<'a>> {
+        if dict.len() > DICT_NAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB dictionary names must be shorter than {} bytes", DICT_NAME_LEN)));
+        }
         if key.len() > KEY_NAME_LEN {
-            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB key names must be shorter than {} bytes", DICT_NAME_LEN)));
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB key names must be shorter than {} bytes", KEY_NAME_LEN)));
         }
+        if basis.map_or(false, |b| b.len() > BASIS_NAME_LEN) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("PDDB basis names must be shorter than {} bytes", BASIS_NAME_LEN)));
+        }
+        let xns = xous_names::XousNames::new().unwrap();
+        REFCOUNT.store(REFCOUNT.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_PDDB).expect("Can't connect to Pddb server");
+
+        // the callback is run from a thread that services a private server, which the PDDB server
+        // pings when someone else changes the key.
+        let (cb_sid, cb_handle) = if let Some(mut cb) = key_changed_cb {
+            let cb_sid = xous::create_server().unwrap();
+            let handle = thread::spawn({
+                let cb_sid_clone = cb_sid.clone();
+                move || {
+                    loop {
+                        let msg = xous::receive_message(cb_sid_clone).unwrap();
+                        match FromPrimitive::from_usize(msg.body.id()) {
+                            Some(KeyCallback::Changed) => {
+                                cb();
+                            }
+                            Some(KeyCallback::Drop) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                                xous::return_scalar(msg.sender, 1).unwrap(); // actual return value doesn't matter -- it's that there is a return value
+                                break;
+                            }),
+                            None => {
+                                log::error!("got unknown message type on key callback: {:?}", msg);
+                            }
+                        }
+                    }
+                }
+            });
+            (Some(cb_sid), Some(handle))
+        } else {
+            (None, None)
+        };
 
         let request = PddbKeyRequest {
-            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict.as_str()),
-            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key.as_str()),
+            basis: basis.map(|b| xous_ipc::String::<BASIS_NAME_LEN>::from_str(b)),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key),
+            create_dict,
+            create_key,
+            alloc_hint: alloc_hint.map(|a| a as u64),
+            cb_sid: cb_sid.map(|sid| sid.to_array()),
+            result: PddbRequestCode::Uninit,
             token: None,
         };
         let mut buf = Buffer::into_buf(request)
@@ -74,16 +128,31 @@ impl<'a> PddbKey<'a> {
         // "work" without this being an even page size, but it's pretty easy to get this wrong,
         // and if it's wrong we can lose a lot in terms of efficiency of execution.
         assert!(core::mem::size_of::<PddbBuf>() == 4096, "PddBuf record has the wrong size");
-        if let Some(token) = response.token {
-            Ok(PddbKey {
-                conn,
-                dict,
-                key,
-                token,
-                buf: Buffer::new(core::mem::size_of::<PddbBuf>()),
-            })
-        } else {
-            Err(Error::new(ErrorKind::PermissionDenied, "Dict/Key access denied"))
+        match (response.result, response.token) {
+            (PddbRequestCode::NoErr, Some(token)) => {
+                Ok(PddbKey {
+                    conn,
+                    dict: String::from(dict),
+                    key: String::from(key),
+                    token,
+                    buf: Buffer::new(core::mem::size_of::<PddbBuf>()),
+                    pos: 0,
+                    cb_sid,
+                    cb_handle,
+                })
+            }
+            (result, _) => {
+                if let Some(sid) = cb_sid {
+                    cb_shutdown(sid, cb_handle);
+                }
+                REFCOUNT.store(REFCOUNT.load(Ordering::Relaxed) - 1, Ordering::Relaxed);
+                match result {
+                    PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Dict/Key not found")),
+                    PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "No free space to create key")),
+                    PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Dict/Key access denied")),
+                    _ => Err(Error::new(ErrorKind::Other, "Internal error requesting key")),
+                }
+            }
         }
     }
     /// this will clear all residual values in the buffer. Should be called whenever the Basis set changes.
@@ -91,6 +160,37 @@ impl<'a> PddbKey<'a> {
         self.buf.volatile_clear();
     }
 
+    pub fn dict(&self) -> &str {
+        &self.dict
+    }
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    /// Returns the current length of the key's data. The length can change underneath us if another
+    /// process writes to the same key.
+    pub fn key_len(&self) -> Result<u64> {
+        let response = send_message(
+            self.conn,
+            Message::new_blocking_scalar(
+                Opcode::KeyLen.to_usize().unwrap(),
+                self.token[0] as usize,
+                self.token[1] as usize,
+                self.token[2] as usize,
+                0,
+            )
+        ).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        if let xous::Result::Scalar2(rcode, len) = response {
+            match FromPrimitive::from_u8(rcode as u8) {
+                Some(PddbRetcode::Ok) => Ok(len as u64),
+                Some(PddbRetcode::BasisLost) => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
+                Some(PddbRetcode::AccessDenied) => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
+                _ => Err(Error::new(ErrorKind::Other, "Unhandled error code in PddbKey key_len")),
+            }
+        } else {
+            Err(Error::new(ErrorKind::Other, "Xous internal error"))
+        }
+    }
+
     pub(crate) fn conn(&self) -> CID {
         self.conn
     }
@@ -99,163 +199,139 @@ impl<'a> PddbKey<'a> {
 impl<'a> Read for PddbKey<'a> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if buf.len() == 0 {
-            Ok(0)
-        } else if buf.len() <= 8 {
-            let response = send_message(
-                self.conn,
-                Message::new_blocking_scalar(
-                    Opcode::ReadKeyScalar.to_usize().unwrap(),
-                    self.token[0] as usize,
-                    self.token[1] as usize,
-                    self.token[2] as usize,
-                    buf.len() as usize,
-                )
-            ).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
-            // scalars can return up to 64 bits of data. Split into an array and copy into the buf as demanded.
-            if let xous::Result::Scalar2(a, b) = response {
-                let mut maxbuf: [u8; 8] = [0; 8];
-                for (&src, dst) in (a as u32).to_le_bytes().iter().zip(maxbuf[..4].iter_mut()) {
-                    *dst = src;
-                }
-                if buf.len() > 4 {
-                    for (&src, dst) in (b as u32).to_le_bytes().iter().zip(maxbuf[4..].iter_mut()) {
-                        *dst = src;
-                    }
-                }
-                for (&src, dst) in maxbuf.iter().zip(buf.iter_mut()) {
-                    *dst = src;
-                }
-                Ok(buf.len())
+            return Ok(0)
+        }
+        // create pbuf from a pre-reserved chunk of memory, to save on allocator thrashing
+        // note that it does mean that un-erased data from previous reads and writes are passed back
+        // to the server, which is a kind of information leakage, but I think in practice we're
+        // leaking that data back to a server where the data had either originated from or was disclosed at
+        // one point.
+        let readlen = {
+            let pbuf = PddbBuf::from_slice_mut(self.buf.as_mut());
+            // sure, we could make it a loop, but...unrolled seems better
+            pbuf.token[0] = self.token[0];
+            pbuf.token[1] = self.token[1];
+            pbuf.token[2] = self.token[2];
+            let readlen = if buf.len() <= pbuf.data.len() {
+                buf.len() as u16
             } else {
-                Err(Error::new(ErrorKind::Other, "Xous internal error"))
-            }
-        } else {
-            // create pbuf from a pre-reserved chunk of memory, to save on allocator thrashing
-            // note that it does mean that un-erased data from previous reads and writes are passed back
-            // to the server, which is a kind of information leakage, but I think in practice we're
-            // leaking that data back to a server where the data had either originated from or was disclosed at
-            // one point.
-            let readlen = {
-                let pbuf = PddbBuf::from_slice_mut(self.buf.as_mut());
-                // sure, we could make it a loop, but...unrolled seems better
-                pbuf.token[0] = self.token[0];
-                pbuf.token[1] = self.token[1];
-                pbuf.token[2] = self.token[2];
-                let readlen = if buf.len() <= pbuf.data.len() {
-                    buf.len() as u16
-                } else {
-                    pbuf.data.len() as u16
-                };
-                pbuf.len = readlen;
-                pbuf.retcode = PddbRetcode::Uninit;
-                readlen
+                pbuf.data.len() as u16
             };
-            // this takes the buffer and remaps it to the server, and on return the data is mapped back
-            self.buf.lend_mut(self.conn, Opcode::ReadKeyMem.to_u32().unwrap())
-                .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
-            {
-                // at this point, pbuf has been mutated by the server with a return code and the return data.
-                let pbuf = PddbBuf::from_slice_mut(self.buf.as_mut());
-                match pbuf.retcode {
-                    PddbRetcode::Ok => {
-                        for (&src, dst) in pbuf.data.iter().zip(buf.iter_mut()) {
-                            *dst = src;
-                        }
-                        assert!(pbuf.len <= readlen, "More data returned than we requested");
-                        Ok(pbuf.len as usize)
+            pbuf.len = readlen;
+            pbuf.retcode = PddbRetcode::Uninit;
+            pbuf.flags = 0;
+            pbuf.position = self.pos;
+            readlen
+        };
+        // this takes the buffer and remaps it to the server, and on return the data is mapped back
+        self.buf.lend_mut(self.conn, Opcode::ReadKeyMem.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        {
+            // at this point, pbuf has been mutated by the server with a return code and the return data.
+            let pbuf = PddbBuf::from_slice_mut(self.buf.as_mut());
+            match pbuf.retcode {
+                PddbRetcode::Ok => {
+                    assert!(pbuf.len <= readlen, "More data returned than we requested");
+                    for (&src, dst) in pbuf.data[..pbuf.len as usize].iter().zip(buf.iter_mut()) {
+                        *dst = src;
                     }
-                    PddbRetcode::BasisLost => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
-                    PddbRetcode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
-                    _ => Err(Error::new(ErrorKind::Other, "Unhandled error code in PddbKey Read")),
+                    self.pos += pbuf.len as u64;
+                    Ok(pbuf.len as usize)
                 }
+                PddbRetcode::BasisLost => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
+                PddbRetcode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
+                _ => Err(Error::new(ErrorKind::Other, "Unhandled error code in PddbKey Read")),
             }
         }
     }
 }
 
-impl<'a> Write for PddbKey<'a> {
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        if buf.len() == 0 {
-            Ok(0)
-        } else if buf.len() <= 4 {
-            let opcode = match buf.len() {
-                1 => Opcode::WriteKeyScalar1.to_usize().unwrap(),
-                2 => Opcode::WriteKeyScalar2.to_usize().unwrap(),
-                3 => Opcode::WriteKeyScalar3.to_usize().unwrap(),
-                4 => Opcode::WriteKeyScalar4.to_usize().unwrap(),
-                _ => panic!("This shouldn't happen")
-            };
-            // guarantee we have enough bytes to do the u32 conversion, even if we have less data in the incoming buf
-            let mut u32buf: [u8; 4] = [0; 4];
-            for (&src, dst) in buf.iter().zip(u32buf.iter_mut()) {
-                *dst = src;
+impl<'a> PddbKey<'a> {
+    /// Discards the key's data past the current cursor position.
+    pub fn truncate(&mut self) -> Result<()> {
+        self.write_chunk(&[], PDDB_BUF_TRUNCATE).map(|_| ())
+    }
+    /// Writes all of `data` at the cursor and discards whatever the key held past the end of it.
+    /// The old tail is cut off by the last chunk rather than by a separate request, so a value that
+    /// fits in one `PddbBuf` is replaced by a single write to the disk.
+    pub(crate) fn write_all_truncate(&mut self, mut data: &[u8]) -> Result<()> {
+        while data.len() > PDDB_BUF_DATA_LEN {
+            let written = self.write_chunk(&data[..PDDB_BUF_DATA_LEN], 0)?;
+            if written == 0 {
+                return Err(Error::new(ErrorKind::WriteZero, "PDDB accepted no data"));
             }
-            let response = send_message(
-                self.conn,
-                Message::new_blocking_scalar(
-                    opcode,
-                    self.token[0] as usize,
-                    self.token[1] as usize,
-                    self.token[2] as usize,
-                    u32::from_le_bytes(u32buf) as usize,
-                )
-            ).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
-            if let xous::Result::Scalar2(rcode, len) = response {
-                match FromPrimitive::from_u8(rcode as u8) {
-                    Some(PddbRetcode::Ok) => Ok(len),
-                    Some(PddbRetcode::BasisLost) => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
-                    Some(PddbRetcode::AccessDenied) => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
-                    _ => Err(Error::new(ErrorKind::Other, "Unhandled error code in PddbKey Read")),
-                }
+            data = &data[written..];
+        }
+        if self.write_chunk(data, PDDB_BUF_TRUNCATE)? < data.len() {
+            return Err(Error::new(ErrorKind::WriteZero, "PDDB accepted a partial write"));
+        }
+        Ok(())
+    }
+    fn write_chunk(&mut self, buf: &[u8], flags: u8) -> Result<usize> {
+        let writelen = {
+            let pbuf = PddbBuf::from_slice_mut(self.buf.as_mut());
+            // sure, we could make it a loop, but...unrolled seems better
+            pbuf.token[0] = self.token[0];
+            pbuf.token[1] = self.token[1];
+            pbuf.token[2] = self.token[2];
+            let writelen = if buf.len() <= pbuf.data.len() {
+                buf.len() as u16
             } else {
-                Err(Error::new(ErrorKind::Other, "Xous internal error"))
-            }
-        } else {
-            let writelen = {
-                let pbuf = PddbBuf::from_slice_mut(self.buf.as_mut());
-                // sure, we could make it a loop, but...unrolled seems better
-                pbuf.token[0] = self.token[0];
-                pbuf.token[1] = self.token[1];
-                pbuf.token[2] = self.token[2];
-                let writelen = if buf.len() <= pbuf.data.len() {
-                    buf.len() as u16
-                } else {
-                    pbuf.data.len() as u16
-                };
-                pbuf.len = writelen;
-                pbuf.retcode = PddbRetcode::Uninit;
-                for (&src, dst) in buf.iter().zip(pbuf.data.iter_mut()) {
-                    *dst = src;
-                }
-                writelen
+                pbuf.data.len() as u16
             };
-            // this takes the buffer and remaps it to the server, and on return the data is mapped back
-            self.buf.lend_mut(self.conn, Opcode::WriteKeyMem.to_u32().unwrap())
-                .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
-            {
-                // at this point, pbuf has been mutated by the server with a return code and the return data.
-                let pbuf = PddbBuf::from_slice_mut(self.buf.as_mut());
-                match pbuf.retcode {
-                    PddbRetcode::Ok => {
-                        assert!(pbuf.len <= writelen, "More data written than we requested");
-                        Ok(pbuf.len as usize)
-                    }
-                    PddbRetcode::BasisLost => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
-                    PddbRetcode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
-                    _ => Err(Error::new(ErrorKind::Other, "Unhandled error code in PddbKey Read")),
+            pbuf.len = writelen;
+            pbuf.retcode = PddbRetcode::Uninit;
+            pbuf.flags = flags;
+            pbuf.position = self.pos;
+            for (&src, dst) in buf.iter().zip(pbuf.data.iter_mut()) {
+                *dst = src;
+            }
+            writelen
+        };
+        // this takes the buffer and remaps it to the server, and on return the data is mapped back
+        self.buf.lend_mut(self.conn, Opcode::WriteKeyMem.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        {
+            // at this point, pbuf has been mutated by the server with a return code and the return data.
+            let pbuf = PddbBuf::from_slice_mut(self.buf.as_mut());
+            match pbuf.retcode {
+                PddbRetcode::Ok => {
+                    assert!(pbuf.len <= writelen, "More data written than we requested");
+                    self.pos += pbuf.len as u64;
+                    Ok(pbuf.len as usize)
                 }
+                PddbRetcode::BasisLost => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
+                PddbRetcode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
+                PddbRetcode::DiskFull => Err(Error::new(ErrorKind::OutOfMemory, "Out of disk space")),
+                _ => Err(Error::new(ErrorKind::Other, "Unhandled error code in PddbKey Write")),
             }
         }
     }
+}
+
+impl<'a> Write for PddbKey<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.len() == 0 {
+            return Ok(0)
+        }
+        self.write_chunk(buf, 0)
+    }
     fn flush(&mut self) -> Result<()> {
         let response = send_message(
             self.conn,
-            Message::new_blocking_scalar(Opcode::WriteKeyFlush.to_usize().unwrap(), 0, 0, 0, 0)
+            Message::new_blocking_scalar(
+                Opcode::WriteKeyFlush.to_usize().unwrap(),
+                self.token[0] as usize,
+                self.token[1] as usize,
+                self.token[2] as usize,
+                0,
+            )
         ).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
         if let xous::Result::Scalar1(rcode) = response {
             match FromPrimitive::from_u8(rcode as u8) {
                 Some(PddbRetcode::Ok) => Ok(()),
                 Some(PddbRetcode::BasisLost) => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
+                Some(PddbRetcode::AccessDenied) => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
                 _ => Err(Error::new(ErrorKind::Interrupted, "Flush failed for unspecified reasons")),
             }
         } else {
@@ -264,20 +340,111 @@ impl<'a> Write for PddbKey<'a> {
     }
 }
 
-use core::sync::atomic::{AtomicU32, Ordering};
-static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl<'a> Seek for PddbKey<'a> {
+    /// Seeking past the end of the key is allowed; a subsequent write will fill the gap with zeroes.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => offset_position(self.pos, offset),
+            SeekFrom::End(offset) => offset_position(self.key_len()?, offset),
+        };
+        if let Some(p) = new_pos {
+            self.pos = p;
+            Ok(p)
+        } else {
+            Err(Error::new(ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"))
+        }
+    }
+}
+
+fn offset_position(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+/// Shuts down a key-changed callback server and waits for its thread to exit. This is called from `drop()`,
+/// so failures are logged rather than panicking.
+fn cb_shutdown(sid: SID, handle: Option<JoinHandle::<()>>) {
+    let drop_cid = match xous::connect(sid) {
+        Ok(cid) => cid,
+        Err(e) => {
+            log::error!("couldn't connect to our key callback server to shut it down: {:?}", e);
+            return;
+        }
+    };
+    let sent = xous::send_message(
+        drop_cid,
+        Message::new_blocking_scalar(KeyCallback::Drop.to_usize().unwrap(), 0, 0, 0, 0)
+    );
+    // should be safe because we're the only connection and the previous was a blocking scalar
+    if let Err(e) = unsafe{xous::disconnect(drop_cid)} {
+        log::warn!("couldn't disconnect from our key callback server: {:?}", e);
+    }
+    if let Err(e) = sent {
+        // the responder thread never got the Drop message, so don't wait on it or pull the server out from under it
+        log::error!("couldn't send Drop to our key callback server: {:?}", e);
+        return;
+    }
+    // this will block until the responder thread exits, which it should because it received the Drop message
+    if let Some(handle) = handle {
+        if handle.join().is_err() {
+            log::error!("key callback thread panicked");
+        }
+    }
+    // now we can detroy the server id of the responder thread
+    if let Err(e) = xous::destroy_server(sid) {
+        log::warn!("couldn't destroy our key callback server: {:?}", e);
+    }
+}
+
 impl<'a> Drop for PddbKey<'a> {
     fn drop(&mut self) {
         self.buf.volatile_clear(); // clears any confidential data in our memory buffer
 
+        // release the token, so the server stops sending us notifications and forgets the key was open.
+        // a failure leaks the token on the server side, which isn't worth panicking over in a drop.
+        if let Err(e) = send_message(
+            self.conn,
+            Message::new_blocking_scalar(
+                Opcode::KeyDrop.to_usize().unwrap(),
+                self.token[0] as usize,
+                self.token[1] as usize,
+                self.token[2] as usize,
+                0,
+            )
+        ) {
+            log::error!("couldn't release PDDB key token for {}:{}: {:?}", self.dict, self.key, e);
+        }
+        if let Some(sid) = self.cb_sid.take() {
+            cb_shutdown(sid, self.cb_handle.take());
+        }
+
         // the connection to the server side must be reference counted, so that multiple instances of this object within
         // a single process do not end up de-allocating the CID on other threads before they go out of scope.
         // Note to future me: you want this. Don't get rid of it because you think, "nah, nobody will ever make more than one copy of this object".
+        REFCOUNT.store(REFCOUNT.load(Ordering::Relaxed) - 1, Ordering::Relaxed);
         if REFCOUNT.load(Ordering::Relaxed) == 0 {
-            unsafe{xous::disconnect(self.conn).unwrap();}
+            if let Err(e) = unsafe{xous::disconnect(self.conn)} {
+                log::warn!("couldn't disconnect from the PDDB server: {:?}", e);
+            }
         }
         // if there was object-specific state (such as a one-time use server for async callbacks, specific to the object instance),
         // de-allocate those items here. They don't need a reference count because they are object-specific
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_offset_position() {
+        assert_eq!(offset_position(10, 5), Some(15));
+        assert_eq!(offset_position(10, -10), Some(0));
+        assert_eq!(offset_position(10, -11), None);
+        assert_eq!(offset_position(u64::MAX, 1), None);
+        assert_eq!(offset_position(u64::MAX, i64::MIN), Some(u64::MAX - (1 << 63)));
+    }
+}
@@ -375,9 +375,35 @@ mod tests;
 use tests::*;
 
 use num_traits::*;
-use xous::{msg_blocking_scalar_unpack, msg_scalar_unpack};
+use xous::{msg_blocking_scalar_unpack, msg_scalar_unpack, Message};
+use xous_ipc::Buffer;
 use core::cell::RefCell;
 use std::rc::Rc;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+
+/// An opaque handle that an application uses to refer to a key it has opened
+type ApiToken = [u32; 3];
+/// Bookkeeping for a key that has been opened by an application
+struct TokenRecord {
+    /// the process that requested the token; tokens can't be used by other processes
+    pid: Option<xous::PID>,
+    basis: Option<String>,
+    dict: String,
+    key: String,
+    /// connection to the application's key-changed callback server, if it registered one
+    cb_conn: Option<xous::CID>,
+}
+
+/// A listing of dictionary or key names, sorted once when the listing is started, and handed out a page
+/// at a time after that
+struct ListCursor {
+    /// the process that started the listing; no other process can page through it
+    pid: Option<xous::PID>,
+    names: Vec::<String>,
+    /// index of the first name on the next page
+    next: usize,
+}
 
 #[xous::xous_main]
 fn xmain() -> ! {
@@ -413,6 +439,24 @@ fn xmain() -> ! {
         log::info!("Doing patch test");
         patch_test(&mut pddb_os, &mut basis_cache, None, None);
         pddb_os.dbg_dump(Some("patch".to_string()));
+
+        log::info!("Doing compaction test");
+        compact_test(&mut pddb_os, &mut basis_cache, None, None);
+        pddb_os.dbg_dump(Some("compact".to_string()));
+
+        log::info!("Doing large key cache test");
+        large_cache_test(&mut pddb_os, &mut basis_cache, None, None);
+        pddb_os.dbg_dump(Some("largecache".to_string()));
+
+        log::info!("Doing secret basis test");
+        basis_test(&mut pddb_os, &mut basis_cache);
+        pddb_os.dbg_dump(Some("basis".to_string()));
+
+        log::info!("Doing secure erase test");
+        erase_test(&mut pddb_os, &mut basis_cache);
+
+        log::info!("Doing crash consistency test");
+        crash_test(&mut pddb_os, &mut basis_cache, None);
         log::info!("CI done");
     }
     /*
@@ -458,36 +502,345 @@ fn xmain() -> ! {
     */
     // register a suspend/resume listener
     let sr_cid = xous::connect(pddb_sid).expect("couldn't create suspend callback connection");
-    let _susres = susres::Susres::new(&xns, api::Opcode::SuspendResume as u32, sr_cid).expect("couldn't create suspend/resume object");
+    let mut susres = susres::Susres::new(&xns, api::Opcode::SuspendResume as u32, sr_cid).expect("couldn't create suspend/resume object");
+
+    let mut basis_cache = BasisCache::new();
+    if let Some(sys_basis) = pddb_os.pddb_mount() {
+        log::info!("PDDB mount operation finished successfully");
+        basis_cache.basis_add(sys_basis);
+    } else {
+        log::warn!("PDDB did not mount; did you remember to format the PDDB region?");
+    }
+    let mut tokens = HashMap::<ApiToken, TokenRecord>::new();
+    let mut listings = HashMap::<ApiToken, ListCursor>::new();
+
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    client_api_test();
 
     loop {
-        let msg = xous::receive_message(pddb_sid).unwrap();
+        let mut msg = xous::receive_message(pddb_sid).unwrap();
         match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::DictRequest) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbDictRequest, _>().unwrap();
+                let basis = req.basis.as_ref().map(|b| b.to_str().to_string());
+                let dict = req.dict.to_str().to_string();
+                req.result = if basis_cache.dict_exists(&mut pddb_os, &dict, basis.as_deref()) {
+                    PddbRequestCode::NoErr
+                } else if req.create {
+                    match basis_cache.dict_add(&mut pddb_os, &dict, basis.as_deref()) {
+                        Ok(_) => PddbRequestCode::NoErr,
+                        Err(e) => match e.kind() {
+                            ErrorKind::NotFound => PddbRequestCode::NotFound,
+                            ErrorKind::OutOfMemory => PddbRequestCode::NoFreeSpace,
+                            _ => PddbRequestCode::InternalError,
+                        }
+                    }
+                } else {
+                    PddbRequestCode::NotFound
+                };
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::DictRemove) | Some(Opcode::DictErase) => {
+                let paranoid = msg.body.id() == Opcode::DictErase.to_usize().unwrap();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbDictRequest, _>().unwrap();
+                let basis = req.basis.as_ref().map(|b| b.to_str().to_string());
+                let dict = req.dict.to_str().to_string();
+                req.result = if !basis_cache.dict_exists(&mut pddb_os, &dict, basis.as_deref()) {
+                    PddbRequestCode::NotFound
+                } else {
+                    match basis_cache.dict_remove(&mut pddb_os, &dict, basis.as_deref(), paranoid) {
+                        Ok(_) => {
+                            notify_key_changed(&tokens, &basis_cache.basis_list(), None, basis.as_deref(), &dict, None);
+                            PddbRequestCode::NoErr
+                        }
+                        Err(e) => match e.kind() {
+                            ErrorKind::NotFound => PddbRequestCode::NotFound,
+                            _ => PddbRequestCode::InternalError,
+                        }
+                    }
+                };
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::DictList) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbListRequest, _>().unwrap();
+                let basis = req.basis.as_ref().map(|b| b.to_str().to_string());
+                list_page(&mut listings, &mut req, msg.sender.pid(),
+                    || basis_cache.dict_list(&mut pddb_os, basis.as_deref()),
+                    || [entropy.borrow_mut().get_u32(), entropy.borrow_mut().get_u32(), entropy.borrow_mut().get_u32()]);
+                buffer.replace(req).unwrap();
+            }
             Some(Opcode::KeyRequest) => {
-                // placeholder
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbKeyRequest, _>().unwrap();
+                let basis = req.basis.as_ref().map(|b| b.to_str().to_string());
+                let dict = req.dict.to_str().to_string();
+                let key = req.key.to_str().to_string();
+                req.token = None;
+                req.result = if !basis_cache.dict_exists(&mut pddb_os, &dict, basis.as_deref()) && !req.create_dict {
+                    PddbRequestCode::NotFound
+                } else {
+                    // key_update() creates the dictionary as a side effect if it doesn't exist, so it's enough to check that the key exists.
+                    match basis_cache.key_len(&mut pddb_os, &dict, &key, basis.as_deref()) {
+                        Ok(_) => PddbRequestCode::NoErr,
+                        Err(_) if req.create_key => {
+                            match basis_cache.key_update(&mut pddb_os, &dict, &key, &[], None,
+                                req.alloc_hint.map(|a| a as usize), basis.as_deref(), false
+                            ) {
+                                Ok(_) => PddbRequestCode::NoErr,
+                                Err(e) => match e.kind() {
+                                    ErrorKind::NotFound => PddbRequestCode::NotFound,
+                                    ErrorKind::OutOfMemory => PddbRequestCode::NoFreeSpace,
+                                    _ => PddbRequestCode::InternalError,
+                                }
+                            }
+                        }
+                        Err(_) => PddbRequestCode::NotFound,
+                    }
+                };
+                if req.result == PddbRequestCode::NoErr {
+                    let token = [
+                        entropy.borrow_mut().get_u32(),
+                        entropy.borrow_mut().get_u32(),
+                        entropy.borrow_mut().get_u32(),
+                    ];
+                    let cb_conn = req.cb_sid.and_then(|sid| xous::connect(xous::SID::from_array(sid)).ok());
+                    tokens.insert(token, TokenRecord { pid, basis, dict, key, cb_conn });
+                    req.token = Some(token);
+                }
+                buffer.replace(req).unwrap();
             }
-            Some(Opcode::ReadKeyScalar) => msg_blocking_scalar_unpack!(msg, _tok0, _tok1, _tok2, _len, {
-                // placeholder
+            Some(Opcode::KeyDrop) => msg_blocking_scalar_unpack!(msg, tok0, tok1, tok2, _, {
+                let token: ApiToken = [tok0 as u32, tok1 as u32, tok2 as u32];
+                if token_lookup(&tokens, &token, msg.sender.pid()).is_some() {
+                    if let Some(rec) = tokens.remove(&token) {
+                        if let Some(cid) = rec.cb_conn {
+                            unsafe { xous::disconnect(cid).ok(); }
+                        }
+                    }
+                    xous::return_scalar(msg.sender, PddbRetcode::Ok.to_usize().unwrap()).unwrap();
+                } else {
+                    xous::return_scalar(msg.sender, PddbRetcode::AccessDenied.to_usize().unwrap()).unwrap();
+                }
             }),
-            Some(Opcode::ReadKeyMem) => {
-                // placeholder
+            Some(Opcode::KeyRemove) | Some(Opcode::KeyErase) => {
+                let paranoid = msg.body.id() == Opcode::KeyErase.to_usize().unwrap();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbKeyRequest, _>().unwrap();
+                let basis = req.basis.as_ref().map(|b| b.to_str().to_string());
+                let dict = req.dict.to_str().to_string();
+                let key = req.key.to_str().to_string();
+                req.result = match basis_cache.key_remove(&mut pddb_os, &dict, &key, basis.as_deref(), paranoid) {
+                    Ok(_) => {
+                        // removes are lazy in the cache; commit the tombstone so the key doesn't come back on the next mount.
+                        // erases have already synced, so this is a no-op for them.
+                        match basis_cache.dict_sync(&mut pddb_os, &dict, basis.as_deref()) {
+                            Ok(_) => {
+                                notify_key_changed(&tokens, &basis_cache.basis_list(), None, basis.as_deref(), &dict, Some(&key));
+                                PddbRequestCode::NoErr
+                            }
+                            Err(_) => PddbRequestCode::InternalError,
+                        }
+                    }
+                    Err(e) => match e.kind() {
+                        ErrorKind::NotFound => PddbRequestCode::NotFound,
+                        ErrorKind::OutOfMemory => PddbRequestCode::NoFreeSpace,
+                        _ => PddbRequestCode::InternalError,
+                    }
+                };
+                buffer.replace(req).unwrap();
             }
-            Some(Opcode::WriteKeyScalar1)
-            | Some(Opcode::WriteKeyScalar2)
-            | Some(Opcode::WriteKeyScalar3)
-            | Some(Opcode::WriteKeyScalar4) => msg_blocking_scalar_unpack!(msg, _tok0, _tok1, _tok2, _data, {
-                // placeholder
+            Some(Opcode::KeyLen) => msg_blocking_scalar_unpack!(msg, tok0, tok1, tok2, _, {
+                let token: ApiToken = [tok0 as u32, tok1 as u32, tok2 as u32];
+                if let Some(rec) = token_lookup(&tokens, &token, msg.sender.pid()) {
+                    match basis_cache.key_len(&mut pddb_os, &rec.dict, &rec.key, rec.basis.as_deref()) {
+                        Ok(len) => xous::return_scalar2(msg.sender, PddbRetcode::Ok.to_usize().unwrap(), len as usize).unwrap(),
+                        Err(_) => xous::return_scalar2(msg.sender, PddbRetcode::BasisLost.to_usize().unwrap(), 0).unwrap(),
+                    }
+                } else {
+                    xous::return_scalar2(msg.sender, PddbRetcode::AccessDenied.to_usize().unwrap(), 0).unwrap();
+                }
             }),
+            Some(Opcode::KeyList) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbListRequest, _>().unwrap();
+                let basis = req.basis.as_ref().map(|b| b.to_str().to_string());
+                let dict = req.dict.to_str().to_string();
+                list_page(&mut listings, &mut req, msg.sender.pid(),
+                    || basis_cache.key_list(&mut pddb_os, &dict, basis.as_deref()),
+                    || [entropy.borrow_mut().get_u32(), entropy.borrow_mut().get_u32(), entropy.borrow_mut().get_u32()]);
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::KeyStat) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbKeyStat, _>().unwrap();
+                let basis = req.basis.as_ref().map(|b| b.to_str().to_string());
+                let dict = req.dict.to_str().to_string();
+                let key = req.key.to_str().to_string();
+                req.result = match basis_cache.key_stat(&mut pddb_os, &dict, &key, basis.as_deref()) {
+                    Ok(stat) => {
+                        req.basis = Some(xous_ipc::String::<BASIS_NAME_LEN>::from_str(&stat.basis));
+                        req.len = stat.len;
+                        req.reserved = stat.reserved;
+                        req.age = stat.age;
+                        req.flags = stat.flags;
+                        PddbRequestCode::NoErr
+                    }
+                    Err(e) => match e.kind() {
+                        ErrorKind::NotFound => PddbRequestCode::NotFound,
+                        _ => PddbRequestCode::InternalError,
+                    }
+                };
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::ReadKeyMem) => {
+                let pid = msg.sender.pid();
+                let body = msg.body.memory_message_mut().expect("Incorrect message type received");
+                let pbuf = PddbBuf::from_slice_mut(body.buf.as_slice_mut());
+                let token = pbuf.token;
+                if let Some(rec) = token_lookup(&tokens, &token, pid) {
+                    let len = (pbuf.len as usize).min(pbuf.data.len());
+                    let position = pbuf.position as usize;
+                    match basis_cache.key_read(&mut pddb_os, &rec.dict, &rec.key, &mut pbuf.data[..len], Some(position), rec.basis.as_deref()) {
+                        Ok(readlen) => {
+                            pbuf.len = readlen as u16;
+                            pbuf.retcode = PddbRetcode::Ok;
+                        }
+                        Err(e) => {
+                            pbuf.len = 0;
+                            pbuf.retcode = match e.kind() {
+                                ErrorKind::NotFound => PddbRetcode::BasisLost,
+                                _ => PddbRetcode::InternalError,
+                            };
+                        }
+                    }
+                } else {
+                    pbuf.len = 0;
+                    pbuf.retcode = PddbRetcode::AccessDenied;
+                }
+            }
             Some(Opcode::WriteKeyMem) => {
-                // placeholder
+                let pid = msg.sender.pid();
+                let body = msg.body.memory_message_mut().expect("Incorrect message type received");
+                let pbuf = PddbBuf::from_slice_mut(body.buf.as_slice_mut());
+                let token = pbuf.token;
+                if let Some(rec) = token_lookup(&tokens, &token, pid) {
+                    let len = (pbuf.len as usize).min(pbuf.data.len());
+                    let position = pbuf.position as usize;
+                    let truncate = pbuf.flags & PDDB_BUF_TRUNCATE != 0;
+                    match basis_cache.key_update(&mut pddb_os, &rec.dict, &rec.key, &pbuf.data[..len], Some(position), None, rec.basis.as_deref(), truncate) {
+                        Ok(_) => {
+                            pbuf.len = len as u16;
+                            pbuf.retcode = PddbRetcode::Ok;
+                            notify_key_changed(&tokens, &basis_cache.basis_list(), Some(&token), rec.basis.as_deref(), &rec.dict, Some(&rec.key));
+                        }
+                        Err(e) => {
+                            pbuf.len = 0;
+                            pbuf.retcode = match e.kind() {
+                                ErrorKind::NotFound => PddbRetcode::BasisLost,
+                                ErrorKind::OutOfMemory => PddbRetcode::DiskFull,
+                                _ => PddbRetcode::InternalError,
+                            };
+                        }
+                    }
+                } else {
+                    pbuf.len = 0;
+                    pbuf.retcode = PddbRetcode::AccessDenied;
+                }
+            }
+            Some(Opcode::WriteKeyFlush) => msg_blocking_scalar_unpack!(msg, tok0, tok1, tok2, _, {
+                // every write is committed to disk before WriteKeyMem returns, so there is nothing buffered to flush;
+                // but the call is still checked, so that a caller finds out about a lost basis at flush time.
+                let token: ApiToken = [tok0 as u32, tok1 as u32, tok2 as u32];
+                if let Some(rec) = token_lookup(&tokens, &token, msg.sender.pid()) {
+                    if basis_cache.key_len(&mut pddb_os, &rec.dict, &rec.key, rec.basis.as_deref()).is_ok() {
+                        xous::return_scalar(msg.sender, PddbRetcode::Ok.to_usize().unwrap()).unwrap();
+                    } else {
+                        xous::return_scalar(msg.sender, PddbRetcode::BasisLost.to_usize().unwrap()).unwrap();
+                    }
+                } else {
+                    xous::return_scalar(msg.sender, PddbRetcode::AccessDenied.to_usize().unwrap()).unwrap();
+                }
+            }),
+            Some(Opcode::BasisCreate) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbBasisRequest, _>().unwrap();
+                // don't echo the password back to the caller
+                req.result = if let Some(password) = req.password.take() {
+                    basis_result_code(basis_cache.basis_create(&mut pddb_os, req.name.to_str(), password.to_str()))
+                } else {
+                    PddbRequestCode::AccessDenied
+                };
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::BasisUnlock) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbBasisRequest, _>().unwrap();
+                req.result = if let Some(password) = req.password.take() {
+                    basis_result_code(basis_cache.basis_unlock(&mut pddb_os, req.name.to_str(), password.to_str()))
+                } else {
+                    PddbRequestCode::AccessDenied
+                };
+                if req.result == PddbRequestCode::NoErr {
+                    // the new basis sits on top of the others, so it may shadow keys that are already open
+                    basis_changed(&mut tokens, None);
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::BasisLock) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbBasisRequest, _>().unwrap();
+                req.password = None;
+                req.result = basis_result_code(basis_cache.basis_lock(&mut pddb_os, req.name.to_str()));
+                if req.result == PddbRequestCode::NoErr {
+                    basis_changed(&mut tokens, Some(req.name.to_str()));
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::BasisList) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut list = buffer.to_original::<PddbBasisList, _>().unwrap();
+                let names = basis_cache.basis_list();
+                if names.len() > BASIS_LIST_MAX {
+                    log::warn!("{} basis are open, but only the {} highest priority ones can be listed", names.len(), BASIS_LIST_MAX);
+                }
+                let skip = names.len().saturating_sub(BASIS_LIST_MAX);
+                for (name, dst) in names[skip..].iter().zip(list.list.iter_mut()) {
+                    *dst = xous_ipc::String::<BASIS_NAME_LEN>::from_str(name);
+                }
+                list.num = (names.len() - skip) as u32;
+                buffer.replace(list).unwrap();
             }
-            Some(Opcode::WriteKeyFlush) => {
-                // placeholder
+            Some(Opcode::BasisCacheLimit) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbCacheRequest, _>().unwrap();
+                let basis = req.basis.as_ref().map(|b| b.to_str().to_string());
+                let set = match req.limit {
+                    Some(limit) => basis_cache.large_cache_set_limit(limit as usize, basis.as_deref()),
+                    None => Ok(()),
+                };
+                req.result = match set.and_then(|_| basis_cache.large_cache_usage(basis.as_deref())) {
+                    Ok(usage) => {
+                        req.usage = usage as u64;
+                        PddbRequestCode::NoErr
+                    }
+                    Err(e) => match e.kind() {
+                        ErrorKind::NotFound => PddbRequestCode::NotFound,
+                        _ => PddbRequestCode::InternalError,
+                    }
+                };
+                buffer.replace(req).unwrap();
             }
-            Some(Opcode::SuspendResume) => msg_scalar_unpack!(msg, _token, _, _, _, {
-                /* pddb.suspend();
+            Some(Opcode::SuspendResume) => msg_scalar_unpack!(msg, token, _, _, _, {
+                // secret basis don't survive a suspend: their keys and contents are purged from RAM, and
+                // they have to be unlocked again after resume.
+                for name in basis_cache.basis_lock_secrets(&mut pddb_os).iter() {
+                    basis_changed(&mut tokens, Some(name));
+                }
                 susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
-                pddb.resume(); */
             }),
             None => {
                 log::error!("couldn't convert opcode");
@@ -503,6 +856,138 @@ fn xmain() -> ! {
     xous::terminate_process(0)
 }
 
+/// Looks up a token, checking that it's being used by the process it was issued to.
+fn token_lookup<'a>(tokens: &'a HashMap::<ApiToken, TokenRecord>, token: &ApiToken, pid: Option<xous::PID>) -> Option<&'a TokenRecord> {
+    tokens.get(token).filter(|rec| rec.pid == pid)
+}
+
+/// Sends a key-changed notification to every token holder with a registered callback that refers to the
+/// given dictionary and key, except for the `source` token that made the change. A `key` of None matches
+/// every key in the dictionary. `basis` is the basis named by the change, and `open_basis` is the
+/// list of open basis from `BasisCache::basis_list()`, which is used to work out which basis a change or
+/// a token that doesn't name one resolves to.
+fn notify_key_changed(tokens: &HashMap::<ApiToken, TokenRecord>, open_basis: &[String], source: Option<&ApiToken>,
+    basis: Option<&str>, dict: &str, key: Option<&str>
+) {
+    for (token, rec) in tokens.iter() {
+        if Some(token) == source || !key_change_matches(rec, open_basis, basis, dict, key) {
+            continue;
+        }
+        if let Some(cid) = rec.cb_conn {
+            if let Err(e) = xous::send_message(cid,
+                Message::new_scalar(KeyCallback::Changed.to_usize().unwrap(), 0, 0, 0, 0)
+            ) {
+                log::warn!("couldn't notify key change on {}:{}: {:?}", rec.dict, rec.key, e);
+            }
+        }
+    }
+}
+
+/// Decides if a change to `dict`/`key` in `basis` is visible through the token record `rec`. Operations that
+/// don't name a basis go to the most recently opened one, which is the last entry of `open_basis`.
+fn key_change_matches(rec: &TokenRecord, open_basis: &[String], basis: Option<&str>, dict: &str, key: Option<&str>) -> bool {
+    let resolve = |b: Option<&str>| b.map(|n| n.to_string()).or_else(|| open_basis.last().cloned());
+    rec.dict == dict
+        && key.map_or(true, |k| k == rec.key)
+        && resolve(basis) == resolve(rec.basis.as_deref())
+}
+
+/// Called whenever the stack of open basis changes. Every callback holder is notified, because the basis
+/// that resolves a key which doesn't name a basis may have changed. If a basis was `locked`, the tokens
+/// that explicitly refer to it are also revoked.
+fn basis_changed(tokens: &mut HashMap::<ApiToken, TokenRecord>, locked: Option<&str>) {
+    notify_key_changed_all(tokens);
+    if let Some(name) = locked {
+        tokens.retain(|_, rec| {
+            if rec.basis.as_deref() == Some(name) {
+                if let Some(cid) = rec.cb_conn {
+                    unsafe { xous::disconnect(cid).ok(); }
+                }
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+fn notify_key_changed_all(tokens: &HashMap::<ApiToken, TokenRecord>) {
+    for rec in tokens.values() {
+        if let Some(cid) = rec.cb_conn {
+            if let Err(e) = xous::send_message(cid,
+                Message::new_scalar(KeyCallback::Changed.to_usize().unwrap(), 0, 0, 0, 0)
+            ) {
+                log::warn!("couldn't notify key change on {}:{}: {:?}", rec.dict, rec.key, e);
+            }
+        }
+    }
+}
+
+/// Fills in the next page of a listing. A request without a token starts a new listing: `listing` is
+/// called to collect the names, which are sorted once and kept under a token from `new_token` until
+/// the last page is handed out. A process has at most one listing going at a time; starting another
+/// one abandons the previous one.
+fn list_page(listings: &mut HashMap::<ApiToken, ListCursor>, req: &mut PddbListRequest, pid: Option<xous::PID>,
+    listing: impl FnOnce() -> std::io::Result<std::collections::HashSet::<String>>, new_token: impl FnOnce() -> ApiToken
+) {
+    req.num = 0;
+    req.total = 0;
+    let token = match req.token.take() {
+        Some(token) => token,
+        None => match listing() {
+            Ok(names) => {
+                let mut names: Vec::<String> = names.into_iter().collect();
+                names.sort();
+                listings.retain(|_, cursor| cursor.pid != pid);
+                let token = new_token();
+                listings.insert(token, ListCursor { pid, names, next: 0 });
+                token
+            }
+            Err(e) => {
+                req.result = match e.kind() {
+                    ErrorKind::NotFound => PddbRequestCode::NotFound,
+                    _ => PddbRequestCode::InternalError,
+                };
+                return;
+            }
+        }
+    };
+    let cursor = match listings.get_mut(&token).filter(|cursor| cursor.pid == pid) {
+        Some(cursor) => cursor,
+        None => {
+            req.result = PddbRequestCode::AccessDenied;
+            return;
+        }
+    };
+    for (name, dst) in cursor.names[cursor.next..].iter().zip(req.list.iter_mut()) {
+        *dst = xous_ipc::String::<DICT_NAME_LEN>::from_str(name);
+        req.num += 1;
+    }
+    cursor.next += req.num as usize;
+    req.total = cursor.names.len() as u32;
+    req.result = PddbRequestCode::NoErr;
+    if cursor.next < cursor.names.len() {
+        req.token = Some(token);
+    } else {
+        listings.remove(&token);
+    }
+}
+
+/// Maps the result of a basis operation onto the code returned to the caller.
+fn basis_result_code(result: std::io::Result<()>) -> PddbRequestCode {
+    match result {
+        Ok(_) => PddbRequestCode::NoErr,
+        Err(e) => match e.kind() {
+            ErrorKind::NotFound => PddbRequestCode::NotFound,
+            ErrorKind::PermissionDenied => PddbRequestCode::AccessDenied,
+            ErrorKind::OutOfMemory => PddbRequestCode::NoFreeSpace,
+            ErrorKind::AlreadyExists => PddbRequestCode::DuplicateEntry,
+            ErrorKind::InvalidInput => PddbRequestCode::InvalidName,
+            _ => PddbRequestCode::InternalError,
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) fn manual_testcase(hw: &mut PddbOs) {
     log::info!("Initializing disk...");
@@ -559,4 +1044,103 @@ pub(crate) fn manual_testcase(hw: &mut PddbOs) {
             log::info!("couldn't read data: {:?}", e);
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod notify_tests {
+    use super::*;
+    fn rec(basis: Option<&str>, dict: &str, key: &str) -> TokenRecord {
+        TokenRecord { pid: None, basis: basis.map(|b| b.to_string()), dict: dict.to_string(), key: key.to_string(), cb_conn: None }
+    }
+    #[test]
+    fn test_key_change_matches() {
+        let open = vec!["sys".to_string(), "secret".to_string()];
+        // a change that doesn't name a basis lands in the most recently opened one
+        assert!(key_change_matches(&rec(None, "d", "k"), &open, None, "d", Some("k")));
+        assert!(key_change_matches(&rec(Some("secret"), "d", "k"), &open, None, "d", Some("k")));
+        assert!(!key_change_matches(&rec(Some("sys"), "d", "k"), &open, None, "d", Some("k")));
+        // changes in a lower priority basis are hidden from tokens that follow the default
+        assert!(!key_change_matches(&rec(None, "d", "k"), &open, Some("sys"), "d", Some("k")));
+        assert!(key_change_matches(&rec(Some("sys"), "d", "k"), &open, Some("sys"), "d", Some("k")));
+        // a change without a key covers the whole dictionary
+        assert!(key_change_matches(&rec(None, "d", "k"), &open, None, "d", None));
+        assert!(!key_change_matches(&rec(None, "d", "k"), &open, None, "d", Some("other")));
+        assert!(!key_change_matches(&rec(None, "e", "k"), &open, None, "d", None));
+    }
+}
+#[cfg(test)]
+mod list_tests {
+    use super::*;
+    use std::collections::HashSet;
+    fn request(token: Option<ApiToken>) -> PddbListRequest {
+        PddbListRequest {
+            basis: None,
+            dict: xous_ipc::String::<DICT_NAME_LEN>::new(),
+            token,
+            list: [xous_ipc::String::<DICT_NAME_LEN>::new(); LIST_PAGE_MAX],
+            num: 0,
+            total: 0,
+            result: PddbRequestCode::Uninit,
+        }
+    }
+    fn names(count: usize) -> HashSet::<String> {
+        (0..count).map(|i| format!("name{:03}", i)).collect()
+    }
+    fn page(req: &PddbListRequest) -> Vec::<String> {
+        req.list[..req.num as usize].iter().map(|name| name.to_str().to_string()).collect()
+    }
+    #[test]
+    fn test_list_paging() {
+        let mut listings = HashMap::<ApiToken, ListCursor>::new();
+        let pid = xous::PID::new(2);
+        let count = 2 * LIST_PAGE_MAX + 5;
+        let mut found = Vec::<String>::new();
+        let mut req = request(None);
+        list_page(&mut listings, &mut req, pid, || Ok(names(count)), || [1, 2, 3]);
+        assert_eq!(req.total as usize, count);
+        loop {
+            assert_eq!(req.result, PddbRequestCode::NoErr);
+            found.extend(page(&req));
+            match req.token {
+                Some(token) => {
+                    req = request(Some(token));
+                    list_page(&mut listings, &mut req, pid, || panic!("listing was collected again"), || [4, 5, 6]);
+                }
+                None => break,
+            }
+        }
+        let mut expected: Vec::<String> = names(count).into_iter().collect();
+        expected.sort();
+        assert_eq!(found, expected);
+        // the cursor is dropped with the last page
+        assert!(listings.is_empty());
+    }
+    #[test]
+    fn test_list_cursor_ownership() {
+        let mut listings = HashMap::<ApiToken, ListCursor>::new();
+        let (pid, other) = (xous::PID::new(2), xous::PID::new(3));
+        let mut req = request(None);
+        list_page(&mut listings, &mut req, pid, || Ok(names(LIST_PAGE_MAX + 1)), || [1, 2, 3]);
+        let token = req.token.expect("listing fits in one page");
+
+        // another process can't page through the listing
+        let mut req = request(Some(token));
+        list_page(&mut listings, &mut req, other, || Ok(names(1)), || [4, 5, 6]);
+        assert_eq!(req.result, PddbRequestCode::AccessDenied);
+        assert_eq!(req.num, 0);
+
+        // starting a new listing abandons the previous one of the same process
+        let mut req = request(None);
+        list_page(&mut listings, &mut req, pid, || Ok(names(0)), || [7, 8, 9]);
+        assert_eq!(req.result, PddbRequestCode::NoErr);
+        assert!(req.token.is_none() && req.total == 0);
+        let mut req = request(Some(token));
+        list_page(&mut listings, &mut req, pid, || Ok(names(1)), || [4, 5, 6]);
+        assert_eq!(req.result, PddbRequestCode::AccessDenied);
+
+        let mut req = request(None);
+        list_page(&mut listings, &mut req, pid,
+            || Err(std::io::Error::new(ErrorKind::NotFound, "dictionary not found")), || [4, 5, 6]);
+        assert_eq!(req.result, PddbRequestCode::NotFound);
+        assert!(listings.is_empty());
+    }
+}
@@ -1,8 +1,13 @@
 use rand::Rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use crate::*;
+use std::collections::{HashMap, HashSet};
 
 fn gen_key(dictname: &str, keynum: usize, lower_size_bound: usize, upper_size_bound: usize) -> (String, Vec::<u8>) {
-    let mut rng = rand::thread_rng();
+    gen_key_rng(&mut rand::thread_rng(), dictname, keynum, lower_size_bound, upper_size_bound)
+}
+fn gen_key_rng(rng: &mut impl Rng, dictname: &str, keynum: usize, lower_size_bound: usize, upper_size_bound: usize) -> (String, Vec::<u8>) {
     // we want roughly half our keys to be in the small bin, and half in the large bin
     let keylen = if rng.gen_bool(0.5) {
         rng.gen_range(lower_size_bound..VPAGE_SIZE)
@@ -67,7 +72,7 @@ pub(crate) fn delete_add_dict_consistency(hw: &mut PddbOs, basis_cache: &mut Bas
     let num_keys = maybe_num_keys.unwrap_or(36);
     let (key_lower_bound, key_upper_bound) = maybe_key_sizes.unwrap_or((1, 9000));
 
-    let dict_list = basis_cache.dict_list(hw);
+    let dict_list = basis_cache.dict_list(hw, None).unwrap();
     let dict_start_index = dict_list.len();
     for (evicted, evict_dict) in dict_list.iter().enumerate() {
         if evicted < evict_count {
@@ -97,9 +102,9 @@ pub(crate) fn patch_test(hw: &mut PddbOs, basis_cache: &mut BasisCache,
     let patch_offset = maybe_patch_offset.unwrap_or(5);
     let patch_data = maybe_patch_data.unwrap_or("patched!".to_string());
 
-    let dict_list = basis_cache.dict_list(hw);
+    let dict_list = basis_cache.dict_list(hw, None).unwrap();
     for dict in dict_list.iter() {
-        if let Ok(key_list) = basis_cache.key_list(hw, dict) {
+        if let Ok(key_list) = basis_cache.key_list(hw, dict, None) {
             for key in key_list.iter() {
                 // this actually does something a bit more complicated on a multi-basis system than you'd think:
                 // it will get the union of all key names, and then patch the *latest open basis* only with new data.
@@ -111,4 +116,598 @@ pub(crate) fn patch_test(hw: &mut PddbOs, basis_cache: &mut BasisCache,
         }
 
     }
-}
\ No newline at end of file
+}
+/// dictionary compaction check: fragment a dictionary by removing every other key, compact it,
+/// and confirm the surviving keys read back intact both from the cache and after a re-mount.
+pub(crate) fn compact_test(hw: &mut PddbOs, basis_cache: &mut BasisCache,
+    maybe_num_keys: Option<usize>, maybe_key_sizes: Option<(usize, usize)>) {
+    let num_keys = maybe_num_keys.unwrap_or(48);
+    let (key_lower_bound, key_upper_bound) = maybe_key_sizes.unwrap_or((1, 9000));
+    let dictname = "compact";
+
+    basis_cache.dict_add(hw, dictname, None).unwrap();
+    let mut survivors = HashMap::<String, Vec::<u8>>::new();
+    let mut removed = Vec::<String>::new();
+    for keynum in 1..=num_keys {
+        let (keyname, keydata) = gen_key(dictname, keynum, key_lower_bound, key_upper_bound);
+        basis_cache.key_update(hw, dictname, &keyname, &keydata, None, None, None, false).unwrap();
+        if keynum % 2 == 0 {
+            removed.push(keyname);
+        } else {
+            survivors.insert(keyname, keydata);
+        }
+    }
+    for keyname in removed.iter() {
+        basis_cache.key_remove(hw, dictname, keyname, None, false).unwrap();
+    }
+    basis_cache.dict_compact(hw, dictname, None).unwrap();
+    compact_check(hw, basis_cache, dictname, &survivors, &removed);
+
+    log::info!("Re-mounting to check compacted dictionary");
+    let mut remount_cache = BasisCache::new();
+    let sys_basis = hw.pddb_mount().expect("couldn't re-mount system basis after compaction");
+    remount_cache.basis_add(sys_basis);
+    *basis_cache = remount_cache;
+    compact_check(hw, basis_cache, dictname, &survivors, &removed);
+}
+
+fn compact_check(hw: &mut PddbOs, basis_cache: &mut BasisCache, dictname: &str,
+    survivors: &HashMap::<String, Vec::<u8>>, removed: &Vec::<String>) {
+    let key_list = basis_cache.key_list(hw, dictname, None).expect("compacted dictionary went missing");
+    if key_list.len() != survivors.len() {
+        panic!("compacted key count mismatch: expected {}, got {}", survivors.len(), key_list.len());
+    }
+    for (keyname, keydata) in survivors.iter() {
+        if !key_list.contains(keyname) {
+            panic!("key {} missing from compacted key list", keyname);
+        }
+        let mut readback = vec![0u8; keydata.len()];
+        match basis_cache.key_read(hw, dictname, keyname, &mut readback, None, None) {
+            Ok(readlen) => {
+                if readlen != keydata.len() || readback != *keydata {
+                    panic!("key {} data mismatch after compaction", keyname);
+                }
+            }
+            Err(e) => panic!("couldn't read back key {} after compaction: {:?}", keyname, e),
+        }
+    }
+    for keyname in removed.iter() {
+        let mut readback = [0u8; 1];
+        match basis_cache.key_read(hw, dictname, keyname, &mut readback, None, None) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            _ => panic!("removed key {} is still readable after compaction", keyname),
+        }
+    }
+}
+
+/// large key cache check: read and patch a multi-page key at unaligned offsets under a small
+/// cache limit, and confirm the cache stays within its bounds and coherent with the disk.
+pub(crate) fn large_cache_test(hw: &mut PddbOs, basis_cache: &mut BasisCache,
+    maybe_cache_pages: Option<usize>, maybe_key_pages: Option<usize>) {
+    let cache_limit = maybe_cache_pages.unwrap_or(2) * VPAGE_SIZE;
+    let key_len = maybe_key_pages.unwrap_or(5) * VPAGE_SIZE + 123;
+    let dictname = "largecache";
+    let keyname = "largekey";
+
+    basis_cache.large_cache_set_limit(cache_limit, None).unwrap();
+    basis_cache.dict_add(hw, dictname, None).unwrap();
+    let mut expected = Vec::<u8>::new();
+    for i in 0..key_len {
+        expected.push((i % 251) as u8);
+    }
+    basis_cache.key_update(hw, dictname, keyname, &expected, None, None, None, false).unwrap();
+
+    // reads that straddle page boundaries, in a scattered order
+    let mut rng = rand::thread_rng();
+    for _ in 0..32 {
+        let offset = rng.gen_range(0..key_len);
+        let mut readback = vec![0u8; rng.gen_range(1..2 * VPAGE_SIZE)];
+        let readlen = basis_cache.key_read(hw, dictname, keyname, &mut readback, Some(offset), None).unwrap();
+        assert!(readlen == readback.len().min(key_len - offset), "large key short read");
+        assert!(readback[..readlen] == expected[offset..offset + readlen], "large key data mismatch at offset {}", offset);
+        assert!(basis_cache.large_cache_usage(None).unwrap() <= cache_limit, "large key cache exceeded its limit");
+    }
+
+    // patches that straddle page boundaries; the patched pages are likely to be in cache from the reads above
+    for page in 1..key_len / VPAGE_SIZE {
+        let offset = page * VPAGE_SIZE - 10;
+        let patch = format!("patched page {}", page);
+        basis_cache.key_update(hw, dictname, keyname, patch.as_bytes(), Some(offset), None, None, false).unwrap();
+        for (&src, dst) in patch.as_bytes().iter().zip(expected[offset..].iter_mut()) {
+            *dst = src;
+        }
+        let mut readback = vec![0u8; 64];
+        basis_cache.key_read(hw, dictname, keyname, &mut readback, Some(offset - 32), None).unwrap();
+        assert!(readback[..] == expected[offset - 32..offset + 32], "stale data in large key cache after patch");
+    }
+    large_cache_check(hw, basis_cache, dictname, keyname, &expected);
+
+    // a limit below one page turns the cache off entirely
+    basis_cache.large_cache_set_limit(0, None).unwrap();
+    assert!(basis_cache.large_cache_usage(None).unwrap() == 0, "large key cache was not trimmed");
+    large_cache_check(hw, basis_cache, dictname, keyname, &expected);
+    assert!(basis_cache.large_cache_usage(None).unwrap() == 0, "large key cache filled while disabled");
+
+    log::info!("Re-mounting to check large key data on disk");
+    let mut remount_cache = BasisCache::new();
+    let sys_basis = hw.pddb_mount().expect("couldn't re-mount system basis");
+    remount_cache.basis_add(sys_basis);
+    *basis_cache = remount_cache;
+    large_cache_check(hw, basis_cache, dictname, keyname, &expected);
+}
+
+fn large_cache_check(hw: &mut PddbOs, basis_cache: &mut BasisCache, dictname: &str, keyname: &str, expected: &Vec::<u8>) {
+    let mut readback = vec![0u8; expected.len()];
+    let readlen = basis_cache.key_read(hw, dictname, keyname, &mut readback, None, None).unwrap();
+    if readlen != expected.len() || readback != *expected {
+        panic!("large key {}:{} data mismatch", dictname, keyname);
+    }
+}
+
+/// secret basis check: data written to an unlocked basis is invisible once it's locked, shadows
+/// the system basis while it's open, and comes back intact after re-unlocking.
+pub(crate) fn basis_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
+    let basisname = "secretbasis";
+    let password = "test password";
+    let dictname = "secretdict";
+    let keyname = "secretkey";
+    let secret = b"the secret data";
+
+    basis_cache.basis_create(hw, basisname, password).unwrap();
+    match basis_cache.basis_create(hw, basisname, password) {
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {},
+        _ => panic!("duplicate basis creation was not rejected"),
+    }
+    match basis_cache.basis_unlock(hw, basisname, "wrong password") {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {},
+        _ => panic!("basis unlocked with the wrong password"),
+    }
+    basis_cache.basis_unlock(hw, basisname, password).unwrap();
+    assert!(basis_cache.basis_list().last().map(|s| s.as_str()) == Some(basisname), "unlocked basis is not the default");
+
+    // writes that don't name a basis go to the most recently unlocked one
+    basis_cache.dict_add(hw, dictname, None).unwrap();
+    basis_cache.key_update(hw, dictname, keyname, secret, None, None, None, false).unwrap();
+    basis_cache.basis_lock(hw, basisname).unwrap();
+    assert!(!basis_cache.basis_list().iter().any(|s| s == basisname), "locked basis is still listed");
+    let mut readback = [0u8; 16];
+    match basis_cache.key_read(hw, dictname, keyname, &mut readback, None, None) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+        _ => panic!("secret key is readable while its basis is locked"),
+    }
+    match basis_cache.basis_lock(hw, PDDB_DEFAULT_SYSTEM_BASIS) {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {},
+        _ => panic!("system basis was locked"),
+    }
+
+    log::info!("Re-mounting to check secret basis data on disk");
+    let mut remount_cache = BasisCache::new();
+    let sys_basis = hw.pddb_mount().expect("couldn't re-mount system basis");
+    remount_cache.basis_add(sys_basis);
+    *basis_cache = remount_cache;
+    basis_cache.basis_unlock(hw, basisname, password).unwrap();
+    let readlen = basis_cache.key_read(hw, dictname, keyname, &mut readback, None, Some(basisname)).unwrap();
+    assert!(readback[..readlen] == secret[..], "secret basis data mismatch after re-unlock");
+    let locked = basis_cache.basis_lock_secrets(hw);
+    assert!(locked.len() == 1 && locked[0] == basisname, "secret basis was not locked on suspend");
+}
+
+/// secure erase check: erase a small key, a large key and a whole dictionary in paranoid mode, and
+/// confirm that none of the ciphertext that held their data is left anywhere in the disk image.
+pub(crate) fn erase_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
+    let dictname = "erasetest";
+    let victim_dict = "erasedict";
+    let bystander = (String::from("bystander"), vec![0xA5u8; 200]);
+    let small = (String::from("smallkey"), vec![0x5Au8; 300]);
+    let mut large = (String::from("largekey"), Vec::<u8>::new());
+    for i in 0..3 * VPAGE_SIZE + 17 {
+        large.1.push((i % 253) as u8);
+    }
+    for (keyname, data) in [&bystander, &small, &large].iter() {
+        basis_cache.key_update(hw, dictname, keyname, data, None, None, None, false).unwrap();
+    }
+    let mut victims = Vec::<(String, String)>::new();
+    for keynum in 0..4 {
+        let (keyname, keydata) = gen_key(victim_dict, keynum, 32, 2 * VPAGE_SIZE);
+        basis_cache.key_update(hw, victim_dict, &keyname, &keydata, None, None, None, false).unwrap();
+        victims.push((victim_dict.to_string(), keyname));
+    }
+    basis_cache.dict_sync(hw, dictname, None).unwrap();
+    basis_cache.dict_sync(hw, victim_dict, None).unwrap();
+    victims.push((dictname.to_string(), small.0.to_string()));
+    victims.push((dictname.to_string(), large.0.to_string()));
+
+    // record every ciphertext block that currently holds the data of the keys to be erased
+    let mut blocks = HashSet::<[u8; 32]>::new();
+    for (dict, keyname) in victims.iter() {
+        for pp in basis_cache.key_phys_pages(hw, dict, keyname, None).unwrap() {
+            let base = pp.page_number() as usize * PAGE_SIZE;
+            for chunk in hw.dbg_data_region()[base..base + PAGE_SIZE].chunks_exact(32) {
+                let mut block = [0u8; 32];
+                block.copy_from_slice(chunk);
+                blocks.insert(block);
+            }
+        }
+    }
+    assert!(blocks.len() > 0, "no ciphertext found for the keys under test");
+    let small_pages = basis_cache.key_phys_pages(hw, dictname, &small.0, None).unwrap();
+    hw.dbg_dump(Some("erase_before".to_string()));
+
+    basis_cache.key_remove(hw, dictname, &small.0, None, true).unwrap();
+    basis_cache.key_remove(hw, dictname, &large.0, None, true).unwrap();
+    basis_cache.dict_remove(hw, victim_dict, None, true).unwrap();
+    hw.dbg_dump(Some("erase_after".to_string()));
+
+    for (index, chunk) in hw.dbg_data_region().chunks_exact(32).enumerate() {
+        if blocks.contains(chunk) {
+            panic!("ciphertext of an erased key is still on disk at data offset 0x{:x}", index * 32);
+        }
+    }
+    // the small pool slot the erased key shared with the bystander was moved to a fresh page, and the old one scrubbed
+    for pp in basis_cache.key_phys_pages(hw, dictname, &bystander.0, None).unwrap() {
+        assert!(!small_pages.iter().any(|old| old.page_number() == pp.page_number()),
+            "small pool was re-written in place over the erased key");
+    }
+    erase_check(hw, basis_cache, dictname, victim_dict, &bystander, &small.0, &large.0);
+
+    log::info!("Re-mounting to check erased keys stay erased");
+    let mut remount_cache = BasisCache::new();
+    let sys_basis = hw.pddb_mount().expect("couldn't re-mount system basis");
+    remount_cache.basis_add(sys_basis);
+    *basis_cache = remount_cache;
+    erase_check(hw, basis_cache, dictname, victim_dict, &bystander, &small.0, &large.0);
+}
+
+fn erase_check(hw: &mut PddbOs, basis_cache: &mut BasisCache, dictname: &str, victim_dict: &str,
+    bystander: &(String, Vec::<u8>), small: &str, large: &str) {
+    // the bystander shared a small pool page with the erased small key; it must survive the re-write
+    let mut readback = vec![0u8; bystander.1.len()];
+    let readlen = basis_cache.key_read(hw, dictname, &bystander.0, &mut readback, None, None).unwrap();
+    assert!(readlen == bystander.1.len() && readback == bystander.1, "bystander key was damaged by the erase");
+    for keyname in [small, large].iter() {
+        match basis_cache.key_read(hw, dictname, keyname, &mut readback, None, None) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            _ => panic!("erased key {} is still readable", keyname),
+        }
+    }
+    assert!(!basis_cache.dict_exists(hw, victim_dict, None), "erased dictionary still exists");
+}
+
+const CRASH_DICT: &str = "crashtest";
+const CRASH_DEFAULT_SEED: u64 = 0x7064_6462_6372_6173;
+const CRASH_DEFAULT_STEPS: usize = 24;
+/// A workload of a couple dozen steps writes far too little to run the FastSpace low or to need a
+/// compaction, so these steps are scheduled in explicitly.
+const CRASH_COMPACT_INTERVAL: usize = 8;
+const CRASH_ROLLOVER_INTERVAL: usize = 10;
+
+/// Seed of the crash test workload. It's printed on every run; set `PDDB_CRASH_SEED` to replay a failure.
+fn crash_seed() -> u64 {
+    let seed = std::env::var("PDDB_CRASH_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(CRASH_DEFAULT_SEED);
+    log::info!("crash test with PDDB_CRASH_SEED={}", seed);
+    seed
+}
+
+enum CrashAction {
+    /// set the key to new contents
+    Write(Vec::<u8>),
+    Delete,
+    /// compact the crash test dictionary of the step's basis
+    Compact,
+    /// replace the FastSpace record, and with it every update record in the FSCB
+    Rollover,
+}
+
+/// One step of a crash test workload. Steps are generated up front, so that the exact same step can
+/// be replayed from the same starting image at every crash point.
+struct CrashStep {
+    basis: String,
+    /// the key that is changed; empty for steps that don't change any key
+    key: String,
+    action: CrashAction,
+}
+impl CrashStep {
+    /// Some(contents after the step) for steps that change a key, where None means the key is deleted
+    fn new_contents(&self) -> Option<Option<&Vec::<u8>>> {
+        match &self.action {
+            CrashAction::Write(data) => Some(Some(data)),
+            CrashAction::Delete => Some(None),
+            CrashAction::Compact | CrashAction::Rollover => None,
+        }
+    }
+}
+
+/// Generates a random mix of key creations, updates and deletions, spread across `bases`, with a
+/// compaction every `CRASH_COMPACT_INTERVAL` steps and an FSCB rollover every `CRASH_ROLLOVER_INTERVAL`.
+fn crash_workload(rng: &mut StdRng, bases: &[&str], num_steps: usize) -> Vec::<CrashStep> {
+    let mut live = Vec::<(String, String)>::new();
+    // bases whose crash test dictionary exists, and so can be compacted
+    let mut written = Vec::<String>::new();
+    let mut steps = Vec::<CrashStep>::new();
+    for stepnum in 0..num_steps {
+        let (_, data) = gen_key_rng(rng, CRASH_DICT, stepnum, 32, 2 * VPAGE_SIZE);
+        let step = if stepnum % CRASH_COMPACT_INTERVAL == CRASH_COMPACT_INTERVAL - 1 && written.len() != 0 {
+            let basis = written[rng.gen_range(0..written.len())].to_string();
+            CrashStep { basis, key: String::new(), action: CrashAction::Compact }
+        } else if stepnum % CRASH_ROLLOVER_INTERVAL == CRASH_ROLLOVER_INTERVAL - 1 {
+            CrashStep { basis: PDDB_DEFAULT_SYSTEM_BASIS.to_string(), key: String::new(), action: CrashAction::Rollover }
+        } else {
+            let choice = if live.len() == 0 { 0 } else { rng.gen_range(0..3) };
+            match choice {
+                0 => {
+                    let basis = bases[rng.gen_range(0..bases.len())].to_string();
+                    let key = format!("crashkey{}", stepnum);
+                    live.push((basis.to_string(), key.to_string()));
+                    if !written.contains(&basis) {
+                        written.push(basis.to_string());
+                    }
+                    CrashStep { basis, key, action: CrashAction::Write(data) }
+                }
+                1 => {
+                    let (basis, key) = live[rng.gen_range(0..live.len())].clone();
+                    CrashStep { basis, key, action: CrashAction::Write(data) }
+                }
+                _ => {
+                    let (basis, key) = live.swap_remove(rng.gen_range(0..live.len()));
+                    CrashStep { basis, key, action: CrashAction::Delete }
+                }
+            }
+        };
+        steps.push(step);
+    }
+    steps
+}
+
+/// Runs a step to completion. A deletion is followed by a sync, as that is what commits it to disk.
+fn crash_step_run(hw: &mut PddbOs, basis_cache: &mut BasisCache, step: &CrashStep) -> std::io::Result<()> {
+    match &step.action {
+        CrashAction::Write(data) => basis_cache.key_update(hw, CRASH_DICT, &step.key, data, None, None, Some(&step.basis), true),
+        CrashAction::Delete => {
+            basis_cache.key_remove(hw, CRASH_DICT, &step.key, Some(&step.basis), false)?;
+            basis_cache.dict_sync(hw, CRASH_DICT, Some(&step.basis))
+        }
+        CrashAction::Compact => basis_cache.dict_compact(hw, CRASH_DICT, Some(&step.basis)),
+        CrashAction::Rollover => {
+            if basis_cache.fast_space_regenerate(hw) {
+                Ok(())
+            } else {
+                Err(std::io::Error::new(std::io::ErrorKind::OutOfMemory, "no free space for a new FastSpace record"))
+            }
+        }
+    }
+}
+
+/// Simulates a power cycle: drops everything the PDDB holds in RAM, then mounts the system basis and
+/// unlocks the secret basis from whatever is on the disk.
+fn crash_boot(hw: &mut PddbOs, secret: &str, password: &str) -> Result<BasisCache, String> {
+    hw.test_reboot();
+    let mut basis_cache = BasisCache::new();
+    basis_cache.basis_add(hw.pddb_mount().ok_or("system basis did not mount".to_string())?);
+    basis_cache.basis_unlock(hw, secret, password)
+        .map_err(|e| format!("secret basis {} did not unlock: {:?}", secret, e))?;
+    Ok(basis_cache)
+}
+
+/// Reads back a key in full; Ok(None) means it doesn't exist.
+fn crash_read(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis: &str, key: &str) -> Result<Option<Vec::<u8>>, String> {
+    match basis_cache.key_len(hw, CRASH_DICT, key, Some(basis)) {
+        Ok(len) => {
+            let mut data = vec![0u8; len as usize];
+            let readlen = basis_cache.key_read(hw, CRASH_DICT, key, &mut data, None, Some(basis))
+                .map_err(|e| format!("couldn't read {}:{}: {:?}", basis, key, e))?;
+            data.truncate(readlen);
+            Ok(Some(data))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("couldn't look up {}:{}: {:?}", basis, key, e)),
+    }
+}
+
+/// Checks every committed key against its expected contents. The key changed by an interrupted
+/// step, if any, may hold either its contents from before the step, or from after it.
+fn crash_check(hw: &mut PddbOs, basis_cache: &mut BasisCache, committed: &HashMap::<(String, String), Vec::<u8>>,
+    interrupted: Option<(&CrashStep, Option<&Vec::<u8>>)>) -> Result<(), String> {
+    // steps that don't change a key must leave every committed key as it was
+    let interrupted = interrupted.filter(|(step, _)| step.new_contents().is_some());
+    for ((basis, key), expected) in committed.iter() {
+        if let Some((step, _)) = interrupted {
+            if step.basis == *basis && step.key == *key {
+                continue;
+            }
+        }
+        match crash_read(hw, basis_cache, basis, key)? {
+            Some(data) if data == *expected => {},
+            Some(_) => return Err(format!("committed key {}:{} is corrupted", basis, key)),
+            None => return Err(format!("committed key {}:{} is missing", basis, key)),
+        }
+    }
+    if let Some((step, before)) = interrupted {
+        let found = crash_read(hw, basis_cache, &step.basis, &step.key)?;
+        if found.as_ref() != before && Some(found.as_ref()) != step.new_contents() {
+            return Err(format!("interrupted key {}:{} is neither its old nor its new value", step.basis, step.key));
+        }
+    }
+    Ok(())
+}
+
+/// Runs `f`, turning a panic inside the PDDB into an error, so the crash point can be reported.
+fn crash_catch<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(e) => {
+            let reason = e.downcast_ref::<String>().map(|s| s.as_str())
+                .or(e.downcast_ref::<&str>().copied())
+                .unwrap_or("unknown cause");
+            Err(format!("the PDDB panicked: {}", reason))
+        }
+    }
+}
+
+/// crash consistency check: replay a random workload of key creations, updates and deletions across
+/// the system basis and a secret basis, along with dictionary compactions and FSCB rollovers, cutting
+/// the power at every sector write of every step, both cleanly and with a torn write. After each cut,
+/// re-mount and confirm that no basis is lost, every committed key is intact, and the interrupted key
+/// is either in its old or its new state.
+pub(crate) fn crash_test(hw: &mut PddbOs, basis_cache: &mut BasisCache, maybe_num_steps: Option<usize>) {
+    let num_steps = maybe_num_steps.unwrap_or(CRASH_DEFAULT_STEPS);
+    let secret = "crashbasis";
+    let password = "crash password";
+
+    basis_cache.basis_create(hw, secret, password).unwrap();
+    let mut rng = StdRng::seed_from_u64(crash_seed());
+    let steps = crash_workload(&mut rng, &[PDDB_DEFAULT_SYSTEM_BASIS, secret], num_steps);
+    let mut storage = EmuStorage::new();
+    let mut image = storage.as_slice().to_vec();
+    let mut committed = HashMap::<(String, String), Vec::<u8>>::new();
+    for (stepnum, step) in steps.iter().enumerate() {
+        let target = (step.basis.to_string(), step.key.to_string());
+        let before = committed.get(&target).cloned();
+        for &fault in [PowerFault::Abort, PowerFault::Tear].iter() {
+            let mut writes = 0;
+            loop {
+                storage.restore(&image);
+                let mut cache = crash_boot(hw, secret, password).expect("couldn't boot from a committed image");
+                storage.power_fault_arm(fault, writes);
+                let result = crash_catch(|| crash_step_run(hw, &mut cache, step).map_err(|e| format!("{:?}", e)));
+                if !storage.power_restore() {
+                    // the step finished before the power failed, so every crash point has been visited
+                    if let Err(e) = result {
+                        panic!("crash test step {} failed without a power failure: {}", stepnum, e);
+                    }
+                    break;
+                }
+                let outcome = crash_catch(|| {
+                    let mut cache = crash_boot(hw, secret, password)?;
+                    crash_check(hw, &mut cache, &committed, Some((step, before.as_ref())))
+                });
+                if let Err(e) = outcome {
+                    hw.dbg_dump(Some("crash".to_string()));
+                    panic!("crash test step {} ({}:{}), {:?} after {} sector writes: {}",
+                        stepnum, step.basis, step.key, fault, writes, e);
+                }
+                writes += 1;
+            }
+            log::info!("crash test step {}: {} crash points checked with {:?}", stepnum, writes, fault);
+        }
+        // the last pass ran the step to completion; its image is the starting point for the next step
+        match &step.action {
+            CrashAction::Write(data) => {committed.insert(target, data.to_vec());},
+            CrashAction::Delete => {committed.remove(&target);},
+            CrashAction::Compact | CrashAction::Rollover => (),
+        }
+        image = storage.as_slice().to_vec();
+    }
+
+    log::info!("Re-mounting to check the final state of the crash test");
+    let mut remount_cache = crash_boot(hw, secret, password).expect("couldn't boot after the crash test");
+    if let Err(e) = crash_check(hw, &mut remount_cache, &committed, None) {
+        panic!("crash test final state is inconsistent: {}", e);
+    }
+    remount_cache.basis_lock(hw, secret).unwrap();
+    *basis_cache = remount_cache;
+}
+
+/// Exercises the application API in the `pddb` library against this server. The requests are only
+/// answered once the main loop is running, so the test runs on its own thread.
+pub(crate) fn client_api_test() {
+    std::thread::spawn(|| {
+        use std::io::{ErrorKind, Seek, SeekFrom, Write};
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        log::info!("Doing client API test");
+        let mut dict = pddb::PddbDict::create("clienttest", None).expect("couldn't create the client test dictionary");
+
+        // insert creates a key, then overwrites it
+        assert!(!dict.insert("short", b"hello world").unwrap());
+        assert!(dict.insert("short", b"bye").unwrap());
+        assert_eq!(client_read(&mut dict, "short"), b"bye");
+
+        // updates that shrink a value spanning several PddbBufs, and then empty it
+        let long: Vec::<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        assert!(!dict.insert("long", &long).unwrap());
+        assert_eq!(client_read(&mut dict, "long"), long);
+        dict.update("long", &long[..5_000]).unwrap();
+        assert_eq!(client_read(&mut dict, "long"), &long[..5_000]);
+        dict.update("long", &[]).unwrap();
+        assert_eq!(client_read(&mut dict, "long"), b"");
+        assert_eq!(dict.update("missing", b"x").unwrap_err().kind(), ErrorKind::NotFound);
+
+        // a partial overwrite at a seek position, then cut off at the cursor
+        let mut key = dict.get_or_create("seek", None, None::<fn()>).unwrap();
+        key.write_all(b"0123456789").unwrap();
+        key.seek(SeekFrom::Start(2)).unwrap();
+        key.write_all(b"ab").unwrap();
+        key.truncate().unwrap();
+        assert_eq!(key.key_len().unwrap(), 4);
+        drop(key);
+        assert_eq!(client_read(&mut dict, "seek"), b"01ab");
+
+        // the callback fires for a change made through another token, but not for one made through its own
+        let changes = Arc::new(AtomicU32::new(0));
+        let counter = changes.clone();
+        let mut watched = dict.get("short", Some(move || {counter.fetch_add(1, Ordering::SeqCst);})).unwrap()
+            .expect("key went missing");
+        watched.write_all(b"own").unwrap();
+        dict.update("short", b"other").unwrap();
+        for _ in 0..100 {
+            if changes.load(Ordering::SeqCst) != 0 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(changes.load(Ordering::SeqCst), 1, "wrong number of key-changed callbacks");
+        drop(watched);
+
+        // the large key cache limit of the default basis can be set and read back, and a long read stays within it
+        let manager = pddb::PddbBasisManager::new();
+        let big: Vec::<u8> = (0..(VPAGE_SIZE * 6) as u32).map(|i| (i >> 3) as u8).collect();
+        dict.insert("big", &big).unwrap();
+        assert!(manager.large_cache_set_limit(VPAGE_SIZE * 2, None).unwrap() <= VPAGE_SIZE * 2);
+        assert_eq!(client_read(&mut dict, "big"), big);
+        assert!(manager.large_cache_usage(None).unwrap() <= VPAGE_SIZE * 2);
+        assert_eq!(manager.large_cache_set_limit(0, None).unwrap(), 0);
+        manager.large_cache_set_limit(LARGE_CACHE_DEFAULT_LIMIT, None).unwrap();
+        assert_eq!(manager.large_cache_usage(Some("no such basis")).unwrap_err().kind(), ErrorKind::NotFound);
+        dict.remove("big").unwrap();
+
+        // a listing that spans several pages comes back whole and sorted
+        let many: Vec::<String> = (0..LIST_PAGE_MAX * 2 + 3).map(|i| format!("list{:03}", i)).collect();
+        for name in many.iter() {
+            dict.insert(name, name.as_bytes()).unwrap();
+        }
+        let mut expected = many.clone();
+        expected.extend(["long", "seek", "short"].iter().map(|name| name.to_string()));
+        expected.sort();
+        assert_eq!(dict.list().unwrap(), expected);
+        assert!(manager.dict_list(None).unwrap().contains(&"clienttest".to_string()));
+        for name in many.iter() {
+            dict.remove(name).unwrap();
+        }
+        assert_eq!(dict.list().unwrap(), ["long", "seek", "short"]);
+
+        // stat reports the copy of the key in the default basis
+        let stat = dict.stat("short").unwrap();
+        assert_eq!(stat.len, b"other".len() as u64);
+        assert!(stat.reserved >= stat.len);
+        assert!(stat.flags & 1 != 0, "stat reported an invalid key");
+        assert_eq!(Some(&stat.basis), manager.list().unwrap().last());
+        assert_eq!(dict.stat("missing").unwrap_err().kind(), ErrorKind::NotFound);
+
+        dict.remove("short").unwrap();
+        assert!(dict.get("short", None::<fn()>).unwrap().is_none());
+        assert_eq!(dict.remove("short").unwrap_err().kind(), ErrorKind::NotFound);
+        dict.delete().unwrap();
+        log::info!("Client API test done");
+    });
+}
+
+fn client_read(dict: &mut pddb::PddbDict, key: &str) -> Vec::<u8> {
+    use std::io::Read;
+    let mut data = Vec::<u8>::new();
+    dict.get(key, None::<fn()>).unwrap().expect("key went missing").read_to_end(&mut data).unwrap();
+    data
+}
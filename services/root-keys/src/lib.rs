@@ -277,8 +277,8 @@ impl BlockDecrypt for RootKeys {
     }
 }
 
-#[cfg(test)]
-mod bcrypt;
+/// exposed so that other services (such as the PDDB) derive password keys the same way the root keys do
+pub mod bcrypt;
 
 // some short tests to just confirm we're not totally broken.
 #[cfg(test)]
@@ -251,6 +251,28 @@ mod implementation {
 }
 
 
+/// Checks the signatures on authenticated name lookups on behalf of the name server, which can't link
+/// against the Ed25519 code itself. The name server is the only process that can reach this server.
+fn signature_verifier_thread(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
+    use ed25519_dalek::{PublicKey, Signature};
+    use xous_names::api::{SignatureVerify, SIGNATURE_VERIFY_OPCODE};
+    let verifier_sid = xous::SID::from_u32(sid0 as u32, sid1 as u32, sid2 as u32, sid3 as u32);
+    loop {
+        let mut msg = xous::receive_message(verifier_sid).unwrap();
+        if msg.body.id() as u32 != SIGNATURE_VERIFY_OPCODE {
+            log::error!("signature verifier got unknown opcode: {:?}", msg);
+            continue;
+        }
+        let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+        let mut request = buffer.to_original::<SignatureVerify, _>().unwrap();
+        request.valid = match PublicKey::from_bytes(&request.pubkey) {
+            Ok(pubkey) => pubkey.verify_strict(&request.message, &Signature::new(request.signature)).is_ok(),
+            Err(_) => false,
+        };
+        buffer.replace(request).expect("couldn't return signature verification result");
+    }
+}
+
 #[xous::xous_main]
 fn xmain() -> ! {
     use crate::implementation::RootKeys;
@@ -269,6 +291,10 @@ fn xmain() -> ! {
           3. PDDB
     */
     let keys_sid = xns.register_name(api::SERVER_NAME_KEYS, Some(3)).expect("can't register server");
+    // no connections allowed: the name server reaches the verifier through its SID
+    let verifier_sid = xns.register_name(xous_names::api::SERVER_NAME_SIGNATURE_VERIFIER, Some(0)).expect("can't register signature verifier");
+    let (sid0, sid1, sid2, sid3) = verifier_sid.to_u32();
+    xous::create_thread_4(signature_verifier_thread, sid0 as usize, sid1 as usize, sid2 as usize, sid3 as usize).expect("couldn't start signature verifier thread");
 
     let mut keys = RootKeys::new();
     log::info!("Boot FPGA key source: {:?}", keys.fpga_key_source());
@@ -100,6 +100,10 @@ mod keys;     use keys::*;
 mod wlan;     use wlan::*;
 mod jtag_cmd; use jtag_cmd::*;
 mod net_cmd;  use net_cmd::*;
+mod pddb_cmd; use pddb_cmd::*;
+mod names;    use names::*;
+mod ps;       use ps::*;
+mod ipctrace; use ipctrace::*;
 
 //mod fcc;      use fcc::*;
 //mod pds; // dependency of the FCC file
@@ -125,6 +129,8 @@ pub struct CmdEnv {
     keys_cmd: Keys,
     jtag_cmd: JtagCmd,
     net_cmd: NetCmd,
+    pddb_cmd: PddbCmd,
+    names_cmd: Names,
 
     //fcc_cmd: Fcc,
 }
@@ -178,6 +184,8 @@ impl CmdEnv {
             keys_cmd: Keys::new(&xns),
             jtag_cmd: JtagCmd::new(&xns),
             net_cmd: NetCmd::new(&xns),
+            pddb_cmd: PddbCmd::new(),
+            names_cmd: Names::new(&xns),
 
             //fcc_cmd: fcc,
         }
@@ -192,6 +200,8 @@ impl CmdEnv {
         let mut backlight_cmd = Backlight{};
         let mut accel_cmd = Accel{};
         let mut console_cmd = Console{};
+        let mut ps_cmd = Ps{};
+        let mut ipctrace_cmd = IpcTrace{};
         let commands: &mut [& mut dyn ShellCmdApi] = &mut [
             ///// 4. add your command to this array, so that it can be looked up and dispatched
             &mut echo_cmd,
@@ -217,6 +227,10 @@ impl CmdEnv {
             &mut wlan_cmd,
             &mut self.jtag_cmd,
             &mut self.net_cmd,
+            &mut self.pddb_cmd,
+            &mut self.names_cmd,
+            &mut ps_cmd,
+            &mut ipctrace_cmd,
 
             //&mut self.fcc_cmd,
         ];
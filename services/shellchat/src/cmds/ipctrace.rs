@@ -0,0 +1,67 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+
+#[derive(Debug)]
+pub struct IpcTrace {
+}
+
+impl<'a> ShellCmdApi<'a> for IpcTrace {
+    cmd_api!(ipctrace); // inserts boilerplate for command API
+
+    fn process(&mut self, _args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+
+        let mut events = std::vec::Vec::new();
+        loop {
+            let seq = events.last().map(|e: &xous::IpcTraceEvent| e.seq + 1).unwrap_or(0);
+            match xous::ipc_trace_event(seq) {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => break,
+                Err(xous::Error::UnhandledSyscall) => {
+                    write!(ret, "ipctrace: the kernel was built without the ipc-trace feature").unwrap();
+                    return Ok(Some(ret));
+                }
+                Err(e) => {
+                    write!(ret, "Couldn't read the IPC trace: {:?}", e).unwrap();
+                    return Ok(Some(ret));
+                }
+            }
+        }
+
+        // the full trace goes to the log, where decode-ipc-trace can pick it up
+        for raw_pid in 1..=u8::MAX {
+            let pid = xous::PID::new(raw_pid).unwrap();
+            let mut name_bytes = [0u8; 64];
+            if let Ok(name_len) = xous::process_name(pid, &mut name_bytes) {
+                let name = core::str::from_utf8(&name_bytes[..name_len.min(name_bytes.len())]).unwrap_or("?");
+                log::info!("process {} {}", pid, name);
+            }
+        }
+        for event in events.iter() {
+            log::info!("event {}", event);
+        }
+
+        // only the most recent events fit on the screen
+        write!(ret, "{} events, all are in the log", events.len()).unwrap();
+        let mut lines = std::vec::Vec::new();
+        let mut len = ret.len();
+        for event in events.iter().rev() {
+            let peer = match event.peer {
+                Some(peer) => std::format!("{}", peer),
+                None => std::string::String::from("-"),
+            };
+            let line = std::format!("\n{} {} {}:{}>{} op{} {}b",
+                event.seq, event.kind, event.pid, event.tid, peer, event.opcode, event.len);
+            if len + line.len() > 1024 {
+                break;
+            }
+            len += line.len();
+            lines.push(line);
+        }
+        for line in lines.iter().rev() {
+            write!(ret, "{}", line).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+
+pub struct Names {
+    token: Option<[u32; 4]>,
+}
+impl Names {
+    pub fn new(xns: &xous_names::XousNames) -> Names {
+        // the token can only be claimed once, so grab it while we are still early in the boot
+        let token = match xns.claim_introspection() {
+            Ok(token) => Some(token),
+            Err(e) => {
+                log::warn!("couldn't claim name server introspection token: {:?}", e);
+                None
+            }
+        };
+        Names {
+            token,
+        }
+    }
+}
+
+impl<'a> ShellCmdApi<'a> for Names {
+    cmd_api!(names); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let filter = args.as_str().unwrap().trim();
+
+        let token = match self.token {
+            Some(token) => token,
+            None => {
+                write!(ret, "names: introspection token is held by another process").unwrap();
+                return Ok(Some(ret));
+            }
+        };
+        let servers = match env.xns.list_servers(&token) {
+            Ok(servers) => servers,
+            Err(e) => {
+                write!(ret, "Couldn't list servers: {:?}", e).unwrap();
+                return Ok(Some(ret));
+            }
+        };
+        let matching = servers.iter().filter(|s| s.name.contains(filter));
+        write!(ret, "name conns/limit(+auth) owner").unwrap();
+        for server in matching {
+            let limit = match server.conn_limit {
+                Some(limit) => std::format!("{}", limit),
+                None => std::string::String::from("-"),
+            };
+            let auth = if server.auth_conns > 0 { std::format!("+{}", server.auth_conns) } else { std::string::String::new() };
+            let owner = match (server.owner, &server.owner_name) {
+                (Some(pid), Some(pname)) => std::format!("{}:{}", pid, pname),
+                (Some(pid), None) => std::format!("{}", pid),
+                (None, _) => std::string::String::from("?"),
+            };
+            let line = std::format!("\n{} {}/{}{} {}", server.name, server.current_conns, limit, auth, owner);
+            // leave room for the ellipsis
+            if ret.len() + line.len() > 1024 - 4 {
+                write!(ret, "...").unwrap();
+                break;
+            }
+            write!(ret, "{}", line).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
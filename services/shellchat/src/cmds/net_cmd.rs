@@ -4,6 +4,7 @@ use net::{Duration, XousServerId, NetPingCallback};
 use xous::MessageEnvelope;
 use num_traits::*;
 use std::net::{SocketAddr, IpAddr};
+use std::io::{Read, Write as IoWrite};
 
 pub struct NetCmd {
     udp: Option<net::UdpSocket>,
@@ -13,6 +14,7 @@ pub struct NetCmd {
     udp_count: u32,
     dns: dns::Dns,
     ping: Option<net::Ping>,
+    pcap: net::Pcap,
 }
 impl NetCmd {
     pub fn new(xns: &xous_names::XousNames) -> Self {
@@ -24,6 +26,7 @@ impl NetCmd {
             udp_count: 0,
             dns: dns::Dns::new(&xns).unwrap(),
             ping: None,
+            pcap: net::Pcap::new(&xns).unwrap(),
         }
     }
 }
@@ -47,7 +50,7 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
 
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
-        let helpstring = "net [udp [port]] [udpclose] [udpclone] [udpcloneclose] [ping [host] [count]]";
+        let helpstring = "net [udp [port]] [udpclose] [udpclone] [udpcloneclose] [ping [host] [count]] [tcpget [host] [port]] [pcap start [bytes]|stop|dump]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -158,6 +161,66 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                         write!(ret, "Missing host: net ping [host] [count]").unwrap();
                     }
                 }
+                // fetches the first line of a web page, to check that TCP works end to end
+                "tcpget" => {
+                    if let Some(name) = tokens.next() {
+                        let port = if let Some(tok_str) = tokens.next() {
+                            if let Ok(n) = tok_str.parse::<u16>() { n } else { 80 }
+                        } else {
+                            80
+                        };
+                        match self.dns.lookup(name) {
+                            Ok(ipaddr) => {
+                                let addr = SocketAddr::new(IpAddr::from(ipaddr), port);
+                                match net::TcpStream::connect_timeout(&addr, Duration::from_millis(10_000)) {
+                                    Ok(mut stream) => {
+                                        stream.set_read_timeout(Some(Duration::from_millis(10_000))).unwrap();
+                                        let request = format!("GET / HTTP/1.0\r\nHost: {}\r\n\r\n", name);
+                                        let mut reply = [0u8; 128];
+                                        match stream.write_all(request.as_bytes()).and_then(|_| stream.read(&mut reply)) {
+                                            Ok(len) => {
+                                                let reply = std::str::from_utf8(&reply[..len]).unwrap_or("(not UTF-8)");
+                                                write!(ret, "{:?} replied: {}", addr, reply.lines().next().unwrap_or("")).unwrap();
+                                            }
+                                            Err(e) => write!(ret, "TCP error talking to {:?}: {:?}", addr, e).unwrap(),
+                                        }
+                                    }
+                                    Err(e) => write!(ret, "Can't connect to {:?}: {:?}", addr, e).unwrap(),
+                                }
+                            }
+                            Err(e) => {
+                                write!(ret, "Can't connect, DNS lookup error: {:?}", e).unwrap();
+                            }
+                        }
+                    } else {
+                        write!(ret, "Missing host: net tcpget [host] [port]").unwrap();
+                    }
+                }
+                // captures raw frames; `dump` writes them to the log as hex-encoded pcap
+                "pcap" => {
+                    match tokens.next() {
+                        Some("start") => {
+                            let limit = tokens.next().and_then(|tok_str| tok_str.parse::<usize>().ok());
+                            match self.pcap.start(limit) {
+                                Ok(limit) => write!(ret, "Capturing the last {} bytes of frames", limit).unwrap(),
+                                Err(e) => write!(ret, "Can't start capture: {:?}", e).unwrap(),
+                            }
+                        }
+                        Some("stop") => {
+                            match self.pcap.stop() {
+                                Ok(stats) => write!(ret, "Capture stopped with {} frames ({} overwritten)", stats.frames, stats.overwritten).unwrap(),
+                                Err(e) => write!(ret, "Can't stop capture: {:?}", e).unwrap(),
+                            }
+                        }
+                        Some("dump") => {
+                            match self.pcap.dump() {
+                                Ok(stats) => write!(ret, "Dumped {} frames to the log ({} overwritten)", stats.frames, stats.overwritten).unwrap(),
+                                Err(e) => write!(ret, "Can't dump capture: {:?}", e).unwrap(),
+                            }
+                        }
+                        _ => write!(ret, "net pcap start [bytes]|stop|dump").unwrap(),
+                    }
+                }
                 _ => {
                     write!(ret, "{}", helpstring).unwrap();
                 }
@@ -0,0 +1,94 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+
+pub struct PddbCmd {
+    manager: pddb::PddbBasisManager,
+}
+impl PddbCmd {
+    pub fn new() -> PddbCmd {
+        PddbCmd {
+            manager: pddb::PddbBasisManager::new(),
+        }
+    }
+}
+
+/// writes out a list of names, stopping with an ellipsis if the list doesn't fit in the response
+fn write_names(ret: &mut String::<1024>, names: &[std::string::String]) {
+    use core::fmt::Write;
+    for name in names.iter() {
+        // leave room for the ellipsis
+        if ret.len() + name.len() + 1 > 1024 - 4 {
+            write!(ret, "...").unwrap();
+            return;
+        }
+        write!(ret, "\n{}", name).unwrap();
+    }
+}
+
+impl<'a> ShellCmdApi<'a> for PddbCmd {
+    cmd_api!(pddb); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "pddb options: basis, dicts [basis], keys <dict>, stat <dict>:<key>";
+
+        let mut tokens = args.as_str().unwrap().splitn(2, ' ');
+        // dictionary and key names may contain spaces, so arguments run to the end of the line
+        let arg = tokens.clone().nth(1).unwrap_or("").trim();
+
+        if let Some(sub_cmd) = tokens.next() {
+            match sub_cmd {
+                "basis" => match self.manager.list() {
+                    Ok(list) => {
+                        write!(ret, "Open basis, lowest priority first:").unwrap();
+                        write_names(&mut ret, &list);
+                    }
+                    Err(e) => write!(ret, "Couldn't list basis: {:?}", e).unwrap(),
+                }
+                "dicts" => {
+                    let basis = if arg.len() > 0 { Some(arg) } else { None };
+                    match self.manager.dict_list(basis) {
+                        Ok(list) => {
+                            write!(ret, "{} dictionaries:", list.len()).unwrap();
+                            write_names(&mut ret, &list);
+                        }
+                        Err(e) => write!(ret, "Couldn't list dictionaries: {:?}", e).unwrap(),
+                    }
+                }
+                "keys" => {
+                    if arg.len() == 0 {
+                        write!(ret, "usage: pddb keys <dict>").unwrap();
+                    } else {
+                        match pddb::PddbDict::open(arg, None).and_then(|dict| dict.list()) {
+                            Ok(list) => {
+                                write!(ret, "{} keys in {}:", list.len(), arg).unwrap();
+                                write_names(&mut ret, &list);
+                            }
+                            Err(e) => write!(ret, "Couldn't list keys in {}: {:?}", arg, e).unwrap(),
+                        }
+                    }
+                }
+                "stat" => {
+                    if let Some((dictname, keyname)) = arg.split_once(':') {
+                        match pddb::PddbDict::open(dictname, None).and_then(|dict| dict.stat(keyname)) {
+                            Ok(stat) => {
+                                write!(ret, "{}:{} in basis {}\nlen: {}\nreserved: {}\nage: {}\nflags: 0x{:x}",
+                                    dictname, keyname, stat.basis, stat.len, stat.reserved, stat.age, stat.flags).unwrap();
+                            }
+                            Err(e) => write!(ret, "Couldn't stat {}:{}: {:?}", dictname, keyname, e).unwrap(),
+                        }
+                    } else {
+                        write!(ret, "usage: pddb stat <dict>:<key>").unwrap();
+                    }
+                }
+                _ => {
+                    write!(ret, "{}", helpstring).unwrap();
+                }
+            }
+        } else {
+            write!(ret, "{}", helpstring).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
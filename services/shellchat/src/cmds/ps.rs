@@ -0,0 +1,55 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+
+#[derive(Debug)]
+pub struct Ps {
+}
+
+impl<'a> ShellCmdApi<'a> for Ps {
+    cmd_api!(ps); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let filter = args.as_str().unwrap().trim();
+
+        // hosted mode has no RAM to report
+        if let Ok((used, total)) = xous::ram_usage() {
+            if total > 0 {
+                writeln!(ret, "RAM {}k/{}k", used / 1024, total / 1024).unwrap();
+            }
+        }
+        write!(ret, "pid ppid state thr ram srv conn irq name").unwrap();
+        for raw_pid in 1..=u8::MAX {
+            let pid = xous::PID::new(raw_pid).unwrap();
+            let info = match xous::process_info(pid) {
+                Ok(info) => info,
+                Err(xous::Error::ProcessNotFound) => continue,
+                Err(e) => {
+                    write!(ret, "\nCouldn't inspect PID {}: {:?}", pid, e).unwrap();
+                    break;
+                }
+            };
+            let mut name_bytes = [0u8; 64];
+            let name_len = xous::process_name(pid, &mut name_bytes).unwrap_or(0).min(name_bytes.len());
+            let name = core::str::from_utf8(&name_bytes[..name_len]).unwrap_or("?");
+            if !name.contains(filter) {
+                continue;
+            }
+            let ram = match xous::page_quota(pid) {
+                Ok((_, Some(limit))) => std::format!("{}k/{}k", info.ram_used / 1024, limit * 4),
+                _ => std::format!("{}k", info.ram_used / 1024),
+            };
+            let line = std::format!("\n{} {} {} {} {} {} {} {:x} {}",
+                info.pid, info.ppid, info.status, info.ready_threads.count_ones(),
+                ram, info.servers, info.connections, info.irqs, name);
+            // leave room for the ellipsis
+            if ret.len() + line.len() > 1024 - 4 {
+                write!(ret, "...").unwrap();
+                break;
+            }
+            write!(ret, "{}", line).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
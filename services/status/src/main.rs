@@ -477,6 +477,17 @@ fn xmain() -> ! {
                         ((latest_activity as f32) / (period as f32)) * 100.0
                     )
                     .expect("|status: can't write string");
+                    // the kernel reports a total of 0 when there's no RAM to account for, e.g. in hosted mode
+                    if let Ok((ram_used, ram_total)) = xous::ram_usage() {
+                        if ram_total > 0 {
+                            write!(
+                                &mut uptime_tv,
+                                " RAM {}%",
+                                (ram_used / 1024) * 100 / (ram_total / 1024)
+                            )
+                            .expect("|status: can't write string");
+                        }
+                    }
                     gam.post_textview(&mut uptime_tv)
                         .expect("|status: can't draw uptime");
                     needs_redraw = true;
@@ -1,6 +1,47 @@
-#[allow(dead_code)]
 pub const AUTHENTICATE_TIMEOUT: u32 = 10_000; // time in ms that a process has to respond to an authentication request
 
+/// Name under which the signature verifier for authenticated lookups is registered. The verifier must be
+/// registered by a trusted, boot-time process with a connection limit of 0: the name server reaches it
+/// directly through its SID, and nobody else may connect to it.
+pub const SERVER_NAME_SIGNATURE_VERIFIER: &str = "_Name server signature verifier_";
+/// The only opcode the signature verifier has to handle; its payload is a lent `SignatureVerify`.
+pub const SIGNATURE_VERIFY_OPCODE: u32 = 0;
+
+/// Length of the message that is signed to answer an `AuthenticateRequest`
+pub const AUTHENTICATE_MESSAGE_LEN: usize = 64 + 16;
+
+/// The message signed in response to a challenge: the requested server name, zero-padded to 64 bytes,
+/// followed by the challenge words in little-endian order. Binding the name keeps a response for one
+/// server from being replayed against another.
+pub fn authenticate_message(name: &str, challenge: &[u32; 4]) -> [u8; AUTHENTICATE_MESSAGE_LEN] {
+    let mut message = [0u8; AUTHENTICATE_MESSAGE_LEN];
+    for (dst, &src) in message[..64].iter_mut().zip(name.as_bytes()) {
+        *dst = src;
+    }
+    for (dst, word) in message[64..].chunks_exact_mut(4).zip(challenge.iter()) {
+        dst.copy_from_slice(&word.to_le_bytes());
+    }
+    message
+}
+
+/// The ID of an Ed25519 public key is its first 160 bits. Compressed Edwards points are uniformly
+/// distributed, so the prefix is as good a tag as a hash of the key would be.
+pub fn pubkey_id(pubkey: &[u8; 32]) -> [u8; 20] {
+    let mut id = [0u8; 20];
+    id.copy_from_slice(&pubkey[..20]);
+    id
+}
+
+/// Request lent by the name server to the signature verifier. The verifier sets `valid` to `true`
+/// if and only if `signature` is a valid (strict) Ed25519 signature of `message` under `pubkey`.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct SignatureVerify {
+    pub pubkey: [u8; 32],
+    pub message: [u8; AUTHENTICATE_MESSAGE_LEN],
+    pub signature: [u8; 64],
+    pub valid: bool,
+}
+
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub(crate) enum Opcode {
     /// Create a new server with the given name and return its SID.
@@ -15,6 +56,10 @@ pub(crate) enum Opcode {
     Disconnect,
     /// indicates if all inherentely trusted slots have been occupied. Should not run untrusted code until this is the case.
     TrustedInitDone,
+    /// claim the token that grants access to `ListServers`. Only the first claim succeeds.
+    RegisterIntrospectionToken,
+    /// list the registered servers and their connection state, given the introspection token
+    ListServers,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -35,6 +80,8 @@ pub(crate) enum Return {
 pub(crate) struct Registration {
     pub name: xous_ipc::String<64>,
     pub conn_limit: Option<u32>,
+    /// Ed25519 public key that callers may authenticate against once the unauthenticated slots are used up
+    pub auth_pubkey: Option<[u8; 32]>,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -47,7 +94,8 @@ pub(crate) struct Disconnect {
 pub(crate) struct AuthenticatedLookup {
     pub name: xous_ipc::String<64>,
     pub pubkey_id: [u8; 20], // 160-bit pubkey ID encoded in network order (big endian)
-    pub response: [u32; 8],
+    pub challenge: [u32; 4], // a copy of the challenge being answered
+    pub response: [u8; 64],  // Ed25519 signature over `authenticate_message(name, challenge)`
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -57,6 +105,49 @@ pub(crate) struct AuthenticateRequest {
     pub challenge: [u32; 4],
 }
 
+/// Number of servers described by one page of a `ServerList`
+pub(crate) const SERVER_LIST_PAGE: usize = 24;
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct ServerListEntry {
+    pub name: xous_ipc::String<64>,
+    pub conn_limit: Option<u32>,
+    pub current_conns: u32,
+    pub auth_conns: u32,
+    /// PID of the process that registered the server, or 0 if unknown
+    pub owner: u8,
+}
+
+/// One page of the server listing, sorted by name. The caller sets `start` to the index of the
+/// first server it wants, and keeps requesting pages until it has `total` entries.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct ServerList {
+    pub token: [u32; 4],
+    pub start: u32,
+    pub entries: [ServerListEntry; SERVER_LIST_PAGE],
+    /// number of valid entries in `entries`
+    pub num: u32,
+    /// total number of registered servers
+    pub total: u32,
+    /// false if the token was not accepted
+    pub granted: bool,
+}
+
+/// A registered server, as reported by `XousNames::list_servers()`
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct ServerInfo {
+    pub name: std::string::String,
+    /// `None` if the server accepts an unlimited number of connections
+    pub conn_limit: Option<u32>,
+    pub current_conns: u32,
+    pub auth_conns: u32,
+    /// the process that registered the server
+    pub owner: Option<xous::PID>,
+    /// name of the owning process, if the kernel knows it
+    pub owner_name: Option<std::string::String>,
+}
+
 //////////////////////////////////////////////////////////////////////////////////////////////
 // We keep XousServerName around because want to be able to index off the server name, without
 // burdening the Kernel String type with the Hash32 methods
@@ -4,7 +4,7 @@
 
 pub mod api;
 
-use api::Disconnect;
+use api::{AuthenticatedLookup, Disconnect, ServerInfo, ServerList, ServerListEntry};
 use core::fmt::Write;
 use num_traits::ToPrimitive;
 use xous_ipc::{Buffer, String};
@@ -56,10 +56,32 @@ impl XousNames {
         &self,
         name: &str,
         max_conns: Option<u32>,
+    ) -> Result<xous::SID, xous::Error> {
+        self.register(name, max_conns, None)
+    }
+
+    /// Like `register_name`, but once `max_conns` unauthenticated connections have been handed out,
+    /// further lookups are answered with a challenge that must be signed with the private half of
+    /// `pubkey` (an Ed25519 public key) to obtain a connection.
+    pub fn register_name_with_auth(
+        &self,
+        name: &str,
+        max_conns: Option<u32>,
+        pubkey: &[u8; 32],
+    ) -> Result<xous::SID, xous::Error> {
+        self.register(name, max_conns, Some(*pubkey))
+    }
+
+    fn register(
+        &self,
+        name: &str,
+        max_conns: Option<u32>,
+        auth_pubkey: Option<[u8; 32]>,
     ) -> Result<xous::SID, xous::Error> {
         let mut registration = api::Registration {
             name: String::<64>::new(),
             conn_limit: max_conns,
+            auth_pubkey,
         };
         // could also do String::from_str() but in this case we want things to fail if the string is too long.
         write!(registration.name, "{}", name).expect("name probably too long");
@@ -93,10 +115,53 @@ impl XousNames {
 
         match buf.to_original().unwrap() {
             api::Return::CID((cid, token)) => Ok((cid, token)),
-            // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
+
+    /// Authenticated counterpart of `request_connection_with_token`. If the name server asks for
+    /// authentication, `sign` is called with the ID of the key the server expects and the message
+    /// to sign, and must return the Ed25519 signature of that message (or `None` to give up).
+    /// Authenticated connections always carry a token; hand it to `disconnect_with_token` once the
+    /// connection is no longer needed, so the name server stops counting it.
+    pub fn request_authenticated_connection_with_token<F>(
+        &self,
+        name: &str,
+        sign: F,
+    ) -> Result<(xous::CID, Option<[u32; 4]>), xous::Error>
+    where
+        F: FnOnce(&[u8; 20], &[u8; api::AUTHENTICATE_MESSAGE_LEN]) -> Option<[u8; 64]>,
+    {
+        let mut lookup_name = xous_ipc::String::<64>::new();
+        write!(lookup_name, "{}", name).expect("name problably too long");
+        let mut buf = Buffer::into_buf(lookup_name).or(Err(xous::Error::InternalError))?;
+
+        buf.lend_mut(self.conn, api::Opcode::Lookup.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+
+        let request = match buf.to_original().unwrap() {
+            api::Return::CID((cid, token)) => return Ok((cid, token)),
+            api::Return::AuthenticateRequest(request) => request,
+            _ => return Err(xous::Error::ServerNotFound),
+        };
+        let message = api::authenticate_message(name, &request.challenge);
+        let response = sign(&request.pubkey_id, &message).ok_or(xous::Error::AccessDenied)?;
+        let auth_lookup = AuthenticatedLookup {
+            name: request.name,
+            pubkey_id: request.pubkey_id,
+            challenge: request.challenge,
+            response,
+        };
+        let mut buf = Buffer::into_buf(auth_lookup).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::AuthenticatedLookup.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+
+        match buf.to_original().unwrap() {
+            api::Return::CID((cid, token)) => Ok((cid, token)),
+            _ => Err(xous::Error::AccessDenied),
+        }
+    }
     pub fn disconnect_with_token(&self, name: &str, token: [u32; 4]) -> Result<(), xous::Error> {
         let disconnect = Disconnect {
             name: String::<64>::from_str(name),
@@ -123,7 +188,7 @@ impl XousNames {
 
         match buf.to_original().unwrap() {
             api::Return::CID((cid, _)) => Ok(cid),
-            // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
@@ -164,10 +229,81 @@ impl XousNames {
         }
     }
 
-    // todo:
-    // pub fn authenticated_connection(&self, name: &str, key: Authkey)
-    // this function will create an authenticated connection, if such are allowed
-    // it's intended for use by dynamically-loaded third-party apps. As of Xous 0.8 this isn't supported, so it's just a "todo"
+    /// Claims the token that grants access to `list_servers()`. Only the first claim made after boot
+    /// succeeds, so this should be called early by the trusted process that will do the listing.
+    pub fn claim_introspection(&self) -> Result<[u32; 4], xous::Error> {
+        let token = xous::create_server_id()?.to_array();
+        let response = xous::send_message(
+            self.conn,
+            xous::Message::new_blocking_scalar(
+                api::Opcode::RegisterIntrospectionToken.to_usize().unwrap(),
+                token[0] as usize,
+                token[1] as usize,
+                token[2] as usize,
+                token[3] as usize,
+            ),
+        )
+        .expect("couldn't claim introspection token");
+        match response {
+            xous::Result::Scalar1(1) => Ok(token),
+            xous::Result::Scalar1(_) => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+
+    /// Lists the registered servers, sorted by name, along with their connection counts and the process
+    /// that registered them. Requires the token returned by `claim_introspection()`.
+    pub fn list_servers(&self, token: &[u32; 4]) -> Result<Vec<ServerInfo>, xous::Error> {
+        let mut servers = Vec::new();
+        loop {
+            let request = ServerList {
+                token: *token,
+                start: servers.len() as u32,
+                entries: [ServerListEntry {
+                    name: String::<64>::new(),
+                    conn_limit: None,
+                    current_conns: 0,
+                    auth_conns: 0,
+                    owner: 0,
+                }; api::SERVER_LIST_PAGE],
+                num: 0,
+                total: 0,
+                granted: false,
+            };
+            let mut buf = Buffer::into_buf(request).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.conn, api::Opcode::ListServers.to_u32().unwrap())
+                .or(Err(xous::Error::InternalError))?;
+            let page = buf.to_original::<ServerList, _>().unwrap();
+            if !page.granted {
+                return Err(xous::Error::AccessDenied);
+            }
+            for entry in page.entries[..page.num as usize].iter() {
+                let owner = xous::PID::new(entry.owner);
+                let owner_name = owner.and_then(|pid| {
+                    let mut name = [0u8; 64];
+                    match xous::process_name(pid, &mut name) {
+                        Ok(len) if len > 0 => Some(
+                            std::string::String::from_utf8_lossy(&name[..len.min(name.len())])
+                                .into_owned(),
+                        ),
+                        _ => None,
+                    }
+                });
+                servers.push(ServerInfo {
+                    name: std::string::String::from(entry.name.as_str().unwrap_or("")),
+                    conn_limit: entry.conn_limit,
+                    current_conns: entry.current_conns,
+                    auth_conns: entry.auth_conns,
+                    owner,
+                    owner_name,
+                });
+            }
+            // the table can change between pages; stop rather than spin if it shrinks under us
+            if page.num == 0 || servers.len() as u32 >= page.total {
+                return Ok(servers);
+            }
+        }
+    }
 }
 
 use core::sync::atomic::{AtomicU32, Ordering};
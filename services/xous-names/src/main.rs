@@ -73,14 +73,16 @@ I'll take safety over speed in this case.
 Eventually, we shall endeavor to remove Heapless entirely, once we have a `libstd` in place
 and we can use heap-allocated Rust primitives...
 */
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 struct Connection {
     pub sid: xous::SID,
     pub current_conns: u32, // number of unauthenticated (inherentely trusted) connections
     pub max_conns: Option<u32>, // if None, unlimited connections allowed
     pub allow_authenticate: bool,
-    pub auth_conns: u32,         // number of authenticated connections
+    pub auth_tokens: Vec<[u32; 4]>, // one disconnect token per live authenticated connection
     pub token: Option<[u32; 4]>, // a random number that must be presented to allow for disconnection for single-connection servers
+    pub auth_pubkey: Option<[u8; 32]>, // Ed25519 key that authenticated connections must prove knowledge of
+    pub owner: Option<xous::PID>, // the process that registered the server
 }
 #[derive(Debug)]
 struct CheckedHashMap {
@@ -97,6 +99,8 @@ impl CheckedHashMap {
         name: XousServerName,
         sid: xous::SID,
         max_conns: Option<u32>,
+        auth_pubkey: Option<[u8; 32]>,
+        owner: Option<xous::PID>,
     ) -> Result<(), xous::Error> {
         let token = if max_conns == Some(1) {
             // for the special case of 1-connection servers, provision a one-time use token for disconnects
@@ -114,9 +118,11 @@ impl CheckedHashMap {
                 sid,
                 current_conns: 0,
                 max_conns,
-                allow_authenticate: false, // for now, we don't support authenticated connections
-                auth_conns: 0,
+                allow_authenticate: auth_pubkey.is_some(),
+                auth_tokens: Vec::new(),
                 token,
+                auth_pubkey,
+                owner,
             },
         );
         Ok(())
@@ -168,6 +174,27 @@ impl CheckedHashMap {
         }
         (None, None)
     }
+    /// Returns the key that a caller has to authenticate against, if the server accepts authenticated connections
+    pub fn auth_pubkey(&self, name: &XousServerName) -> Option<[u8; 32]> {
+        match self.map.get(name) {
+            Some(entry) if entry.allow_authenticate => entry.auth_pubkey,
+            _ => None,
+        }
+    }
+    /// Authenticated connections are not counted against `max_conns`; the caller must have been verified already.
+    /// `token` is handed back to the caller, who presents it to `disconnect_with_token` when it's done.
+    pub fn connect_authenticated(
+        &mut self,
+        name: &XousServerName,
+        token: [u32; 4],
+    ) -> Option<&xous::SID> {
+        let entry = self.map.get_mut(name)?;
+        if !entry.allow_authenticate {
+            return None;
+        }
+        entry.auth_tokens.push(token);
+        Some(&entry.sid)
+    }
     pub fn trusted_init_done(&self) -> bool {
         let mut trusted_done = true;
         for (name, entry) in self.map.iter() {
@@ -203,7 +230,8 @@ impl CheckedHashMap {
         None
     }
     // this is a safer version of disconnect. we track servers that allow exactly one connection at a time
-    // and give them a one-time-use token that a connector can use to disconnect.
+    // and give them a one-time-use token that a connector can use to disconnect. Authenticated connections
+    // each get a token of their own, which is retired on disconnect.
     pub fn disconnect_with_token(&mut self, name: &XousServerName, token: [u32; 4]) -> bool {
         if let Some(entry) = self.map.get_mut(name) {
             if let Some(old_token) = entry.token {
@@ -218,11 +246,104 @@ impl CheckedHashMap {
                     return true;
                 }
             }
+            if let Some(index) = entry.auth_tokens.iter().position(|t| *t == token) {
+                entry.auth_tokens.swap_remove(index);
+                return true;
+            }
         }
         false
     }
 }
 
+/// A challenge handed out in response to a `Lookup`, waiting for the matching `AuthenticatedLookup`.
+/// Each process holds at most one outstanding challenge, so the table is bounded by the number of processes.
+struct PendingChallenge {
+    name: XousServerName,
+    challenge: [u32; 4],
+    issued_ms: u64,
+}
+
+/// The ticktimer links against this crate, so we can't use its library; talk to its well-known SID
+/// directly instead. Opcode 0 is `ElapsedMs`.
+fn elapsed_ms(ticktimer_conn: &mut Option<xous::CID>) -> u64 {
+    let conn = *ticktimer_conn.get_or_insert_with(|| {
+        xous::connect(xous::SID::from_bytes(b"ticktimer-server").unwrap())
+            .expect("couldn't connect to ticktimer")
+    });
+    match xous::send_message(conn, xous::Message::new_blocking_scalar(0, 0, 0, 0, 0))
+        .expect("couldn't query ticktimer")
+    {
+        xous::Result::Scalar2(lower, upper) => lower as u64 | ((upper as u64) << 32),
+        other => panic!("unexpected ticktimer response: {:?}", other),
+    }
+}
+
+/// The signature verifier is pinned by its first registration, which must come before trusted init
+/// is seen to be done, and must allow no connections other than ours. Any later attempt on the name
+/// is refused, so a process started after boot can't stand in for the verifier, even if the real
+/// one has gone away.
+fn may_register(
+    name: &XousServerName,
+    conn_limit: Option<u32>,
+    verifier_sid: Option<xous::SID>,
+    trusted_init_seen: bool,
+) -> bool {
+    if *name != XousServerName::from_str(SERVER_NAME_SIGNATURE_VERIFIER) {
+        return true;
+    }
+    verifier_sid.is_none() && !trusted_init_seen && conn_limit == Some(0)
+}
+
+/// The name server can't link against the Ed25519 code (the curve25519 engine depends on us), so
+/// signature checks are delegated to the server pinned as `SERVER_NAME_SIGNATURE_VERIFIER`.
+/// Anything short of a positive answer from that verifier counts as a bad signature.
+fn verify_signature(
+    verifier_sid: Option<xous::SID>,
+    verifier_conn: &mut Option<xous::CID>,
+    pubkey: [u8; 32],
+    message: [u8; AUTHENTICATE_MESSAGE_LEN],
+    signature: [u8; 64],
+) -> bool {
+    let verifier_sid = match verifier_sid {
+        Some(sid) => sid,
+        None => {
+            error!("no trusted signature verifier is registered, can't authenticate");
+            return false;
+        }
+    };
+    let cid = match *verifier_conn {
+        Some(cid) => cid,
+        None => match xous::connect(verifier_sid) {
+            Ok(cid) => {
+                *verifier_conn = Some(cid);
+                cid
+            }
+            Err(e) => {
+                error!("couldn't connect to the signature verifier: {:?}", e);
+                return false;
+            }
+        },
+    };
+    let request = SignatureVerify {
+        pubkey,
+        message,
+        signature,
+        valid: false,
+    };
+    let mut buf = match Buffer::into_buf(request) {
+        Ok(buf) => buf,
+        Err(_) => return false,
+    };
+    if buf.lend_mut(cid, SIGNATURE_VERIFY_OPCODE).is_err() {
+        error!("signature verifier didn't respond");
+        return false;
+    }
+    match buf.to_original::<SignatureVerify, _>() {
+        Ok(result) => result.valid,
+        Err(_) => false,
+    }
+}
+
 #[xous::xous_main]
 fn xmain() -> ! {
     use implementation::*;
@@ -238,6 +359,15 @@ fn xmain() -> ! {
     // this limits the number of available servers to be requested to 128...!
     //let mut name_table = FnvIndexMap::<XousServerName, xous::SID, 128>::new();
     let mut name_table = CheckedHashMap::new();
+    let mut pending_challenges = HashMap::<xous::PID, PendingChallenge>::new();
+    let mut ticktimer_conn: Option<xous::CID> = None;
+    // the signature verifier, fixed at its first registration during boot
+    let mut verifier_sid: Option<xous::SID> = None;
+    let mut verifier_conn: Option<xous::CID> = None;
+    // set the first time a caller is told trusted init is done, and never cleared
+    let mut trusted_init_seen = false;
+    // only the first process to claim it may list the servers; this should be a trusted process started at boot
+    let mut introspection_token: Option<[u32; 4]> = None;
 
     info!("started");
     loop {
@@ -258,12 +388,29 @@ fn xmain() -> ! {
                 let response: api::Return;
 
                 log::trace!("registration request for '{}'", name);
-                if !name_table.contains_key(&name) {
+                if !name_table.contains_key(&name)
+                    && may_register(
+                        &name,
+                        registration.conn_limit,
+                        verifier_sid,
+                        trusted_init_seen,
+                    )
+                {
                     let new_sid =
                         xous::create_server_id().expect("create server failed, maybe OOM?");
                     name_table
-                        .insert(name, new_sid, registration.conn_limit)
+                        .insert(
+                            name,
+                            new_sid,
+                            registration.conn_limit,
+                            registration.auth_pubkey,
+                            msg.sender.pid(),
+                        )
                         .expect("register name failure, maybe out of HashMap capacity?");
+                    if name == XousServerName::from_str(SERVER_NAME_SIGNATURE_VERIFIER) {
+                        info!("signature verifier registered");
+                        verifier_sid = Some(new_sid);
+                    }
                     log::trace!("request successful, SID is {:?}", new_sid);
 
                     response = api::Return::SID(new_sid.into());
@@ -320,24 +467,42 @@ fn xmain() -> ! {
                             response = api::Return::Failure
                         }
                     }
-                } else {
-                    log::debug!("Can't find request '{}' in table, dumping table:", name);
-                    for (_name, conn) in name_table.map.iter() {
-                        log::debug!("{:?}", conn);
-                    }
-                    // no authenticate remedy currently supported, but we'd put that code somewhere around here eventually.
+                } else if let Some(pubkey) = name_table.auth_pubkey(&name) {
+                    // the trusted slots are used up, but the server accepts callers that can sign for its key
+                    let sender_pid = msg
+                        .sender
+                        .pid()
+                        .expect("can't extract sender PID on Lookup");
                     let (c1, c2, c3, c4) = xous::create_server_id().unwrap().to_u32();
-                    let auth_request = AuthenticateRequest {
+                    let now = elapsed_ms(&mut ticktimer_conn);
+                    pending_challenges.retain(|_, pending| {
+                        now.saturating_sub(pending.issued_ms) <= AUTHENTICATE_TIMEOUT as u64
+                    });
+                    pending_challenges.insert(
+                        sender_pid,
+                        PendingChallenge {
+                            name,
+                            challenge: [c1, c2, c3, c4],
+                            issued_ms: now,
+                        },
+                    );
+                    log::trace!("Lookup for '{}' requires authentication", name);
+                    response = api::Return::AuthenticateRequest(AuthenticateRequest {
                         name: String::<64>::from_str(
                             name_string
                                 .as_str()
                                 .expect("couldn't convert server name to string"),
                         ),
-                        pubkey_id: [0; 20], // placeholder
+                        pubkey_id: pubkey_id(&pubkey),
                         challenge: [c1, c2, c3, c4],
-                    };
+                    })
+                } else {
+                    log::debug!("Can't find request '{}' in table, dumping table:", name);
+                    for (_name, conn) in name_table.map.iter() {
+                        log::debug!("{:?}", conn);
+                    }
                     d11ctimeout.hosted_delay();
-                    response = api::Return::AuthenticateRequest(auth_request) // this code just exists to exercise the return path
+                    response = api::Return::Failure
                 }
                 buffer
                     .replace(response)
@@ -345,19 +510,125 @@ fn xmain() -> ! {
             }
             Some(api::Opcode::AuthenticatedLookup) => {
                 let mem = msg.body.memory_message_mut().unwrap();
-                let buffer = unsafe { Buffer::from_memory_message_mut(mem) };
-                let auth_lookup: AuthenticatedLookup = buffer.to_original().unwrap();
-                info!("AuthenticatedLookup request {:?}", auth_lookup);
-                error!("AuthenticatedLookup not yet implemented");
-                unimplemented!("AuthenticatedLookup not yet implemented");
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let auth_lookup = buffer.to_original::<AuthenticatedLookup, _>().unwrap();
+                let name = XousServerName::from_str(
+                    auth_lookup
+                        .name
+                        .as_str()
+                        .expect("couldn't convert server name to string"),
+                );
+                let sender_pid = msg
+                    .sender
+                    .pid()
+                    .expect("can't extract sender PID on AuthenticatedLookup");
+                log::trace!("AuthenticatedLookup request for '{}'", name);
+                // a challenge is good for exactly one attempt, whatever its outcome
+                let authenticated = match (
+                    pending_challenges.remove(&sender_pid),
+                    name_table.auth_pubkey(&name),
+                ) {
+                    (Some(pending), Some(pubkey)) => {
+                        pending.name == name
+                            && pending.challenge == auth_lookup.challenge
+                            && pubkey_id(&pubkey) == auth_lookup.pubkey_id
+                            && elapsed_ms(&mut ticktimer_conn).saturating_sub(pending.issued_ms)
+                                <= AUTHENTICATE_TIMEOUT as u64
+                            && verify_signature(
+                                verifier_sid,
+                                &mut verifier_conn,
+                                pubkey,
+                                authenticate_message(name.to_str(), &pending.challenge),
+                                auth_lookup.response,
+                            )
+                    }
+                    _ => false,
+                };
+                let response: api::Return;
+                if authenticated {
+                    let token = xous::create_server_id()
+                        .expect("couldn't create token")
+                        .to_array();
+                    let server_sid = *name_table
+                        .connect_authenticated(&name, token)
+                        .expect("authenticated server disappeared from the table");
+                    match xous::connect_for_process(sender_pid, server_sid)
+                        .expect("can't broker connection")
+                    {
+                        xous::Result::ConnectionID(connection_id) => {
+                            log::trace!(
+                                "authenticated lookup success, returning connection {}",
+                                connection_id
+                            );
+                            response = api::Return::CID((connection_id, Some(token)))
+                        }
+                        _ => {
+                            name_table.disconnect_with_token(&name, token);
+                            response = api::Return::Failure
+                        }
+                    }
+                } else {
+                    info!("authentication failed, waiting for deterministic timeout");
+                    d11ctimeout.deterministic_busy_wait();
+                    response = api::Return::Failure
+                }
+                buffer
+                    .replace(response)
+                    .expect("AuthenticatedLookup can't serialize return value");
             }
             Some(api::Opcode::TrustedInitDone) => {
                 if name_table.trusted_init_done() {
+                    trusted_init_seen = true;
                     xous::return_scalar(msg.sender, 1).expect("couldn't return trusted_init_done");
                 } else {
                     xous::return_scalar(msg.sender, 0).expect("couldn't return trusted_init_done");
                 }
             }
+            Some(api::Opcode::RegisterIntrospectionToken) => {
+                msg_blocking_scalar_unpack!(msg, t0, t1, t2, t3, {
+                    if introspection_token.is_none() {
+                        introspection_token = Some([t0 as u32, t1 as u32, t2 as u32, t3 as u32]);
+                        xous::return_scalar(msg.sender, 1).unwrap();
+                    } else {
+                        log::warn!("introspection token already claimed");
+                        xous::return_scalar(msg.sender, 0).unwrap();
+                    }
+                })
+            }
+            Some(api::Opcode::ListServers) => {
+                let mem = msg.body.memory_message_mut().unwrap();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let mut list = buffer.to_original::<ServerList, _>().unwrap();
+                if introspection_token.is_some() && introspection_token == Some(list.token) {
+                    let mut names: Vec<&XousServerName> = name_table.map.keys().collect();
+                    names.sort_by(|a, b| a.to_str().cmp(b.to_str()));
+                    list.total = names.len() as u32;
+                    list.num = 0;
+                    for (entry, name) in list
+                        .entries
+                        .iter_mut()
+                        .zip(names.iter().skip(list.start as usize))
+                    {
+                        let conn = &name_table.map[*name];
+                        *entry = ServerListEntry {
+                            name: String::<64>::from_str(name.to_str()),
+                            conn_limit: conn.max_conns,
+                            current_conns: conn.current_conns,
+                            auth_conns: conn.auth_tokens.len() as u32,
+                            owner: conn.owner.map(|pid| pid.get()).unwrap_or(0),
+                        };
+                        list.num += 1;
+                    }
+                    list.granted = true;
+                } else {
+                    info!("server listing denied, waiting for deterministic timeout");
+                    d11ctimeout.deterministic_busy_wait();
+                    list.num = 0;
+                    list.total = 0;
+                    list.granted = false;
+                }
+                buffer.replace(list).expect("ListServers can't serialize return value");
+            }
             Some(api::Opcode::Disconnect) => {
                 let mem = msg.body.memory_message_mut().unwrap();
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
@@ -382,3 +653,81 @@ fn xmain() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBKEY: [u8; 32] = [7; 32];
+
+    fn sid(n: u32) -> xous::SID {
+        xous::SID::from_u32(n, n, n, n)
+    }
+
+    #[test]
+    fn auth_pubkey_only_when_allowed() {
+        let mut table = CheckedHashMap::new();
+        let open = XousServerName::from_str("open");
+        let keyed = XousServerName::from_str("keyed");
+        table.insert(open, sid(1), Some(2), None, None).unwrap();
+        table.insert(keyed, sid(2), Some(2), Some(PUBKEY), None).unwrap();
+
+        assert_eq!(table.auth_pubkey(&open), None);
+        assert_eq!(table.auth_pubkey(&keyed), Some(PUBKEY));
+        assert_eq!(table.auth_pubkey(&XousServerName::from_str("missing")), None);
+        assert!(table.connect_authenticated(&open, [1; 4]).is_none());
+        assert!(table.map[&open].auth_tokens.is_empty());
+    }
+
+    #[test]
+    fn authenticated_connections_are_released() {
+        let mut table = CheckedHashMap::new();
+        let keyed = XousServerName::from_str("keyed");
+        table.insert(keyed, sid(2), Some(2), Some(PUBKEY), None).unwrap();
+
+        assert_eq!(table.connect_authenticated(&keyed, [1; 4]), Some(&sid(2)));
+        assert_eq!(table.connect_authenticated(&keyed, [2; 4]), Some(&sid(2)));
+        assert_eq!(table.map[&keyed].auth_tokens.len(), 2);
+        // authenticated connections don't use up the trusted slots
+        assert_eq!(table.map[&keyed].current_conns, 0);
+
+        assert!(!table.disconnect_with_token(&keyed, [3; 4]));
+        assert!(!table.disconnect_with_token(&XousServerName::from_str("other"), [1; 4]));
+        assert!(table.disconnect_with_token(&keyed, [1; 4]));
+        assert_eq!(table.map[&keyed].auth_tokens, vec![[2; 4]]);
+        // each token is only good once
+        assert!(!table.disconnect_with_token(&keyed, [1; 4]));
+        assert!(table.disconnect_with_token(&keyed, [2; 4]));
+        assert!(table.map[&keyed].auth_tokens.is_empty());
+    }
+
+    #[test]
+    fn verifier_is_pinned_during_boot() {
+        let verifier = XousServerName::from_str(SERVER_NAME_SIGNATURE_VERIFIER);
+        let other = XousServerName::from_str("other");
+
+        assert!(may_register(&verifier, Some(0), None, false));
+        // the verifier must not be reachable by anyone but the name server
+        assert!(!may_register(&verifier, None, None, false));
+        assert!(!may_register(&verifier, Some(1), None, false));
+        // only the first registration counts, even if that server has since gone away
+        assert!(!may_register(&verifier, Some(0), Some(sid(1)), false));
+        // and nothing started after boot can claim the name
+        assert!(!may_register(&verifier, Some(0), None, true));
+
+        assert!(may_register(&other, None, Some(sid(1)), true));
+    }
+
+    #[test]
+    fn verify_signature_needs_a_verifier() {
+        let mut conn = None;
+        assert!(!verify_signature(
+            None,
+            &mut conn,
+            PUBKEY,
+            authenticate_message("keyed", &[1, 2, 3, 4]),
+            [0; 64]
+        ));
+        assert!(conn.is_none());
+    }
+}
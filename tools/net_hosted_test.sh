@@ -0,0 +1,47 @@
+#!/bin/bash
+set -e
+
+# Runs the hosted network test: `net-peer` stands in for the rest of the network, and the
+# `net-test` program pings it, has it echo UDP, and resolves a name through the DNS server.
+# Exits with 0 if `net-test` passes, and 1 if it fails or doesn't finish in time.
+
+PEER_ADDR=127.0.0.1:7001
+TIMEOUT_SECS=${1:-600}
+
+cd "$(dirname "$0")/.."
+mkdir -p target
+PEER_LOG=target/net-peer.log
+TEST_LOG=target/net-test.log
+
+cargo build --package tools --bin net-peer
+cargo build --package xtask
+
+# background jobs get their own process group, so the hosted Xous and everything it
+# started can be stopped together
+set -m
+target/debug/net-peer --listen $PEER_ADDR --host betrusted.io=185.199.108.153 > $PEER_LOG 2>&1 &
+PEER=$!
+XOUS_NET_PEER=$PEER_ADDR cargo xtask net-test > $TEST_LOG 2>&1 &
+XOUS=$!
+trap 'kill -- -$XOUS -$PEER 2> /dev/null || true' EXIT
+
+RESULT=1
+for i in $(seq $TIMEOUT_SECS); do
+    if grep -q "net-test: PASS" $TEST_LOG; then
+        RESULT=0
+        break
+    fi
+    if grep -q "net-test: FAIL" $TEST_LOG || ! kill -0 $XOUS 2> /dev/null; then
+        break
+    fi
+    sleep 1
+done
+
+grep "net-test:" $TEST_LOG || echo "net-test didn't finish within ${TIMEOUT_SECS}s"
+if [ $RESULT -ne 0 ]; then
+    echo "--- hosted Xous log ($TEST_LOG) ---"
+    tail -n 50 $TEST_LOG
+    echo "--- net-peer log ($PEER_LOG) ---"
+    tail -n 50 $PEER_LOG
+fi
+exit $RESULT
@@ -10,6 +10,7 @@ use tools::elf::{read_minielf, read_program};
 use tools::tags::bflg::Bflg;
 use tools::tags::inie::IniE;
 use tools::tags::memory::{MemoryRegion, MemoryRegions};
+use tools::tags::plim::ProcessPageLimits;
 use tools::tags::pnam::ProcessNames;
 use tools::tags::xkrn::XousKernel;
 use tools::utils::{parse_csr_csv, parse_u32};
@@ -87,6 +88,15 @@ fn main() {
                 .number_of_values(1)
                 .help("Initial program to load"),
         )
+        .arg(
+            Arg::with_name("page-limit")
+                .long("page-limit")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PROGRAM:PAGES")
+                .help("Limit an initial program to owning this many pages of RAM"),
+        )
         .arg(
             Arg::with_name("csv")
                 .short("c")
@@ -140,6 +150,25 @@ fn main() {
     };
 
     let mut process_names = ProcessNames::new();
+    let mut page_limits = ProcessPageLimits::new();
+    let mut program_limits = std::collections::HashMap::new();
+    if let Some(limits) = matches.values_of("page-limit") {
+        for limit in limits {
+            let limit_parts: Vec<&str> = limit.rsplitn(2, ':').collect();
+            if limit_parts.len() != 2 {
+                eprintln!("Error: --page-limit argument should be of the form [program]:[pages]");
+                return;
+            }
+            let pages = match parse_u32(limit_parts[0]) {
+                Ok(pages) => pages,
+                Err(e) => {
+                    eprintln!("Error: Unable to parse {}: {:?}", limit_parts[0], e);
+                    return;
+                }
+            };
+            program_limits.insert(limit_parts[1].to_owned(), pages);
+        }
+    }
 
     if let Some(val) = matches.value_of("ram") {
         let ram_parts: Vec<&str> = val.split(':').collect();
@@ -238,15 +267,15 @@ fn main() {
     if let Some(init_paths) = matches.values_of("init") {
         let mut pid = 2;
         for init_path in init_paths {
-            let program_name = std::path::Path::new(init_path);
-            process_names.set(
-                pid,
-                program_name
-                    .file_stem()
-                    .expect("program had no name")
-                    .to_str()
-                    .expect("program name is not valid utf-8"),
-            );
+            let program_name = std::path::Path::new(init_path)
+                .file_stem()
+                .expect("program had no name")
+                .to_str()
+                .expect("program name is not valid utf-8");
+            process_names.set(pid, program_name);
+            if let Some(pages) = program_limits.remove(program_name) {
+                page_limits.set(pid, pages);
+            }
             pid += 1;
             let init = read_minielf(init_path).expect("couldn't parse init file");
             args.add(IniE::new(init.entry_point, init.sections, init.program));
@@ -266,6 +295,17 @@ fn main() {
 
     args.add(process_names);
 
+    if let Some(program_name) = program_limits.keys().next() {
+        eprintln!(
+            "Error: --page-limit given for {}, which is not an initial program",
+            program_name
+        );
+        return;
+    }
+    if !page_limits.is_empty() {
+        args.add(page_limits);
+    }
+
     // Add tags for init and kernel.  These point to the actual data, which should
     // immediately follow the tags.  Therefore, we must know the length of the tags
     // before we create them.
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// One `event` line of an IPC trace, as printed by the kernel's `t` debug
+/// command or the shell's `ipctrace` command.
+struct Event<'a> {
+    seq: &'a str,
+    timestamp: &'a str,
+    kind: &'a str,
+    pid: u8,
+    tid: &'a str,
+    cid: &'a str,
+    sid: Option<[u32; 4]>,
+    peer: Option<u8>,
+    opcode: usize,
+    len: &'a str,
+}
+
+fn field<'a>(token: Option<&'a str>, name: &str) -> Option<&'a str> {
+    token?.strip_prefix(name)?.strip_prefix('=')
+}
+
+fn parse_event(line: &str) -> Option<Event<'_>> {
+    let mut tokens = line.split_whitespace();
+    let seq = tokens.next()?;
+    let timestamp = tokens.next()?;
+    let kind = tokens.next()?;
+    let mut caller = tokens.next()?.splitn(2, ':');
+    let pid = caller.next()?.parse().ok()?;
+    let tid = caller.next()?;
+    let cid = field(tokens.next(), "cid")?;
+    let sid = match field(tokens.next(), "sid")? {
+        "-" => None,
+        s if s.len() == 32 => {
+            let mut words = [0u32; 4];
+            for (i, word) in words.iter_mut().enumerate() {
+                *word = u32::from_str_radix(s.get(i * 8..i * 8 + 8)?, 16).ok()?;
+            }
+            Some(words)
+        }
+        _ => return None,
+    };
+    let peer = match field(tokens.next(), "peer")? {
+        "-" => None,
+        p => Some(p.parse().ok()?),
+    };
+    let opcode = field(tokens.next(), "op")?.parse().ok()?;
+    let len = field(tokens.next(), "len")?;
+    Some(Event {
+        seq,
+        timestamp,
+        kind,
+        pid,
+        tid,
+        cid,
+        sid,
+        peer,
+        opcode,
+        len,
+    })
+}
+
+/// Servers created with `create_server_with_address()` have a SID that is
+/// their name, which is far more useful than the number.
+fn sid_name(sid: &[u32; 4]) -> Option<String> {
+    let bytes: Vec<u8> = sid.iter().flat_map(|w| w.to_le_bytes().to_vec()).collect();
+    if bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        Some(String::from_utf8_lossy(&bytes).trim_end().to_owned())
+    } else {
+        None
+    }
+}
+
+/// Pull the variants of `enum Opcode` out of a service's `api.rs`, giving
+/// each one the same value the compiler would.
+fn parse_opcodes(source: &str) -> HashMap<usize, String> {
+    let body = match source.find("enum Opcode") {
+        Some(start) => &source[start..],
+        None => return HashMap::new(),
+    };
+    let body = match body.find('{') {
+        Some(start) => &body[start + 1..],
+        None => return HashMap::new(),
+    };
+
+    // Strip comments and attributes, and stop at the closing brace
+    let mut variants = String::new();
+    let mut depth = 0;
+    for line in body.lines() {
+        let line = line.split("//").next().unwrap_or("").trim();
+        if line.starts_with("#[") {
+            continue;
+        }
+        for c in line.chars() {
+            match c {
+                '{' | '(' => depth += 1,
+                ')' => depth -= 1,
+                '}' if depth == 0 => return number_variants(&variants),
+                '}' => depth -= 1,
+                ',' if depth == 0 => variants.push('\n'),
+                _ if depth == 0 => variants.push(c),
+                _ => {}
+            }
+        }
+        variants.push(' ');
+    }
+    number_variants(&variants)
+}
+
+fn number_variants(variants: &str) -> HashMap<usize, String> {
+    let mut opcodes = HashMap::new();
+    let mut next = 0;
+    for variant in variants.lines() {
+        let mut parts = variant.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        if name.is_empty() {
+            continue;
+        }
+        if let Some(value) = parts.next() {
+            let value = value.trim();
+            let parsed = if let Some(hex) = value.strip_prefix("0x") {
+                usize::from_str_radix(&hex.replace('_', ""), 16)
+            } else {
+                value.replace('_', "").parse()
+            };
+            if let Ok(value) = parsed {
+                next = value;
+            }
+        }
+        opcodes.insert(next, name.to_owned());
+        next += 1;
+    }
+    opcodes
+}
+
+struct Decoder {
+    services: PathBuf,
+    processes: HashMap<u8, String>,
+    opcodes: HashMap<String, HashMap<usize, String>>,
+}
+
+impl Decoder {
+    fn process_name(&self, pid: u8) -> String {
+        match self.processes.get(&pid) {
+            Some(name) if !name.is_empty() => format!("{}({})", name, pid),
+            _ => format!("PID{}", pid),
+        }
+    }
+
+    /// Look up `opcode` in the API of the service that runs as `pid`.
+    fn opcode_name(&mut self, pid: u8, opcode: usize) -> String {
+        let process = match self.processes.get(&pid) {
+            Some(name) => name.clone(),
+            None => return opcode.to_string(),
+        };
+        let services = &self.services;
+        let opcodes = self.opcodes.entry(process.clone()).or_insert_with(|| {
+            fs::read_to_string(services.join(&process).join("src").join("api.rs"))
+                .map(|source| parse_opcodes(&source))
+                .unwrap_or_default()
+        });
+        match opcodes.get(&opcode) {
+            Some(name) => format!("{}({})", name, opcode),
+            None => opcode.to_string(),
+        }
+    }
+
+    fn decode(&mut self, event: &Event) -> String {
+        let caller = format!("{}:{}", self.process_name(event.pid), event.tid);
+        let server = match (&event.sid, event.kind) {
+            (Some(sid), _) if sid_name(sid).is_some() => format!("\"{}\"", sid_name(sid).unwrap()),
+            // Servers being received on or replied from belong to the caller
+            (Some(_), "ReceiveMessage") | (Some(_), "ReturnMemory") | (Some(_), "ReturnScalar") => {
+                format!("server of {}", self.process_name(event.pid))
+            }
+            (Some(_), _) => match event.peer {
+                Some(peer) => format!("server of {}", self.process_name(peer)),
+                None => "unknown server".to_owned(),
+            },
+            (None, _) => "unknown server".to_owned(),
+        };
+        let mut line = format!(
+            "{:>5} {:>10} {:<14} {} ",
+            event.seq, event.timestamp, event.kind, caller
+        );
+        match event.kind {
+            "SendMessage" => {
+                let opcode = match event.peer {
+                    Some(peer) => self.opcode_name(peer, event.opcode),
+                    None => event.opcode.to_string(),
+                };
+                line.push_str(&format!(
+                    "-> {} via CID {}: {} ({} bytes)",
+                    server, event.cid, opcode, event.len
+                ));
+            }
+            "ReturnMemory" | "ReturnScalar" => {
+                let client = match event.peer {
+                    Some(peer) => self.process_name(peer),
+                    None => "unknown client".to_owned(),
+                };
+                line.push_str(&format!(
+                    "-> {} from {} ({} bytes)",
+                    client, server, event.len
+                ));
+            }
+            "Disconnect" => line.push_str(&format!("-x {} via CID {}", server, event.cid)),
+            _ => line.push_str(&format!("-> {}", server)),
+        }
+        line
+    }
+}
+
+fn doit() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        println!(
+            "Usage: {} trace.log [services-dir]",
+            args.first().unwrap_or(&"decode-ipc-trace".to_owned())
+        );
+        println!();
+        println!("Decodes an IPC trace captured from the kernel's `t` debug command or the");
+        println!("shell's `ipctrace` command, naming servers and the opcodes from their api.rs.");
+        process::exit(1);
+    }
+
+    let mut trace = String::new();
+    if args[1] == "-" {
+        io::stdin().read_to_string(&mut trace)?;
+    } else {
+        trace = fs::read_to_string(Path::new(&args[1]))?;
+    }
+
+    let mut decoder = Decoder {
+        services: PathBuf::from(args.get(2).map(|s| s.as_str()).unwrap_or("services")),
+        processes: HashMap::new(),
+        opcodes: HashMap::new(),
+    };
+
+    // Lines may carry a log prefix, so look for the markers anywhere. All
+    // the process names are read first, since they come after the events
+    // when a trace is dumped more than once.
+    for line in trace.lines() {
+        if let Some(start) = line.find("process ") {
+            let mut parts = line[start + "process ".len()..].trim().splitn(2, ' ');
+            if let Some(Ok(pid)) = parts.next().map(|p| p.parse::<u8>()) {
+                let name = parts.next().unwrap_or("").trim().to_owned();
+                decoder.processes.insert(pid, name);
+            }
+        }
+    }
+    for line in trace.lines() {
+        if let Some(start) = line.find("event ") {
+            if let Some(event) = parse_event(&line[start + "event ".len()..]) {
+                println!("{}", decoder.decode(&event));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    doit().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn implicit_and_explicit_values() {
+        let opcodes = parse_opcodes(
+            "
+            #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+            pub(crate) enum Opcode {
+                /// starts at zero
+                Register,
+                Lookup, // trailing comment
+                Disconnect = 0x10,
+                AfterHex,
+                Quit = 1_000,
+                Last
+            }
+            ",
+        );
+        assert_eq!(opcodes.len(), 6);
+        assert_eq!(opcodes[&0], "Register");
+        assert_eq!(opcodes[&1], "Lookup");
+        assert_eq!(opcodes[&0x10], "Disconnect");
+        assert_eq!(opcodes[&0x11], "AfterHex");
+        assert_eq!(opcodes[&1000], "Quit");
+        assert_eq!(opcodes[&1001], "Last");
+    }
+
+    #[test]
+    fn variants_with_fields() {
+        let opcodes = parse_opcodes(
+            "
+            enum Opcode {
+                Scalar(usize, usize),
+                Struct { first: u32, second: (u8, u8) },
+                Tail,
+            }
+            enum Other { NotThis }
+            ",
+        );
+        assert_eq!(opcodes.len(), 3);
+        assert_eq!(opcodes[&0], "Scalar");
+        assert_eq!(opcodes[&1], "Struct");
+        assert_eq!(opcodes[&2], "Tail");
+    }
+
+    #[test]
+    fn missing_or_unterminated_enum() {
+        assert!(parse_opcodes("pub struct Opcode;").is_empty());
+        assert!(parse_opcodes("enum Opcode").is_empty());
+        let opcodes = parse_opcodes("enum Opcode { First, Second");
+        assert_eq!(opcodes[&0], "First");
+        assert_eq!(opcodes[&1], "Second");
+    }
+
+    #[test]
+    fn unparseable_values_keep_counting() {
+        let opcodes = number_variants("A = 5\nB = SOME_CONST\n\nC\n");
+        assert_eq!(opcodes[&5], "A");
+        assert_eq!(opcodes[&6], "B");
+        assert_eq!(opcodes[&7], "C");
+        assert_eq!(opcodes.len(), 3);
+    }
+}
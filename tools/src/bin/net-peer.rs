@@ -0,0 +1,621 @@
+//! The other end of the Net server's hosted link. It exchanges Ethernet frames, one per
+//! UDP datagram, with a hosted Xous, and pretends to be the gateway and DNS server of the
+//! network that the hosted `NetPhy` makes up:
+//!
+//! * ARP requests for the gateway and DNS server are answered
+//! * pings to any address are answered, as if the whole internet were up
+//! * DNS queries are answered from the `--host` names, and anything else doesn't exist
+//! * UDP datagrams to port 7, on any address, are echoed back
+//!
+//! Only IPv4 is spoken. Start it, then point the hosted Xous at it:
+//!
+//! ```sh
+//! cargo run --bin net-peer -- --host betrusted.io=185.199.108.153
+//! XOUS_NET_PEER=127.0.0.1:7001 cargo xtask run
+//! ```
+//!
+//! `tools/net_hosted_test.sh` does the same with `cargo xtask net-test`, whose `net-test`
+//! program checks ping, UDP and DNS against it, and reports whether they worked.
+
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::process;
+
+/// The MAC address of the gateway and DNS server, which are the same host here
+const PEER_MAC: [u8; 6] = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+const ECHO_PORT: u16 = 7;
+const DNS_PORT: u16 = 53;
+const DNS_TTL: u32 = 300;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_UDP: u8 = 17;
+
+struct Peer {
+    gateway: Ipv4Addr,
+    dns: Ipv4Addr,
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+fn be16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn ipv4(data: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    )
+}
+
+/// The one's complement sum used by the IPv4, ICMP and UDP checksums
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in chunks {
+        for pair in chunk.chunks(2) {
+            let word = if pair.len() == 2 {
+                u16::from_be_bytes([pair[0], pair[1]])
+            } else {
+                u16::from_be_bytes([pair[0], 0])
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn ethernet(dst: &[u8], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(14 + payload.len());
+    frame.extend_from_slice(dst);
+    frame.extend_from_slice(&PEER_MAC);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0u8; 20];
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+    packet[8] = 64;
+    packet[9] = protocol;
+    packet[12..16].copy_from_slice(&src.octets());
+    packet[16..20].copy_from_slice(&dst.octets());
+    let sum = checksum(&[&packet]);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+fn udp_datagram(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let len = (8 + payload.len()) as u16;
+    let mut datagram = Vec::with_capacity(len as usize);
+    datagram.extend_from_slice(&src_port.to_be_bytes());
+    datagram.extend_from_slice(&dst_port.to_be_bytes());
+    datagram.extend_from_slice(&len.to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    let mut pseudo_header = Vec::with_capacity(12);
+    pseudo_header.extend_from_slice(&src.octets());
+    pseudo_header.extend_from_slice(&dst.octets());
+    pseudo_header.extend_from_slice(&[0, PROTOCOL_UDP]);
+    pseudo_header.extend_from_slice(&len.to_be_bytes());
+    let sum = match checksum(&[&pseudo_header, &datagram]) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    datagram
+}
+
+impl Peer {
+    /// Work out the reply to a frame from the device, if there is one
+    fn reply(&self, frame: &[u8]) -> Option<Vec<u8>> {
+        if frame.len() < 14 {
+            return None;
+        }
+        let src_mac = &frame[6..12];
+        match be16(frame, 12) {
+            ETHERTYPE_ARP => self.reply_arp(src_mac, &frame[14..]),
+            ETHERTYPE_IPV4 => self.reply_ipv4(src_mac, &frame[14..]),
+            _ => None,
+        }
+    }
+
+    fn reply_arp(&self, src_mac: &[u8], arp: &[u8]) -> Option<Vec<u8>> {
+        // only requests for an IPv4 address over Ethernet
+        if arp.len() < 28
+            || be16(arp, 0) != 1
+            || be16(arp, 2) != ETHERTYPE_IPV4
+            || be16(arp, 6) != 1
+        {
+            return None;
+        }
+        let target = ipv4(arp, 24);
+        if target != self.gateway && target != self.dns {
+            return None;
+        }
+        println!("arp: {} is at {:02x?}", target, PEER_MAC);
+        let mut reply = Vec::with_capacity(28);
+        reply.extend_from_slice(&arp[0..6]);
+        reply.extend_from_slice(&2u16.to_be_bytes());
+        reply.extend_from_slice(&PEER_MAC);
+        reply.extend_from_slice(&target.octets());
+        reply.extend_from_slice(&arp[8..14]);
+        reply.extend_from_slice(&arp[14..18]);
+        Some(ethernet(src_mac, ETHERTYPE_ARP, &reply))
+    }
+
+    fn reply_ipv4(&self, src_mac: &[u8], packet: &[u8]) -> Option<Vec<u8>> {
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = ((packet[0] & 0xf) as usize) * 4;
+        let total_len = (be16(packet, 2) as usize).min(packet.len());
+        if header_len < 20 || total_len < header_len {
+            return None;
+        }
+        let src = ipv4(packet, 12);
+        let dst = ipv4(packet, 16);
+        let payload = &packet[header_len..total_len];
+        let (protocol, reply) = match packet[9] {
+            PROTOCOL_ICMP => (PROTOCOL_ICMP, self.reply_icmp(src, dst, payload)?),
+            PROTOCOL_UDP => (PROTOCOL_UDP, self.reply_udp(src, dst, payload)?),
+            _ => return None,
+        };
+        Some(ethernet(
+            src_mac,
+            ETHERTYPE_IPV4,
+            &ipv4_packet(dst, src, protocol, &reply),
+        ))
+    }
+
+    fn reply_icmp(&self, src: Ipv4Addr, dst: Ipv4Addr, icmp: &[u8]) -> Option<Vec<u8>> {
+        // echo requests only
+        if icmp.len() < 8 || icmp[0] != 8 {
+            return None;
+        }
+        println!("ping: {} -> {} seq {}", src, dst, be16(icmp, 6));
+        let mut reply = icmp.to_vec();
+        reply[0] = 0;
+        reply[2..4].copy_from_slice(&[0, 0]);
+        let sum = checksum(&[&reply]);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+        Some(reply)
+    }
+
+    fn reply_udp(&self, src: Ipv4Addr, dst: Ipv4Addr, udp: &[u8]) -> Option<Vec<u8>> {
+        if udp.len() < 8 {
+            return None;
+        }
+        let src_port = be16(udp, 0);
+        let dst_port = be16(udp, 2);
+        let len = (be16(udp, 4) as usize).min(udp.len());
+        if len < 8 {
+            return None;
+        }
+        let payload = &udp[8..len];
+        let reply = match dst_port {
+            ECHO_PORT => {
+                println!(
+                    "udp echo: {}:{} -> {}, {} bytes",
+                    src,
+                    src_port,
+                    dst,
+                    payload.len()
+                );
+                payload.to_vec()
+            }
+            DNS_PORT if dst == self.dns => self.reply_dns(payload)?,
+            _ => return None,
+        };
+        Some(udp_datagram(dst, src, dst_port, src_port, &reply))
+    }
+
+    /// Answer a query for one name's A or AAAA records
+    fn reply_dns(&self, query: &[u8]) -> Option<Vec<u8>> {
+        if query.len() < 12 || be16(query, 4) != 1 {
+            return None;
+        }
+        let mut labels = Vec::new();
+        let mut index = 12;
+        loop {
+            let len = *query.get(index)? as usize;
+            index += 1;
+            if len == 0 {
+                break;
+            }
+            labels.push(String::from_utf8_lossy(query.get(index..index + len)?).to_lowercase());
+            index += len;
+        }
+        let qtype = be16(query.get(index..index + 4)?, 0);
+        let question = &query[12..index + 4];
+        let name = labels.join(".");
+
+        let answers: Vec<&IpAddr> = self
+            .hosts
+            .get(&name)
+            .map(|addrs| {
+                addrs
+                    .iter()
+                    .filter(|addr| match addr {
+                        IpAddr::V4(_) => qtype == 1,
+                        IpAddr::V6(_) => qtype == 28,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        // a name we know of, but without records of the type asked for, gets no answers
+        let rcode = if self.hosts.contains_key(&name) { 0 } else { 3 };
+        println!("dns: {} type {} -> {:?}", name, qtype, answers);
+
+        let mut reply = Vec::new();
+        reply.extend_from_slice(&query[0..2]);
+        reply.extend_from_slice(&(0x8180u16 | rcode).to_be_bytes());
+        reply.extend_from_slice(&1u16.to_be_bytes());
+        reply.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        reply.extend_from_slice(&[0, 0, 0, 0]);
+        reply.extend_from_slice(question);
+        for addr in answers {
+            // the name is a pointer back to the question
+            reply.extend_from_slice(&[0xc0, 0x0c]);
+            match addr {
+                IpAddr::V4(v4) => {
+                    reply.extend_from_slice(&1u16.to_be_bytes());
+                    reply.extend_from_slice(&1u16.to_be_bytes());
+                    reply.extend_from_slice(&DNS_TTL.to_be_bytes());
+                    reply.extend_from_slice(&4u16.to_be_bytes());
+                    reply.extend_from_slice(&v4.octets());
+                }
+                IpAddr::V6(v6) => {
+                    reply.extend_from_slice(&28u16.to_be_bytes());
+                    reply.extend_from_slice(&1u16.to_be_bytes());
+                    reply.extend_from_slice(&DNS_TTL.to_be_bytes());
+                    reply.extend_from_slice(&16u16.to_be_bytes());
+                    reply.extend_from_slice(&v6.octets());
+                }
+            }
+        }
+        Some(reply)
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: net-peer [--listen ADDR:PORT] [--gateway IP] [--dns IP] [--host NAME=IP]...\n\
+         \n\
+         Answers a hosted Xous started with XOUS_NET_PEER set to the --listen address,\n\
+         which is 127.0.0.1:7001 by default. The gateway and DNS server default to\n\
+         10.0.2.2 and 10.0.2.3, which is what the hosted Net server expects."
+    );
+    process::exit(1);
+}
+
+fn main() {
+    let mut listen: SocketAddr = "127.0.0.1:7001".parse().unwrap();
+    let mut peer = Peer {
+        gateway: Ipv4Addr::new(10, 0, 2, 2),
+        dns: Ipv4Addr::new(10, 0, 2, 3),
+        hosts: HashMap::new(),
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--listen" => listen = value.parse().unwrap_or_else(|_| usage()),
+            "--gateway" => peer.gateway = value.parse().unwrap_or_else(|_| usage()),
+            "--dns" => peer.dns = value.parse().unwrap_or_else(|_| usage()),
+            "--host" => {
+                let mut parts = value.splitn(2, '=');
+                let name = parts.next().unwrap().to_lowercase();
+                let addr: IpAddr = parts
+                    .next()
+                    .and_then(|addr| addr.parse().ok())
+                    .unwrap_or_else(|| usage());
+                peer.hosts.entry(name).or_default().push(addr);
+            }
+            _ => usage(),
+        }
+    }
+
+    let socket = UdpSocket::bind(listen).unwrap_or_else(|e| {
+        eprintln!("couldn't listen on {}: {}", listen, e);
+        process::exit(1);
+    });
+    println!("net-peer listening on {}", listen);
+    let mut frame = [0u8; 2048];
+    loop {
+        let (len, device) = match socket.recv_from(&mut frame) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("receive failed: {}", e);
+                continue;
+            }
+        };
+        if let Some(reply) = peer.reply(&frame[..len]) {
+            if let Err(e) = socket.send_to(&reply, device) {
+                eprintln!("couldn't reply to {}: {}", device, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+    const DEVICE: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+
+    fn peer() -> Peer {
+        let mut hosts = HashMap::new();
+        hosts.insert(
+            "betrusted.io".to_string(),
+            vec![
+                "185.199.108.153".parse().unwrap(),
+                "2606:50c0:8000::153".parse().unwrap(),
+            ],
+        );
+        Peer {
+            gateway: Ipv4Addr::new(10, 0, 2, 2),
+            dns: Ipv4Addr::new(10, 0, 2, 3),
+            hosts,
+        }
+    }
+
+    /// A frame from the device, to the peer
+    fn from_device(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = PEER_MAC.to_vec();
+        frame.extend_from_slice(&DEVICE_MAC);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn arp_request(target: Ipv4Addr) -> Vec<u8> {
+        let mut arp = vec![0, 1, 0x08, 0x00, 6, 4, 0, 1];
+        arp.extend_from_slice(&DEVICE_MAC);
+        arp.extend_from_slice(&DEVICE.octets());
+        arp.extend_from_slice(&[0; 6]);
+        arp.extend_from_slice(&target.octets());
+        from_device(ETHERTYPE_ARP, &arp)
+    }
+
+    fn udp_request(dst: Ipv4Addr, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let udp = udp_datagram(DEVICE, dst, 49152, dst_port, payload);
+        from_device(
+            ETHERTYPE_IPV4,
+            &ipv4_packet(DEVICE, dst, PROTOCOL_UDP, &udp),
+        )
+    }
+
+    fn dns_query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&qtype.to_be_bytes());
+        query.extend_from_slice(&1u16.to_be_bytes());
+        query
+    }
+
+    /// Check the Ethernet and IPv4 headers of a reply to the device, and return its IPv4 payload
+    fn ipv4_payload(reply: &[u8], src: Ipv4Addr, protocol: u8) -> Vec<u8> {
+        assert_eq!(&reply[0..6], &DEVICE_MAC);
+        assert_eq!(&reply[6..12], &PEER_MAC);
+        assert_eq!(be16(reply, 12), ETHERTYPE_IPV4);
+        let packet = &reply[14..];
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(be16(packet, 2) as usize, packet.len());
+        assert_eq!(packet[9], protocol);
+        assert_eq!(ipv4(packet, 12), src);
+        assert_eq!(ipv4(packet, 16), DEVICE);
+        assert_eq!(checksum(&[&packet[..20]]), 0);
+        packet[20..].to_vec()
+    }
+
+    /// Check a UDP reply to the device, and return its payload
+    fn udp_payload(reply: &[u8], src: Ipv4Addr, src_port: u16) -> Vec<u8> {
+        let udp = ipv4_payload(reply, src, PROTOCOL_UDP);
+        assert_eq!(be16(&udp, 0), src_port);
+        assert_eq!(be16(&udp, 2), 49152);
+        assert_eq!(be16(&udp, 4) as usize, udp.len());
+        let mut pseudo_header = src.octets().to_vec();
+        pseudo_header.extend_from_slice(&DEVICE.octets());
+        pseudo_header.extend_from_slice(&[0, PROTOCOL_UDP]);
+        pseudo_header.extend_from_slice(&(udp.len() as u16).to_be_bytes());
+        assert_eq!(checksum(&[&pseudo_header, &udp]), 0);
+        udp[8..].to_vec()
+    }
+
+    #[test]
+    fn checksums_fold_carries_and_pad_odd_bytes() {
+        assert_eq!(checksum(&[&[0xff, 0xff, 0x00, 0x01]]), !0x0001);
+        assert_eq!(checksum(&[&[0x12]]), !0x1200);
+        assert_eq!(checksum(&[&[0x12], &[0x34]]), !0x4600);
+    }
+
+    #[test]
+    fn arp_for_the_gateway_and_dns_server() {
+        let peer = peer();
+        for &target in &[peer.gateway, peer.dns] {
+            let reply = peer.reply(&arp_request(target)).unwrap();
+            assert_eq!(&reply[0..6], &DEVICE_MAC);
+            assert_eq!(be16(&reply, 12), ETHERTYPE_ARP);
+            let arp = &reply[14..];
+            assert_eq!(arp.len(), 28);
+            assert_eq!(be16(arp, 6), 2);
+            assert_eq!(&arp[8..14], &PEER_MAC);
+            assert_eq!(ipv4(arp, 14), target);
+            assert_eq!(&arp[18..24], &DEVICE_MAC);
+            assert_eq!(ipv4(arp, 24), DEVICE);
+        }
+        assert!(peer
+            .reply(&arp_request(Ipv4Addr::new(10, 0, 2, 99)))
+            .is_none());
+    }
+
+    #[test]
+    fn arp_replies_and_truncated_requests_are_ignored() {
+        let peer = peer();
+        let mut reply = arp_request(peer.gateway);
+        reply[14 + 7] = 2;
+        assert!(peer.reply(&reply).is_none());
+        let request = arp_request(peer.gateway);
+        assert!(peer.reply(&request[..request.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn pings_to_anywhere_are_answered() {
+        let peer = peer();
+        let far = Ipv4Addr::new(192, 0, 2, 1);
+        let mut icmp = vec![8, 0, 0, 0, 0x12, 0x34, 0, 7, 1, 2, 3];
+        let sum = checksum(&[&icmp]);
+        icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+        let request = from_device(
+            ETHERTYPE_IPV4,
+            &ipv4_packet(DEVICE, far, PROTOCOL_ICMP, &icmp),
+        );
+
+        let reply = ipv4_payload(&peer.reply(&request).unwrap(), far, PROTOCOL_ICMP);
+        assert_eq!(reply[0], 0);
+        assert_eq!(&reply[4..], &icmp[4..]);
+        assert_eq!(checksum(&[&reply]), 0);
+
+        // only echo requests get answers
+        icmp[0] = 0;
+        let request = from_device(
+            ETHERTYPE_IPV4,
+            &ipv4_packet(DEVICE, far, PROTOCOL_ICMP, &icmp),
+        );
+        assert!(peer.reply(&request).is_none());
+    }
+
+    #[test]
+    fn udp_is_echoed_on_the_echo_port_only() {
+        let peer = peer();
+        let far = Ipv4Addr::new(192, 0, 2, 1);
+        let reply = peer.reply(&udp_request(far, ECHO_PORT, b"hello")).unwrap();
+        assert_eq!(udp_payload(&reply, far, ECHO_PORT), b"hello");
+        assert!(peer.reply(&udp_request(far, 9, b"hello")).is_none());
+    }
+
+    #[test]
+    fn malformed_ipv4_is_ignored() {
+        let peer = peer();
+        let request = udp_request(peer.gateway, ECHO_PORT, b"hello");
+        // too short for an Ethernet header
+        assert!(peer.reply(&request[..13]).is_none());
+        // header length shorter than the minimum
+        let mut bad = request.clone();
+        bad[14] = 0x44;
+        assert!(peer.reply(&bad).is_none());
+        // not IPv4
+        let mut bad = request.clone();
+        bad[14] = 0x65;
+        assert!(peer.reply(&bad).is_none());
+        // a UDP length too short for its own header
+        let mut bad = request;
+        bad[14 + 20 + 4..14 + 20 + 6].copy_from_slice(&4u16.to_be_bytes());
+        assert!(peer.reply(&bad).is_none());
+    }
+
+    #[test]
+    fn dns_answers_known_names() {
+        let peer = peer();
+        let reply = peer
+            .reply(&udp_request(
+                peer.dns,
+                DNS_PORT,
+                &dns_query("BeTrusted.io", 1),
+            ))
+            .unwrap();
+        let dns = udp_payload(&reply, peer.dns, DNS_PORT);
+        let question_len = dns_query("betrusted.io", 1).len() - 12;
+        assert_eq!(&dns[0..2], &[0x12, 0x34]);
+        assert_eq!(be16(&dns, 2), 0x8180);
+        assert_eq!(be16(&dns, 6), 1);
+        let answer = &dns[12 + question_len..];
+        assert_eq!(&answer[0..2], &[0xc0, 0x0c]);
+        assert_eq!(be16(answer, 2), 1);
+        assert_eq!(be16(answer, 10), 4);
+        assert_eq!(ipv4(answer, 12), Ipv4Addr::new(185, 199, 108, 153));
+
+        let reply = peer
+            .reply(&udp_request(
+                peer.dns,
+                DNS_PORT,
+                &dns_query("betrusted.io", 28),
+            ))
+            .unwrap();
+        let dns = udp_payload(&reply, peer.dns, DNS_PORT);
+        let answer = &dns[12 + question_len..];
+        assert_eq!(be16(answer, 2), 28);
+        assert_eq!(be16(answer, 10), 16);
+        assert_eq!(
+            answer[12..28],
+            "2606:50c0:8000::153"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets()
+        );
+    }
+
+    #[test]
+    fn dns_reports_unknown_names() {
+        let peer = peer();
+        let reply = peer
+            .reply(&udp_request(
+                peer.dns,
+                DNS_PORT,
+                &dns_query("example.com", 1),
+            ))
+            .unwrap();
+        let dns = udp_payload(&reply, peer.dns, DNS_PORT);
+        assert_eq!(be16(&dns, 2), 0x8183);
+        assert_eq!(be16(&dns, 6), 0);
+    }
+
+    #[test]
+    fn dns_ignores_malformed_and_misdirected_queries() {
+        let peer = peer();
+        // only the DNS server answers
+        assert!(peer
+            .reply(&udp_request(
+                peer.gateway,
+                DNS_PORT,
+                &dns_query("betrusted.io", 1)
+            ))
+            .is_none());
+        // the name runs off the end of the query
+        let query = dns_query("betrusted.io", 1);
+        assert!(peer
+            .reply(&udp_request(peer.dns, DNS_PORT, &query[..18]))
+            .is_none());
+        // the question type is cut off
+        assert!(peer
+            .reply(&udp_request(peer.dns, DNS_PORT, &query[..query.len() - 3]))
+            .is_none());
+        // more than one question
+        let mut query = query;
+        query[5] = 2;
+        assert!(peer
+            .reply(&udp_request(peer.dns, DNS_PORT, &query))
+            .is_none());
+    }
+}
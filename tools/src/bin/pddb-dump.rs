@@ -0,0 +1,186 @@
+use clap::{crate_version, App, Arg};
+use std::io::Read;
+
+use tools::pddb::{parse_key_file, BasisKey, PddbImage, Report, AES_KEYSIZE};
+
+const IMAGE_DIR: &str = "tools/pddb-images";
+
+fn read_file(path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut data = vec![];
+    std::fs::File::open(path)
+        .map_err(|e| format!("couldn't open {}: {}", path, e))?
+        .read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// parses a `name:hexkey` pair given on the command line
+fn parse_basis_key(arg: &str) -> Result<BasisKey, String> {
+    let mut parts = arg.rsplitn(2, ':');
+    let hex = parts.next().unwrap_or("");
+    let name = parts.next().ok_or(format!("basis key {} is not of the form name:hexkey", arg))?;
+    if hex.len() != AES_KEYSIZE * 2 {
+        return Err(format!("basis key for {} must be {} hex digits", name, AES_KEYSIZE * 2));
+    }
+    let mut key = [0u8; AES_KEYSIZE];
+    for (i, dst) in key.iter_mut().enumerate() {
+        *dst = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("basis key for {} is not valid hex", name))?;
+    }
+    Ok(BasisKey { name: name.to_string(), key })
+}
+
+fn printable(data: &[u8]) -> String {
+    data.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect()
+}
+
+fn print_report(report: &Report, show_ptes: bool, show_fscb: bool, show_data: bool) {
+    const PREVIEW_LEN: usize = 64;
+    for basis in report.bases.iter() {
+        println!("Basis {}", basis.name);
+        println!(" Journal rev: {}", basis.journal);
+        println!(" Age: {}", basis.age);
+        println!(" Pages mapped: {}", basis.v2p.len());
+        println!(" Dictionaries: {}/{}", basis.dicts.len(), basis.num_dicts);
+        if show_ptes {
+            println!(" Page table:");
+            for pte in basis.ptes.iter() {
+                println!("  {}", pte);
+            }
+        }
+        for dict in basis.dicts.iter() {
+            println!("  Dict {} (index {})", dict.name, dict.index);
+            println!("   Flags: {:x} | Age: {} | Free key index: {}", dict.flags, dict.age, dict.free_key_index);
+            println!("   Keys: {}/{}", dict.keys.len(), dict.num_keys);
+            for key in dict.keys.iter() {
+                let check = match key.checksum_ok {
+                    Some(true) => " | CI OK",
+                    Some(false) => " | CI FAIL",
+                    None => "",
+                };
+                println!("    {} (descriptor {})", key.name, key.descriptor_index);
+                println!("     Start: 0x{:x} | Len: {}/{} | Flags: {:x} | Age: {}{}",
+                    key.start, key.len, key.reserved, key.flags, key.age, check);
+                if show_data {
+                    match &key.data {
+                        Some(data) => {
+                            let preview = &data[..data.len().min(PREVIEW_LEN)];
+                            let extra = if data.len() > PREVIEW_LEN { "..." } else { "" };
+                            let hex: String = preview.iter().map(|b| format!("{:02x}", b)).collect();
+                            println!("     Data (hex): {}{}", hex, extra);
+                            println!("     Data (txt): {}{}", printable(preview), extra);
+                        }
+                        None => println!("     Data: unreadable"),
+                    }
+                }
+            }
+        }
+    }
+    if let Some(fscb) = &report.fscb {
+        match fscb.record_page {
+            Some(page) => println!("FastSpace record at FSCB page {}, {} free pool entries", page, fscb.free_pool.len()),
+            None => println!("No FastSpace record"),
+        }
+        println!("{} space update records", fscb.updates.len());
+        if show_fscb {
+            println!(" Free pool:");
+            for pp in fscb.free_pool.iter() {
+                println!("  {}", pp);
+            }
+            println!(" Space updates:");
+            for update in fscb.updates.iter() {
+                match update.page {
+                    Some(pp) => println!("  @{:05x}: {}", update.offset, pp),
+                    None => println!("  @{:05x}: corrupt", update.offset),
+                }
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = App::new("pddb-dump")
+        .version(crate_version!())
+        .about("Inspect and check PDDB disk images dumped by the hosted emulation")
+        .arg(
+            Arg::with_name("name")
+                .long("name")
+                .help("root name of the image and key files in tools/pddb-images")
+                .value_name("name")
+                .takes_value(true)
+                .default_value("pddb"),
+        )
+        .arg(
+            Arg::with_name("image")
+                .long("image")
+                .help("path to the disk image; overrides --name")
+                .value_name("image")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keys")
+                .long("keys")
+                .help("path to the exported key file; overrides --name")
+                .value_name("keys")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("basis-key")
+                .long("basis-key")
+                .help("additional basis key, as name:hexkey")
+                .value_name("name:hexkey")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("dna")
+                .long("dna")
+                .help("FPGA DNA of the device the image came from")
+                .value_name("dna")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(Arg::with_name("ptes").long("ptes").help("print the page table entries of each basis"))
+        .arg(Arg::with_name("fscb").long("fscb").help("print the FastSpace and space update records"))
+        .arg(Arg::with_name("data").long("data").help("print a preview of each key's data"))
+        .get_matches();
+
+    let name = matches.value_of("name").unwrap();
+    let image_path = matches.value_of("image").map(|s| s.to_string())
+        .unwrap_or(format!("{}/{}.bin", IMAGE_DIR, name));
+    let keys_path = matches.value_of("keys").map(|s| s.to_string())
+        .unwrap_or(format!("{}/{}.key", IMAGE_DIR, name));
+    let dna: u64 = matches.value_of("dna").unwrap().parse()
+        .map_err(|_| "dna must be a decimal u64".to_string())?;
+
+    let mut keys = match read_file(&keys_path) {
+        Ok(data) => parse_key_file(&data)?,
+        Err(e) if matches.is_present("basis-key") => {
+            eprintln!("{}; continuing with the keys given on the command line", e);
+            Vec::new()
+        }
+        Err(e) => return Err(e),
+    };
+    if let Some(basis_keys) = matches.values_of("basis-key") {
+        for arg in basis_keys {
+            keys.push(parse_basis_key(arg)?);
+        }
+    }
+    println!("Basis keys: {}", keys.iter().map(|k| k.name.as_str()).collect::<Vec<_>>().join(", "));
+
+    let raw = read_file(&image_path)?;
+    let image = PddbImage::new(&raw, dna)?;
+    let report = image.decode(&keys);
+    print_report(&report, matches.is_present("ptes"), matches.is_present("fscb"), matches.is_present("data"));
+
+    if report.errors.is_empty() {
+        println!("No integrity errors found.");
+        Ok(())
+    } else {
+        println!("{} integrity errors found:", report.errors.len());
+        for e in report.errors.iter() {
+            println!(" {}", e);
+        }
+        std::process::exit(1);
+    }
+}
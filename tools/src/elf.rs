@@ -8,9 +8,6 @@ use xmas_elf::program::Type as ProgramType;
 use xmas_elf::sections::ShType;
 use xmas_elf::ElfFile;
 
-// Normal ELF flags
-use xmas_elf::sections::{SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE};
-
 bitflags! {
     pub struct MiniElfFlags: u8 {
         const NONE = 0;
@@ -104,6 +101,9 @@ pub enum ElfReadError {
 
     /// Couldn't write the section to the file
     WriteSectionError(std::io::Error),
+
+    /// The program couldn't be loaded into a process
+    ProgramError(xous::elf::ElfError),
 }
 
 impl fmt::Display for ElfReadError {
@@ -121,6 +121,7 @@ impl fmt::Display for ElfReadError {
             SectionNotAligned(s, a) => write!(f, "elf section {} had unaligned length {}", s, a),
             FileSeekError(e) => write!(f, "couldn't seek in the output file: {}", e),
             WriteSectionError(e) => write!(f, "couldn't write a section to the output file: {}", e),
+            ProgramError(e) => write!(f, "the program couldn't be loaded: {:?}", e),
         }
     }
 }
@@ -267,8 +268,9 @@ pub fn read_program<P: AsRef<Path>>(filename: P) -> Result<ProgramDescription, E
     })
 }
 
-/// Read an ELF file into a mini ELF file.
-#[allow(clippy::cognitive_complexity)]
+/// Read an ELF file into a mini ELF file. This uses the same reader as
+/// `xous::create_process()`, so anything that makes it into an image could
+/// also be launched at runtime.
 pub fn read_minielf<P: AsRef<Path>>(filename: P) -> Result<MiniElf, ElfReadError> {
     let mut b = Vec::new();
     {
@@ -276,76 +278,33 @@ pub fn read_minielf<P: AsRef<Path>>(filename: P) -> Result<MiniElf, ElfReadError
         fi.read_to_end(&mut b)
             .map_err(ElfReadError::ReadFileError)?;
     }
-    let elf = ElfFile::new(&b).map_err(|x| ElfReadError::ParseElfError(x))?;
-    let entry_point = elf.header.pt2.entry_point() as u32;
+    let elf = xous::elf::ElfFile::parse(&b).map_err(ElfReadError::ProgramError)?;
+    let layout = elf.layout().map_err(ElfReadError::ProgramError)?;
+    debug!("Layout: {:?}", layout);
     let mut program_data = Cursor::new(Vec::new());
-
     let mut sections = vec![];
 
-    debug!("ELF: {:?}", elf.header);
-    for ph in elf.program_iter() {
-        debug!("Program Header: {:?}", ph);
-        debug!("Physical address: {:08x}", ph.physical_addr());
-        debug!("Virtual address: {:08x}", ph.virtual_addr());
-        debug!("Offset: {:08x}", ph.offset());
-        debug!("Size: {:08x}", ph.file_size());
-    }
-    debug!("Program starts at 0x{:x}", entry_point);
-
     // This keeps a running offset of where data is getting copied.
     let mut program_offset = 0;
-    for s in elf.section_iter() {
+    for s in elf.sections() {
         let mut flags = MiniElfFlags::NONE;
-        let name = s.get_name(&elf).unwrap_or("<<error>>");
-
-        if s.address() == 0 {
-            debug!("(Skipping section {} -- invalid address)", name);
-            continue;
-        }
-
-        debug!("Section {}:", name);
-        debug!("Official header:");
-        debug!("{:?}", s);
-        debug!("Interpreted:");
-        debug!("    flags:            {:?}", s.flags());
-        debug!("    type:             {:?}", s.get_type());
-        debug!("    address:          {:08x}", s.address());
-        debug!("    offset:           {:08x}", s.offset());
-        debug!("    size:             {:?}", s.size());
-        debug!("    link:             {:?}", s.link());
-        let size = s.size();
-
-        if s.flags() & SHF_ALLOC == 0 {
-            debug!("section has no allocations -- skipping");
-            continue;
-        }
-        if s.get_type() == Ok(ShType::NoBits) {
+        if s.data.is_none() {
             flags |= MiniElfFlags::NOCOPY;
         }
-        if s.flags() & SHF_EXECINSTR != 0 {
+        if s.executable {
             flags |= MiniElfFlags::EXECUTE;
         }
-        if s.flags() & SHF_WRITE != 0 {
+        if s.writable {
             flags |= MiniElfFlags::WRITE;
         }
 
-        debug!("Adding {} to the file", name);
-        debug!(
-            "{} offset: {:08x}  program_offset: {:08x}  bytes: {}  seek: {}",
-            name,
-            s.offset(),
-            program_offset,
-            s.raw_data(&elf).len(),
-            program_offset
-        );
-        let section_data = s.raw_data(&elf);
         debug!(
-            "Section start: {:02x} {:02x} {:02x} {:02x} going into offset 0x{:08x}",
-            section_data[0], section_data[1], section_data[2], section_data[3], program_offset
+            "Adding {} ({} bytes @ {:08x}, flags {:?}) at program offset {:08x}",
+            s.name, s.size, s.virt, flags, program_offset
         );
 
         // If this section gets copied, add it to the program stream.
-        if s.get_type() != Ok(ShType::NoBits) {
+        if let Some(section_data) = s.data {
             program_data
                 .seek(SeekFrom::Start(program_offset))
                 .map_err(ElfReadError::FileSeekError)?;
@@ -355,9 +314,9 @@ pub fn read_minielf<P: AsRef<Path>>(filename: P) -> Result<MiniElf, ElfReadError
             program_offset += section_data.len() as u64;
         }
         sections.push(MiniElfSection {
-            virt: s.address() as u32,
-            size: size as u32,
-            name: name.to_string(),
+            virt: s.virt as u32,
+            size: s.size as u32,
+            name: s.name.to_string(),
             flags,
         });
     }
@@ -367,7 +326,7 @@ pub fn read_minielf<P: AsRef<Path>>(filename: P) -> Result<MiniElf, ElfReadError
 
     debug!("Program size: {} bytes", observed_size);
     Ok(MiniElf {
-        entry_point,
+        entry_point: elf.entry_point() as u32,
         sections,
         program: program_data.into_inner(),
     })
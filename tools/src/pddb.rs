@@ -0,0 +1,1190 @@
+//! Offline decoder for PDDB disk images.
+//!
+//! The hosted PDDB emulation dumps its flash image with `dump_fs()` and the keys it knows about
+//! with `dump_keys()` (see `services/pddb/src/backend/hosted.rs`). This module walks such an image
+//! the same way the PDDB does at mount time -- page table, basis roots, dictionaries, keys and the
+//! FastSpace records -- and notes every inconsistency it finds along the way, so that a corrupted
+//! image can be diagnosed without a running system.
+//!
+//! The constants and on-disk layouts here mirror the ones in `services/pddb`; `layout_matches_pddb`
+//! in the tests below reads them out of the PDDB's sources and fails if the two drift apart. Only
+//! the default 32-bit physical address layout is supported.
+
+use aes::Aes256;
+use aes::cipher::{BlockDecrypt, NewBlockCipher, generic_array::GenericArray};
+use aes_gcm_siv::{Aes256GcmSiv, Key as AeadKey, Nonce};
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+
+pub const PAGE_SIZE: usize = 4096;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const JOURNAL_LEN: usize = 4;
+pub const VPAGE_SIZE: usize = PAGE_SIZE - NONCE_LEN - TAG_LEN - JOURNAL_LEN;
+const AES_BLOCK_SIZE: usize = 16;
+pub const AES_KEYSIZE: usize = 32;
+
+const PTE_LEN: usize = 16;
+/// flag of an entry staged by a compaction, which only counts once the basis root's age matches its tag
+const PT_FLAG_PENDING: u8 = 0b100;
+const KEY_PAGES: usize = 1;
+const MBBB_PAGES: usize = 10;
+const FSCB_PAGES: usize = 16;
+const FASTSPACE_PAGES: usize = 2;
+
+const PDDB_MAGIC: [u8; 4] = [0x50, 0x44, 0x44, 0x42];
+const PDDB_VERSION: u32 = 0x00_00_00_01;
+pub const PDDB_DEFAULT_SYSTEM_BASIS: &str = ".System";
+const PDDB_FAST_SPACE_SYSTEM_BASIS: &str = ".FastSpace";
+
+pub const BASIS_NAME_LEN: usize = 64;
+pub const DICT_NAME_LEN: usize = 111;
+pub const KEY_NAME_LEN: usize = 95;
+const DK_STRIDE: usize = 127;
+const DK_PER_VPAGE: usize = VPAGE_SIZE / DK_STRIDE;
+const DICT_VSIZE: u64 = 0xFE_0000;
+const DICT_MAXCOUNT: usize = 16383;
+const KEY_MAXCOUNT: usize = 131_071;
+const SMALL_POOL_START: u64 = 0x0000_003F_8000_0000;
+const SMALL_POOL_STRIDE: u64 = 0xFE_0000;
+const LARGE_POOL_START: u64 = 0x0000_FE00_0000_0000;
+
+/// Prefix of the key names generated by the PDDB's hosted tests. These keys carry a murmur3 checksum
+/// of their contents in their last four bytes.
+const SANITYCHECK_PREFIX: &str = "sanitycheck|";
+
+/// A basis name and the AES key that unlocks it.
+#[derive(Clone)]
+pub struct BasisKey {
+    pub name: String,
+    pub key: [u8; AES_KEYSIZE],
+}
+
+/// Parses a `.key` file as written by `EmuStorage::dump_keys()`: a little-endian u32 count, followed by
+/// that many records of a 64-byte, zero-padded basis name and a 32-byte key.
+pub fn parse_key_file(data: &[u8]) -> Result<Vec<BasisKey>, String> {
+    const RECORD_LEN: usize = BASIS_NAME_LEN + AES_KEYSIZE;
+    if data.len() < 4 {
+        return Err("key file is too short to hold a key count".to_string());
+    }
+    let count = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+    if data.len() < 4 + count * RECORD_LEN {
+        return Err(format!("key file claims {} keys, but only has room for {}", count, (data.len() - 4) / RECORD_LEN));
+    }
+    let mut keys = Vec::new();
+    for record in data[4..4 + count * RECORD_LEN].chunks_exact(RECORD_LEN) {
+        keys.push(BasisKey {
+            name: cstr_to_string(&record[..BASIS_NAME_LEN]),
+            key: record[BASIS_NAME_LEN..].try_into().unwrap(),
+        });
+    }
+    Ok(keys)
+}
+
+/// A problem found while decoding an image.
+#[derive(Clone, Debug)]
+pub struct IntegrityError {
+    /// the structure the error was found in, e.g. `.System:dictname:keyname`
+    pub location: String,
+    pub description: String,
+}
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.description)
+    }
+}
+
+/// Physical page record, as used by the FastSpace structures. Mirrors the `PhysPage` bitfield.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PhysPage(pub u32);
+impl PhysPage {
+    pub fn page_number(&self) -> u32 { self.0 & 0xF_FFFF }
+    pub fn clean(&self) -> bool { self.0 & 0x10_0000 != 0 }
+    pub fn valid(&self) -> bool { self.0 & 0x20_0000 != 0 }
+    pub fn space_state(&self) -> SpaceState {
+        match (self.0 >> 22) & 0x3 {
+            0 => SpaceState::Free,
+            1 => SpaceState::MaybeUsed,
+            2 => SpaceState::Used,
+            _ => SpaceState::Dirty,
+        }
+    }
+    pub fn journal(&self) -> u8 { ((self.0 >> 24) & 0xF) as u8 }
+}
+impl fmt::Display for PhysPage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "p_{:05x} | c_{} | v_{} | ss_{:?} | j_{:02}",
+            self.page_number(), self.clean() as u8, self.valid() as u8, self.space_state(), self.journal())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpaceState {
+    Free,
+    MaybeUsed,
+    Used,
+    Dirty,
+}
+
+/// A decrypted page table entry, along with the physical page it describes.
+#[derive(Copy, Clone, Debug)]
+pub struct PageTableEntry {
+    pub page_number: u32,
+    pub vaddr: u64,
+    pub flags: u8,
+    /// commit tag of a pending entry
+    pub tag: u8,
+    pub nonce: u32,
+}
+impl PageTableEntry {
+    pub fn pending(&self) -> bool { self.flags & PT_FLAG_PENDING != 0 }
+}
+impl fmt::Display for PageTableEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = match self.flags & !PT_FLAG_PENDING {
+            1 => "CLN",
+            2 => "CHK",
+            3 => "COK",
+            _ => "INV",
+        };
+        write!(f, "p_{:05x} -> v_{:012x} | f_{}", self.page_number, self.vaddr, flags)?;
+        if self.pending() {
+            write!(f, " PND_{:02x}", self.tag)?;
+        }
+        write!(f, " | n_{:08x}", self.nonce)
+    }
+}
+
+pub struct Key {
+    pub name: String,
+    pub descriptor_index: u32,
+    pub start: u64,
+    pub len: u64,
+    pub reserved: u64,
+    pub flags: u32,
+    pub age: u32,
+    /// None if any part of the key's data could not be recovered
+    pub data: Option<Vec<u8>>,
+    /// Some(result) for keys written by the PDDB's hosted tests, which carry a checksum of their data
+    pub checksum_ok: Option<bool>,
+}
+impl Key {
+    pub fn valid(&self) -> bool { self.flags & 1 != 0 }
+    pub fn unresolved(&self) -> bool { self.flags & 2 != 0 }
+}
+
+pub struct Dict {
+    pub name: String,
+    pub index: u32,
+    pub flags: u32,
+    pub age: u32,
+    pub num_keys: u32,
+    pub free_key_index: u32,
+    pub keys: Vec<Key>,
+}
+
+pub struct Basis {
+    pub name: String,
+    pub journal: u32,
+    pub age: u32,
+    pub num_dicts: u32,
+    pub dicts: Vec<Dict>,
+    /// the entries of the page table that belong to this basis
+    pub ptes: Vec<PageTableEntry>,
+    /// the resolved virtual to physical page map, after journal conflicts are settled
+    pub v2p: HashMap<u64, u32>,
+}
+
+pub struct SpaceUpdate {
+    /// offset of the record from the start of the FSCB region
+    pub offset: usize,
+    /// None if the record failed its checksum
+    pub page: Option<PhysPage>,
+}
+
+pub struct Fscb {
+    /// page offset of the FastSpace record within the FSCB region
+    pub record_page: Option<usize>,
+    pub free_pool: Vec<PhysPage>,
+    pub updates: Vec<SpaceUpdate>,
+    /// the free space state after the update records are applied, keyed by page number
+    pub merged: HashMap<u32, PhysPage>,
+}
+
+/// Everything that could be decoded from an image
+pub struct Report {
+    pub bases: Vec<Basis>,
+    pub fscb: Option<Fscb>,
+    pub errors: Vec<IntegrityError>,
+}
+
+/// A PDDB image, with its regions located the same way `PddbOs::new()` lays them out.
+pub struct PddbImage<'a> {
+    raw: &'a [u8],
+    dna: u64,
+    pt_len: usize,
+    mbbb_base: usize,
+    fscb_base: usize,
+    data_base: usize,
+}
+impl<'a> PddbImage<'a> {
+    /// `dna` is the FPGA DNA of the device the image came from; the hosted emulation uses 0.
+    pub fn new(raw: &'a [u8], dna: u64) -> Result<PddbImage<'a>, String> {
+        if raw.len() % PAGE_SIZE != 0 {
+            return Err(format!("image length 0x{:x} is not a multiple of the page size", raw.len()));
+        }
+        let pt_len = (raw.len() / PAGE_SIZE) * PTE_LEN;
+        // the key page starts on the first page boundary past the page table
+        let mbbb_base = (pt_len + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE + KEY_PAGES * PAGE_SIZE;
+        let fscb_base = mbbb_base + MBBB_PAGES * PAGE_SIZE;
+        let data_base = fscb_base + FSCB_PAGES * PAGE_SIZE;
+        if data_base >= raw.len() {
+            return Err(format!("image length 0x{:x} is too short to hold a PDDB", raw.len()));
+        }
+        Ok(PddbImage { raw, dna, pt_len, mbbb_base, fscb_base, data_base })
+    }
+    /// number of physical pages in the data region
+    pub fn data_pages(&self) -> usize {
+        (self.raw.len() - self.data_base) / PAGE_SIZE
+    }
+
+    /// Decodes every structure that the given keys unlock.
+    pub fn decode(&self, keys: &[BasisKey]) -> Report {
+        let mut errors = Vec::new();
+        let mut bases = Vec::new();
+        let mut owners = HashMap::<u32, String>::new();
+        for key in keys {
+            let ptes = self.page_table(key, &mut errors);
+            for pte in ptes.iter() {
+                if let Some(prev) = owners.insert(pte.page_number, key.name.clone()) {
+                    if prev != key.name {
+                        errors.push(err(&key.name, format!("page 0x{:x} is also claimed by basis {}", pte.page_number, prev)));
+                    }
+                }
+            }
+            if ptes.len() == 0 {
+                errors.push(err(&key.name, "no page table entries decrypt with this key".to_string()));
+                continue;
+            }
+            if let Some(basis) = self.basis(key, ptes, &mut errors) {
+                bases.push(basis);
+            }
+        }
+        let fscb = keys.iter().find(|k| k.name == PDDB_DEFAULT_SYSTEM_BASIS)
+            .and_then(|k| self.fscb(&k.key, &mut errors));
+        if let Some(fscb) = &fscb {
+            for pp in fscb.merged.values() {
+                if pp.valid() && pp.space_state() == SpaceState::Free {
+                    if let Some(owner) = owners.get(&pp.page_number()) {
+                        errors.push(err("FSCB", format!("page 0x{:x} is marked free, but is mapped by basis {}", pp.page_number(), owner)));
+                    }
+                }
+            }
+        }
+        Report { bases, fscb, errors }
+    }
+
+    /// Returns the page table page at `index`, substituting the MBBB copy if the page was caught
+    /// mid-update (i.e. it's blank).
+    fn pt_page(&self, index: usize, errors: &mut Vec<IntegrityError>) -> &'a [u8] {
+        let page = &self.raw[index * PAGE_SIZE..((index + 1) * PAGE_SIZE).min(self.pt_len)];
+        if page[..AES_BLOCK_SIZE].iter().all(|&b| b == 0xFF) {
+            let mbbb = &self.raw[self.mbbb_base..self.mbbb_base + MBBB_PAGES * PAGE_SIZE];
+            if let Some(stashed) = mbbb.chunks(PAGE_SIZE).find(|p| !p[..AES_BLOCK_SIZE].iter().all(|&b| b == 0xFF)) {
+                return stashed;
+            }
+            errors.push(err("page table", format!("page {} is blank, and there is no MBBB copy of it", index)));
+        }
+        page
+    }
+    fn page_table(&self, key: &BasisKey, errors: &mut Vec<IntegrityError>) -> Vec<PageTableEntry> {
+        let cipher = Aes256::new(GenericArray::from_slice(&key.key));
+        let mut ptes = Vec::new();
+        for page_index in 0..(self.pt_len + PAGE_SIZE - 1) / PAGE_SIZE {
+            let page = self.pt_page(page_index, errors);
+            for (index, ct) in page.chunks_exact(PTE_LEN).enumerate() {
+                let mut block = GenericArray::clone_from_slice(ct);
+                cipher.decrypt_block(&mut block);
+                let nonce = u32::from_le_bytes(block[8..12].try_into().unwrap());
+                if u32::from_le_bytes(block[12..16].try_into().unwrap()) == murmur3_32(&block[..12], nonce) {
+                    let mut vaddr = [0u8; 8];
+                    vaddr[..6].copy_from_slice(&block[..6]);
+                    let pte = PageTableEntry {
+                        page_number: (page_index * PAGE_SIZE / PTE_LEN + index) as u32,
+                        vaddr: u64::from_le_bytes(vaddr),
+                        flags: block[6],
+                        tag: block[7],
+                        nonce,
+                    };
+                    if pte.page_number as usize >= self.data_pages() {
+                        errors.push(err(&key.name, format!("PTE maps vaddr 0x{:x} past the end of the data region", pte.vaddr)));
+                    } else if pte.vaddr == 0 {
+                        errors.push(err(&key.name, format!("PTE for page 0x{:x} maps a null vaddr", pte.page_number)));
+                    } else {
+                        ptes.push(pte);
+                    }
+                }
+            }
+        }
+        ptes
+    }
+
+    /// Decrypts a data page, returning its plaintext with the journal revision still at the front.
+    pub fn data_page(&self, cipher: &Aes256GcmSiv, aad: &[u8], page_number: u32) -> Option<Vec<u8>> {
+        let base = self.data_base + page_number as usize * PAGE_SIZE;
+        if base + PAGE_SIZE > self.raw.len() {
+            return None;
+        }
+        let page = &self.raw[base..base + PAGE_SIZE];
+        cipher.decrypt(Nonce::from_slice(&page[..NONCE_LEN]), Payload { msg: &page[NONCE_LEN..], aad }).ok()
+    }
+    fn data_aad(&self, name: &str) -> Vec<u8> {
+        let mut aad = vec![0u8; BASIS_NAME_LEN];
+        for (&src, dst) in name.as_bytes().iter().zip(aad.iter_mut()) {
+            *dst = src;
+        }
+        aad.extend_from_slice(&PDDB_VERSION.to_le_bytes());
+        aad.extend_from_slice(&self.dna.to_le_bytes());
+        aad
+    }
+
+    fn basis(&self, key: &BasisKey, ptes: Vec<PageTableEntry>, errors: &mut Vec<IntegrityError>) -> Option<Basis> {
+        let cipher = Aes256GcmSiv::new(AeadKey::from_slice(&key.key));
+        let aad = self.data_aad(&key.name);
+        let loc = key.name.as_str();
+
+        // resolve duplicate mappings of a vaddr by picking the page with the newest journal revision
+        let mut v2p = HashMap::<u64, (u32, Option<u32>)>::new();
+        let resolve = |v2p: &mut HashMap<u64, (u32, Option<u32>)>, pte: &PageTableEntry, errors: &mut Vec<IntegrityError>| {
+            let journal = self.data_page(&cipher, &aad, pte.page_number)
+                .map(|d| u32::from_le_bytes(d[..JOURNAL_LEN].try_into().unwrap()));
+            match v2p.get(&pte.vaddr) {
+                Some(&(prev_page, prev_journal)) => {
+                    if journal.is_some() && journal == prev_journal {
+                        errors.push(err(loc, format!("vaddr 0x{:x} is mapped to pages 0x{:x} and 0x{:x} with the same journal revision",
+                            pte.vaddr, prev_page, pte.page_number)));
+                    } else if journal > prev_journal {
+                        v2p.insert(pte.vaddr, (pte.page_number, journal));
+                    }
+                }
+                None => {
+                    v2p.insert(pte.vaddr, (pte.page_number, journal));
+                }
+            }
+        };
+        for pte in ptes.iter().filter(|pte| !pte.pending()) {
+            resolve(&mut v2p, pte, errors);
+        }
+        // pending entries count only if the basis root found above commits them, which is how the PDDB mounts too
+        let committed_tag = v2p.get(&(VPAGE_SIZE as u64))
+            .and_then(|&(pp, _)| self.data_page(&cipher, &aad, pp))
+            .map(|root| root[JOURNAL_LEN + 8] /* low byte of the age */);
+        for pte in ptes.iter().filter(|pte| pte.pending()) {
+            if Some(pte.tag) == committed_tag {
+                resolve(&mut v2p, pte, errors);
+            } else {
+                errors.push(err(loc, format!("page 0x{:x} has a pending entry for vaddr 0x{:x} from a compaction that never committed",
+                    pte.page_number, pte.vaddr)));
+            }
+        }
+        let v2p: HashMap<u64, u32> = v2p.into_iter().map(|(va, (pp, _))| (va, pp)).collect();
+
+        let root = match v2p.get(&(VPAGE_SIZE as u64)) {
+            Some(&pp) => match self.data_page(&cipher, &aad, pp) {
+                Some(root) => root,
+                None => {
+                    errors.push(err(loc, format!("basis root at page 0x{:x} does not decrypt", pp)));
+                    return None;
+                }
+            }
+            None => {
+                errors.push(err(loc, "basis root page is not mapped".to_string()));
+                return None;
+            }
+        };
+        let r = &root[JOURNAL_LEN..];
+        if r[..4] != PDDB_MAGIC {
+            errors.push(err(loc, format!("bad basis magic {:x?}", &r[..4])));
+        }
+        let version = u32::from_le_bytes(r[4..8].try_into().unwrap());
+        if version != PDDB_VERSION {
+            errors.push(err(loc, format!("unsupported basis version 0x{:x}", version)));
+        }
+        let root_name = cstr_to_string(&r[16..16 + BASIS_NAME_LEN]);
+        if root_name != key.name {
+            errors.push(err(loc, format!("basis root is named {}", root_name)));
+        }
+        let mut basis = Basis {
+            name: key.name.clone(),
+            journal: u32::from_le_bytes(root[..JOURNAL_LEN].try_into().unwrap()),
+            age: u32::from_le_bytes(r[8..12].try_into().unwrap()),
+            num_dicts: u32::from_le_bytes(r[12..16].try_into().unwrap()),
+            dicts: Vec::new(),
+            ptes,
+            v2p,
+        };
+
+        for index in 1..=DICT_MAXCOUNT as u32 {
+            if basis.dicts.len() >= basis.num_dicts as usize {
+                break;
+            }
+            if let Some(dict) = self.dict(&basis, &cipher, &aad, index, errors) {
+                if basis.dicts.iter().any(|d| d.name == dict.name) {
+                    errors.push(err(loc, format!("dictionary {} appears more than once", dict.name)));
+                }
+                basis.dicts.push(dict);
+            }
+        }
+        if basis.dicts.len() != basis.num_dicts as usize {
+            errors.push(err(loc, format!("basis root records {} dictionaries, but {} were found", basis.num_dicts, basis.dicts.len())));
+        }
+        self.check_extents(&basis, errors);
+        Some(basis)
+    }
+
+    fn dict(&self, basis: &Basis, cipher: &Aes256GcmSiv, aad: &[u8], index: u32, errors: &mut Vec<IntegrityError>) -> Option<Dict> {
+        let dict_offset = index as u64 * DICT_VSIZE;
+        let pp = *basis.v2p.get(&dict_offset)?;
+        let page = match self.data_page(cipher, aad, pp) {
+            Some(page) => page,
+            None => {
+                errors.push(err(&basis.name, format!("dictionary {} header at page 0x{:x} does not decrypt", index, pp)));
+                return None;
+            }
+        };
+        let d = &page[JOURNAL_LEN..];
+        let flags = u32::from_le_bytes(d[..4].try_into().unwrap());
+        if flags & 1 == 0 {
+            return None;
+        }
+        let mut dict = Dict {
+            name: cstr_to_string(&d[16..16 + DICT_NAME_LEN]),
+            index,
+            flags,
+            age: u32::from_le_bytes(d[4..8].try_into().unwrap()),
+            num_keys: u32::from_le_bytes(d[8..12].try_into().unwrap()),
+            free_key_index: u32::from_le_bytes(d[12..16].try_into().unwrap()),
+            keys: Vec::new(),
+        };
+        let loc = format!("{}:{}", basis.name, dict.name);
+
+        let mut try_entry = 1;
+        let mut cached: Option<(usize, Option<Vec<u8>>)> = None;
+        while try_entry < KEY_MAXCOUNT && dict.keys.len() < dict.num_keys as usize {
+            let vpage_num = try_entry / DK_PER_VPAGE;
+            if cached.as_ref().map_or(true, |(n, _)| *n != vpage_num) {
+                let vpage = dict_offset + (vpage_num * VPAGE_SIZE) as u64;
+                let data = match basis.v2p.get(&vpage) {
+                    Some(&pp) => {
+                        let data = self.data_page(cipher, aad, pp);
+                        if data.is_none() {
+                            errors.push(err(&loc, format!("key descriptor page 0x{:x} does not decrypt", pp)));
+                        }
+                        data
+                    }
+                    None => None,
+                };
+                cached = Some((vpage_num, data));
+            }
+            let page = match &cached {
+                Some((_, Some(page))) => page,
+                _ => {
+                    // nothing allocated here, so every entry on this page is unused
+                    try_entry = (vpage_num + 1) * DK_PER_VPAGE;
+                    continue;
+                }
+            };
+            let start = JOURNAL_LEN + (try_entry % DK_PER_VPAGE) * DK_STRIDE;
+            let desc = &page[start..start + DK_STRIDE];
+            let flags = u32::from_le_bytes(desc[24..28].try_into().unwrap());
+            if flags & 1 != 0 {
+                let mut key = Key {
+                    name: cstr_to_string(&desc[32..32 + KEY_NAME_LEN]),
+                    descriptor_index: try_entry as u32,
+                    start: u64::from_le_bytes(desc[..8].try_into().unwrap()),
+                    len: u64::from_le_bytes(desc[8..16].try_into().unwrap()),
+                    reserved: u64::from_le_bytes(desc[16..24].try_into().unwrap()),
+                    flags,
+                    age: u32::from_le_bytes(desc[28..32].try_into().unwrap()),
+                    data: None,
+                    checksum_ok: None,
+                };
+                self.check_key(basis, cipher, aad, &dict, &mut key, errors);
+                if dict.keys.iter().any(|k| k.name == key.name) {
+                    errors.push(err(&loc, format!("key {} appears more than once", key.name)));
+                }
+                dict.keys.push(key);
+            }
+            try_entry += 1;
+        }
+        if dict.keys.len() != dict.num_keys as usize {
+            errors.push(err(&loc, format!("dictionary records {} keys, but {} were found", dict.num_keys, dict.keys.len())));
+        }
+        Some(dict)
+    }
+
+    fn check_key(&self, basis: &Basis, cipher: &Aes256GcmSiv, aad: &[u8], dict: &Dict, key: &mut Key, errors: &mut Vec<IntegrityError>) {
+        let loc = format!("{}:{}:{}", basis.name, dict.name, key.name);
+        if key.len > key.reserved {
+            errors.push(err(&loc, format!("length {} exceeds the reserved space {}", key.len, key.reserved)));
+        }
+        if key.unresolved() {
+            errors.push(err(&loc, "key was committed with an unresolved start address".to_string()));
+        }
+        if key.start < LARGE_POOL_START {
+            let pool_start = SMALL_POOL_START + dict.index as u64 * SMALL_POOL_STRIDE;
+            if key.start < pool_start || key.start + key.reserved > pool_start + SMALL_POOL_STRIDE {
+                errors.push(err(&loc, format!("small key at 0x{:x} is outside of its dictionary's small pool", key.start)));
+                return;
+            }
+            if key.len > 0 && (key.start % VPAGE_SIZE as u64) + key.len > VPAGE_SIZE as u64 {
+                errors.push(err(&loc, format!("small key at 0x{:x} straddles a page boundary", key.start)));
+                return;
+            }
+        }
+
+        let mut data = Vec::with_capacity(key.len as usize);
+        let mut pos = key.start;
+        while pos < key.start + key.len {
+            let vpage = (pos / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
+            let offset = (pos - vpage) as usize;
+            let chunk = ((VPAGE_SIZE - offset) as u64).min(key.start + key.len - pos) as usize;
+            match basis.v2p.get(&vpage).and_then(|&pp| self.data_page(cipher, aad, pp)) {
+                Some(page) => data.extend_from_slice(&page[JOURNAL_LEN + offset..JOURNAL_LEN + offset + chunk]),
+                None => {
+                    if basis.v2p.contains_key(&vpage) {
+                        errors.push(err(&loc, format!("data page at vaddr 0x{:x} does not decrypt", vpage)));
+                    } else {
+                        errors.push(err(&loc, format!("data page at vaddr 0x{:x} is not mapped", vpage)));
+                    }
+                    return;
+                }
+            }
+            pos += chunk as u64;
+        }
+        if key.name.starts_with(SANITYCHECK_PREFIX) && data.len() >= 4 {
+            let (payload, sum) = data.split_at(data.len() - 4);
+            let mut check = payload.to_vec();
+            while check.len() % 4 != 0 {
+                check.push(0);
+            }
+            let ok = murmur3_32(&check, 0) == u32::from_le_bytes(sum.try_into().unwrap());
+            if !ok {
+                errors.push(err(&loc, "data does not match its embedded checksum".to_string()));
+            }
+            key.checksum_ok = Some(ok);
+        }
+        key.data = Some(data);
+    }
+
+    /// Checks that no two keys in a basis claim the same virtual space.
+    fn check_extents(&self, basis: &Basis, errors: &mut Vec<IntegrityError>) {
+        let mut extents = Vec::new();
+        for dict in basis.dicts.iter() {
+            for key in dict.keys.iter() {
+                if key.reserved > 0 {
+                    extents.push((key.start, key.start + key.reserved, format!("{}:{}", dict.name, key.name)));
+                }
+            }
+        }
+        extents.sort_by_key(|e| e.0);
+        for pair in extents.windows(2) {
+            if pair[1].0 < pair[0].1 {
+                errors.push(err(&basis.name, format!("keys {} and {} overlap at vaddr 0x{:x}", pair[0].2, pair[1].2, pair[1].0)));
+            }
+        }
+    }
+
+    fn fscb(&self, system_key: &[u8; AES_KEYSIZE], errors: &mut Vec<IntegrityError>) -> Option<Fscb> {
+        let region = &self.raw[self.fscb_base..self.fscb_base + FSCB_PAGES * PAGE_SIZE];
+        let blank = |s: &[u8]| s.iter().all(|&b| b == 0xFF);
+        let mut fscb = Fscb { record_page: None, free_pool: Vec::new(), updates: Vec::new(), merged: HashMap::new() };
+        let mut log_pages = Vec::new();
+        for (index, page) in region.chunks(PAGE_SIZE).enumerate() {
+            if blank(&page[..AES_BLOCK_SIZE]) {
+                if !blank(&page[AES_BLOCK_SIZE..2 * AES_BLOCK_SIZE]) {
+                    log_pages.push(index);
+                }
+            } else if fscb.record_page.is_none() {
+                fscb.record_page = Some(index);
+            }
+        }
+        match fscb.record_page {
+            Some(index) if index + FASTSPACE_PAGES <= FSCB_PAGES => {
+                let record = &region[index * PAGE_SIZE..(index + FASTSPACE_PAGES) * PAGE_SIZE];
+                let mut aad = Vec::new();
+                aad.extend_from_slice(PDDB_FAST_SPACE_SYSTEM_BASIS.as_bytes());
+                aad.extend_from_slice(&PDDB_VERSION.to_le_bytes());
+                aad.extend_from_slice(&self.dna.to_le_bytes());
+                let cipher = Aes256GcmSiv::new(AeadKey::from_slice(system_key));
+                match cipher.decrypt(Nonce::from_slice(&record[..NONCE_LEN]), Payload { msg: &record[NONCE_LEN..], aad: &aad }) {
+                    Ok(pool) => {
+                        for raw in pool.chunks_exact(4) {
+                            let pp = PhysPage(u32::from_le_bytes(raw.try_into().unwrap()));
+                            if pp.valid() {
+                                fscb.free_pool.push(pp);
+                                fscb.merged.insert(pp.page_number(), pp);
+                            }
+                        }
+                    }
+                    Err(_) => errors.push(err("FSCB", format!("FastSpace record at FSCB page {} does not decrypt", index))),
+                }
+            }
+            Some(index) => errors.push(err("FSCB", format!("FastSpace record at FSCB page {} runs past the end of the FSCB", index))),
+            None => errors.push(err("FSCB", "no FastSpace record found".to_string())),
+        }
+
+        let cipher = Aes256::new(GenericArray::from_slice(system_key));
+        for index in log_pages {
+            let page = &region[index * PAGE_SIZE..(index + 1) * PAGE_SIZE];
+            for (slot, ct) in page[AES_BLOCK_SIZE..].chunks_exact(AES_BLOCK_SIZE).enumerate() {
+                if blank(ct) {
+                    break;
+                }
+                let mut block = GenericArray::clone_from_slice(ct);
+                cipher.decrypt_block(&mut block);
+                // note that the seed is a big-endian re-encoding of part of the nonce
+                let seed = u32::from_be_bytes(block[4..8].try_into().unwrap());
+                let offset = index * PAGE_SIZE + (slot + 1) * AES_BLOCK_SIZE;
+                if u32::from_le_bytes(block[12..16].try_into().unwrap()) == murmur3_32(&block[..12], seed) {
+                    let pp = PhysPage(u32::from_le_bytes(block[8..12].try_into().unwrap()));
+                    match fscb.merged.get(&pp.page_number()) {
+                        Some(prev) if prev.journal() == pp.journal() => {
+                            errors.push(err("FSCB", format!("update at offset 0x{:x} repeats journal revision {} of page 0x{:x}",
+                                offset, pp.journal(), pp.page_number())));
+                        }
+                        Some(prev) if prev.journal() > pp.journal() => (),
+                        _ => { fscb.merged.insert(pp.page_number(), pp); }
+                    }
+                    fscb.updates.push(SpaceUpdate { offset, page: Some(pp) });
+                } else {
+                    errors.push(err("FSCB", format!("update record at offset 0x{:x} fails its checksum", offset)));
+                    fscb.updates.push(SpaceUpdate { offset, page: None });
+                }
+            }
+        }
+        Some(fscb)
+    }
+}
+
+fn err(location: &str, description: String) -> IntegrityError {
+    IntegrityError { location: location.to_string(), description }
+}
+
+fn cstr_to_string(cstr: &[u8]) -> String {
+    let len = cstr.iter().position(|&b| b == 0).unwrap_or(cstr.len());
+    String::from_utf8_lossy(&cstr[..len]).to_string()
+}
+
+/// Same murmur3 variant as `services/pddb/src/backend/murmur3.rs`; only operates on whole words.
+fn murmur3_32(source: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mut state = seed;
+    for k8 in source.chunks_exact(4) {
+        let k = u32::from_le_bytes(k8.try_into().unwrap());
+        state ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        state = state.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    let mut hash = state ^ (source.len() as u32 & !3);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncrypt;
+    use std::mem::size_of;
+
+    /// The PDDB sources that the layout constants above are taken from.
+    const PDDB_SOURCES: [&str; 7] = [
+        include_str!("../../services/pddb/src/api.rs"),
+        include_str!("../../services/pddb/src/backend/hw.rs"),
+        include_str!("../../services/pddb/src/backend/types.rs"),
+        include_str!("../../services/pddb/src/backend/basis.rs"),
+        include_str!("../../services/pddb/src/backend/fastspace.rs"),
+        include_str!("../../services/pddb/src/backend/pagetable.rs"),
+        include_str!("../../services/spinor/src/api.rs"),
+    ];
+    const PT_FLAG_CLEAN: u8 = 0b1;
+
+    /// Returns the right hand side of the PDDB's `const NAME: type = <value>;` definition of `name`.
+    fn pddb_definition(name: &str) -> String {
+        let pattern = format!("const {}", name);
+        for line in PDDB_SOURCES.iter().flat_map(|source| source.lines()) {
+            let line = line.split_whitespace().collect::<Vec<&str>>().join(" ");
+            if line.starts_with("//") {
+                continue;
+            }
+            if let Some(start) = line.find(&pattern) {
+                let rest = line[start + pattern.len()..].trim_start();
+                // the ones in a `bitflags!` have no type
+                if !rest.starts_with(':') && !rest.starts_with('=') {
+                    continue;
+                }
+                let value = &rest[rest.find('=').expect("definition without a value") + 1..];
+                return value[..value.find(';').expect("definition spans several lines")].trim().to_string();
+            }
+        }
+        panic!("no definition of {} in the PDDB sources", name);
+    }
+
+    fn pddb_type_size(name: &str) -> usize {
+        match name {
+            "Nonce" => size_of::<aes_gcm_siv::Nonce>(),
+            "Tag" => size_of::<aes_gcm_siv::Tag>(),
+            "u8" => 1,
+            "u16" => 2,
+            "u32" => 4,
+            "u64" => 8,
+            _ => {
+                let pattern = format!("type {} = ", name);
+                let line = PDDB_SOURCES.iter().flat_map(|source| source.lines())
+                    .find(|line| line.contains(&pattern))
+                    .unwrap_or_else(|| panic!("no definition of type {} in the PDDB sources", name));
+                let alias = &line[line.find(&pattern).unwrap() + pattern.len()..];
+                pddb_type_size(alias.trim_end_matches(';').trim())
+            }
+        }
+    }
+
+    fn pddb_operand(token: &str) -> u64 {
+        if let Some(ty) = token.strip_prefix("size_of::<").and_then(|t| t.strip_suffix(">()")) {
+            return pddb_type_size(ty) as u64;
+        }
+        let token = token.rsplit("::").next().unwrap();
+        if !token.starts_with(|c: char| c.is_ascii_digit()) {
+            return pddb_value(token);
+        }
+        let digits = token.replace('_', "");
+        if let Some(hex) = digits.strip_prefix("0x") {
+            u64::from_str_radix(hex, 16).unwrap()
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            u64::from_str_radix(bin, 2).unwrap()
+        } else {
+            digits.parse().unwrap()
+        }
+    }
+
+    /// Evaluates a PDDB constant. Covers what its layout is defined with: literals, other constants,
+    /// `size_of` a type, casts, and `+ - * /` without parentheses.
+    fn pddb_value(name: &str) -> u64 {
+        let expr = pddb_definition(name).replace(" as usize", "").replace(" as u64", "").replace(" as u32", "");
+        let tokens: Vec<&str> = expr.split_whitespace().collect();
+        let mut sum = 0i128;
+        let mut negate = false;
+        let mut term = pddb_operand(tokens[0]) as i128;
+        for pair in tokens[1..].chunks(2) {
+            let operand = pddb_operand(pair[1]) as i128;
+            match pair[0] {
+                "*" => term *= operand,
+                "/" => term /= operand,
+                "+" | "-" => {
+                    sum += if negate { -term } else { term };
+                    negate = pair[0] == "-";
+                    term = operand;
+                }
+                op => panic!("can't evaluate operator {} in the definition of {}", op, name),
+            }
+        }
+        sum += if negate { -term } else { term };
+        sum as u64
+    }
+
+    /// Field names, offsets and lengths of the packed `Pte` struct.
+    fn pddb_pte_layout() -> Vec<(String, usize, usize)> {
+        const PTE_DECL: &str = "pub(crate) struct Pte {";
+        let source = PDDB_SOURCES.iter().find(|source| source.contains(PTE_DECL)).unwrap();
+        let decl = &source[source.find(PTE_DECL).unwrap() + PTE_DECL.len()..];
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        for line in decl[..decl.find('}').unwrap()].lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let mut parts = line.trim_end_matches(',').splitn(2, ':');
+            let name = parts.next().unwrap().trim().to_string();
+            let len = match parts.next().unwrap().trim() {
+                "u8" | "PtFlags" => 1,
+                array => array.strip_prefix("[u8;").and_then(|a| a.strip_suffix(']'))
+                    .unwrap_or_else(|| panic!("unexpected type of Pte field {}", name))
+                    .trim().parse().unwrap(),
+            };
+            fields.push((name, offset, len));
+            offset += len;
+        }
+        fields
+    }
+
+    #[test]
+    fn layout_matches_pddb() {
+        let values: [(&str, u64); 20] = [
+            ("PAGE_SIZE", PAGE_SIZE as u64),
+            ("VPAGE_SIZE", VPAGE_SIZE as u64),
+            ("AES_KEYSIZE", AES_KEYSIZE as u64),
+            ("MBBB_PAGES", MBBB_PAGES as u64),
+            ("FSCB_PAGES", FSCB_PAGES as u64),
+            ("FASTSPACE_PAGES", FASTSPACE_PAGES as u64),
+            ("PDDB_VERSION", PDDB_VERSION as u64),
+            ("BASIS_NAME_LEN", BASIS_NAME_LEN as u64),
+            ("DICT_NAME_LEN", DICT_NAME_LEN as u64),
+            ("KEY_NAME_LEN", KEY_NAME_LEN as u64),
+            ("DK_STRIDE", DK_STRIDE as u64),
+            ("DK_PER_VPAGE", DK_PER_VPAGE as u64),
+            ("DICT_VSIZE", DICT_VSIZE),
+            ("DICT_MAXCOUNT", DICT_MAXCOUNT as u64),
+            ("KEY_MAXCOUNT", KEY_MAXCOUNT as u64),
+            ("SMALL_POOL_START", SMALL_POOL_START),
+            ("SMALL_POOL_STRIDE", SMALL_POOL_STRIDE),
+            ("LARGE_POOL_START", LARGE_POOL_START),
+            ("CLEAN", PT_FLAG_CLEAN as u64),
+            ("PENDING", PT_FLAG_PENDING as u64),
+        ];
+        for &(name, value) in values.iter() {
+            assert_eq!(pddb_value(name), value, "{} doesn't match the PDDB's definition", name);
+        }
+        assert_eq!(NONCE_LEN, pddb_type_size("Nonce"));
+        assert_eq!(TAG_LEN, pddb_type_size("Tag"));
+        assert_eq!(JOURNAL_LEN, pddb_type_size("JournalType"));
+
+        assert_eq!(pddb_definition("PDDB_DEFAULT_SYSTEM_BASIS").trim_matches('"'), PDDB_DEFAULT_SYSTEM_BASIS);
+        assert_eq!(pddb_definition("PDDB_FAST_SPACE_SYSTEM_BASIS").trim_matches('"'), PDDB_FAST_SPACE_SYSTEM_BASIS);
+        let magic: Vec<u8> = pddb_definition("PDDB_MAGIC").trim_matches(|c| c == '[' || c == ']')
+            .split(',').map(|b| pddb_operand(b.trim()) as u8).collect();
+        assert_eq!(magic, PDDB_MAGIC);
+
+        // the key region is a single page, between the page table and the MBBB
+        assert!(PDDB_SOURCES.iter().any(|s| s.contains("let mbbb_phys_base = key_phys_base + PageAlignedPa::from(PAGE_SIZE);")));
+        assert_eq!(KEY_PAGES, 1);
+        let pte: Vec<(String, usize, usize)> = vec![
+            ("pddb_addr".to_string(), 0, 6),
+            ("flags".to_string(), 6, 1),
+            ("reserved".to_string(), 7, 1),
+            ("nonce".to_string(), 8, 4),
+            ("checksum".to_string(), 12, 4),
+        ];
+        assert_eq!(pddb_pte_layout(), pte);
+        assert_eq!(pte.iter().map(|f| f.2).sum::<usize>(), PTE_LEN);
+    }
+
+    /// Builds images from the same layouts the decoder reads, with deterministic nonces.
+    struct ImageBuilder {
+        raw: Vec<u8>,
+        mbbb_base: usize,
+        fscb_base: usize,
+        data_base: usize,
+        nonce: u32,
+    }
+    /// enough for a page table smaller than a page, and room for 36 pages of data
+    const TEST_PAGES: usize = 64;
+
+    impl ImageBuilder {
+        fn new() -> ImageBuilder {
+            let raw = vec![0xFFu8; TEST_PAGES * PAGE_SIZE];
+            let image = PddbImage::new(&raw, 0).unwrap();
+            let (mbbb_base, fscb_base, data_base) = (image.mbbb_base, image.fscb_base, image.data_base);
+            ImageBuilder { raw, mbbb_base, fscb_base, data_base, nonce: 0 }
+        }
+        fn next_nonce(&mut self) -> u32 {
+            self.nonce += 1;
+            self.nonce
+        }
+        fn pte(&mut self, key: &BasisKey, page: u32, vaddr: u64, flags: u8, tag: u8) {
+            let nonce = self.next_nonce();
+            let mut block = [0u8; PTE_LEN];
+            block[..6].copy_from_slice(&vaddr.to_le_bytes()[..6]);
+            block[6] = flags;
+            block[7] = tag;
+            block[8..12].copy_from_slice(&nonce.to_le_bytes());
+            let checksum = murmur3_32(&block[..12], nonce);
+            block[12..16].copy_from_slice(&checksum.to_le_bytes());
+            let mut block = GenericArray::clone_from_slice(&block);
+            Aes256::new(GenericArray::from_slice(&key.key)).encrypt_block(&mut block);
+            let offset = page as usize * PTE_LEN;
+            self.raw[offset..offset + PTE_LEN].copy_from_slice(&block);
+        }
+        /// `contents` goes after the journal revision, and is padded out to a full page
+        fn data(&mut self, key: &BasisKey, page: u32, journal: u32, contents: &[u8]) {
+            let mut plaintext = journal.to_le_bytes().to_vec();
+            plaintext.extend_from_slice(contents);
+            assert!(plaintext.len() <= JOURNAL_LEN + VPAGE_SIZE);
+            plaintext.resize(JOURNAL_LEN + VPAGE_SIZE, 0);
+            let mut nonce = [0u8; NONCE_LEN];
+            nonce[..4].copy_from_slice(&self.next_nonce().to_le_bytes());
+            let image = PddbImage::new(&self.raw, 0).unwrap();
+            let aad = image.data_aad(&key.name);
+            let ct = Aes256GcmSiv::new(AeadKey::from_slice(&key.key))
+                .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: &aad }).unwrap();
+            let base = self.data_base + page as usize * PAGE_SIZE;
+            self.raw[base..base + NONCE_LEN].copy_from_slice(&nonce);
+            self.raw[base + NONCE_LEN..base + PAGE_SIZE].copy_from_slice(&ct);
+        }
+        fn map(&mut self, key: &BasisKey, page: u32, vaddr: u64, journal: u32, contents: &[u8]) {
+            self.pte(key, page, vaddr, PT_FLAG_CLEAN, 0);
+            self.data(key, page, journal, contents);
+        }
+        fn fastspace(&mut self, system: &BasisKey, fscb_page: usize, free: &[PhysPage]) {
+            let mut pool = vec![0u8; FASTSPACE_PAGES * PAGE_SIZE - NONCE_LEN - TAG_LEN];
+            for (pp, dst) in free.iter().zip(pool.chunks_exact_mut(4)) {
+                dst.copy_from_slice(&pp.0.to_le_bytes());
+            }
+            let mut aad = PDDB_FAST_SPACE_SYSTEM_BASIS.as_bytes().to_vec();
+            aad.extend_from_slice(&PDDB_VERSION.to_le_bytes());
+            aad.extend_from_slice(&0u64.to_le_bytes());
+            let mut nonce = [0u8; NONCE_LEN];
+            nonce[..4].copy_from_slice(&self.next_nonce().to_le_bytes());
+            let ct = Aes256GcmSiv::new(AeadKey::from_slice(&system.key))
+                .encrypt(Nonce::from_slice(&nonce), Payload { msg: &pool, aad: &aad }).unwrap();
+            let base = self.fscb_base + fscb_page * PAGE_SIZE;
+            self.raw[base..base + NONCE_LEN].copy_from_slice(&nonce);
+            self.raw[base + NONCE_LEN..base + FASTSPACE_PAGES * PAGE_SIZE].copy_from_slice(&ct);
+        }
+        /// writes an update record into `slot` of an update log page in the FSCB
+        fn space_update(&mut self, system: &BasisKey, fscb_page: usize, slot: usize, pp: PhysPage) {
+            let seed = self.next_nonce();
+            let mut block = [0u8; AES_BLOCK_SIZE];
+            block[4..8].copy_from_slice(&seed.to_be_bytes());
+            block[8..12].copy_from_slice(&pp.0.to_le_bytes());
+            let checksum = murmur3_32(&block[..12], seed);
+            block[12..16].copy_from_slice(&checksum.to_le_bytes());
+            let mut block = GenericArray::clone_from_slice(&block);
+            Aes256::new(GenericArray::from_slice(&system.key)).encrypt_block(&mut block);
+            let offset = self.fscb_base + fscb_page * PAGE_SIZE + (slot + 1) * AES_BLOCK_SIZE;
+            self.raw[offset..offset + AES_BLOCK_SIZE].copy_from_slice(&block);
+        }
+        fn decode(&self, keys: &[BasisKey]) -> Report {
+            PddbImage::new(&self.raw, 0).unwrap().decode(keys)
+        }
+        /// the decode errors, formatted for matching against
+        fn errors(&self, keys: &[BasisKey]) -> Vec<String> {
+            self.decode(keys).errors.iter().map(|e| e.to_string()).collect()
+        }
+    }
+
+    fn test_key(name: &str, seed: u8) -> BasisKey {
+        let mut key = [0u8; AES_KEYSIZE];
+        for (i, k) in key.iter_mut().enumerate() {
+            *k = seed.wrapping_add(i as u8 * 7);
+        }
+        BasisKey { name: name.to_string(), key }
+    }
+    fn padded(name: &str, len: usize) -> Vec<u8> {
+        let mut padded = name.as_bytes().to_vec();
+        padded.resize(len, 0);
+        padded
+    }
+    fn phys_page(page_number: u32, state: SpaceState, journal: u8) -> PhysPage {
+        PhysPage(page_number | 0x20_0000 | (state as u32) << 22 | (journal as u32) << 24)
+    }
+    fn basis_root(name: &str, age: u32, num_dicts: u32) -> Vec<u8> {
+        let mut root = PDDB_MAGIC.to_vec();
+        root.extend_from_slice(&PDDB_VERSION.to_le_bytes());
+        root.extend_from_slice(&age.to_le_bytes());
+        root.extend_from_slice(&num_dicts.to_le_bytes());
+        root.extend_from_slice(&padded(name, BASIS_NAME_LEN));
+        root
+    }
+    /// A dictionary's first page: its header in slot 0, then descriptors for `keys`, given as
+    /// (name, start, len, reserved).
+    fn dict_page(name: &str, keys: &[(&str, u64, u64, u64)]) -> Vec<u8> {
+        let mut page = vec![0u8; VPAGE_SIZE];
+        page[..4].copy_from_slice(&1u32.to_le_bytes());
+        page[8..12].copy_from_slice(&(keys.len() as u32).to_le_bytes());
+        page[12..16].copy_from_slice(&(keys.len() as u32 + 1).to_le_bytes());
+        page[16..16 + DICT_NAME_LEN].copy_from_slice(&padded(name, DICT_NAME_LEN));
+        for (slot, &(name, start, len, reserved)) in keys.iter().enumerate() {
+            let desc = &mut page[(slot + 1) * DK_STRIDE..(slot + 2) * DK_STRIDE];
+            desc[..8].copy_from_slice(&start.to_le_bytes());
+            desc[8..16].copy_from_slice(&len.to_le_bytes());
+            desc[16..24].copy_from_slice(&reserved.to_le_bytes());
+            desc[24..28].copy_from_slice(&1u32.to_le_bytes());
+            desc[32..32 + KEY_NAME_LEN].copy_from_slice(&padded(name, KEY_NAME_LEN));
+        }
+        page
+    }
+    /// appends the checksum that the PDDB's hosted tests put at the end of their keys
+    fn with_checksum(payload: &[u8]) -> Vec<u8> {
+        let mut check = payload.to_vec();
+        check.resize((payload.len() + 3) & !3, 0);
+        let mut data = payload.to_vec();
+        data.extend_from_slice(&murmur3_32(&check, 0).to_le_bytes());
+        data
+    }
+    fn key_file(keys: &[BasisKey]) -> Vec<u8> {
+        let mut file = (keys.len() as u32).to_le_bytes().to_vec();
+        for key in keys {
+            file.extend_from_slice(&padded(&key.name, BASIS_NAME_LEN));
+            file.extend_from_slice(&key.key);
+        }
+        file
+    }
+
+    fn large_key() -> Vec<u8> {
+        (0..VPAGE_SIZE + 100).map(|i| i as u8).collect()
+    }
+    /// Two bases with a dictionary each. The system basis has a checksummed and a plain key sharing
+    /// a small pool page, and a large key across two pages. The secret basis has two copies of its
+    /// root, and its small pool is on a page written by a compaction that the newer root commits.
+    /// The FSCB has a free pool, and an update record that takes one page out of it.
+    fn sample() -> (ImageBuilder, Vec<BasisKey>) {
+        let system = test_key(PDDB_DEFAULT_SYSTEM_BASIS, 0x11);
+        let secret = test_key("secret", 0x5a);
+        let mut b = ImageBuilder::new();
+        let pool = SMALL_POOL_START + SMALL_POOL_STRIDE; // the small pool of dictionary 1
+        let checked = with_checksum(b"hunter2");
+        let plain = b"plain text";
+        let large = large_key();
+        let (checked_len, plain_len, large_len) = (checked.len() as u64, plain.len() as u64, large.len() as u64);
+
+        b.map(&system, 0, VPAGE_SIZE as u64, 1, &basis_root(PDDB_DEFAULT_SYSTEM_BASIS, 2, 1));
+        b.map(&system, 1, DICT_VSIZE, 1, &dict_page("wifi", &[
+            ("sanitycheck|psk", pool, checked_len, checked_len),
+            ("plain", pool + checked_len, plain_len, plain_len),
+            ("large", LARGE_POOL_START, large_len, 2 * VPAGE_SIZE as u64),
+        ]));
+        let mut small = checked.clone();
+        small.extend_from_slice(plain);
+        b.map(&system, 2, pool, 1, &small);
+        b.map(&system, 3, LARGE_POOL_START, 1, &large[..VPAGE_SIZE]);
+        b.map(&system, 4, LARGE_POOL_START + VPAGE_SIZE as u64, 1, &large[VPAGE_SIZE..]);
+
+        b.map(&secret, 5, VPAGE_SIZE as u64, 1, &basis_root("secret", 2, 1));
+        b.map(&secret, 6, VPAGE_SIZE as u64, 2, &basis_root("secret", 3, 1));
+        b.map(&secret, 7, DICT_VSIZE, 1, &dict_page("notes", &[("todo", pool, 4, 4)]));
+        b.pte(&secret, 8, pool, PT_FLAG_CLEAN | PT_FLAG_PENDING, 3);
+        b.data(&secret, 8, 1, b"test");
+
+        let free: Vec<PhysPage> = (9..20).map(|page| phys_page(page, SpaceState::Free, 0)).collect();
+        b.fastspace(&system, 0, &free);
+        b.space_update(&system, FASTSPACE_PAGES, 0, phys_page(9, SpaceState::Used, 1));
+        (b, vec![system, secret])
+    }
+
+    fn check_sample(report: &Report) {
+        assert!(report.errors.is_empty(), "unexpected errors: {:?}", report.errors);
+        assert_eq!(report.bases.len(), 2);
+
+        let system = &report.bases[0];
+        assert_eq!((system.name.as_str(), system.journal, system.age), (PDDB_DEFAULT_SYSTEM_BASIS, 1, 2));
+        assert_eq!(system.dicts.len(), 1);
+        assert_eq!(system.dicts[0].name, "wifi");
+        let keys = &system.dicts[0].keys;
+        let names: Vec<&str> = keys.iter().map(|k| k.name.as_str()).collect();
+        assert_eq!(names, ["sanitycheck|psk", "plain", "large"]);
+        assert_eq!(keys[0].checksum_ok, Some(true));
+        assert_eq!(keys[0].data.as_deref(), Some(&with_checksum(b"hunter2")[..]));
+        assert_eq!(keys[1].checksum_ok, None);
+        assert_eq!(keys[1].data.as_deref(), Some(&b"plain text"[..]));
+        assert_eq!(keys[2].data, Some(large_key()));
+
+        let secret = &report.bases[1];
+        assert_eq!((secret.name.as_str(), secret.journal, secret.age), ("secret", 2, 3));
+        assert_eq!(secret.v2p[&(VPAGE_SIZE as u64)], 6);
+        assert_eq!(secret.dicts[0].keys[0].data.as_deref(), Some(&b"test"[..]));
+
+        let fscb = report.fscb.as_ref().unwrap();
+        assert_eq!(fscb.record_page, Some(0));
+        assert_eq!(fscb.free_pool.len(), 11);
+        assert_eq!(fscb.updates.len(), 1);
+        assert_eq!(fscb.merged[&9].space_state(), SpaceState::Used);
+        assert_eq!(fscb.merged[&9].journal(), 1);
+    }
+
+    #[test]
+    fn sample_decodes_cleanly() {
+        let (b, keys) = sample();
+        check_sample(&b.decode(&keys));
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let (mut b, keys) = sample();
+        let mut small = with_checksum(b"hunter2");
+        small[0] ^= 1;
+        small.extend_from_slice(b"plain text");
+        b.data(&keys[0], 2, 1, &small);
+        assert_eq!(b.errors(&keys), [".System:wifi:sanitycheck|psk: data does not match its embedded checksum"]);
+    }
+
+    #[test]
+    fn corrupted_data_page() {
+        let (mut b, keys) = sample();
+        let offset = b.data_base + 3 * PAGE_SIZE + 100;
+        b.raw[offset] ^= 1;
+        assert_eq!(b.errors(&keys), [format!(".System:wifi:large: data page at vaddr 0x{:x} does not decrypt", LARGE_POOL_START)]);
+    }
+
+    #[test]
+    fn journal_conflict() {
+        let (mut b, keys) = sample();
+        b.map(&keys[0], 20, VPAGE_SIZE as u64, 1, &basis_root(PDDB_DEFAULT_SYSTEM_BASIS, 2, 1));
+        assert_eq!(b.errors(&keys), [format!(
+            ".System: vaddr 0x{:x} is mapped to pages 0x0 and 0x14 with the same journal revision", VPAGE_SIZE)]);
+    }
+
+    #[test]
+    fn uncommitted_compaction() {
+        let (mut b, keys) = sample();
+        b.pte(&keys[1], 20, DICT_VSIZE, PT_FLAG_CLEAN | PT_FLAG_PENDING, 4);
+        b.data(&keys[1], 20, 2, &dict_page("notes", &[]));
+        assert_eq!(b.errors(&keys), [format!(
+            "secret: page 0x14 has a pending entry for vaddr 0x{:x} from a compaction that never committed", DICT_VSIZE)]);
+    }
+
+    #[test]
+    fn free_page_still_mapped() {
+        let (mut b, keys) = sample();
+        b.space_update(&keys[0], FASTSPACE_PAGES, 1, phys_page(3, SpaceState::Free, 1));
+        assert_eq!(b.errors(&keys), ["FSCB: page 0x3 is marked free, but is mapped by basis .System"]);
+    }
+
+    #[test]
+    fn blank_page_table_page() {
+        let (mut b, keys) = sample();
+        let stashed = b.raw[..PAGE_SIZE].to_vec();
+        b.raw[..PAGE_SIZE].iter_mut().for_each(|byte| *byte = 0xFF);
+        assert!(b.errors(&keys).contains(&"page table: page 0 is blank, and there is no MBBB copy of it".to_string()));
+        // a page table page that was caught mid-update is found in the MBBB instead
+        let mbbb = b.mbbb_base + 3 * PAGE_SIZE;
+        b.raw[mbbb..mbbb + PAGE_SIZE].copy_from_slice(&stashed);
+        check_sample(&b.decode(&keys));
+    }
+
+    #[test]
+    fn key_file_round_trip() {
+        let (_, keys) = sample();
+        let file = key_file(&keys);
+        let parsed = parse_key_file(&file).unwrap();
+        assert_eq!(parsed.len(), keys.len());
+        for (parsed, key) in parsed.iter().zip(keys.iter()) {
+            assert_eq!((&parsed.name, parsed.key), (&key.name, key.key));
+        }
+        assert!(parse_key_file(&file[..file.len() - 1]).is_err());
+        assert!(parse_key_file(&file[..2]).is_err());
+    }
+
+    /// `pddb-images/golden.{bin,key}` hold the image `sample()` builds. Unlike `sample()` itself, they
+    /// don't change along with this module, so they pin down the on-disk format it reads.
+    #[test]
+    fn golden_image() {
+        let keys = parse_key_file(include_bytes!("../pddb-images/golden.key")).unwrap();
+        let report = PddbImage::new(include_bytes!("../pddb-images/golden.bin"), 0).unwrap().decode(&keys);
+        check_sample(&report);
+    }
+
+    /// Regenerates the golden image; run with `cargo test -p tools -- --ignored write_golden_image`
+    #[test]
+    #[ignore]
+    fn write_golden_image() {
+        let (b, keys) = sample();
+        std::fs::write(concat!(env!("CARGO_MANIFEST_DIR"), "/pddb-images/golden.bin"), &b.raw).unwrap();
+        std::fs::write(concat!(env!("CARGO_MANIFEST_DIR"), "/pddb-images/golden.key"), key_file(&keys)).unwrap();
+    }
+}
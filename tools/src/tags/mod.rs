@@ -1,5 +1,6 @@
 pub mod bflg;
 pub mod inie;
 pub mod memory;
+pub mod plim;
 pub mod pnam;
 pub mod xkrn;
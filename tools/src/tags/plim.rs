@@ -0,0 +1,55 @@
+use crate::xous_arguments::{XousArgument, XousArgumentCode, XousSize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub struct ProcessPageLimits {
+    /// The most pages of RAM each process may own
+    limits: BTreeMap<u32, u32>,
+}
+
+impl fmt::Display for ProcessPageLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "    process page limits:")?;
+        for (pid, pages) in self.limits.iter() {
+            writeln!(f, "        PID {}: {} pages", pid, pages)?;
+        }
+        Ok(())
+    }
+}
+
+impl ProcessPageLimits {
+    pub fn new() -> ProcessPageLimits {
+        ProcessPageLimits {
+            limits: BTreeMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, pid: u32, pages: u32) {
+        self.limits.insert(pid, pages);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+}
+
+impl XousArgument for ProcessPageLimits {
+    fn code(&self) -> XousArgumentCode {
+        u32::from_le_bytes(*b"PLim")
+    }
+
+    fn length(&self) -> XousSize {
+        (self.limits.len() * 8) as XousSize
+    }
+
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = 0;
+        for (pid, pages) in self.limits.iter() {
+            written += output.write(&pid.to_le_bytes())?;
+            written += output.write(&pages.to_le_bytes())?;
+        }
+        Ok(written)
+    }
+}
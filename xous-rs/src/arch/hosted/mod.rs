@@ -5,7 +5,7 @@ use std::mem::size_of;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs};
 use std::sync::{Arc, Condvar, Mutex};
 
-use crate::{Result, SysCall, SysCallResult, PID, TID};
+use crate::{Result, SysCall, SysCallResult, ThreadPriority, PID, THREAD_PRIORITY_MAX, TID};
 
 mod mem;
 pub use mem::*;
@@ -120,6 +120,10 @@ impl ProcessKey {
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ProcessInit {
     pub key: ProcessKey,
+    /// Priority of the initial thread of the new process
+    pub priority: ThreadPriority,
+    /// The most pages of RAM the new process may own, or 0 for no limit
+    pub page_limit: usize,
 }
 
 pub struct ProcessArgs {
@@ -217,8 +221,8 @@ pub fn process_to_args(call: usize, init: &ProcessInit) -> [usize; 8] {
         u32::from_le_bytes(init.key.0[4..8].try_into().unwrap()) as _,
         u32::from_le_bytes(init.key.0[8..12].try_into().unwrap()) as _,
         u32::from_le_bytes(init.key.0[12..16].try_into().unwrap()) as _,
-        0,
-        0,
+        init.priority as usize,
+        init.page_limit,
         0,
     ]
 }
@@ -228,10 +232,13 @@ pub fn args_to_process(
     a2: usize,
     a3: usize,
     a4: usize,
-    _a5: usize,
-    _a6: usize,
+    a5: usize,
+    a6: usize,
     _a7: usize,
 ) -> core::result::Result<ProcessInit, crate::Error> {
+    if a5 > THREAD_PRIORITY_MAX as usize {
+        return Err(crate::Error::InvalidSyscall);
+    }
     let mut v = vec![];
     v.extend_from_slice(&(a1 as u32).to_le_bytes());
     v.extend_from_slice(&(a2 as u32).to_le_bytes());
@@ -241,6 +248,8 @@ pub fn args_to_process(
     key.copy_from_slice(&v);
     Ok(ProcessInit {
         key: ProcessKey(key),
+        priority: a5 as ThreadPriority,
+        page_limit: a6,
     })
 }
 
@@ -2,21 +2,49 @@ use std::cell::RefCell;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread_local;
 
-use crate::{Result, TID};
+use crate::{Result, ThreadPriority, THREAD_PRIORITY_DEFAULT, THREAD_PRIORITY_MAX, TID};
 
 thread_local!(pub static THREAD_ID: RefCell<Option<TID>> = RefCell::new(None));
 
 /// Describes the parameters required to create a new thread on this platform.
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct ThreadInit {}
+pub struct ThreadInit {
+    pub priority: ThreadPriority,
+}
+
+impl Default for ThreadInit {
+    fn default() -> Self {
+        ThreadInit {
+            priority: THREAD_PRIORITY_DEFAULT,
+        }
+    }
+}
+
+impl ThreadInit {
+    /// Run the thread at `priority` rather than the default. The priority has
+    /// a register of its own here, so this can't fail.
+    pub fn with_priority(
+        self,
+        priority: ThreadPriority,
+    ) -> core::result::Result<Self, crate::Error> {
+        Ok(ThreadInit { priority })
+    }
+}
 pub struct WaitHandle<T>(std::thread::JoinHandle<T>);
 
-pub fn thread_to_args(call: usize, _init: &ThreadInit) -> [usize; 8] {
-    [call, 0, 0, 0, 0, 0, 0, 0]
+impl<T> WaitHandle<T> {
+    /// Wait for the thread to exit, and return the value it returned.
+    pub fn join(self) -> core::result::Result<T, crate::Error> {
+        self.0.join().map_err(|_| crate::Error::InternalError)
+    }
+}
+
+pub fn thread_to_args(call: usize, init: &ThreadInit) -> [usize; 8] {
+    [call, init.priority as usize, 0, 0, 0, 0, 0, 0]
 }
 
 pub fn args_to_thread(
-    _a1: usize,
+    a1: usize,
     _a2: usize,
     _a3: usize,
     _a4: usize,
@@ -24,14 +52,33 @@ pub fn args_to_thread(
     _a6: usize,
     _a7: usize,
 ) -> core::result::Result<ThreadInit, crate::Error> {
-    Ok(ThreadInit {})
+    if a1 > THREAD_PRIORITY_MAX as usize {
+        return Err(crate::Error::InvalidSyscall);
+    }
+    Ok(ThreadInit {
+        priority: a1 as ThreadPriority,
+    })
+}
+
+/// The priority always has a register of its own here, so both thread
+/// layouts are the same.
+pub fn args_to_thread_with_priority(
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> core::result::Result<ThreadInit, crate::Error> {
+    args_to_thread(a1, a2, a3, a4, a5, a6, a7)
 }
 
 pub fn create_thread_0_pre<U>(_f: &fn() -> U) -> core::result::Result<ThreadInit, crate::Error>
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 pub fn create_thread_1_pre<U>(
     _f: &fn(usize) -> U,
@@ -40,7 +87,7 @@ pub fn create_thread_1_pre<U>(
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 pub fn create_thread_2_pre<U>(
     _f: &fn(usize, usize) -> U,
@@ -50,7 +97,7 @@ pub fn create_thread_2_pre<U>(
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 pub fn create_thread_3_pre<U>(
     _f: &fn(usize, usize, usize) -> U,
@@ -61,7 +108,7 @@ pub fn create_thread_3_pre<U>(
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 pub fn create_thread_4_pre<U>(
     _f: &fn(usize, usize, usize, usize) -> U,
@@ -73,7 +120,7 @@ pub fn create_thread_4_pre<U>(
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 
 pub fn create_thread_0_post<U>(
@@ -144,7 +191,7 @@ where
     T: Send + 'static,
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 
 pub fn create_thread_simple_post<T, U>(
@@ -165,7 +212,17 @@ where
     F: Send + 'static,
     T: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
+}
+
+/// Nothing is set aside for the thread until it's spawned, so there's nothing
+/// to undo.
+pub fn create_thread_abort<F, T>(_init: ThreadInit)
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
 }
 
 /// Spawn a new thread with the given thread ID.
@@ -207,7 +264,7 @@ pub(crate) fn thread_id() -> TID {
         if let Some(tid) = *tid.borrow() {
             return tid;
         }
-        let call = crate::SysCall::CreateThread(ThreadInit {});
+        let call = crate::SysCall::CreateThread(ThreadInit::default());
 
         let fake_tid = FAKE_THREAD_COUNTER.fetch_add(1, Ordering::SeqCst);
         println!(
@@ -1,14 +1,45 @@
-use crate::{MemoryRange, PID, TID};
-use core::convert::TryInto;
+use crate::{MemoryRange, ThreadPriority, PID, THREAD_PRIORITY_DEFAULT, THREAD_PRIORITY_MAX, TID};
+
+// libstd spawns its own threads, so closures are only boxed up when this crate
+// is used on its own.
+#[cfg(not(feature = "rustc-dep-of-std"))]
+extern crate alloc;
 
 mod mem;
 pub use mem::*;
 mod syscall;
 pub use syscall::*;
 
+/// A program to launch with `create_process()`.
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct ProcessArgs {
-    name: [u8; 16],
+pub struct ProcessArgs<'a> {
+    elf: &'a [u8],
+    priority: ThreadPriority,
+    page_limit: usize,
+}
+
+impl<'a> ProcessArgs<'a> {
+    /// Launch the ELF executable held in `elf`, which may live anywhere in
+    /// memory and is no longer needed once the process has been created.
+    pub fn new(elf: &'a [u8]) -> ProcessArgs<'a> {
+        ProcessArgs {
+            elf,
+            priority: THREAD_PRIORITY_DEFAULT,
+            page_limit: 0,
+        }
+    }
+
+    /// Set the priority of the initial thread of the new process.
+    pub fn with_priority(mut self, priority: ThreadPriority) -> ProcessArgs<'a> {
+        self.priority = priority;
+        self
+    }
+
+    /// Limit the number of pages of RAM the new process may own.
+    pub fn with_page_limit(mut self, pages: usize) -> ProcessArgs<'a> {
+        self.page_limit = pages;
+        self
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -20,6 +51,10 @@ pub struct ThreadInit {
     pub arg2: usize,
     pub arg3: usize,
     pub arg4: usize,
+    /// A thread with a priority other than `THREAD_PRIORITY_DEFAULT` takes at
+    /// most three arguments, so set it with `with_priority()`, which checks
+    /// `arg4`. See `thread_to_args()`.
+    pub priority: ThreadPriority,
     // pub name: [u8; 12],
 }
 
@@ -40,9 +75,28 @@ impl ThreadInit {
             arg2,
             arg3,
             arg4,
+            priority: THREAD_PRIORITY_DEFAULT,
             // name,
         }
     }
+
+    /// Run the thread at `priority` rather than the default. A thread with
+    /// any other priority takes at most three arguments, as the priority is
+    /// passed where the fourth would go.
+    ///
+    /// # Errors
+    ///
+    /// * **InvalidSyscall**: The priority isn't the default, and `arg4` is
+    ///   in use
+    pub fn with_priority(
+        self,
+        priority: ThreadPriority,
+    ) -> core::result::Result<Self, crate::Error> {
+        if priority != THREAD_PRIORITY_DEFAULT && self.arg4 != 0 {
+            return Err(crate::Error::InvalidSyscall);
+        }
+        Ok(ThreadInit { priority, ..self })
+    }
 }
 
 impl Default for ThreadInit {
@@ -54,6 +108,7 @@ impl Default for ThreadInit {
             arg2: 0,
             arg3: 0,
             arg4: 0,
+            priority: THREAD_PRIORITY_DEFAULT,
             // name: [0; 12],
         }
     }
@@ -69,25 +124,122 @@ impl ProcessKey {
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ProcessInit {
-    pub key: ProcessKey,
+    /// Page-aligned span of the new process, inside the image, holding its
+    /// code. It's mapped read-only and executable, and the rest of the image
+    /// read-write.
+    pub text_base: usize,
+    pub text_size: usize,
+    /// Priority of the initial thread of the new process
+    pub priority: ThreadPriority,
+    /// The most pages of RAM the new process may own, or 0 for no limit
+    pub page_limit: usize,
+    /// Pages in the calling process holding the program exactly as it should
+    /// appear in memory. They are moved into the new process, not copied.
+    pub image: MemoryRange,
+    /// Address of `image` in the new process
+    pub image_base: usize,
+    pub entrypoint: usize,
 }
 
 pub struct WaitHandle<T> {
     tid: TID,
+    /// `true` if the thread returns a pointer to a boxed `T` rather than
+    /// returning `T` itself in a register
+    boxed: bool,
     data: core::marker::PhantomData<T>,
 }
-pub struct ProcessHandle(());
 
+impl<T> WaitHandle<T> {
+    /// Wait for the thread to exit, and return the value it returned.
+    ///
+    /// # Errors
+    ///
+    /// * **ThreadNotAvailable**: The thread's return value was already
+    ///   collected, or its thread ID was reused before it was joined
+    /// * **InternalError**: The thread was started from a function pointer
+    ///   and `T` is too large to be returned in a register
+    pub fn join(self) -> core::result::Result<T, crate::Error> {
+        let value = match crate::syscall::rsyscall(crate::SysCall::JoinThread(self.tid))? {
+            crate::Result::Scalar1(value) => value,
+            _ => return Err(crate::Error::InternalError),
+        };
+        if self.boxed {
+            if value == 0 {
+                return Err(crate::Error::ThreadNotAvailable);
+            }
+            Ok(unsafe { unbox_result(value) })
+        } else if core::mem::size_of::<T>() <= core::mem::size_of::<usize>() {
+            // The value came back in `a0`, and this is little endian, so the
+            // low bytes of the register hold the whole of `T`.
+            Ok(unsafe { core::mem::transmute_copy(&value) })
+        } else {
+            Err(crate::Error::InternalError)
+        }
+    }
+}
+
+/// Entrypoint for threads started by `create_thread()`. `closure` points to
+/// the boxed closure, and the return value points to its boxed result.
+#[cfg(not(feature = "rustc-dep-of-std"))]
+extern "C" fn closure_thread<F, T>(closure: usize) -> usize
+where
+    F: FnOnce() -> T,
+{
+    let f = unsafe { alloc::boxed::Box::from_raw(closure as *mut F) };
+    alloc::boxed::Box::into_raw(alloc::boxed::Box::new(f())) as usize
+}
+
+#[cfg(not(feature = "rustc-dep-of-std"))]
+unsafe fn unbox_result<T>(result: usize) -> T {
+    *alloc::boxed::Box::from_raw(result as *mut T)
+}
+
+#[cfg(feature = "rustc-dep-of-std")]
+unsafe fn unbox_result<T>(_result: usize) -> T {
+    unreachable!("closure threads are not available inside libstd")
+}
+pub struct ProcessHandle(PID);
+
+impl ProcessHandle {
+    pub fn pid(&self) -> PID {
+        self.0
+    }
+}
+
+/// `CreateThread` fills every argument register with the entrypoint, the stack
+/// and the four thread arguments, which leaves no room for the priority. A
+/// thread that doesn't run at the default priority is created with
+/// `CreateThreadWithPriority` instead, which gives up the fourth thread
+/// argument to carry it. If the fourth argument is in use anyway, the call is
+/// sent with a priority the kernel refuses, so that it fails with
+/// `InvalidSyscall` rather than starting the thread without the argument.
 pub fn thread_to_args(syscall: usize, init: &ThreadInit) -> [usize; 8] {
+    if init.priority == THREAD_PRIORITY_DEFAULT {
+        return [
+            syscall,
+            init.call,
+            init.stack.as_ptr() as _,
+            init.stack.len(),
+            init.arg1,
+            init.arg2,
+            init.arg3,
+            init.arg4,
+        ];
+    }
+    let priority = if init.arg4 == 0 {
+        init.priority as usize
+    } else {
+        usize::MAX
+    };
     [
-        syscall,
+        crate::SysCallNumber::CreateThreadWithPriority as usize,
         init.call,
         init.stack.as_ptr() as _,
         init.stack.len(),
         init.arg1,
         init.arg2,
         init.arg3,
-        init.arg4,
+        priority,
     ]
 }
 
@@ -110,32 +262,105 @@ pub fn args_to_thread(
         arg2: a5,
         arg3: a6,
         arg4: a7,
+        priority: THREAD_PRIORITY_DEFAULT,
         // name: [0; 12],
     })
 }
 
+/// Like `args_to_thread()`, but for `CreateThreadWithPriority`, where the
+/// last register holds the priority rather than the fourth argument.
+pub fn args_to_thread_with_priority(
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> core::result::Result<ThreadInit, crate::Error> {
+    if a7 > THREAD_PRIORITY_MAX as usize {
+        return Err(crate::Error::InvalidSyscall);
+    }
+    let mut init = args_to_thread(a1, a2, a3, a4, a5, a6, 0)?;
+    init.priority = a7 as ThreadPriority;
+    Ok(init)
+}
+
+/// Prepare to run `f` in a new thread. The closure is moved onto the heap
+/// here so the new thread can pick it up as soon as it starts, which may be
+/// before `create_thread_post()` is called.
+#[cfg(not(feature = "rustc-dep-of-std"))]
+pub fn create_thread_pre<F, T>(f: &F) -> core::result::Result<ThreadInit, crate::Error>
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
+    let start: extern "C" fn(usize) -> usize = closure_thread::<F, T>;
+    let mut init = create_thread_n_pre(start as usize, &0, &0, &0, &0)?;
+    // Take a bitwise copy of the closure. The original is forgotten in
+    // `create_thread_post()` once the thread has been created.
+    let closure = alloc::boxed::Box::new(unsafe { core::ptr::read(f) });
+    init.arg1 = alloc::boxed::Box::into_raw(closure) as usize;
+    Ok(init)
+}
+
+#[cfg(feature = "rustc-dep-of-std")]
 pub fn create_thread_pre<F, T>(_f: &F) -> core::result::Result<ThreadInit, crate::Error>
 where
     F: FnOnce() -> T,
     F: Send + 'static,
     T: Send + 'static,
 {
-    todo!()
+    Err(crate::Error::UnhandledSyscall)
+}
+
+/// Undo `create_thread_pre()` once the kernel has refused to create the
+/// thread. The caller still owns `f`, so the copy on the heap is freed without
+/// being dropped a second time, and the stack is handed back.
+#[cfg(not(feature = "rustc-dep-of-std"))]
+pub fn create_thread_abort<F, T>(init: ThreadInit)
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
+    drop(unsafe { alloc::boxed::Box::from_raw(init.arg1 as *mut core::mem::ManuallyDrop<F>) });
+    crate::unmap_memory(init.stack).ok();
+}
+
+#[cfg(feature = "rustc-dep-of-std")]
+pub fn create_thread_abort<F, T>(_init: ThreadInit)
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
 }
 
 pub fn create_thread_post<F, T>(
-    _f: F,
-    _thread_id: TID,
+    f: F,
+    thread_id: TID,
 ) -> core::result::Result<WaitHandle<T>, crate::Error>
 where
     F: FnOnce() -> T,
     F: Send + 'static,
     T: Send + 'static,
 {
-    todo!()
+    // The new thread owns the copy made in `create_thread_pre()`.
+    core::mem::forget(f);
+    Ok(WaitHandle {
+        tid: thread_id,
+        boxed: true,
+        data: core::marker::PhantomData,
+    })
 }
 
 pub fn wait_thread<T>(joiner: WaitHandle<T>) -> crate::SysCallResult {
+    if joiner.boxed {
+        // Unbox the result so it gets dropped
+        return joiner.join().map(|_| crate::Result::Ok);
+    }
     let call = crate::SysCall::JoinThread(joiner.tid);
     crate::syscall::rsyscall(call)
 }
@@ -143,26 +368,40 @@ pub fn wait_thread<T>(joiner: WaitHandle<T>) -> crate::SysCallResult {
 pub fn process_to_args(call: usize, init: &ProcessInit) -> [usize; 8] {
     [
         call,
-        u32::from_le_bytes(init.key.0[0..4].try_into().unwrap()) as _,
-        u32::from_le_bytes(init.key.0[4..8].try_into().unwrap()) as _,
-        0,
-        0,
-        0,
-        0,
-        0,
+        init.text_base,
+        init.text_size,
+        init.priority as usize | init.page_limit << 8,
+        init.image.as_ptr() as usize,
+        init.image.len(),
+        init.image_base,
+        init.entrypoint,
     ]
 }
 
 pub fn args_to_process(
-    _a1: usize,
-    _a2: usize,
-    _a3: usize,
-    _a4: usize,
-    _a5: usize,
-    _a6: usize,
-    _a7: usize,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
 ) -> core::result::Result<ProcessInit, crate::Error> {
-    todo!()
+    if a3 & 0xff > THREAD_PRIORITY_MAX as usize {
+        return Err(crate::Error::InvalidSyscall);
+    }
+    if a4 == 0 || a5 == 0 {
+        return Err(crate::Error::BadAddress);
+    }
+    Ok(ProcessInit {
+        text_base: a1,
+        text_size: a2,
+        priority: (a3 & 0xff) as ThreadPriority,
+        page_limit: a3 >> 8,
+        image: unsafe { MemoryRange::new(a4, a5)? },
+        image_base: a6,
+        entrypoint: a7,
+    })
 }
 
 pub fn create_thread_0_pre<U>(f: &fn() -> U) -> core::result::Result<ThreadInit, crate::Error>
@@ -329,18 +568,46 @@ where
     })
 }
 
-/// If no connection exists, create a new connection to the server. This means
-/// our parent PID will be PID1. Otherwise, reuse the same connection.
-pub fn create_process_pre(_args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
-    todo!()
+/// Lay the program out in freshly-allocated pages, exactly as it will appear
+/// in the new process. The kernel moves these pages into the new address
+/// space.
+pub fn create_process_pre(args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
+    if args.priority > THREAD_PRIORITY_MAX {
+        return Err(crate::Error::InvalidSyscall);
+    }
+    let elf = crate::elf::ElfFile::parse(args.elf)?;
+    let layout = elf.layout()?;
+
+    #[cfg(feature = "bit-flags")]
+    let flags = crate::MemoryFlags::R | crate::MemoryFlags::W;
+    #[cfg(not(feature = "bit-flags"))]
+    let flags = 0b0000_0010 | 0b0000_0100;
+
+    let mut image = crate::map_memory(None, None, layout.image_size, flags)?;
+    if let Err(e) = elf.copy_image(&layout, image.as_slice_mut()) {
+        crate::unmap_memory(image).ok();
+        return Err(e.into());
+    }
+    // The new process will run this code, so it can't be left in the data cache.
+    cache_flush();
+
+    Ok(ProcessInit {
+        text_base: layout.text_base,
+        text_size: layout.text_size,
+        priority: args.priority,
+        page_limit: args.page_limit,
+        image,
+        image_base: layout.image_base,
+        entrypoint: layout.entry_point,
+    })
 }
 
 pub fn create_process_post(
     _args: ProcessArgs,
     _init: ProcessInit,
-    _pid: PID,
+    pid: PID,
 ) -> core::result::Result<ProcessHandle, crate::Error> {
-    todo!()
+    Ok(ProcessHandle(pid))
 }
 
 pub fn create_thread_n_pre(
@@ -372,6 +639,7 @@ where
 {
     Ok(WaitHandle {
         tid: thread_id,
+        boxed: false,
         data: core::marker::PhantomData,
     })
 }
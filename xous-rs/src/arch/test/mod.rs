@@ -7,7 +7,10 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs};
 use std::sync::{Arc, Mutex};
 use std::thread_local;
 
-use crate::{Result, SysCall, SysCallResult, PID, TID};
+use crate::{
+    Result, SysCall, SysCallResult, ThreadPriority, PID, THREAD_PRIORITY_DEFAULT,
+    THREAD_PRIORITY_MAX, TID,
+};
 
 mod mem;
 pub use mem::*;
@@ -21,11 +24,36 @@ impl ProcessKey {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct ThreadInit {}
+pub struct ThreadInit {
+    pub priority: ThreadPriority,
+}
+
+impl Default for ThreadInit {
+    fn default() -> Self {
+        ThreadInit {
+            priority: THREAD_PRIORITY_DEFAULT,
+        }
+    }
+}
+
+impl ThreadInit {
+    /// Run the thread at `priority` rather than the default. The priority has
+    /// a register of its own here, so this can't fail.
+    pub fn with_priority(
+        self,
+        priority: ThreadPriority,
+    ) -> core::result::Result<Self, crate::Error> {
+        Ok(ThreadInit { priority })
+    }
+}
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ProcessInit {
     pub key: ProcessKey,
+    /// Priority of the initial thread of the new process
+    pub priority: ThreadPriority,
+    /// The most pages of RAM the new process may own, or 0 for no limit
+    pub page_limit: usize,
 }
 
 pub struct ProcessArgsAsThread<F: FnOnce()> {
@@ -63,6 +91,8 @@ where
         key: PROCESS_KEY
             .with(|pk| *pk.borrow())
             .unwrap_or_else(default_process_key),
+        priority: THREAD_PRIORITY_DEFAULT,
+        page_limit: 0,
     })
 }
 
@@ -140,6 +170,8 @@ pub fn create_process_pre(_args: &ProcessArgs) -> core::result::Result<ProcessIn
         key: PROCESS_KEY
             .with(|pk| *pk.borrow())
             .unwrap_or_else(default_process_key),
+        priority: THREAD_PRIORITY_DEFAULT,
+        page_limit: 0,
     })
 }
 
@@ -192,6 +224,13 @@ pub fn wait_process(mut joiner: ProcessHandle) -> crate::SysCallResult {
 
 pub struct WaitHandle<T>(std::thread::JoinHandle<T>);
 
+impl<T> WaitHandle<T> {
+    /// Wait for the thread to exit, and return the value it returned.
+    pub fn join(self) -> core::result::Result<T, crate::Error> {
+        self.0.join().map_err(|_| crate::Error::InternalError)
+    }
+}
+
 #[derive(Clone)]
 struct ServerConnection {
     send: Arc<Mutex<TcpStream>>,
@@ -199,8 +238,8 @@ struct ServerConnection {
     mailbox: Arc<Mutex<HashMap<TID, Result>>>,
 }
 
-pub fn thread_to_args(call: usize, _init: &ThreadInit) -> [usize; 8] {
-    [call, 0, 0, 0, 0, 0, 0, 0]
+pub fn thread_to_args(call: usize, init: &ThreadInit) -> [usize; 8] {
+    [call, init.priority as usize, 0, 0, 0, 0, 0, 0]
 }
 
 pub fn process_to_args(call: usize, init: &ProcessInit) -> [usize; 8] {
@@ -210,14 +249,14 @@ pub fn process_to_args(call: usize, init: &ProcessInit) -> [usize; 8] {
         u32::from_le_bytes(init.key.0[4..8].try_into().unwrap()) as _,
         u32::from_le_bytes(init.key.0[8..12].try_into().unwrap()) as _,
         u32::from_le_bytes(init.key.0[12..16].try_into().unwrap()) as _,
-        0,
-        0,
+        init.priority as usize,
+        init.page_limit,
         0,
     ]
 }
 
 pub fn args_to_thread(
-    _a1: usize,
+    a1: usize,
     _a2: usize,
     _a3: usize,
     _a4: usize,
@@ -225,7 +264,26 @@ pub fn args_to_thread(
     _a6: usize,
     _a7: usize,
 ) -> core::result::Result<ThreadInit, crate::Error> {
-    Ok(ThreadInit {})
+    if a1 > THREAD_PRIORITY_MAX as usize {
+        return Err(crate::Error::InvalidSyscall);
+    }
+    Ok(ThreadInit {
+        priority: a1 as ThreadPriority,
+    })
+}
+
+/// The priority always has a register of its own here, so both thread
+/// layouts are the same.
+pub fn args_to_thread_with_priority(
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> core::result::Result<ThreadInit, crate::Error> {
+    args_to_thread(a1, a2, a3, a4, a5, a6, a7)
 }
 
 pub fn args_to_process(
@@ -233,10 +291,13 @@ pub fn args_to_process(
     a2: usize,
     a3: usize,
     a4: usize,
-    _a5: usize,
-    _a6: usize,
+    a5: usize,
+    a6: usize,
     _a7: usize,
 ) -> core::result::Result<ProcessInit, crate::Error> {
+    if a5 > THREAD_PRIORITY_MAX as usize {
+        return Err(crate::Error::InvalidSyscall);
+    }
     let mut v = vec![];
     v.extend_from_slice(&(a1 as u32).to_le_bytes());
     v.extend_from_slice(&(a2 as u32).to_le_bytes());
@@ -246,6 +307,8 @@ pub fn args_to_process(
     key.copy_from_slice(&v);
     Ok(ProcessInit {
         key: ProcessKey(key),
+        priority: a5 as ThreadPriority,
+        page_limit: a6,
     })
 }
 
@@ -301,7 +364,7 @@ pub fn create_thread_0_pre<U>(_f: &fn() -> U) -> core::result::Result<ThreadInit
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 pub fn create_thread_1_pre<U>(
     _f: &fn(usize) -> U,
@@ -310,7 +373,7 @@ pub fn create_thread_1_pre<U>(
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 pub fn create_thread_2_pre<U>(
     _f: &fn(usize, usize) -> U,
@@ -320,7 +383,7 @@ pub fn create_thread_2_pre<U>(
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 pub fn create_thread_3_pre<U>(
     _f: &fn(usize, usize, usize) -> U,
@@ -331,7 +394,7 @@ pub fn create_thread_3_pre<U>(
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 pub fn create_thread_4_pre<U>(
     _f: &fn(usize, usize, usize, usize) -> U,
@@ -343,7 +406,7 @@ pub fn create_thread_4_pre<U>(
 where
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 
 pub fn create_thread_0_post<U>(
@@ -414,7 +477,7 @@ where
     T: Send + 'static,
     U: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
 }
 
 pub fn create_thread_simple_post<T, U>(
@@ -435,7 +498,17 @@ where
     F: Send + 'static,
     T: Send + 'static,
 {
-    Ok(ThreadInit {})
+    Ok(ThreadInit::default())
+}
+
+/// Nothing is set aside for the thread until it's spawned, so there's nothing
+/// to undo.
+pub fn create_thread_abort<F, T>(_init: ThreadInit)
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
 }
 
 pub fn create_thread_post<F, U>(
@@ -527,10 +600,38 @@ fn xous_connect_impl(
 }
 
 pub fn syscall(call: SysCall) -> SysCallResult {
+    let args = call.as_args();
+    let [nr, a1, a2, a3, a4, a5, a6, a7] = args;
+    let copy = crate::SysCall::from_args(nr, a1, a2, a3, a4, a5, a6, a7).unwrap();
+    syscall_with_args(call, copy, args)
+}
+
+/// Make a syscall out of raw register values, without checking that they
+/// describe a valid call, the way a misbehaving process might. Values that
+/// describe a call with memory attached are refused, since there is no
+/// buffer to send along with them.
+pub fn raw_syscall(args: [usize; 8]) -> SysCallResult {
+    let [nr, a1, a2, a3, a4, a5, a6, a7] = args;
+    if let Ok(call) = crate::SysCall::from_args(nr, a1, a2, a3, a4, a5, a6, a7) {
+        if call.memory().is_some() {
+            return Err(crate::Error::InvalidSyscall);
+        }
+    }
+    syscall_with_args(
+        crate::SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7),
+        crate::SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7),
+        args,
+    )
+}
+
+/// Send `args` to the kernel and wait for the result. `call` and `copy` are
+/// what the arguments describe, and are used to send and receive any memory
+/// that goes along with the call.
+fn syscall_with_args(call: SysCall, copy: SysCall, args: [usize; 8]) -> SysCallResult {
     let mut ret = Result::Ok;
     XOUS_SERVER_CONNECTION.with(|xsc| {
         THREAD_ID.with(|tid| {
-            let [nr, a1, a2, a3, a4, a5, a6, a7] = call.as_args();
+            let [nr, a1, a2, a3, a4, a5, a6, a7] = args;
             {
                 CALL_FOR_THREAD.with(|cft| {
                     let cft_rc = cft.borrow();
@@ -540,7 +641,7 @@ pub fn syscall(call: SysCall) -> SysCallResult {
                     cft_mtx.insert(tid, call)
                 });
             }
-            let call = crate::SysCall::from_args(nr, a1, a2, a3, a4, a5, a6, a7).unwrap();
+            let call = copy;
 
             let mut xsc_borrowed = xsc.borrow_mut();
             let xsc_asmut = xsc_borrowed.as_mut().expect("not connected to server (did you forget to create a thread with xous::create_thread()?)");
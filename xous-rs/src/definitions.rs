@@ -145,6 +145,19 @@ pub type CID = u32;
 /// Thread ID
 pub type TID = usize;
 
+/// Scheduling priority of a thread. The kernel always runs the highest-priority
+/// thread that is ready, and round-robins between threads of equal priority.
+pub type ThreadPriority = u8;
+
+/// The lowest priority, for threads that should only run when nothing else can.
+pub const THREAD_PRIORITY_IDLE: ThreadPriority = 0;
+
+/// The priority given to threads and processes that don't ask for one.
+pub const THREAD_PRIORITY_DEFAULT: ThreadPriority = 4;
+
+/// The highest priority a thread may have.
+pub const THREAD_PRIORITY_MAX: ThreadPriority = 7;
+
 /// Equivalent to a RISC-V Hart ID
 pub type CpuID = usize;
 
@@ -636,12 +649,11 @@ impl MemoryRange {
     /// This allows for creating a `MemoryRange` from any arbitrary pointer,
     /// so it is imperitive that this only be used to point to valid, page-aligned
     /// ranges.
+    ///
+    /// Returns `Error::BadAddress` if `addr` or `size` is zero. The kernel
+    /// builds ranges out of whatever a process passed it, so this must not
+    /// panic.
     pub unsafe fn new(addr: usize, size: usize) -> core::result::Result<MemoryRange, Error> {
-        assert!(
-            addr != 0,
-            "tried to construct a memory range with a null pointer"
-        );
-        assert!(size != 0, "tried to construct a zero-length memory range");
         Ok(MemoryRange {
             addr: MemoryAddress::new(addr).ok_or(Error::BadAddress)?,
             size: MemorySize::new(size).ok_or(Error::BadAddress)?,
@@ -742,6 +754,278 @@ impl core::fmt::Display for MemoryType {
     }
 }
 
+/// What a process is doing, as reported by `process_info()`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProcessStatus {
+    /// The process exists, but has no threads yet.
+    Allocated = 1,
+
+    /// The process has been created and will start the next time it runs.
+    Setup = 2,
+
+    /// The process has threads that are ready to run.
+    Ready = 3,
+
+    /// The process is the one that is currently running.
+    Running = 4,
+
+    /// All threads are waiting for an event, such as a message.
+    Sleeping = 5,
+
+    /// The process has been stopped by the debugger.
+    Debug = 6,
+
+    /// The process is handling an exception.
+    Exception = 7,
+}
+
+impl From<usize> for ProcessStatus {
+    fn from(arg: usize) -> Self {
+        match arg {
+            2 => ProcessStatus::Setup,
+            3 => ProcessStatus::Ready,
+            4 => ProcessStatus::Running,
+            5 => ProcessStatus::Sleeping,
+            6 => ProcessStatus::Debug,
+            7 => ProcessStatus::Exception,
+            _ => ProcessStatus::Allocated,
+        }
+    }
+}
+
+impl core::fmt::Display for ProcessStatus {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            ProcessStatus::Allocated => write!(fmt, "Allocated"),
+            ProcessStatus::Setup => write!(fmt, "Setup"),
+            ProcessStatus::Ready => write!(fmt, "Ready"),
+            ProcessStatus::Running => write!(fmt, "Running"),
+            ProcessStatus::Sleeping => write!(fmt, "Sleeping"),
+            ProcessStatus::Debug => write!(fmt, "Debug"),
+            ProcessStatus::Exception => write!(fmt, "Exception"),
+        }
+    }
+}
+
+/// A snapshot of a process, as returned by `process_info()`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: PID,
+
+    /// The process that created this one.
+    pub ppid: PID,
+
+    pub status: ProcessStatus,
+
+    /// A bitmask of the threads that are able to run, including the one
+    /// that is running right now.
+    pub ready_threads: usize,
+
+    /// The number of bytes of RAM owned by the process. This is always 0 in
+    /// hosted mode.
+    pub ram_used: usize,
+
+    /// The number of servers the process has created.
+    pub servers: usize,
+
+    /// The number of connections the process has open to servers.
+    pub connections: usize,
+
+    /// A bitmask of the interrupts the process has claimed.
+    pub irqs: usize,
+}
+
+impl ProcessInfo {
+    /// Pack everything but the PID into the five words of a `Scalar5`.
+    pub fn to_args(&self) -> [usize; 5] {
+        [
+            self.ppid.get() as usize | (self.status as usize) << 8,
+            self.ready_threads,
+            self.ram_used,
+            self.servers | self.connections << 16,
+            self.irqs,
+        ]
+    }
+
+    /// Unpack the words created by `to_args()` for process `pid`.
+    pub fn from_args(pid: PID, args: [usize; 5]) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            ppid: PID::new(args[0] as u8).unwrap_or(pid),
+            status: ProcessStatus::from((args[0] >> 8) & 0xff),
+            ready_threads: args[1],
+            ram_used: args[2],
+            servers: args[3] & 0xffff,
+            connections: args[3] >> 16,
+            irqs: args[4],
+        }
+    }
+}
+
+/// The IPC calls that are recorded by a kernel built with `ipc-trace`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IpcTraceKind {
+    /// `SendMessage` or `TrySendMessage`
+    SendMessage = 1,
+
+    /// `ReceiveMessage` or `TryReceiveMessage`
+    ReceiveMessage = 2,
+
+    ReturnMemory = 3,
+
+    /// `ReturnScalar1` or `ReturnScalar2`
+    ReturnScalar = 4,
+
+    /// `Connect`, `TryConnect` or `ConnectForProcess`
+    Connect = 5,
+
+    Disconnect = 6,
+}
+
+impl IpcTraceKind {
+    pub fn from_usize(arg: usize) -> Option<Self> {
+        match arg {
+            1 => Some(IpcTraceKind::SendMessage),
+            2 => Some(IpcTraceKind::ReceiveMessage),
+            3 => Some(IpcTraceKind::ReturnMemory),
+            4 => Some(IpcTraceKind::ReturnScalar),
+            5 => Some(IpcTraceKind::Connect),
+            6 => Some(IpcTraceKind::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+impl core::fmt::Display for IpcTraceKind {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            IpcTraceKind::SendMessage => write!(fmt, "SendMessage"),
+            IpcTraceKind::ReceiveMessage => write!(fmt, "ReceiveMessage"),
+            IpcTraceKind::ReturnMemory => write!(fmt, "ReturnMemory"),
+            IpcTraceKind::ReturnScalar => write!(fmt, "ReturnScalar"),
+            IpcTraceKind::Connect => write!(fmt, "Connect"),
+            IpcTraceKind::Disconnect => write!(fmt, "Disconnect"),
+        }
+    }
+}
+
+/// One IPC call, as returned by `ipc_trace_event()`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IpcTraceEvent {
+    /// The position of this event in the trace, starting from 0.
+    pub seq: usize,
+
+    /// When the call was made. Hosted kernels count microseconds since they
+    /// started. The kernel has no clock of its own on hardware, so there
+    /// this is the number of syscalls that had been made before this one.
+    pub timestamp: usize,
+
+    pub kind: IpcTraceKind,
+
+    /// The process that made the call.
+    pub pid: PID,
+
+    /// The thread that made the call.
+    pub tid: TID,
+
+    /// The connection used by `SendMessage` and `Disconnect`, otherwise 0.
+    pub cid: CID,
+
+    /// The server involved, if the kernel could find it.
+    pub sid: Option<SID>,
+
+    /// The process at the other end. This is the server's owner for calls
+    /// made by a client, and the client being replied to for `ReturnMemory`
+    /// and `ReturnScalar`.
+    pub peer: Option<PID>,
+
+    /// The message ID of a `SendMessage`, otherwise 0.
+    pub opcode: usize,
+
+    /// The number of bytes lent, moved or returned, or 0 for scalars.
+    pub len: usize,
+}
+
+impl IpcTraceEvent {
+    /// Pack the event into the two `Scalar5` responses of `GetIpcTrace`.
+    pub fn to_args(&self) -> [[usize; 5]; 2] {
+        let sid = self.sid.map(|sid| sid.to_array()).unwrap_or([0; 4]);
+        [
+            [
+                self.seq,
+                self.kind as usize
+                    | (self.pid.get() as usize) << 8
+                    | (self.tid & 0xff) << 16
+                    | (self.peer.map(|p| p.get() as usize).unwrap_or(0)) << 24,
+                self.opcode,
+                self.len,
+                self.timestamp,
+            ],
+            [
+                self.cid as usize,
+                sid[0] as usize,
+                sid[1] as usize,
+                sid[2] as usize,
+                sid[3] as usize,
+            ],
+        ]
+    }
+
+    /// Unpack the words created by `to_args()`.
+    pub fn from_args(header: [usize; 5], target: [usize; 5]) -> Option<IpcTraceEvent> {
+        let sid = [
+            target[1] as u32,
+            target[2] as u32,
+            target[3] as u32,
+            target[4] as u32,
+        ];
+        Some(IpcTraceEvent {
+            seq: header[0],
+            timestamp: header[4],
+            kind: IpcTraceKind::from_usize(header[1] & 0xff)?,
+            pid: PID::new((header[1] >> 8) as u8)?,
+            tid: (header[1] >> 16) & 0xff,
+            cid: target[0] as CID,
+            sid: if sid == [0; 4] {
+                None
+            } else {
+                Some(SID::from_array(sid))
+            },
+            peer: PID::new((header[1] >> 24) as u8),
+            opcode: header[2],
+            len: header[3],
+        })
+    }
+}
+
+/// Events are printed one per line, in the form that `decode-ipc-trace`
+/// reads back.
+impl core::fmt::Display for IpcTraceEvent {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            fmt,
+            "{} {} {} {}:{} cid={} sid=",
+            self.seq, self.timestamp, self.kind, self.pid, self.tid, self.cid
+        )?;
+        match self.sid {
+            Some(sid) => {
+                let sid = sid.to_array();
+                write!(
+                    fmt,
+                    "{:08x}{:08x}{:08x}{:08x}",
+                    sid[0], sid[1], sid[2], sid[3]
+                )?;
+            }
+            None => write!(fmt, "-")?,
+        }
+        match self.peer {
+            Some(peer) => write!(fmt, " peer={}", peer)?,
+            None => write!(fmt, " peer=-")?,
+        }
+        write!(fmt, " op={} len={}", self.opcode, self.len)
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub enum Result {
@@ -793,6 +1077,9 @@ pub enum Result {
         Option<MemorySize>, /* valid */
     ),
 
+    /// A scalar with five values
+    Scalar5(usize, usize, usize, usize, usize),
+
     UnknownResult(usize, usize, usize, usize, usize, usize, usize),
 }
 
@@ -849,6 +1136,7 @@ impl Result {
                 0,
                 0,
             ],
+            Result::Scalar5(a, b, c, d, e) => [19, *a, *b, *c, *d, *e, 0, 0],
             Result::UnknownResult(arg1, arg2, arg3, arg4, arg5, arg6, arg7) => {
                 [usize::MAX, *arg1, *arg2, *arg3, *arg4, *arg5, *arg6, *arg7]
             }
@@ -925,6 +1213,7 @@ impl Result {
             16 => Result::RetryCall,
             17 => Result::None,
             18 => Result::MemoryReturned(MemorySize::new(src[1]), MemorySize::new(src[2])),
+            19 => Result::Scalar5(src[1], src[2], src[3], src[4], src[5]),
             _ => Result::UnknownResult(src[0], src[1], src[2], src[3], src[4], src[5], src[6]),
         }
     }
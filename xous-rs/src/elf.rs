@@ -0,0 +1,567 @@
+//! A minimal reader for the 32-bit RISC-V ELF files that Xous programs are
+//! built as. This is shared between `create-image`, which bakes programs into
+//! the boot image, and `create_process()`, which launches them at runtime, so
+//! both agree on where every section ends up.
+//!
+//! Only section headers are consulted, and only sections marked as
+//! allocated with a nonzero address are considered part of the program.
+
+use core::convert::TryInto;
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// The initial stack grows down from this address.
+pub const USER_STACK_TOP: usize = 0x8000_0000;
+
+/// Bytes reserved for the stack of the initial thread.
+pub const USER_STACK_SIZE: usize = 128 * 1024;
+
+/// Everything from here up belongs to the kernel.
+pub const USER_AREA_END: usize = 0xff00_0000;
+
+const EM_RISCV: u16 = 243;
+const ET_EXEC: u16 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u32 = 1;
+const SHF_ALLOC: u32 = 2;
+const SHF_EXECINSTR: u32 = 4;
+
+const EHDR_SIZE: usize = 52;
+const SHDR_SIZE: usize = 40;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ElfError {
+    /// The file ended before a header or section it refers to
+    Truncated,
+
+    /// The file doesn't start with the ELF magic number
+    NotElf,
+
+    /// The file isn't a little-endian, 32-bit RISC-V executable
+    Unsupported,
+
+    /// A section lies outside of the file
+    SectionOutOfRange,
+
+    /// There was nothing to load
+    NoSections,
+
+    /// Loadable sections overlap the stack or the kernel, or a writable
+    /// section shares a page with code
+    BadLayout,
+
+    /// The entrypoint isn't inside an executable section
+    BadEntryPoint,
+}
+
+impl From<ElfError> for crate::Error {
+    fn from(e: ElfError) -> crate::Error {
+        match e {
+            ElfError::BadLayout => crate::Error::BadAddress,
+            _ => crate::Error::InvalidString,
+        }
+    }
+}
+
+/// One allocated section of the program.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ElfSection<'a> {
+    pub name: &'a str,
+    pub virt: usize,
+    pub size: usize,
+    pub writable: bool,
+    pub executable: bool,
+
+    /// The contents of the section, or `None` for sections such as `.bss`
+    /// that start out zeroed and take up no room in the file.
+    pub data: Option<&'a [u8]>,
+}
+
+/// Where a program goes in a fresh address space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProcessLayout {
+    /// Page-aligned address of the lowest section
+    pub image_base: usize,
+
+    /// Number of bytes from `image_base` to the end of the highest section,
+    /// rounded up to a whole page
+    pub image_size: usize,
+
+    pub entry_point: usize,
+
+    /// Page-aligned span holding every executable section. It's mapped
+    /// read-only and executable, and the rest of the image read-write.
+    pub text_base: usize,
+    pub text_size: usize,
+
+    /// Address just past the top of the initial stack
+    pub stack_top: usize,
+    pub stack_size: usize,
+}
+
+pub struct ElfFile<'a> {
+    bytes: &'a [u8],
+    entry_point: usize,
+    shoff: usize,
+    shnum: usize,
+    shentsize: usize,
+    shstrndx: usize,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, ElfError> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(ElfError::Truncated)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ElfError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(ElfError::Truncated)
+}
+
+fn page_down(addr: usize) -> usize {
+    addr & !(PAGE_SIZE - 1)
+}
+
+fn page_up(addr: usize) -> Option<usize> {
+    addr.checked_add(PAGE_SIZE - 1).map(page_down)
+}
+
+impl<'a> ElfFile<'a> {
+    /// Check the ELF header and every section header, so that iterating
+    /// through sections afterwards can't fail.
+    pub fn parse(bytes: &'a [u8]) -> Result<ElfFile<'a>, ElfError> {
+        if bytes.len() < EHDR_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if bytes[0..4] != *b"\x7fELF" {
+            return Err(ElfError::NotElf);
+        }
+        // 32-bit, little endian
+        if bytes[4] != 1 || bytes[5] != 1 {
+            return Err(ElfError::Unsupported);
+        }
+        if read_u16(bytes, 16)? != ET_EXEC || read_u16(bytes, 18)? != EM_RISCV {
+            return Err(ElfError::Unsupported);
+        }
+
+        let elf = ElfFile {
+            bytes,
+            entry_point: read_u32(bytes, 24)? as usize,
+            shoff: read_u32(bytes, 32)? as usize,
+            shentsize: read_u16(bytes, 46)? as usize,
+            shnum: read_u16(bytes, 48)? as usize,
+            shstrndx: read_u16(bytes, 50)? as usize,
+        };
+        if elf.shnum != 0 && elf.shentsize < SHDR_SIZE {
+            return Err(ElfError::Unsupported);
+        }
+        let table_size = elf
+            .shnum
+            .checked_mul(elf.shentsize)
+            .ok_or(ElfError::Truncated)?;
+        match elf.shoff.checked_add(table_size) {
+            Some(end) if end <= bytes.len() => (),
+            _ => return Err(ElfError::Truncated),
+        }
+
+        for index in 0..elf.shnum {
+            let header = elf.section_header(index);
+            let sh_type = read_u32(header, 4)?;
+            let offset = read_u32(header, 16)? as usize;
+            let size = read_u32(header, 20)? as usize;
+            if sh_type == SHT_NOBITS {
+                continue;
+            }
+            match offset.checked_add(size) {
+                Some(end) if end <= bytes.len() => (),
+                _ => return Err(ElfError::SectionOutOfRange),
+            }
+        }
+        Ok(elf)
+    }
+
+    pub fn entry_point(&self) -> usize {
+        self.entry_point
+    }
+
+    fn section_header(&self, index: usize) -> &'a [u8] {
+        let start = self.shoff + index * self.shentsize;
+        &self.bytes[start..start + SHDR_SIZE]
+    }
+
+    fn section_name(&self, name_offset: usize) -> Option<&'a str> {
+        if self.shstrndx >= self.shnum {
+            return None;
+        }
+        let strtab = self.section_header(self.shstrndx);
+        let start = read_u32(strtab, 16).ok()? as usize;
+        let size = read_u32(strtab, 20).ok()? as usize;
+        let names = self
+            .bytes
+            .get(start..start.checked_add(size)?)?
+            .get(name_offset..)?;
+        let len = names.iter().position(|&c| c == 0)?;
+        core::str::from_utf8(&names[..len]).ok()
+    }
+
+    /// All sections that get loaded into memory, in file order.
+    pub fn sections(&self) -> impl Iterator<Item = ElfSection<'a>> + '_ {
+        (0..self.shnum).filter_map(move |index| {
+            let header = self.section_header(index);
+            let field = |offset| read_u32(header, offset).unwrap();
+            let (sh_type, sh_flags, virt) = (field(4), field(8), field(12) as usize);
+            if sh_flags & SHF_ALLOC == 0 || virt == 0 {
+                return None;
+            }
+            let offset = field(16) as usize;
+            let size = field(20) as usize;
+            Some(ElfSection {
+                name: self.section_name(field(0) as usize).unwrap_or("<<error>>"),
+                virt,
+                size,
+                writable: sh_flags & SHF_WRITE != 0,
+                executable: sh_flags & SHF_EXECINSTR != 0,
+                data: if sh_type == SHT_NOBITS {
+                    None
+                } else {
+                    Some(&self.bytes[offset..offset + size])
+                },
+            })
+        })
+    }
+
+    /// Work out where the program and its stack live, and make sure the
+    /// result is something the kernel can actually map.
+    pub fn layout(&self) -> Result<ProcessLayout, ElfError> {
+        let mut bounds: Option<(usize, usize)> = None;
+        let mut text: Option<(usize, usize)> = None;
+        let mut entry_found = false;
+        for section in self.sections() {
+            let end = section
+                .virt
+                .checked_add(section.size)
+                .ok_or(ElfError::BadLayout)?;
+            bounds = Some(match bounds {
+                Some((low, high)) => (low.min(section.virt), high.max(end)),
+                None => (section.virt, end),
+            });
+            if section.executable {
+                text = Some(match text {
+                    Some((low, high)) => (low.min(section.virt), high.max(end)),
+                    None => (section.virt, end),
+                });
+                if self.entry_point >= section.virt && self.entry_point < end {
+                    entry_found = true;
+                }
+            }
+        }
+        let (low, high) = bounds.ok_or(ElfError::NoSections)?;
+        let image_base = page_down(low);
+        let image_end = page_up(high).ok_or(ElfError::BadLayout)?;
+
+        // The kernel reserves one page more than the stack itself
+        let stack_bottom = USER_STACK_TOP - USER_STACK_SIZE - PAGE_SIZE;
+        if image_base < PAGE_SIZE
+            || image_end > USER_AREA_END
+            || (image_base < USER_STACK_TOP && image_end > stack_bottom)
+        {
+            return Err(ElfError::BadLayout);
+        }
+        if !entry_found {
+            return Err(ElfError::BadEntryPoint);
+        }
+
+        // Pages are either writable or executable, never both
+        let (text_low, text_high) = text.ok_or(ElfError::BadEntryPoint)?;
+        let text_base = page_down(text_low);
+        let text_end = page_up(text_high).ok_or(ElfError::BadLayout)?;
+        for section in self.sections() {
+            if section.writable
+                && section.size != 0
+                && section.virt < text_end
+                && section.virt + section.size > text_base
+            {
+                return Err(ElfError::BadLayout);
+            }
+        }
+
+        Ok(ProcessLayout {
+            image_base,
+            image_size: image_end - image_base,
+            entry_point: self.entry_point,
+            text_base,
+            text_size: text_end - text_base,
+            stack_top: USER_STACK_TOP,
+            stack_size: USER_STACK_SIZE,
+        })
+    }
+
+    /// Fill `image` with the program as it should appear in memory starting
+    /// at `layout.image_base`. Any gaps, as well as `.bss`, are zeroed.
+    pub fn copy_image(&self, layout: &ProcessLayout, image: &mut [u8]) -> Result<(), ElfError> {
+        if image.len() < layout.image_size {
+            return Err(ElfError::Truncated);
+        }
+        for b in image.iter_mut() {
+            *b = 0;
+        }
+        for section in self.sections() {
+            if let Some(data) = section.data {
+                let start = section
+                    .virt
+                    .checked_sub(layout.image_base)
+                    .ok_or(ElfError::BadLayout)?;
+                image
+                    .get_mut(start..start + data.len())
+                    .ok_or(ElfError::BadLayout)?
+                    .copy_from_slice(data);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Section {
+        name: &'static str,
+        sh_type: u32,
+        flags: u32,
+        virt: u32,
+        contents: &'static [u8],
+        size: u32,
+    }
+
+    /// Assemble a minimal executable out of the given sections, with a
+    /// section name table tacked on the end.
+    fn build_elf(entry: u32, sections: &[Section]) -> Vec<u8> {
+        let mut names = vec![0u8];
+        let mut name_offsets = vec![];
+        for section in sections.iter() {
+            name_offsets.push(names.len() as u32);
+            names.extend_from_slice(section.name.as_bytes());
+            names.push(0);
+        }
+        let shstrtab_name = names.len() as u32;
+        names.extend_from_slice(b".shstrtab\0");
+
+        let mut body = vec![];
+        let mut data_offsets = vec![];
+        for section in sections.iter() {
+            data_offsets.push((EHDR_SIZE + body.len()) as u32);
+            body.extend_from_slice(section.contents);
+        }
+        let names_offset = (EHDR_SIZE + body.len()) as u32;
+        body.extend_from_slice(&names);
+        let shoff = (EHDR_SIZE + body.len()) as u32;
+
+        let mut elf = vec![0u8; EHDR_SIZE];
+        elf[0..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 1;
+        elf[5] = 1;
+        elf[6] = 1;
+        elf[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        elf[18..20].copy_from_slice(&EM_RISCV.to_le_bytes());
+        elf[24..28].copy_from_slice(&entry.to_le_bytes());
+        elf[32..36].copy_from_slice(&shoff.to_le_bytes());
+        elf[46..48].copy_from_slice(&(SHDR_SIZE as u16).to_le_bytes());
+        elf[48..50].copy_from_slice(&(sections.len() as u16 + 2).to_le_bytes());
+        elf[50..52].copy_from_slice(&(sections.len() as u16 + 1).to_le_bytes());
+        elf.extend_from_slice(&body);
+
+        let mut push_header = |fields: [u32; 10]| {
+            for field in fields.iter() {
+                elf.extend_from_slice(&field.to_le_bytes());
+            }
+        };
+        push_header([0; 10]);
+        for (idx, section) in sections.iter().enumerate() {
+            push_header([
+                name_offsets[idx],
+                section.sh_type,
+                section.flags,
+                section.virt,
+                data_offsets[idx],
+                section.size,
+                0,
+                0,
+                4,
+                0,
+            ]);
+        }
+        let names_size = names.len() as u32;
+        push_header([shstrtab_name, 3, 0, 0, names_offset, names_size, 0, 0, 1, 0]);
+        elf
+    }
+
+    fn program() -> Vec<u8> {
+        build_elf(
+            0x0010_0004,
+            &[
+                Section {
+                    name: ".text",
+                    sh_type: 1,
+                    flags: SHF_ALLOC | SHF_EXECINSTR,
+                    virt: 0x0010_0000,
+                    contents: &[0x13, 0, 0, 0, 0x6f, 0, 0, 0],
+                    size: 8,
+                },
+                Section {
+                    name: ".comment",
+                    sh_type: 1,
+                    flags: 0,
+                    virt: 0,
+                    contents: b"rustc",
+                    size: 5,
+                },
+                Section {
+                    name: ".data",
+                    sh_type: 1,
+                    flags: SHF_ALLOC | SHF_WRITE,
+                    virt: 0x0010_1000,
+                    contents: &[1, 2, 3, 4],
+                    size: 4,
+                },
+                Section {
+                    name: ".bss",
+                    sh_type: SHT_NOBITS,
+                    flags: SHF_ALLOC | SHF_WRITE,
+                    virt: 0x0010_1004,
+                    contents: &[],
+                    size: 0x2000,
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn sections() {
+        let bytes = program();
+        let elf = ElfFile::parse(&bytes).unwrap();
+        let sections: Vec<_> = elf.sections().collect();
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[0].name, ".text");
+        assert!(sections[0].executable && !sections[0].writable);
+        assert_eq!(sections[1].name, ".data");
+        assert_eq!(sections[1].data, Some(&[1u8, 2, 3, 4][..]));
+        assert_eq!(sections[2].name, ".bss");
+        assert_eq!(sections[2].data, None);
+        assert_eq!(sections[2].size, 0x2000);
+    }
+
+    #[test]
+    fn layout_and_image() {
+        let bytes = program();
+        let elf = ElfFile::parse(&bytes).unwrap();
+        let layout = elf.layout().unwrap();
+        assert_eq!(layout.image_base, 0x0010_0000);
+        assert_eq!(layout.image_size, 0x4000);
+        assert_eq!(layout.entry_point, 0x0010_0004);
+        assert_eq!(layout.text_base, 0x0010_0000);
+        assert_eq!(layout.text_size, 0x1000);
+        assert_eq!(layout.stack_top, USER_STACK_TOP);
+
+        let mut image = vec![0xaau8; layout.image_size];
+        elf.copy_image(&layout, &mut image).unwrap();
+        assert_eq!(&image[0..8], &[0x13, 0, 0, 0, 0x6f, 0, 0, 0]);
+        assert_eq!(&image[0x1000..0x1004], &[1, 2, 3, 4]);
+        assert!(image[8..0x1000].iter().all(|&b| b == 0));
+        assert!(image[0x1004..].iter().all(|&b| b == 0));
+        assert_eq!(
+            elf.copy_image(&layout, &mut image[..0x1000]),
+            Err(ElfError::Truncated)
+        );
+    }
+
+    #[test]
+    fn bad_files() {
+        let bytes = program();
+        assert_eq!(
+            ElfFile::parse(&bytes[..20]).err(),
+            Some(ElfError::Truncated)
+        );
+        assert_eq!(
+            ElfFile::parse(&bytes[..bytes.len() - 1]).err(),
+            Some(ElfError::Truncated)
+        );
+
+        let mut not_elf = bytes.clone();
+        not_elf[0] = 0;
+        assert_eq!(ElfFile::parse(&not_elf).err(), Some(ElfError::NotElf));
+
+        let mut wrong_machine = bytes.clone();
+        wrong_machine[18] = 0x3e;
+        assert_eq!(
+            ElfFile::parse(&wrong_machine).err(),
+            Some(ElfError::Unsupported)
+        );
+
+        // Point .text past the end of the file
+        let mut out_of_range = bytes.clone();
+        let shoff = read_u32(&bytes, 32).unwrap() as usize;
+        let text_offset = shoff + SHDR_SIZE + 16;
+        out_of_range[text_offset..text_offset + 4].copy_from_slice(&0xffff_0000u32.to_le_bytes());
+        assert_eq!(
+            ElfFile::parse(&out_of_range).err(),
+            Some(ElfError::SectionOutOfRange)
+        );
+    }
+
+    #[test]
+    fn bad_layouts() {
+        let text = |virt| Section {
+            name: ".text",
+            sh_type: 1,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            virt,
+            contents: &[0; 4],
+            size: 4,
+        };
+
+        let empty = build_elf(0x0010_0000, &[]);
+        assert_eq!(
+            ElfFile::parse(&empty).unwrap().layout(),
+            Err(ElfError::NoSections)
+        );
+
+        let on_stack = build_elf(0x7fff_0000, &[text(0x7fff_0000)]);
+        assert_eq!(
+            ElfFile::parse(&on_stack).unwrap().layout(),
+            Err(ElfError::BadLayout)
+        );
+
+        let in_kernel = build_elf(0xff80_0000, &[text(0xff80_0000)]);
+        assert_eq!(
+            ElfFile::parse(&in_kernel).unwrap().layout(),
+            Err(ElfError::BadLayout)
+        );
+
+        let missed_entry = build_elf(0x0020_0000, &[text(0x0010_0000)]);
+        assert_eq!(
+            ElfFile::parse(&missed_entry).unwrap().layout(),
+            Err(ElfError::BadEntryPoint)
+        );
+
+        let data = |virt| Section {
+            name: ".data",
+            sh_type: 1,
+            flags: SHF_ALLOC | SHF_WRITE,
+            virt,
+            contents: &[0; 4],
+            size: 4,
+        };
+        let writable_code = build_elf(0x0010_0000, &[text(0x0010_0000), data(0x0010_0800)]);
+        assert_eq!(
+            ElfFile::parse(&writable_code).unwrap().layout(),
+            Err(ElfError::BadLayout)
+        );
+        let next_page = build_elf(0x0010_0000, &[text(0x0010_0000), data(0x0010_1000)]);
+        assert!(ElfFile::parse(&next_page).unwrap().layout().is_ok());
+    }
+}
@@ -13,6 +13,7 @@ pub mod arch;
 
 pub mod carton;
 pub mod definitions;
+pub mod elf;
 mod messages;
 
 pub mod process;
@@ -1,7 +1,8 @@
 use crate::{
-    pid_from_usize, CpuID, Error, Exception, MemoryAddress, MemoryFlags, MemoryMessage,
-    MemoryRange, MemorySize, MemoryType, Message, MessageEnvelope, MessageSender, ProcessArgs,
-    ProcessInit, Result, ScalarMessage, SysCallResult, ThreadInit, CID, PID, SID, TID,
+    pid_from_usize, CpuID, Error, Exception, IpcTraceEvent, MemoryAddress, MemoryFlags,
+    MemoryMessage, MemoryRange, MemorySize, MemoryType, Message, MessageEnvelope, MessageSender,
+    ProcessArgs, ProcessInfo, ProcessInit, Result, ScalarMessage, SysCallResult, ThreadInit,
+    ThreadPriority, CID, PID, SID, THREAD_PRIORITY_MAX, TID,
 };
 use core::convert::{TryFrom, TryInto};
 
@@ -328,6 +329,134 @@ pub enum SysCall {
         usize, /* stack pointer */
     ),
 
+    /// Get part of the name of a process, as given to the loader when the
+    /// image was created. Returns a `Scalar5` containing the total length of
+    /// the name in bytes, followed by four words holding the name starting
+    /// at the given byte offset, packed little-endian. Processes without a
+    /// name report a length of 0.
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The given PID does not exist
+    GetProcessName(PID, usize /* byte offset */),
+
+    /// Change the scheduling priority of a thread in the current process.
+    /// Returns a `Scalar1` containing the thread's previous priority. The new
+    /// priority takes effect the next time the kernel picks a thread to run.
+    ///
+    /// # Errors
+    ///
+    /// * **ThreadNotAvailable**: The given thread ID is out of range
+    SetThreadPriority(TID, ThreadPriority),
+
+    /// Describe the given process: its parent, what it is doing, which of
+    /// its threads can run, and how much RAM, how many servers and
+    /// connections and which interrupts it holds. Returns a `Scalar5` that
+    /// `ProcessInfo::from_args()` turns back into a `ProcessInfo`. Any
+    /// process may inspect itself, but inspecting other processes is
+    /// reserved for the processes started from the boot image.
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The given PID does not exist
+    /// * **AccessDenied**: The caller may not inspect other processes
+    GetProcessInfo(PID),
+
+    /// Report system-wide RAM usage. Returns a `Scalar2` containing the
+    /// number of bytes of RAM that are in use, followed by the total
+    /// number of bytes of RAM. Hosted mode has no RAM to report, and
+    /// returns 0 for both.
+    GetRamUsage,
+
+    /// Report how many pages of RAM a process owns, and how many it may
+    /// own. Returns a `Scalar2` containing the number of pages in use,
+    /// followed by the page limit, which is 0 if the process has no limit.
+    /// Hosted mode does not track pages, so the number in use is always 0.
+    /// The same rules as `GetProcessInfo` decide who may ask.
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The given PID does not exist
+    /// * **AccessDenied**: The caller may not inspect other processes
+    GetPageQuota(PID),
+
+    /// Read an event from the kernel's IPC trace, which only exists when
+    /// the kernel is built with the `ipc-trace` feature. The first argument
+    /// is the sequence number of the event, and the second selects which
+    /// half of it to return: 0 for a `Scalar5` of the sequence number,
+    /// caller, opcode, length and timestamp, or 1 for a `Scalar5` of the
+    /// CID and SID. If the event has already been overwritten, half 0
+    /// describes the oldest event that remains, while half 1 returns
+    /// `None`. Both return `None` for events that have not happened yet.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The caller was not part of the boot image
+    /// * **UnhandledSyscall**: The kernel does not keep an IPC trace
+    GetIpcTrace(usize /* seq */, usize /* half */),
+
+    /// Allocate `size` bytes of physically-contiguous RAM as a shared region
+    /// called `name`, and map it into the current process with the given
+    /// flags. The pages are zeroed, and count against the caller's page
+    /// limit. Other processes may then be offered the region with
+    /// `OfferSharedRegion`. Unmapping the whole range with `UnmapMemory` lets
+    /// go of the region, and its memory is freed once every process holding
+    /// it has let go or exited. Shared regions can't be moved to another
+    /// process in a message.
+    ///
+    /// # Errors
+    ///
+    /// * **BadAlignment**: The size isn't a multiple of the page width
+    /// * **InvalidSyscall**: The flags don't include `R`
+    /// * **MemoryInUse**: A shared region with that name already exists
+    /// * **OutOfMemory**: There isn't enough contiguous RAM, the caller would
+    ///                    exceed its page limit, or there are already too
+    ///                    many shared regions
+    /// * **UnhandledSyscall**: The kernel is running in hosted mode
+    CreateSharedRegion(
+        usize,       /* name */
+        MemorySize,  /* region size */
+        MemoryFlags, /* flags */
+    ),
+
+    /// Map the shared region called `name`, which another process has
+    /// offered to the current process with `OfferSharedRegion`, with the
+    /// given flags. These may not allow more than the offer did. The offer is
+    /// used up once the region has been mapped.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The flags allow more than the offer
+    /// * **BadAddress**: No shared region with that name has been offered to
+    ///                   the caller
+    /// * **InvalidSyscall**: The flags don't include `R`
+    /// * **MemoryInUse**: The caller already holds the region
+    /// * **OutOfMemory**: There is no room in the caller's address space
+    /// * **UnhandledSyscall**: The kernel is running in hosted mode
+    MapSharedRegion(usize /* name */, MemoryFlags /* flags */),
+
+    /// Let process `PID` map the shared region called `name`, which the
+    /// current process holds, with at most the given flags. These may not
+    /// allow anything the caller can't do itself, so a process that can write
+    /// to a region may hand out read-only copies of it. Nothing is mapped
+    /// until the other process calls `MapSharedRegion`, so the caller will
+    /// usually send the name along in a message. A second offer to the same
+    /// process replaces the first.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The flags allow more than the caller has
+    /// * **BadAddress**: The caller holds no shared region with that name
+    /// * **InvalidSyscall**: The flags don't include `R`
+    /// * **MemoryInUse**: The other process already holds the region
+    /// * **ProcessNotFound**: The given PID does not exist
+    /// * **UnhandledSyscall**: The kernel is running in hosted mode
+    OfferSharedRegion(
+        usize,       /* name */
+        PID,         /* target process */
+        MemoryFlags, /* flags */
+    ),
+
     /// This syscall does not exist. It captures all possible
     /// arguments so detailed analysis can be performed.
     Invalid(usize, usize, usize, usize, usize, usize, usize),
@@ -371,6 +500,16 @@ pub enum SysCallNumber {
     Disconnect = 35,
     JoinThread = 36,
     SetExceptionHandler = 37,
+    GetProcessName = 38,
+    SetThreadPriority = 39,
+    GetProcessInfo = 40,
+    GetRamUsage = 41,
+    GetPageQuota = 42,
+    GetIpcTrace = 43,
+    CreateSharedRegion = 44,
+    MapSharedRegion = 45,
+    CreateThreadWithPriority = 46,
+    OfferSharedRegion = 47,
     Invalid,
 }
 
@@ -414,6 +553,16 @@ impl SysCallNumber {
             35 => Disconnect,
             36 => JoinThread,
             37 => SetExceptionHandler,
+            38 => GetProcessName,
+            39 => SetThreadPriority,
+            40 => GetProcessInfo,
+            41 => GetRamUsage,
+            42 => GetPageQuota,
+            43 => GetIpcTrace,
+            44 => CreateSharedRegion,
+            45 => MapSharedRegion,
+            46 => CreateThreadWithPriority,
+            47 => OfferSharedRegion,
             _ => Invalid,
         }
     }
@@ -755,6 +904,87 @@ impl SysCall {
                 0,
                 0,
             ],
+            SysCall::GetProcessName(pid, offset) => [
+                SysCallNumber::GetProcessName as usize,
+                pid.get() as usize,
+                *offset,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::SetThreadPriority(tid, priority) => [
+                SysCallNumber::SetThreadPriority as usize,
+                *tid,
+                *priority as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::GetProcessInfo(pid) => [
+                SysCallNumber::GetProcessInfo as usize,
+                pid.get() as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::GetRamUsage => [SysCallNumber::GetRamUsage as usize, 0, 0, 0, 0, 0, 0, 0],
+            SysCall::GetPageQuota(pid) => [
+                SysCallNumber::GetPageQuota as usize,
+                pid.get() as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::GetIpcTrace(seq, half) => [
+                SysCallNumber::GetIpcTrace as usize,
+                *seq,
+                *half,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::CreateSharedRegion(name, size, flags) => [
+                SysCallNumber::CreateSharedRegion as usize,
+                *name,
+                size.get(),
+                crate::get_bits(flags),
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::MapSharedRegion(name, flags) => [
+                SysCallNumber::MapSharedRegion as usize,
+                *name,
+                crate::get_bits(flags),
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::OfferSharedRegion(name, pid, flags) => [
+                SysCallNumber::OfferSharedRegion as usize,
+                *name,
+                pid.get() as usize,
+                crate::get_bits(flags),
+                0,
+                0,
+                0,
+                0,
+            ],
             SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7) => [
                 SysCallNumber::Invalid as usize,
                 *a1,
@@ -829,9 +1059,12 @@ impl SysCall {
             SysCallNumber::Connect => {
                 SysCall::Connect(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
             }
-            SysCallNumber::SendMessage => Message::try_from((a2, a3, a4, a5, a6, a7))
-                .map(|m| SysCall::SendMessage(a1.try_into().unwrap(), m))
-                .unwrap_or_else(|_| SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7)),
+            SysCallNumber::SendMessage => {
+                let cid = a1.try_into().map_err(|_| Error::InvalidSyscall)?;
+                Message::try_from((a2, a3, a4, a5, a6, a7))
+                    .map(|m| SysCall::SendMessage(cid, m))
+                    .unwrap_or_else(|_| SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7))
+            }
             SysCallNumber::ReturnMemory => SysCall::ReturnMemory(
                 MessageSender::from_usize(a1),
                 unsafe { MemoryRange::new(a2, a3) }?,
@@ -841,6 +1074,9 @@ impl SysCall {
             SysCallNumber::CreateThread => {
                 SysCall::CreateThread(crate::arch::args_to_thread(a1, a2, a3, a4, a5, a6, a7)?)
             }
+            SysCallNumber::CreateThreadWithPriority => SysCall::CreateThread(
+                crate::arch::args_to_thread_with_priority(a1, a2, a3, a4, a5, a6, a7)?,
+            ),
             SysCallNumber::CreateProcess => {
                 SysCall::CreateProcess(crate::arch::args_to_process(a1, a2, a3, a4, a5, a6, a7)?)
             }
@@ -888,7 +1124,7 @@ impl SysCall {
                     }),
                 ),
                 5 => SysCall::TrySendMessage(
-                    a1.try_into().unwrap(),
+                    a1.try_into().map_err(|_| Error::InvalidSyscall)?,
                     Message::BlockingScalar(ScalarMessage {
                         id: a3,
                         arg1: a4,
@@ -918,6 +1154,36 @@ impl SysCall {
             SysCallNumber::Disconnect => SysCall::Disconnect(a1 as _),
             SysCallNumber::JoinThread => SysCall::JoinThread(a1 as _),
             SysCallNumber::SetExceptionHandler => SysCall::SetExceptionHandler(a1 as _, a2 as _),
+            SysCallNumber::GetProcessName => {
+                SysCall::GetProcessName(PID::new(a1 as _).ok_or(Error::InvalidSyscall)?, a2)
+            }
+            SysCallNumber::SetThreadPriority => {
+                if a2 > THREAD_PRIORITY_MAX as usize {
+                    return Err(Error::InvalidSyscall);
+                }
+                SysCall::SetThreadPriority(a1 as _, a2 as _)
+            }
+            SysCallNumber::GetProcessInfo => {
+                SysCall::GetProcessInfo(PID::new(a1 as _).ok_or(Error::InvalidSyscall)?)
+            }
+            SysCallNumber::GetRamUsage => SysCall::GetRamUsage,
+            SysCallNumber::GetPageQuota => {
+                SysCall::GetPageQuota(PID::new(a1 as _).ok_or(Error::InvalidSyscall)?)
+            }
+            SysCallNumber::GetIpcTrace => SysCall::GetIpcTrace(a1, a2),
+            SysCallNumber::CreateSharedRegion => SysCall::CreateSharedRegion(
+                a1,
+                MemorySize::new(a2).ok_or(Error::InvalidSyscall)?,
+                crate::from_bits(a3).ok_or(Error::InvalidSyscall)?,
+            ),
+            SysCallNumber::MapSharedRegion => {
+                SysCall::MapSharedRegion(a1, crate::from_bits(a2).ok_or(Error::InvalidSyscall)?)
+            }
+            SysCallNumber::OfferSharedRegion => SysCall::OfferSharedRegion(
+                a1,
+                PID::new(a2 as _).ok_or(Error::InvalidSyscall)?,
+                crate::from_bits(a3).ok_or(Error::InvalidSyscall)?,
+            ),
             SysCallNumber::Invalid => SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7),
         })
     }
@@ -1494,7 +1760,8 @@ where
     })
 }
 
-/// Create a new thread with the given closure.
+/// Create a new thread with the given closure. Call `join()` on the returned
+/// handle to wait for the thread and get back the value the closure returned.
 pub fn create_thread<F, T>(f: F) -> core::result::Result<crate::arch::WaitHandle<T>, Error>
 where
     F: FnOnce() -> T,
@@ -1502,13 +1769,62 @@ where
     T: Send + 'static,
 {
     let thread_info = crate::arch::create_thread_pre(&f)?;
-    rsyscall(SysCall::CreateThread(thread_info)).and_then(|result| {
-        if let Result::ThreadID(thread_id) = result {
-            crate::arch::create_thread_post(f, thread_id)
-        } else {
-            Err(Error::InternalError)
+    spawn_closure_thread(f, thread_info)
+}
+
+/// Create a new thread with the given scheduling priority, and with the
+/// specified closure as its body.
+///
+/// A thread that doesn't run at `THREAD_PRIORITY_DEFAULT` takes at most three
+/// arguments, as the kernel gets its priority where the fourth would go. A
+/// closure needs only one, but a `ThreadInit` made by hand with all four has
+/// to go through `ThreadInit::with_priority()`, which refuses it.
+///
+/// # Errors
+///
+/// * **InvalidSyscall**: The priority is greater than `THREAD_PRIORITY_MAX`
+/// * **AccessDenied**: Only privileged processes may use `THREAD_PRIORITY_MAX`
+pub fn create_thread_with_priority<F, T>(
+    priority: ThreadPriority,
+    f: F,
+) -> core::result::Result<crate::arch::WaitHandle<T>, Error>
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
+    let thread_info = crate::arch::create_thread_pre(&f)?;
+    let thread_info = match thread_info.with_priority(priority) {
+        Ok(thread_info) => thread_info,
+        Err(e) => {
+            crate::arch::create_thread_abort::<F, T>(thread_info);
+            return Err(e);
         }
-    })
+    };
+    spawn_closure_thread(f, thread_info)
+}
+
+/// Ask the kernel for the thread set up by `create_thread_pre()`, and clean up
+/// after it if the thread can't be created.
+fn spawn_closure_thread<F, T>(
+    f: F,
+    thread_info: ThreadInit,
+) -> core::result::Result<crate::arch::WaitHandle<T>, Error>
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
+    match rsyscall(SysCall::CreateThread(thread_info)) {
+        Ok(Result::ThreadID(thread_id)) => crate::arch::create_thread_post(f, thread_id),
+        other => {
+            crate::arch::create_thread_abort::<F, T>(thread_info);
+            match other {
+                Err(e) => Err(e),
+                _ => Err(Error::InternalError),
+            }
+        }
+    }
 }
 
 /// Wait for a thread to finish. This is equivalent to `join_thread`
@@ -1628,6 +1944,207 @@ pub fn join_thread(tid: TID) -> core::result::Result<usize, Error> {
     })
 }
 
+/// Copy the name of process `pid` into `name`, truncating it if the buffer is
+/// too small. Returns the full length of the name, which is 0 if the process
+/// has no name.
+///
+/// # Errors
+///
+/// * **ProcessNotFound**: The given PID does not exist
+pub fn process_name(pid: PID, name: &mut [u8]) -> core::result::Result<usize, Error> {
+    let mut offset = 0;
+    loop {
+        let (len, words) = match rsyscall(SysCall::GetProcessName(pid, offset))? {
+            Result::Scalar5(len, w0, w1, w2, w3) => (len, [w0, w1, w2, w3]),
+            Result::Error(e) => return Err(e),
+            _ => return Err(Error::InternalError),
+        };
+        for word in words.iter() {
+            for &byte in word.to_le_bytes().iter() {
+                if offset >= len || offset >= name.len() {
+                    return Ok(len);
+                }
+                name[offset] = byte;
+                offset += 1;
+            }
+        }
+    }
+}
+
+/// Describe process `pid`. A process may always describe itself, but only
+/// processes from the boot image may describe other processes. Use
+/// `process_name()` to get the name that goes along with it.
+///
+/// # Errors
+///
+/// * **ProcessNotFound**: The given PID does not exist
+/// * **AccessDenied**: The caller may not inspect other processes
+pub fn process_info(pid: PID) -> core::result::Result<ProcessInfo, Error> {
+    match rsyscall(SysCall::GetProcessInfo(pid))? {
+        Result::Scalar5(a1, a2, a3, a4, a5) => {
+            Ok(ProcessInfo::from_args(pid, [a1, a2, a3, a4, a5]))
+        }
+        Result::Error(e) => Err(e),
+        _ => Err(Error::InternalError),
+    }
+}
+
+/// Return the number of bytes of RAM in use and the total number of bytes of
+/// RAM in the system, in that order. Both are 0 in hosted mode.
+pub fn ram_usage() -> core::result::Result<(usize, usize), Error> {
+    match rsyscall(SysCall::GetRamUsage)? {
+        Result::Scalar2(used, total) => Ok((used, total)),
+        Result::Error(e) => Err(e),
+        _ => Err(Error::InternalError),
+    }
+}
+
+/// Return the number of pages of RAM owned by process `pid`, along with the
+/// most pages it may own, or `None` if it has no limit. Processes that reach
+/// their limit get `OutOfMemory` when they try to allocate more.
+///
+/// # Errors
+///
+/// * **ProcessNotFound**: The given PID does not exist
+/// * **AccessDenied**: The caller may not inspect other processes
+pub fn page_quota(pid: PID) -> core::result::Result<(usize, Option<usize>), Error> {
+    match rsyscall(SysCall::GetPageQuota(pid))? {
+        Result::Scalar2(used, 0) => Ok((used, None)),
+        Result::Scalar2(used, limit) => Ok((used, Some(limit))),
+        Result::Error(e) => Err(e),
+        _ => Err(Error::InternalError),
+    }
+}
+
+/// Read event `seq` from the kernel's IPC trace, or `None` if it hasn't
+/// happened yet. The trace only holds the most recent events, so if `seq`
+/// has already been overwritten, the oldest remaining event is returned
+/// instead. Check the `seq` of the result before asking for the next one.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The caller was not part of the boot image
+/// * **UnhandledSyscall**: The kernel was built without `ipc-trace`
+pub fn ipc_trace_event(seq: usize) -> core::result::Result<Option<IpcTraceEvent>, Error> {
+    loop {
+        let header = match rsyscall(SysCall::GetIpcTrace(seq, 0))? {
+            Result::Scalar5(a1, a2, a3, a4, a5) => [a1, a2, a3, a4, a5],
+            Result::None => return Ok(None),
+            Result::Error(e) => return Err(e),
+            _ => return Err(Error::InternalError),
+        };
+        let target = match rsyscall(SysCall::GetIpcTrace(header[0], 1))? {
+            Result::Scalar5(a1, a2, a3, a4, a5) => [a1, a2, a3, a4, a5],
+            // The event was overwritten between the two calls, so start
+            // again from the oldest one.
+            Result::None => continue,
+            Result::Error(e) => return Err(e),
+            _ => return Err(Error::InternalError),
+        };
+        return IpcTraceEvent::from_args(header, target)
+            .map(Some)
+            .ok_or(Error::InternalError);
+    }
+}
+
+/// Create a shared region of `size` bytes called `name`, and map it into this
+/// process with `flags`. The name is chosen by the caller, and is how the
+/// region is referred to when handing it to other processes with
+/// `offer_shared_region()`. Pass the range to `unmap_memory()` to let go of it.
+///
+/// # Errors
+///
+/// * **BadAlignment**: The size isn't a multiple of the page width
+/// * **InvalidSyscall**: The flags don't include `R`
+/// * **MemoryInUse**: A shared region with that name already exists
+/// * **OutOfMemory**: There isn't enough memory for the region
+/// * **UnhandledSyscall**: The kernel is running in hosted mode
+pub fn create_shared_region(
+    name: usize,
+    size: usize,
+    flags: MemoryFlags,
+) -> core::result::Result<MemoryRange, Error> {
+    match rsyscall(SysCall::CreateSharedRegion(
+        name,
+        MemorySize::new(size).ok_or(Error::InvalidSyscall)?,
+        flags,
+    ))? {
+        Result::MemoryRange(range) => Ok(range),
+        Result::Error(e) => Err(e),
+        _ => Err(Error::InternalError),
+    }
+}
+
+/// Map the shared region called `name`, which another process has offered to
+/// this one with `offer_shared_region()`, with `flags`. These may not allow
+/// more than the offer did.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The flags allow more than the offer
+/// * **BadAddress**: No shared region with that name has been offered
+/// * **InvalidSyscall**: The flags don't include `R`
+/// * **MemoryInUse**: This process already holds the region
+/// * **OutOfMemory**: There is no room for the region in this process
+/// * **UnhandledSyscall**: The kernel is running in hosted mode
+pub fn map_shared_region(
+    name: usize,
+    flags: MemoryFlags,
+) -> core::result::Result<MemoryRange, Error> {
+    match rsyscall(SysCall::MapSharedRegion(name, flags))? {
+        Result::MemoryRange(range) => Ok(range),
+        Result::Error(e) => Err(e),
+        _ => Err(Error::InternalError),
+    }
+}
+
+/// Let process `pid` map the shared region called `name` with at most
+/// `flags`, which may not grant more than this process has. Nothing changes in
+/// `pid` until it calls `map_shared_region()`.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The flags allow more than this process has
+/// * **BadAddress**: This process holds no shared region with that name
+/// * **InvalidSyscall**: The flags don't include `R`
+/// * **MemoryInUse**: `pid` already holds the region
+/// * **ProcessNotFound**: The given PID does not exist
+/// * **UnhandledSyscall**: The kernel is running in hosted mode
+pub fn offer_shared_region(
+    name: usize,
+    pid: PID,
+    flags: MemoryFlags,
+) -> core::result::Result<(), Error> {
+    match rsyscall(SysCall::OfferSharedRegion(name, pid, flags))? {
+        Result::Ok => Ok(()),
+        Result::Error(e) => Err(e),
+        _ => Err(Error::InternalError),
+    }
+}
+
+/// Change the scheduling priority of thread `tid` in this process, returning
+/// the priority it had before. A thread may lower its own priority to let
+/// more urgent work run, or raise it while it is servicing something the user
+/// is waiting on.
+///
+/// # Errors
+///
+/// * **InvalidSyscall**: The priority is greater than `THREAD_PRIORITY_MAX`
+/// * **ThreadNotAvailable**: The given thread ID is out of range
+pub fn set_thread_priority(
+    tid: TID,
+    priority: ThreadPriority,
+) -> core::result::Result<ThreadPriority, Error> {
+    if priority > THREAD_PRIORITY_MAX {
+        return Err(Error::InvalidSyscall);
+    }
+    match rsyscall(SysCall::SetThreadPriority(tid, priority))? {
+        Result::Scalar1(previous) => Ok(previous as ThreadPriority),
+        Result::Error(e) => Err(e),
+        _ => Err(Error::InternalError),
+    }
+}
+
 static EXCEPTION_HANDLER: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
 fn handle_exception(exception_type: usize, arg1: usize, arg2: usize) -> isize {
     let exception = crate::exceptions::Exception::new(exception_type, arg1, arg2);
@@ -137,6 +137,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         */
     ];
 
+    // the Net and DNS servers, and a client that checks them against `net-peer`
+    let net_test_pkgs = [
+        "ticktimer-server",
+        "log-server",
+        "xous-names",
+        "susres",
+        "trng",
+        "llio",
+        "com",
+        "net",
+        "dns",
+        "net-test",
+    ];
+
     let aestest_pkgs = ["ticktimer-server", "log-server", "aes-test"];
     let mut args = env::args();
     let task = args.nth(1);
@@ -172,6 +186,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &[],
         )?,
         Some("pddb-hosted") => run(false, &pddb_dev_pkgs)?,
+        Some("net-test") => run(false, &net_test_pkgs)?,
         Some("benchmark") => build_hw_image(
             false,
             env::args().nth(2),
@@ -267,6 +282,7 @@ generate-locales        only generate the locales include for the language selec
 wycheproof-import       generate binary test vectors for engine-25519 from whycheproof-import/x25519.json
 pddb-dev                PDDB testing only for live hardware
 pddb-hosted             PDDB testing in a hosted environment
+net-test                network testing in a hosted environment, against `net-peer`; see tools/net_hosted_test.sh
 
 Please refer to tools/README_UPDATE.md for instructions on how to set up `usb_update.py`
 "